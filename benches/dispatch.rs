@@ -0,0 +1,109 @@
+//! Benchmarks of the request hot path: parsing, routing and full dispatch in memory.
+//!
+//! Run with `cargo bench`. Each benchmark reports the time and the number of
+//! allocations per iteration; pass a name to run only the benchmarks containing it,
+//! as in `cargo bench -- routing`.
+
+use rustic::app::App;
+use rustic::connection::handle_connection;
+use rustic::parse_headers::parse_headers;
+use rustic::test::TestClient;
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::hint::black_box;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::{Duration, Instant};
+
+/// Counts every allocation, so that each benchmark can report its own.
+struct CountingAllocator;
+
+static ALLOCATIONS: AtomicUsize = AtomicUsize::new(0);
+
+unsafe impl GlobalAlloc for CountingAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout)
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        System.realloc(ptr, layout, new_size)
+    }
+}
+
+#[global_allocator]
+static GLOBAL: CountingAllocator = CountingAllocator;
+
+/// How long each benchmark runs once warmed up.
+const MEASUREMENT_TIME: Duration = Duration::from_secs(1);
+
+/// Runs `routine` repeatedly for about [`MEASUREMENT_TIME`] and prints its mean time
+/// and allocations per iteration.
+fn bench(name: &str, mut routine: impl FnMut()) {
+    let filter = std::env::args().skip(1).find(|arg| !arg.starts_with('-'));
+    if filter.is_some_and(|filter| !name.contains(&filter)) {
+        return;
+    }
+    for _ in 0..1_000 {
+        routine();
+    }
+    let mut iterations = 0u64;
+    let allocations = ALLOCATIONS.load(Ordering::Relaxed);
+    let started = Instant::now();
+    while started.elapsed() < MEASUREMENT_TIME {
+        for _ in 0..100 {
+            routine();
+        }
+        iterations += 100;
+    }
+    let elapsed = started.elapsed();
+    let allocated = ALLOCATIONS.load(Ordering::Relaxed) - allocations;
+    println!(
+        "{:<28} {:>10.0} ns/iter {:>8.1} allocations/iter",
+        name,
+        elapsed.as_nanos() as f64 / iterations as f64,
+        allocated as f64 / iterations as f64
+    );
+}
+
+const REQUEST: &[u8] = b"GET /hello?name=Ferris HTTP/1.1\r\nHost: localhost:8080\r\n\
+    User-Agent: bench/1.0\r\nAccept: */*\r\nAccept-Encoding: gzip, br\r\n\r\n";
+
+fn main() {
+    bench("read_request", || {
+        black_box(handle_connection(&mut black_box(REQUEST)).unwrap());
+    });
+
+    let (lines, _) = handle_connection(&mut &REQUEST[..]).unwrap().unwrap();
+    bench("parse_headers", || {
+        black_box(parse_headers(black_box(lines.clone())).unwrap());
+    });
+
+    let mut hello = App::new();
+    hello.get("hello", |_| "Hello, world!");
+    let client = TestClient::new(hello);
+    bench("dispatch_hello_world", || {
+        black_box(client.get("/hello").send());
+    });
+    bench("dispatch_query_unread", || {
+        black_box(client.get("/hello?name=Ferris&lang=en&page=2").send());
+    });
+
+    let mut routes = App::new();
+    for index in 0..50 {
+        routes.get(format!("resource{}/{{id}}", index), |_| "static");
+    }
+    routes.get("users/{id}/posts/{post}", |request| {
+        request.path_param("post").unwrap_or_default().to_string()
+    });
+    let client = TestClient::new(routes);
+    bench("routing_path_params", || {
+        black_box(client.get("/users/42/posts/7").send());
+    });
+    bench("routing_not_found", || {
+        black_box(client.get("/missing/route").send());
+    });
+}
@@ -1,56 +1,914 @@
-use crate::connection::{handle_connection, listen_at_port};
-use crate::http11_response::{write_connection, Response};
-use crate::parse_headers::{parse_headers, RequestType};
+use crate::body_reader::{BodyReader, StreamedBody, UnbufferedBody};
+use crate::connection::{
+    copy_body, framing, is_disconnect, is_listener_broken, listen_at_port, read_body,
+    read_request_head as read_request_lines, BodyError, Framing, MAX_PREALLOCATED_BODY,
+};
+use crate::content_type::{essence, ContentType};
+use crate::echo::RawHead;
+use crate::error::Error;
+use crate::extensions::Extensions;
+use crate::forwarded::LocalHost;
+use crate::header_map::HeaderMap;
+use crate::hijack::{HijackHandler, HijackSlot, Hijacked, Takeover};
+use crate::http11_response::{reason_phrase, ErrorResponse, Message, Response};
+use crate::http_error::HttpError;
+use crate::inflate::{gunzip, zlib_decompress, InflateError};
+use crate::into_response::{body_response, text_response, IntoResponse};
+use crate::lifecycle::{
+    log_startup, run_shutdown_hooks, run_start_hooks, ServerInfo, ShutdownHook, StartHook,
+};
+use crate::maintenance::Maintenance;
+use crate::metrics::Metrics;
+use crate::middleware::{Middleware, Next};
+use crate::parse_headers::{
+    has_space_before_colon, http_major_version, is_http2_preface, is_token, parse_headers,
+    parse_method, HttpType, RawRequest, RequestType,
+};
 use crate::parse_path::parse_path;
-use crate::parse_url::parse_url_param;
+use crate::query::missing_params;
+use crate::response_writer::{ResponseWriter, WriteError};
+use crate::schedule::{ScheduledJob, Scheduler};
+use crate::shutdown::{Shutdown, ShutdownOutcome};
+use crate::spool::{Spool, Spooled};
+use crate::tasks::Tasks;
+use crate::trace::trace_response;
+use crate::traffic::{ByteCounts, CountingReader, Exchange, RequestBytes, Traffic};
+use crate::tunnel::ConnectHandler;
+use crate::worker_pool::WorkerPool;
+use std::borrow::Cow;
+use std::cmp::Ordering;
 use std::collections::HashMap;
-use std::sync::Arc;
-use std::thread;
+use std::fmt;
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream};
+use std::panic::{self, AssertUnwindSafe};
+use std::str::FromStr;
+use std::sync::{Arc, Condvar, Mutex, RwLock};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 
 /// Represents an HTTP request.
 pub struct Request {
-    pub headers: HashMap<String, String>,
+    pub method: RequestType,
+    pub path: String,
+    /// The request target as sent by the client, including any query string.
+    pub url: String,
+    /// The headers in the order they were sent. Repeated headers keep every value.
+    pub headers: HeaderMap,
+    /// The body, read in full before the handler runs unless it is streamed or spilled.
+    /// A body cut short or not valid UTF-8 is answered with `400 Bad Request` instead.
     pub body: String,
-    pub url_params: HashMap<String, String>,
+    pub extensions: Extensions,
+    /// The address of the peer that sent the request, when it came over a socket.
+    pub remote_addr: Option<SocketAddr>,
+}
+
+/// An empty `GET /` request, for building requests in tests with struct update syntax.
+///
+/// # Examples
+///
+/// ```
+/// use rustic::app::Request;
+/// use rustic::parse_headers::RequestType;
+/// let request = Request {
+///     method: RequestType::POST,
+///     url: "/search?q=rust".to_string(),
+///     ..Request::default()
+/// };
+/// assert_eq!(request.query_param("q").value().as_deref(), Some("rust"));
+/// ```
+impl Default for Request {
+    fn default() -> Self {
+        Request {
+            method: RequestType::GET,
+            path: String::new(),
+            url: "/".to_string(),
+            headers: HeaderMap::new(),
+            body: String::new(),
+            extensions: Extensions::new(),
+            remote_addr: None,
+        }
+    }
+}
+
+impl Request {
+    /// Looks up a request header by name, ignoring ASCII case.
+    ///
+    /// # Arguments
+    ///
+    /// * `name` - The header name to look up.
+    ///
+    /// # Returns
+    ///
+    /// * `Option<&str>` - The header value, if the client sent the header.
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers.get(name)
+    }
+
+    /// Returns a parameter captured by a `{name}` segment of the matched endpoint path.
+    ///
+    /// # Arguments
+    ///
+    /// * `name` - The parameter name, without the braces.
+    ///
+    /// # Returns
+    ///
+    /// * `Option<&str>` - The matching segment of the request path.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use rustic::app::App;
+    /// let mut application = App::new();
+    /// application.get("users/{id}", |request| {
+    ///     format!("User {}", request.path_param("id").unwrap_or_default())
+    /// });
+    /// ```
+    pub fn path_param(&self, name: &str) -> Option<&str> {
+        self.extensions
+            .get::<PathParamMap>()
+            .and_then(|params| params.0.get(name))
+            .map(String::as_str)
+    }
+
+    /// Parses a parameter captured by a `{name}` segment of the matched endpoint path.
+    ///
+    /// A parameter from a `{name:u64}` segment always parses as a `u64`, since the
+    /// endpoint only matches segments that do.
+    ///
+    /// # Arguments
+    ///
+    /// * `name` - The parameter name, without the braces or constraint.
+    ///
+    /// # Returns
+    ///
+    /// * `Option<T>` - The parsed segment, or `None` when there is no such parameter or
+    ///   it does not parse as `T`.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use rustic::app::App;
+    /// use rustic::test::TestClient;
+    ///
+    /// let mut application = App::new();
+    /// application.get("users/{id:u64}", |request| {
+    ///     let id: u64 = request.path_param_as("id").unwrap();
+    ///     format!("User {}", id + 1)
+    /// });
+    /// application.get("users/{file}", |request| {
+    ///     format!("File {}", request.path_param("file").unwrap_or_default())
+    /// });
+    /// let client = TestClient::new(application);
+    /// assert_eq!(client.get("/users/41").send().text(), "User 42");
+    /// assert_eq!(client.get("/users/me.png").send().text(), "File me.png");
+    /// ```
+    pub fn path_param_as<T: FromStr>(&self, name: &str) -> Option<T> {
+        self.path_param(name)?.parse().ok()
+    }
+
+    /// Returns the name of the endpoint the request was routed to, when it was given one
+    /// with [`EndpointConfig::name`].
+    ///
+    /// The endpoint is looked up as the request arrived, before middleware such as
+    /// [`MethodOverride`](crate::method_override::MethodOverride) may change it.
+    pub fn route_name(&self) -> Option<&str> {
+        self.extensions
+            .get::<RouteConfig>()
+            .and_then(|route| route.0.name.as_deref())
+    }
+
+    /// Returns the path pattern of the endpoint the request was routed to, as it was
+    /// registered, such as `users/{id}` for a request to `/users/12345`.
+    ///
+    /// Unlike the path, the pattern takes one value per endpoint, which makes it fit
+    /// for labelling logs and metrics by. It is `None` for requests matching no endpoint,
+    /// which logs and metrics report as [`UNMATCHED_ROUTE`]. The endpoint is looked up
+    /// as for [`Request::route_name`].
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use rustic::app::App;
+    /// use rustic::test::TestClient;
+    ///
+    /// let mut application = App::new();
+    /// application.get("users/{id}", |request| {
+    ///     request.matched_route().unwrap_or_default().to_string()
+    /// });
+    /// let client = TestClient::new(application);
+    /// assert_eq!(client.get("/users/12345").send().text(), "users/{id}");
+    /// ```
+    pub fn matched_route(&self) -> Option<&str> {
+        self.extensions
+            .get::<RouteConfig>()
+            .map(|route| route.0.pattern.as_str())
+    }
+
+    /// Sends an interim `1xx` response, such as `103 Early Hints` listing resources the
+    /// client can start loading, ahead of the final response.
+    ///
+    /// Interim responses go straight to the connection, before the handler returns.
+    /// They are only possible for HTTP/1.1 requests served by [`run`] and its
+    /// variants; HTTP/1.0 clients do not expect them.
+    ///
+    /// # Arguments
+    ///
+    /// * `status_code` - The `1xx` status code, other than `101`.
+    /// * `headers` - The headers of the interim response.
+    ///
+    /// # Errors
+    ///
+    /// Fails with `ErrorKind::InvalidInput` for a status that is not interim, with
+    /// `ErrorKind::Unsupported` when the request cannot receive interim responses or its
+    /// final response has begun, and with the error of the write otherwise.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use rustic::app::App;
+    /// use rustic::header_map::HeaderMap;
+    ///
+    /// let mut application = App::new();
+    /// application.get("page", |request| {
+    ///     let mut hints = HeaderMap::new();
+    ///     hints.append("Link", "</style.css>; rel=preload; as=style");
+    ///     // Clients that cannot get hints still get the page.
+    ///     let _ = request.send_interim(103, &hints);
+    ///     "<link rel=stylesheet href=/style.css>"
+    /// });
+    /// ```
+    pub fn send_interim(&self, status_code: u16, headers: &HeaderMap) -> io::Result<()> {
+        match self.extensions.get::<InterimSender>() {
+            Some(sender) => sender.send(status_code, headers),
+            None => Err(io::Error::new(
+                io::ErrorKind::Unsupported,
+                "the request cannot receive interim responses",
+            )),
+        }
+    }
+
+    /// Copies everything but the body and extensions, for error handlers to inspect once
+    /// the original request has been handed to an endpoint.
+    fn snapshot(&self) -> Request {
+        Request {
+            method: self.method,
+            path: self.path.clone(),
+            url: self.url.clone(),
+            headers: self.headers.clone(),
+            body: String::new(),
+            extensions: Extensions::new(),
+            remote_addr: self.remote_addr,
+        }
+    }
+}
+
+/// The parameters captured from the request path by the matched endpoint.
+pub(crate) struct PathParamMap(pub(crate) HashMap<String, String>);
+
+/// The route reported by the access, slow request and metrics series for a request
+/// matching no endpoint, in place of its [`Request::matched_route`].
+pub const UNMATCHED_ROUTE: &str = "<unmatched>";
+
+/// The settings of the endpoint a request was routed to as it arrived.
+struct RouteConfig(Arc<EndpointConfig>);
+
+/// The writing side of a connection, through whose [`ResponseWriter`] every response to
+/// its requests is written, in order.
+///
+/// It is shared with the request being answered so that its handler can write interim
+/// responses, only until its final response begins.
+struct ConnectionWriter {
+    output: Mutex<ConnectionOutput>,
+}
+
+struct ConnectionOutput {
+    writer: ResponseWriter<TcpStream>,
+    /// The number of the request that may write interim responses, if any.
+    interim_for: Option<u64>,
+}
+
+impl ConnectionWriter {
+    fn new(stream: TcpStream) -> Self {
+        ConnectionWriter {
+            output: Mutex::new(ConnectionOutput {
+                writer: ResponseWriter::new(stream),
+                interim_for: None,
+            }),
+        }
+    }
+
+    /// Lets the request numbered `request` write interim responses, returning what it
+    /// needs to write them.
+    fn open(self: &Arc<Self>, request: u64) -> InterimSender {
+        self.output.lock().unwrap().interim_for = Some(request);
+        InterimSender {
+            channel: Arc::clone(self),
+            request,
+        }
+    }
+
+    /// Stops interim responses before the final response is written, waiting for one
+    /// being written to finish.
+    fn close(&self) {
+        self.output.lock().unwrap().interim_for = None;
+    }
+
+    /// Writes the final response to the current request.
+    fn send(&self, message: &Message) -> Result<(), WriteError> {
+        self.output.lock().unwrap().writer.send(message)
+    }
+
+    /// Starts the response to the next request, once the current one is written.
+    fn next_response(&self) -> Result<(), WriteError> {
+        self.output.lock().unwrap().writer.next_response()
+    }
+
+    /// Stops all writes to a connection a handler took over.
+    fn hand_over(&self) {
+        self.output.lock().unwrap().writer.hand_over();
+    }
+}
+
+/// The handle a request keeps in its extensions to send interim responses.
+struct InterimSender {
+    channel: Arc<ConnectionWriter>,
+    request: u64,
+}
+
+impl InterimSender {
+    fn send(&self, status_code: u16, headers: &HeaderMap) -> io::Result<()> {
+        let mut output = self.channel.output.lock().unwrap();
+        if output.interim_for != Some(self.request) {
+            return Err(io::Error::new(
+                io::ErrorKind::Unsupported,
+                "the final response has begun",
+            ));
+        }
+        Ok(output.writer.write_interim(status_code, headers)?)
+    }
+}
+
+/// A request handler stored in the endpoint table.
+///
+/// Handlers are reference counted so a request can keep running its handler after
+/// releasing the lock on the endpoint table.
+pub type Handler = Arc<dyn Fn(Request) -> Option<Response> + Send + Sync>;
+
+mod private {
+    pub trait Sealed {}
+}
+
+/// The return types an endpoint handler may have.
+///
+/// Any [`IntoResponse`] type is sent as the response. `Option<T>` answers `None` with
+/// `404 Not Found`. `Result<T, E>` answers `Err` with the response the error converts
+/// into, which lets handlers use `?` with [`HttpError`](crate::http_error::HttpError).
+/// This trait is sealed; implement [`IntoResponse`] for custom return types instead.
+pub trait IntoHandlerResult: private::Sealed {
+    /// Converts the handler's return value into the response to send, if any.
+    fn into_handler_result(self) -> Option<Response>;
+}
+
+impl<T: IntoResponse> private::Sealed for T {}
+
+impl<T: IntoResponse> IntoHandlerResult for T {
+    fn into_handler_result(self) -> Option<Response> {
+        Some(self.into_response())
+    }
+}
+
+impl<T: IntoResponse> private::Sealed for Option<T> {}
+
+impl<T: IntoResponse> IntoHandlerResult for Option<T> {
+    fn into_handler_result(self) -> Option<Response> {
+        self.map(IntoResponse::into_response)
+    }
+}
+
+impl<T: IntoResponse, E: IntoResponse> private::Sealed for Result<T, E> {}
+
+impl<T: IntoResponse, E: IntoResponse> IntoHandlerResult for Result<T, E> {
+    fn into_handler_result(self) -> Option<Response> {
+        Some(match self {
+            Ok(value) => value.into_response(),
+            Err(error) => error.into_response(),
+        })
+    }
 }
 
 /// Represents an endpoint in the application.
-pub struct Endpoint<'a> {
-    pub path: &'a str,
-    pub request: RequestType,
-    pub mapper: fn(Request) -> Option<Response<'a>>,
+pub struct Endpoint {
+    pub path: String,
+    /// The request type served, or `None` for an endpoint added with [`App::any`].
+    pub request: Option<RequestType>,
+    pub mapper: Handler,
+    pub config: Arc<EndpointConfig>,
+    /// The middleware of the endpoint's scopes, then of the endpoint itself, run after
+    /// the app's middleware.
+    pub(crate) middleware: Arc<[Box<dyn Middleware>]>,
+    /// Whether the endpoint is a default that every other endpoint takes precedence
+    /// over, whenever it was added.
+    pub(crate) fallback: bool,
 }
 
-/// Represents the application with multiple endpoints.
-pub struct App<'a> {
-    pub endpoints: Vec<Endpoint<'a>>,
+/// An endpoint as listed by [`Routes::list`].
+#[derive(Debug, Clone, PartialEq)]
+pub struct RouteInfo {
+    /// The request type served, or `None` for an endpoint added with [`App::any`].
+    pub method: Option<RequestType>,
+    /// The path the endpoint was registered with, such as `users/{id}`.
+    pub pattern: String,
+    /// The name given with [`EndpointConfig::name`].
+    pub name: Option<String>,
 }
 
-impl<'a> App<'a> {
-    /// Creates a new instance of the application.
+/// An endpoint matching a request, with the path parameters it captured.
+struct Matched {
+    handler: Handler,
+    params: PathParamMap,
+    config: Arc<EndpointConfig>,
+    middleware: Arc<[Box<dyn Middleware>]>,
+}
+
+/// Per-endpoint settings checked by the dispatcher before the handler runs.
+///
+/// # Examples
+///
+/// ```
+/// use rustic::app::{App, EndpointConfig};
+/// use rustic::parse_headers::RequestType;
+///
+/// let mut application = App::new();
+/// application.add_endpoint_with_config(
+///     "uploads",
+///     RequestType::POST,
+///     |request| format!("Stored {} bytes", request.body.len()),
+///     EndpointConfig::new().accepts(&["application/json", "multipart/*"]),
+/// );
+/// ```
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct EndpointConfig {
+    /// The path the endpoint was registered with, filled in when it is added.
+    pattern: String,
+    pub(crate) accepts: Vec<String>,
+    pub(crate) name: Option<String>,
+    max_body_size: Option<usize>,
+    read_timeout: Option<Duration>,
+    stream_body: bool,
+    spill_threshold: Option<usize>,
+    pub(crate) required_params: Vec<String>,
+    response_header_limits: Option<(usize, usize)>,
+}
+
+impl EndpointConfig {
+    /// Creates the default configuration, accepting requests of any content type with
+    /// the limits of the [`ServerConfig`].
     pub fn new() -> Self {
-        App { endpoints: vec![] }
+        Self::default()
     }
 
-    /// Adds a new endpoint to the application.
+    /// Names the endpoint, for [`Request::route_name`] and the access log of
+    /// [`RequestIdMiddleware`](crate::request_id::RequestIdMiddleware).
+    pub fn name(mut self, name: impl Into<String>) -> Self {
+        self.name = Some(name.into());
+        self
+    }
+
+    /// Sets the largest body the endpoint accepts, in bytes, instead of
+    /// [`ServerConfig::max_body_size`]. Larger bodies are answered with
+    /// `413 Payload Too Large`.
+    pub fn limit_body(mut self, bytes: usize) -> Self {
+        self.max_body_size = Some(bytes);
+        self
+    }
+
+    /// Sets how long to wait for each read of the request body, instead of
+    /// [`ServerConfig::read_timeout`], for endpoints receiving large uploads over slow
+    /// links.
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.read_timeout = Some(timeout);
+        self
+    }
+
+    /// Leaves the request body on the connection for the handler to read with
+    /// [`Request::body_reader`], instead of buffering it into [`Request::body`] first.
+    ///
+    /// This keeps memory flat for large uploads, such as those relayed to storage. The
+    /// body is still limited by [`EndpointConfig::limit_body`] or
+    /// [`ServerConfig::max_body_size`], but its content codings are not undone, and
+    /// [`Request::body`] stays empty for middleware and for the handler unless it calls
+    /// [`Request::buffer_body`]. Handlers wrapped with
+    /// [`with_extractors`](crate::extract::with_extractors) get the body buffered.
+    ///
+    /// Only requests served over a socket by [`run`] and its variants are streamed;
+    /// elsewhere, such as with [`TestClient`](crate::test::TestClient), the body is
+    /// buffered and [`Request::body_reader`] reads it from memory. A buffered body that
+    /// is not valid UTF-8 is then only available through [`Request::body_reader`].
+    pub fn stream_body(mut self) -> Self {
+        self.stream_body = true;
+        self
+    }
+
+    /// Sets the size past which request bodies are written to a temporary file as they
+    /// are read, instead of [`ServerConfig::spill_threshold`].
+    ///
+    /// Handlers read a spilled body with [`Request::body_reader`] or
+    /// [`Request::body_bytes`], or move its file with [`Request::body_path`];
+    /// [`Request::body`] stays empty. See [`ServerConfig::spill_threshold`].
+    pub fn spill_threshold(mut self, bytes: usize) -> Self {
+        self.spill_threshold = Some(bytes);
+        self
+    }
+
+    /// Sets the longest response header value and the largest response header block the
+    /// endpoint may send, in bytes, instead of
+    /// [`ServerConfig::max_response_header_value`] and
+    /// [`ServerConfig::max_response_header_size`], for an endpoint whose headers are
+    /// legitimately large, such as one setting many cookies.
+    pub fn limit_response_headers(mut self, value_bytes: usize, total_bytes: usize) -> Self {
+        self.response_header_limits = Some((value_bytes, total_bytes));
+        self
+    }
+
+    /// Restricts the media types of request bodies the endpoint accepts.
+    ///
+    /// A request whose `Content-Type` matches none of them, or that has a body but no
+    /// `Content-Type`, is answered with `415 Unsupported Media Type`. Parameters such as
+    /// `charset` are ignored, and `type/*` accepts any subtype. Requests without a body
+    /// and without a `Content-Type` are always accepted.
     ///
     /// # Arguments
     ///
-    /// * `path` - The path for the endpoint.
-    /// * `request` - The type of HTTP request (GET, POST, etc.).
-    /// * `mapper` - The function that maps a request to a response.
-    pub fn add_endpoint(
-        &mut self,
-        path: &'a str,
+    /// * `media_types` - The accepted media types, such as `"application/json"`.
+    pub fn accepts(mut self, media_types: &[&str]) -> Self {
+        self.accepts = media_types
+            .iter()
+            .map(|media_type| media_type.to_string())
+            .collect();
+        self
+    }
+
+    /// Requires URL query parameters, answering requests missing any of them with
+    /// `400 Bad Request` before middleware for the endpoint or its handler runs.
+    ///
+    /// The body of the response lists the missing names, unless the app has an
+    /// [error handler](App::set_error_handler) for `400`. Names are compared once the query
+    /// is percent-decoded, and a parameter with an empty value, as in `?from=`, counts as
+    /// missing. See [`Request::query_param`] to read and convert the values.
+    ///
+    /// # Arguments
+    ///
+    /// * `names` - The required parameter names, such as `"user_id"`.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use rustic::app::{App, EndpointConfig};
+    /// use rustic::parse_headers::RequestType;
+    /// use rustic::test::TestClient;
+    ///
+    /// let mut application = App::new();
+    /// application.add_endpoint_with_config(
+    ///     "report",
+    ///     RequestType::GET,
+    ///     |request| format!("Report for {}", request.query_param("user_id").value().unwrap()),
+    ///     EndpointConfig::new().require_params(&["user_id", "from"]),
+    /// );
+    /// let client = TestClient::new(application);
+    /// let response = client.get("/report?user_id=7").send();
+    /// assert_eq!(response.status, 400);
+    /// assert_eq!(response.text(), "Bad Request: missing query parameter `from`");
+    /// ```
+    pub fn require_params(mut self, names: &[&str]) -> Self {
+        self.required_params = names.iter().map(|name| name.to_string()).collect();
+        self
+    }
+
+    /// Checks whether the endpoint accepts a request's body.
+    fn accepts_body(&self, request: &Request) -> bool {
+        if self.accepts.is_empty() {
+            return true;
+        }
+        let Some(content_type) = request.header("Content-Type") else {
+            return request.body.is_empty() && !request.has_unbuffered_body();
+        };
+        let essence = essence(content_type);
+        let (kind, subtype) = essence.split_once('/').unwrap_or((essence, ""));
+        self.accepts
+            .iter()
+            .any(|accepted| match accepted.split_once('/') {
+                Some((accepted_kind, "*")) => {
+                    accepted_kind == "*" || accepted_kind.eq_ignore_ascii_case(kind)
+                }
+                _ => accepted.eq_ignore_ascii_case(essence) && !subtype.is_empty(),
+            })
+    }
+}
+
+/// An endpoint being added with [`App::endpoint`].
+pub struct EndpointBuilder<'a> {
+    app: &'a mut App,
+    path: String,
+    methods: Vec<RequestType>,
+    config: EndpointConfig,
+    middleware: Vec<Box<dyn Middleware>>,
+}
+
+impl<'a> EndpointBuilder<'a> {
+    /// Starts an endpoint whose handler runs behind `middleware`.
+    pub(crate) fn new(
+        app: &'a mut App,
+        path: String,
+        request: RequestType,
+        middleware: Vec<Box<dyn Middleware>>,
+    ) -> Self {
+        EndpointBuilder {
+            app,
+            path,
+            methods: vec![request],
+            config: EndpointConfig::new(),
+            middleware,
+        }
+    }
+
+    /// Adds a middleware layer wrapped around this endpoint only.
+    ///
+    /// It runs after the app's middleware and that of the endpoint's
+    /// [scopes](crate::scope::Scope), in registration order, and can short-circuit the
+    /// handler as they can. Requests that match no endpoint never reach it.
+    pub fn middleware(mut self, middleware: impl Middleware + 'static) -> Self {
+        self.middleware.push(Box::new(middleware));
+        self
+    }
+
+    /// Serves `request` as well, with the same handler and settings; see
+    /// [`App::add_endpoint_methods`].
+    pub fn method(mut self, request: RequestType) -> Self {
+        self.methods.push(request);
+        self
+    }
+
+    /// Names the endpoint; see [`EndpointConfig::name`].
+    pub fn name(mut self, name: impl Into<String>) -> Self {
+        self.config = self.config.name(name);
+        self
+    }
+
+    /// Restricts the accepted media types; see [`EndpointConfig::accepts`].
+    pub fn accepts(mut self, media_types: &[&str]) -> Self {
+        self.config = self.config.accepts(media_types);
+        self
+    }
+
+    /// Requires query parameters; see [`EndpointConfig::require_params`].
+    pub fn require_params(mut self, names: &[&str]) -> Self {
+        self.config = self.config.require_params(names);
+        self
+    }
+
+    /// Sets the largest accepted body; see [`EndpointConfig::limit_body`].
+    pub fn limit_body(mut self, bytes: usize) -> Self {
+        self.config = self.config.limit_body(bytes);
+        self
+    }
+
+    /// Sets the body read timeout; see [`EndpointConfig::timeout`].
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.config = self.config.timeout(timeout);
+        self
+    }
+
+    /// Streams the request body to the handler; see [`EndpointConfig::stream_body`].
+    pub fn stream_body(mut self) -> Self {
+        self.config = self.config.stream_body();
+        self
+    }
+
+    /// Sets the size past which bodies are spilled to a file; see
+    /// [`EndpointConfig::spill_threshold`].
+    pub fn spill_threshold(mut self, bytes: usize) -> Self {
+        self.config = self.config.spill_threshold(bytes);
+        self
+    }
+
+    /// Sets the largest response headers; see [`EndpointConfig::limit_response_headers`].
+    pub fn limit_response_headers(mut self, value_bytes: usize, total_bytes: usize) -> Self {
+        self.config = self.config.limit_response_headers(value_bytes, total_bytes);
+        self
+    }
+
+    /// Adds the endpoint with its handler; see [`App::add_endpoint`].
+    pub fn handler<R: IntoHandlerResult>(
+        self,
+        mapper: impl Fn(Request) -> R + Send + Sync + 'static,
+    ) {
+        self.app.routes.insert(
+            self.path,
+            &self.methods,
+            mapper,
+            self.config,
+            self.middleware,
+        );
+    }
+}
+
+/// A shared, interior-mutable endpoint table.
+///
+/// Every clone refers to the same table, so a handle taken with [`App::routes`] before
+/// the server starts can add and remove endpoints while it runs. Changes take effect for
+/// the next request that is routed. Routing only takes a read lock for the duration of
+/// the lookup.
+///
+/// # Examples
+///
+/// ```
+/// use rustic::app::App;
+/// use rustic::header_map::HeaderMap;
+/// use rustic::http11_response::Response;
+/// use rustic::parse_headers::RequestType;
+/// let application = App::new();
+/// let routes = application.routes();
+/// routes.add_endpoint("plugins/hello", RequestType::GET, |_| {
+///     Some(Response {
+///         status_code: 200,
+///         reason: "OK".into(),
+///         response_body: Some("Hello from a plugin".into()),
+///         headers: HeaderMap::new(),
+///     })
+/// });
+/// assert!(routes.remove_endpoint("plugins/hello", RequestType::GET));
+/// assert!(!routes.remove_endpoint("plugins/hello", RequestType::GET));
+/// ```
+#[derive(Clone, Default)]
+pub struct Routes {
+    pub(crate) endpoints: Arc<RwLock<Vec<Endpoint>>>,
+}
+
+impl Routes {
+    /// Adds a new endpoint; see [`App::add_endpoint`].
+    pub fn add_endpoint<R: IntoHandlerResult>(
+        &self,
+        path: impl Into<String>,
+        request: RequestType,
+        mapper: impl Fn(Request) -> R + Send + Sync + 'static,
+    ) {
+        self.add_endpoint_with_config(path, request, mapper, EndpointConfig::new());
+    }
+
+    /// Adds a new endpoint with its own settings; see [`App::add_endpoint_with_config`].
+    pub fn add_endpoint_with_config<R: IntoHandlerResult>(
+        &self,
+        path: impl Into<String>,
+        request: RequestType,
+        mapper: impl Fn(Request) -> R + Send + Sync + 'static,
+        config: EndpointConfig,
+    ) {
+        self.insert(path.into(), &[request], mapper, config, Vec::new());
+    }
+
+    /// Adds one endpoint serving several request types; see
+    /// [`App::add_endpoint_methods`].
+    pub fn add_endpoint_methods<R: IntoHandlerResult>(
+        &self,
+        path: impl Into<String>,
+        methods: &[RequestType],
+        mapper: impl Fn(Request) -> R + Send + Sync + 'static,
+    ) {
+        self.insert(
+            path.into(),
+            methods,
+            mapper,
+            EndpointConfig::new(),
+            Vec::new(),
+        );
+    }
+
+    /// Adds an endpoint serving every request type; see [`App::any`].
+    pub fn add_any<R: IntoHandlerResult>(
+        &self,
+        path: impl Into<String>,
+        mapper: impl Fn(Request) -> R + Send + Sync + 'static,
+    ) {
+        self.insert_any(path.into(), mapper, EndpointConfig::new(), Vec::new());
+    }
+
+    /// Adds an endpoint for each of `methods`, sharing one handler that runs behind its
+    /// own middleware chain.
+    pub(crate) fn insert<R: IntoHandlerResult>(
+        &self,
+        path: String,
+        methods: &[RequestType],
+        mapper: impl Fn(Request) -> R + Send + Sync + 'static,
+        config: EndpointConfig,
+        middleware: Vec<Box<dyn Middleware>>,
+    ) {
+        let methods: Vec<_> = methods.iter().copied().map(Some).collect();
+        self.insert_filtered(path, &methods, mapper, config, middleware);
+    }
+
+    /// Adds an endpoint serving every request type its path gets that no endpoint for
+    /// that type matches, behind its own middleware chain.
+    pub(crate) fn insert_any<R: IntoHandlerResult>(
+        &self,
+        path: String,
+        mapper: impl Fn(Request) -> R + Send + Sync + 'static,
+        config: EndpointConfig,
+        middleware: Vec<Box<dyn Middleware>>,
+    ) {
+        self.insert_filtered(path, &[None], mapper, config, middleware);
+    }
+
+    /// Adds an endpoint for each of `methods`, where `None` stands for any request type.
+    fn insert_filtered<R: IntoHandlerResult>(
+        &self,
+        path: String,
+        methods: &[Option<RequestType>],
+        mapper: impl Fn(Request) -> R + Send + Sync + 'static,
+        mut config: EndpointConfig,
+        middleware: Vec<Box<dyn Middleware>>,
+    ) {
+        check_constraints(&path);
+        config.pattern = path.clone();
+        let mapper: Handler = Arc::new(move |request| mapper(request).into_handler_result());
+        let config = Arc::new(config);
+        let middleware: Arc<[Box<dyn Middleware>]> = middleware.into();
+        let mut endpoints = self.endpoints.write().unwrap();
+        for (position, &request) in methods.iter().enumerate() {
+            if methods[..position].contains(&request) {
+                continue;
+            }
+            // Endpoints match in order, so defaults are kept behind every other endpoint,
+            // and constrained ones ahead of those they narrow down.
+            let index = endpoints
+                .iter()
+                .position(|other| {
+                    other.fallback
+                        || other.request == request
+                            && compare_shapes(&path, &other.path) == Some(Ordering::Greater)
+                })
+                .unwrap_or(endpoints.len());
+            if let Some(shadowing) = endpoints[..index].iter().find(|other| {
+                other.request == request
+                    && compare_shapes(&path, &other.path) == Some(Ordering::Equal)
+            }) {
+                log::warn!(
+                    "Endpoint {} {} is unreachable behind {}, added before it",
+                    request.map_or("ANY", |request| request.as_str()),
+                    path,
+                    shadowing.path
+                );
+            }
+            let endpoint = Endpoint {
+                path: path.clone(),
+                request,
+                mapper: Arc::clone(&mapper),
+                config: Arc::clone(&config),
+                middleware: Arc::clone(&middleware),
+                fallback: false,
+            };
+            endpoints.insert(index, endpoint);
+        }
+    }
+
+    /// Adds a default endpoint, which only answers requests that no other endpoint
+    /// matches, even one added later.
+    pub(crate) fn insert_fallback<R: IntoHandlerResult>(
+        &self,
+        path: String,
         request: RequestType,
-        mapper: fn(Request) -> Option<Response<'a>>,
+        mapper: impl Fn(Request) -> R + Send + Sync + 'static,
     ) {
+        check_constraints(&path);
+        let config = EndpointConfig {
+            pattern: path.clone(),
+            ..EndpointConfig::new()
+        };
         let endpoint = Endpoint {
             path,
-            request,
-            mapper,
+            request: Some(request),
+            mapper: Arc::new(move |request| mapper(request).into_handler_result()),
+            config: Arc::new(config),
+            middleware: Vec::new().into(),
+            fallback: true,
         };
-        self.endpoints.push(endpoint);
+        self.endpoints.write().unwrap().push(endpoint);
+    }
+
+    /// Removes the endpoints registered with exactly this path and request type.
+    ///
+    /// # Arguments
+    ///
+    /// * `path` - The path the endpoint was registered with.
+    /// * `request` - The type of HTTP request the endpoint was registered for.
+    ///
+    /// # Returns
+    ///
+    /// * `bool` - Whether an endpoint was removed.
+    pub fn remove_endpoint(&self, path: &str, request: RequestType) -> bool {
+        let mut endpoints = self.endpoints.write().unwrap();
+        let before = endpoints.len();
+        endpoints.retain(|endpoint| !(endpoint.path == path && endpoint.request == Some(request)));
+        endpoints.len() != before
     }
 
     /// Matches an endpoint based on the path and request type.
@@ -62,76 +920,3645 @@ impl<'a> App<'a> {
     ///
     /// # Returns
     ///
-    /// * `Result<&Endpoint<'a>, &str>` - The matching endpoint or an error message.
+    /// * `Result<Handler, &str>` - The handler of the matching endpoint or an error message.
     pub fn match_endpoint(
         &self,
         path: &str,
         request_type: RequestType,
-    ) -> Result<&Endpoint<'a>, &str> {
-        for endpoint in &self.endpoints {
-            if endpoint.path == path && endpoint.request == request_type {
-                return Ok(endpoint);
-            }
-        }
-        Err("No matching endpoint found")
+    ) -> Result<Handler, &'static str> {
+        self.find(path, request_type, false)
+            .map(|matched| matched.handler)
+            .ok_or("No matching endpoint found")
     }
-}
 
-/// Runs the application, listening for incoming connections and handling requests.
-///
-/// # Arguments
-///
-/// * `app` - The application instance.
-/// * `port` - The port to listen on.
-/// * `verbose` - Whether to print verbose output.
-pub fn run(app: App<'static>, port: u16, verbose: bool) {
-    let listener = listen_at_port(port);
-    if verbose {
-        println!("Listening at port {:?}", port);
+    /// Lists the endpoints in the order they are matched in, each request type of an
+    /// endpoint serving several as an entry of its own.
+    ///
+    /// # Returns
+    ///
+    /// * `Vec<RouteInfo>` - The method, pattern and name of each endpoint.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use rustic::app::{App, EndpointConfig};
+    /// use rustic::parse_headers::RequestType;
+    ///
+    /// let mut application = App::new();
+    /// application.get("users", |_| "Users");
+    /// application.add_endpoint_with_config(
+    ///     "users/{id:u64}",
+    ///     RequestType::GET,
+    ///     |_| "User",
+    ///     EndpointConfig::new().name("user"),
+    /// );
+    /// let routes = application.routes().list();
+    /// assert_eq!(routes.len(), 2);
+    /// assert_eq!(routes[1].pattern, "users/{id:u64}");
+    /// assert_eq!(routes[1].method, Some(RequestType::GET));
+    /// assert_eq!(routes[1].name.as_deref(), Some("user"));
+    /// ```
+    pub fn list(&self) -> Vec<RouteInfo> {
+        self.endpoints
+            .read()
+            .unwrap()
+            .iter()
+            .map(|endpoint| RouteInfo {
+                method: endpoint.request,
+                pattern: endpoint.path.clone(),
+                name: endpoint.config.name.clone(),
+            })
+            .collect()
     }
 
-    let app = Arc::new(app);
+    /// Finds the endpoint for a request along with the path parameters it captured.
+    fn find(&self, path: &str, request_type: RequestType, ignore_case: bool) -> Option<Matched> {
+        let endpoints = self.endpoints.read().unwrap();
+        let endpoint = select(&endpoints, path, request_type, ignore_case)?;
+        match_path(&endpoint.path, path, ignore_case).map(|params| Matched {
+            handler: Arc::clone(&endpoint.mapper),
+            params: PathParamMap(params),
+            config: Arc::clone(&endpoint.config),
+            middleware: Arc::clone(&endpoint.middleware),
+        })
+    }
 
-    for stream in listener.incoming() {
-        match stream {
-            Ok(mut stream) => {
-                let app_clone = Arc::clone(&app);
-
-                thread::spawn(move || {
-                    let (headers, body) = handle_connection(&mut stream);
-                    let (request_type, _, headers_map, url) = parse_headers(headers).unwrap();
-
-                    if let Some(url) = url {
-                        let url_str = url.as_str();
-                        let url_params = parse_url_param(url_str);
-                        let path = parse_path(url_str).unwrap();
-
-                        match app_clone.match_endpoint(path, request_type) {
-                            Ok(endpoint) => {
-                                let request = Request {
-                                    headers: headers_map,
-                                    body,
-                                    url_params,
-                                };
-
-                                if let Some(response) = (endpoint.mapper)(request) {
-                                    write_connection(&mut stream, response);
-                                }
-                            }
-                            Err(err) => {
-                                if verbose {
-                                    eprintln!("Error matching endpoint: {}", err);
-                                }
-                            }
+    /// Finds the settings of the endpoint matching the request, without collecting its
+    /// path parameters.
+    fn find_config(
+        &self,
+        path: &str,
+        request_type: RequestType,
+        ignore_case: bool,
+    ) -> Option<Arc<EndpointConfig>> {
+        let endpoints = self.endpoints.read().unwrap();
+        select(&endpoints, path, request_type, ignore_case)
+            .map(|endpoint| Arc::clone(&endpoint.config))
+    }
+
+    /// Lists the request types registered for paths matching `path`.
+    fn allowed_methods(&self, path: &str, ignore_case: bool) -> Vec<RequestType> {
+        let mut methods = Vec::new();
+        for endpoint in self.endpoints.read().unwrap().iter() {
+            if let Some(request) = endpoint.request {
+                if path_matches(&endpoint.path, path, ignore_case) && !methods.contains(&request) {
+                    methods.push(request);
+                }
+            }
+        }
+        methods
+    }
+}
+
+/// How routing treats a trailing slash on the request path.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub enum TrailingSlash {
+    /// `/users/` and `/users` are the same path; endpoints are registered without the
+    /// trailing slash.
+    #[default]
+    MergeSlashes,
+    /// Each path has one canonical form, the one registered. A request for the other
+    /// form is answered with `301 Moved Permanently` pointing to it.
+    RedirectToCanonical,
+    /// `/users/` and `/users` are distinct, so `users/` and `users` can be registered as
+    /// separate endpoints.
+    Strict,
+}
+
+/// The body format of the error responses the framework generates.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub enum ErrorFormat {
+    /// The reason phrase as plain text, such as `Not Found`.
+    #[default]
+    Text,
+    /// A JSON object for API clients, such as
+    /// `{"error":{"code":404,"message":"Not Found"}}`, sent as `application/json`.
+    Json,
+    /// JSON when the request's `Accept` header prefers `application/json` to
+    /// `text/plain`, and plain text otherwise, including for `*/*` and when there is no
+    /// request to look at.
+    Negotiate,
+}
+
+/// What the server does with a `POST`, `PUT` or `PATCH` request that has neither a
+/// `Content-Length` nor a `Transfer-Encoding` header.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub enum MissingLength {
+    /// Answer `411 Length Required`, so a body is never silently dropped.
+    #[default]
+    Reject,
+    /// Read the body until the client closes the connection, as HTTP/1.0 clients may.
+    /// This only applies to HTTP/1.0 requests and requests with `Connection: close`;
+    /// others are still answered with `411 Length Required`.
+    ReadUntilClose,
+}
+
+/// The deviations from the request syntax of RFC 9112 that the server accepts, for
+/// [`ServerConfig::leniency`].
+///
+/// Each request exercising an accepted deviation is logged at debug level, so clients
+/// relying on one can be found. Requests exercising a refused one are answered with
+/// `400 Bad Request`, or `501 Not Implemented` for a method in the wrong case, and
+/// their connection is closed.
+///
+/// The default is [`Leniency::STRICT`], refusing every deviation, so clients that rely
+/// on one have to be opted in to explicitly.
+///
+/// # Examples
+///
+/// ```
+/// use rustic::app::{Leniency, ServerConfig};
+///
+/// // An old proxy sends `get /x HTTP/1.1`, but every other deviation is refused.
+/// let config = ServerConfig::new().leniency(Leniency {
+///     lowercase_methods: true,
+///     ..Leniency::STRICT
+/// });
+/// ```
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct Leniency {
+    /// Match methods regardless of case, so `get` is served as `GET`.
+    pub lowercase_methods: bool,
+    /// Accept request targets in absolute form, such as `GET http://host/x HTTP/1.1`,
+    /// from methods other than `CONNECT`. Proxies send them, and RFC 9112 requires
+    /// servers to accept them, so enable this when the server is reached through a
+    /// forward proxy.
+    pub absolute_form: bool,
+    /// Accept header lines without whitespace after the colon, such as `Host:a`.
+    pub missing_space: bool,
+    /// Accept lines ending in a bare LF rather than CRLF, in the head and in the framing
+    /// of a chunked body alike.
+    pub bare_lf: bool,
+}
+
+impl Leniency {
+    /// Refuses every deviation, which is the default.
+    pub const STRICT: Leniency = Leniency {
+        lowercase_methods: false,
+        absolute_form: false,
+        missing_space: false,
+        bare_lf: false,
+    };
+    /// Accepts every deviation.
+    pub const ALL: Leniency = Leniency {
+        lowercase_methods: true,
+        absolute_form: true,
+        missing_space: true,
+        bare_lf: true,
+    };
+}
+
+/// A handler producing the response for an error status generated by the framework.
+pub type ErrorHandler = Box<dyn Fn(Request) -> Response + Send + Sync>;
+
+/// Represents the application with multiple endpoints.
+pub struct App {
+    pub(crate) routes: Routes,
+    pub middleware: Vec<Box<dyn Middleware>>,
+    /// The metrics registry, once [`App::enable_metrics_endpoint`] has been called.
+    pub(crate) metrics: Option<Arc<Metrics>>,
+    error_handlers: HashMap<u16, ErrorHandler>,
+    intercept_handler_errors: bool,
+    trailing_slash: TrailingSlash,
+    case_insensitive_routing: bool,
+    error_format: ErrorFormat,
+    /// What approves `CONNECT` tunnels, once [`App::on_connect`] has been called.
+    pub(crate) connect_handler: Option<ConnectHandler>,
+    /// Whether `TRACE` requests are echoed, once [`App::enable_trace`] has been called.
+    pub(crate) trace_enabled: bool,
+    /// The threads running [`App::spawn_background`] tasks.
+    pub(crate) tasks: Tasks,
+    /// The jobs registered with [`App::schedule`].
+    pub(crate) scheduled: Vec<ScheduledJob>,
+    /// The hooks added with [`App::on_start`].
+    pub(crate) start_hooks: Vec<StartHook>,
+    /// The hooks added with [`App::on_shutdown`].
+    pub(crate) shutdown_hooks: Vec<ShutdownHook>,
+}
+
+impl Default for App {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl App {
+    /// Creates a new instance of the application.
+    pub fn new() -> Self {
+        App {
+            routes: Routes::default(),
+            middleware: vec![],
+            metrics: None,
+            error_handlers: HashMap::new(),
+            intercept_handler_errors: false,
+            trailing_slash: TrailingSlash::default(),
+            case_insensitive_routing: false,
+            error_format: ErrorFormat::default(),
+            connect_handler: None,
+            trace_enabled: false,
+            tasks: Tasks::new(),
+            scheduled: Vec::new(),
+            start_hooks: Vec::new(),
+            shutdown_hooks: Vec::new(),
+        }
+    }
+
+    /// Adds a new endpoint to the application.
+    ///
+    /// A path ending in `/*` matches every path below that prefix, as well as the
+    /// prefix itself. A `{name}` segment matches any single segment, which handlers read
+    /// with [`Request::path_param`]. Handlers taking typed extractors instead of the
+    /// request are registered through [`with_extractors`](crate::extract::with_extractors).
+    ///
+    /// A `{name:kind}` segment only matches segments of that kind, letting requests
+    /// that do not fit fall through to the next endpoint:
+    ///
+    /// * `u64` - An unsigned integer, which [`Request::path_param_as`] reads as a `u64`.
+    /// * `uuid` - A UUID in its hyphenated form, in either case.
+    /// * `alpha` - ASCII letters only.
+    ///
+    /// Endpoints are tried in the order they were added, except that one constraining a
+    /// segment goes ahead of those for the same method that would match the same paths
+    /// without it, such as `users/{id:u64}` ahead of `users/{name}`. An endpoint that can
+    /// never match, because one added before it has the same path up to the names of its
+    /// parameters, is logged as a warning.
+    ///
+    /// # Arguments
+    ///
+    /// * `path` - The path for the endpoint, either a literal or a `String` built at runtime.
+    /// * `request` - The type of HTTP request (GET, POST, etc.).
+    /// * `mapper` - The function or closure that maps a request to a response. It may
+    ///   return any [`IntoResponse`] type, an `Option` of one, where `None` answers the
+    ///   request with `404 Not Found`, or a `Result` whose error converts into the
+    ///   response to send; see [`IntoHandlerResult`].
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use rustic::app::App;
+    /// use rustic::header_map::HeaderMap;
+    /// use rustic::http11_response::Response;
+    /// use rustic::parse_headers::RequestType;
+    /// fn ok(_: rustic::app::Request) -> Option<Response> {
+    ///     Some(Response {
+    ///         status_code: 200,
+    ///         reason: "OK".into(),
+    ///         response_body: None,
+    ///         headers: HeaderMap::new(),
+    ///     })
+    /// }
+    /// let version = 2;
+    /// let mut application = App::new();
+    /// application.add_endpoint("health", RequestType::GET, ok);
+    /// application.add_endpoint(format!("v{}/users", version), RequestType::GET, ok);
+    /// assert!(application.match_endpoint("v2/users", RequestType::GET).is_ok());
+    /// ```
+    ///
+    /// # Panics
+    ///
+    /// Panics when a `{name:kind}` segment names no known kind.
+    pub fn add_endpoint<R: IntoHandlerResult>(
+        &mut self,
+        path: impl Into<String>,
+        request: RequestType,
+        mapper: impl Fn(Request) -> R + Send + Sync + 'static,
+    ) {
+        self.routes.add_endpoint(path, request, mapper);
+    }
+
+    /// Adds one endpoint serving several request types with the same handler, which
+    /// tells them apart with [`Request::method`].
+    ///
+    /// The endpoint answers each of `methods` as if it were added for it alone with
+    /// [`App::add_endpoint`], and they are all listed in the `Allow` header of a
+    /// `405 Method Not Allowed` for its path. The handler is shared rather than copied
+    /// for each request type. With [`App::endpoint`], the same is done by adding request
+    /// types with [`EndpointBuilder::method`].
+    ///
+    /// # Arguments
+    ///
+    /// * `path` - The path for the endpoint; see [`App::add_endpoint`].
+    /// * `methods` - The types of HTTP request the endpoint serves.
+    /// * `mapper` - The function or closure that maps a request to a response.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use rustic::app::App;
+    /// use rustic::parse_headers::RequestType;
+    /// use rustic::test::TestClient;
+    ///
+    /// let mut application = App::new();
+    /// application.add_endpoint_methods(
+    ///     "settings",
+    ///     &[RequestType::POST, RequestType::PUT],
+    ///     |request| format!("Saved with {}", request.method.as_str()),
+    /// );
+    /// let client = TestClient::new(application);
+    /// assert_eq!(client.put("/settings").send().text(), "Saved with PUT");
+    /// let response = client.delete("/settings").send();
+    /// assert_eq!(response.header("Allow"), Some("POST, PUT"));
+    /// ```
+    pub fn add_endpoint_methods<R: IntoHandlerResult>(
+        &mut self,
+        path: impl Into<String>,
+        methods: &[RequestType],
+        mapper: impl Fn(Request) -> R + Send + Sync + 'static,
+    ) {
+        self.routes.add_endpoint_methods(path, methods, mapper);
+    }
+
+    /// Adds a new endpoint with its own settings, such as the content types it accepts.
+    ///
+    /// # Arguments
+    ///
+    /// * `path` - The path for the endpoint; see [`App::add_endpoint`].
+    /// * `request` - The type of HTTP request (GET, POST, etc.).
+    /// * `mapper` - The function or closure that maps a request to a response.
+    /// * `config` - The settings checked before the handler runs; see [`EndpointConfig`].
+    pub fn add_endpoint_with_config<R: IntoHandlerResult>(
+        &mut self,
+        path: impl Into<String>,
+        request: RequestType,
+        mapper: impl Fn(Request) -> R + Send + Sync + 'static,
+        config: EndpointConfig,
+    ) {
+        self.routes
+            .add_endpoint_with_config(path, request, mapper, config);
+    }
+
+    /// Starts adding an endpoint with its own settings, which are set on the returned
+    /// builder before its handler; see [`App::add_endpoint_with_config`].
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use rustic::app::App;
+    /// use rustic::parse_headers::RequestType;
+    /// use std::time::Duration;
+    ///
+    /// let mut application = App::new();
+    /// application
+    ///     .endpoint("upload", RequestType::POST)
+    ///     .name("upload")
+    ///     .limit_body(10 << 20)
+    ///     .timeout(Duration::from_secs(30))
+    ///     .handler(|request| format!("Stored {} bytes", request.body.len()));
+    /// ```
+    pub fn endpoint(
+        &mut self,
+        path: impl Into<String>,
+        request: RequestType,
+    ) -> EndpointBuilder<'_> {
+        EndpointBuilder::new(self, path.into(), request, Vec::new())
+    }
+
+    /// Adds an endpoint serving every request type, for catch-all handlers such as
+    /// webhooks or proxies, which tell the types apart with [`Request::method`].
+    ///
+    /// Endpoints added for a request type take precedence over it whenever they match,
+    /// whether added before or after it, so it only gets the requests they leave. A path
+    /// it matches is never answered with `405 Method Not Allowed`, leaving the handler to
+    /// refuse request types it does not serve, and to answer `OPTIONS` with an `Allow`
+    /// header if it wants to. `CONNECT` and `TRACE` requests still go to
+    /// [`App::on_connect`] and [`App::enable_trace`] when they are set up.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use rustic::app::App;
+    /// use rustic::test::TestClient;
+    ///
+    /// let mut application = App::new();
+    /// application.any("hook/github", |request| {
+    ///     format!("Logged {}", request.method.as_str())
+    /// });
+    /// application.get("hook/github", |_| "Webhook endpoint");
+    /// let client = TestClient::new(application);
+    /// assert_eq!(client.get("/hook/github").send().text(), "Webhook endpoint");
+    /// assert_eq!(client.delete("/hook/github").send().text(), "Logged DELETE");
+    /// ```
+    pub fn any<R: IntoHandlerResult>(
+        &mut self,
+        path: impl Into<String>,
+        mapper: impl Fn(Request) -> R + Send + Sync + 'static,
+    ) {
+        self.routes.add_any(path, mapper);
+    }
+
+    /// Adds a `GET` endpoint; see [`App::add_endpoint`].
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use rustic::app::App;
+    /// let mut application = App::new();
+    /// application.get("hello", |_| "hi");
+    /// ```
+    pub fn get<R: IntoHandlerResult>(
+        &mut self,
+        path: impl Into<String>,
+        mapper: impl Fn(Request) -> R + Send + Sync + 'static,
+    ) {
+        self.add_endpoint(path, RequestType::GET, mapper);
+    }
+
+    /// Adds a `POST` endpoint; see [`App::add_endpoint`].
+    pub fn post<R: IntoHandlerResult>(
+        &mut self,
+        path: impl Into<String>,
+        mapper: impl Fn(Request) -> R + Send + Sync + 'static,
+    ) {
+        self.add_endpoint(path, RequestType::POST, mapper);
+    }
+
+    /// Adds a `PUT` endpoint; see [`App::add_endpoint`].
+    pub fn put<R: IntoHandlerResult>(
+        &mut self,
+        path: impl Into<String>,
+        mapper: impl Fn(Request) -> R + Send + Sync + 'static,
+    ) {
+        self.add_endpoint(path, RequestType::PUT, mapper);
+    }
+
+    /// Adds a `PATCH` endpoint; see [`App::add_endpoint`].
+    pub fn patch<R: IntoHandlerResult>(
+        &mut self,
+        path: impl Into<String>,
+        mapper: impl Fn(Request) -> R + Send + Sync + 'static,
+    ) {
+        self.add_endpoint(path, RequestType::PATCH, mapper);
+    }
+
+    /// Adds a `DELETE` endpoint; see [`App::add_endpoint`].
+    pub fn delete<R: IntoHandlerResult>(
+        &mut self,
+        path: impl Into<String>,
+        mapper: impl Fn(Request) -> R + Send + Sync + 'static,
+    ) {
+        self.add_endpoint(path, RequestType::DELETE, mapper);
+    }
+
+    /// Returns a handle to the endpoint table that stays usable after `run` takes the app.
+    pub fn routes(&self) -> Routes {
+        self.routes.clone()
+    }
+
+    /// Matches an endpoint based on the path and request type.
+    ///
+    /// # Arguments
+    ///
+    /// * `path` - The path to match.
+    /// * `request_type` - The type of HTTP request (GET, POST, etc.).
+    ///
+    /// # Returns
+    ///
+    /// * `Result<Handler, &str>` - The handler of the matching endpoint or an error message.
+    pub fn match_endpoint(
+        &self,
+        path: &str,
+        request_type: RequestType,
+    ) -> Result<Handler, &'static str> {
+        self.routes.match_endpoint(path, request_type)
+    }
+
+    /// Adds a middleware layer wrapped around every request.
+    ///
+    /// Middleware runs in registration order: the first one added sees the request
+    /// first and the response last. Middleware attached to a [`Scope`](crate::scope::Scope)
+    /// or through [`EndpointBuilder::middleware`] runs after it, once the request is routed.
+    ///
+    /// Responses the framework generates go through it too: `404 Not Found` and
+    /// `405 Method Not Allowed`, and the errors answering requests refused once their
+    /// head is read, such as `413 Content Too Large`. Only requests whose head cannot be
+    /// read or is refused outright, such as a malformed request line, are answered
+    /// without it, and then only counted in the metrics.
+    ///
+    /// # Arguments
+    ///
+    /// * `middleware` - The layer to add.
+    pub fn add_middleware(&mut self, middleware: impl Middleware + 'static) {
+        self.middleware.push(Box::new(middleware));
+    }
+
+    /// Sets the handler producing responses for an error status the framework generates.
+    ///
+    /// The framework generates `404 Not Found` for routing misses and handlers returning
+    /// `None`, `405 Method Not Allowed` when the path exists for other methods only, and
+    /// `500 Internal Server Error` when a handler panics. The error handler receives the
+    /// request without its body, and its response is sent as is. Statuses without an
+    /// error handler keep the default body, in the format set with [`App::error_format`].
+    ///
+    /// # Arguments
+    ///
+    /// * `status` - The status code to customise.
+    /// * `handler` - The function building the response.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use rustic::app::App;
+    /// use rustic::header_map::HeaderMap;
+    /// use rustic::http11_response::Response;
+    /// let mut application = App::new();
+    /// application.set_error_handler(404, |request| {
+    ///     let mut headers = HeaderMap::new();
+    ///     headers.insert("Content-Type", "text/html");
+    ///     Response {
+    ///         status_code: 404,
+    ///         reason: "Not Found".into(),
+    ///         response_body: Some(format!("<h1>No page at /{}</h1>", request.path).into()),
+    ///         headers,
+    ///     }
+    /// });
+    /// ```
+    pub fn set_error_handler(
+        &mut self,
+        status: u16,
+        handler: impl Fn(Request) -> Response + Send + Sync + 'static,
+    ) {
+        self.error_handlers.insert(status, Box::new(handler));
+    }
+
+    /// Sets whether error handlers also replace error responses returned by endpoints.
+    ///
+    /// By default a response an endpoint builds itself is never rewritten, whatever its
+    /// status.
+    pub fn intercept_handler_errors(&mut self, intercept: bool) {
+        self.intercept_handler_errors = intercept;
+    }
+
+    /// Sets how routing treats a trailing slash on the request path.
+    ///
+    /// The policy applies to the last segment only: with `{name}` segments and `/*`
+    /// wildcards, `users/{id}/` is a distinct pattern from `users/{id}` under
+    /// [`TrailingSlash::Strict`], and a wildcard matches paths below it with or without a
+    /// trailing slash in every mode.
+    ///
+    /// # Arguments
+    ///
+    /// * `policy` - The policy to use; [`TrailingSlash::MergeSlashes`] by default.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use rustic::app::{App, TrailingSlash};
+    /// let mut application = App::new();
+    /// application.trailing_slash(TrailingSlash::RedirectToCanonical);
+    /// application.get("docs/", |_| "Documentation index");
+    /// application.get("users", |_| "All users");
+    /// ```
+    pub fn trailing_slash(&mut self, policy: TrailingSlash) {
+        self.trailing_slash = policy;
+    }
+
+    /// Sets whether routing ignores the case of the request path.
+    ///
+    /// Only the comparison is affected: [`Request::path`] and captured path parameters
+    /// keep the casing the client sent, and query parameter keys are left as they are.
+    ///
+    /// # Arguments
+    ///
+    /// * `enabled` - Whether `/API/Users` should match an endpoint registered as
+    ///   `api/users`; off by default.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use rustic::app::App;
+    /// let mut application = App::new();
+    /// application.case_insensitive_routing(true);
+    /// application.get("api/users/{name}", |request| {
+    ///     format!("Hello, {}", request.path_param("name").unwrap_or_default())
+    /// });
+    /// ```
+    pub fn case_insensitive_routing(&mut self, enabled: bool) {
+        self.case_insensitive_routing = enabled;
+    }
+
+    /// Sets the body format of the error responses the framework generates, such as
+    /// `404 Not Found` for routing misses or `413 Payload Too Large`.
+    ///
+    /// Error handlers set with [`App::set_error_handler`] still take precedence for their
+    /// status, and error responses returned by endpoints are left as they are.
+    ///
+    /// # Arguments
+    ///
+    /// * `format` - The format to use; [`ErrorFormat::Text`] by default.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use rustic::app::{App, ErrorFormat};
+    /// let mut application = App::new();
+    /// application.error_format(ErrorFormat::Negotiate);
+    /// application.get("api/users", |_| "[]");
+    /// ```
+    pub fn error_format(&mut self, format: ErrorFormat) {
+        self.error_format = format;
+    }
+
+    /// Builds the response for an error status generated by the framework.
+    ///
+    /// The configured error handler is used when there is one and a request to give it.
+    pub(crate) fn error_response(&self, status: u16, request: Option<Request>) -> Response {
+        self.error_response_with(status, reason_phrase(status), request)
+    }
+
+    /// Builds the response for an error status like [`App::error_response`], with
+    /// `message` in place of the reason phrase when there is no error handler to use.
+    fn error_response_with(
+        &self,
+        status: u16,
+        message: &'static str,
+        request: Option<Request>,
+    ) -> Response {
+        match (self.error_handlers.get(&status), request) {
+            (Some(handler), Some(request)) => handler(request),
+            (_, request) => {
+                let json = match self.error_format {
+                    ErrorFormat::Text => false,
+                    ErrorFormat::Json => true,
+                    ErrorFormat::Negotiate => request.is_some_and(|request| {
+                        request.negotiate(&["text/plain", "application/json"])
+                            == Some("application/json")
+                    }),
+                };
+                let mut response = default_error_response(status, message, json);
+                if self.error_format == ErrorFormat::Negotiate {
+                    response.headers.insert("Vary", "Accept");
+                }
+                response
+            }
+        }
+    }
+
+    /// Routes a request through the middleware chain to its endpoint.
+    pub(crate) fn dispatch(&self, request: Request) -> Response {
+        let endpoint = |request: Request| self.route(request);
+        Next::new(&self.middleware, &endpoint).run(request)
+    }
+
+    /// Answers a request refused before routing, such as one whose body is too large,
+    /// with the error response for `status`, through the middleware chain like any
+    /// other request. Middleware then adds its headers, such as those of CORS, and logs
+    /// the request, though it may also answer it before the error response is built.
+    ///
+    /// `message` replaces the reason phrase in the default error response.
+    pub(crate) fn dispatch_error(
+        &self,
+        status: u16,
+        message: &'static str,
+        request: Request,
+    ) -> Response {
+        let endpoint = |request: Request| self.error_response_with(status, message, Some(request));
+        Next::new(&self.middleware, &endpoint).run(request)
+    }
+
+    /// Answers a request with the `503` of [`Maintenance`] instead of routing it, through
+    /// the middleware chain like any other request.
+    fn dispatch_maintenance(&self, maintenance: &Maintenance, request: Request) -> Response {
+        let endpoint = |_: Request| maintenance.response();
+        Next::new(&self.middleware, &endpoint).run(request)
+    }
+
+    /// Returns the path a request is routed by, which keeps its trailing slash unless
+    /// slashes are merged.
+    fn routing_path<'a>(&self, path: &'a str, url: &str) -> Cow<'a, str> {
+        match self.trailing_slash {
+            TrailingSlash::MergeSlashes => Cow::Borrowed(path),
+            _ if has_trailing_slash(url) => Cow::Owned(format!("{}/", path)),
+            _ => Cow::Borrowed(path),
+        }
+    }
+
+    /// Finds the settings of the endpoint a request is for, before its body is read and
+    /// middleware runs.
+    fn endpoint_config(&self, method: RequestType, url: &str) -> Option<Arc<EndpointConfig>> {
+        let path = self.routing_path(parse_path(url).unwrap_or(""), url);
+        self.routes
+            .find_config(&path, method, self.case_insensitive_routing)
+    }
+
+    /// Runs the endpoint matching a request, or generates the error response for it.
+    fn route(&self, mut request: Request) -> Response {
+        if let (RequestType::CONNECT, Some(handler)) = (request.method, &self.connect_handler) {
+            return self.connect_tunnel(handler, request);
+        }
+        if request.method == RequestType::TRACE && self.trace_enabled {
+            return trace_response(&request);
+        }
+        let trailing = has_trailing_slash(&request.url);
+        let path = self.routing_path(&request.path, &request.url);
+        let ignore_case = self.case_insensitive_routing;
+        let matched = match self.routes.find(&path, request.method, ignore_case) {
+            Some(matched) => {
+                if !matched.config.accepts_body(&request) {
+                    return self.error_response(415, Some(request));
+                }
+                let missing = missing_params(&request.url, &matched.config.required_params);
+                if !missing.is_empty() {
+                    return self.missing_params_response(&missing, request);
+                }
+                matched
+            }
+            None => {
+                log::debug!(
+                    "No endpoint for {} /{} from {}",
+                    request.method.as_str(),
+                    request.path,
+                    Peer(request.remote_addr)
+                );
+                let allowed = self.routes.allowed_methods(&path, ignore_case);
+                if allowed.is_empty() {
+                    if self.trailing_slash == TrailingSlash::RedirectToCanonical
+                        && !request.path.is_empty()
+                    {
+                        let canonical = if trailing {
+                            request.path.clone()
+                        } else {
+                            format!("{}/", request.path)
+                        };
+                        if self
+                            .routes
+                            .find(&canonical, request.method, ignore_case)
+                            .is_some()
+                        {
+                            return redirect_to(&canonical, &request.url);
                         }
                     }
-                });
+                    return self.error_response(404, Some(request));
+                }
+                let allow = allowed
+                    .iter()
+                    .map(RequestType::as_str)
+                    .collect::<Vec<_>>()
+                    .join(", ");
+                let mut response = self.error_response(405, Some(request));
+                response.headers.insert("Allow", allow);
+                return response;
             }
-            Err(e) => {
-                if verbose {
-                    eprintln!("Error accepting connection: {}", e);
+        };
+
+        request.extensions.insert(matched.params);
+        let handler = matched.handler;
+        let endpoint = |request: Request| self.run_handler(&handler, request);
+        Next::new(&matched.middleware, &endpoint).run(request)
+    }
+
+    /// Generates the `400 Bad Request` response for a request missing the query
+    /// parameters its endpoint requires, naming them unless an error handler takes over.
+    fn missing_params_response(&self, missing: &[&str], request: Request) -> Response {
+        log::debug!(
+            "Missing query parameters {:?} for {} /{} from {}",
+            missing,
+            request.method.as_str(),
+            request.path,
+            Peer(request.remote_addr)
+        );
+        if self.error_handlers.contains_key(&400) {
+            return self.error_response(400, Some(request));
+        }
+        let names = missing
+            .iter()
+            .map(|name| format!("`{}`", name))
+            .collect::<Vec<_>>()
+            .join(", ");
+        let plural = if missing.len() > 1 { "s" } else { "" };
+        HttpError::bad_request(format!(
+            "Bad Request: missing query parameter{} {}",
+            plural, names
+        ))
+        .into()
+    }
+
+    /// Runs an endpoint's handler, generating the error response for a handler that
+    /// panics or returns `None`.
+    fn run_handler(&self, handler: &Handler, request: Request) -> Response {
+        let needs_request =
+            !self.error_handlers.is_empty() || self.error_format == ErrorFormat::Negotiate;
+        let snapshot = needs_request.then(|| request.snapshot());
+        let method = request.method;
+        let path = request.path.clone();
+        let peer = Peer(request.remote_addr);
+        match panic::catch_unwind(AssertUnwindSafe(|| handler(request))) {
+            Ok(Some(response)) => {
+                let status = response.status_code;
+                if self.intercept_handler_errors
+                    && status >= 400
+                    && self.error_handlers.contains_key(&status)
+                {
+                    self.error_response(status, snapshot)
+                } else {
+                    response
                 }
             }
+            Ok(None) => self.error_response(404, snapshot),
+            Err(_) => {
+                log::error!(
+                    "Handler for {} /{} from {} panicked",
+                    method.as_str(),
+                    path,
+                    peer
+                );
+                self.error_response(500, snapshot)
+            }
+        }
+    }
+}
+
+/// Formats the peer address of a request for log messages, or `-` when it is unknown.
+struct Peer(Option<SocketAddr>);
+
+impl fmt::Display for Peer {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.0 {
+            Some(address) => write!(f, "{}", address),
+            None => f.write_str("-"),
+        }
+    }
+}
+
+/// Picks the endpoint answering a request among those matching its path.
+///
+/// Endpoints for the request type are tried before those added with [`App::any`], and
+/// both before defaults, each in the order of the table.
+fn select<'a>(
+    endpoints: &'a [Endpoint],
+    path: &str,
+    request_type: RequestType,
+    ignore_case: bool,
+) -> Option<&'a Endpoint> {
+    let mut best: Option<(u8, &Endpoint)> = None;
+    for endpoint in endpoints {
+        if endpoint
+            .request
+            .is_some_and(|request| request != request_type)
+        {
+            continue;
+        }
+        let rank = u8::from(endpoint.fallback) * 2 + u8::from(endpoint.request.is_none());
+        if best.is_some_and(|(best, _)| best <= rank)
+            || !path_matches(&endpoint.path, path, ignore_case)
+        {
+            continue;
+        }
+        best = Some((rank, endpoint));
+        if rank == 0 {
+            break;
+        }
+    }
+    best.map(|(_, endpoint)| endpoint)
+}
+
+/// Checks whether a request path matches a registered endpoint path.
+fn path_matches(pattern: &str, path: &str, ignore_case: bool) -> bool {
+    match_segments(pattern, path, ignore_case, |_, _| {})
+}
+
+/// Matches a request path against a registered endpoint path, segment by segment.
+///
+/// A `{name}` segment matches any non-empty segment and captures it under `name`, and a
+/// `{name:kind}` segment only one satisfying the [`Constraint`] of that kind. A
+/// trailing `*` segment matches any remainder, including an empty one. With
+/// `ignore_case`, literal segments are compared after lowercasing both sides, while
+/// captured segments keep the casing of the request.
+///
+/// # Returns
+///
+/// * `Option<HashMap<String, String>>` - The captured parameters if the path matches.
+fn match_path(pattern: &str, path: &str, ignore_case: bool) -> Option<HashMap<String, String>> {
+    let mut params = HashMap::new();
+    match_segments(pattern, path, ignore_case, |name, segment| {
+        params.insert(name.to_string(), segment.to_string());
+    })
+    .then_some(params)
+}
+
+/// Matches a request path against an endpoint path segment by segment, passing each
+/// path parameter to `param` as it is found, even when a later segment fails to match.
+fn match_segments<'a>(
+    pattern: &'a str,
+    path: &'a str,
+    ignore_case: bool,
+    mut param: impl FnMut(&'a str, &'a str),
+) -> bool {
+    let mut segments = path.split('/');
+    for part in pattern.split('/') {
+        if part == "*" {
+            return true;
+        }
+        let Some(segment) = segments.next() else {
+            return false;
+        };
+        match param_segment(part) {
+            Some((name, constraint))
+                if !segment.is_empty()
+                    && constraint.is_none_or(|constraint| constraint.accepts(segment)) =>
+            {
+                param(name, segment)
+            }
+            None if part == segment => {}
+            None if ignore_case && eq_lowercase(part, segment) => {}
+            _ => return false,
+        }
+    }
+    segments.next().is_none()
+}
+
+/// What a `{name:kind}` path segment requires of the request segment it matches.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Constraint {
+    /// `u64`: an unsigned integer that fits in a `u64`.
+    U64,
+    /// `uuid`: a UUID in its hyphenated form, such as
+    /// `67e55044-10b1-426f-9247-bb680e5fe0c8`, in either case.
+    Uuid,
+    /// `alpha`: one or more ASCII letters.
+    Alpha,
+}
+
+impl Constraint {
+    /// Looks up the constraint of a kind.
+    fn from_kind(kind: &str) -> Option<Self> {
+        match kind {
+            "u64" => Some(Constraint::U64),
+            "uuid" => Some(Constraint::Uuid),
+            "alpha" => Some(Constraint::Alpha),
+            _ => None,
+        }
+    }
+
+    /// Checks whether a request segment satisfies the constraint.
+    fn accepts(self, segment: &str) -> bool {
+        match self {
+            Constraint::U64 => {
+                segment.bytes().all(|byte| byte.is_ascii_digit()) && segment.parse::<u64>().is_ok()
+            }
+            Constraint::Uuid => {
+                segment.len() == 36
+                    && segment
+                        .bytes()
+                        .enumerate()
+                        .all(|(index, byte)| match index {
+                            8 | 13 | 18 | 23 => byte == b'-',
+                            _ => byte.is_ascii_hexdigit(),
+                        })
+            }
+            Constraint::Alpha => segment.bytes().all(|byte| byte.is_ascii_alphabetic()),
         }
     }
 }
+
+/// Splits a `{name}` or `{name:kind}` endpoint path segment into the name and the
+/// constraint, or returns `None` for a literal segment. An unknown kind leaves the
+/// segment matching nothing, although [`check_constraints`] refuses it beforehand.
+fn param_segment(part: &str) -> Option<(&str, Option<Constraint>)> {
+    let param = part.strip_prefix('{')?.strip_suffix('}')?;
+    Some(match param.split_once(':') {
+        Some((name, kind)) => (name, Some(Constraint::from_kind(kind)?)),
+        None => (param, None),
+    })
+}
+
+/// Panics on a `{name:kind}` segment of an endpoint path naming an unknown kind, so that
+/// a typo fails as the endpoint is added rather than leaving it unreachable.
+fn check_constraints(pattern: &str) {
+    if let Some(kind) = unknown_constraint(pattern) {
+        panic!(
+            "Unknown constraint `{}` in endpoint path {}, expected u64, uuid or alpha",
+            kind, pattern
+        );
+    }
+}
+
+/// Returns the first constraint kind of an endpoint path that is not a known one.
+pub(crate) fn unknown_constraint(pattern: &str) -> Option<&str> {
+    pattern
+        .split('/')
+        .filter_map(|part| {
+            part.strip_prefix('{')
+                .and_then(|param| param.strip_suffix('}'))
+                .and_then(|param| param.split_once(':'))
+                .map(|(_, kind)| kind)
+        })
+        .find(|kind| Constraint::from_kind(kind).is_none())
+}
+
+/// Compares how narrowly two endpoint paths of the same shape match, where the shape is
+/// the path with its parameters unnamed and unconstrained.
+///
+/// Returns `Greater` when `pattern` constrains a parameter that `other` leaves free and
+/// never the reverse, `Equal` when both match the same paths, and `None` when the paths
+/// differ in shape or neither is narrower.
+fn compare_shapes(pattern: &str, other: &str) -> Option<Ordering> {
+    let mut ordering = Ordering::Equal;
+    let mut parts = pattern.split('/');
+    let mut others = other.split('/');
+    loop {
+        let step = match (parts.next(), others.next()) {
+            (None, None) => return Some(ordering),
+            (Some(part), Some(other)) => match (param_segment(part), param_segment(other)) {
+                (None, None) if part == other => Ordering::Equal,
+                (Some((_, None)), Some((_, None))) => Ordering::Equal,
+                (Some((_, Some(a))), Some((_, Some(b)))) if a == b => Ordering::Equal,
+                (Some((_, Some(_))), Some((_, None))) => Ordering::Greater,
+                (Some((_, None)), Some((_, Some(_)))) => Ordering::Less,
+                _ => return None,
+            },
+            _ => return None,
+        };
+        ordering = match (ordering, step) {
+            (Ordering::Equal, step) => step,
+            (ordering, Ordering::Equal) => ordering,
+            (ordering, step) if ordering == step => ordering,
+            _ => return None,
+        };
+    }
+}
+
+/// Compares two strings after lowercasing them.
+fn eq_lowercase(a: &str, b: &str) -> bool {
+    a.chars()
+        .flat_map(char::to_lowercase)
+        .eq(b.chars().flat_map(char::to_lowercase))
+}
+
+/// Checks whether the path of a request target ends in a slash, not counting the root.
+fn has_trailing_slash(url: &str) -> bool {
+    let target = url.split(['?', '#']).next().unwrap_or(url);
+    target.ends_with('/') && parse_path(target).is_some()
+}
+
+/// Builds the `301 Moved Permanently` response pointing a request at the canonical form of
+/// its path, keeping the query string.
+fn redirect_to(canonical: &str, url: &str) -> Response {
+    let query = url.find('?').map_or("", |index| &url[index..]);
+    let mut headers = HeaderMap::new();
+    headers.insert("Location", format!("/{}{}", canonical, query));
+    Response {
+        status_code: 301,
+        reason: reason_phrase(301).into(),
+        response_body: None,
+        headers,
+    }
+}
+
+/// Builds the response for an error status without a custom handler, with `message` as
+/// plain text or, if `json` is set, in a JSON object.
+fn default_error_response(status: u16, message: &'static str, json: bool) -> Response {
+    if json {
+        // Reason phrases and framework messages hold no characters that need escaping
+        // in a JSON string.
+        let body = format!(
+            "{{\"error\":{{\"code\":{},\"message\":\"{}\"}}}}",
+            status, message
+        );
+        return body_response(status, ContentType::APPLICATION_JSON, body);
+    }
+    Response {
+        status_code: status,
+        reason: reason_phrase(status).into(),
+        response_body: Some(message.into()),
+        headers: HeaderMap::new(),
+    }
+}
+
+/// What the server does with a new connection while [`ServerConfig::max_connections`]
+/// are already open, or while [`ServerConfig::max_queued_connections`] are waiting for
+/// a worker.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub enum OverloadPolicy {
+    /// Stop accepting connections until there is room again, leaving new clients
+    /// waiting in the operating system's backlog.
+    #[default]
+    PauseAccepting,
+    /// Accept the connection, answer `503 Service Unavailable` with `Retry-After` right
+    /// away and close it, without involving a worker.
+    RespondUnavailable,
+}
+
+/// Settings for a server started with [`run_with_config`] or [`run_with_listener`].
+///
+/// Every setting has a default suited to a server exposed directly to clients, so only
+/// the ones that matter to an application need to be set.
+///
+/// # Examples
+///
+/// ```
+/// use rustic::app::{OverloadPolicy, ServerConfig};
+/// use rustic::shutdown::Shutdown;
+/// use std::time::Duration;
+/// let config = ServerConfig::new()
+///     .workers(8)
+///     .max_connections(256)
+///     .overload_policy(OverloadPolicy::RespondUnavailable)
+///     .keep_alive(Duration::from_secs(15))
+///     .shutdown(Shutdown::new(Duration::from_secs(10)));
+/// ```
+#[derive(Clone)]
+pub struct ServerConfig {
+    verbose: bool,
+    pub(crate) log_startup: bool,
+    shutdown: Option<Shutdown>,
+    maintenance: Option<Maintenance>,
+    workers: usize,
+    max_queued_connections: usize,
+    max_connections: Option<usize>,
+    overload_policy: OverloadPolicy,
+    retry_after: Duration,
+    pub(crate) read_timeout: Option<Duration>,
+    pub(crate) header_timeout: Option<Duration>,
+    pub(crate) write_timeout: Option<Duration>,
+    pub(crate) keep_alive: Option<Duration>,
+    pub(crate) max_requests_per_connection: usize,
+    pub(crate) max_header_size: usize,
+    pub(crate) read_buffer_size: usize,
+    decompress_requests: bool,
+    pub(crate) max_body_size: usize,
+    spill_threshold: Option<usize>,
+    missing_length: MissingLength,
+    pub(crate) leniency: Leniency,
+    slow_request_threshold: Option<Duration>,
+    max_response_header_value: usize,
+    max_response_header_size: usize,
+    pub(crate) keep_raw_head: bool,
+}
+
+/// The default of [`ServerConfig::workers`].
+pub const DEFAULT_WORKERS: usize = 64;
+/// The default of [`ServerConfig::max_queued_connections`].
+pub const DEFAULT_MAX_QUEUED_CONNECTIONS: usize = 1024;
+/// The default of [`ServerConfig::retry_after`].
+pub const DEFAULT_RETRY_AFTER: Duration = Duration::from_secs(1);
+/// The default of [`ServerConfig::read_timeout`] and [`ServerConfig::write_timeout`].
+pub const DEFAULT_TIMEOUT: Duration = Duration::from_secs(30);
+/// The default of [`ServerConfig::header_timeout`].
+pub const DEFAULT_HEADER_TIMEOUT: Duration = Duration::from_secs(10);
+/// The default of [`ServerConfig::keep_alive`].
+pub const DEFAULT_KEEP_ALIVE: Duration = Duration::from_secs(5);
+/// The default of [`ServerConfig::max_requests_per_connection`].
+pub const DEFAULT_MAX_REQUESTS_PER_CONNECTION: usize = 100;
+/// The default of [`ServerConfig::max_header_size`], 64 KiB.
+pub const DEFAULT_MAX_HEADER_SIZE: usize = 64 * 1024;
+/// The default of [`ServerConfig::read_buffer_size`], 8 KiB.
+pub const DEFAULT_READ_BUFFER_SIZE: usize = 8 * 1024;
+/// The default of [`ServerConfig::max_body_size`], 10 MiB.
+pub const DEFAULT_MAX_BODY_SIZE: usize = 10 * 1024 * 1024;
+/// The default of [`ServerConfig::max_response_header_value`], 16 KiB.
+pub const DEFAULT_MAX_RESPONSE_HEADER_VALUE: usize = 16 * 1024;
+/// The default of [`ServerConfig::max_response_header_size`], 64 KiB.
+pub const DEFAULT_MAX_RESPONSE_HEADER_SIZE: usize = 64 * 1024;
+
+impl Default for ServerConfig {
+    fn default() -> Self {
+        ServerConfig {
+            verbose: false,
+            log_startup: true,
+            shutdown: None,
+            maintenance: None,
+            workers: DEFAULT_WORKERS,
+            max_queued_connections: DEFAULT_MAX_QUEUED_CONNECTIONS,
+            max_connections: None,
+            overload_policy: OverloadPolicy::default(),
+            retry_after: DEFAULT_RETRY_AFTER,
+            read_timeout: Some(DEFAULT_TIMEOUT),
+            header_timeout: Some(DEFAULT_HEADER_TIMEOUT),
+            write_timeout: Some(DEFAULT_TIMEOUT),
+            keep_alive: Some(DEFAULT_KEEP_ALIVE),
+            max_requests_per_connection: DEFAULT_MAX_REQUESTS_PER_CONNECTION,
+            max_header_size: DEFAULT_MAX_HEADER_SIZE,
+            read_buffer_size: DEFAULT_READ_BUFFER_SIZE,
+            decompress_requests: false,
+            max_body_size: DEFAULT_MAX_BODY_SIZE,
+            spill_threshold: None,
+            missing_length: MissingLength::default(),
+            leniency: Leniency::STRICT,
+            slow_request_threshold: None,
+            max_response_header_value: DEFAULT_MAX_RESPONSE_HEADER_VALUE,
+            max_response_header_size: DEFAULT_MAX_RESPONSE_HEADER_SIZE,
+            keep_raw_head: false,
+        }
+    }
+}
+
+impl ServerConfig {
+    /// Creates the default configuration, running until the process exits.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Sets whether to install a logger printing debug and more severe records to
+    /// standard error when the server starts.
+    ///
+    /// Diagnostics go through the [`log`] facade, so applications should install the
+    /// logger of their choice instead. Nothing is installed if a logger is already set.
+    #[deprecated(note = "install a `log` logger instead")]
+    pub fn verbose(mut self, verbose: bool) -> Self {
+        self.verbose = verbose;
+        self
+    }
+
+    /// Sets whether to log what the server runs with once it is listening, which is on
+    /// by default: the crate version, the address, the number of workers, and a line for
+    /// each endpoint with its method, pattern and name, sorted by pattern.
+    ///
+    /// The lines are logged at info level through the [`log`] facade, and help spotting
+    /// endpoints added to the wrong path or not at all. When off, only the address is
+    /// logged.
+    pub fn log_startup(mut self, log_startup: bool) -> Self {
+        self.log_startup = log_startup;
+        self
+    }
+
+    /// Sets the handle that stops the server gracefully.
+    pub fn shutdown(mut self, shutdown: Shutdown) -> Self {
+        self.shutdown = Some(shutdown);
+        self
+    }
+
+    /// Sets the switch that puts the server into maintenance mode; see [`Maintenance`].
+    pub fn maintenance(mut self, maintenance: Maintenance) -> Self {
+        self.maintenance = Some(maintenance);
+        self
+    }
+
+    /// Sets the number of threads serving connections, which defaults to
+    /// [`DEFAULT_WORKERS`]. Each thread serves one connection at a time, including the
+    /// idle time between keep-alive requests.
+    pub fn workers(mut self, workers: usize) -> Self {
+        self.workers = workers.max(1);
+        self
+    }
+
+    /// Sets how many accepted connections may wait for a free worker, which defaults to
+    /// [`DEFAULT_MAX_QUEUED_CONNECTIONS`]. What happens to connections arriving while
+    /// the queue is full is set by [`ServerConfig::overload_policy`].
+    pub fn max_queued_connections(mut self, max_queued_connections: usize) -> Self {
+        self.max_queued_connections = max_queued_connections;
+        self
+    }
+
+    /// Sets how many connections may be open at once, queued or being served, which is
+    /// unlimited by default. What happens to further connections is set by
+    /// [`ServerConfig::overload_policy`].
+    pub fn max_connections(mut self, max_connections: impl Into<Option<usize>>) -> Self {
+        self.max_connections = max_connections.into().map(|max| max.max(1));
+        self
+    }
+
+    /// Sets what to do with connections beyond [`ServerConfig::max_connections`] or
+    /// [`ServerConfig::max_queued_connections`], which defaults to
+    /// [`OverloadPolicy::PauseAccepting`].
+    pub fn overload_policy(mut self, policy: OverloadPolicy) -> Self {
+        self.overload_policy = policy;
+        self
+    }
+
+    /// Sets the delay suggested in the `Retry-After` header of connections refused with
+    /// [`OverloadPolicy::RespondUnavailable`], which defaults to [`DEFAULT_RETRY_AFTER`].
+    /// It is rounded up to whole seconds.
+    pub fn retry_after(mut self, delay: Duration) -> Self {
+        self.retry_after = delay;
+        self
+    }
+
+    /// Sets how long a read from a client may block while a request arrives, which
+    /// defaults to [`DEFAULT_TIMEOUT`]. `None` waits forever.
+    ///
+    /// A new connection sending nothing for this long is closed without a response. A
+    /// read stalling in the middle of the request line or headers is answered with
+    /// `408 Request Timeout`.
+    pub fn read_timeout(mut self, timeout: impl Into<Option<Duration>>) -> Self {
+        self.read_timeout = timeout.into().filter(|timeout| !timeout.is_zero());
+        self
+    }
+
+    /// Sets how long a client has to send the request line and headers once a request
+    /// has started arriving, which defaults to [`DEFAULT_HEADER_TIMEOUT`]. `None` only
+    /// limits each read, by [`ServerConfig::read_timeout`].
+    ///
+    /// Clients that take longer, such as ones trickling a byte at a time, are answered
+    /// with `408 Request Timeout` and their connection is closed.
+    pub fn header_timeout(mut self, timeout: impl Into<Option<Duration>>) -> Self {
+        self.header_timeout = timeout.into().filter(|timeout| !timeout.is_zero());
+        self
+    }
+
+    /// Sets how long a write to a client may block, which defaults to
+    /// [`DEFAULT_TIMEOUT`]. `None` waits forever.
+    ///
+    /// A client that stops reading its response, or reads it slower than this allows,
+    /// has the response abandoned once the write times out and its connection closed,
+    /// freeing the worker. The async server applies the timeout to the whole response.
+    /// Abandoned responses are counted by the metrics of
+    /// [`App::enable_metrics_endpoint`], whose bytes written only include those that
+    /// were.
+    pub fn write_timeout(mut self, timeout: impl Into<Option<Duration>>) -> Self {
+        self.write_timeout = timeout.into().filter(|timeout| !timeout.is_zero());
+        self
+    }
+
+    /// Sets how long a connection is kept open waiting for the next request, which
+    /// defaults to [`DEFAULT_KEEP_ALIVE`]. `None` closes every connection after one
+    /// request. Idle connections are closed without a response once it passes.
+    pub fn keep_alive(mut self, timeout: impl Into<Option<Duration>>) -> Self {
+        self.keep_alive = timeout.into().filter(|timeout| !timeout.is_zero());
+        self
+    }
+
+    /// Sets how many requests a keep-alive connection serves before it is closed, which
+    /// defaults to [`DEFAULT_MAX_REQUESTS_PER_CONNECTION`].
+    pub fn max_requests_per_connection(mut self, max_requests: usize) -> Self {
+        self.max_requests_per_connection = max_requests.max(1);
+        self
+    }
+
+    /// Sets the largest request line and header block accepted, in bytes, which
+    /// defaults to [`DEFAULT_MAX_HEADER_SIZE`]. Longer heads are answered with
+    /// `431 Request Header Fields Too Large`.
+    pub fn max_header_size(mut self, max_header_size: usize) -> Self {
+        self.max_header_size = max_header_size;
+        self
+    }
+
+    /// Sets the size of the buffer each connection is read through, in bytes, which
+    /// defaults to [`DEFAULT_READ_BUFFER_SIZE`].
+    ///
+    /// The buffer is allocated once per connection and reused by its keep-alive
+    /// requests. A larger one takes fewer reads for large heads and bodies, at the cost
+    /// of memory per open connection.
+    pub fn read_buffer_size(mut self, bytes: usize) -> Self {
+        self.read_buffer_size = bytes.max(1);
+        self
+    }
+
+    /// Sets whether to decompress request bodies sent with `Content-Encoding: gzip` or
+    /// `deflate`, which is off by default.
+    ///
+    /// Handlers then see the decoded body, without the `Content-Encoding` header and
+    /// with a `Content-Length` matching the decoded size. Requests in any other coding
+    /// are answered with `415 Unsupported Media Type`, and bodies that would decompress
+    /// to more than [`ServerConfig::max_body_size`] with `413 Content Too Large`.
+    pub fn decompress_requests(mut self, decompress: bool) -> Self {
+        self.decompress_requests = decompress;
+        self
+    }
+
+    /// Sets whether to keep the request line and header lines of each request as they
+    /// were received, for [`Request::raw_head_lines`] and
+    /// [`Request::parse_diagnostics`], which is off by default.
+    ///
+    /// The lines are stored alongside the parsed headers, so this doubles the memory the
+    /// head of each request takes; it is meant for debugging misbehaving clients, such
+    /// as with [`App::enable_echo_endpoint`].
+    pub fn keep_raw_head(mut self, keep: bool) -> Self {
+        self.keep_raw_head = keep;
+        self
+    }
+
+    /// Sets the largest request body accepted, in bytes, both as sent and once
+    /// decompressed, which defaults to [`DEFAULT_MAX_BODY_SIZE`]. Longer bodies are
+    /// answered with `413 Content Too Large`.
+    pub fn max_body_size(mut self, max_body_size: usize) -> Self {
+        self.max_body_size = max_body_size;
+        self
+    }
+
+    /// Sets the size past which request bodies are written to a temporary file as they
+    /// are read, rather than kept in memory; by default, bodies are never spilled.
+    ///
+    /// Smaller bodies are read into [`Request::body`] as usual. A larger one is written
+    /// to a file under the system temporary directory, which handlers read with
+    /// [`Request::body_reader`] or [`Request::body_bytes`] or move into place with
+    /// [`Request::body_path`], and which is removed when the request is dropped. Content
+    /// codings of spilled bodies are not undone. [`ServerConfig::max_body_size`] still
+    /// bounds every body, and [`EndpointConfig::spill_threshold`] overrides this for an
+    /// endpoint. Only the threaded server spills bodies; the `async` one keeps them in
+    /// memory.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use rustic::app::{App, EndpointConfig, ServerConfig};
+    /// use rustic::parse_headers::RequestType;
+    ///
+    /// let config = ServerConfig::new()
+    ///     .max_body_size(100 * 1024 * 1024)
+    ///     .spill_threshold(1024 * 1024);
+    /// let mut application = App::new();
+    /// application.add_endpoint_with_config(
+    ///     "avatars",
+    ///     RequestType::PUT,
+    ///     |request| format!("{} bytes", request.body_bytes().map_or(0, |body| body.len())),
+    ///     EndpointConfig::new().spill_threshold(64 * 1024),
+    /// );
+    /// ```
+    pub fn spill_threshold(mut self, bytes: impl Into<Option<usize>>) -> Self {
+        self.spill_threshold = bytes.into();
+        self
+    }
+
+    /// Sets what to do with a request that may carry a body but does not say how long
+    /// it is, which defaults to [`MissingLength::Reject`].
+    ///
+    /// `GET`, `HEAD`, `DELETE` and the other methods without a defined body meaning are
+    /// always read as body-less when they have no framing headers.
+    pub fn missing_length(mut self, policy: MissingLength) -> Self {
+        self.missing_length = policy;
+        self
+    }
+
+    /// Sets which deviations from the request syntax to accept, which defaults to
+    /// [`Leniency::STRICT`].
+    pub fn leniency(mut self, leniency: Leniency) -> Self {
+        self.leniency = leniency;
+        self
+    }
+
+    /// Sets whether to accept every deviation of [`Leniency`], for clients such as old
+    /// proxies that cannot be fixed, or none of them.
+    ///
+    /// This is a shorthand for [`ServerConfig::leniency`] with [`Leniency::ALL`] or
+    /// [`Leniency::STRICT`].
+    pub fn lenient_parsing(self, lenient: bool) -> Self {
+        self.leniency(if lenient {
+            Leniency::ALL
+        } else {
+            Leniency::STRICT
+        })
+    }
+
+    /// Sets the duration past which a request is logged as slow, at warn level under the
+    /// `rustic::slow` target. `None`, the default, logs no request as slow.
+    ///
+    /// A request is slow when its handler, middleware included, or the whole exchange,
+    /// from the end of the request headers until the response is written, takes longer.
+    /// The entry gives the method, path, [route pattern](Request::matched_route) and
+    /// peer address of the request along with both durations, the first being the one
+    /// the access log of [`RequestIdMiddleware`](crate::request_id::RequestIdMiddleware)
+    /// reports.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use rustic::app::ServerConfig;
+    /// use std::time::Duration;
+    /// let config = ServerConfig::new().slow_request_threshold(Duration::from_millis(500));
+    /// ```
+    pub fn slow_request_threshold(mut self, threshold: impl Into<Option<Duration>>) -> Self {
+        self.slow_request_threshold = threshold.into();
+        self
+    }
+
+    /// Sets the longest response header value a handler may send, in bytes, which
+    /// defaults to [`DEFAULT_MAX_RESPONSE_HEADER_VALUE`].
+    ///
+    /// A response with a longer value is replaced by `500 Internal Server Error`, and
+    /// the header is named in an error logged under the `rustic` target, rather than
+    /// sending a head clients may choke on. [`EndpointConfig::limit_response_headers`]
+    /// sets it for a single endpoint.
+    pub fn max_response_header_value(mut self, bytes: usize) -> Self {
+        self.max_response_header_value = bytes;
+        self
+    }
+
+    /// Sets the largest block of response headers a handler may send, in bytes, which
+    /// defaults to [`DEFAULT_MAX_RESPONSE_HEADER_SIZE`].
+    ///
+    /// Each header counts its name, value and 4 bytes of separators. A response with
+    /// larger headers is replaced as for [`ServerConfig::max_response_header_value`],
+    /// and its largest header is named.
+    pub fn max_response_header_size(mut self, bytes: usize) -> Self {
+        self.max_response_header_size = bytes;
+        self
+    }
+}
+
+/// Runs the application, listening for incoming connections and handling requests.
+///
+/// Diagnostics are reported through the [`log`] facade.
+///
+/// # Arguments
+///
+/// * `app` - The application instance.
+/// * `port` - The port to listen on.
+///
+/// # Errors
+///
+/// Fails as [`run_with_config`] does, as the server otherwise runs until the process
+/// exits.
+pub fn run(app: App, port: u16) -> Result<(), Error> {
+    run_with_config(app, port, ServerConfig::new()).map(|_| ())
+}
+
+/// Runs the application with the given settings, listening for incoming connections
+/// and handling requests.
+///
+/// # Arguments
+///
+/// * `app` - The application instance.
+/// * `port` - The port to listen on.
+/// * `config` - The server settings.
+///
+/// # Returns
+///
+/// * `Result<ShutdownOutcome, Error>` - Whether every in-flight request finished
+///   before returning.
+///
+/// # Errors
+///
+/// Fails with [`Error::Bind`] if the port cannot be bound, and otherwise as
+/// [`run_with_listener`] does.
+///
+/// # Examples
+///
+/// ```no_run
+/// use rustic::app::{run_with_config, App, ServerConfig};
+///
+/// fn main() -> Result<(), rustic::Error> {
+///     run_with_config(App::new(), 8080, ServerConfig::new().workers(4))?;
+///     Ok(())
+/// }
+/// ```
+pub fn run_with_config(
+    app: App,
+    port: u16,
+    config: ServerConfig,
+) -> Result<ShutdownOutcome, Error> {
+    run_with_listener(app, listen_at_port(port)?, config)
+}
+
+/// Runs the application on a listener that is already bound.
+///
+/// This suits sockets inherited from a supervisor, listeners bound to port 0, and
+/// processes that drop privileges after binding. Without a shutdown handle in `config`
+/// the server runs until the process exits. Once the handle is triggered, the server
+/// stops accepting connections and [scheduled jobs](App::schedule), and waits for
+/// in-flight requests, then for [background tasks](crate::tasks::Tasks), to finish, up
+/// to the handle's drain timeout. [`App::on_start`] hooks run before the first request
+/// is accepted, followed by the startup log of [`ServerConfig::log_startup`], and
+/// [`App::on_shutdown`] hooks just before returning.
+///
+/// # Arguments
+///
+/// * `app` - The application instance.
+/// * `listener` - The bound listener to accept connections from.
+/// * `config` - The server settings.
+///
+/// # Returns
+///
+/// * `Result<ShutdownOutcome, Error>` - Whether every in-flight request finished before
+///   returning.
+///
+/// # Errors
+///
+/// * [`Error::Config`] - An [`App::on_start`] hook panicked, so no request was served.
+/// * [`Error::Accept`] - The listener stopped working. The server then shuts down as if
+///   triggered, in-flight requests included, before returning.
+/// * [`Error::Io`] - The listener's address or the server's threads were unavailable.
+///
+/// # Examples
+///
+/// ```no_run
+/// use rustic::app::{run_with_listener, App, ServerConfig};
+/// use std::net::TcpListener;
+///
+/// fn main() -> Result<(), Box<dyn std::error::Error>> {
+///     let listener = TcpListener::bind("127.0.0.1:0")?;
+///     println!("Listening at {}", listener.local_addr()?);
+///     run_with_listener(App::new(), listener, ServerConfig::new())?;
+///     Ok(())
+/// }
+/// ```
+pub fn run_with_listener(
+    app: App,
+    listener: TcpListener,
+    config: ServerConfig,
+) -> Result<ShutdownOutcome, Error> {
+    if config.verbose {
+        install_stderr_logger();
+    }
+    // A handle that is never triggered keeps the server running forever.
+    let shutdown = config
+        .shutdown
+        .clone()
+        .unwrap_or_else(|| Shutdown::new(Duration::ZERO));
+    let local_addr = listener.local_addr()?;
+    let server = ServerInfo { local_addr };
+    if !run_start_hooks(&app, &server) {
+        return Err(Error::Config("an on_start hook panicked".to_string()));
+    }
+    if config.log_startup {
+        log_startup(&app, &server, Some(config.workers));
+    } else {
+        log::info!("Listening at {}", local_addr);
+    }
+    shutdown.register_listener(local_addr);
+
+    let app = Arc::new(app);
+    let metrics = app.metrics.clone();
+    let tasks = app.tasks.clone();
+    let scheduler = Scheduler::start(&app.scheduled)?;
+    let retry_after = config.retry_after;
+    let limit = config.max_connections.map(ConnectionLimit::new);
+    let policy = config.overload_policy;
+    let pool = {
+        let config = Arc::new(config);
+        let shutdown = shutdown.clone();
+        let app = Arc::clone(&app);
+        WorkerPool::new(
+            "worker",
+            config.workers,
+            config.max_queued_connections,
+            move |(stream, slot): (TcpStream, Option<ConnectionSlot>)| {
+                serve_connection(&app, &config, &shutdown, stream);
+                drop(slot);
+            },
+        )?
+    };
+    let mut refusal = Refusal::new(retry_after);
+    let mut shed = |stream| {
+        if let Some(metrics) = &metrics {
+            metrics.connection_shed();
+        }
+        refuse_connection(stream, refusal.bytes());
+    };
+
+    let mut failure = None;
+    loop {
+        if let (Some(limit), OverloadPolicy::PauseAccepting) = (&limit, policy) {
+            limit.wait_for_room(&shutdown);
+        }
+        if shutdown.is_triggered() {
+            break;
+        }
+        let stream = match listener.accept() {
+            Ok((stream, _)) => stream,
+            // The client gave up before the connection was accepted.
+            Err(e) if is_disconnect(&e) => {
+                log::debug!("Connection closed before it was accepted: {}", e);
+                continue;
+            }
+            Err(e) if is_listener_broken(&e) => {
+                log::error!("The listener failed, shutting down: {}", e);
+                failure = Some(Error::Accept(e));
+                break;
+            }
+            Err(e) => {
+                log::warn!("Error accepting connection: {}", e);
+                continue;
+            }
+        };
+        if shutdown.is_triggered() {
+            break;
+        }
+        let slot = match &limit {
+            Some(limit) => match limit.try_acquire() {
+                Some(slot) => Some(slot),
+                None => {
+                    shed(stream);
+                    continue;
+                }
+            },
+            None => None,
+        };
+        match policy {
+            OverloadPolicy::PauseAccepting => pool.execute((stream, slot)),
+            OverloadPolicy::RespondUnavailable => {
+                if let Err((stream, _)) = pool.try_execute((stream, slot)) {
+                    shed(stream);
+                }
+            }
+        }
+    }
+
+    drop(listener);
+    drop(scheduler);
+    if failure.is_some() {
+        shutdown.trigger();
+    }
+    let deadline = Instant::now() + shutdown.drain_timeout();
+    let mut outcome = shutdown.drain();
+    // Background tasks get what is left of the drain timeout.
+    let remaining = deadline.saturating_duration_since(Instant::now());
+    if outcome == ShutdownOutcome::Drained && !tasks.drain(remaining) {
+        outcome = ShutdownOutcome::TimedOut;
+    }
+    run_shutdown_hooks(&app);
+    log::info!("Server stopped: {:?}", outcome);
+    failure.map_or(Ok(outcome), Err)
+}
+
+/// Counts the open connections against [`ServerConfig::max_connections`].
+struct ConnectionLimit {
+    max: usize,
+    open: Mutex<usize>,
+    closed: Condvar,
+}
+
+impl ConnectionLimit {
+    fn new(max: usize) -> Arc<Self> {
+        Arc::new(ConnectionLimit {
+            max,
+            open: Mutex::new(0),
+            closed: Condvar::new(),
+        })
+    }
+
+    /// Waits until fewer than the maximum connections are open or the shutdown is
+    /// triggered.
+    fn wait_for_room(&self, shutdown: &Shutdown) {
+        let mut open = self.open.lock().unwrap();
+        while *open >= self.max && !shutdown.is_triggered() {
+            // Wake up now and then, since a shutdown does not signal the condition.
+            open = self
+                .closed
+                .wait_timeout(open, Duration::from_millis(100))
+                .unwrap()
+                .0;
+        }
+    }
+
+    /// Counts a new connection as open until the returned slot is dropped, unless the
+    /// maximum is reached.
+    fn try_acquire(self: &Arc<Self>) -> Option<ConnectionSlot> {
+        let mut open = self.open.lock().unwrap();
+        if *open >= self.max {
+            return None;
+        }
+        *open += 1;
+        Some(ConnectionSlot(Arc::clone(self)))
+    }
+}
+
+/// An open connection counted by a [`ConnectionLimit`].
+struct ConnectionSlot(Arc<ConnectionLimit>);
+
+impl Drop for ConnectionSlot {
+    fn drop(&mut self) {
+        *self.0.open.lock().unwrap() -= 1;
+        self.0.closed.notify_one();
+    }
+}
+
+/// The `503 Service Unavailable` answering connections shed under load, serialized once
+/// and again only when the second its `Date` shows has passed, so that shedding costs
+/// the accept loop little more than a write.
+struct Refusal {
+    retry_after: Duration,
+    /// The second since the epoch that `bytes` was rendered in.
+    second: u64,
+    bytes: Vec<u8>,
+}
+
+impl Refusal {
+    fn new(retry_after: Duration) -> Self {
+        Refusal {
+            retry_after,
+            second: 0,
+            bytes: Vec::new(),
+        }
+    }
+
+    /// Returns the response, rendering it again if its `Date` is out of date.
+    fn bytes(&mut self) -> &[u8] {
+        let second = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |elapsed| elapsed.as_secs());
+        if self.bytes.is_empty() || second != self.second {
+            let retry_after = self.retry_after;
+            let seconds = retry_after.as_secs() + u64::from(retry_after.subsec_nanos() > 0);
+            self.bytes = ErrorResponse::new(503, reason_phrase(503))
+                .header("Retry-After", seconds.to_string())
+                .into_message()
+                .bytes;
+            self.second = second;
+        }
+        &self.bytes
+    }
+}
+
+/// Writes the prerendered `refusal` to a connection refused under load and closes it.
+fn refuse_connection(mut stream: TcpStream, refusal: &[u8]) {
+    let _ = stream.set_write_timeout(Some(Duration::from_secs(1)));
+    if let Err(err) = stream.write_all(refusal) {
+        log::debug!("Failed to refuse a connection: {}", err);
+    }
+    // Closing a socket with unread input resets it, which can destroy the response
+    // before the client reads it, so discard what has arrived without waiting for more.
+    let _ = stream.shutdown(std::net::Shutdown::Write);
+    if stream.set_nonblocking(true).is_ok() {
+        let _ = io::copy(&mut stream.take(64 * 1024), &mut io::sink());
+    }
+}
+
+/// Prints log records to standard error, for [`ServerConfig::verbose`].
+struct StderrLogger;
+
+impl log::Log for StderrLogger {
+    fn enabled(&self, metadata: &log::Metadata) -> bool {
+        metadata.level() <= log::Level::Debug
+    }
+
+    fn log(&self, record: &log::Record) {
+        if self.enabled(record.metadata()) {
+            eprintln!("[{}] {}", record.level(), record.args());
+        }
+    }
+
+    fn flush(&self) {}
+}
+
+/// Installs [`StderrLogger`] unless the application already set a logger.
+fn install_stderr_logger() {
+    static LOGGER: StderrLogger = StderrLogger;
+    if log::set_logger(&LOGGER).is_ok() {
+        log::set_max_level(log::LevelFilter::Debug);
+    }
+}
+
+/// Serves the requests of a connection until it closes, its keep-alive timeout passes
+/// or it reaches [`ServerConfig::max_requests_per_connection`].
+fn serve_connection(app: &App, config: &ServerConfig, shutdown: &Shutdown, stream: TcpStream) {
+    let remote_addr = stream.peer_addr().ok();
+    let _ = stream.set_write_timeout(config.write_timeout);
+    let mut reader = BufReader::with_capacity(
+        config.read_buffer_size,
+        DeadlineStream {
+            stream: &stream,
+            timeout: config.read_timeout,
+            deadline: None,
+            unread: Vec::new(),
+        },
+    );
+    let writer = match stream.try_clone() {
+        Ok(stream) => Arc::new(ConnectionWriter::new(stream)),
+        Err(err) => {
+            log::warn!("Failed to set up writing to {}: {}", Peer(remote_addr), err);
+            return;
+        }
+    };
+    let connection = shutdown.track_connection(&stream);
+    for served in 0..config.max_requests_per_connection {
+        // Wait for the first byte of a request. A connection idle for too long, before
+        // its first request or between keep-alive requests, is closed silently.
+        let idle_timeout = if served == 0 {
+            config.read_timeout
+        } else {
+            config.keep_alive
+        };
+        // Between keep-alive requests, unless the next one is already buffered, the
+        // connection is idle, and a shutdown closes it rather than waiting on it.
+        if served > 0 && reader.buffer().is_empty() && !connection.idle() {
+            return;
+        }
+        if served > 0 && writer.next_response().is_err() {
+            return;
+        }
+        reader.get_mut().timeout = idle_timeout;
+        let waiting = matches!(reader.fill_buf(), Ok(buffer) if !buffer.is_empty());
+        reader.get_mut().timeout = config.read_timeout;
+        connection.busy();
+        if !waiting || served > 0 && shutdown.is_triggered() {
+            return;
+        }
+        let may_persist = config.keep_alive.is_some()
+            && served + 1 < config.max_requests_per_connection
+            && !shutdown.is_triggered();
+        let in_flight = shutdown.track_request();
+        let output = (&writer, served as u64);
+        let served = serve_request(app, config, &mut reader, remote_addr, output, may_persist);
+        drop(in_flight);
+        match served {
+            Served::KeepAlive => {}
+            Served::Close => return,
+            Served::Hijacked(handler) => {
+                let buffered = [reader.buffer(), &reader.get_ref().unread].concat();
+                drop(reader);
+                if let Ok(stream) = stream.try_clone() {
+                    handler(Hijacked::new(stream, buffered));
+                }
+                return;
+            }
+        }
+    }
+}
+
+/// What became of a connection after serving a request.
+enum Served {
+    /// The connection can serve another request.
+    KeepAlive,
+    /// The connection is to be closed.
+    Close,
+    /// The handler took the connection over with [`Request::hijack`] or
+    /// [`Request::on_upgrade`].
+    Hijacked(HijackHandler),
+}
+
+/// A connection whose reads fail with `TimedOut` once a deadline has passed, on top of
+/// the timeout of each read.
+struct DeadlineStream<'a> {
+    stream: &'a TcpStream,
+    timeout: Option<Duration>,
+    deadline: Option<Instant>,
+    /// Bytes read past a streamed body, returned before reading the connection again.
+    unread: Vec<u8>,
+}
+
+impl Read for DeadlineStream<'_> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if !self.unread.is_empty() {
+            let read = self.unread.len().min(buf.len());
+            buf[..read].copy_from_slice(&self.unread[..read]);
+            self.unread.drain(..read);
+            return Ok(read);
+        }
+        let timeout = match self.deadline {
+            Some(deadline) => {
+                let remaining = deadline.saturating_duration_since(Instant::now());
+                if remaining.is_zero() {
+                    return Err(io::ErrorKind::TimedOut.into());
+                }
+                Some(
+                    self.timeout
+                        .map_or(remaining, |timeout| timeout.min(remaining)),
+                )
+            }
+            None => self.timeout,
+        };
+        self.stream.set_read_timeout(timeout)?;
+        let mut stream = self.stream;
+        stream.read(buf)
+    }
+}
+
+/// Reads one request from a connection, dispatches it and writes the response.
+///
+/// Returns whether the connection can serve another request, which `may_persist` or
+/// either side asking to close it rules out.
+///
+/// `output` is the connection's writer, with the number of the request on the
+/// connection.
+fn serve_request(
+    app: &App,
+    config: &ServerConfig,
+    reader: &mut BufReader<DeadlineStream>,
+    remote_addr: Option<SocketAddr>,
+    output: (&Arc<ConnectionWriter>, u64),
+    may_persist: bool,
+) -> Served {
+    let (writer, request) = output;
+    reader.get_mut().deadline = config
+        .header_timeout
+        .map(|timeout| Instant::now() + timeout);
+    let head = read_request_head(app, config, reader, remote_addr);
+    reader.get_mut().deadline = None;
+    let mut streamed = None;
+    let (message, persist, slow) = match head {
+        Ok((mut head, too_large)) => {
+            if head.http_1_1 {
+                head.interim = Some(writer.open(request));
+            }
+            if head.lacks_host() {
+                head.local_addr = reader.get_ref().stream.local_addr().ok();
+            }
+            let hijack = HijackSlot::new(head.http_1_1);
+            head.hijack = Some(hijack.clone());
+            reader.get_mut().timeout = head.read_timeout(config);
+            if !too_large {
+                streamed = stream_body(config, reader, &mut head);
+            }
+            let answer = answer_request(app, config, reader, head, too_large, may_persist);
+            reader.get_mut().timeout = config.read_timeout;
+            writer.close();
+            match hijack.take() {
+                // A hijacking handler writes everything itself, so its response is dropped.
+                Some(Takeover::Hijack(handler)) => {
+                    writer.hand_over();
+                    if let Some(unread) = streamed.and_then(|body| body.finish(false)) {
+                        reader.get_mut().unread = unread;
+                    }
+                    return Served::Hijacked(handler);
+                }
+                // An upgrading handler takes over once its 101 is written.
+                Some(Takeover::Upgrade(handler)) => {
+                    let (message, _, slow) = answer;
+                    let sent = send_response(app, writer, &message, remote_addr);
+                    if let Some(slow) = slow {
+                        slow.written();
+                    }
+                    if !sent {
+                        return Served::Close;
+                    }
+                    writer.hand_over();
+                    if let Some(unread) = streamed.and_then(|body| body.finish(false)) {
+                        reader.get_mut().unread = unread;
+                    }
+                    return Served::Hijacked(handler);
+                }
+                None => answer,
+            }
+        }
+        Err(Some(message)) => (message, false, None),
+        Err(None) => return Served::Close,
+    };
+    let sent = send_response(app, writer, &message, remote_addr);
+    if let Some(slow) = slow {
+        slow.written();
+    }
+    if !sent || !persist {
+        return Served::Close;
+    }
+    // What the handler left of a streamed body is skipped to reach the next request.
+    if let Some(body) = streamed {
+        match body.finish(true) {
+            Some(unread) => reader.get_mut().unread = unread,
+            None => return Served::Close,
+        }
+    }
+    Served::KeepAlive
+}
+
+/// Writes a response onto a connection, returning whether it was written whole.
+///
+/// Each write may block for up to [`ServerConfig::write_timeout`], so a client that
+/// stops reading frees the thread once it passes. A response that could not be written
+/// whole is logged and counted as aborted in the metrics, which only keep the bytes
+/// that were written, and its connection must be closed, as the client cannot tell
+/// where the next response would start. A response the writer refuses, as a second one
+/// to the same request would be, is logged by it and writes nothing.
+fn send_response(
+    app: &App,
+    writer: &ConnectionWriter,
+    message: &Message,
+    remote_addr: Option<SocketAddr>,
+) -> bool {
+    match writer.send(message) {
+        Ok(()) => true,
+        Err(WriteError::Io { error, written }) => {
+            abort_response(app, message, written, &error, remote_addr);
+            false
+        }
+        Err(_) => false,
+    }
+}
+
+/// Logs and counts a response that was only written up to `written` bytes, failing
+/// with `err`.
+pub(crate) fn abort_response(
+    app: &App,
+    message: &Message,
+    written: usize,
+    err: &io::Error,
+    remote_addr: Option<SocketAddr>,
+) {
+    let timed_out = matches!(
+        err.kind(),
+        io::ErrorKind::TimedOut | io::ErrorKind::WouldBlock
+    );
+    log::debug!(
+        "{} response to {} after {} of {} bytes: {}",
+        if timed_out {
+            "Timed out writing the"
+        } else {
+            "Failed to write the"
+        },
+        Peer(remote_addr),
+        written,
+        message.len(),
+        err
+    );
+    if let Some(metrics) = &app.metrics {
+        metrics.response_aborted(message.len().saturating_sub(written));
+    }
+}
+
+/// Hands the body of a request for an endpoint streaming it to a [`BodyReader`], which
+/// reads it from the connection after the bytes `reader` has buffered.
+///
+/// Returns the server's side of the streamed body, or `None` when the body is to be
+/// read as usual: when the endpoint does not stream its body, when there is no body or
+/// it is refused without being read, or when the connection cannot be shared.
+fn stream_body(
+    config: &ServerConfig,
+    reader: &mut BufReader<DeadlineStream>,
+    head: &mut RequestHead,
+) -> Option<StreamedBody> {
+    if !head.streams_body() {
+        return None;
+    }
+    let limit = head.max_body_size(config);
+    let bare_lf = config.leniency.bare_lf;
+    let framing = match head.body_plan(config) {
+        BodyPlan::Read(Framing::Length(length)) if length == 0 || length > limit => return None,
+        BodyPlan::Read(framing @ (Framing::Length(_) | Framing::Chunked)) => framing,
+        _ => return None,
+    };
+    let stream = reader.get_ref().stream.try_clone().ok()?;
+    let timeout = reader.get_ref().timeout;
+    let buffered = reader.buffer().to_vec();
+    let (streamed, body_reader) =
+        StreamedBody::start(stream, timeout, buffered, framing, limit, bare_lf).ok()?;
+    reader.consume(reader.buffer().len());
+    head.unbuffered = Some(UnbufferedBody::Streamed(body_reader));
+    Some(streamed)
+}
+
+/// Reads one request from `reader` and produces the serialized response to it, along
+/// with whether the connection can serve another request and the slow request check
+/// to run once it is written.
+///
+/// Returns `None` when no request could be read, so there is nothing to answer.
+pub(crate) fn process_request<R: BufRead>(
+    app: &App,
+    config: &ServerConfig,
+    reader: &mut R,
+    remote_addr: Option<SocketAddr>,
+    may_persist: bool,
+) -> Option<(Message, bool, Option<SlowRequest>)> {
+    match read_request_head(app, config, reader, remote_addr) {
+        Ok((head, too_large)) => Some(answer_request(
+            app,
+            config,
+            reader,
+            head,
+            too_large,
+            may_persist,
+        )),
+        Err(message) => message.map(|message| (message, false, None)),
+    }
+}
+
+/// Reads the request line and headers of a request, along with whether they exceeded
+/// [`ServerConfig::max_header_size`].
+///
+/// Fails with the response to send before closing the connection, which is
+/// `408 Request Timeout` when the client stalled, or `None` when there is nothing to
+/// answer.
+fn read_request_head<R: BufRead>(
+    app: &App,
+    config: &ServerConfig,
+    reader: &mut R,
+    remote_addr: Option<SocketAddr>,
+) -> Result<(RequestHead, bool), Option<Message>> {
+    let mut limited = reader.take(config.max_header_size as u64);
+    let (mut lines, bare_lf) = match read_request_lines(&mut limited) {
+        Ok(read) => read,
+        Err(err)
+            if matches!(
+                err.kind(),
+                io::ErrorKind::TimedOut | io::ErrorKind::WouldBlock
+            ) =>
+        {
+            log::debug!(
+                "Timed out reading request headers from {}",
+                Peer(remote_addr)
+            );
+            return Err(Some(timeout_response(app)));
+        }
+        Err(_) => return Err(None),
+    };
+    if let Some(message) = refuse_deviations(app, config, &mut lines, bare_lf, remote_addr)
+        .or_else(|| refuse_request_line(app, &lines, remote_addr))
+    {
+        return Err(Some(message));
+    }
+    let too_large = limited.limit() == 0;
+    let raw_lines = config.keep_raw_head.then(|| lines.clone());
+    let mut head = RequestHead::parse(lines, remote_addr).ok_or(None)?;
+    head.raw_lines = raw_lines;
+    head.received.head = (config.max_header_size as u64 - limited.limit()) as usize;
+    head.find_route(app);
+    Ok((head, too_large))
+}
+
+/// Serializes the `408 Request Timeout` response sent before closing a connection that
+/// stalled while sending its headers.
+pub(crate) fn timeout_response(app: &App) -> Message {
+    closing_response(app, 408)
+}
+
+/// Serializes the response sent before closing a connection whose request line the
+/// server refuses, or returns `None` when `lines` may be a request it serves.
+///
+/// These are:
+///
+/// * the `PRI * HTTP/2.0` line starting the connection preface of HTTP/2 clients that
+///   have not negotiated it, which is meant to fail on HTTP/1.1 servers and gets
+///   `505 HTTP Version Not Supported`. Those probing with `Upgrade: h2c` need nothing
+///   special: their request is served over HTTP/1.1, as it comes with the upgrade
+///   ignored.
+/// * a method the server does not implement, such as `PROPFIND`, which gets
+///   `501 Not Implemented` naming it.
+/// * a method that is not a valid token, such as `G<ET`, which gets `400 Bad Request`.
+/// * a request line without a target and version, or with a version that is not of the
+///   `HTTP/1.1` form, which gets `400 Bad Request`.
+/// * a version other than HTTP/1.x, such as `HTTP/3.0`, which gets
+///   `505 HTTP Version Not Supported`.
+///
+/// The connection is closed after each of them, as whatever follows the head cannot be
+/// trusted to be framed the way the server would read it.
+pub(crate) fn refuse_request_line(
+    app: &App,
+    lines: &[String],
+    remote_addr: Option<SocketAddr>,
+) -> Option<Message> {
+    let request_line = lines.first()?;
+    if is_http2_preface(request_line) {
+        log::debug!(
+            "Refused the HTTP/2 connection preface from {}",
+            Peer(remote_addr)
+        );
+        return Some(closing_response(app, 505));
+    }
+    let mut parts = request_line.split_whitespace();
+    let method = parts.next()?;
+    let (target, version) = (parts.next(), parts.next());
+    let response = if parse_method(method).is_none() && is_token(method) {
+        log::debug!(
+            "Refused the unsupported method `{}` from {}",
+            method,
+            Peer(remote_addr)
+        );
+        text_response(
+            501,
+            format!("Not Implemented: the `{}` method is not supported", method),
+        )
+    } else if parse_method(method).is_none() {
+        log::debug!(
+            "Refused the invalid method {:?} from {}",
+            method,
+            Peer(remote_addr)
+        );
+        text_response(400, format!("Bad Request: invalid method {:?}", method))
+    } else if let (Some(_), Some(version)) = (target, version) {
+        match http_major_version(version) {
+            Some(1) => return None,
+            Some(_) => {
+                log::debug!(
+                    "Refused the unsupported version {} from {}",
+                    version,
+                    Peer(remote_addr)
+                );
+                return Some(closing_response(app, 505));
+            }
+            None => {
+                log::debug!(
+                    "Refused the invalid version {:?} from {}",
+                    version,
+                    Peer(remote_addr)
+                );
+                text_response(
+                    400,
+                    format!("Bad Request: invalid HTTP version {:?}", version),
+                )
+            }
+        }
+    } else {
+        log::debug!(
+            "Refused the request line {:?} without a version from {}",
+            request_line,
+            Peer(remote_addr)
+        );
+        text_response(400, "Bad Request: invalid request line")
+    };
+    Some(close_with(app, response))
+}
+
+/// Serializes the response sent before closing a connection whose head deviates from
+/// the request syntax in a way [`ServerConfig::leniency`] refuses, or returns `None`
+/// after logging the deviations it accepts.
+///
+/// A method accepted in lowercase is rewritten in uppercase in `lines`, so the request
+/// is served as if it were sent so. `bare_lf` tells whether a line of the head ended in
+/// a bare LF.
+pub(crate) fn refuse_deviations(
+    app: &App,
+    config: &ServerConfig,
+    lines: &mut [String],
+    bare_lf: bool,
+    remote_addr: Option<SocketAddr>,
+) -> Option<Message> {
+    let leniency = config.leniency;
+    let (request_line, header_lines) = lines.split_first_mut()?;
+    let refused = |deviation: &str, message: &'static str| {
+        log::debug!("Refused {} from {}", deviation, Peer(remote_addr));
+        Some(close_with(app, text_response(400, message)))
+    };
+    let accepted = |deviation: &str| {
+        log::debug!("Accepted {} from {}", deviation, Peer(remote_addr));
+    };
+
+    if bare_lf {
+        if !leniency.bare_lf {
+            return refused("a head with bare LF line endings", "Bad Request: bare LF");
+        }
+        accepted("a head with bare LF line endings");
+    }
+    let method = request_line.split(' ').next().unwrap_or("");
+    if leniency.lowercase_methods && parse_method(method).is_none() {
+        let uppercase = method.to_ascii_uppercase();
+        if parse_method(&uppercase).is_some() {
+            accepted(&format!("the method `{}` as {}", method, uppercase));
+            request_line.replace_range(..method.len(), &uppercase);
+        }
+    }
+    let mut parts = request_line.split_whitespace();
+    let is_connect = parts.next() == Some(RequestType::CONNECT.as_str());
+    if let Some(target) = parts.next().filter(|_| !is_connect) {
+        let absolute = target.split_once("://").is_some_and(|(scheme, _)| {
+            !scheme.is_empty()
+                && scheme
+                    .bytes()
+                    .all(|byte| byte.is_ascii_alphanumeric() || b"+-.".contains(&byte))
+        });
+        if absolute {
+            if !leniency.absolute_form {
+                return refused(
+                    "a request target in absolute form",
+                    "Bad Request: absolute-form request target",
+                );
+            }
+            accepted("a request target in absolute form");
+        }
+    }
+    let tight = header_lines.iter().any(|line| {
+        line.split_once(':').is_some_and(|(name, value)| {
+            !name.starts_with([' ', '\t']) && !value.is_empty() && !value.starts_with([' ', '\t'])
+        })
+    });
+    if tight {
+        if !leniency.missing_space {
+            return refused(
+                "a header without a space after its colon",
+                "Bad Request: no space after a header colon",
+            );
+        }
+        accepted("a header without a space after its colon");
+    }
+    None
+}
+
+/// Serializes the error response for `status_code` sent before closing a connection
+/// whose request could not be read, counting it in the metrics.
+fn closing_response(app: &App, status_code: u16) -> Message {
+    close_with(app, app.error_response(status_code, None))
+}
+
+/// Serializes `response` with `Connection: close`, counting it in the metrics like
+/// [`closing_response`].
+fn close_with(app: &App, response: Response) -> Message {
+    let status_code = response.status_code;
+    let message = ErrorResponse::from(response).into_message();
+    if let Some(metrics) = &app.metrics {
+        metrics.request_started();
+        metrics.request_finished(
+            UNMATCHED_ROUTE,
+            status_code,
+            Duration::ZERO,
+            0,
+            message.len(),
+        );
+    }
+    message
+}
+
+/// Reads the body of a request according to its head, dispatches the request and
+/// serializes the response, returning it as [`respond`] does.
+fn answer_request<R: BufRead>(
+    app: &App,
+    config: &ServerConfig,
+    reader: &mut R,
+    mut head: RequestHead,
+    head_too_large: bool,
+    may_persist: bool,
+) -> (Message, bool, Option<SlowRequest>) {
+    let remote_addr = head.remote_addr;
+    let max_body_size = head.max_body_size(config);
+    let spill_threshold = head.spill_threshold(config);
+    let bare_lf = config.leniency.bare_lf;
+    let reader = &mut CountingReader::new(reader);
+    let read_error = |err| match err {
+        BodyError::TooLarge => ReadBody::Rejected(413),
+        BodyError::Io(err) => {
+            log::debug!("Failed to read body from {}: {}", Peer(remote_addr), err);
+            ReadBody::Unreadable
+        }
+    };
+    let body = match head.body_plan(config) {
+        _ if head_too_large => ReadBody::Rejected(431),
+        _ if head.unbuffered.is_some() => ReadBody::Empty,
+        BodyPlan::Read(Framing::Unframed | Framing::Length(0)) => ReadBody::Empty,
+        BodyPlan::Read(framing) => match spill_threshold {
+            Some(threshold) => {
+                let capacity = match framing {
+                    Framing::Length(length) => length.min(MAX_PREALLOCATED_BODY),
+                    _ => 0,
+                };
+                let mut spool = Spool::new(threshold, capacity);
+                let copied = copy_body(reader, framing, max_body_size, bare_lf, &mut spool);
+                keep_spooled(&mut head, spool, copied.map_err(read_error))
+            }
+            None => match read_body(reader, framing, max_body_size, bare_lf) {
+                Ok(body) => ReadBody::Read(body),
+                Err(err) => read_error(err),
+            },
+        },
+        BodyPlan::UntilClose => {
+            // The client half-closes the connection to end the body, so anything else
+            // ending it, such as a reset or a timeout, leaves it incomplete.
+            let mut spool = Spool::new(spill_threshold.unwrap_or(usize::MAX), 0);
+            let limit = max_body_size as u64 + 1;
+            match io::copy(&mut reader.take(limit), &mut spool) {
+                _ if spool.len() > max_body_size => ReadBody::Rejected(413),
+                Ok(_) => keep_spooled(&mut head, spool, Ok(())),
+                Err(err) => keep_spooled(&mut head, spool, Err(read_error(BodyError::Io(err)))),
+            }
+        }
+        BodyPlan::Reject(status) => ReadBody::Rejected(status),
+    };
+    head.received.body = reader.count();
+
+    respond(app, config, head, body, may_persist)
+}
+
+/// Takes the body a request was spooled into, leaving it in the head if it was spilled
+/// to a file, or returns why it could not be read, as `copied` says, or stored.
+fn keep_spooled(head: &mut RequestHead, spool: Spool, copied: Result<(), ReadBody>) -> ReadBody {
+    head.received.decoded_body = spool.len();
+    match (spool.finish(), copied) {
+        (Err(err), _) => {
+            log::error!(
+                "Failed to spill a request body to a temporary file: {}",
+                err
+            );
+            ReadBody::Rejected(500)
+        }
+        (_, Err(failed)) => failed,
+        (Ok(Spooled::Memory(body)), Ok(())) => ReadBody::Read(body),
+        (Ok(Spooled::File(file)), Ok(())) => {
+            head.unbuffered = Some(UnbufferedBody::Spilled(file));
+            ReadBody::Empty
+        }
+    }
+}
+
+/// What came of reading the body of a request before dispatching it.
+pub(crate) enum ReadBody {
+    /// There is no body in memory: the request has none, or its handler reads it as it
+    /// is streamed or spilled.
+    Empty,
+    /// The body, read in full.
+    Read(Vec<u8>),
+    /// A body was sent but could not be read in full, or is not valid UTF-8. The request
+    /// is answered with `400 Bad Request` without running its handler and, since the
+    /// end of the body may not be known, its connection is closed.
+    Unreadable,
+    /// The request is refused with this status without running its handler.
+    Rejected(u16),
+}
+
+/// The message of the `400 Bad Request` answering a request whose body is
+/// [unreadable](ReadBody::Unreadable).
+const BODY_READ_ERROR: &str = "Bad Request: body read error";
+
+/// The request line and headers of a request, before its body is read.
+pub(crate) struct RequestHead {
+    method: RequestType,
+    url: String,
+    headers: HeaderMap,
+    /// How the body is framed, or the status to refuse the request with.
+    framing: Result<Framing, u16>,
+    /// Whether the client asked for the connection to be closed after this request.
+    client_closes: bool,
+    /// Whether the request is HTTP/1.1, so that it may get interim responses.
+    http_1_1: bool,
+    remote_addr: Option<SocketAddr>,
+    /// What the handler can send interim responses with, when served over a socket.
+    interim: Option<InterimSender>,
+    /// Where the handler can leave a callback taking the connection over.
+    hijack: Option<HijackSlot>,
+    /// The settings of the endpoint the request is for, once looked up.
+    route: Option<Arc<EndpointConfig>>,
+    /// The body, when it is streamed or spilled rather than read into memory.
+    unbuffered: Option<UnbufferedBody>,
+    /// The bytes the request took on the wire, counted as it is read.
+    pub(crate) received: RequestBytes,
+    /// When the head was read, which the whole exchange is timed from.
+    received_at: Instant,
+    /// The address the connection was accepted on, only looked up for a request
+    /// without a `Host` header, for [`Request::host`] to fall back on.
+    pub(crate) local_addr: Option<SocketAddr>,
+    /// The head lines as received, under [`ServerConfig::keep_raw_head`].
+    pub(crate) raw_lines: Option<Vec<String>>,
+}
+
+/// How the body of a request is to be read.
+pub(crate) enum BodyPlan {
+    /// Read the body as framed by its headers.
+    Read(Framing),
+    /// Read the body until the client closes the connection.
+    UntilClose,
+    /// Answer with this error status without reading the body.
+    Reject(u16),
+}
+
+impl RequestHead {
+    /// Parses the raw head lines of a request, or returns `None` after logging why
+    /// they are not a request.
+    pub(crate) fn parse(lines: Vec<String>, remote_addr: Option<SocketAddr>) -> Option<Self> {
+        let framing = if lines
+            .iter()
+            .skip(1)
+            .any(|line| has_space_before_colon(line))
+        {
+            Err(400)
+        } else {
+            framing(&lines)
+        };
+        let RawRequest {
+            method,
+            version,
+            target,
+            headers,
+        } = match parse_headers(lines) {
+            Ok(request) => request,
+            Err(err) => {
+                log::debug!(
+                    "Failed to parse request from {}: {}",
+                    Peer(remote_addr),
+                    err
+                );
+                return None;
+            }
+        };
+        // A front proxy may pick another line of a repeated Host or Content-Length than
+        // this server would, so those are refused even when the values agree.
+        let repeated = ["Host", "Content-Length"]
+            .iter()
+            .any(|name| headers.get_all(name).nth(1).is_some());
+        let framing = if repeated { Err(400) } else { framing };
+        // A request framed by both chunked coding and a length may have been read
+        // differently by a proxy, so its connection is not reused.
+        let client_closes = version != HttpType::OnePointOne
+            || framing == Ok(Framing::Chunked) && headers.contains_key("Content-Length")
+            || headers.get_all("Connection").any(|value| {
+                value
+                    .split(',')
+                    .any(|token| token.trim().eq_ignore_ascii_case("close"))
+            });
+        Some(RequestHead {
+            method,
+            url: target,
+            headers,
+            framing,
+            client_closes,
+            http_1_1: version == HttpType::OnePointOne,
+            remote_addr,
+            interim: None,
+            hijack: None,
+            route: None,
+            unbuffered: None,
+            received: RequestBytes::default(),
+            received_at: Instant::now(),
+            local_addr: None,
+            raw_lines: None,
+        })
+    }
+
+    /// Checks whether the request came without a `Host` header, as HTTP/1.0 ones may.
+    pub(crate) fn lacks_host(&self) -> bool {
+        !self.headers.contains_key("Host")
+    }
+
+    /// Looks up the settings of the endpoint the request is for, whose limits then
+    /// apply instead of those of the [`ServerConfig`].
+    pub(crate) fn find_route(&mut self, app: &App) {
+        self.route = app.endpoint_config(self.method, &self.url);
+    }
+
+    /// Checks whether the endpoint the request is for streams its body.
+    fn streams_body(&self) -> bool {
+        self.route.as_ref().is_some_and(|route| route.stream_body)
+    }
+
+    /// Returns the body size past which the body is spilled to a file, if any.
+    fn spill_threshold(&self, config: &ServerConfig) -> Option<usize> {
+        self.route
+            .as_ref()
+            .and_then(|route| route.spill_threshold)
+            .or(config.spill_threshold)
+    }
+
+    /// Returns the longest response header value and the largest response header block
+    /// the endpoint may send.
+    fn response_header_limits(&self, config: &ServerConfig) -> (usize, usize) {
+        self.route
+            .as_ref()
+            .and_then(|route| route.response_header_limits)
+            .unwrap_or((
+                config.max_response_header_value,
+                config.max_response_header_size,
+            ))
+    }
+
+    /// Returns the largest body accepted for the request.
+    pub(crate) fn max_body_size(&self, config: &ServerConfig) -> usize {
+        self.route
+            .as_ref()
+            .and_then(|route| route.max_body_size)
+            .unwrap_or(config.max_body_size)
+    }
+
+    /// Returns how long to wait for each read of the request body.
+    pub(crate) fn read_timeout(&self, config: &ServerConfig) -> Option<Duration> {
+        self.route
+            .as_ref()
+            .and_then(|route| route.read_timeout)
+            .or(config.read_timeout)
+    }
+
+    /// Decides how to read the body, following [`ServerConfig::missing_length`] for a
+    /// bodied request without framing headers.
+    pub(crate) fn body_plan(&self, config: &ServerConfig) -> BodyPlan {
+        match self.framing {
+            Err(status) => {
+                log::debug!(
+                    "Refused the headers or framing of a request from {}",
+                    Peer(self.remote_addr)
+                );
+                BodyPlan::Reject(status)
+            }
+            Ok(Framing::Unframed) if self.method.requires_length() => {
+                if config.missing_length == MissingLength::ReadUntilClose && self.client_closes {
+                    BodyPlan::UntilClose
+                } else {
+                    BodyPlan::Reject(411)
+                }
+            }
+            Ok(framing) => BodyPlan::Read(framing),
+        }
+    }
+}
+
+/// Dispatches a request, or generates the error response for the status its body was
+/// rejected with, and serializes the response.
+///
+/// Returns the response bytes, whether the connection can serve another request and,
+/// under [`ServerConfig::slow_request_threshold`], what to check once they are written.
+pub(crate) fn respond(
+    app: &App,
+    config: &ServerConfig,
+    head: RequestHead,
+    body: ReadBody,
+    may_persist: bool,
+) -> (Message, bool, Option<SlowRequest>) {
+    let max_body_size = head.max_body_size(config);
+    let (max_header_value, max_header_size) = head.response_header_limits(config);
+    let RequestHead {
+        method,
+        url,
+        mut headers,
+        client_closes,
+        remote_addr,
+        interim,
+        hijack,
+        route,
+        mut unbuffered,
+        mut received,
+        received_at,
+        local_addr,
+        raw_lines,
+        ..
+    } = head;
+    let body = match body {
+        ReadBody::Read(body) if config.decompress_requests && unbuffered.is_none() => {
+            match decode_body(&mut headers, body, max_body_size) {
+                Ok(body) => ReadBody::Read(body),
+                Err(status) => ReadBody::Rejected(status),
+            }
+        }
+        body => body,
+    };
+    let (body, rejection) = match body {
+        ReadBody::Empty => (String::new(), None),
+        ReadBody::Read(body) => match String::from_utf8(body) {
+            Ok(body) => (body, None),
+            // A body the endpoint reads as bytes, but was buffered anyway, need not be text.
+            Err(err) if route.as_ref().is_some_and(|route| route.stream_body) => {
+                let body = err.into_bytes();
+                received.decoded_body = body.len();
+                unbuffered = Some(UnbufferedBody::Streamed(BodyReader::buffered(body)));
+                (String::new(), None)
+            }
+            Err(_) => {
+                log::debug!(
+                    "Refused a body that is not valid UTF-8 from {}",
+                    Peer(remote_addr)
+                );
+                (String::new(), Some((400, BODY_READ_ERROR)))
+            }
+        },
+        ReadBody::Unreadable => (String::new(), Some((400, BODY_READ_ERROR))),
+        ReadBody::Rejected(status) => (String::new(), Some((status, reason_phrase(status)))),
+    };
+    if unbuffered.is_none() {
+        received.decoded_body = body.len();
+    }
+    let streamed = unbuffered.as_ref().and_then(UnbufferedBody::progress);
+
+    let path = parse_path(&url).unwrap_or("").to_string();
+    let slow = config.slow_request_threshold.map(|threshold| SlowRequest {
+        threshold,
+        method,
+        path: path.clone(),
+        route: route.clone(),
+        remote_addr,
+        received_at,
+        handled: Duration::ZERO,
+    });
+    let mut request = Request {
+        method,
+        path,
+        url,
+        headers,
+        body,
+        extensions: Extensions::new(),
+        remote_addr,
+    };
+    if let Some(interim) = interim {
+        request.extensions.insert(interim);
+    }
+    if let Some(hijack) = &hijack {
+        request.extensions.insert(hijack.clone());
+    }
+    if let Some(body) = unbuffered {
+        body.attach(&mut request);
+    }
+    if let Some(local_addr) = local_addr {
+        request.extensions.insert(LocalHost(local_addr.to_string()));
+    }
+    if let Some(lines) = raw_lines {
+        request.extensions.insert(RawHead(lines));
+    }
+    if let Some(route) = &route {
+        request.extensions.insert(RouteConfig(Arc::clone(route)));
+    }
+    request.extensions.insert(app.tasks.clone());
+    let traffic = Traffic::new(received);
+    request.extensions.insert(traffic.clone());
+
+    let started = Instant::now();
+    if let Some(metrics) = &app.metrics {
+        metrics.request_started();
+    }
+    // Rejected requests may leave unread bytes behind, so their connection is closed.
+    let server_persists = may_persist && rejection.is_none() && !client_closes;
+    let maintenance = config
+        .maintenance
+        .as_ref()
+        .filter(|maintenance| maintenance.refuses(&request.path));
+    let mut response = match (rejection, maintenance) {
+        (Some((status, message)), _) => {
+            ErrorResponse::from(app.dispatch_error(status, message, request)).into_response()
+        }
+        (None, Some(maintenance)) => app.dispatch_maintenance(maintenance, request),
+        (None, None) => app.dispatch(request),
+    };
+    let handled = started.elapsed();
+    if let Err(err) = response.check_header_sizes(max_header_value, max_header_size) {
+        log::error!(
+            "Replaced the {} response to {} route={} with a 500: {}",
+            response.status_code,
+            method.as_str(),
+            route
+                .as_ref()
+                .map_or(UNMATCHED_ROUTE, |route| route.pattern.as_str()),
+            err
+        );
+        response = app.error_response(500, None);
+    }
+    let upgrades = match &hijack {
+        Some(hijack) => hijack.settle(response.status_code) && response.status_code == 101,
+        None => false,
+    };
+    // Switching protocols without a handler to switch to would strand the client.
+    if response.status_code == 101 && !upgrades {
+        log::error!(
+            "A handler answered 101 without registering an upgrade with Request::on_upgrade"
+        );
+        response = app.error_response(500, None);
+    }
+    let handler_closes = response
+        .header("Connection")
+        .is_some_and(|value| value.eq_ignore_ascii_case("close"));
+    let persist = server_persists && !handler_closes && !upgrades;
+    // An upgraded connection is closed once its handler is done, not by this response.
+    if !persist && !upgrades {
+        response.headers.insert("Connection", "close");
+    }
+    let status_code = response.status_code;
+    let message = response.into_message();
+    // A streamed body is only known once the handler has read it.
+    if let Some(progress) = streamed {
+        (received.body, received.decoded_body) = progress.bytes();
+    }
+    let response_head = message.head_len();
+    let counts = ByteCounts {
+        request: received,
+        response_head,
+        response_body: message.len() - response_head,
+    };
+    // Record the request before sending it, so a client that has read its response
+    // always finds it counted.
+    if let Some(metrics) = &app.metrics {
+        let route = route
+            .as_ref()
+            .map_or(UNMATCHED_ROUTE, |route| route.pattern.as_str());
+        metrics.request_finished(route, status_code, handled, counts.read(), counts.written());
+    }
+    traffic.finish(&Exchange {
+        bytes: counts,
+        handled,
+    });
+    let slow = slow.map(|slow| SlowRequest { handled, ..slow });
+    (message, persist, slow)
+}
+
+/// A request to log once its response is written, if it took longer than
+/// [`ServerConfig::slow_request_threshold`].
+pub(crate) struct SlowRequest {
+    threshold: Duration,
+    method: RequestType,
+    path: String,
+    route: Option<Arc<EndpointConfig>>,
+    remote_addr: Option<SocketAddr>,
+    received_at: Instant,
+    /// How long the handler took, middleware included.
+    handled: Duration,
+}
+
+impl SlowRequest {
+    /// Logs the request if it was slow, now that its response has been written.
+    pub(crate) fn written(self) {
+        let total = self.received_at.elapsed();
+        if self.handled <= self.threshold && total <= self.threshold {
+            return;
+        }
+        let route = self
+            .route
+            .as_ref()
+            .map_or(UNMATCHED_ROUTE, |route| route.pattern.as_str());
+        log::warn!(
+            target: "rustic::slow",
+            "Slow request from {}: {} /{} route={} handler={}ms total={}ms",
+            Peer(self.remote_addr),
+            self.method.as_str(),
+            self.path,
+            route,
+            self.handled.as_millis(),
+            total.as_millis()
+        );
+    }
+}
+
+/// Undoes the content codings of a request body, removing `Content-Encoding` and setting
+/// `Content-Length` to the decoded size.
+///
+/// Codings are undone from the last applied to the first. Fails with the status to
+/// answer: `415` for an unsupported coding, `413` when the decoded body would exceed
+/// `limit` bytes and `400` for corrupt data.
+fn decode_body(headers: &mut HeaderMap, mut body: Vec<u8>, limit: usize) -> Result<Vec<u8>, u16> {
+    // Repeated headers list the codings in the order they were applied, like one would.
+    let codings: Vec<&str> = headers
+        .get_all("Content-Encoding")
+        .flat_map(|value| value.split(','))
+        .map(str::trim)
+        .collect();
+    if codings.is_empty() {
+        return Ok(body);
+    }
+    for coding in codings.into_iter().rev() {
+        let decoded = match coding.to_ascii_lowercase().as_str() {
+            "identity" | "" => continue,
+            "gzip" | "x-gzip" => gunzip(&body, limit),
+            "deflate" => zlib_decompress(&body, limit),
+            _ => return Err(415),
+        };
+        body = match decoded {
+            Ok(decoded) => decoded,
+            Err(InflateError::TooLarge) => return Err(413),
+            Err(InflateError::Invalid) => return Err(400),
+        };
+    }
+    headers.remove("Content-Encoding");
+    headers.insert("Content-Length", body.len().to_string());
+    Ok(body)
+}
+
+#[cfg(test)]
+mod test_app {
+    use super::*;
+    use crate::http_error::HttpError;
+    use std::io::Cursor;
+
+    fn request(method: RequestType, path: &str) -> Request {
+        Request {
+            method,
+            path: path.to_string(),
+            url: format!("/{}", path),
+            body: String::new(),
+            ..Request::default()
+        }
+    }
+
+    fn teapot(_: Request) -> Option<Response> {
+        Some(Response {
+            status_code: 418,
+            reason: "I'm a teapot".into(),
+            response_body: Some("short and stout".into()),
+            headers: HeaderMap::new(),
+        })
+    }
+
+    fn body(response: &Response) -> &[u8] {
+        response.response_body.as_ref().unwrap().as_bytes()
+    }
+
+    /// Tests that a path registered for other methods answers `405` with `Allow`.
+    #[test]
+    fn test_method_not_allowed() {
+        let mut application = App::new();
+        application.add_endpoint("tea", RequestType::GET, teapot);
+        application.add_endpoint("tea", RequestType::POST, teapot);
+
+        let response = application.dispatch(request(RequestType::DELETE, "tea"));
+        assert_eq!(response.status_code, 405);
+        assert_eq!(response.header("Allow"), Some("GET, POST"));
+        let response = application.dispatch(request(RequestType::DELETE, "coffee"));
+        assert_eq!(response.status_code, 404);
+    }
+
+    /// Tests that `Err` from a `Result` handler is converted into its response.
+    #[test]
+    fn test_result_handler() {
+        fn parse(request: Request) -> Result<Response, HttpError> {
+            let id: u32 = request.path.trim_start_matches("items/").parse()?;
+            Ok(Response {
+                status_code: 200,
+                reason: "OK".into(),
+                response_body: Some(id.to_string().into()),
+                headers: HeaderMap::new(),
+            })
+        }
+
+        let mut application = App::new();
+        application.add_endpoint("items/*", RequestType::GET, parse);
+
+        let response = application.dispatch(request(RequestType::GET, "items/7"));
+        assert_eq!(response.status_code, 200);
+        assert_eq!(body(&response), b"7");
+        let response = application.dispatch(request(RequestType::GET, "items/seven"));
+        assert_eq!(response.status_code, 400);
+        assert_eq!(
+            body(&response),
+            b"Bad Request: invalid digit found in string"
+        );
+    }
+
+    /// Tests that a panicking handler is answered with `500`.
+    #[test]
+    fn test_handler_panic() {
+        let mut application = App::new();
+        application.add_endpoint("boom", RequestType::GET, |_| -> Option<Response> {
+            panic!("boom")
+        });
+        application.set_error_handler(500, |request| Response {
+            status_code: 500,
+            reason: "Internal Server Error".into(),
+            response_body: Some(format!("{} failed", request.path).into()),
+            headers: HeaderMap::new(),
+        });
+
+        let response = application.dispatch(request(RequestType::GET, "boom"));
+        assert_eq!(response.status_code, 500);
+        assert_eq!(body(&response), b"boom failed");
+    }
+
+    /// Tests that endpoint error responses are only rewritten after opting in.
+    #[test]
+    fn test_intercept_handler_errors() {
+        let mut application = App::new();
+        application.add_endpoint("tea", RequestType::GET, teapot);
+        application.set_error_handler(418, |_| Response {
+            status_code: 418,
+            reason: "I'm a teapot".into(),
+            response_body: Some("branded".into()),
+            headers: HeaderMap::new(),
+        });
+
+        let response = application.dispatch(request(RequestType::GET, "tea"));
+        assert_eq!(body(&response), b"short and stout");
+
+        application.intercept_handler_errors(true);
+        let response = application.dispatch(request(RequestType::GET, "tea"));
+        assert_eq!(body(&response), b"branded");
+    }
+
+    /// Tests the JSON and negotiated error bodies, which custom handlers still override.
+    #[test]
+    fn test_error_format() {
+        let mut application = App::new();
+        application.add_endpoint("tea", RequestType::GET, teapot);
+        application.error_format(ErrorFormat::Json);
+        let response = application.dispatch(request(RequestType::GET, "coffee"));
+        assert_eq!(response.header("Content-Type"), Some("application/json"));
+        assert_eq!(
+            body(&response),
+            br#"{"error":{"code":404,"message":"Not Found"}}"#
+        );
+        let response = application.error_response(408, None);
+        assert_eq!(
+            body(&response),
+            br#"{"error":{"code":408,"message":"Request Timeout"}}"#
+        );
+        let response = application.dispatch(request(RequestType::POST, "tea"));
+        assert_eq!(response.status_code, 405);
+        assert_eq!(response.header("Allow"), Some("GET"));
+
+        application.error_format(ErrorFormat::Negotiate);
+        let accepting = |accept: &str| {
+            let mut request = request(RequestType::GET, "coffee");
+            request.headers.insert("Accept", accept);
+            request
+        };
+        let response = application.dispatch(accepting("application/json"));
+        assert_eq!(response.header("Content-Type"), Some("application/json"));
+        assert_eq!(response.header("Vary"), Some("Accept"));
+        for accept in ["text/html", "*/*", "text/plain, application/json"] {
+            let response = application.dispatch(accepting(accept));
+            assert_eq!(body(&response), b"Not Found", "{}", accept);
+        }
+        let response = application.dispatch(request(RequestType::GET, "coffee"));
+        assert_eq!(body(&response), b"Not Found");
+
+        application.set_error_handler(404, |_| Response {
+            status_code: 404,
+            reason: "Not Found".into(),
+            response_body: Some("custom".into()),
+            headers: HeaderMap::new(),
+        });
+        let response = application.dispatch(accepting("application/json"));
+        assert_eq!(body(&response), b"custom");
+    }
+
+    /// Tests matching exact paths and `/*` prefix patterns.
+    #[test]
+    fn test_path_matches() {
+        assert!(path_matches("users", "users", false));
+        assert!(!path_matches("users", "users/1", false));
+        assert!(path_matches("api/*", "api", false));
+        assert!(path_matches("api/*", "api/users/1", false));
+        assert!(!path_matches("api/*", "apis", false));
+        assert!(path_matches("*", "anything/at/all", false));
+    }
+
+    /// Tests that an endpoint added for several request types serves each of them with
+    /// one shared handler, and is listed under all of them in `Allow`.
+    #[test]
+    fn test_endpoint_methods() {
+        let mut application = App::new();
+        application.add_endpoint_methods(
+            "items/{id}",
+            &[RequestType::POST, RequestType::PUT, RequestType::POST],
+            |request| format!("{} item", request.method.as_str()),
+        );
+        application
+            .endpoint("feed", RequestType::GET)
+            .method(RequestType::HEAD)
+            .name("feed")
+            .handler(|_| "feed");
+
+        for method in [RequestType::POST, RequestType::PUT] {
+            let response = application.dispatch(request(method, "items/7"));
+            assert_eq!(
+                body(&response),
+                format!("{} item", method.as_str()).as_bytes()
+            );
+            assert!(application.match_endpoint("items/7", method).is_ok());
+        }
+        let response = application.dispatch(request(RequestType::OPTIONS, "items/7"));
+        assert_eq!(response.status_code, 405);
+        assert_eq!(response.header("Allow"), Some("POST, PUT"));
+        let response = application.dispatch(request(RequestType::OPTIONS, "feed"));
+        assert_eq!(response.header("Allow"), Some("GET, HEAD"));
+
+        let endpoints = application.routes.endpoints.read().unwrap();
+        assert_eq!(endpoints.len(), 4);
+        assert!(Arc::ptr_eq(&endpoints[0].mapper, &endpoints[1].mapper));
+        assert!(Arc::ptr_eq(&endpoints[2].config, &endpoints[3].config));
+    }
+
+    /// Tests that an endpoint for any request type answers those no endpoint for their
+    /// type matches, whenever it was added, and never lets a `405` through.
+    #[test]
+    fn test_any_method() {
+        let mut application = App::new();
+        application.any("hook/*", |request| {
+            format!("any {}", request.method.as_str())
+        });
+        application.get("hook/github", |_| "get");
+        application.post("hook/{name}", |_| "post");
+        let text = |method: RequestType, path: &str| {
+            let response = application.dispatch(request(method, path));
+            assert_eq!(response.status_code, 200);
+            String::from_utf8(body(&response).to_vec()).unwrap()
+        };
+
+        assert_eq!(text(RequestType::GET, "hook/github"), "get");
+        assert_eq!(text(RequestType::POST, "hook/github"), "post");
+        assert_eq!(text(RequestType::DELETE, "hook/github"), "any DELETE");
+        assert_eq!(text(RequestType::GET, "hook/gitlab"), "any GET");
+        assert!(application
+            .match_endpoint("hook/a/b", RequestType::PATCH)
+            .is_ok());
+    }
+
+    /// Tests that constrained segments only match what they accept, and that constrained
+    /// endpoints take precedence over unconstrained ones of the same shape.
+    #[test]
+    fn test_path_constraints() {
+        let mut application = App::new();
+        application.get("users/{name}", |request| {
+            format!("name {}", request.path_param("name").unwrap_or_default())
+        });
+        application.get("users/{id:u64}", |request| {
+            format!("id {}", request.path_param_as::<u64>("id").unwrap())
+        });
+        application.get("orders/{id:uuid}", |_| "order");
+        application.get("tags/{tag:alpha}", |_| "tag");
+        let text = |path: &str| {
+            let response = application.dispatch(request(RequestType::GET, path));
+            match response.status_code {
+                200 => String::from_utf8(body(&response).to_vec()).unwrap(),
+                status => status.to_string(),
+            }
+        };
+
+        assert_eq!(text("users/42"), "id 42");
+        assert_eq!(text("users/avatar.png"), "name avatar.png");
+        assert_eq!(
+            text("users/18446744073709551616"),
+            "name 18446744073709551616"
+        );
+        assert_eq!(text("users/+1"), "name +1");
+        assert_eq!(text("orders/67E55044-10b1-426f-9247-bb680e5fe0c8"), "order");
+        assert_eq!(text("orders/67e55044-10b1-426f-9247"), "404");
+        assert_eq!(text("orders/67e55044x10b1-426f-9247-bb680e5fe0c8"), "404");
+        assert_eq!(text("tags/rust"), "tag");
+        assert_eq!(text("tags/rust2"), "404");
+    }
+
+    /// Tests ordering endpoint paths by how narrowly they match.
+    #[test]
+    fn test_compare_shapes() {
+        use std::cmp::Ordering::{Equal, Greater, Less};
+        assert_eq!(
+            compare_shapes("users/{id:u64}", "users/{name}"),
+            Some(Greater)
+        );
+        assert_eq!(compare_shapes("users/{name}", "users/{id:u64}"), Some(Less));
+        assert_eq!(compare_shapes("users/{id}", "users/{name}"), Some(Equal));
+        assert_eq!(compare_shapes("users/{id:u64}", "users/{tag:alpha}"), None);
+        assert_eq!(compare_shapes("a/{x:u64}/{y}", "a/{x}/{y:alpha}"), None);
+        assert_eq!(compare_shapes("users/{id:u64}", "users/me"), None);
+        assert_eq!(compare_shapes("users/{id:u64}", "users/{id}/*"), None);
+    }
+
+    /// Tests that a constraint of an unknown kind is refused as the endpoint is added.
+    #[test]
+    #[should_panic(expected = "Unknown constraint `i32`")]
+    fn test_unknown_constraint() {
+        App::new().get("users/{id:i32}", |_| "user");
+    }
+
+    /// Builds the app used to test each trailing slash policy against the same routes.
+    fn slash_app(policy: TrailingSlash) -> App {
+        let mut application = App::new();
+        application.trailing_slash(policy);
+        application.get("users", |_| "list");
+        application.get("docs/", |_| "docs");
+        application.get("users/{id}", |request| {
+            request.path_param("id").unwrap_or_default().to_string()
+        });
+        application
+    }
+
+    /// Dispatches a GET request for the given target and returns its status and
+    /// `Location` header.
+    fn get(application: &App, url: &str) -> (u16, Option<String>) {
+        let mut request = request(RequestType::GET, parse_path(url).unwrap_or(""));
+        request.url = url.to_string();
+        let response = application.dispatch(request);
+        let location = response.header("Location").map(str::to_string);
+        (response.status_code, location)
+    }
+
+    /// Tests that `MergeSlashes` treats both forms of a path alike.
+    #[test]
+    fn test_merge_slashes() {
+        let application = slash_app(TrailingSlash::MergeSlashes);
+        assert_eq!(get(&application, "/users").0, 200);
+        assert_eq!(get(&application, "/users/").0, 200);
+        assert_eq!(get(&application, "/users/7/").0, 200);
+        assert_eq!(get(&application, "/docs/").0, 404);
+    }
+
+    /// Tests that `RedirectToCanonical` redirects to the registered form of a path.
+    #[test]
+    fn test_redirect_to_canonical() {
+        let application = slash_app(TrailingSlash::RedirectToCanonical);
+        assert_eq!(get(&application, "/users").0, 200);
+        assert_eq!(
+            get(&application, "/users/?page=2"),
+            (301, Some("/users?page=2".to_string()))
+        );
+        assert_eq!(
+            get(&application, "/docs"),
+            (301, Some("/docs/".to_string()))
+        );
+        assert_eq!(get(&application, "/docs/").0, 200);
+        assert_eq!(
+            get(&application, "/users/7/"),
+            (301, Some("/users/7".to_string()))
+        );
+        assert_eq!(get(&application, "/missing/").0, 404);
+    }
+
+    /// Tests that `Strict` keeps both forms of a path distinct.
+    #[test]
+    fn test_strict_slashes() {
+        let application = slash_app(TrailingSlash::Strict);
+        assert_eq!(get(&application, "/users").0, 200);
+        assert_eq!(get(&application, "/users/").0, 404);
+        assert_eq!(get(&application, "/users/7/").0, 404);
+        assert_eq!(get(&application, "/docs").0, 404);
+        assert_eq!(get(&application, "/docs/").0, 200);
+    }
+
+    /// Tests matching mixed-case requests against lowercase endpoints when enabled.
+    #[test]
+    fn test_case_insensitive_routing() {
+        let mut application = App::new();
+        application.get("api/users/{name}", |request| {
+            format!("{} {}", request.path, request.path_param("name").unwrap())
+        });
+        let mut request = request(RequestType::GET, "API/Users/McAdmin");
+        request.url = "/API/Users/McAdmin?Sort=Asc".to_string();
+        assert_eq!(application.dispatch(request.snapshot()).status_code, 404);
+
+        application.case_insensitive_routing(true);
+        application.get("echo/*", |request| {
+            format!("{:?}", request.query_param("Sort").value())
+        });
+        let response = application.dispatch(request.snapshot());
+        assert_eq!(body(&response), b"API/Users/McAdmin McAdmin");
+
+        let mut echo = request.snapshot();
+        echo.path = "ECHO/x".to_string();
+        echo.url = "/ECHO/x?Sort=Asc".to_string();
+        let response = application.dispatch(echo);
+        assert_eq!(body(&response), b"Some(\"Asc\")");
+        assert!(path_matches("caf\u{e9}", "CAF\u{c9}", true));
+    }
+
+    /// Tests capturing `{name}` segments.
+    #[test]
+    fn test_path_params() {
+        let params = match_path("teams/{team}/users/{id}", "teams/core/users/7", false).unwrap();
+        assert_eq!(params["team"], "core");
+        assert_eq!(params["id"], "7");
+        assert!(match_path("users/{id}", "users/", false).is_none());
+        assert!(match_path("users/{id}", "users/7/posts", false).is_none());
+        assert_eq!(
+            match_path("files/{dir}/*", "files/docs/a/b", false).unwrap()["dir"],
+            "docs"
+        );
+    }
+
+    /// Tests that an endpoint declaring accepted content types answers others with `415`.
+    #[test]
+    fn test_endpoint_accepts() {
+        let mut application = App::new();
+        application.add_endpoint_with_config(
+            "items",
+            RequestType::POST,
+            |_| "stored",
+            EndpointConfig::new().accepts(&["application/json", "multipart/*"]),
+        );
+        application.post("anything", |_| "stored");
+        let post = |path: &str, content_type: Option<&str>, payload: &str| {
+            let mut request = request(RequestType::POST, path);
+            if let Some(content_type) = content_type {
+                request
+                    .headers
+                    .insert("Content-Type".to_string(), content_type.to_string());
+            }
+            request.body = payload.to_string();
+            application.dispatch(request).status_code
+        };
+
+        assert_eq!(post("items", Some("text/plain"), "hi"), 415);
+        assert_eq!(post("items", None, "{}"), 415);
+        assert_eq!(
+            post("items", Some("application/json; charset=utf-8"), "{}"),
+            200
+        );
+        assert_eq!(
+            post("items", Some("Multipart/Form-Data; boundary=x"), "--x"),
+            200
+        );
+        assert_eq!(post("items", None, ""), 200);
+        assert_eq!(post("anything", Some("text/plain"), "hi"), 200);
+    }
+
+    /// Tests that a body that is not UTF-8 reaches an endpoint streaming its body through
+    /// the body reader, even when it was buffered, and is refused elsewhere.
+    #[test]
+    fn test_binary_body() {
+        let mut application = App::new();
+        let echo = |mut request: Request| {
+            let mut body = Vec::new();
+            request.body_reader().read_to_end(&mut body).unwrap();
+            text_response(200, body)
+        };
+        application.add_endpoint_with_config(
+            "raw",
+            RequestType::PUT,
+            echo,
+            EndpointConfig::new().stream_body(),
+        );
+        application.add_endpoint_with_config("text", RequestType::PUT, echo, EndpointConfig::new());
+        let client = crate::test::TestClient::new(application);
+
+        let payload = [0xff, 0x00, 0xfe, 0x80];
+        let response = client.put("/raw").body(payload).send();
+        assert_eq!(response.status, 200);
+        assert_eq!(response.body, payload);
+        let response = client.put("/raw").body("text").send();
+        assert_eq!(response.body, b"text");
+        assert_eq!(client.put("/text").body(payload).send().status, 400);
+    }
+
+    /// Tests that requests missing required query parameters are refused with a `400`
+    /// naming them before the handler runs, unless an error handler takes over.
+    #[test]
+    fn test_endpoint_requires_params() {
+        let reached = Arc::new(Mutex::new(0));
+        let counter = Arc::clone(&reached);
+        let mut application = App::new();
+        application
+            .endpoint("report", RequestType::GET)
+            .require_params(&["user_id", "from"])
+            .handler(move |_| {
+                *counter.lock().unwrap() += 1;
+                "report"
+            });
+        let get = |application: &App, url: &str| {
+            let mut request = request(RequestType::GET, "report");
+            request.url = url.to_string();
+            application.dispatch(request)
+        };
+
+        let response = get(&application, "/report?user_id=7");
+        assert_eq!(response.status_code, 400);
+        assert_eq!(
+            body(&response),
+            b"Bad Request: missing query parameter `from`"
+        );
+        let response = get(&application, "/report?from=");
+        assert_eq!(
+            body(&response),
+            b"Bad Request: missing query parameters `user_id`, `from`"
+        );
+        assert_eq!(*reached.lock().unwrap(), 0);
+
+        let response = get(&application, "/report?user%5Fid=7&from=2024-01-01");
+        assert_eq!(response.status_code, 200);
+        assert_eq!(*reached.lock().unwrap(), 1);
+
+        application.set_error_handler(400, |_| {
+            crate::into_response::text_response(400, "Check the link")
+        });
+        let response = get(&application, "/report");
+        assert_eq!(body(&response), b"Check the link");
+    }
+
+    /// Tests that responses with oversized headers are replaced by a `500`, under the
+    /// server's limits or those of their endpoint.
+    #[test]
+    fn test_response_header_limits() {
+        let header = |bytes: usize| {
+            move |_: Request| {
+                let mut response = text_response(200, "ok");
+                response.headers.insert("X-Blob", "x".repeat(bytes));
+                response
+            }
+        };
+        let mut application = App::new();
+        application.get("blob", header(DEFAULT_MAX_RESPONSE_HEADER_VALUE + 1));
+        application
+            .endpoint("cookies", RequestType::GET)
+            .limit_response_headers(32 * 1024, 128 * 1024)
+            .handler(header(DEFAULT_MAX_RESPONSE_HEADER_VALUE + 1));
+        application.get("fits", header(1000));
+        let config = ServerConfig::new().max_response_header_size(2048);
+        let client = crate::test::TestClient::with_config(application, config);
+
+        let response = client.get("/blob").send();
+        assert_eq!(response.status, 500);
+        assert_eq!(response.header("X-Blob"), None);
+        assert_eq!(client.get("/cookies").send().status, 200);
+        assert_eq!(client.get("/fits").send().status, 200);
+
+        // `X-Blob` takes 1010 bytes, which only pass the limit with `Content-Type` left out.
+        let mut application = App::new();
+        application.get("fits", header(1000));
+        let config = ServerConfig::new().max_response_header_size(1040);
+        let client = crate::test::TestClient::with_config(application, config);
+        assert_eq!(client.get("/fits").send().status, 500);
+    }
+
+    /// Tests that only the request being answered can send interim responses, and only
+    /// until its final response begins.
+    #[test]
+    fn test_interim_channel() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let mut client = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        let (stream, _) = listener.accept().unwrap();
+        let channel = Arc::new(ConnectionWriter::new(stream));
+        let headers = HeaderMap::new();
+
+        let first = channel.open(0);
+        assert!(first.send(100, &headers).is_ok());
+        assert_eq!(
+            first.send(204, &headers).unwrap_err().kind(),
+            io::ErrorKind::InvalidInput
+        );
+        let second = channel.open(1);
+        assert!(first.send(100, &headers).is_err());
+        channel.close();
+        assert!(second.send(103, &headers).is_err());
+
+        drop((first, second, channel));
+        let mut written = String::new();
+        client.read_to_string(&mut written).unwrap();
+        assert_eq!(written, "HTTP/1.1 100 Continue \r\n\r\n");
+    }
+
+    /// Tests that each request gets one final response, after its interim responses,
+    /// and that nothing is written to a connection once it is taken over.
+    #[test]
+    fn test_connection_writer() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let mut client = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        let (stream, _) = listener.accept().unwrap();
+        let writer = Arc::new(ConnectionWriter::new(stream));
+        let message = |text: &'static str| text_response(200, text).into_message();
+
+        let interim = writer.open(0);
+        writer.close();
+        writer.send(&message("first")).unwrap();
+        assert!(matches!(
+            writer.send(&message("twice")),
+            Err(WriteError::OutOfOrder { .. })
+        ));
+        let late = writer.open(0);
+        assert!(late.send(100, &HeaderMap::new()).is_err());
+        drop((interim, late));
+
+        writer.next_response().unwrap();
+        assert!(writer.next_response().is_err());
+        writer.send(&message("second")).unwrap();
+        writer.next_response().unwrap();
+        writer.hand_over();
+        assert!(matches!(
+            writer.send(&message("hijacked")),
+            Err(WriteError::Closed)
+        ));
+
+        drop(writer);
+        let mut written = String::new();
+        client.read_to_string(&mut written).unwrap();
+        assert_eq!(written.matches("HTTP/1.1").count(), 2);
+        assert!(written.contains("\r\n\r\nfirstHTTP/1.1 200 OK \r\n"));
+        assert!(written.ends_with("\r\n\r\nsecond"));
+    }
+
+    /// Well-formed requests the fuzzing tests below start from.
+    const SEED_REQUESTS: &[&[u8]] = &[
+        b"GET / HTTP/1.1\r\nHost: a\r\n\r\n",
+        b"GET /users/7/posts?page=2&sort=new#top HTTP/1.1\r\nHost: a\r\n\r\n",
+        b"POST /upload HTTP/1.1\r\nHost: a\r\nContent-Length: 3\r\n\r\nabc",
+        b"PUT /files/a/b%20c HTTP/1.1\r\nTransfer-Encoding: chunked\r\n\r\n3;x=y\r\nabc\r\n0\r\nT: v\r\n\r\n",
+        b"POST /form HTTP/1.1\r\nContent-Type: application/x-www-form-urlencoded\r\nContent-Length: 7\r\n\r\na=1&b=2",
+        b"OPTIONS * HTTP/1.1\r\nHost: a\r\n\r\nGET /b HTTP/1.0\r\nConnection: close\r\n\r\n",
+    ];
+
+    /// Bytes the mutations insert, chosen to hit the delimiters parsers look for.
+    const INTERESTING_BYTES: &[u8] = b":/ \t\r\n?#&=%;,{}*.0129afAF-\xff\xc3";
+
+    /// A xorshift generator, so that every run checks the same inputs.
+    struct Xorshift(u64);
+
+    impl Xorshift {
+        fn next(&mut self) -> usize {
+            self.0 ^= self.0 << 13;
+            self.0 ^= self.0 >> 7;
+            self.0 ^= self.0 << 17;
+            self.0 as usize
+        }
+
+        fn below(&mut self, bound: usize) -> usize {
+            self.next() % bound.max(1)
+        }
+    }
+
+    /// Derives a malformed request from a seed by a few random insertions, deletions,
+    /// duplications and truncations.
+    fn mutate(rng: &mut Xorshift) -> Vec<u8> {
+        let mut input = SEED_REQUESTS[rng.below(SEED_REQUESTS.len())].to_vec();
+        for _ in 0..=rng.below(4) {
+            let at = rng.below(input.len() + 1);
+            match rng.below(4) {
+                0 => input.insert(at, INTERESTING_BYTES[rng.below(INTERESTING_BYTES.len())]),
+                1 if at < input.len() => {
+                    input.remove(at);
+                }
+                2 => {
+                    let end = (at + rng.below(16)).min(input.len());
+                    let copy = input[at..end].to_vec();
+                    input.splice(at..at, copy);
+                }
+                _ => input.truncate(at.max(1)),
+            }
+        }
+        input
+    }
+
+    /// Tests that malformed requests driven through the whole request pipeline are
+    /// answered or refused without panicking.
+    #[test]
+    fn test_malformed_requests_never_panic() {
+        let mut app = App::new();
+        app.get("", |_| "root");
+        app.get("users/{id}/posts", |request| {
+            format!("{:?}", request.path_param("id"))
+        });
+        app.put("files/*", |request| request.body);
+        app.post("upload", |request| request.body);
+        app.post("form", |request| {
+            format!("{:?}", crate::parse_url::parse_url_param(&request.url))
+        });
+        let config = ServerConfig::new().max_header_size(512).max_body_size(64);
+
+        let mut rng = Xorshift(0x2545_f491_4f6c_dd1d);
+        for _ in 0..5000 {
+            let input = mutate(&mut rng);
+            let mut reader = Cursor::new(input.clone());
+            // Every request consumes at least its request line, so this bounds the loop.
+            for _ in 0..=input.len() {
+                let Ok((head, too_large)) = read_request_head(&app, &config, &mut reader, None)
+                else {
+                    break;
+                };
+                let (response, persist, _) =
+                    answer_request(&app, &config, &mut reader, head, too_large, true);
+                assert!(response.bytes.starts_with(b"HTTP/1.1 "), "{:?}", input);
+                if !persist {
+                    break;
+                }
+            }
+        }
+    }
+
+    /// Tests that the shed `503` is rendered once, and again only once its `Date` is
+    /// out of date.
+    #[test]
+    fn test_refusal_prerendered() {
+        let mut refusal = Refusal::new(Duration::from_millis(1500));
+        let first = refusal.bytes().to_vec();
+        let text = String::from_utf8(first.clone()).unwrap();
+        assert!(text.starts_with("HTTP/1.1 503 "), "{}", text);
+        assert!(text.contains("Retry-After: 2\r\n"), "{}", text);
+        assert!(text.contains("Connection: close\r\n"), "{}", text);
+        assert!(text.contains("Date: "), "{}", text);
+        let rendered = refusal.bytes().as_ptr();
+        assert_eq!(refusal.bytes().as_ptr(), rendered);
+
+        refusal.second -= 1;
+        refusal.bytes = b"stale".to_vec();
+        assert_eq!(refusal.bytes().len(), first.len());
+    }
+}
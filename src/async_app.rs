@@ -0,0 +1,563 @@
+use crate::app::{
+    abort_response, refuse_deviations, refuse_request_line, respond, timeout_response, App,
+    BodyPlan, IntoHandlerResult, ReadBody, Request, RequestHead, ServerConfig,
+    DEFAULT_MAX_BODY_SIZE, DEFAULT_MAX_HEADER_SIZE,
+};
+use crate::connection::{
+    framing, is_disconnect, is_listener_broken, BodyError, ChunkDecoder, ChunkStep, Framing,
+    MAX_CHUNK_LINE, MAX_PREALLOCATED_BODY,
+};
+use crate::error::Error;
+use crate::http11_response::{Message, Response};
+use crate::http_error::HttpError;
+use crate::lifecycle::{log_startup, run_start_hooks, ServerInfo};
+use crate::parse_headers::RequestType;
+use crate::schedule::Scheduler;
+use crate::sendfile::file_ended;
+use crate::traffic::CountingReader;
+use std::future::Future;
+use std::io::{self, Read, Seek, SeekFrom};
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::io::{
+    AsyncBufRead, AsyncBufReadExt, AsyncReadExt, AsyncWrite, AsyncWriteExt, BufReader,
+};
+use tokio::net::{TcpListener, TcpStream, ToSocketAddrs};
+use tokio::runtime::Handle;
+use tokio::time::timeout;
+
+/// How many bytes of a file body are read and written at a time.
+const FILE_CHUNK_SIZE: usize = 64 * 1024;
+
+/// Runs the application on tokio, listening at `address` and handling requests.
+///
+/// Tokio's nonblocking sockets let a few threads hold many slow or idle connections.
+/// Handlers and middleware keep their blocking signatures and run on tokio's blocking
+/// thread pool, so the same [`App`] can be served by [`crate::app::run`] or by this
+/// function. The caller provides the runtime.
+///
+/// # Arguments
+///
+/// * `app` - The application instance.
+/// * `address` - The address to listen at, such as `"127.0.0.1:8080"`.
+///
+/// # Returns
+///
+/// * `Result<(), Error>` - Why the server could not start or stopped; it otherwise runs
+///   until the runtime shuts down, so [`App::on_shutdown`] hooks never run.
+///
+/// # Errors
+///
+/// * [`Error::Parse`] - The address is not a valid socket address.
+/// * [`Error::Bind`] - The address could not be bound.
+/// * [`Error::Config`] - An [`App::on_start`] hook panicked.
+/// * [`Error::Accept`] - The listener stopped working.
+///
+/// # Examples
+///
+/// ```no_run
+/// use rustic::app::App;
+/// use rustic::async_app;
+///
+/// let mut application = App::new();
+/// application.get("hello", |_| "Hello!");
+/// let runtime = tokio::runtime::Builder::new_current_thread()
+///     .enable_all()
+///     .build()
+///     .unwrap();
+/// runtime.block_on(async_app::run(application, "127.0.0.1:8080")).unwrap();
+/// ```
+pub async fn run(app: App, address: impl ToSocketAddrs) -> Result<(), Error> {
+    run_with_config(app, address, ServerConfig::new()).await
+}
+
+/// Runs the application on tokio with the given settings; see [`run`].
+///
+/// The timeouts, keep-alive and request settings of `config` apply. The worker,
+/// queue, connection limit and shutdown settings only concern the blocking server.
+///
+/// # Arguments
+///
+/// * `app` - The application instance.
+/// * `address` - The address to listen at.
+/// * `config` - The server settings.
+///
+/// # Returns
+///
+/// * `Result<(), Error>` - Why the server could not start or stopped, as for [`run`].
+pub async fn run_with_config(
+    app: App,
+    address: impl ToSocketAddrs,
+    config: ServerConfig,
+) -> Result<(), Error> {
+    let listener = TcpListener::bind(address).await.map_err(|err| {
+        // Tokio reports an address it cannot parse as invalid input.
+        if err.kind() == io::ErrorKind::InvalidInput {
+            Error::Parse(err.to_string())
+        } else {
+            Error::Bind(err)
+        }
+    })?;
+    let local_addr = listener.local_addr()?;
+    let server = ServerInfo { local_addr };
+    if !run_start_hooks(&app, &server) {
+        return Err(Error::Config("an on_start hook panicked".to_string()));
+    }
+    if config.log_startup {
+        log_startup(&app, &server, None);
+    } else {
+        log::info!("Listening at {}", local_addr);
+    }
+    // Stopped when the server future is dropped.
+    let _scheduler = Scheduler::start(&app.scheduled)?;
+    let app = Arc::new(app);
+    let config = Arc::new(config);
+    loop {
+        match listener.accept().await {
+            Ok((stream, remote_addr)) => {
+                let app = Arc::clone(&app);
+                let config = Arc::clone(&config);
+                tokio::spawn(serve_connection(app, config, stream, remote_addr));
+            }
+            Err(e) if is_disconnect(&e) => {
+                log::debug!("Connection closed before it was accepted: {}", e)
+            }
+            Err(e) if is_listener_broken(&e) => return Err(Error::Accept(e)),
+            Err(e) => log::warn!("Error accepting connection: {}", e),
+        }
+    }
+}
+
+/// Reads the HTTP request headers and body from a connection, like
+/// [`crate::connection::handle_connection`] does for a blocking stream.
+///
+/// # Arguments
+///
+/// * `reader` - The buffered connection to read the request from.
+///
+/// # Returns
+///
+/// * `io::Result<(Vec<String>, String)>` - The header lines, starting with the request
+///   line, and the body.
+///
+/// # Errors
+///
+/// Fails if the connection fails, the head is longer than [`DEFAULT_MAX_HEADER_SIZE`],
+/// or the body is ambiguously framed, cut short, malformed, longer than
+/// [`DEFAULT_MAX_BODY_SIZE`] or not valid UTF-8.
+///
+/// # Examples
+///
+/// ```no_run
+/// use rustic::async_app::handle_connection;
+/// use tokio::io::BufReader;
+/// use tokio::net::TcpListener;
+///
+/// # async fn example() -> std::io::Result<()> {
+/// let listener = TcpListener::bind("127.0.0.1:8080").await?;
+/// let (stream, _) = listener.accept().await?;
+/// let (headers, body) = handle_connection(&mut BufReader::new(stream)).await?;
+/// # Ok(())
+/// # }
+/// ```
+pub async fn handle_connection<R: AsyncBufRead + Unpin>(
+    reader: &mut R,
+) -> io::Result<(Vec<String>, String)> {
+    let mut limited = (&mut *reader).take(DEFAULT_MAX_HEADER_SIZE as u64);
+    let (headers, _) = read_request_head(&mut limited).await?;
+    if limited.limit() == 0 {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "Request head too large",
+        ));
+    }
+    let framing = framing(&headers)
+        .map_err(|_| io::Error::new(io::ErrorKind::InvalidData, "Invalid body framing"))?;
+    let body = match read_body(reader, framing, DEFAULT_MAX_BODY_SIZE, true).await {
+        Ok(body) => body,
+        Err(BodyError::Io(err)) => return Err(err),
+        Err(BodyError::TooLarge) => {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "Request body too large",
+            ))
+        }
+    };
+    let body = String::from_utf8(body).map_err(|_| {
+        io::Error::new(
+            io::ErrorKind::InvalidData,
+            "Request body is not valid UTF-8",
+        )
+    })?;
+    Ok((headers, body))
+}
+
+/// Serializes a response and writes it to a connection.
+///
+/// A [file body](crate::http11_response::Body::file) is copied from its file in chunks.
+///
+/// # Arguments
+///
+/// * `writer` - The connection to write the response to.
+/// * `response` - The response to send.
+pub async fn write_connection<W: AsyncWrite + Unpin>(
+    writer: &mut W,
+    response: Response,
+) -> io::Result<()> {
+    write_message(writer, &response.into_message(), &mut 0).await?;
+    writer.flush().await
+}
+
+/// Writes a serialized response, copying the region of the file it ends with in chunks
+/// of [`FILE_CHUNK_SIZE`] bytes, and adding the bytes written to `written` as they go.
+///
+/// The chunks are read with blocking reads, which a file in the page cache answers
+/// without waiting; the cost lies in writing them to the connection.
+async fn write_message<W: AsyncWrite + Unpin>(
+    writer: &mut W,
+    message: &Message,
+    written: &mut usize,
+) -> io::Result<()> {
+    write_counted(writer, &message.bytes, written).await?;
+    let Some((file, offset, len)) = &message.file else {
+        return Ok(());
+    };
+    let mut file = &**file;
+    file.seek(SeekFrom::Start(*offset))?;
+    let mut chunk = vec![0; (*len).min(FILE_CHUNK_SIZE as u64) as usize];
+    let mut remaining = *len;
+    while remaining > 0 {
+        let wanted = remaining.min(chunk.len() as u64) as usize;
+        let read = file.read(&mut chunk[..wanted])?;
+        if read == 0 {
+            return Err(file_ended());
+        }
+        write_counted(writer, &chunk[..read], written).await?;
+        remaining -= read as u64;
+    }
+    Ok(())
+}
+
+/// Writes all of `bytes` like `write_all`, adding each write to `written` so that it
+/// stays accurate when the future is dropped partway.
+async fn write_counted<W: AsyncWrite + Unpin>(
+    writer: &mut W,
+    mut bytes: &[u8],
+    written: &mut usize,
+) -> io::Result<()> {
+    while !bytes.is_empty() {
+        let count = writer.write(bytes).await?;
+        if count == 0 {
+            return Err(io::ErrorKind::WriteZero.into());
+        }
+        *written += count;
+        bytes = &bytes[count..];
+    }
+    Ok(())
+}
+
+impl App {
+    /// Adds an endpoint whose handler is an `async` function, such as one awaiting
+    /// another service.
+    ///
+    /// Like every handler, it runs through the middleware on tokio's blocking thread
+    /// pool, where its future is driven to completion. It only works when the app is
+    /// served by [`run`]; under the blocking server, it answers
+    /// `500 Internal Server Error`.
+    ///
+    /// # Arguments
+    ///
+    /// * `path` - The path of the endpoint, as for [`App::add_endpoint`].
+    /// * `request` - The request type the endpoint answers.
+    /// * `handler` - The `async` function producing the response.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use rustic::app::{App, Request};
+    /// use rustic::parse_headers::RequestType;
+    /// use std::time::Duration;
+    ///
+    /// let mut application = App::new();
+    /// application.add_async_endpoint("later", RequestType::GET, |_: Request| async {
+    ///     tokio::time::sleep(Duration::from_millis(10)).await;
+    ///     "Done"
+    /// });
+    /// ```
+    pub fn add_async_endpoint<F, Fut, R>(
+        &mut self,
+        path: impl Into<String>,
+        request: RequestType,
+        handler: F,
+    ) where
+        F: Fn(Request) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = R>,
+        R: IntoHandlerResult,
+    {
+        self.add_endpoint(path, request, move |request| match Handle::try_current() {
+            Ok(runtime) => runtime.block_on(handler(request)).into_handler_result(),
+            Err(_) => {
+                log::error!("Async endpoint for /{} called outside tokio", request.path);
+                Some(HttpError::internal().into())
+            }
+        });
+    }
+}
+
+/// Serves the requests of a connection until it closes, its keep-alive timeout passes
+/// or it reaches [`ServerConfig::max_requests_per_connection`].
+async fn serve_connection(
+    app: Arc<App>,
+    config: Arc<ServerConfig>,
+    stream: TcpStream,
+    remote_addr: SocketAddr,
+) {
+    let mut reader = BufReader::with_capacity(config.read_buffer_size, stream);
+    for served in 0..config.max_requests_per_connection {
+        // A connection idle for too long, before its first request or between
+        // keep-alive requests, is closed silently.
+        let idle_timeout = if served == 0 {
+            config.read_timeout
+        } else if config.keep_alive.is_some() {
+            config.keep_alive
+        } else {
+            return;
+        };
+        let waiting = timeout_or_unbounded(idle_timeout, reader.fill_buf()).await;
+        if !matches!(waiting, Some(Ok(buffer)) if !buffer.is_empty()) {
+            return;
+        }
+        let may_persist =
+            config.keep_alive.is_some() && served + 1 < config.max_requests_per_connection;
+        let served = serve_request(&app, &config, &mut reader, Some(remote_addr), may_persist);
+        if !served.await.unwrap_or(false) {
+            return;
+        }
+    }
+}
+
+/// Reads one request from a connection, dispatches it and writes the response.
+///
+/// Returns whether the connection can serve another request, or an error when the
+/// connection failed.
+async fn serve_request(
+    app: &Arc<App>,
+    config: &Arc<ServerConfig>,
+    reader: &mut BufReader<TcpStream>,
+    remote_addr: Option<SocketAddr>,
+    may_persist: bool,
+) -> io::Result<bool> {
+    let mut limited = (&mut *reader).take(config.max_header_size as u64);
+    let head_timeout = match (config.header_timeout, config.read_timeout) {
+        (Some(header), Some(read)) => Some(header.min(read)),
+        (header, read) => header.or(read),
+    };
+    let (mut lines, bare_lf) =
+        match timeout_or_unbounded(head_timeout, read_request_head(&mut limited)).await {
+            Some(read) => read?,
+            None => {
+                let message = timeout_response(app);
+                let stream = reader.get_mut();
+                with_timeout(
+                    config.write_timeout,
+                    write_message(stream, &message, &mut 0),
+                )
+                .await?;
+                return Ok(false);
+            }
+        };
+    let refused = refuse_deviations(app, config, &mut lines, bare_lf, remote_addr)
+        .or_else(|| refuse_request_line(app, &lines, remote_addr));
+    if let Some(message) = refused {
+        let stream = reader.get_mut();
+        with_timeout(
+            config.write_timeout,
+            write_message(stream, &message, &mut 0),
+        )
+        .await?;
+        return Ok(false);
+    }
+    let head_too_large = limited.limit() == 0;
+    let raw_lines = config.keep_raw_head.then(|| lines.clone());
+    let Some(mut head) = RequestHead::parse(lines, remote_addr) else {
+        return Ok(false);
+    };
+    head.raw_lines = raw_lines;
+    head.received.head = (config.max_header_size as u64 - limited.limit()) as usize;
+    if head.lacks_host() {
+        head.local_addr = reader.get_ref().local_addr().ok();
+    }
+    head.find_route(app);
+    let max_body_size = head.max_body_size(config);
+    let read_timeout = head.read_timeout(config);
+
+    let mut counted = CountingReader::new(&mut *reader);
+    let body = match head.body_plan(config) {
+        _ if head_too_large => ReadBody::Rejected(431),
+        BodyPlan::Read(Framing::Unframed | Framing::Length(0)) => ReadBody::Empty,
+        BodyPlan::Read(framing) => {
+            let read = read_body(
+                &mut counted,
+                framing,
+                max_body_size,
+                config.leniency.bare_lf,
+            );
+            match timeout_or_unbounded(read_timeout, read).await {
+                Some(Ok(body)) => ReadBody::Read(body),
+                Some(Err(BodyError::TooLarge)) => ReadBody::Rejected(413),
+                Some(Err(BodyError::Io(_))) | None => ReadBody::Unreadable,
+            }
+        }
+        BodyPlan::UntilClose => {
+            let mut body = Vec::new();
+            let limit = max_body_size as u64 + 1;
+            let mut limited = (&mut counted).take(limit);
+            let read = limited.read_to_end(&mut body);
+            match timeout_or_unbounded(read_timeout, read).await {
+                _ if body.len() > max_body_size => ReadBody::Rejected(413),
+                Some(Ok(_)) => ReadBody::Read(body),
+                Some(Err(_)) | None => ReadBody::Unreadable,
+            }
+        }
+        BodyPlan::Reject(status) => ReadBody::Rejected(status),
+    };
+    head.received.body = counted.count();
+
+    let (app_clone, config_clone) = (Arc::clone(app), Arc::clone(config));
+    let (message, persist, slow) = tokio::task::spawn_blocking(move || {
+        respond(&app_clone, &config_clone, head, body, may_persist)
+    })
+    .await
+    .map_err(io::Error::other)?;
+    let stream = reader.get_mut();
+    let mut written = 0;
+    let sent = with_timeout(
+        config.write_timeout,
+        write_message(stream, &message, &mut written),
+    )
+    .await;
+    if let Some(slow) = slow {
+        slow.written();
+    }
+    if let Err(err) = sent {
+        abort_response(app, &message, written, &err, remote_addr);
+        return Ok(false);
+    }
+    Ok(persist)
+}
+
+/// Waits for `future`, failing with `TimedOut` once `duration` has passed.
+async fn with_timeout<T>(
+    duration: Option<Duration>,
+    future: impl Future<Output = io::Result<T>>,
+) -> io::Result<T> {
+    timeout_or_unbounded(duration, future)
+        .await
+        .unwrap_or_else(|| Err(io::ErrorKind::TimedOut.into()))
+}
+
+/// Waits for `future`, returning `None` once `duration` has passed.
+async fn timeout_or_unbounded<T>(
+    duration: Option<Duration>,
+    future: impl Future<Output = T>,
+) -> Option<T> {
+    match duration {
+        Some(duration) => timeout(duration, future).await.ok(),
+        None => Some(future.await),
+    }
+}
+
+/// Reads the start line and header lines of a request, up to the empty line that
+/// ends the header block or the end of the stream, setting `bare_lf` if a line ends in
+/// a bare LF.
+async fn read_head<R: AsyncBufRead + Unpin>(
+    reader: &mut R,
+    bare_lf: &mut bool,
+) -> io::Result<Vec<String>> {
+    let mut headers = Vec::new();
+    loop {
+        let mut line = String::new();
+        if reader.read_line(&mut line).await? == 0 {
+            return Ok(headers);
+        }
+        let read = line.len();
+        let line = line.strip_suffix('\n').unwrap_or(&line);
+        let line = match line.strip_suffix('\r') {
+            Some(line) => line,
+            None => {
+                *bare_lf |= line.len() < read;
+                line
+            }
+        };
+        if line.is_empty() {
+            return Ok(headers);
+        }
+        headers.push(line.to_string());
+    }
+}
+
+/// Reads the head of a request, skipping one empty line before the request line, like
+/// [`crate::connection`] does for a blocking stream.
+///
+/// Returns the lines, and whether one of them ended in a bare LF.
+async fn read_request_head<R: AsyncBufRead + Unpin>(
+    reader: &mut R,
+) -> io::Result<(Vec<String>, bool)> {
+    let mut bare_lf = false;
+    let head = match read_head(reader, &mut bare_lf).await? {
+        head if head.is_empty() => read_head(reader, &mut bare_lf).await?,
+        head => head,
+    };
+    Ok((head, bare_lf))
+}
+
+/// Reads a request body framed by `Content-Length` or chunked coding, of at most `limit`
+/// bytes. An unframed body is read as empty.
+///
+/// Chunked framing is parsed by the [`ChunkDecoder`] the blocking server uses, with the
+/// same line and trailer limits. Its lines may end in a bare LF only if `bare_lf` is set.
+async fn read_body<R: AsyncBufRead + Unpin>(
+    reader: &mut R,
+    framing: Framing,
+    limit: usize,
+    bare_lf: bool,
+) -> Result<Vec<u8>, BodyError> {
+    let mut body = Vec::new();
+    match framing {
+        Framing::Length(length) if length > limit => return Err(BodyError::TooLarge),
+        Framing::Length(length) => {
+            // The length is only a claim, so memory is reserved as the body arrives.
+            body.reserve(length.min(MAX_PREALLOCATED_BODY));
+            if reader.take(length as u64).read_to_end(&mut body).await? < length {
+                return Err(io::Error::from(io::ErrorKind::UnexpectedEof).into());
+            }
+        }
+        Framing::Chunked => {
+            let mut chunks = ChunkDecoder::new(limit as u64, bare_lf);
+            let mut line = String::new();
+            loop {
+                match chunks.step() {
+                    ChunkStep::Line => {
+                        line.clear();
+                        (&mut *reader)
+                            .take(MAX_CHUNK_LINE)
+                            .read_line(&mut line)
+                            .await?;
+                        chunks.line(&line)?;
+                    }
+                    ChunkStep::Data(size) => {
+                        let read = (&mut *reader).take(size).read_to_end(&mut body).await?;
+                        if (read as u64) < size {
+                            return Err(io::Error::from(io::ErrorKind::UnexpectedEof).into());
+                        }
+                        chunks.data(size);
+                    }
+                    ChunkStep::Done => break,
+                }
+            }
+        }
+        Framing::Unframed => {}
+    }
+    Ok(body)
+}
@@ -0,0 +1,563 @@
+use crate::app::Request;
+use crate::connection::{BodyError, ChunkDecoder, ChunkStep, Framing, MAX_CHUNK_LINE};
+use crate::spool::TempBody;
+use std::borrow::Cow;
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, Cursor, Read, Seek, SeekFrom};
+use std::mem;
+use std::net::TcpStream;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+/// The buffer a streamed body is read through, which bounds how much of the body is in
+/// memory at once.
+const STREAM_BUFFER_SIZE: usize = 8 * 1024;
+
+/// The body of a request, read as the handler goes rather than buffered upfront.
+///
+/// A reader is obtained with [`Request::body_reader`]. For an endpoint configured with
+/// [`EndpointConfig::stream_body`](crate::app::EndpointConfig::stream_body) and served
+/// over a socket by [`run`](crate::app::run) or its variants, reading pulls the body
+/// from the connection, bounded by its `Content-Length` or decoded from chunked coding,
+/// and ends where the body does. A body spilled to a temporary file, past
+/// [`EndpointConfig::spill_threshold`](crate::app::EndpointConfig::spill_threshold), is
+/// read from the file. Otherwise, the body is read from memory.
+///
+/// A streamed body can only be read while the handler runs. Whatever the handler leaves
+/// unread is then skipped by the server, so the connection can serve the next request.
+///
+/// # Examples
+///
+/// ```
+/// use rustic::app::{App, EndpointConfig};
+/// use rustic::parse_headers::RequestType;
+/// use std::io::Read;
+///
+/// let mut application = App::new();
+/// application.add_endpoint_with_config(
+///     "uploads",
+///     RequestType::PUT,
+///     |mut request| {
+///         let mut reader = request.body_reader();
+///         let mut chunk = [0; 8192];
+///         let mut total = 0;
+///         while let Ok(read @ 1..) = reader.read(&mut chunk) {
+///             total += read;
+///         }
+///         format!("Received {} bytes", total)
+///     },
+///     EndpointConfig::new().stream_body().limit_body(1 << 30),
+/// );
+/// ```
+pub struct BodyReader {
+    source: Source,
+}
+
+impl BodyReader {
+    /// Creates a reader over a body already in memory.
+    pub(crate) fn buffered(body: Vec<u8>) -> Self {
+        BodyReader {
+            source: Source::Buffered(Cursor::new(body)),
+        }
+    }
+}
+
+/// Where a [`BodyReader`] reads from.
+enum Source {
+    /// A body buffered before the handler ran.
+    Buffered(Cursor<Vec<u8>>),
+    /// A body spilled to a temporary file, at this offset of it.
+    File(Arc<File>, u64),
+    /// A body still on the connection, shared with the server so it can take it back
+    /// once the handler returns.
+    Streamed(Arc<Mutex<Option<BodyStream>>>),
+}
+
+impl Read for BodyReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match &mut self.source {
+            Source::Buffered(body) => body.read(buf),
+            Source::File(file, position) => {
+                // The file may be read through other readers, so each read seeks first.
+                let mut file = &**file;
+                file.seek(SeekFrom::Start(*position))?;
+                let read = file.read(buf)?;
+                *position += read as u64;
+                Ok(read)
+            }
+            Source::Streamed(shared) => match shared.lock().unwrap().as_mut() {
+                Some(body) => body.read(buf),
+                None => Err(io::Error::new(
+                    io::ErrorKind::BrokenPipe,
+                    "the request has already been answered",
+                )),
+            },
+        }
+    }
+}
+
+impl Request {
+    /// Returns a reader over the request body; see [`BodyReader`].
+    ///
+    /// A streamed body can only be taken once, after which this returns a reader over
+    /// [`Request::body`], which is empty unless the handler filled it. A spilled body
+    /// can be read any number of times from its start. A body in memory is moved out of
+    /// [`Request::body`] into the reader.
+    pub fn body_reader(&mut self) -> BodyReader {
+        if let Some(reader) = self.extensions.remove::<BodyReader>() {
+            return reader;
+        }
+        let source = match self.extensions.get::<TempBody>() {
+            Some(spilled) => Source::File(Arc::clone(spilled.file()), 0),
+            None => Source::Buffered(Cursor::new(mem::take(&mut self.body).into_bytes())),
+        };
+        BodyReader { source }
+    }
+
+    /// Returns the whole request body, loading it from its temporary file if it was
+    /// spilled to one.
+    ///
+    /// A body spilled past
+    /// [`EndpointConfig::spill_threshold`](crate::app::EndpointConfig::spill_threshold)
+    /// is read into memory on each call, so handlers expecting large bodies should read
+    /// them with [`Request::body_reader`] instead. Other bodies are borrowed from
+    /// [`Request::body`], which is empty for a streamed body not buffered yet.
+    ///
+    /// # Errors
+    ///
+    /// Fails when the temporary file cannot be read.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use rustic::app::{App, EndpointConfig, Request};
+    /// use rustic::http_error::HttpError;
+    /// use rustic::parse_headers::RequestType;
+    ///
+    /// fn count(request: Request) -> Result<String, HttpError> {
+    ///     Ok(format!("{} bytes", request.body_bytes()?.len()))
+    /// }
+    ///
+    /// let mut application = App::new();
+    /// application.add_endpoint_with_config(
+    ///     "notes",
+    ///     RequestType::POST,
+    ///     count,
+    ///     EndpointConfig::new().spill_threshold(64 * 1024),
+    /// );
+    /// ```
+    pub fn body_bytes(&self) -> io::Result<Cow<'_, [u8]>> {
+        match self.extensions.get::<TempBody>() {
+            Some(spilled) => {
+                let mut body = Vec::new();
+                BodyReader {
+                    source: Source::File(Arc::clone(spilled.file()), 0),
+                }
+                .read_to_end(&mut body)?;
+                Ok(Cow::Owned(body))
+            }
+            None => Ok(Cow::Borrowed(self.body.as_bytes())),
+        }
+    }
+
+    /// Returns the temporary file a large body was spilled to, if it was.
+    ///
+    /// The file is removed once the request is dropped, even if the handler panics.
+    /// Handlers keeping the body can move the file into place with
+    /// [`std::fs::rename`] first, provided the destination is on the same file system.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use rustic::app::{App, EndpointConfig};
+    /// use rustic::parse_headers::RequestType;
+    ///
+    /// let mut application = App::new();
+    /// application.add_endpoint_with_config(
+    ///     "videos",
+    ///     RequestType::PUT,
+    ///     |request| match request.body_path() {
+    ///         Some(path) => std::fs::rename(path, "/srv/videos/latest.mp4").is_ok(),
+    ///         None => std::fs::write("/srv/videos/latest.mp4", &request.body).is_ok(),
+    ///     }
+    ///     .to_string(),
+    ///     EndpointConfig::new().spill_threshold(1024 * 1024),
+    /// );
+    /// ```
+    pub fn body_path(&self) -> Option<&Path> {
+        self.extensions.get::<TempBody>().map(TempBody::path)
+    }
+
+    /// Reads a streamed or spilled body into [`Request::body`], for code that needs all
+    /// of it at once, such as form parsing. Does nothing when the body is in memory
+    /// already.
+    ///
+    /// A spilled body's temporary file is removed once it has been read.
+    ///
+    /// # Errors
+    ///
+    /// Fails when the body cannot be read from the connection or its file, exceeds the
+    /// limit of the endpoint, or is not valid UTF-8. The body is then left empty.
+    pub fn buffer_body(&mut self) -> io::Result<()> {
+        if !self.has_unbuffered_body() {
+            return Ok(());
+        }
+        let mut body = Vec::new();
+        self.body_reader().read_to_end(&mut body)?;
+        self.extensions.remove::<TempBody>();
+        self.body = String::from_utf8(body)
+            .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))?;
+        Ok(())
+    }
+
+    /// Checks whether the request has a body outside of [`Request::body`], streamed or
+    /// spilled.
+    pub(crate) fn has_unbuffered_body(&self) -> bool {
+        self.extensions.get::<BodyReader>().is_some() || self.extensions.get::<TempBody>().is_some()
+    }
+}
+
+/// A request body kept out of [`Request::body`].
+pub(crate) enum UnbufferedBody {
+    /// The body is read by the handler as it arrives.
+    Streamed(BodyReader),
+    /// The body was written to a temporary file as it arrived.
+    Spilled(TempBody),
+}
+
+impl UnbufferedBody {
+    /// Returns a watch on how much of a streamed body has been read.
+    pub(crate) fn progress(&self) -> Option<StreamProgress> {
+        match self {
+            UnbufferedBody::Streamed(BodyReader {
+                source: Source::Streamed(shared),
+            }) => Some(StreamProgress(Arc::clone(shared))),
+            _ => None,
+        }
+    }
+
+    /// Puts the body where the accessors of the request find it.
+    pub(crate) fn attach(self, request: &mut Request) {
+        match self {
+            UnbufferedBody::Streamed(reader) => {
+                request.extensions.insert(reader);
+            }
+            UnbufferedBody::Spilled(file) => {
+                request.extensions.insert(file);
+            }
+        }
+    }
+}
+
+/// How much of a streamed body has been read, watched while the handler reads it.
+pub(crate) struct StreamProgress(Arc<Mutex<Option<BodyStream>>>);
+
+impl StreamProgress {
+    /// Returns the bytes consumed from the connection so far, framing included, and
+    /// the bytes of body data they held.
+    pub(crate) fn bytes(&self) -> (usize, usize) {
+        match &*self.0.lock().unwrap() {
+            Some(body) => {
+                let buffered = body.reader.buffer().len() as u64;
+                let consumed = body.reader.get_ref().received - buffered;
+                (consumed as usize, body.delivered as usize)
+            }
+            None => (0, 0),
+        }
+    }
+}
+
+/// The server's side of a streamed body, taking it back from the handler.
+pub(crate) struct StreamedBody(Arc<Mutex<Option<BodyStream>>>);
+
+impl StreamedBody {
+    /// Starts streaming a body from `stream`, after the bytes the server has already
+    /// read from it.
+    ///
+    /// # Arguments
+    ///
+    /// * `stream` - The connection, on which reads wait for at most `timeout`.
+    /// * `buffered` - The bytes read from the connection but not processed yet.
+    /// * `framing` - How the body is delimited.
+    /// * `limit` - The largest body accepted, in bytes.
+    /// * `bare_lf` - Whether lines of the chunked framing may end in a bare LF.
+    ///
+    /// # Errors
+    ///
+    /// Fails when the read timeout of the connection cannot be set.
+    pub(crate) fn start(
+        stream: TcpStream,
+        timeout: Option<Duration>,
+        buffered: Vec<u8>,
+        framing: Framing,
+        limit: usize,
+        bare_lf: bool,
+    ) -> io::Result<(Self, BodyReader)> {
+        stream.set_read_timeout(timeout)?;
+        let state = match framing {
+            Framing::Length(length) => State::Length(length as u64),
+            Framing::Chunked => State::Chunked,
+            Framing::Unframed => State::Done,
+        };
+        let body = BodyStream {
+            reader: BufReader::with_capacity(
+                STREAM_BUFFER_SIZE,
+                Prefixed {
+                    buffered,
+                    position: 0,
+                    stream,
+                    received: 0,
+                },
+            ),
+            state,
+            chunks: ChunkDecoder::new(limit as u64, bare_lf),
+            delivered: 0,
+        };
+        let shared = Arc::new(Mutex::new(Some(body)));
+        let reader = BodyReader {
+            source: Source::Streamed(Arc::clone(&shared)),
+        };
+        Ok((StreamedBody(shared), reader))
+    }
+
+    /// Takes the body back once the handler has returned, skipping what it left unread
+    /// if `drain` is set.
+    ///
+    /// # Returns
+    ///
+    /// * `Option<Vec<u8>>` - The bytes read from the connection past the body, which
+    ///   start the next request, or `None` if the body could not be read to its end.
+    pub(crate) fn finish(self, drain: bool) -> Option<Vec<u8>> {
+        let mut body = self.0.lock().unwrap().take()?;
+        if drain {
+            io::copy(&mut body, &mut io::sink()).ok()?;
+        }
+        let prefixed = body.reader.get_ref();
+        let mut unread = body.reader.buffer().to_vec();
+        unread.extend_from_slice(&prefixed.buffered[prefixed.position..]);
+        Some(unread)
+    }
+}
+
+/// A connection read after the bytes the server had buffered from it.
+struct Prefixed {
+    buffered: Vec<u8>,
+    position: usize,
+    stream: TcpStream,
+    /// The bytes read so far, from the buffer and then the connection.
+    received: u64,
+}
+
+impl Read for Prefixed {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let unread = &self.buffered[self.position..];
+        let read = if unread.is_empty() {
+            self.stream.read(buf)?
+        } else {
+            let read = unread.len().min(buf.len());
+            buf[..read].copy_from_slice(&unread[..read]);
+            self.position += read;
+            read
+        };
+        self.received += read as u64;
+        Ok(read)
+    }
+}
+
+/// Where a streamed body is in its framing.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum State {
+    /// This many bytes of a `Content-Length` body are left.
+    Length(u64),
+    /// The body is chunked, with its framing tracked by the [`ChunkDecoder`].
+    Chunked,
+    /// The body has been read to its end.
+    Done,
+    /// The body is malformed, too large or the connection failed.
+    Failed,
+}
+
+/// A body being read from the connection by a [`BodyReader`].
+struct BodyStream {
+    reader: BufReader<Prefixed>,
+    state: State,
+    chunks: ChunkDecoder,
+    /// The bytes of body data handed to the handler so far.
+    delivered: u64,
+}
+
+impl Read for BodyStream {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let result = self.advance(buf);
+        match result {
+            Ok(read) => self.delivered += read as u64,
+            Err(_) => self.state = State::Failed,
+        }
+        result
+    }
+}
+
+impl BodyStream {
+    /// Reads the next bytes of the body, moving through its framing.
+    fn advance(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        loop {
+            match self.state {
+                State::Done => return Ok(0),
+                State::Failed => {
+                    return Err(io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        "the request body could not be read",
+                    ))
+                }
+                State::Length(0) => self.state = State::Done,
+                State::Length(remaining) => {
+                    let read = self.read_data(buf, remaining)?;
+                    self.state = State::Length(remaining - read as u64);
+                    return Ok(read);
+                }
+                State::Chunked => match self.chunks.step() {
+                    ChunkStep::Line => {
+                        let mut line = String::new();
+                        (&mut self.reader)
+                            .take(MAX_CHUNK_LINE)
+                            .read_line(&mut line)?;
+                        self.chunks.line(&line).map_err(|err| match err {
+                            BodyError::TooLarge => io::Error::new(
+                                io::ErrorKind::InvalidData,
+                                "the request body exceeds the limit",
+                            ),
+                            BodyError::Io(err) => err,
+                        })?;
+                    }
+                    ChunkStep::Data(remaining) => {
+                        let read = self.read_data(buf, remaining)?;
+                        self.chunks.data(read as u64);
+                        return Ok(read);
+                    }
+                    ChunkStep::Done => self.state = State::Done,
+                },
+            }
+        }
+    }
+
+    /// Reads at most `remaining` bytes of body data, failing if the connection ends
+    /// first.
+    fn read_data(&mut self, buf: &mut [u8], remaining: u64) -> io::Result<usize> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        let len = buf
+            .len()
+            .min(usize::try_from(remaining).unwrap_or(usize::MAX));
+        match self.reader.read(&mut buf[..len])? {
+            0 => Err(io::ErrorKind::UnexpectedEof.into()),
+            read => Ok(read),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test_body_reader {
+    use super::*;
+    use crate::parse_headers::RequestType;
+    use std::io::Write;
+    use std::net::TcpListener;
+    use std::thread;
+
+    /// Opens a connection on which the client has sent `sent` before closing it, and
+    /// starts streaming a body of at most 100 bytes from it, with `prefix` standing for
+    /// the bytes the server had buffered.
+    fn start(prefix: &[u8], sent: &'static [u8], framing: Framing) -> (StreamedBody, BodyReader) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        let client = thread::spawn(move || {
+            let mut stream = TcpStream::connect(address).unwrap();
+            stream.write_all(sent).unwrap();
+            stream
+        });
+        let (stream, _) = listener.accept().unwrap();
+        client.join().unwrap();
+        let timeout = Some(Duration::from_millis(200));
+        StreamedBody::start(stream, timeout, prefix.to_vec(), framing, 100, true).unwrap()
+    }
+
+    /// Reads a whole streamed body, returning it with what the server gets back.
+    fn stream(
+        prefix: &[u8],
+        sent: &'static [u8],
+        framing: Framing,
+    ) -> (io::Result<Vec<u8>>, Option<Vec<u8>>) {
+        let (streamed, mut reader) = start(prefix, sent, framing);
+        let mut body = Vec::new();
+        let result = reader.read_to_end(&mut body).map(|_| body);
+        (result, streamed.finish(true))
+    }
+
+    /// Tests that `Content-Length` and chunked bodies end where their framing says,
+    /// leaving the bytes past them for the next request.
+    #[test]
+    fn test_streamed_body() {
+        let (body, unread) = stream(b"hel", b"lo world", Framing::Length(5));
+        assert_eq!(body.unwrap(), b"hello");
+        assert_eq!(unread.unwrap(), b" world");
+
+        let chunked =
+            b"4;ext=1\r\nWiki\r\n5\r\npedia\r\n0\r\nExpires: never\r\n\r\nGET / HTTP/1.1\r\n\r\n";
+        let (body, unread) = stream(b"", chunked, Framing::Chunked);
+        assert_eq!(body.unwrap(), b"Wikipedia");
+        assert_eq!(unread.unwrap(), b"GET / HTTP/1.1\r\n\r\n");
+    }
+
+    /// Tests that malformed, oversized and truncated bodies fail, and cannot be drained.
+    #[test]
+    fn test_streamed_body_errors() {
+        for (sent, framing) in [
+            (&b"zz\r\nabc\r\n0\r\n\r\n"[..], Framing::Chunked),
+            (b"3\r\nabcX\r\n0\r\n\r\n", Framing::Chunked),
+            (b"65\r\n", Framing::Chunked),
+            (b"+3\r\nabc\r\n0\r\n\r\n", Framing::Chunked),
+            (b"3\r\nabc\r\n0\r\nX-Sum: 1\r\n", Framing::Chunked),
+            (b"short", Framing::Length(10)),
+        ] {
+            let (body, unread) = stream(b"", sent, framing);
+            assert!(body.is_err(), "{:?}", String::from_utf8_lossy(sent));
+            assert_eq!(unread, None);
+        }
+    }
+
+    /// Tests that a body left unread is drained, and that a reader kept past the
+    /// handler fails.
+    #[test]
+    fn test_unread_body() {
+        let (streamed, mut reader) = start(b"", b"abcdefXYZ", Framing::Length(6));
+        let mut first = [0; 2];
+        reader.read_exact(&mut first).unwrap();
+        assert_eq!(&first, b"ab");
+        assert_eq!(streamed.finish(true).unwrap(), b"XYZ");
+        let err = reader.read(&mut first).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::BrokenPipe);
+    }
+
+    /// Tests that a streamed body is buffered on request, once.
+    #[test]
+    fn test_buffer_body() {
+        let (_streamed, reader) = start(b"", b"a=1&b=2", Framing::Length(7));
+        let mut request = Request {
+            method: RequestType::POST,
+            path: "form".to_string(),
+            url: "/form".to_string(),
+            body: String::new(),
+            ..Request::default()
+        };
+        request.extensions.insert(reader);
+        assert!(request.has_unbuffered_body());
+        request.buffer_body().unwrap();
+        assert!(!request.has_unbuffered_body());
+        assert_eq!(request.body, "a=1&b=2");
+        let mut body = String::new();
+        request.body_reader().read_to_string(&mut body).unwrap();
+        assert_eq!(body, "a=1&b=2");
+        assert!(request.body.is_empty());
+    }
+}
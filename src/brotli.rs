@@ -0,0 +1,768 @@
+//! Brotli compression (RFC 7932), for the `br` content coding without a dependency.
+//!
+//! Matches are found as for DEFLATE, over the same 32 KiB window, and each meta-block codes
+//! its literals, commands and distances with prefix codes built from its own symbol counts.
+//! Block splitting, context modeling and the static dictionary are left out, which costs
+//! some ratio against the reference encoder but keeps the output valid for every decoder.
+
+use std::cmp::Reverse;
+use std::collections::BinaryHeap;
+
+use crate::deflate::{BitWriter, MatchFinder};
+
+/// The most bytes coded in one meta-block, under prefix codes of its own.
+const METABLOCK_SIZE: usize = 1 << 16;
+/// The shortest match worth a command over plain literals.
+const MIN_COPY: usize = 4;
+/// The longest match coded by one command.
+const MAX_COPY: usize = 1 << 12;
+/// The base and extra bit count of each insert length code.
+const INSERT_LENGTHS: [(usize, u32); 24] = [
+    (0, 0),
+    (1, 0),
+    (2, 0),
+    (3, 0),
+    (4, 0),
+    (5, 0),
+    (6, 1),
+    (8, 1),
+    (10, 2),
+    (14, 2),
+    (18, 3),
+    (26, 3),
+    (34, 4),
+    (50, 4),
+    (66, 5),
+    (98, 5),
+    (130, 6),
+    (194, 7),
+    (322, 8),
+    (578, 9),
+    (1090, 10),
+    (2114, 12),
+    (6210, 14),
+    (22594, 24),
+];
+/// The base and extra bit count of each copy length code.
+const COPY_LENGTHS: [(usize, u32); 24] = [
+    (2, 0),
+    (3, 0),
+    (4, 0),
+    (5, 0),
+    (6, 0),
+    (7, 0),
+    (8, 0),
+    (9, 0),
+    (10, 1),
+    (12, 1),
+    (14, 2),
+    (18, 2),
+    (22, 3),
+    (30, 3),
+    (38, 4),
+    (54, 4),
+    (70, 5),
+    (102, 5),
+    (134, 6),
+    (198, 7),
+    (326, 8),
+    (582, 9),
+    (1094, 10),
+    (2118, 24),
+];
+/// The first command symbol for each pair of insert and copy code groups of eight, indexed
+/// by `copy / 8 + 3 * (insert / 8)`, for commands with an explicit distance.
+const COMMAND_CELLS: [u16; 9] = [128, 192, 384, 256, 320, 512, 448, 576, 640];
+/// The sizes of the literal, command and distance alphabets.
+const LITERALS: usize = 256;
+const COMMANDS: usize = 704;
+const DISTANCES: usize = 64;
+/// The longest code of a symbol, and of a code length.
+const MAX_CODE_LENGTH: u8 = 15;
+const MAX_CODE_LENGTH_CODE: u8 = 5;
+/// The order the code lengths of code lengths are sent in.
+const CODE_LENGTH_ORDER: [usize; 18] =
+    [1, 2, 3, 4, 0, 5, 17, 6, 16, 7, 8, 9, 10, 11, 12, 13, 14, 15];
+/// The fixed code, as value and bit count, of each code length of a code length.
+const CODE_LENGTH_CODES: [(u32, u32); 6] = [(0, 2), (7, 4), (3, 3), (2, 2), (1, 2), (15, 4)];
+
+/// Compresses `data` into a Brotli stream.
+///
+/// # Arguments
+///
+/// * `data` - The bytes to compress.
+///
+/// # Returns
+///
+/// * `Vec<u8>` - The compressed stream, with a 22-bit window in its header.
+pub(crate) fn brotli(data: &[u8]) -> Vec<u8> {
+    let mut output = Vec::with_capacity(data.len() / 2 + 16);
+    let mut bits = BitWriter::new(&mut output);
+    // WBITS: a one bit, then 22 - 17 in three bits.
+    bits.write(0b1011, 4);
+    let mut matches = MatchFinder::new();
+    let mut start = 0;
+    while start < data.len() {
+        let end = data.len().min(start + METABLOCK_SIZE);
+        let commands = find_commands(data, start, end, &mut matches);
+        write_metablock(&mut bits, data, start, end, &commands);
+        start = end;
+    }
+    // ISLAST and ISLASTEMPTY.
+    bits.write(0b11, 2);
+    bits.flush();
+    output
+}
+
+/// A run of literals followed by a copy of earlier output, the unit Brotli codes data in.
+struct Command {
+    /// The number of literals, taken in order from the input.
+    insert: usize,
+    /// The number of bytes copied, `0` for the literals ending a meta-block.
+    copy: usize,
+    /// How far back the copy starts.
+    distance: usize,
+}
+
+/// Splits `data[start..end]` into commands, matching back into data before `start`.
+fn find_commands(data: &[u8], start: usize, end: usize, matches: &mut MatchFinder) -> Vec<Command> {
+    let mut commands = Vec::new();
+    let mut insert = 0;
+    let mut position = start;
+    while position < end {
+        let limit = MAX_COPY.min(end - position);
+        let (length, distance) = matches.longest(data, position, limit);
+        let step = if length >= MIN_COPY {
+            commands.push(Command {
+                insert,
+                copy: length,
+                distance,
+            });
+            insert = 0;
+            length
+        } else {
+            insert += 1;
+            1
+        };
+        matches.insert(data, position..position + step);
+        position += step;
+    }
+    if insert > 0 {
+        commands.push(Command {
+            insert,
+            copy: 0,
+            distance: 0,
+        });
+    }
+    commands
+}
+
+/// The symbols and extra bits one command is coded with.
+struct CommandCode {
+    symbol: u16,
+    insert_extra: (u32, u32),
+    copy_extra: (u32, u32),
+    /// The distance symbol and extra bits, `None` when no copy follows.
+    distance: Option<(u16, (u32, u32))>,
+}
+
+impl CommandCode {
+    /// Chooses the codes for `command`.
+    fn new(command: &Command) -> Self {
+        let (insert_code, insert_extra) = length_code(&INSERT_LENGTHS, command.insert);
+        let (copy_code, copy_extra) = length_code(&COPY_LENGTHS, command.copy.max(2));
+        let cell = COMMAND_CELLS[copy_code / 8 + 3 * (insert_code / 8)];
+        let symbol = cell + ((insert_code as u16 & 7) << 3) + (copy_code as u16 & 7);
+        let distance = (command.copy > 0).then(|| distance_code(command.distance));
+        CommandCode {
+            symbol,
+            insert_extra,
+            copy_extra,
+            distance,
+        }
+    }
+}
+
+/// Finds the code of `length` in `table`.
+///
+/// # Returns
+///
+/// * `(usize, (u32, u32))` - The code, with its extra bits as value and bit count.
+fn length_code(table: &[(usize, u32); 24], length: usize) -> (usize, (u32, u32)) {
+    let code = table
+        .iter()
+        .rposition(|&(base, _)| base <= length)
+        .unwrap_or(0);
+    let (base, extra) = table[code];
+    (code, ((length - base) as u32, extra))
+}
+
+/// Finds the code of `distance`, always one that spells it out rather than referring to
+/// the distances used before.
+///
+/// # Returns
+///
+/// * `(u16, (u32, u32))` - The distance symbol, with its extra bits as value and bit count.
+fn distance_code(distance: usize) -> (u16, (u32, u32)) {
+    let value = distance - 1;
+    for code in 0..48 {
+        let extra = 1 + (code >> 1);
+        let offset = ((2 + (code & 1)) << extra) - 4;
+        if value < offset + (1 << extra) {
+            return (16 + code as u16, ((value - offset) as u32, extra as u32));
+        }
+    }
+    unreachable!("distances never exceed the window")
+}
+
+/// Writes `data[start..end]` as one meta-block of `commands`.
+fn write_metablock(
+    bits: &mut BitWriter<'_>,
+    data: &[u8],
+    start: usize,
+    end: usize,
+    commands: &[Command],
+) {
+    let codes: Vec<CommandCode> = commands.iter().map(CommandCode::new).collect();
+    let mut literal_counts = vec![0; LITERALS];
+    let mut command_counts = vec![0; COMMANDS];
+    let mut distance_counts = vec![0; DISTANCES];
+    let mut position = start;
+    for (command, code) in commands.iter().zip(&codes) {
+        for &byte in &data[position..position + command.insert] {
+            literal_counts[usize::from(byte)] += 1;
+        }
+        position += command.insert + command.copy;
+        command_counts[usize::from(code.symbol)] += 1;
+        if let Some((symbol, _)) = code.distance {
+            distance_counts[usize::from(symbol)] += 1;
+        }
+    }
+    let literal_code = PrefixCode::new(&literal_counts, MAX_CODE_LENGTH);
+    let command_code = PrefixCode::new(&command_counts, MAX_CODE_LENGTH);
+    let distance_code = PrefixCode::new(&distance_counts, MAX_CODE_LENGTH);
+
+    // ISLAST is left clear, so an empty last meta-block ends the stream.
+    bits.write(0, 1);
+    // MLEN - 1 in four nibbles, which a meta-block never outgrows.
+    bits.write(0, 2);
+    bits.write((end - start - 1) as u32, 16);
+    // ISUNCOMPRESSED, then one block type each for literals, commands and distances.
+    bits.write(0, 4);
+    // NPOSTFIX and NDIRECT, then the LSB6 context mode for the one literal block type.
+    bits.write(0, 6);
+    bits.write(0, 2);
+    // One literal and one distance prefix code, so no context maps.
+    bits.write(0, 2);
+    literal_code.write_definition(bits, 8);
+    command_code.write_definition(bits, 10);
+    distance_code.write_definition(bits, 6);
+
+    let mut position = start;
+    for (command, code) in commands.iter().zip(&codes) {
+        command_code.write_symbol(bits, usize::from(code.symbol));
+        bits.write(code.insert_extra.0, code.insert_extra.1);
+        bits.write(code.copy_extra.0, code.copy_extra.1);
+        for &byte in &data[position..position + command.insert] {
+            literal_code.write_symbol(bits, usize::from(byte));
+        }
+        if let Some((symbol, (extra, count))) = code.distance {
+            distance_code.write_symbol(bits, usize::from(symbol));
+            bits.write(extra, count);
+        }
+        position += command.insert + command.copy;
+    }
+}
+
+/// A canonical prefix code over one alphabet.
+struct PrefixCode {
+    /// The code length of each symbol, `0` for symbols never coded.
+    lengths: Vec<u8>,
+    /// The code of each symbol, most significant bit first.
+    codes: Vec<u16>,
+    /// The symbol coded in zero bits, when at most one symbol is used.
+    only: Option<usize>,
+}
+
+impl PrefixCode {
+    /// Builds a code from symbol counts, with no code longer than `limit` bits.
+    fn new(counts: &[u32], limit: u8) -> Self {
+        let used: Vec<usize> = (0..counts.len())
+            .filter(|&symbol| counts[symbol] > 0)
+            .collect();
+        if used.len() < 2 {
+            return PrefixCode {
+                lengths: vec![0; counts.len()],
+                codes: vec![0; counts.len()],
+                only: Some(used.first().copied().unwrap_or(0)),
+            };
+        }
+        let lengths = code_lengths(counts, limit);
+        let codes = canonical_codes(&lengths);
+        PrefixCode {
+            lengths,
+            codes,
+            only: None,
+        }
+    }
+
+    /// Writes the code itself, for alphabets whose symbols take `symbol_bits` bits.
+    fn write_definition(&self, bits: &mut BitWriter<'_>, symbol_bits: u32) {
+        if let Some(symbol) = self.only {
+            // HSKIP of 1 marks a simple code, here of a single symbol.
+            bits.write(1, 2);
+            bits.write(0, 2);
+            bits.write(symbol as u32, symbol_bits);
+            return;
+        }
+        let last = self
+            .lengths
+            .iter()
+            .rposition(|&length| length > 0)
+            .unwrap_or(0);
+        let sent = &self.lengths[..=last];
+        let mut counts = [0; 18];
+        for &length in sent {
+            counts[usize::from(length)] += 1;
+        }
+        let length_code = PrefixCode::new(&counts, MAX_CODE_LENGTH_CODE);
+        let mut length_lengths = length_code.lengths.clone();
+        if let Some(length) = length_code.only {
+            // Any length marks the one code length used, which then takes zero bits.
+            length_lengths[length] = 1;
+        }
+        // HSKIP of 0 sends the lengths of all code lengths, up to the last one needed.
+        bits.write(0, 2);
+        let mut space = 32;
+        for &length in &CODE_LENGTH_ORDER {
+            let (code, count) = CODE_LENGTH_CODES[usize::from(length_lengths[length])];
+            bits.write(code, count);
+            if length_lengths[length] > 0 {
+                space -= 32 >> length_lengths[length];
+                if space == 0 {
+                    break;
+                }
+            }
+        }
+        for &length in sent {
+            length_code.write_symbol(bits, usize::from(length));
+        }
+    }
+
+    /// Writes `symbol` with its code.
+    fn write_symbol(&self, bits: &mut BitWriter<'_>, symbol: usize) {
+        let length = self.lengths[symbol];
+        if length > 0 {
+            let reversed = self.codes[symbol].reverse_bits() >> (16 - length);
+            bits.write(u32::from(reversed), u32::from(length));
+        }
+    }
+}
+
+/// Builds Huffman code lengths for `counts`, halving the counts until no code is longer
+/// than `limit` bits.
+fn code_lengths(counts: &[u32], limit: u8) -> Vec<u8> {
+    let mut counts = counts.to_vec();
+    loop {
+        let lengths = huffman_lengths(&counts);
+        if lengths.iter().all(|&length| length <= limit) {
+            return lengths;
+        }
+        for count in counts.iter_mut().filter(|count| **count > 0) {
+            *count = count.div_ceil(2);
+        }
+    }
+}
+
+/// Builds unlimited Huffman code lengths for `counts`, of which at least two are nonzero.
+fn huffman_lengths(counts: &[u32]) -> Vec<u8> {
+    let mut parents = vec![usize::MAX; counts.len()];
+    let mut heap: BinaryHeap<Reverse<(u64, usize)>> = (0..counts.len())
+        .filter(|&symbol| counts[symbol] > 0)
+        .map(|symbol| Reverse((u64::from(counts[symbol]), symbol)))
+        .collect();
+    while let (Some(Reverse((first, a))), Some(Reverse((second, b)))) = (heap.pop(), heap.pop()) {
+        let node = parents.len();
+        parents.push(usize::MAX);
+        parents[a] = node;
+        parents[b] = node;
+        heap.push(Reverse((first + second, node)));
+    }
+    (0..counts.len())
+        .map(|symbol| {
+            let mut length = 0;
+            let mut node = symbol;
+            while parents[node] != usize::MAX {
+                node = parents[node];
+                length += 1;
+            }
+            length
+        })
+        .collect()
+}
+
+/// Assigns canonical codes to `lengths`, shorter codes first and symbols in order within
+/// a length.
+fn canonical_codes(lengths: &[u8]) -> Vec<u16> {
+    let mut counts = [0u16; 16];
+    for &length in lengths.iter().filter(|&&length| length > 0) {
+        counts[usize::from(length)] += 1;
+    }
+    let mut next = [0u16; 16];
+    let mut code = 0;
+    for length in 1..16 {
+        code = (code + counts[length - 1]) << 1;
+        next[length] = code;
+    }
+    lengths
+        .iter()
+        .map(|&length| {
+            if length == 0 {
+                return 0;
+            }
+            let code = next[usize::from(length)];
+            next[usize::from(length)] += 1;
+            code
+        })
+        .collect()
+}
+
+/// A decoder for the subset of Brotli that [`brotli`] writes, to check it against.
+#[cfg(test)]
+pub(crate) mod decoder {
+    use super::*;
+
+    /// Decodes a stream without block splitting, context maps or repeated code lengths,
+    /// but with any distance code and simple or complex prefix codes.
+    pub(crate) fn decompress(data: &[u8]) -> Result<Vec<u8>, &'static str> {
+        let mut bits = BitReader { data, position: 0 };
+        let window_bits = if bits.read(1)? == 0 {
+            16
+        } else {
+            match bits.read(3)? {
+                0 => match bits.read(3)? {
+                    0 => 17,
+                    1 => return Err("reserved window size"),
+                    large => 8 + large,
+                },
+                size => 17 + size,
+            }
+        };
+        let max_distance = (1usize << window_bits) - 16;
+        let mut output = Vec::new();
+        let mut last_distances = [16, 15, 11, 4];
+        loop {
+            let last = bits.read(1)? == 1;
+            if last && bits.read(1)? == 1 {
+                return Ok(output);
+            }
+            let nibbles = bits.read(2)?;
+            if nibbles == 3 {
+                return Err("metadata blocks are not supported");
+            }
+            let length = bits.read((nibbles + 4) * 4)? as usize + 1;
+            if !last && bits.read(1)? == 1 {
+                return Err("uncompressed blocks are not supported");
+            }
+            for _ in 0..3 {
+                if bits.read(1)? != 0 {
+                    return Err("block splitting is not supported");
+                }
+            }
+            if bits.read(6)? != 0 {
+                return Err("postfix and direct distances are not supported");
+            }
+            bits.read(2)?;
+            if bits.read(2)? != 0 {
+                return Err("context maps are not supported");
+            }
+            let literals = Decoder::read(&mut bits, LITERALS, 8)?;
+            let commands = Decoder::read(&mut bits, COMMANDS, 10)?;
+            let distances = Decoder::read(&mut bits, DISTANCES, 6)?;
+            let end = output.len() + length;
+            while output.len() < end {
+                let symbol = commands.decode(&mut bits)?;
+                let cell = symbol >> 6;
+                let (insert_group, copy_group) = match cell {
+                    0 | 2 => (0, 0),
+                    1 | 3 => (0, 8),
+                    4 => (8, 0),
+                    5 => (8, 8),
+                    6 => (0, 16),
+                    7 => (16, 0),
+                    8 => (8, 16),
+                    9 => (16, 8),
+                    _ => (16, 16),
+                };
+                let (insert_base, insert_extra) =
+                    INSERT_LENGTHS[insert_group + ((symbol >> 3) & 7)];
+                let insert = insert_base + bits.read(insert_extra)? as usize;
+                let (copy_base, copy_extra) = COPY_LENGTHS[copy_group + (symbol & 7)];
+                let copy = copy_base + bits.read(copy_extra)? as usize;
+                for _ in 0..insert {
+                    output.push(literals.decode(&mut bits)? as u8);
+                }
+                if output.len() == end {
+                    break;
+                }
+                if output.len() > end {
+                    return Err("literals overrun the meta-block");
+                }
+                let code = if cell < 2 {
+                    0
+                } else {
+                    distances.decode(&mut bits)?
+                };
+                let distance = if code < 16 {
+                    let base = match code {
+                        0 | 4..=9 => last_distances[3],
+                        1 | 10..=15 => last_distances[2],
+                        2 => last_distances[1],
+                        _ => last_distances[0],
+                    };
+                    let delta = [0, 0, 0, 0, -1, 1, -2, 2, -3, 3, -1, 1, -2, 2, -3, 3][code];
+                    let value = base as isize + delta;
+                    if value <= 0 {
+                        return Err("invalid repeated distance");
+                    }
+                    value as usize
+                } else {
+                    let code = code - 16;
+                    let extra = 1 + (code >> 1) as u32;
+                    let offset = ((2 + (code & 1)) << extra) - 4;
+                    offset + bits.read(extra)? as usize + 1
+                };
+                if distance > output.len().min(max_distance) {
+                    return Err("dictionary references are not supported");
+                }
+                if code != 0 {
+                    last_distances.rotate_left(1);
+                    last_distances[3] = distance;
+                }
+                for _ in 0..copy {
+                    output.push(output[output.len() - distance]);
+                }
+                if output.len() > end {
+                    return Err("copy overruns the meta-block");
+                }
+            }
+            if last {
+                return Ok(output);
+            }
+        }
+    }
+
+    /// Reads bits least significant first.
+    struct BitReader<'a> {
+        data: &'a [u8],
+        position: usize,
+    }
+
+    impl BitReader<'_> {
+        fn read(&mut self, count: u32) -> Result<u32, &'static str> {
+            let mut value = 0;
+            for index in 0..count {
+                let byte = self
+                    .data
+                    .get(self.position / 8)
+                    .ok_or("unexpected end of stream")?;
+                value |= u32::from(byte >> (self.position % 8) & 1) << index;
+                self.position += 1;
+            }
+            Ok(value)
+        }
+    }
+
+    /// Decodes one canonical prefix code.
+    struct Decoder {
+        /// The number of codes of each length.
+        counts: [usize; 16],
+        /// The symbols, ordered by code.
+        symbols: Vec<usize>,
+    }
+
+    impl Decoder {
+        fn read(
+            bits: &mut BitReader<'_>,
+            alphabet: usize,
+            symbol_bits: u32,
+        ) -> Result<Self, &'static str> {
+            let mut lengths = vec![0u8; alphabet];
+            if bits.read(2)? == 1 {
+                let count = bits.read(2)? as usize + 1;
+                let mut symbols = Vec::new();
+                for _ in 0..count {
+                    let symbol = bits.read(symbol_bits)? as usize;
+                    if symbol >= alphabet {
+                        return Err("symbol outside the alphabet");
+                    }
+                    symbols.push(symbol);
+                }
+                let shape: &[u8] = match count {
+                    1 => &[0],
+                    2 => &[1, 1],
+                    3 => &[1, 2, 2],
+                    _ if bits.read(1)? == 0 => &[2, 2, 2, 2],
+                    _ => &[1, 2, 3, 3],
+                };
+                for (&symbol, &length) in symbols.iter().zip(shape) {
+                    lengths[symbol] = length;
+                }
+                if count == 1 {
+                    return Ok(Decoder {
+                        counts: [0; 16],
+                        symbols,
+                    });
+                }
+                return Ok(Decoder::new(&lengths));
+            }
+            bits.position -= 2;
+            let skip = bits.read(2)? as usize;
+            let mut length_lengths = [0u8; 18];
+            let mut space = 32;
+            let mut used = 0;
+            for &symbol in &CODE_LENGTH_ORDER[skip..] {
+                let length = match (bits.read(1)?, bits.read(1)?) {
+                    (0, 0) => 0,
+                    (0, _) => 3,
+                    (_, 0) => 4,
+                    _ if bits.read(1)? == 0 => 2,
+                    _ if bits.read(1)? == 0 => 1,
+                    _ => 5,
+                };
+                length_lengths[symbol] = length;
+                if length > 0 {
+                    space -= 32 >> length;
+                    used += 1;
+                    if space <= 0 {
+                        break;
+                    }
+                }
+            }
+            if used != 1 && space != 0 {
+                return Err("incomplete code length code");
+            }
+            let length_code = if used == 1 {
+                let only = length_lengths
+                    .iter()
+                    .position(|&length| length > 0)
+                    .unwrap_or(0);
+                Decoder {
+                    counts: [0; 16],
+                    symbols: vec![only],
+                }
+            } else {
+                Decoder::new(&length_lengths)
+            };
+            let mut space = 32768;
+            let mut symbol = 0;
+            while symbol < alphabet && space > 0 {
+                let length = length_code.decode(bits)?;
+                if length > 15 {
+                    return Err("repeated code lengths are not supported");
+                }
+                lengths[symbol] = length as u8;
+                if length > 0 {
+                    space -= 32768 >> length;
+                }
+                symbol += 1;
+            }
+            if space != 0 {
+                return Err("incomplete prefix code");
+            }
+            Ok(Decoder::new(&lengths))
+        }
+
+        fn new(lengths: &[u8]) -> Self {
+            let mut counts = [0; 16];
+            for &length in lengths.iter().filter(|&&length| length > 0) {
+                counts[usize::from(length)] += 1;
+            }
+            let mut symbols: Vec<usize> = (0..lengths.len()).filter(|&s| lengths[s] > 0).collect();
+            symbols.sort_by_key(|&symbol| lengths[symbol]);
+            Decoder { counts, symbols }
+        }
+
+        fn decode(&self, bits: &mut BitReader<'_>) -> Result<usize, &'static str> {
+            if self.counts.iter().all(|&count| count == 0) {
+                return self.symbols.first().copied().ok_or("empty prefix code");
+            }
+            let (mut code, mut first, mut index) = (0, 0, 0);
+            for length in 1..16 {
+                code |= bits.read(1)? as usize;
+                let count = self.counts[length];
+                if code - first < count {
+                    return Ok(self.symbols[index + code - first]);
+                }
+                index += count;
+                first = (first + count) << 1;
+                code <<= 1;
+            }
+            Err("invalid prefix code")
+        }
+    }
+}
+
+#[cfg(test)]
+mod test_brotli {
+    use super::decoder::decompress;
+    use super::*;
+    use crate::deflate::WINDOW;
+
+    /// Round-trips `data` through the encoder and the decoder.
+    fn round_trip(data: &[u8]) {
+        assert_eq!(decompress(&brotli(data)).unwrap(), data);
+    }
+
+    /// An empty body is the window header and an empty last meta-block.
+    #[test]
+    fn test_empty() {
+        assert_eq!(brotli(b""), [0x3b]);
+        round_trip(b"");
+    }
+
+    /// Single bytes and short runs take the simple prefix codes.
+    #[test]
+    fn test_short_inputs() {
+        round_trip(b"a");
+        round_trip(b"ab");
+        round_trip(b"aaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaa");
+    }
+
+    /// Repetitive text comes out smaller and round-trips.
+    #[test]
+    fn test_repetitive_text() {
+        let data = "The quick brown fox jumps over the lazy dog. ".repeat(200);
+        let compressed = brotli(data.as_bytes());
+        assert!(compressed.len() < data.len() / 10);
+        round_trip(data.as_bytes());
+    }
+
+    /// Input spanning several meta-blocks, with matches back across their boundaries and
+    /// every byte value, round-trips.
+    #[test]
+    fn test_large_input() {
+        let mut state = 0x1234_5678u32;
+        let data: Vec<u8> = (0..3 * METABLOCK_SIZE + 17)
+            .map(|index| {
+                state = state.wrapping_mul(1_103_515_245).wrapping_add(12345);
+                if index % 3000 < 1500 {
+                    (state >> 24) as u8
+                } else {
+                    (index % 251) as u8
+                }
+            })
+            .collect();
+        round_trip(&data);
+    }
+
+    /// Matches at the far end of the window decode to the right bytes.
+    #[test]
+    fn test_long_distances() {
+        let mut data: Vec<u8> = (0..WINDOW as u32)
+            .map(|i| (i.wrapping_mul(2_654_435_761) >> 13) as u8)
+            .collect();
+        data.extend_from_within(..1000);
+        round_trip(&data);
+    }
+}
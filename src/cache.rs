@@ -0,0 +1,544 @@
+use crate::app::{App, Request};
+use crate::http11_response::{format_http_date, Response};
+use crate::middleware::{Middleware, Next};
+use crate::parse_headers::RequestType;
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::Mutex;
+use std::time::{Duration, Instant, SystemTime};
+
+/// Who may store a response, as set by the `public` and `private` directives.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Visibility {
+    Public,
+    Private,
+}
+
+/// A `Cache-Control` response header value.
+///
+/// Directives render in a fixed order. `public` and `private` exclude each other, so the
+/// one set last wins. `no-store` forbids storing the response at all, which makes every
+/// freshness and revalidation directive meaningless; once it is set, only `no-store`
+/// (and `private`, if set) is rendered.
+///
+/// # Examples
+///
+/// ```
+/// use rustic::cache::CacheControl;
+/// use std::time::Duration;
+/// let cache_control = CacheControl::new()
+///     .public()
+///     .max_age(Duration::from_secs(3600))
+///     .immutable();
+/// assert_eq!(cache_control.to_string(), "public, max-age=3600, immutable");
+/// ```
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct CacheControl {
+    visibility: Option<Visibility>,
+    no_store: bool,
+    no_cache: bool,
+    max_age: Option<Duration>,
+    s_maxage: Option<Duration>,
+    must_revalidate: bool,
+    no_transform: bool,
+    immutable: bool,
+}
+
+impl CacheControl {
+    /// Creates an empty set of directives.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Allows shared caches to store the response.
+    pub fn public(mut self) -> Self {
+        self.visibility = Some(Visibility::Public);
+        self
+    }
+
+    /// Restricts storing the response to the client's own cache.
+    pub fn private(mut self) -> Self {
+        self.visibility = Some(Visibility::Private);
+        self
+    }
+
+    /// Forbids storing the response; see the type documentation for how this overrides
+    /// other directives.
+    pub fn no_store(mut self) -> Self {
+        self.no_store = true;
+        self
+    }
+
+    /// Requires caches to revalidate a stored response before every use.
+    pub fn no_cache(mut self) -> Self {
+        self.no_cache = true;
+        self
+    }
+
+    /// Sets how long the response stays fresh, in whole seconds.
+    pub fn max_age(mut self, max_age: Duration) -> Self {
+        self.max_age = Some(max_age);
+        self
+    }
+
+    /// Sets how long the response stays fresh in shared caches, overriding `max-age` there.
+    pub fn s_maxage(mut self, s_maxage: Duration) -> Self {
+        self.s_maxage = Some(s_maxage);
+        self
+    }
+
+    /// Forbids serving the response once stale without revalidating it.
+    pub fn must_revalidate(mut self) -> Self {
+        self.must_revalidate = true;
+        self
+    }
+
+    /// Forbids intermediaries from transforming the body.
+    pub fn no_transform(mut self) -> Self {
+        self.no_transform = true;
+        self
+    }
+
+    /// Declares that the response will not change while fresh.
+    pub fn immutable(mut self) -> Self {
+        self.immutable = true;
+        self
+    }
+}
+
+impl fmt::Display for CacheControl {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut directives = Vec::new();
+        match self.visibility {
+            Some(Visibility::Public) if !self.no_store => directives.push("public".to_string()),
+            Some(Visibility::Private) => directives.push("private".to_string()),
+            _ => {}
+        }
+        if self.no_store {
+            directives.push("no-store".to_string());
+        } else {
+            if self.no_cache {
+                directives.push("no-cache".to_string());
+            }
+            if let Some(max_age) = self.max_age {
+                directives.push(format!("max-age={}", max_age.as_secs()));
+            }
+            if let Some(s_maxage) = self.s_maxage {
+                directives.push(format!("s-maxage={}", s_maxage.as_secs()));
+            }
+            if self.must_revalidate {
+                directives.push("must-revalidate".to_string());
+            }
+            if self.no_transform {
+                directives.push("no-transform".to_string());
+            }
+            if self.immutable {
+                directives.push("immutable".to_string());
+            }
+        }
+        f.write_str(&directives.join(", "))
+    }
+}
+
+impl Response {
+    /// Sets the `Cache-Control` header, replacing any previous value.
+    ///
+    /// # Arguments
+    ///
+    /// * `cache_control` - The caching directives to send.
+    pub fn cache_control(&mut self, cache_control: &CacheControl) {
+        self.headers
+            .insert("Cache-Control", cache_control.to_string());
+    }
+
+    /// Sets the `Expires` header, replacing any previous value.
+    ///
+    /// # Arguments
+    ///
+    /// * `time` - When the response becomes stale.
+    pub fn expires(&mut self, time: SystemTime) {
+        self.headers.insert("Expires", format_http_date(time));
+    }
+}
+
+struct CacheEntry {
+    response: Response,
+    stored_at: Instant,
+    last_used: u64,
+}
+
+/// A bounded map evicting the least recently used entry once full.
+struct LruStore {
+    entries: HashMap<String, CacheEntry>,
+    max_entries: usize,
+    ttl: Duration,
+    clock: u64,
+}
+
+impl LruStore {
+    fn new(ttl: Duration, max_entries: usize) -> Self {
+        LruStore {
+            entries: HashMap::new(),
+            max_entries,
+            ttl,
+            clock: 0,
+        }
+    }
+
+    /// Returns a fresh entry and its age, dropping it instead if it has outlived the TTL.
+    fn get(&mut self, key: &str) -> Option<(Response, Duration)> {
+        self.clock += 1;
+        let entry = self.entries.get_mut(key)?;
+        let age = entry.stored_at.elapsed();
+        if age >= self.ttl {
+            self.entries.remove(key);
+            return None;
+        }
+        entry.last_used = self.clock;
+        Some((entry.response.clone(), age))
+    }
+
+    fn insert(&mut self, key: String, response: Response) {
+        if self.max_entries == 0 {
+            return;
+        }
+        self.clock += 1;
+        if !self.entries.contains_key(&key) && self.entries.len() >= self.max_entries {
+            let ttl = self.ttl;
+            self.entries
+                .retain(|_, entry| entry.stored_at.elapsed() < ttl);
+            if self.entries.len() >= self.max_entries {
+                let oldest = self
+                    .entries
+                    .iter()
+                    .min_by_key(|(_, entry)| entry.last_used)
+                    .map(|(key, _)| key.clone());
+                if let Some(oldest) = oldest {
+                    self.entries.remove(&oldest);
+                }
+            }
+        }
+        self.entries.insert(
+            key,
+            CacheEntry {
+                response,
+                stored_at: Instant::now(),
+                last_used: self.clock,
+            },
+        );
+    }
+}
+
+/// Middleware caching successful `GET` responses below a path prefix in memory.
+///
+/// Responses are keyed by path and query string. A hit is served without invoking the
+/// handler, with a fresh `Date` and an `Age` header giving the seconds since the
+/// response was stored. Entries expire after the TTL, and once the cache is full the
+/// least recently used entry is evicted.
+///
+/// The key holds nothing that identifies the client, so requests carrying
+/// `Authorization`, or `Cookie` unless [`allow_cookies`](Self::allow_cookies) is set, go
+/// straight to the handler. Only `2xx` responses are stored, and never those setting a
+/// cookie or carrying `Vary`, whose content depends on request headers left out of the
+/// key. A handler opts a response out with `Cache-Control: no-store`, `private`,
+/// `no-cache` or a zero `max-age` or `s-maxage`.
+pub struct ResponseCache {
+    prefix: String,
+    allow_cookies: bool,
+    store: Mutex<LruStore>,
+}
+
+impl ResponseCache {
+    /// Creates a cache for the paths below `path_prefix`.
+    ///
+    /// # Arguments
+    ///
+    /// * `path_prefix` - The path whose subtree is cached; an empty prefix caches every path.
+    /// * `ttl` - How long a stored response is served.
+    /// * `max_entries` - The maximum number of stored responses.
+    pub fn new(path_prefix: &str, ttl: Duration, max_entries: usize) -> Self {
+        ResponseCache {
+            prefix: path_prefix.trim_matches('/').to_string(),
+            allow_cookies: false,
+            store: Mutex::new(LruStore::new(ttl, max_entries)),
+        }
+    }
+
+    /// Caches requests carrying a `Cookie` header too, for paths whose responses do not
+    /// depend on it. The same stored response is then served whatever the cookie holds.
+    pub fn allow_cookies(mut self) -> Self {
+        self.allow_cookies = true;
+        self
+    }
+
+    fn applies_to(&self, request: &Request) -> bool {
+        let under_prefix = self.prefix.is_empty()
+            || request
+                .path
+                .strip_prefix(&self.prefix)
+                .is_some_and(|rest| rest.is_empty() || rest.starts_with('/'));
+        request.method == RequestType::GET
+            && under_prefix
+            && request.header("Authorization").is_none()
+            && (self.allow_cookies || request.header("Cookie").is_none())
+    }
+}
+
+/// Checks whether a handler allowed its response to be stored.
+fn is_cacheable(response: &Response) -> bool {
+    let opted_out = response.headers.get_all("Cache-Control").any(|value| {
+        value.split(',').any(|directive| {
+            let directive = directive.trim();
+            ["no-store", "private", "no-cache"]
+                .iter()
+                .any(|name| directive.eq_ignore_ascii_case(name))
+                || directive.split_once('=').is_some_and(|(name, seconds)| {
+                    let name = name.trim();
+                    (name.eq_ignore_ascii_case("max-age") || name.eq_ignore_ascii_case("s-maxage"))
+                        && seconds.trim().trim_matches('"') == "0"
+                })
+        })
+    });
+    (200..300).contains(&response.status_code)
+        && response.status_code != 206
+        && !opted_out
+        && response.header("Set-Cookie").is_none()
+        && response.header("Vary").is_none()
+}
+
+impl Middleware for ResponseCache {
+    fn handle(&self, request: Request, next: Next) -> Response {
+        if !self.applies_to(&request) {
+            return next.run(request);
+        }
+
+        let key = request.url.clone();
+        let hit = self.store.lock().unwrap().get(&key);
+        if let Some((mut response, age)) = hit {
+            response.headers.insert("Age", age.as_secs().to_string());
+            return response;
+        }
+
+        let response = next.run(request);
+        if is_cacheable(&response) {
+            let mut stored = response.clone();
+            stored.headers.remove("Date");
+            stored.headers.remove("Age");
+            self.store.lock().unwrap().insert(key, stored);
+        }
+        response
+    }
+}
+
+impl App {
+    /// Caches successful `GET` responses below `path_prefix` with a [`ResponseCache`].
+    ///
+    /// # Arguments
+    ///
+    /// * `path_prefix` - The path whose subtree is cached.
+    /// * `ttl` - How long a stored response is served.
+    /// * `max_entries` - The maximum number of stored responses.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use rustic::app::App;
+    /// use std::time::Duration;
+    /// let mut application = App::new();
+    /// application.cache("reports", Duration::from_secs(60), 100);
+    /// ```
+    pub fn cache(&mut self, path_prefix: &str, ttl: Duration, max_entries: usize) {
+        self.add_middleware(ResponseCache::new(path_prefix, ttl, max_entries));
+    }
+}
+
+#[cfg(test)]
+mod test_cache {
+    use super::*;
+    use crate::header_map::HeaderMap;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::thread;
+    use std::time::UNIX_EPOCH;
+
+    fn response(body: &'static str) -> Response {
+        Response {
+            status_code: 200,
+            reason: "OK".into(),
+            response_body: Some(body.into()),
+            headers: HeaderMap::new(),
+        }
+    }
+
+    /// Tests that the least recently used entry is evicted when the store is full.
+    #[test]
+    fn test_lru_eviction() {
+        let mut store = LruStore::new(Duration::from_secs(60), 2);
+        store.insert("/a".to_string(), response("a"));
+        store.insert("/b".to_string(), response("b"));
+        assert!(store.get("/a").is_some());
+        store.insert("/c".to_string(), response("c"));
+
+        assert!(store.get("/a").is_some());
+        assert!(store.get("/b").is_none());
+        assert!(store.get("/c").is_some());
+    }
+
+    /// Tests that entries are dropped once they outlive the TTL.
+    #[test]
+    fn test_ttl_expiry() {
+        let mut store = LruStore::new(Duration::from_millis(20), 2);
+        store.insert("/a".to_string(), response("a"));
+        assert!(store.get("/a").is_some());
+        thread::sleep(Duration::from_millis(30));
+        assert!(store.get("/a").is_none());
+        assert!(store.entries.is_empty());
+    }
+
+    /// Tests the status and `Cache-Control` rules for storing a response.
+    #[test]
+    fn test_is_cacheable() {
+        assert!(is_cacheable(&response("ok")));
+
+        let mut private = response("private");
+        private.headers.insert(
+            "cache-control".to_string(),
+            "max-age=0, Private".to_string(),
+        );
+        assert!(!is_cacheable(&private));
+
+        let mut error = response("error");
+        error.status_code = 500;
+        assert!(!is_cacheable(&error));
+
+        for directives in ["no-cache", "max-age=0", "public, S-MaxAge=\"0\""] {
+            let mut response = response("revalidate");
+            response.headers.insert("Cache-Control", directives);
+            assert!(!is_cacheable(&response), "{}", directives);
+        }
+        let mut fresh = response("fresh");
+        fresh.headers.insert("Cache-Control", "max-age=60");
+        assert!(is_cacheable(&fresh));
+    }
+
+    /// Tests that responses setting a cookie or carrying `Vary` are never stored.
+    #[test]
+    fn test_is_cacheable_per_client() {
+        let mut cookie = response("session");
+        cookie.headers.insert("Set-Cookie", "sid=user0");
+        assert!(!is_cacheable(&cookie));
+
+        let mut vary = response("negotiated");
+        vary.headers.insert("Vary", "Accept-Language");
+        assert!(!is_cacheable(&vary));
+    }
+
+    /// Builds an application counting its calls, answering `/me` from the request cookie.
+    fn cached_app(cache: ResponseCache, calls: &'static AtomicUsize) -> App {
+        let mut application = App::new();
+        application.add_endpoint("me", RequestType::GET, move |request| {
+            calls.fetch_add(1, Ordering::SeqCst);
+            let cookie = request.header("Cookie").unwrap_or("anonymous").to_string();
+            let mut response = response("me");
+            response.response_body = Some(cookie.into());
+            Some(response)
+        });
+        application.add_middleware(cache);
+        application
+    }
+
+    fn body(response: &Response) -> &[u8] {
+        response.response_body.as_ref().unwrap().as_bytes()
+    }
+
+    fn request(headers: &[(&str, &str)]) -> Request {
+        Request {
+            method: RequestType::GET,
+            path: "me".to_string(),
+            url: "/me".to_string(),
+            headers: headers
+                .iter()
+                .map(|(name, value)| (name.to_string(), value.to_string()))
+                .collect(),
+            ..Request::default()
+        }
+    }
+
+    /// Tests that requests carrying a cookie bypass the cache unless it allows cookies.
+    #[test]
+    fn test_cookie_requests() {
+        static CALLS: AtomicUsize = AtomicUsize::new(0);
+        let application = cached_app(ResponseCache::new("", Duration::from_secs(60), 10), &CALLS);
+        let alice = [("Cookie", "sid=alice")];
+        assert_eq!(body(&application.dispatch(request(&alice))), b"sid=alice");
+        assert_eq!(application.dispatch(request(&alice)).header("Age"), None);
+        let anonymous = application.dispatch(request(&[]));
+        assert_eq!(body(&anonymous), b"anonymous");
+        assert_eq!(CALLS.load(Ordering::SeqCst), 3);
+        assert_eq!(application.dispatch(request(&[])).header("Age"), Some("0"));
+        assert_eq!(CALLS.load(Ordering::SeqCst), 3);
+
+        static SHARED_CALLS: AtomicUsize = AtomicUsize::new(0);
+        let cache = ResponseCache::new("", Duration::from_secs(60), 10).allow_cookies();
+        let application = cached_app(cache, &SHARED_CALLS);
+        application.dispatch(request(&alice));
+        let shared = application.dispatch(request(&[("Cookie", "sid=bob")]));
+        assert_eq!(body(&shared), b"sid=alice");
+        assert_eq!(SHARED_CALLS.load(Ordering::SeqCst), 1);
+    }
+
+    /// Tests rendering directive combinations against known-good header values.
+    #[test]
+    fn test_cache_control_rendering() {
+        assert_eq!(CacheControl::new().to_string(), "");
+        assert_eq!(
+            CacheControl::new()
+                .private()
+                .no_cache()
+                .must_revalidate()
+                .to_string(),
+            "private, no-cache, must-revalidate"
+        );
+        assert_eq!(
+            CacheControl::new()
+                .max_age(Duration::from_secs(60))
+                .s_maxage(Duration::from_millis(600_900))
+                .no_transform()
+                .to_string(),
+            "max-age=60, s-maxage=600, no-transform"
+        );
+        assert_eq!(CacheControl::new().private().public().to_string(), "public");
+    }
+
+    /// Tests that `no-store` suppresses the directives it conflicts with.
+    #[test]
+    fn test_cache_control_no_store() {
+        let cache_control = CacheControl::new()
+            .public()
+            .max_age(Duration::from_secs(3600))
+            .immutable()
+            .no_store();
+        assert_eq!(cache_control.to_string(), "no-store");
+        assert_eq!(
+            CacheControl::new().no_store().private().to_string(),
+            "private, no-store"
+        );
+    }
+
+    /// Tests setting the `Cache-Control` and `Expires` headers on a response.
+    #[test]
+    fn test_response_cache_headers() {
+        let mut response = response("ok");
+        response
+            .headers
+            .insert("cache-control".to_string(), "no-cache".to_string());
+        response.cache_control(&CacheControl::new().max_age(Duration::from_secs(5)));
+        response.expires(UNIX_EPOCH + Duration::from_secs(1_000_000_000));
+
+        assert_eq!(response.headers.len(), 2);
+        assert_eq!(response.header("Cache-Control"), Some("max-age=5"));
+        assert_eq!(
+            response.header("Expires"),
+            Some("Sun, 09 Sep 2001 01:46:40 GMT")
+        );
+    }
+}
@@ -0,0 +1,376 @@
+use crate::app::{DEFAULT_MAX_BODY_SIZE, DEFAULT_MAX_HEADER_SIZE};
+use crate::connection::{copy_body, framing, read_head, BodyError, Framing};
+use crate::header_map::HeaderMap;
+use crate::http11_response::format_header_lines;
+use crate::parse_headers::{parse_response_head, RequestType};
+use std::error::Error;
+use std::fmt;
+use std::io::{self, BufReader, Read, Write};
+use std::net::{TcpStream, ToSocketAddrs};
+use std::time::Duration;
+
+/// An error from sending a request with [`ClientRequest::send`].
+#[derive(Debug)]
+pub enum ClientError {
+    /// The URL is malformed or uses an unsupported scheme.
+    InvalidUrl(String),
+    /// Connecting, writing the request or reading the response failed.
+    Io(io::Error),
+    /// The server's answer is not a valid HTTP/1.1 response.
+    InvalidResponse(String),
+}
+
+impl fmt::Display for ClientError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ClientError::InvalidUrl(url) => write!(f, "Invalid URL: {}", url),
+            ClientError::Io(err) => write!(f, "I/O error: {}", err),
+            ClientError::InvalidResponse(reason) => write!(f, "Invalid response: {}", reason),
+        }
+    }
+}
+
+impl Error for ClientError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            ClientError::Io(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for ClientError {
+    fn from(err: io::Error) -> Self {
+        ClientError::Io(err)
+    }
+}
+
+/// An outbound HTTP/1.1 request.
+///
+/// The request is sent over a fresh TCP connection which is closed after the response
+/// has been read. Only `http://` URLs are supported, and redirects are not followed.
+///
+/// # Examples
+///
+/// ```no_run
+/// use rustic::client::ClientRequest;
+/// let response = ClientRequest::post("http://127.0.0.1:8080/hooks")
+///     .header("Content-Type", "application/json")
+///     .body("{\"event\": \"deploy\"}")
+///     .send()
+///     .unwrap();
+/// println!("{} {}", response.status, response.text());
+/// ```
+#[derive(Debug, Clone)]
+pub struct ClientRequest {
+    method: RequestType,
+    url: String,
+    headers: HeaderMap,
+    body: Vec<u8>,
+    timeout: Duration,
+    max_body_size: usize,
+}
+
+impl ClientRequest {
+    /// Creates a request with the given method and URL.
+    pub fn new(method: RequestType, url: &str) -> Self {
+        ClientRequest {
+            method,
+            url: url.to_string(),
+            headers: HeaderMap::new(),
+            body: Vec::new(),
+            timeout: Duration::from_secs(30),
+            max_body_size: DEFAULT_MAX_BODY_SIZE,
+        }
+    }
+
+    /// Creates a `GET` request.
+    pub fn get(url: &str) -> Self {
+        Self::new(RequestType::GET, url)
+    }
+
+    /// Creates a `HEAD` request.
+    pub fn head(url: &str) -> Self {
+        Self::new(RequestType::HEAD, url)
+    }
+
+    /// Creates a `POST` request.
+    pub fn post(url: &str) -> Self {
+        Self::new(RequestType::POST, url)
+    }
+
+    /// Creates a `PUT` request.
+    pub fn put(url: &str) -> Self {
+        Self::new(RequestType::PUT, url)
+    }
+
+    /// Creates a `PATCH` request.
+    pub fn patch(url: &str) -> Self {
+        Self::new(RequestType::PATCH, url)
+    }
+
+    /// Creates a `DELETE` request.
+    pub fn delete(url: &str) -> Self {
+        Self::new(RequestType::DELETE, url)
+    }
+
+    /// Sets a request header, replacing any previous value.
+    pub fn header(mut self, name: &str, value: &str) -> Self {
+        self.headers.insert(name, value);
+        self
+    }
+
+    /// Sets the request body.
+    pub fn body(mut self, body: impl Into<Vec<u8>>) -> Self {
+        self.body = body.into();
+        self
+    }
+
+    /// Sets how long to wait for the connection, each write and each read.
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    /// Sets the longest response body to accept, in bytes, [`DEFAULT_MAX_BODY_SIZE`] by
+    /// default.
+    pub fn max_body_size(mut self, bytes: usize) -> Self {
+        self.max_body_size = bytes;
+        self
+    }
+
+    /// Sends the request and reads the complete response.
+    ///
+    /// `Host`, `Content-Length` and `Connection: close` are added automatically.
+    /// Response bodies framed by `Content-Length`, by chunked transfer coding, or by the
+    /// connection closing are all supported.
+    ///
+    /// # Returns
+    ///
+    /// * `Result<ClientResponse, ClientError>` - The response, or why it could not be obtained.
+    ///
+    /// # Errors
+    ///
+    /// Fails with [`ClientError::InvalidResponse`] when the response head is longer than
+    /// [`DEFAULT_MAX_HEADER_SIZE`], the body is framed ambiguously, as by conflicting
+    /// `Content-Length` values, or the body is longer than
+    /// [`ClientRequest::max_body_size`].
+    pub fn send(self) -> Result<ClientResponse, ClientError> {
+        let (authority, target) = split_url(&self.url)?;
+        let address = if authority.contains(':') {
+            authority.to_string()
+        } else {
+            format!("{}:80", authority)
+        };
+
+        let stream = connect(&address, self.timeout)?;
+        stream.set_read_timeout(Some(self.timeout))?;
+        stream.set_write_timeout(Some(self.timeout))?;
+
+        let mut headers = self.headers;
+        if !headers.contains_key("Host") {
+            headers.insert("Host", authority);
+        }
+        headers.remove("Content-Length");
+        // Methods with a body always state its length, so servers need not answer `411`.
+        if self.method.requires_length() || !self.body.is_empty() {
+            headers.insert("Content-Length", self.body.len().to_string());
+        }
+        headers.insert("Connection", "close");
+
+        let mut message = format!("{} {} HTTP/1.1\r\n", self.method.as_str(), target).into_bytes();
+        message.extend_from_slice(format_header_lines(&headers).as_bytes());
+        message.extend_from_slice(&self.body);
+        (&stream).write_all(&message)?;
+
+        read_response(&mut BufReader::new(stream), self.method, self.max_body_size)
+    }
+}
+
+/// A response received by [`ClientRequest::send`].
+///
+/// The headers keep every value of a repeated header, such as `Set-Cookie`, in the order
+/// they were received.
+#[derive(Debug, Clone)]
+pub struct ClientResponse {
+    pub status: u16,
+    pub headers: HeaderMap,
+    pub body: Vec<u8>,
+}
+
+impl ClientResponse {
+    /// Looks up the first value of a response header by name, ignoring ASCII case.
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers.get(name)
+    }
+
+    /// Returns the body as text, replacing invalid UTF-8 sequences.
+    pub fn text(&self) -> String {
+        String::from_utf8_lossy(&self.body).into_owned()
+    }
+}
+
+/// Splits an `http://` URL into its authority and its request target.
+fn split_url(url: &str) -> Result<(&str, String), ClientError> {
+    let rest = url.strip_prefix("http://").ok_or_else(|| {
+        ClientError::InvalidUrl(format!("{} (only http:// URLs are supported)", url))
+    })?;
+    let (authority, target) = match rest.find(['/', '?']) {
+        Some(i) if rest[i..].starts_with('?') => (&rest[..i], format!("/{}", &rest[i..])),
+        Some(i) => (&rest[..i], rest[i..].to_string()),
+        None => (rest, "/".to_string()),
+    };
+    if authority.is_empty() {
+        return Err(ClientError::InvalidUrl(url.to_string()));
+    }
+    Ok((authority, target))
+}
+
+/// Connects to the first address `address` resolves to that accepts within `timeout`.
+pub(crate) fn connect(address: impl ToSocketAddrs, timeout: Duration) -> io::Result<TcpStream> {
+    let mut last_error = io::Error::new(io::ErrorKind::NotFound, "Host did not resolve");
+    for socket_addr in address.to_socket_addrs()? {
+        match TcpStream::connect_timeout(&socket_addr, timeout) {
+            Ok(stream) => return Ok(stream),
+            Err(err) => last_error = err,
+        }
+    }
+    Err(last_error)
+}
+
+/// Reads a complete response to a request made with `method`, with a body of at most
+/// `max_body_size` bytes.
+pub(crate) fn read_response<R: io::BufRead>(
+    reader: &mut R,
+    method: RequestType,
+    max_body_size: usize,
+) -> Result<ClientResponse, ClientError> {
+    let mut limited = reader.take(DEFAULT_MAX_HEADER_SIZE as u64);
+    let head = read_head(&mut limited)?;
+    if limited.limit() == 0 {
+        return Err(ClientError::InvalidResponse(
+            "Response head too large".to_string(),
+        ));
+    }
+    let (status, headers) = parse_response_head(&head).map_err(ClientError::InvalidResponse)?;
+
+    let mut body = Vec::new();
+    let has_body = method != RequestType::HEAD
+        && !(100..200).contains(&status)
+        && status != 204
+        && status != 304;
+    if has_body {
+        let framing = framing(&head)
+            .map_err(|_| ClientError::InvalidResponse("Invalid body framing".to_string()))?;
+        let result = match framing {
+            // Without framing headers, the body runs until the server closes the connection.
+            Framing::Unframed => {
+                reader
+                    .take(max_body_size as u64 + 1)
+                    .read_to_end(&mut body)?;
+                if body.len() > max_body_size {
+                    Err(BodyError::TooLarge)
+                } else {
+                    Ok(())
+                }
+            }
+            framing => copy_body(reader, framing, max_body_size, true, &mut body),
+        };
+        match result {
+            Ok(()) => {}
+            Err(BodyError::Io(err)) => return Err(ClientError::Io(err)),
+            Err(BodyError::TooLarge) => {
+                return Err(ClientError::InvalidResponse(
+                    "Response body too large".to_string(),
+                ))
+            }
+        }
+    }
+
+    Ok(ClientResponse {
+        status,
+        headers,
+        body,
+    })
+}
+
+#[cfg(test)]
+mod test_client {
+    use super::*;
+    use std::io::Cursor;
+
+    /// Tests splitting URLs into authority and target.
+    #[test]
+    fn test_split_url() {
+        assert_eq!(
+            split_url("http://localhost:8080/a/b?c=d").unwrap(),
+            ("localhost:8080", "/a/b?c=d".to_string())
+        );
+        assert_eq!(
+            split_url("http://example.com").unwrap(),
+            ("example.com", "/".to_string())
+        );
+        assert_eq!(
+            split_url("http://example.com?q=1").unwrap(),
+            ("example.com", "/?q=1".to_string())
+        );
+        assert!(split_url("https://example.com/").is_err());
+        assert!(split_url("http:///path").is_err());
+    }
+
+    /// Tests reading responses framed by length, by chunks and by connection close.
+    #[test]
+    fn test_read_response() {
+        let mut reader = Cursor::new(b"HTTP/1.1 200 OK\r\nContent-Length: 2\r\n\r\nhiXX".to_vec());
+        let response = read_response(&mut reader, RequestType::GET, DEFAULT_MAX_BODY_SIZE).unwrap();
+        assert_eq!(response.body, b"hi");
+
+        let mut reader = Cursor::new(
+            b"HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\n\r\n2\r\nhi\r\n0\r\n\r\n".to_vec(),
+        );
+        let response = read_response(&mut reader, RequestType::GET, DEFAULT_MAX_BODY_SIZE).unwrap();
+        assert_eq!(response.text(), "hi");
+
+        let mut reader = Cursor::new(b"HTTP/1.1 200 OK\r\n\r\nuntil close".to_vec());
+        let response = read_response(&mut reader, RequestType::GET, DEFAULT_MAX_BODY_SIZE).unwrap();
+        assert_eq!(response.text(), "until close");
+
+        let mut reader = Cursor::new(b"HTTP/1.1 200 OK\r\nContent-Length: 5\r\n\r\n".to_vec());
+        let response =
+            read_response(&mut reader, RequestType::HEAD, DEFAULT_MAX_BODY_SIZE).unwrap();
+        assert!(response.body.is_empty());
+    }
+
+    /// Tests that repeated headers keep every value, and that oversized heads and bodies
+    /// and ambiguous lengths are refused rather than read as something else.
+    #[test]
+    fn test_read_response_limits() {
+        let mut reader = Cursor::new(
+            b"HTTP/1.1 200 OK\r\nSet-Cookie: a=1\r\nSet-Cookie: b=2\r\nContent-Length: 0\r\n\r\n"
+                .to_vec(),
+        );
+        let response = read_response(&mut reader, RequestType::GET, 16).unwrap();
+        let cookies: Vec<&str> = response.headers.get_all("set-cookie").collect();
+        assert_eq!(cookies, ["a=1", "b=2"]);
+
+        for input in [
+            &b"HTTP/1.1 200 OK\r\nContent-Length: 2\r\nContent-Length: 3\r\n\r\nhi"[..],
+            b"HTTP/1.1 200 OK\r\nContent-Length: two\r\n\r\nhi",
+            b"HTTP/1.1 200 OK\r\nContent-Length: 17\r\n\r\n",
+            b"HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\n\r\n11\r\n",
+            b"HTTP/1.1 200 OK\r\n\r\nseventeen bytes!!",
+        ] {
+            let result = read_response(&mut Cursor::new(input.to_vec()), RequestType::GET, 16);
+            assert!(
+                matches!(result, Err(ClientError::InvalidResponse(_))),
+                "{:?}",
+                String::from_utf8_lossy(input)
+            );
+        }
+
+        let mut head = b"HTTP/1.1 200 OK\r\nX-Padding: ".to_vec();
+        head.resize(DEFAULT_MAX_HEADER_SIZE + 1, b'a');
+        let result = read_response(&mut Cursor::new(head), RequestType::GET, 16);
+        assert!(matches!(result, Err(ClientError::InvalidResponse(_))));
+    }
+}
@@ -0,0 +1,404 @@
+use crate::app::Request;
+#[cfg(feature = "brotli")]
+use crate::brotli::brotli;
+use crate::deflate::{gzip, zlib_compress};
+use crate::header_map::HeaderMap;
+use crate::http11_response::{Body, Response};
+use crate::into_response::text_response;
+use crate::middleware::{Middleware, Next};
+
+/// The smallest body [`Compression`] compresses by default, in bytes. Smaller bodies fit
+/// in a packet or two either way.
+pub const DEFAULT_MIN_SIZE: usize = 1024;
+
+/// Media types whose content is already compressed, which [`Compression`] leaves alone.
+/// A type ending in `/` covers every subtype except SVG, which is text.
+const COMPRESSED_TYPES: [&str; 9] = [
+    "image/",
+    "audio/",
+    "video/",
+    "font/woff",
+    "font/woff2",
+    "application/zip",
+    "application/gzip",
+    "application/zstd",
+    "application/x-7z-compressed",
+];
+
+/// A content coding [`Compression`] can encode response bodies with.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Encoding {
+    /// `gzip`, which every client supporting compression understands.
+    Gzip,
+    /// `deflate`, as a zlib stream.
+    Deflate,
+    /// `br`, Brotli, which most browsers accept over HTTPS. Needs the `brotli` feature.
+    #[cfg(feature = "brotli")]
+    Brotli,
+}
+
+impl Encoding {
+    /// Returns the name of the coding in `Accept-Encoding` and `Content-Encoding`.
+    pub fn name(self) -> &'static str {
+        match self {
+            Encoding::Gzip => "gzip",
+            Encoding::Deflate => "deflate",
+            #[cfg(feature = "brotli")]
+            Encoding::Brotli => "br",
+        }
+    }
+
+    /// Encodes `data` with the coding.
+    fn encode(self, data: &[u8]) -> Vec<u8> {
+        match self {
+            Encoding::Gzip => gzip(data),
+            Encoding::Deflate => zlib_compress(data),
+            #[cfg(feature = "brotli")]
+            Encoding::Brotli => brotli(data),
+        }
+    }
+}
+
+/// Middleware that compresses response bodies with the best content coding the client
+/// accepts.
+///
+/// The coding is picked among those the middleware is configured with, by the `q` values
+/// of `Accept-Encoding`; codings the client rates the same are picked in the order of
+/// [`Compression::encodings`]. The body is compressed when it is at least
+/// [`Compression::min_size`] bytes long and not of a type that is already compressed,
+/// such as images and archives, and only kept compressed when that makes it smaller.
+/// The response then gets the matching `Content-Encoding`, and a strong `ETag` is made
+/// weak, as the compressed bytes differ from those it was computed over.
+///
+/// A client refusing uncompressed bodies, with `identity;q=0` or `*;q=0`, gets even
+/// small bodies compressed, or `406 Not Acceptable` when none of its codings is
+/// available.
+///
+/// Responses are left alone when they already have a `Content-Encoding`, when they are
+/// sent from a file, when they carry `Cache-Control: no-transform`, and when they are
+/// partial (`206`) or have no body to compress. Requests without `Accept-Encoding` get
+/// uncompressed bodies. Every other response gets `Vary: Accept-Encoding`.
+///
+/// # Examples
+///
+/// ```
+/// use rustic::app::App;
+/// use rustic::compression::{Compression, Encoding};
+/// use rustic::test::TestClient;
+///
+/// let mut application = App::new();
+/// application.add_middleware(Compression::new().encodings(&[Encoding::Deflate, Encoding::Gzip]));
+/// application.get("report", |_| "All systems nominal. ".repeat(100));
+/// let client = TestClient::new(application);
+///
+/// let response = client.get("/report").header("Accept-Encoding", "gzip, deflate").send();
+/// assert_eq!(response.header("Content-Encoding"), Some("deflate"));
+/// let response = client.get("/report").header("Accept-Encoding", "gzip, deflate;q=0.5").send();
+/// assert_eq!(response.header("Content-Encoding"), Some("gzip"));
+/// ```
+#[derive(Debug, Clone)]
+pub struct Compression {
+    encodings: Vec<Encoding>,
+    min_size: usize,
+}
+
+impl Default for Compression {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Compression {
+    /// Creates the middleware preferring `br` when the `brotli` feature is enabled, then
+    /// `gzip`, then `deflate`, for bodies of at least [`DEFAULT_MIN_SIZE`] bytes.
+    pub fn new() -> Self {
+        Compression {
+            encodings: vec![
+                #[cfg(feature = "brotli")]
+                Encoding::Brotli,
+                Encoding::Gzip,
+                Encoding::Deflate,
+            ],
+            min_size: DEFAULT_MIN_SIZE,
+        }
+    }
+
+    /// Sets the codings to compress with, in the order they are preferred when the
+    /// client rates several of them the same.
+    pub fn encodings(mut self, encodings: &[Encoding]) -> Self {
+        self.encodings = encodings.to_vec();
+        self
+    }
+
+    /// Sets the smallest body to compress, in bytes.
+    pub fn min_size(mut self, bytes: usize) -> Self {
+        self.min_size = bytes;
+        self
+    }
+
+    /// Picks the coding for an `Accept-Encoding` header.
+    ///
+    /// # Returns
+    ///
+    /// * `(Option<Encoding>, bool)` - The coding to compress with, or `None` to send the
+    ///   body as it is, and whether the client refuses it as it is.
+    fn choose(&self, accept_encoding: &str) -> (Option<Encoding>, bool) {
+        let identity = coding_quality(accept_encoding, "identity");
+        let mut chosen = None;
+        let mut best = 0.0;
+        for &encoding in &self.encodings {
+            let quality = coding_quality(accept_encoding, encoding.name()).unwrap_or(0.0);
+            if quality > best {
+                best = quality;
+                chosen = Some(encoding);
+            }
+        }
+        if best < identity.unwrap_or(0.0) {
+            chosen = None;
+        }
+        (chosen, identity == Some(0.0))
+    }
+
+    /// Compresses `response` for a request sent with `accept_encoding`.
+    fn compress(&self, accept_encoding: Option<&str>, mut response: Response) -> Response {
+        let transformable = response.status_code != 206
+            && !response.headers.contains_key("Content-Encoding")
+            && !response.headers.get("Cache-Control").is_some_and(|value| {
+                value
+                    .split(',')
+                    .any(|directive| directive.trim().eq_ignore_ascii_case("no-transform"))
+            });
+        let body = match &response.response_body {
+            Some(body) if transformable && !body.is_file() && !body.is_empty() => body,
+            _ => return response,
+        };
+        add_vary(&mut response.headers);
+        let Some(accept_encoding) = accept_encoding else {
+            return response;
+        };
+        let (chosen, refused) = self.choose(accept_encoding);
+        let Some(encoding) = chosen else {
+            if refused {
+                let mut refusal = text_response(
+                    406,
+                    "Not Acceptable: the response cannot be sent in any coding \
+                     Accept-Encoding allows",
+                );
+                add_vary(&mut refusal.headers);
+                return refusal;
+            }
+            return response;
+        };
+        let worthwhile = body.len() >= self.min_size
+            && !response
+                .headers
+                .get("Content-Type")
+                .is_some_and(is_compressed_type);
+        if !worthwhile && !refused {
+            return response;
+        }
+        let encoded = encoding.encode(body.as_bytes());
+        if encoded.len() >= body.len() && !refused {
+            return response;
+        }
+        response.response_body = Some(Body::from(encoded));
+        response.headers.remove("Content-Length");
+        response.headers.insert("Content-Encoding", encoding.name());
+        if let Some(etag) = response.headers.get("ETag") {
+            if !etag.starts_with("W/") {
+                let weak = format!("W/{}", etag);
+                response.headers.insert("ETag", weak);
+            }
+        }
+        response
+    }
+}
+
+impl Middleware for Compression {
+    fn handle(&self, request: Request, next: Next) -> Response {
+        let accept_encoding = request.header("Accept-Encoding").map(str::to_string);
+        let response = next.run(request);
+        self.compress(accept_encoding.as_deref(), response)
+    }
+}
+
+/// Adds `Accept-Encoding` to the `Vary` header of a response, unless it is already there.
+fn add_vary(headers: &mut HeaderMap) {
+    let listed = headers.get_all("Vary").any(|value| {
+        value.split(',').any(|name| {
+            let name = name.trim();
+            name == "*" || name.eq_ignore_ascii_case("Accept-Encoding")
+        })
+    });
+    if !listed {
+        headers.append("Vary", "Accept-Encoding");
+    }
+}
+
+/// Returns whether `content_type` names a media type whose content is already
+/// compressed; see [`COMPRESSED_TYPES`].
+fn is_compressed_type(content_type: &str) -> bool {
+    let media_type = content_type.split(';').next().unwrap_or("").trim();
+    if media_type.eq_ignore_ascii_case("image/svg+xml") {
+        return false;
+    }
+    COMPRESSED_TYPES
+        .iter()
+        .any(|compressed| match compressed.strip_suffix('/') {
+            Some(family) => media_type
+                .split_once('/')
+                .is_some_and(|(top, _)| top.eq_ignore_ascii_case(family)),
+            None => media_type.eq_ignore_ascii_case(compressed),
+        })
+}
+
+/// Returns the quality an `Accept-Encoding` header gives a content coding.
+///
+/// A coding listed by name takes its own `q` value, and otherwise the value of `*`
+/// applies. Malformed `q` values count as `1.0`.
+///
+/// # Returns
+///
+/// * `Option<f32>` - The quality, or `None` if the header neither names the coding nor
+///   contains `*`.
+pub(crate) fn coding_quality(accept_encoding: &str, coding: &str) -> Option<f32> {
+    let mut wildcard = None;
+    for item in accept_encoding.split(',') {
+        let mut parts = item.split(';');
+        let name = parts.next().unwrap_or("").trim();
+        let quality = parts
+            .find_map(|param| {
+                let (key, value) = param.split_once('=')?;
+                key.trim()
+                    .eq_ignore_ascii_case("q")
+                    .then(|| value.trim().parse().unwrap_or(1.0))
+            })
+            .unwrap_or(1.0);
+        if name.eq_ignore_ascii_case(coding) {
+            return Some(quality);
+        }
+        if name == "*" {
+            wildcard = Some(quality);
+        }
+    }
+    wildcard
+}
+
+#[cfg(test)]
+mod test_compression {
+    use super::*;
+    use crate::app::App;
+    #[cfg(feature = "brotli")]
+    use crate::brotli::decoder::decompress;
+    use crate::inflate::{gunzip, zlib_decompress};
+    use crate::into_response::body_response;
+    use crate::test::TestClient;
+
+    /// Decodes a compressed body back to the original bytes.
+    type Decoder = fn(&[u8]) -> Vec<u8>;
+
+    fn client(compression: Compression) -> TestClient {
+        let mut application = App::new();
+        application.add_middleware(compression);
+        application.get("page", |_| "<p>Hello, compression!</p>\n".repeat(100));
+        application.get("small", |_| "tiny");
+        application.get("photo", |_| body_response(200, "image/png", vec![7; 4096]));
+        TestClient::new(application)
+    }
+
+    /// Tests that each coding asked for is used, and decodes back to the original body.
+    #[test]
+    fn test_encodings() {
+        let client = client(Compression::new());
+        let original = "<p>Hello, compression!</p>\n".repeat(100);
+        let codings: &[(&str, Decoder)] = &[
+            ("gzip", |body| gunzip(body, usize::MAX).unwrap()),
+            ("deflate", |body| zlib_decompress(body, usize::MAX).unwrap()),
+            #[cfg(feature = "brotli")]
+            ("br", |body| decompress(body).unwrap()),
+        ];
+        for (coding, decode) in codings {
+            let response = client.get("/page").header("Accept-Encoding", coding).send();
+            assert_eq!(response.header("Content-Encoding"), Some(*coding));
+            assert_eq!(response.header("Vary"), Some("Accept-Encoding"));
+            let body = &response.body;
+            assert!(body.len() < original.len());
+            assert_eq!(decode(body), original.as_bytes());
+        }
+
+        let plain = client.get("/page").send();
+        assert_eq!(plain.header("Content-Encoding"), None);
+        assert_eq!(plain.header("Vary"), Some("Accept-Encoding"));
+        let response = client
+            .get("/small")
+            .header("Accept-Encoding", "gzip")
+            .send();
+        assert_eq!(response.text(), "tiny");
+        let response = client
+            .get("/photo")
+            .header("Accept-Encoding", "gzip")
+            .send();
+        assert_eq!(response.header("Content-Encoding"), None);
+    }
+
+    /// Tests that codings rated the same are picked by the server's preference, and
+    /// that higher quality wins over it.
+    #[test]
+    fn test_quality_ties() {
+        let client = client(Compression::new());
+        let pick = |accept: &str| {
+            let response = client.get("/page").header("Accept-Encoding", accept).send();
+            response.header("Content-Encoding").map(str::to_string)
+        };
+        assert_eq!(pick("deflate;q=0.8, gzip;q=0.8").as_deref(), Some("gzip"));
+        assert_eq!(pick("gzip;q=0.5, deflate").as_deref(), Some("deflate"));
+        assert_eq!(pick("gzip;q=0.5, identity").as_deref(), None);
+        assert_eq!(pick("zstd").as_deref(), None);
+        if cfg!(feature = "brotli") {
+            assert_eq!(pick("*").as_deref(), Some("br"));
+            assert_eq!(pick("gzip, deflate, br").as_deref(), Some("br"));
+            assert_eq!(pick("br;q=0.5, gzip").as_deref(), Some("gzip"));
+        } else {
+            assert_eq!(pick("*").as_deref(), Some("gzip"));
+            assert_eq!(pick("br").as_deref(), None);
+        }
+
+        let preferring_deflate = Compression::new().encodings(&[Encoding::Deflate, Encoding::Gzip]);
+        let client = self::client(preferring_deflate);
+        let response = client
+            .get("/page")
+            .header("Accept-Encoding", "gzip, deflate")
+            .send();
+        assert_eq!(response.header("Content-Encoding"), Some("deflate"));
+    }
+
+    /// Tests that a client refusing uncompressed bodies gets small ones compressed, and
+    /// `406 Not Acceptable` when none of its codings is available.
+    #[test]
+    fn test_identity_refused() {
+        let client = client(Compression::new());
+        let response = client
+            .get("/small")
+            .header("Accept-Encoding", "gzip, identity;q=0")
+            .send();
+        assert_eq!(response.header("Content-Encoding"), Some("gzip"));
+        assert_eq!(gunzip(&response.body, usize::MAX).unwrap(), b"tiny");
+
+        let response = client
+            .get("/page")
+            .header("Accept-Encoding", "zstd, *;q=0")
+            .send();
+        assert_eq!(response.status, 406);
+        assert_eq!(response.header("Vary"), Some("Accept-Encoding"));
+    }
+
+    /// Tests recognizing media types that are already compressed.
+    #[test]
+    fn test_is_compressed_type() {
+        assert!(is_compressed_type("image/PNG"));
+        assert!(is_compressed_type("application/zip; charset=binary"));
+        assert!(!is_compressed_type("image/svg+xml"));
+        assert!(!is_compressed_type("text/html; charset=utf-8"));
+        assert!(!is_compressed_type("imagery/png"));
+    }
+}
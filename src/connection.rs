@@ -1,8 +1,13 @@
+use crate::app::{DEFAULT_MAX_BODY_SIZE, DEFAULT_MAX_HEADER_SIZE};
+use crate::error::Error;
 use std::{
-    io::{prelude::*, BufReader},
-    net::{TcpListener, TcpStream},
+    io::{self, prelude::*, BufReader},
+    net::TcpListener,
 };
 
+/// The most memory reserved up front for a body, whatever length its headers claim.
+pub(crate) const MAX_PREALLOCATED_BODY: usize = 64 * 1024;
+
 /// Binds a TCP listener to the specified port on the localhost.
 ///
 /// This function creates a `TcpListener` that listens for incoming TCP connections on the
@@ -14,75 +19,805 @@ use std::{
 ///
 /// # Returns
 ///
-/// * `TcpListener` - The bound TCP listener.
-///
-/// # Panics
-///
-/// This function will panic if it fails to bind to the specified port.
+/// * `Result<TcpListener, Error>` - The bound TCP listener, or [`Error::Bind`] with why
+///   binding failed, for example `ErrorKind::AddrInUse` when the port is taken.
 ///
 /// # Examples
 ///
 /// ```no_run
 /// use rustic::connection::listen_at_port;
-/// let listener = listen_at_port(8080);
+/// let listener = match listen_at_port(8080) {
+///     Ok(listener) => listener,
+///     Err(err) => panic!("Cannot listen at port 8080: {}", err),
+/// };
 /// ```
-pub fn listen_at_port(port: u16) -> TcpListener {
-    let address = format!("127.0.0.1:{}", port);
-    TcpListener::bind(address).expect("Failed to bind to port")
+pub fn listen_at_port(port: u16) -> Result<TcpListener, Error> {
+    TcpListener::bind(("127.0.0.1", port)).map_err(Error::Bind)
 }
 
-/// Handles an incoming TCP connection, reading the HTTP request headers and body.
+/// Handles an incoming connection, reading the HTTP request headers and body.
 ///
-/// This function reads from the given TCP stream using a buffered reader, collecting
+/// This function reads from the given stream using a buffered reader, collecting
 /// the headers and body of the HTTP request separately. It first reads the headers
 /// line by line until an empty line is encountered, which signifies the end of the
 /// HTTP headers. Then, it reads the bytes set by content-length header as body.
 ///
+/// Any reader works, so a `TcpStream`, a TLS stream or a Unix socket can be read, as can
+/// an in-memory `Cursor` holding a canned request.
+///
 /// # Arguments
 ///
-/// * `stream` - A mutable reference to the `TcpStream` from which to read the HTTP request.
+/// * `stream` - A mutable reference to the stream from which to read the HTTP request.
 ///
 /// # Returns
 ///
-/// * `(Vec<String>, String)` - A tuple containing:
+/// * `io::Result<Option<(Vec<String>, String)>>` - `None` when the client closed the
+///   connection, or reset it, before sending a request, which is a normal end of a
+///   connection rather than an error. Otherwise a tuple containing:
 ///   - A vector of strings, each representing a line of the HTTP headers.
 ///   - A string containing the body of the HTTP request.
 ///
+/// # Errors
+///
+/// Fails if the connection fails once the request has started, the head is longer than
+/// [`DEFAULT_MAX_HEADER_SIZE`], or the body is malformed, ambiguously framed, longer
+/// than [`DEFAULT_MAX_BODY_SIZE`] or not valid UTF-8. A body cut short is an error
+/// rather than a shorter body.
+///
 /// # Examples
 ///
 /// ```no_run
 /// use rustic::connection::{listen_at_port, handle_connection};
-/// let listener = listen_at_port(8080);
+/// let listener = listen_at_port(8080).unwrap();
 /// let mut stream = listener.accept().unwrap().0;
-/// let (headers, body) = handle_connection(&mut stream);
+/// match handle_connection(&mut stream) {
+///     Ok(Some((headers, body))) => println!("{} header lines", headers.len()),
+///     Ok(None) => println!("The client left without asking anything"),
+///     Err(err) => println!("Bad request: {}", err),
+/// }
+/// ```
+///
 /// ```
-pub fn handle_connection(stream: &mut TcpStream) -> (Vec<String>, String) {
-    let mut buf_reader = BufReader::new(stream);
+/// use rustic::connection::handle_connection;
+/// use std::io::Cursor;
+/// let mut request = Cursor::new(b"POST /echo HTTP/1.1\r\nContent-Length: 2\r\n\r\nhi".to_vec());
+/// let (headers, body) = handle_connection(&mut request).unwrap().unwrap();
+/// assert_eq!(headers, ["POST /echo HTTP/1.1", "Content-Length: 2"]);
+/// assert_eq!(body, "hi");
+/// assert!(handle_connection(&mut Cursor::new(Vec::new())).unwrap().is_none());
+/// ```
+pub fn handle_connection<R: Read>(stream: &mut R) -> io::Result<Option<(Vec<String>, String)>> {
+    read_request(&mut BufReader::new(stream))
+}
 
-    let mut headers: Vec<String> = Vec::new();
-    let mut content_length = 0;
+/// Reads one HTTP request from a buffered reader, leaving any bytes past its body in
+/// the reader.
+///
+/// [`handle_connection`] buffers the stream it is given and drops that buffer when it
+/// returns, losing the start of a pipelined request sent right after the first one.
+/// To read successive requests from one connection, wrap it in a `BufReader` once and
+/// call this function for each request. The body is read exactly as its framing says,
+/// so the next request starts where this one ends.
+///
+/// Malformed input never panics.
+///
+/// # Arguments
+///
+/// * `reader` - The buffered connection to read the request from.
+///
+/// # Returns
+///
+/// * `io::Result<Option<(Vec<String>, String)>>` - The header lines and the body, or
+///   `None` when the connection ended before another request, as for
+///   [`handle_connection`].
+///
+/// # Errors
+///
+/// Fails as [`handle_connection`] does.
+///
+/// # Examples
+///
+/// ```
+/// use rustic::connection::read_request;
+/// use std::io::Cursor;
+/// let mut connection =
+///     Cursor::new(b"GET /a HTTP/1.1\r\n\r\nGET /b HTTP/1.1\r\n\r\n".to_vec());
+/// assert_eq!(read_request(&mut connection).unwrap().unwrap().0, ["GET /a HTTP/1.1"]);
+/// assert_eq!(read_request(&mut connection).unwrap().unwrap().0, ["GET /b HTTP/1.1"]);
+/// assert!(read_request(&mut connection).unwrap().is_none());
+/// ```
+pub fn read_request<R: BufRead>(reader: &mut R) -> io::Result<Option<(Vec<String>, String)>> {
+    // Read headers and find how the body is framed
+    let mut limited = reader.take(DEFAULT_MAX_HEADER_SIZE as u64);
+    let headers = match read_request_head(&mut limited) {
+        Ok(_) if limited.limit() == 0 => {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "Request head too large",
+            ))
+        }
+        Ok((headers, _)) if headers.is_empty() => return Ok(None),
+        Ok((headers, _)) => headers,
+        Err(err) if is_disconnect(&err) => {
+            log::debug!("Connection closed before a request: {}", err);
+            return Ok(None);
+        }
+        Err(err) => return Err(err),
+    };
+    let framing = framing(&headers)
+        .map_err(|_| io::Error::new(io::ErrorKind::InvalidData, "Invalid body framing"))?;
+
+    // Read body
+    let body = match read_body(reader, framing, DEFAULT_MAX_BODY_SIZE, true) {
+        Ok(body) => body,
+        Err(BodyError::Io(err)) => return Err(err),
+        Err(BodyError::TooLarge) => {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "Request body too large",
+            ))
+        }
+    };
+    let body = String::from_utf8(body).map_err(|_| {
+        io::Error::new(
+            io::ErrorKind::InvalidData,
+            "Request body is not valid UTF-8",
+        )
+    })?;
+    Ok(Some((headers, body)))
+}
+
+/// Returns whether an error means the client went away, by resetting or aborting the
+/// connection, rather than that something went wrong.
+pub(crate) fn is_disconnect(err: &io::Error) -> bool {
+    matches!(
+        err.kind(),
+        io::ErrorKind::ConnectionReset | io::ErrorKind::ConnectionAborted
+    )
+}
+
+/// Returns whether accepting a connection failed because the listener itself is
+/// unusable, so that accepting again would fail the same way.
+pub(crate) fn is_listener_broken(err: &io::Error) -> bool {
+    #[cfg(target_os = "linux")]
+    if matches!(
+        err.raw_os_error(),
+        Some(libc::EBADF | libc::ENOTSOCK | libc::EOPNOTSUPP)
+    ) {
+        return true;
+    }
+    err.kind() == io::ErrorKind::InvalidInput
+}
+
+/// How the body of a request is delimited.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) enum Framing {
+    /// The body is sent with `Transfer-Encoding: chunked`.
+    Chunked,
+    /// The body is as long as its `Content-Length` says.
+    Length(usize),
+    /// The request has neither header, so its body, if any, runs until the client closes
+    /// the connection.
+    Unframed,
+}
 
-    // Read headers and find Content-Length
-    for line in buf_reader.by_ref().lines() {
-        let line = line.unwrap();
+/// Determines how the body of a request is delimited from its raw header lines,
+/// following the message body length rules of RFC 9112.
+///
+/// `Transfer-Encoding` takes precedence over `Content-Length`, and is only accepted when
+/// `chunked` is its final coding. Ambiguous framing a front proxy could read differently
+/// is refused rather than guessed at.
+///
+/// # Errors
+///
+/// Fails with the status to answer, and close the connection with:
+///
+/// * `400` - When `chunked` is not the final transfer coding or appears twice, when
+///   `Content-Length` values differ or are not numbers, or when a framing header is
+///   continued with obs-fold or has whitespace before its colon.
+/// * `501` - When a transfer coding other than `chunked` is applied.
+pub(crate) fn framing(headers: &[String]) -> Result<Framing, u16> {
+    let mut transfer_encoding = None::<Vec<String>>;
+    let mut lengths = Vec::new();
+    let mut continues_framing = false;
+    for line in headers.iter().skip(1) {
+        if line.starts_with([' ', '\t']) {
+            if continues_framing {
+                return Err(400);
+            }
+            continue;
+        }
+        let Some((name, value)) = line.split_once(':') else {
+            continues_framing = false;
+            continue;
+        };
+        continues_framing = true;
+        if name.trim().eq_ignore_ascii_case("Transfer-Encoding") {
+            let codings = transfer_encoding.get_or_insert_with(Vec::new);
+            codings.extend(
+                value
+                    .split(',')
+                    .map(str::trim)
+                    .filter(|coding| !coding.is_empty())
+                    .map(str::to_ascii_lowercase),
+            );
+        } else if name.trim().eq_ignore_ascii_case("Content-Length") {
+            lengths.extend(value.split(',').map(str::trim));
+        } else {
+            continues_framing = false;
+        }
+        if continues_framing && name != name.trim() {
+            return Err(400);
+        }
+    }
+
+    if let Some(codings) = transfer_encoding {
+        return match codings.iter().position(|coding| coding == "chunked") {
+            Some(0) if codings.len() == 1 => Ok(Framing::Chunked),
+            Some(index) if index == codings.len() - 1 => Err(501),
+            _ => Err(400),
+        };
+    }
+    let mut length = None;
+    for value in lengths {
+        if value.is_empty() || !value.bytes().all(|byte| byte.is_ascii_digit()) {
+            return Err(400);
+        }
+        let value = value.parse::<usize>().map_err(|_| 400u16)?;
+        if length.is_some_and(|length| length != value) {
+            return Err(400);
+        }
+        length = Some(value);
+    }
+    Ok(length.map_or(Framing::Unframed, Framing::Length))
+}
+
+/// Why a request body could not be read.
+#[derive(Debug)]
+pub(crate) enum BodyError {
+    /// The body is longer than the limit.
+    TooLarge,
+    /// The body is malformed or the connection failed.
+    Io(io::Error),
+}
+
+impl From<io::Error> for BodyError {
+    fn from(err: io::Error) -> Self {
+        BodyError::Io(err)
+    }
+}
+
+/// Reads a request body framed by `Content-Length` or chunked coding, of at most `limit`
+/// bytes. An unframed body is read as empty.
+///
+/// Lines of the chunked framing may end in a bare LF only if `bare_lf` is set.
+pub(crate) fn read_body<R: BufRead>(
+    reader: &mut R,
+    framing: Framing,
+    limit: usize,
+    bare_lf: bool,
+) -> Result<Vec<u8>, BodyError> {
+    // The length is only a claim, so memory is reserved as the body arrives.
+    let mut body = match framing {
+        Framing::Length(length) if length <= limit => {
+            Vec::with_capacity(length.min(MAX_PREALLOCATED_BODY))
+        }
+        _ => Vec::new(),
+    };
+    copy_body(reader, framing, limit, bare_lf, &mut body)?;
+    Ok(body)
+}
+
+/// Copies a request body framed like [`read_body`] reads it to `sink` as it arrives.
+///
+/// A `Content-Length` body that is too large is refused before anything is copied; a
+/// chunked one once the chunk crossing the limit is announced.
+pub(crate) fn copy_body<R: BufRead, W: Write + ?Sized>(
+    reader: &mut R,
+    framing: Framing,
+    limit: usize,
+    bare_lf: bool,
+    sink: &mut W,
+) -> Result<(), BodyError> {
+    match framing {
+        Framing::Chunked => copy_chunks(reader, limit, bare_lf, sink),
+        Framing::Length(length) if length > limit => Err(BodyError::TooLarge),
+        Framing::Length(length) => {
+            // A client closing the connection early must not pass for a shorter body.
+            if io::copy(&mut reader.take(length as u64), sink)? < length as u64 {
+                return Err(io::Error::from(io::ErrorKind::UnexpectedEof).into());
+            }
+            Ok(())
+        }
+        Framing::Unframed => Ok(()),
+    }
+}
+
+/// Reads the start line and header lines of an HTTP message, up to the empty line that
+/// ends the header block or the end of the stream.
+///
+/// Lines may end in CRLF or a bare LF. Only the line ending is removed, so a stray `\r`
+/// elsewhere is kept for the parser to refuse.
+pub(crate) fn read_head<R: BufRead>(reader: &mut R) -> io::Result<Vec<String>> {
+    read_lines(reader, &mut false)
+}
+
+/// Reads a head like [`read_head`], setting `bare_lf` if a line ends in a bare LF.
+fn read_lines<R: BufRead>(reader: &mut R, bare_lf: &mut bool) -> io::Result<Vec<String>> {
+    let mut headers: Vec<String> = Vec::new();
+    // Lines are read into one buffer, so that each costs a single allocation.
+    let mut buffer = String::new();
+    loop {
+        buffer.clear();
+        if reader.read_line(&mut buffer)? == 0 {
+            return Ok(headers);
+        }
+        let line = buffer.strip_suffix('\n').unwrap_or(&buffer);
+        let line = match line.strip_suffix('\r') {
+            Some(line) => line,
+            None => {
+                *bare_lf |= line.len() < buffer.len();
+                line
+            }
+        };
         if line.is_empty() {
-            break;
+            return Ok(headers);
         }
-        if line.to_lowercase().starts_with("content-length:") {
-            content_length = line
-                .split(':')
-                .nth(1)
-                .and_then(|len| len.trim().parse::<usize>().ok())
-                .unwrap_or(0);
+        headers.push(line.to_string());
+    }
+}
+
+/// Reads the request line and header lines of a request, skipping one empty line before
+/// the request line.
+///
+/// RFC 9112 asks servers to ignore at least one empty line there, as some clients end
+/// a body with an extra CRLF that is not counted in its length.
+///
+/// # Returns
+///
+/// * `io::Result<(Vec<String>, bool)>` - The lines, and whether one of them ended in a
+///   bare LF, for [`Leniency::bare_lf`](crate::app::Leniency::bare_lf).
+pub(crate) fn read_request_head<R: BufRead>(reader: &mut R) -> io::Result<(Vec<String>, bool)> {
+    let mut bare_lf = false;
+    let head = match read_lines(reader, &mut bare_lf)? {
+        head if head.is_empty() => read_lines(reader, &mut bare_lf)?,
+        head => head,
+    };
+    Ok((head, bare_lf))
+}
+
+/// Checks a line of chunked framing, as read with its ending, which may only be a bare
+/// LF if `bare_lf` is set, as [`Leniency::bare_lf`](crate::app::Leniency::bare_lf) allows
+/// for the head.
+fn check_chunk_line(line: &str, bare_lf: bool) -> io::Result<()> {
+    if !bare_lf && line.ends_with('\n') && !line.ends_with("\r\n") {
+        return Err(bare_lf_error());
+    }
+    Ok(())
+}
+
+/// The error refusing a chunked body with a bare LF line ending.
+fn bare_lf_error() -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, "Bare LF in chunked body")
+}
+
+/// Copies the data of a chunked body of at most `limit` bytes to `sink`, for
+/// [`copy_body`], including any trailer section.
+fn copy_chunks<R: BufRead, W: Write + ?Sized>(
+    reader: &mut R,
+    limit: usize,
+    bare_lf: bool,
+    sink: &mut W,
+) -> Result<(), BodyError> {
+    let mut chunks = ChunkDecoder::new(limit as u64, bare_lf);
+    let mut line = String::new();
+    loop {
+        match chunks.step() {
+            ChunkStep::Line => {
+                line.clear();
+                reader.take(MAX_CHUNK_LINE).read_line(&mut line)?;
+                chunks.line(&line)?;
+            }
+            ChunkStep::Data(size) => {
+                if io::copy(&mut reader.take(size), sink)? < size {
+                    return Err(io::Error::from(io::ErrorKind::UnexpectedEof).into());
+                }
+                chunks.data(size);
+            }
+            ChunkStep::Done => return Ok(()),
         }
-        headers.push(line);
     }
+}
 
-    // Read body
-    let mut body = String::with_capacity(content_length);
-    buf_reader
-        .take(content_length as u64)
-        .read_to_string(&mut body)
-        .unwrap_or(0);
-    (headers, body)
+/// The longest line of chunked framing, a chunk-size line with its extensions or a
+/// trailer field, line ending included.
+pub(crate) const MAX_CHUNK_LINE: u64 = 4096;
+
+/// The most bytes of trailer fields accepted after a chunked body.
+pub(crate) const MAX_TRAILER_SIZE: usize = 16 * 1024;
+
+/// What a [`ChunkDecoder`] needs next from the stream.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) enum ChunkStep {
+    /// A line of the framing, read through `take(MAX_CHUNK_LINE)` and passed with its
+    /// ending to [`ChunkDecoder::line`].
+    Line,
+    /// At most this many bytes of chunk data, counted with [`ChunkDecoder::data`].
+    Data(u64),
+    /// The body and its trailer section have been read.
+    Done,
+}
+
+/// Where a [`ChunkDecoder`] is in the framing.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum ChunkState {
+    Size,
+    Data(u64),
+    DataEnd,
+    Trailer,
+    Done,
+}
+
+/// Decodes chunked framing without doing any I/O itself, so that the blocking server,
+/// streamed bodies and the async server share one parser with the same limits.
+///
+/// The caller reads what [`step`](Self::step) asks for and hands it back. A chunk size
+/// is `1*HEXDIG`, optionally followed by extensions after a `;`; extensions and trailer
+/// fields are discarded. Lines are at most [`MAX_CHUNK_LINE`] bytes and the trailer
+/// section at most [`MAX_TRAILER_SIZE`], so only the data counts towards what a body
+/// may cost.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) struct ChunkDecoder {
+    state: ChunkState,
+    limit: u64,
+    /// The bytes of chunk data announced so far.
+    decoded: u64,
+    trailer_size: usize,
+    /// Whether lines may end in a bare LF.
+    bare_lf: bool,
+}
+
+impl ChunkDecoder {
+    /// Starts decoding a body of at most `limit` bytes of data.
+    pub(crate) fn new(limit: u64, bare_lf: bool) -> Self {
+        ChunkDecoder {
+            state: ChunkState::Size,
+            limit,
+            decoded: 0,
+            trailer_size: 0,
+            bare_lf,
+        }
+    }
+
+    /// Tells what to read next.
+    pub(crate) fn step(&self) -> ChunkStep {
+        match self.state {
+            ChunkState::Size | ChunkState::DataEnd | ChunkState::Trailer => ChunkStep::Line,
+            ChunkState::Data(remaining) => ChunkStep::Data(remaining),
+            ChunkState::Done => ChunkStep::Done,
+        }
+    }
+
+    /// Takes a line of the framing as read, with its ending; a line without one was cut
+    /// short by the end of the stream or by [`MAX_CHUNK_LINE`].
+    ///
+    /// # Errors
+    ///
+    /// Fails with [`BodyError::TooLarge`] when a chunk crosses the limit, and with
+    /// `InvalidData` or `UnexpectedEof` when the framing is malformed or cut short.
+    pub(crate) fn line(&mut self, line: &str) -> Result<(), BodyError> {
+        let Some(content) = line.strip_suffix('\n') else {
+            if line.len() as u64 >= MAX_CHUNK_LINE {
+                return Err(invalid_chunk("Chunked framing line too long"));
+            }
+            return Err(io::Error::from(io::ErrorKind::UnexpectedEof).into());
+        };
+        check_chunk_line(line, self.bare_lf)?;
+        let content = content.strip_suffix('\r').unwrap_or(content);
+        match self.state {
+            ChunkState::Size => {
+                let size =
+                    chunk_size(content).ok_or_else(|| invalid_chunk("Invalid chunk size"))?;
+                if size == 0 {
+                    self.state = ChunkState::Trailer;
+                } else if size > self.limit - self.decoded {
+                    return Err(BodyError::TooLarge);
+                } else {
+                    self.decoded += size;
+                    self.state = ChunkState::Data(size);
+                }
+            }
+            ChunkState::DataEnd if content.is_empty() => self.state = ChunkState::Size,
+            ChunkState::DataEnd => return Err(invalid_chunk("Missing CRLF after chunk")),
+            ChunkState::Trailer if content.is_empty() => self.state = ChunkState::Done,
+            ChunkState::Trailer => {
+                self.trailer_size += line.len();
+                if self.trailer_size > MAX_TRAILER_SIZE {
+                    return Err(invalid_chunk("Trailer section too large"));
+                }
+            }
+            ChunkState::Data(_) | ChunkState::Done => unreachable!("no line was asked for"),
+        }
+        Ok(())
+    }
+
+    /// Counts `read` bytes of chunk data, at most as many as [`step`](Self::step) asked
+    /// for.
+    pub(crate) fn data(&mut self, read: u64) {
+        if let ChunkState::Data(remaining) = self.state {
+            self.state = match remaining - read {
+                0 => ChunkState::DataEnd,
+                remaining => ChunkState::Data(remaining),
+            };
+        }
+    }
+}
+
+/// Parses a chunk-size line without its ending: `1*HEXDIG`, then any extensions.
+fn chunk_size(line: &str) -> Option<u64> {
+    let size = line.split_once(';').map_or(line, |(size, _)| size);
+    if size.is_empty() || !size.bytes().all(|byte| byte.is_ascii_hexdigit()) {
+        return None;
+    }
+    u64::from_str_radix(size, 16).ok()
+}
+
+/// The error refusing malformed chunked framing.
+fn invalid_chunk(message: &'static str) -> BodyError {
+    io::Error::new(io::ErrorKind::InvalidData, message).into()
+}
+
+#[cfg(test)]
+mod test_connection {
+    use super::*;
+    use std::io::Cursor;
+
+    fn lines(head: &[&str]) -> Vec<String> {
+        head.iter().map(|line| line.to_string()).collect()
+    }
+
+    /// Tests that a canned request is read from memory, with either body framing.
+    #[test]
+    fn test_handle_connection() {
+        let mut request = Cursor::new(
+            b"POST /echo HTTP/1.1\r\nHost: localhost\r\nContent-Length: 5\r\n\r\nhello".to_vec(),
+        );
+        let (headers, body) = handle_connection(&mut request).unwrap().unwrap();
+        assert_eq!(
+            headers,
+            [
+                "POST /echo HTTP/1.1",
+                "Host: localhost",
+                "Content-Length: 5"
+            ]
+        );
+        assert_eq!(body, "hello");
+
+        let mut request = Cursor::new(
+            b"PUT / HTTP/1.1\r\nTransfer-Encoding: chunked\r\n\r\n3\r\nabc\r\n0\r\n\r\n".to_vec(),
+        );
+        let (headers, body) = handle_connection(&mut request).unwrap().unwrap();
+        assert_eq!(headers.len(), 2);
+        assert_eq!(body, "abc");
+
+        let mut request = Cursor::new(b"GET / HTTP/1.1\r\n\r\n".to_vec());
+        assert_eq!(handle_connection(&mut request).unwrap().unwrap().1, "");
+    }
+
+    /// A reader failing with the given error once its data runs out.
+    struct Failing(&'static [u8], io::ErrorKind);
+
+    impl Read for Failing {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            if self.0.is_empty() {
+                return Err(self.1.into());
+            }
+            self.0.read(buf)
+        }
+    }
+
+    /// Tests that a connection ending or reset before a request reads as no request,
+    /// while requests cut short or malformed are errors.
+    #[test]
+    fn test_no_request() {
+        assert!(handle_connection(&mut &b""[..]).unwrap().is_none());
+        assert!(handle_connection(&mut &b"\r\n"[..]).unwrap().is_none());
+        let mut reset = Failing(b"", io::ErrorKind::ConnectionReset);
+        assert!(handle_connection(&mut reset).unwrap().is_none());
+        let mut aborted = Failing(b"GET / HT", io::ErrorKind::ConnectionAborted);
+        assert!(handle_connection(&mut aborted).unwrap().is_none());
+
+        let mut failed = Failing(b"", io::ErrorKind::TimedOut);
+        let err = handle_connection(&mut failed).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::TimedOut);
+        let cut = b"POST / HTTP/1.1\r\nTransfer-Encoding: chunked\r\n\r\n5\r\nhi";
+        assert!(handle_connection(&mut &cut[..]).is_err());
+        let huge = format!(
+            "GET / HTTP/1.1\r\nX: {}\r\n\r\n",
+            "a".repeat(DEFAULT_MAX_HEADER_SIZE)
+        );
+        let err = handle_connection(&mut huge.as_bytes()).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        let ambiguous = b"POST / HTTP/1.1\r\nContent-Length: 1\r\nContent-Length: 2\r\n\r\n";
+        assert!(handle_connection(&mut &ambiguous[..]).is_err());
+
+        let truncated = b"POST / HTTP/1.1\r\nContent-Length: 10\r\n\r\nhello";
+        let err = handle_connection(&mut &truncated[..]).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::UnexpectedEof);
+        let binary = b"POST / HTTP/1.1\r\nContent-Length: 2\r\n\r\n\xff\xfe";
+        let err = handle_connection(&mut &binary[..]).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+    }
+
+    /// Tests that chunked coding wins over `Content-Length` and that neither means unframed.
+    #[test]
+    fn test_framing() {
+        let chunked = lines(&[
+            "POST / HTTP/1.1",
+            "Content-Length: 4",
+            "transfer-encoding: Chunked",
+        ]);
+        assert_eq!(framing(&chunked), Ok(Framing::Chunked));
+        let length = lines(&["POST / HTTP/1.1", "Content-Length: 4"]);
+        assert_eq!(framing(&length), Ok(Framing::Length(4)));
+        let repeated = lines(&[
+            "POST / HTTP/1.1",
+            "Content-Length: 4, 4",
+            "Content-Length: 4",
+        ]);
+        assert_eq!(framing(&repeated), Ok(Framing::Length(4)));
+        let unframed = lines(&["POST / HTTP/1.1", "Host: localhost", " folded"]);
+        assert_eq!(framing(&unframed), Ok(Framing::Unframed));
+    }
+
+    /// Tests that ambiguous framing is refused.
+    #[test]
+    fn test_framing_rejected() {
+        for (head, status) in [
+            (&["Transfer-Encoding: chunked, identity"][..], 400),
+            (
+                &["Transfer-Encoding: chunked", "Transfer-Encoding: identity"],
+                400,
+            ),
+            (
+                &["Transfer-Encoding: chunked", "Transfer-Encoding: chunked"],
+                400,
+            ),
+            (&["Transfer-Encoding: "], 400),
+            (&["Transfer-Encoding: gzip, chunked"], 501),
+            (&["Content-Length: 4", "Content-Length: 5"], 400),
+            (&["Content-Length: 4, 5"], 400),
+            (&["Content-Length: +4"], 400),
+            (&["Content-Length: "], 400),
+            (&["Content-Length : 4"], 400),
+            (&["Transfer-Encoding: identity", " chunked"], 400),
+        ] {
+            let head = [&["POST / HTTP/1.1"][..], head].concat();
+            assert_eq!(framing(&lines(&head)), Err(status), "{:?}", head);
+        }
+    }
+
+    /// Tests decoding a chunked body with an extension and a trailer.
+    #[test]
+    fn test_read_chunked_body() {
+        let mut reader = Cursor::new(
+            b"4\r\nWiki\r\n6;name=value\r\npedia \r\nE\r\nin \r\n\r\nchunks.\r\n0\r\nExpires: never\r\n\r\nnext"
+                .to_vec(),
+        );
+        let body = read_body(&mut reader, Framing::Chunked, usize::MAX, true).unwrap();
+        assert_eq!(body, b"Wikipedia in \r\n\r\nchunks.");
+
+        let mut rest = String::new();
+        reader.read_to_string(&mut rest).unwrap();
+        assert_eq!(rest, "next");
+    }
+
+    /// Tests that truncated and malformed chunked bodies are rejected.
+    #[test]
+    fn test_read_chunked_body_invalid() {
+        for input in [
+            &b"4\r\nWi"[..],
+            b"zz\r\nWiki\r\n0\r\n\r\n",
+            b"4\r\nWikiX\r\n0\r\n\r\n",
+            b"+4\r\nWiki\r\n0\r\n\r\n",
+            b" 4 \r\nWiki\r\n0\r\n\r\n",
+            b"4 ;name\r\nWiki\r\n0\r\n\r\n",
+            b";name\r\nWiki\r\n0\r\n\r\n",
+            b"4\r\nWiki\r\r\n0\r\n\r\n",
+            b"4\r\nWiki\r\n0\r\nExpires: never\r\n",
+        ] {
+            let mut reader = Cursor::new(input.to_vec());
+            assert!(read_body(&mut reader, Framing::Chunked, usize::MAX, true).is_err());
+        }
+    }
+
+    /// Tests that the lines of chunked framing and the trailer section are bounded, so
+    /// that neither is buffered past its limit.
+    #[test]
+    fn test_chunked_framing_limits() {
+        let padded = format!(
+            "{}5\r\nhello\r\n0\r\n\r\n",
+            "0".repeat(MAX_CHUNK_LINE as usize)
+        );
+        let long_line = read_body(&mut Cursor::new(padded), Framing::Chunked, usize::MAX, true);
+        assert!(
+            matches!(long_line, Err(BodyError::Io(err)) if err.kind() == io::ErrorKind::InvalidData)
+        );
+        let padded = format!("{}5\r\nhello\r\n0\r\n\r\n", "0".repeat(100));
+        let body = read_body(&mut Cursor::new(padded), Framing::Chunked, usize::MAX, true);
+        assert_eq!(body.unwrap(), b"hello");
+
+        let field = format!("X-Pad: {}\r\n", "a".repeat(1000));
+        let trailer = format!("5\r\nhello\r\n0\r\n{}\r\n", field.repeat(20));
+        let refused = read_body(
+            &mut Cursor::new(trailer),
+            Framing::Chunked,
+            usize::MAX,
+            true,
+        );
+        assert!(matches!(refused, Err(BodyError::Io(_))));
+        let trailer = format!("5\r\nhello\r\n0\r\n{}\r\n", field.repeat(10));
+        let body = read_body(
+            &mut Cursor::new(trailer),
+            Framing::Chunked,
+            usize::MAX,
+            true,
+        );
+        assert_eq!(body.unwrap(), b"hello");
+
+        let oversized = read_body(
+            &mut Cursor::new(b"b\r\nhello world\r\n0\r\n\r\n"),
+            Framing::Chunked,
+            10,
+            true,
+        );
+        assert!(matches!(oversized, Err(BodyError::TooLarge)));
+    }
+
+    /// Tests that chunked framing with bare LF line endings is read only when they are
+    /// allowed, in the chunk lines and the trailers alike.
+    #[test]
+    fn test_chunked_bare_lf() {
+        let crlf = b"3\r\nabc\r\n0\r\nX-Sum: 1\r\n\r\n";
+        for allowed in [false, true] {
+            let body = read_body(&mut Cursor::new(crlf), Framing::Chunked, 10, allowed);
+            assert_eq!(body.unwrap(), b"abc");
+        }
+        for bare in [
+            &b"3\nabc\r\n0\r\n\r\n"[..],
+            b"3\r\nabc\n0\r\n\r\n",
+            b"3\r\nabc\r\n0\r\nX-Sum: 1\n\r\n",
+            b"3\nabc\n0\n\n",
+        ] {
+            let body = read_body(&mut Cursor::new(bare), Framing::Chunked, 10, true);
+            assert_eq!(body.unwrap(), b"abc");
+            let refused = read_body(&mut Cursor::new(bare), Framing::Chunked, 10, false);
+            assert!(
+                matches!(refused, Err(BodyError::Io(err)) if err.kind() == io::ErrorKind::InvalidData)
+            );
+        }
+    }
+
+    /// Tests that one empty line before the request line is skipped, that lines keep a
+    /// stray carriage return that is not part of their ending, and that bare LF endings
+    /// are reported.
+    #[test]
+    fn test_read_request_head() {
+        let mut reader = Cursor::new(b"\r\nGET / HTTP/1.1\nHost: a\r\r\n\r\n".to_vec());
+        let (head, bare_lf) = read_request_head(&mut reader).unwrap();
+        assert_eq!(head, ["GET / HTTP/1.1", "Host: a\r"]);
+        assert!(bare_lf);
+
+        let mut reader = Cursor::new(b"\r\nGET / HTTP/1.1\r\nHost: a\r\n\r\n".to_vec());
+        assert_eq!(
+            read_request_head(&mut reader).unwrap(),
+            (
+                vec!["GET / HTTP/1.1".to_string(), "Host: a".to_string()],
+                false
+            )
+        );
+
+        let mut reader = Cursor::new(b"\r\n\r\nGET / HTTP/1.1\r\n\r\n".to_vec());
+        assert!(read_request_head(&mut reader).unwrap().0.is_empty());
+    }
 }
@@ -0,0 +1,360 @@
+use crate::app::Request;
+use crate::http11_response::Response;
+use std::borrow::Cow;
+use std::fmt;
+
+/// A `Content-Type` header value: a media type with its `charset` and `boundary`
+/// parameters.
+///
+/// The media type and charset are case-insensitive and render in lowercase, so
+/// `Text/HTML; Charset=UTF-8` renders as `text/html; charset=utf-8`. The boundary of a
+/// multipart body is case-sensitive and kept as it is, quoted when it holds characters
+/// a token cannot. The same type parses the header of a request, through
+/// [`Request::content_type`], and sets that of a response, by passing it to
+/// [`body_response`](crate::into_response::body_response) or
+/// [`Response::set_content_type`].
+///
+/// # Examples
+///
+/// ```
+/// use rustic::content_type::ContentType;
+/// use rustic::into_response::body_response;
+///
+/// let csv = ContentType::new("Text/CSV").with_charset("UTF-8");
+/// assert_eq!(csv.to_string(), "text/csv; charset=utf-8");
+/// let response = body_response(200, csv, "id,name\n1,Ada\n");
+/// assert_eq!(response.header("Content-Type"), Some("text/csv; charset=utf-8"));
+///
+/// let parsed = ContentType::parse("multipart/form-data; boundary=\"a b\"").unwrap();
+/// assert_eq!(parsed.essence(), "multipart/form-data");
+/// assert_eq!(parsed.boundary(), Some("a b"));
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ContentType {
+    essence: Cow<'static, str>,
+    charset: Option<Cow<'static, str>>,
+    boundary: Option<String>,
+}
+
+impl ContentType {
+    /// `text/plain; charset=utf-8`, for plain text.
+    pub const TEXT_PLAIN_UTF8: ContentType = ContentType::constant("text/plain", "utf-8");
+    /// `text/html; charset=utf-8`, for HTML pages.
+    pub const TEXT_HTML_UTF8: ContentType = ContentType::constant("text/html", "utf-8");
+    /// `application/json`, for JSON, which is always UTF-8 and takes no charset.
+    pub const APPLICATION_JSON: ContentType = ContentType {
+        essence: Cow::Borrowed("application/json"),
+        charset: None,
+        boundary: None,
+    };
+    /// `application/octet-stream`, for bytes of no particular type.
+    pub const OCTET_STREAM: ContentType = ContentType {
+        essence: Cow::Borrowed("application/octet-stream"),
+        charset: None,
+        boundary: None,
+    };
+
+    /// Builds one of the constants, with a media type and charset in lowercase already.
+    const fn constant(essence: &'static str, charset: &'static str) -> Self {
+        ContentType {
+            essence: Cow::Borrowed(essence),
+            charset: Some(Cow::Borrowed(charset)),
+            boundary: None,
+        }
+    }
+
+    /// Creates a content type of the media type `essence`, such as `text/csv`, without
+    /// parameters.
+    pub fn new(essence: &str) -> Self {
+        ContentType {
+            essence: Cow::Owned(essence.trim().to_ascii_lowercase()),
+            charset: None,
+            boundary: None,
+        }
+    }
+
+    /// Sets the `charset` parameter, such as `utf-8`.
+    pub fn with_charset(mut self, charset: &str) -> Self {
+        self.charset = Some(Cow::Owned(charset.trim().to_ascii_lowercase()));
+        self
+    }
+
+    /// Sets the `boundary` parameter separating the parts of a multipart body.
+    pub fn with_boundary(mut self, boundary: &str) -> Self {
+        self.boundary = Some(boundary.to_string());
+        self
+    }
+
+    /// Parses a `Content-Type` header value.
+    ///
+    /// Parameters other than `charset` and `boundary` are dropped.
+    ///
+    /// # Arguments
+    ///
+    /// * `value` - The header value, such as `text/html; charset=UTF-8`.
+    ///
+    /// # Returns
+    ///
+    /// * `Option<ContentType>` - The content type, or `None` if the value does not start
+    ///   with a `type/subtype` media type.
+    pub fn parse(value: &str) -> Option<Self> {
+        let essence = essence(value);
+        let (kind, subtype) = essence.split_once('/')?;
+        if !is_token(kind) || !is_token(subtype) {
+            return None;
+        }
+        let mut content_type = ContentType::new(essence);
+        if let Some(charset) = parameter(value, "charset") {
+            content_type = content_type.with_charset(&charset);
+        }
+        content_type.boundary = parameter(value, "boundary");
+        Some(content_type)
+    }
+
+    /// Returns the media type, such as `text/html`, in lowercase.
+    pub fn essence(&self) -> &str {
+        &self.essence
+    }
+
+    /// Returns the `charset` parameter, in lowercase.
+    pub fn charset(&self) -> Option<&str> {
+        self.charset.as_deref()
+    }
+
+    /// Returns the `boundary` parameter, unquoted.
+    pub fn boundary(&self) -> Option<&str> {
+        self.boundary.as_deref()
+    }
+
+    /// Checks whether the media type is `essence`, ignoring case and parameters.
+    pub fn is(&self, essence: &str) -> bool {
+        self.essence.eq_ignore_ascii_case(essence)
+    }
+}
+
+impl fmt::Display for ContentType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.essence)?;
+        if let Some(charset) = &self.charset {
+            write!(f, "; charset={}", charset)?;
+        }
+        if let Some(boundary) = &self.boundary {
+            f.write_str("; boundary=")?;
+            if is_token(boundary) {
+                f.write_str(boundary)?;
+            } else {
+                f.write_str("\"")?;
+                for character in boundary.chars() {
+                    if matches!(character, '"' | '\\') {
+                        f.write_str("\\")?;
+                    }
+                    write!(f, "{}", character)?;
+                }
+                f.write_str("\"")?;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl From<ContentType> for String {
+    fn from(content_type: ContentType) -> Self {
+        String::from(&content_type)
+    }
+}
+
+impl From<&ContentType> for String {
+    /// Renders the header value into a string allocated once, as responses are built
+    /// with it.
+    fn from(content_type: &ContentType) -> Self {
+        let mut length = content_type.essence.len();
+        if let Some(charset) = &content_type.charset {
+            length += "; charset=".len() + charset.len();
+        }
+        if let Some(boundary) = &content_type.boundary {
+            // Room for quoting every character.
+            length += "; boundary=\"\"".len() + 2 * boundary.len();
+        }
+        let mut rendered = String::with_capacity(length);
+        fmt::Write::write_fmt(&mut rendered, format_args!("{}", content_type))
+            .expect("Writing to a String cannot fail");
+        rendered
+    }
+}
+
+impl Request {
+    /// Parses the `Content-Type` header of the request.
+    ///
+    /// # Returns
+    ///
+    /// * `Option<ContentType>` - The content type, or `None` if the header is missing
+    ///   or malformed.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use rustic::app::App;
+    /// use rustic::test::TestClient;
+    ///
+    /// let mut application = App::new();
+    /// application.post("notes", |request| {
+    ///     match request.content_type() {
+    ///         Some(content_type) if content_type.charset().unwrap_or("utf-8") == "utf-8" => {
+    ///             (201, "Saved")
+    ///         }
+    ///         _ => (415, "Send UTF-8 text"),
+    ///     }
+    /// });
+    /// let client = TestClient::new(application);
+    /// let response = client
+    ///     .post("/notes")
+    ///     .header("Content-Type", "text/plain; charset=ISO-8859-1")
+    ///     .send();
+    /// assert_eq!(response.status, 415);
+    /// ```
+    pub fn content_type(&self) -> Option<ContentType> {
+        self.header("Content-Type").and_then(ContentType::parse)
+    }
+}
+
+impl Response {
+    /// Sets the `Content-Type` header, replacing any previous value.
+    ///
+    /// # Arguments
+    ///
+    /// * `content_type` - The media type of the body.
+    pub fn set_content_type(&mut self, content_type: &ContentType) {
+        self.headers
+            .insert("Content-Type", String::from(content_type));
+    }
+}
+
+/// Returns the media type of a `Content-Type` header value, without its parameters and
+/// in the case it was sent in.
+pub(crate) fn essence(value: &str) -> &str {
+    value.split(';').next().unwrap_or("").trim()
+}
+
+/// Returns the value of the parameter `name` of a header value such as
+/// `form-data; name="field"`, unquoted.
+pub(crate) fn parameter(value: &str, name: &str) -> Option<String> {
+    let mut rest = value.split_once(';')?.1;
+    loop {
+        let (key, after) = rest.split_once('=')?;
+        let key = key.trim();
+        let after = after.trim_start();
+        let (parsed, remainder) = match after.strip_prefix('"') {
+            Some(quoted) => {
+                let mut unquoted = String::new();
+                let mut characters = quoted.char_indices();
+                let mut end = None;
+                while let Some((index, character)) = characters.next() {
+                    match character {
+                        '\\' => unquoted.extend(characters.next().map(|(_, escaped)| escaped)),
+                        '"' => {
+                            end = Some(index + 1);
+                            break;
+                        }
+                        _ => unquoted.push(character),
+                    }
+                }
+                let remainder = &quoted[end?..];
+                (
+                    unquoted,
+                    remainder.split_once(';').map_or("", |(_, next)| next),
+                )
+            }
+            None => {
+                let (token, remainder) = after.split_once(';').unwrap_or((after, ""));
+                (token.trim().to_string(), remainder)
+            }
+        };
+        if key.eq_ignore_ascii_case(name) {
+            return Some(parsed);
+        }
+        if remainder.is_empty() {
+            return None;
+        }
+        rest = remainder;
+    }
+}
+
+/// Checks whether `value` is an RFC 9110 token, which needs no quoting.
+fn is_token(value: &str) -> bool {
+    !value.is_empty()
+        && value
+            .bytes()
+            .all(|byte| byte.is_ascii_alphanumeric() || b"!#$%&'*+-.^_`|~".contains(&byte))
+}
+
+#[cfg(test)]
+mod test_content_type {
+    use super::*;
+
+    /// Tests that content types render in lowercase, quoting boundaries that are not
+    /// tokens.
+    #[test]
+    fn test_render() {
+        assert_eq!(
+            ContentType::TEXT_PLAIN_UTF8.to_string(),
+            "text/plain; charset=utf-8"
+        );
+        assert_eq!(
+            ContentType::TEXT_HTML_UTF8.to_string(),
+            "text/html; charset=utf-8"
+        );
+        assert_eq!(
+            ContentType::APPLICATION_JSON.to_string(),
+            "application/json"
+        );
+        assert_eq!(
+            String::from(ContentType::OCTET_STREAM),
+            "application/octet-stream"
+        );
+        assert_eq!(
+            ContentType::new(" Text/CSV ")
+                .with_charset("UTF-8")
+                .to_string(),
+            "text/csv; charset=utf-8"
+        );
+        assert_eq!(
+            String::from(ContentType::new("multipart/form-data").with_boundary("XyZ-12")),
+            "multipart/form-data; boundary=XyZ-12"
+        );
+        assert_eq!(
+            String::from(ContentType::new("multipart/mixed").with_boundary("a \"b\" c")),
+            "multipart/mixed; boundary=\"a \\\"b\\\" c\""
+        );
+    }
+
+    /// Tests that header values parse into the content type they render back to, and
+    /// that values without a media type do not parse.
+    #[test]
+    fn test_parse() {
+        let parsed = ContentType::parse("Text/HTML; Charset=\"UTF-8\"; level=1").unwrap();
+        assert_eq!(parsed, ContentType::TEXT_HTML_UTF8);
+        assert!(parsed.is("text/html"));
+        assert_eq!(parsed.charset(), Some("utf-8"));
+        assert_eq!(parsed.boundary(), None);
+
+        let parsed = ContentType::parse("multipart/form-data; boundary=\"a;b c\"").unwrap();
+        assert_eq!(parsed.essence(), "multipart/form-data");
+        assert_eq!(parsed.boundary(), Some("a;b c"));
+        assert_eq!(ContentType::parse(&parsed.to_string()), Some(parsed));
+        for content_type in [
+            ContentType::TEXT_PLAIN_UTF8,
+            ContentType::APPLICATION_JSON,
+            ContentType::new("multipart/mixed").with_boundary("x\\\"y"),
+        ] {
+            assert_eq!(
+                ContentType::parse(&content_type.to_string()),
+                Some(content_type)
+            );
+        }
+
+        assert_eq!(ContentType::parse(""), None);
+        assert_eq!(ContentType::parse("text"), None);
+        assert_eq!(ContentType::parse("text/"), None);
+        assert_eq!(ContentType::parse("text html/plain"), None);
+    }
+}
@@ -0,0 +1,393 @@
+use crate::app::Request;
+use crate::crypto::{base64_url_decode, base64_url_encode, constant_time_eq, hmac_sha256};
+use crate::http11_response::Response;
+use std::collections::HashMap;
+use std::fmt;
+use std::time::Duration;
+
+/// The `SameSite` attribute of a cookie.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SameSite {
+    Strict,
+    Lax,
+    None,
+}
+
+/// A cookie to be sent to the client in a `Set-Cookie` header.
+///
+/// # Examples
+///
+/// ```
+/// use rustic::cookie::{Cookie, SameSite};
+/// let cookie = Cookie::new("theme", "dark")
+///     .http_only(true)
+///     .same_site(SameSite::Strict);
+/// assert_eq!(
+///     cookie.to_string(),
+///     "theme=dark; Path=/; HttpOnly; SameSite=Strict"
+/// );
+/// ```
+#[derive(Debug, Clone, PartialEq)]
+pub struct Cookie {
+    name: String,
+    value: String,
+    path: Option<String>,
+    max_age: Option<Duration>,
+    http_only: bool,
+    secure: bool,
+    same_site: Option<SameSite>,
+}
+
+impl Cookie {
+    /// Creates a cookie scoped to the whole site (`Path=/`) with no other attributes.
+    pub fn new(name: &str, value: &str) -> Self {
+        Cookie {
+            name: name.to_string(),
+            value: value.to_string(),
+            path: Some("/".to_string()),
+            max_age: None,
+            http_only: false,
+            secure: false,
+            same_site: None,
+        }
+    }
+
+    /// Sets or clears the `Path` attribute.
+    pub fn path<'a>(mut self, path: impl Into<Option<&'a str>>) -> Self {
+        self.path = path.into().map(str::to_string);
+        self
+    }
+
+    /// Sets the `Max-Age` attribute, turning the cookie into a persistent one.
+    pub fn max_age(mut self, max_age: Duration) -> Self {
+        self.max_age = Some(max_age);
+        self
+    }
+
+    /// Sets whether the cookie is hidden from client-side scripts.
+    pub fn http_only(mut self, http_only: bool) -> Self {
+        self.http_only = http_only;
+        self
+    }
+
+    /// Sets whether the cookie is only sent over HTTPS.
+    pub fn secure(mut self, secure: bool) -> Self {
+        self.secure = secure;
+        self
+    }
+
+    /// Sets the `SameSite` attribute.
+    pub fn same_site(mut self, same_site: SameSite) -> Self {
+        self.same_site = Some(same_site);
+        self
+    }
+
+    /// Returns the cookie name.
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Returns the cookie value.
+    pub fn value(&self) -> &str {
+        &self.value
+    }
+}
+
+impl fmt::Display for Cookie {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}={}", self.name, self.value)?;
+        if let Some(path) = &self.path {
+            write!(f, "; Path={}", path)?;
+        }
+        if let Some(max_age) = self.max_age {
+            write!(f, "; Max-Age={}", max_age.as_secs())?;
+        }
+        if self.http_only {
+            f.write_str("; HttpOnly")?;
+        }
+        if self.secure {
+            f.write_str("; Secure")?;
+        }
+        match self.same_site {
+            Some(SameSite::Strict) => f.write_str("; SameSite=Strict"),
+            Some(SameSite::Lax) => f.write_str("; SameSite=Lax"),
+            Some(SameSite::None) => f.write_str("; SameSite=None"),
+            None => Ok(()),
+        }
+    }
+}
+
+/// Parses the value of a `Cookie` request header into name/value pairs.
+///
+/// Pairs are separated by `;`, surrounding whitespace is ignored, and values wrapped in
+/// double quotes are unquoted. Pairs without a `=` are skipped.
+///
+/// # Arguments
+///
+/// * `header` - The raw `Cookie` header value.
+///
+/// # Returns
+///
+/// * `HashMap<String, String>` - The cookies sent by the client.
+///
+/// # Examples
+///
+/// ```
+/// use rustic::cookie::parse_cookies;
+/// let cookies = parse_cookies("session=abc123; theme=\"dark\"");
+/// assert_eq!(cookies.get("session"), Some(&"abc123".to_string()));
+/// assert_eq!(cookies.get("theme"), Some(&"dark".to_string()));
+/// ```
+pub fn parse_cookies(header: &str) -> HashMap<String, String> {
+    header
+        .split(';')
+        .filter_map(|pair| pair.split_once('='))
+        .map(|(name, value)| {
+            let value = value.trim();
+            let value = value
+                .strip_prefix('"')
+                .and_then(|value| value.strip_suffix('"'))
+                .unwrap_or(value);
+            (name.trim().to_string(), value.to_string())
+        })
+        .collect()
+}
+
+/// The secret keys used to sign and verify cookies.
+///
+/// New signatures always use the current key. Verification also accepts the previous
+/// keys, which allows rotating the secret without logging out every client at once.
+///
+/// # Examples
+///
+/// ```
+/// use rustic::cookie::SigningKey;
+/// let key = SigningKey::new(b"new secret").with_previous(b"old secret");
+/// ```
+#[derive(Clone)]
+pub struct SigningKey {
+    keys: Vec<Vec<u8>>,
+}
+
+impl SigningKey {
+    /// Creates a key set that signs and verifies with `current`.
+    pub fn new(current: &[u8]) -> Self {
+        SigningKey {
+            keys: vec![current.to_vec()],
+        }
+    }
+
+    /// Adds a retired key that is still accepted for verification.
+    pub fn with_previous(mut self, previous: &[u8]) -> Self {
+        self.keys.push(previous.to_vec());
+        self
+    }
+}
+
+/// Signs a cookie value.
+///
+/// The signed value has the format `<value>.<signature>`, where the signature is the
+/// HMAC-SHA256 of `<name>=<value>` under the current key, encoded as unpadded URL-safe
+/// base64. Covering the name prevents a signed value from being replayed under another
+/// cookie name. The value itself is not encrypted and must only contain characters
+/// allowed in a cookie value.
+///
+/// # Arguments
+///
+/// * `name` - The name of the cookie.
+/// * `value` - The value to sign.
+/// * `key` - The signing keys.
+///
+/// # Returns
+///
+/// * `String` - The value with its signature appended.
+///
+/// # Examples
+///
+/// ```
+/// use rustic::cookie::{sign_cookie_value, verify_cookie_value, SigningKey};
+/// let key = SigningKey::new(b"secret");
+/// let signed = sign_cookie_value("user", "42", &key);
+/// assert!(signed.starts_with("42."));
+/// assert_eq!(verify_cookie_value("user", &signed, &key), Some("42".to_string()));
+/// ```
+pub fn sign_cookie_value(name: &str, value: &str, key: &SigningKey) -> String {
+    let signature = hmac_sha256(&key.keys[0], format!("{}={}", name, value).as_bytes());
+    format!("{}.{}", value, base64_url_encode(&signature))
+}
+
+/// Verifies a value produced by [`sign_cookie_value`] and strips its signature.
+///
+/// The signature is checked in constant time against the current and every previous key.
+///
+/// # Arguments
+///
+/// * `name` - The name of the cookie.
+/// * `signed` - The signed value as received from the client.
+/// * `key` - The signing keys.
+///
+/// # Returns
+///
+/// * `Option<String>` - The original value, or `None` if the signature is missing,
+///   truncated, malformed or does not match.
+pub fn verify_cookie_value(name: &str, signed: &str, key: &SigningKey) -> Option<String> {
+    let (value, signature) = signed.rsplit_once('.')?;
+    let signature = base64_url_decode(signature)?;
+    let message = format!("{}={}", name, value);
+    key.keys
+        .iter()
+        .any(|key| constant_time_eq(&hmac_sha256(key, message.as_bytes()), &signature))
+        .then(|| value.to_string())
+}
+
+impl Request {
+    /// Returns the value of a cookie sent with the request.
+    ///
+    /// # Arguments
+    ///
+    /// * `name` - The name of the cookie.
+    pub fn cookie(&self, name: &str) -> Option<String> {
+        self.header("Cookie")
+            .and_then(|header| parse_cookies(header).remove(name))
+    }
+
+    /// Returns the value of a signed cookie, if its signature is valid.
+    ///
+    /// # Arguments
+    ///
+    /// * `name` - The name of the cookie.
+    /// * `key` - The keys the cookie may have been signed with.
+    pub fn signed_cookie(&self, name: &str, key: &SigningKey) -> Option<String> {
+        self.cookie(name)
+            .and_then(|signed| verify_cookie_value(name, &signed, key))
+    }
+}
+
+impl Response {
+    /// Sets a cookie on the response by adding a `Set-Cookie` header.
+    ///
+    /// Each call adds its own header, so several cookies can be set on one response. A
+    /// cookie already set under the same name is replaced.
+    ///
+    /// # Arguments
+    ///
+    /// * `cookie` - The cookie to send.
+    pub fn set_cookie(&mut self, cookie: &Cookie) {
+        let prefix = format!("{}=", cookie.name);
+        self.headers.retain(|key, value| {
+            !(key.eq_ignore_ascii_case("Set-Cookie") && value.starts_with(&prefix))
+        });
+        self.headers.append("Set-Cookie", cookie.to_string());
+    }
+
+    /// Sets an `HttpOnly` cookie whose value is signed with [`sign_cookie_value`].
+    ///
+    /// # Arguments
+    ///
+    /// * `name` - The name of the cookie.
+    /// * `value` - The value to sign and send.
+    /// * `key` - The signing keys.
+    pub fn add_signed_cookie(&mut self, name: &str, value: &str, key: &SigningKey) {
+        let signed = sign_cookie_value(name, value, key);
+        self.set_cookie(&Cookie::new(name, &signed).http_only(true));
+    }
+}
+
+#[cfg(test)]
+mod test_cookie {
+    use super::*;
+
+    /// Tests rendering a cookie with every attribute set.
+    #[test]
+    fn test_cookie_display() {
+        let cookie = Cookie::new("id", "42")
+            .max_age(Duration::from_secs(60))
+            .http_only(true)
+            .secure(true)
+            .same_site(SameSite::Lax);
+        assert_eq!(
+            cookie.to_string(),
+            "id=42; Path=/; Max-Age=60; HttpOnly; Secure; SameSite=Lax"
+        );
+    }
+
+    /// Tests parsing a header with irregular spacing and an invalid pair.
+    #[test]
+    fn test_parse_cookies() {
+        let cookies = parse_cookies(" a=1;b = 2 ; broken; c=");
+        assert_eq!(cookies.len(), 3);
+        assert_eq!(cookies["a"], "1");
+        assert_eq!(cookies["b"], "2");
+        assert_eq!(cookies["c"], "");
+    }
+
+    /// Tests that a value containing the separator survives signing.
+    #[test]
+    fn test_signed_value_with_separator() {
+        let key = SigningKey::new(b"secret");
+        let signed = sign_cookie_value("file", "report.v2.pdf", &key);
+        assert_eq!(
+            verify_cookie_value("file", &signed, &key),
+            Some("report.v2.pdf".to_string())
+        );
+    }
+
+    /// Tests that tampering with the value, signature or name is detected.
+    #[test]
+    fn test_tampered_signature() {
+        let key = SigningKey::new(b"secret");
+        let signed = sign_cookie_value("user", "42", &key);
+        let (value, signature) = signed.rsplit_once('.').unwrap();
+
+        let mut raw = base64_url_decode(signature).unwrap();
+        raw[7] ^= 0x01;
+        let flipped = format!("{}.{}", value, base64_url_encode(&raw));
+        assert_eq!(verify_cookie_value("user", &flipped, &key), None);
+
+        let truncated = &signed[..signed.len() - 2];
+        assert_eq!(verify_cookie_value("user", truncated, &key), None);
+        assert_eq!(
+            verify_cookie_value("user", &signed.replace("42", "43"), &key),
+            None
+        );
+        assert_eq!(verify_cookie_value("admin", &signed, &key), None);
+        assert_eq!(verify_cookie_value("user", "42", &key), None);
+        assert_eq!(verify_cookie_value("user", "42.!!!", &key), None);
+    }
+
+    /// Tests that cookies with different names each get a header while a repeated name
+    /// replaces the earlier cookie.
+    #[test]
+    fn test_set_multiple_cookies() {
+        let mut response = Response {
+            status_code: 200,
+            reason: "OK".into(),
+            response_body: None,
+            headers: Default::default(),
+        };
+        response.set_cookie(&Cookie::new("a", "1"));
+        response.set_cookie(&Cookie::new("b", "2"));
+        response.set_cookie(&Cookie::new("a", "3"));
+        assert_eq!(
+            response.headers.get_all("Set-Cookie").collect::<Vec<_>>(),
+            vec!["b=2; Path=/", "a=3; Path=/"]
+        );
+    }
+
+    /// Tests that values signed with a previous key are still accepted after rotation.
+    #[test]
+    fn test_key_rotation() {
+        let old = SigningKey::new(b"old");
+        let rotated = SigningKey::new(b"new").with_previous(b"old");
+        let signed = sign_cookie_value("user", "42", &old);
+        assert_eq!(
+            verify_cookie_value("user", &signed, &rotated),
+            Some("42".to_string())
+        );
+        assert_ne!(sign_cookie_value("user", "42", &rotated), signed);
+        assert_eq!(
+            verify_cookie_value("user", &signed, &SigningKey::new(b"new")),
+            None
+        );
+    }
+}
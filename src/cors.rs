@@ -0,0 +1,425 @@
+use crate::app::Request;
+use crate::http11_response::Response;
+use crate::into_response::IntoResponse;
+use crate::middleware::{Middleware, Next};
+use crate::parse_headers::RequestType;
+use std::time::Duration;
+
+/// The request headers a preflight varies with, sent in its `Vary` header so that caches
+/// never answer one preflight with the response to another.
+const PREFLIGHT_VARY: &str =
+    "Origin, Access-Control-Request-Method, Access-Control-Request-Headers";
+
+/// Middleware letting pages from other origins call the application from the browser,
+/// following the Fetch standard's CORS protocol.
+///
+/// Requests with an allowed `Origin` get an `Access-Control-Allow-Origin` header on
+/// their response, along with `Access-Control-Allow-Credentials` and
+/// `Access-Control-Expose-Headers` when configured. Preflight requests, which are
+/// `OPTIONS` requests carrying `Access-Control-Request-Method`, are answered by the
+/// middleware itself with `204 No Content` and never reach an endpoint:
+///
+/// * `Access-Control-Allow-Methods` lists the allowed methods.
+/// * `Access-Control-Allow-Headers` echoes the headers the browser asked for in
+///   `Access-Control-Request-Headers`, or lists the allowed ones when set with
+///   [`Cors::allow_headers`].
+/// * `Access-Control-Max-Age` lets the browser keep the answer instead of sending a
+///   preflight before every request, once set with [`Cors::max_age`].
+/// * `Access-Control-Allow-Private-Network: true` answers Chrome's
+///   `Access-Control-Request-Private-Network: true`, sent when a public page calls a
+///   server on the local network, once enabled with [`Cors::allow_private_network`].
+/// * `Vary: Origin, Access-Control-Request-Method, Access-Control-Request-Headers`.
+///
+/// A preflight from an origin that is not allowed, for a method that is not allowed or
+/// asking for a header outside the allow-list gets no CORS headers, which the browser
+/// reads as a refusal. Responses that depend on the origin carry `Vary: Origin`.
+///
+/// By default every origin is allowed, without credentials, for the methods `GET`,
+/// `HEAD`, `POST`, `PUT`, `PATCH` and `DELETE`.
+///
+/// # Examples
+///
+/// ```
+/// use rustic::app::App;
+/// use rustic::cors::Cors;
+/// use std::time::Duration;
+///
+/// let mut application = App::new();
+/// application.add_middleware(
+///     Cors::new()
+///         .allow_origin("https://app.example.com")
+///         .allow_credentials(true)
+///         .max_age(Duration::from_secs(600))
+///         .allow_private_network(true),
+/// );
+/// ```
+#[derive(Debug, Clone)]
+pub struct Cors {
+    origins: Option<Vec<String>>,
+    methods: Vec<String>,
+    headers: Option<Vec<String>>,
+    exposed_headers: Vec<String>,
+    credentials: bool,
+    max_age: Option<Duration>,
+    private_network: bool,
+}
+
+impl Default for Cors {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Cors {
+    /// Creates the middleware allowing every origin, without credentials.
+    pub fn new() -> Self {
+        Cors {
+            origins: None,
+            methods: ["GET", "HEAD", "POST", "PUT", "PATCH", "DELETE"]
+                .map(str::to_string)
+                .to_vec(),
+            headers: None,
+            exposed_headers: Vec::new(),
+            credentials: false,
+            max_age: None,
+            private_network: false,
+        }
+    }
+
+    /// Allows an origin, such as `"https://app.example.com"`, restricting the
+    /// middleware to the origins allowed this way.
+    pub fn allow_origin(mut self, origin: &str) -> Self {
+        self.origins
+            .get_or_insert_with(Vec::new)
+            .push(origin.trim_end_matches('/').to_string());
+        self
+    }
+
+    /// Sets the methods allowed by preflights, replacing the default ones.
+    pub fn allow_methods(mut self, methods: &[RequestType]) -> Self {
+        self.methods = methods
+            .iter()
+            .map(|method| method.as_str().to_string())
+            .collect();
+        self
+    }
+
+    /// Sets the request headers allowed by preflights.
+    ///
+    /// Without this call, every header a preflight asks for is allowed by echoing
+    /// `Access-Control-Request-Headers` back.
+    pub fn allow_headers(mut self, headers: &[&str]) -> Self {
+        self.headers = Some(headers.iter().map(|name| name.to_string()).collect());
+        self
+    }
+
+    /// Sets the response headers scripts may read beyond the CORS-safelisted ones, sent
+    /// in `Access-Control-Expose-Headers`.
+    pub fn expose_headers(mut self, headers: &[&str]) -> Self {
+        self.exposed_headers = headers.iter().map(|name| name.to_string()).collect();
+        self
+    }
+
+    /// Sets whether requests may carry cookies and HTTP authentication.
+    ///
+    /// Browsers refuse `Access-Control-Allow-Origin: *` on such requests, so the
+    /// request's origin is echoed instead when every origin is allowed.
+    pub fn allow_credentials(mut self, allow: bool) -> Self {
+        self.credentials = allow;
+        self
+    }
+
+    /// Sets or disables how long browsers may keep the answer to a preflight, sent in
+    /// `Access-Control-Max-Age` in whole seconds.
+    ///
+    /// Browsers cap it, Chrome at two hours and Firefox at a day. Without it, Chrome
+    /// keeps an answer for five seconds only.
+    pub fn max_age(mut self, max_age: impl Into<Option<Duration>>) -> Self {
+        self.max_age = max_age.into();
+        self
+    }
+
+    /// Sets whether preflights asking with `Access-Control-Request-Private-Network`
+    /// may reach the application from a public page, as local network tools need.
+    pub fn allow_private_network(mut self, allow: bool) -> Self {
+        self.private_network = allow;
+        self
+    }
+
+    /// Returns whether the value of `Access-Control-Allow-Origin` depends on the origin.
+    fn varies_by_origin(&self) -> bool {
+        self.origins.is_some() || self.credentials
+    }
+
+    /// Returns the value of `Access-Control-Allow-Origin` for a request's origin, or
+    /// `None` when the origin is not allowed.
+    fn allowed_origin<'a>(&self, origin: &'a str) -> Option<&'a str> {
+        match &self.origins {
+            None if self.credentials => Some(origin),
+            None => Some("*"),
+            Some(origins) => origins
+                .iter()
+                .any(|allowed| allowed.eq_ignore_ascii_case(origin))
+                .then_some(origin),
+        }
+    }
+
+    /// Returns the value of `Access-Control-Allow-Headers` for the headers a preflight
+    /// asks for, or `None` when one of them is not allowed.
+    fn allowed_headers(&self, requested: Option<&str>) -> Option<String> {
+        let requested = requested.unwrap_or("");
+        let Some(allowed) = &self.headers else {
+            return Some(requested.to_string());
+        };
+        let all_allowed = requested
+            .split(',')
+            .map(str::trim)
+            .filter(|name| !name.is_empty())
+            .all(|name| {
+                allowed
+                    .iter()
+                    .any(|allowed| allowed.eq_ignore_ascii_case(name))
+            });
+        all_allowed.then(|| allowed.join(", "))
+    }
+
+    /// Adds the headers every response to an allowed origin carries.
+    fn add_origin_headers(&self, response: &mut Response, allow_origin: &str) {
+        response
+            .headers
+            .insert("Access-Control-Allow-Origin", allow_origin);
+        if self.credentials {
+            response
+                .headers
+                .insert("Access-Control-Allow-Credentials", "true");
+        }
+    }
+
+    /// Answers a preflight request.
+    fn preflight(&self, request: &Request, origin: &str, method: &str) -> Response {
+        let mut response = ().into_response();
+        response.headers.append("Vary", PREFLIGHT_VARY);
+        let Some(allow_origin) = self.allowed_origin(origin) else {
+            return response;
+        };
+        if !self.methods.iter().any(|allowed| allowed == method.trim()) {
+            return response;
+        }
+        let Some(allow_headers) =
+            self.allowed_headers(request.header("Access-Control-Request-Headers"))
+        else {
+            return response;
+        };
+        self.add_origin_headers(&mut response, allow_origin);
+        response
+            .headers
+            .insert("Access-Control-Allow-Methods", self.methods.join(", "));
+        if !allow_headers.is_empty() {
+            response
+                .headers
+                .insert("Access-Control-Allow-Headers", allow_headers);
+        }
+        if let Some(max_age) = self.max_age {
+            response
+                .headers
+                .insert("Access-Control-Max-Age", max_age.as_secs().to_string());
+        }
+        let private_network = request
+            .header("Access-Control-Request-Private-Network")
+            .is_some_and(|value| value.trim().eq_ignore_ascii_case("true"));
+        if private_network && self.private_network {
+            response
+                .headers
+                .insert("Access-Control-Allow-Private-Network", "true");
+        }
+        response
+    }
+}
+
+impl Middleware for Cors {
+    fn handle(&self, request: Request, next: Next) -> Response {
+        let Some(origin) = request.header("Origin").map(str::to_string) else {
+            let mut response = next.run(request);
+            if self.varies_by_origin() {
+                response.headers.append("Vary", "Origin");
+            }
+            return response;
+        };
+        if request.method == RequestType::OPTIONS {
+            if let Some(method) = request.header("Access-Control-Request-Method") {
+                return self.preflight(&request, &origin, method);
+            }
+        }
+
+        let mut response = next.run(request);
+        if let Some(allow_origin) = self.allowed_origin(&origin) {
+            if !response.headers.contains_key("Access-Control-Allow-Origin") {
+                self.add_origin_headers(&mut response, allow_origin);
+                if !self.exposed_headers.is_empty() {
+                    response.headers.insert(
+                        "Access-Control-Expose-Headers",
+                        self.exposed_headers.join(", "),
+                    );
+                }
+            }
+        }
+        if self.varies_by_origin() {
+            response.headers.append("Vary", "Origin");
+        }
+        response
+    }
+}
+
+#[cfg(test)]
+mod test_cors {
+    use super::*;
+    use crate::app::App;
+
+    fn request(method: RequestType, headers: &[(&str, &str)]) -> Request {
+        Request {
+            method,
+            path: "items".to_string(),
+            url: "/items".to_string(),
+            headers: headers
+                .iter()
+                .map(|(name, value)| (name.to_string(), value.to_string()))
+                .collect(),
+            body: String::new(),
+            ..Request::default()
+        }
+    }
+
+    /// Builds an application with one endpoint behind the middleware.
+    fn app(cors: Cors) -> App {
+        let mut application = App::new();
+        application.add_middleware(cors);
+        application.get("items", |_| "items");
+        application
+    }
+
+    /// Tests that simple requests get the origin headers, with `*` unless the origin is
+    /// restricted or credentials are allowed, and that requests without an origin pass
+    /// through untouched.
+    #[test]
+    fn test_simple_requests() {
+        let origin = ("Origin", "https://app.example.com");
+        let response = app(Cors::new()).dispatch(request(RequestType::GET, &[origin]));
+        assert_eq!(response.header("Access-Control-Allow-Origin"), Some("*"));
+        assert_eq!(response.header("Vary"), None);
+
+        let restricted = Cors::new()
+            .allow_origin("https://app.example.com/")
+            .allow_credentials(true)
+            .expose_headers(&["X-Total-Count"]);
+        let application = app(restricted);
+        let response = application.dispatch(request(RequestType::GET, &[origin]));
+        assert_eq!(
+            response.header("Access-Control-Allow-Origin"),
+            Some("https://app.example.com")
+        );
+        assert_eq!(
+            response.header("Access-Control-Allow-Credentials"),
+            Some("true")
+        );
+        assert_eq!(
+            response.header("Access-Control-Expose-Headers"),
+            Some("X-Total-Count")
+        );
+        assert_eq!(response.header("Vary"), Some("Origin"));
+
+        let foreign = ("Origin", "https://evil.example");
+        let response = application.dispatch(request(RequestType::GET, &[foreign]));
+        assert_eq!(response.status_code, 200);
+        assert_eq!(response.header("Access-Control-Allow-Origin"), None);
+        let response = application.dispatch(request(RequestType::GET, &[]));
+        assert_eq!(response.header("Access-Control-Allow-Origin"), None);
+        assert_eq!(response.header("Vary"), Some("Origin"));
+    }
+
+    /// Tests that preflights echo the requested headers or check them against the
+    /// allow-list, and are refused for other methods and origins.
+    #[test]
+    fn test_preflight() {
+        let asking = |method, headers| {
+            request(
+                RequestType::OPTIONS,
+                &[
+                    ("Origin", "https://app.example.com"),
+                    ("Access-Control-Request-Method", method),
+                    ("Access-Control-Request-Headers", headers),
+                ],
+            )
+        };
+        let response = app(Cors::new()).dispatch(asking("PUT", "content-type,x-token"));
+        assert_eq!(response.status_code, 204);
+        assert_eq!(
+            response.header("Access-Control-Allow-Headers"),
+            Some("content-type,x-token")
+        );
+        assert_eq!(
+            response.header("Access-Control-Allow-Methods"),
+            Some("GET, HEAD, POST, PUT, PATCH, DELETE")
+        );
+        assert_eq!(response.header("Access-Control-Max-Age"), None);
+        assert_eq!(response.header("Vary"), Some(PREFLIGHT_VARY));
+
+        let listed = app(Cors::new()
+            .allow_headers(&["Content-Type", "X-Token"])
+            .allow_methods(&[RequestType::GET, RequestType::PUT]));
+        let response = listed.dispatch(asking("PUT", "x-token, content-type"));
+        assert_eq!(
+            response.header("Access-Control-Allow-Headers"),
+            Some("Content-Type, X-Token")
+        );
+        assert_eq!(
+            response.header("Access-Control-Allow-Methods"),
+            Some("GET, PUT")
+        );
+        for refused in [
+            asking("PUT", "x-token, x-other"),
+            asking("DELETE", "x-token"),
+            asking("put", ""),
+        ] {
+            let response = listed.dispatch(refused);
+            assert_eq!(response.status_code, 204);
+            assert_eq!(response.header("Access-Control-Allow-Origin"), None);
+            assert_eq!(response.header("Access-Control-Allow-Methods"), None);
+            assert_eq!(response.header("Vary"), Some(PREFLIGHT_VARY));
+        }
+
+        let foreign = app(Cors::new().allow_origin("https://other.example"));
+        let response = foreign.dispatch(asking("GET", ""));
+        assert_eq!(response.header("Access-Control-Allow-Origin"), None);
+        assert_eq!(response.header("Access-Control-Allow-Headers"), None);
+    }
+
+    /// Tests that the private network header is only granted when enabled, and that
+    /// the max-age is sent in whole seconds.
+    #[test]
+    fn test_private_network_and_max_age() {
+        let preflight = || {
+            request(
+                RequestType::OPTIONS,
+                &[
+                    ("Origin", "https://public.example"),
+                    ("Access-Control-Request-Method", "GET"),
+                    ("Access-Control-Request-Private-Network", "true"),
+                ],
+            )
+        };
+        let response =
+            app(Cors::new().max_age(Duration::from_millis(90_500))).dispatch(preflight());
+        assert_eq!(response.header("Access-Control-Allow-Origin"), Some("*"));
+        assert_eq!(
+            response.header("Access-Control-Allow-Private-Network"),
+            None
+        );
+        assert_eq!(response.header("Access-Control-Max-Age"), Some("90"));
+
+        let response = app(Cors::new().allow_private_network(true)).dispatch(preflight());
+        assert_eq!(
+            response.header("Access-Control-Allow-Private-Network"),
+            Some("true")
+        );
+    }
+}
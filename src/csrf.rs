@@ -0,0 +1,405 @@
+use crate::app::Request;
+use crate::cookie::{Cookie, SameSite};
+use crate::crypto::{base64_url_decode, base64_url_encode, constant_time_eq};
+use crate::http11_response::Response;
+use crate::http_error::HttpError;
+use crate::middleware::{Middleware, Next};
+use crate::parse_headers::RequestType;
+use crate::parse_url::form_pairs;
+
+/// The number of random bytes in a token.
+const TOKEN_LEN: usize = 32;
+
+/// The body of the `403 Forbidden` response to a request failing the check, telling it
+/// apart from other refusals.
+const REJECTION: &str = "Forbidden: missing or invalid CSRF token";
+
+/// The CSRF token of the current request, stored in its extensions by
+/// [`CsrfMiddleware`].
+#[derive(Debug, Clone, PartialEq)]
+pub struct CsrfToken(String);
+
+impl CsrfToken {
+    /// Returns the token, to be sent back in the `X-CSRF-Token` header or the `_csrf`
+    /// form field.
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+}
+
+impl Request {
+    /// Returns the CSRF token issued by [`CsrfMiddleware`], if it is installed, for
+    /// pages and scripts to submit with their state-changing requests.
+    pub fn csrf_token(&self) -> Option<&str> {
+        self.extensions.get::<CsrfToken>().map(CsrfToken::as_str)
+    }
+}
+
+/// Where [`CsrfMiddleware`] keeps the token it checks requests against.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub enum CsrfStorage {
+    /// In a cookie, which the submitted token must match: the double-submit cookie
+    /// pattern. Works without server-side state.
+    #[default]
+    Cookie,
+    /// In the [session](crate::session::Session), which requires a
+    /// [`SessionMiddleware`](crate::session::SessionMiddleware) added before this one.
+    Session,
+}
+
+/// Middleware defending cookie-authenticated applications against cross-site request
+/// forgery.
+///
+/// Every request gets a random token, issued once per client and kept as
+/// [`CsrfStorage`] says, which handlers read with [`Request::csrf_token`] to embed in
+/// forms or pages. Requests with a method other than `GET`, `HEAD`, `OPTIONS` and
+/// `TRACE`, such as `POST`, `PUT`, `PATCH` and `DELETE`, must send the token back in
+/// the `X-CSRF-Token` header or, for a form body
+/// (`application/x-www-form-urlencoded`), in its `_csrf` field. Those that do not are
+/// refused with `403 Forbidden` and the body `Forbidden: missing or invalid CSRF token`
+/// before reaching their endpoint. Tokens are compared in constant time.
+///
+/// A page from another site can make the browser send the cookies of this one, but can
+/// neither read the token nor set the header, which is what the check relies on. Paths
+/// called by other servers rather than browsers, such as webhook receivers, can be
+/// exempted with [`CsrfMiddleware::exempt`].
+///
+/// With [`CsrfStorage::Cookie`], the cookie is not `HttpOnly`, so that scripts can copy
+/// it into the header.
+///
+/// # Examples
+///
+/// ```
+/// use rustic::app::App;
+/// use rustic::csrf::CsrfMiddleware;
+/// use rustic::http11_response::Response;
+/// use rustic::http_error::HttpError;
+/// use std::collections::HashMap;
+///
+/// let mut application = App::new();
+/// application.add_middleware(CsrfMiddleware::new().exempt("/webhooks"));
+/// application.get("comments/new", |request| {
+///     let vars = HashMap::from([("csrf", request.csrf_token().unwrap_or_default())]);
+///     let form = Response::html_template(
+///         "<form method=\"post\" action=\"/comments\">\
+///          <input type=\"hidden\" name=\"_csrf\" value=\"{{csrf}}\">\
+///          <textarea name=\"text\"></textarea></form>",
+///         &vars,
+///     )?;
+///     Ok::<_, HttpError>(form)
+/// });
+/// application.post("comments", |_| "Posted");
+/// application.post("webhooks/payments", |_| "Received");
+/// ```
+#[derive(Debug, Clone)]
+pub struct CsrfMiddleware {
+    storage: CsrfStorage,
+    cookie_name: String,
+    header: String,
+    form_field: String,
+    exempt: Vec<String>,
+    secure: bool,
+}
+
+impl Default for CsrfMiddleware {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl CsrfMiddleware {
+    /// Creates the middleware keeping the token in a `SameSite=Lax` cookie named
+    /// `csrf_token`, and reading it back from the `X-CSRF-Token` header or the `_csrf`
+    /// form field.
+    pub fn new() -> Self {
+        CsrfMiddleware {
+            storage: CsrfStorage::Cookie,
+            cookie_name: "csrf_token".to_string(),
+            header: "X-CSRF-Token".to_string(),
+            form_field: "_csrf".to_string(),
+            exempt: Vec::new(),
+            secure: false,
+        }
+    }
+
+    /// Sets where the token is kept.
+    pub fn storage(mut self, storage: CsrfStorage) -> Self {
+        self.storage = storage;
+        self
+    }
+
+    /// Sets the name of the cookie holding the token, which is also the session key
+    /// with [`CsrfStorage::Session`].
+    pub fn cookie_name(mut self, name: &str) -> Self {
+        self.cookie_name = name.to_string();
+        self
+    }
+
+    /// Sets the header requests send the token in.
+    pub fn header(mut self, name: &str) -> Self {
+        self.header = name.to_string();
+        self
+    }
+
+    /// Sets the form field requests send the token in.
+    pub fn form_field(mut self, name: &str) -> Self {
+        self.form_field = name.to_string();
+        self
+    }
+
+    /// Exempts the requests to a path, and to the paths below it, from the check.
+    ///
+    /// # Arguments
+    ///
+    /// * `path` - The path, such as `"/webhooks"`, with or without slashes around it.
+    pub fn exempt(mut self, path: &str) -> Self {
+        self.exempt.push(path.trim_matches('/').to_string());
+        self
+    }
+
+    /// Sets whether the token cookie is only sent over HTTPS.
+    pub fn secure(mut self, secure: bool) -> Self {
+        self.secure = secure;
+        self
+    }
+
+    /// Returns whether a request may change state, so that it must carry the token.
+    fn is_checked(&self, request: &Request) -> bool {
+        let safe = matches!(
+            request.method,
+            RequestType::GET | RequestType::HEAD | RequestType::OPTIONS | RequestType::TRACE
+        );
+        let exempt = self.exempt.iter().any(|prefix| {
+            prefix.is_empty()
+                || request
+                    .path
+                    .strip_prefix(prefix.as_str())
+                    .is_some_and(|rest| rest.is_empty() || rest.starts_with('/'))
+        });
+        !safe && !exempt
+    }
+
+    /// Finds the token the client was issued, if it is well-formed.
+    fn stored_token(&self, request: &Request) -> Option<String> {
+        let token = match self.storage {
+            CsrfStorage::Cookie => request.cookie(&self.cookie_name),
+            CsrfStorage::Session => request.session()?.get(&self.cookie_name),
+        }?;
+        base64_url_decode(&token)
+            .is_some_and(|bytes| bytes.len() == TOKEN_LEN)
+            .then_some(token)
+    }
+
+    /// Finds the token a request sent back, from the header or else the form field. A
+    /// header sent more than once yields no token, rather than one picked among them.
+    fn submitted_token(&self, request: &Request) -> Option<String> {
+        let mut tokens = request.headers.get_all(&self.header);
+        if let Some(token) = tokens.next() {
+            return tokens.next().is_none().then(|| token.trim().to_string());
+        }
+        let is_form = request.header("Content-Type").is_some_and(|content_type| {
+            content_type
+                .split(';')
+                .next()
+                .unwrap_or("")
+                .trim()
+                .eq_ignore_ascii_case("application/x-www-form-urlencoded")
+        });
+        if !is_form {
+            return None;
+        }
+        form_pairs(&request.body)
+            .find(|(key, _)| *key == self.form_field)
+            .map(|(_, value)| value)
+    }
+}
+
+/// Generates a new token from 32 bytes of operating-system randomness.
+fn generate_token() -> String {
+    let mut bytes = [0u8; TOKEN_LEN];
+    getrandom::fill(&mut bytes).expect("Failed to gather randomness for CSRF token");
+    base64_url_encode(&bytes)
+}
+
+impl Middleware for CsrfMiddleware {
+    fn handle(&self, mut request: Request, next: Next) -> Response {
+        if self.storage == CsrfStorage::Session && request.session().is_none() {
+            log::error!("CsrfMiddleware keeps tokens in the session, but no session is set up");
+            return HttpError::internal().into();
+        }
+        let stored = self.stored_token(&request);
+        let accepted = !self.is_checked(&request)
+            || stored.as_ref().is_some_and(|stored| {
+                self.submitted_token(&request).is_some_and(|submitted| {
+                    constant_time_eq(submitted.as_bytes(), stored.as_bytes())
+                })
+            });
+        let issued = stored.is_none();
+        let token = stored.unwrap_or_else(generate_token);
+        if issued {
+            if let Some(session) = request.session() {
+                session.insert(&self.cookie_name, &token);
+            }
+        }
+        request.extensions.insert(CsrfToken(token.clone()));
+
+        let mut response = if accepted {
+            next.run(request)
+        } else {
+            log::debug!(
+                "Refused {} /{} without a valid CSRF token",
+                request.method.as_str(),
+                request.path
+            );
+            HttpError::new(403, REJECTION).into()
+        };
+        if issued && self.storage == CsrfStorage::Cookie {
+            let cookie = Cookie::new(&self.cookie_name, &token)
+                .secure(self.secure)
+                .same_site(SameSite::Lax);
+            response.set_cookie(&cookie);
+        }
+        response
+    }
+}
+
+#[cfg(test)]
+mod test_csrf {
+    use super::*;
+    use crate::app::App;
+    use crate::session::SessionMiddleware;
+
+    fn request(method: RequestType, path: &str, headers: &[(&str, &str)], body: &str) -> Request {
+        Request {
+            method,
+            path: path.to_string(),
+            url: format!("/{}", path),
+            headers: headers
+                .iter()
+                .map(|(name, value)| (name.to_string(), value.to_string()))
+                .collect(),
+            body: body.to_string(),
+            ..Request::default()
+        }
+    }
+
+    /// Builds an application whose endpoints answer with the token they were given.
+    fn app(middleware: CsrfMiddleware) -> App {
+        let mut application = App::new();
+        application.add_middleware(middleware);
+        for method in [RequestType::GET, RequestType::POST, RequestType::DELETE] {
+            application.add_endpoint("form", method, |request: Request| {
+                request.csrf_token().unwrap_or_default().to_string()
+            });
+        }
+        application.post("hooks/payments", |_| "received");
+        application
+    }
+
+    fn body(response: &Response) -> &[u8] {
+        response.response_body.as_ref().unwrap().as_bytes()
+    }
+
+    /// Returns the token cookie a response sets, if any.
+    fn token_cookie(response: &Response) -> Option<String> {
+        response
+            .headers
+            .get_all("Set-Cookie")
+            .find_map(|cookie| cookie.strip_prefix("csrf_token="))
+            .map(|cookie| cookie.split(';').next().unwrap().to_string())
+    }
+
+    /// Tests that a token is issued on a safe request, that state-changing requests
+    /// need it in the header or form field, and that a rejection has its own body.
+    #[test]
+    fn test_double_submit_cookie() {
+        let application = app(CsrfMiddleware::new());
+        let response = application.dispatch(request(RequestType::GET, "form", &[], ""));
+        assert_eq!(response.status_code, 200);
+        let token = token_cookie(&response).unwrap();
+        assert_eq!(body(&response), token.as_bytes());
+        assert_eq!(base64_url_decode(&token).unwrap().len(), TOKEN_LEN);
+        assert!(!response.header("Set-Cookie").unwrap().contains("HttpOnly"));
+
+        let cookie = format!("csrf_token={}", token);
+        let posted = |headers: &[(&str, &str)], body: &str| {
+            let mut headers = headers.to_vec();
+            headers.push(("Cookie", &cookie));
+            application.dispatch(request(RequestType::POST, "form", &headers, body))
+        };
+        let response = posted(&[("X-CSRF-Token", &token)], "");
+        assert_eq!(response.status_code, 200);
+        assert_eq!(token_cookie(&response), None);
+        let form = ("Content-Type", "application/x-www-form-urlencoded");
+        let response = posted(&[form], &format!("text=hi&_csrf={}", token));
+        assert_eq!(response.status_code, 200);
+
+        let mut forged = token.clone().into_bytes();
+        forged[0] = if forged[0] == b'A' { b'B' } else { b'A' };
+        let forged = String::from_utf8(forged).unwrap();
+        for response in [
+            posted(&[], ""),
+            posted(&[("X-CSRF-Token", &forged)], ""),
+            posted(&[("X-CSRF-Token", &token), ("x-csrf-token", &forged)], ""),
+            posted(
+                &[("Content-Type", "text/plain")],
+                &format!("_csrf={}", token),
+            ),
+        ] {
+            assert_eq!(response.status_code, 403);
+            assert_eq!(body(&response), REJECTION.as_bytes());
+        }
+
+        // Without a cookie, the header alone proves nothing.
+        let headers = [("X-CSRF-Token", token.as_str())];
+        let response = application.dispatch(request(RequestType::DELETE, "form", &headers, ""));
+        assert_eq!(response.status_code, 403);
+        assert!(token_cookie(&response).is_some());
+    }
+
+    /// Tests that safe methods and exempt paths are never blocked.
+    #[test]
+    fn test_unchecked_requests() {
+        let application = app(CsrfMiddleware::new().exempt("/hooks/"));
+        let response = application.dispatch(request(RequestType::GET, "form", &[], ""));
+        assert_eq!(response.status_code, 200);
+        for method in [RequestType::HEAD, RequestType::OPTIONS, RequestType::TRACE] {
+            let response = application.dispatch(request(method, "form", &[], ""));
+            assert_ne!(response.status_code, 403);
+        }
+        let response = application.dispatch(request(RequestType::POST, "hooks/payments", &[], ""));
+        assert_eq!(body(&response), b"received");
+        let response = application.dispatch(request(RequestType::POST, "hooksmith", &[], ""));
+        assert_eq!(response.status_code, 403);
+    }
+
+    /// Tests that the token can live in the session, and that a missing session is a
+    /// server error rather than a silent pass.
+    #[test]
+    fn test_session_storage() {
+        let mut application = App::new();
+        application.add_middleware(SessionMiddleware::new());
+        application.add_middleware(CsrfMiddleware::new().storage(CsrfStorage::Session));
+        application.get("form", |request| request.csrf_token().unwrap().to_string());
+        application.post("form", |_| "posted");
+
+        let response = application.dispatch(request(RequestType::GET, "form", &[], ""));
+        assert_eq!(token_cookie(&response), None);
+        let token = String::from_utf8(body(&response).to_vec()).unwrap();
+        let session = response
+            .header("Set-Cookie")
+            .unwrap()
+            .split(';')
+            .next()
+            .unwrap();
+        let headers = [("Cookie", session), ("X-CSRF-Token", token.as_str())];
+        let response = application.dispatch(request(RequestType::POST, "form", &headers, ""));
+        assert_eq!(body(&response), b"posted");
+        let response = application.dispatch(request(RequestType::POST, "form", &headers[..1], ""));
+        assert_eq!(response.status_code, 403);
+
+        let application = app(CsrfMiddleware::new().storage(CsrfStorage::Session));
+        let response = application.dispatch(request(RequestType::GET, "form", &[], ""));
+        assert_eq!(response.status_code, 500);
+    }
+}
@@ -0,0 +1,238 @@
+//! DEFLATE compression (RFC 1951) with the gzip (RFC 1952) and zlib (RFC 1950)
+//! wrappers, for compressing response bodies without pulling in a dependency.
+//!
+//! Matches are found through hash chains over the 32 KiB window and coded with the
+//! fixed Huffman codes, in a single block. That gives up some ratio against `zlib`, but
+//! keeps the encoder short and its output well within reach for text.
+
+use crate::inflate::{adler32, crc32, DISTANCE_BASE, DISTANCE_EXTRA, LENGTH_BASE, LENGTH_EXTRA};
+
+/// The furthest back a match may start, in bytes.
+pub(crate) const WINDOW: usize = 32 * 1024;
+/// The shortest match worth coding, and the bytes matches are hashed by.
+const MIN_MATCH: usize = 3;
+/// The longest match DEFLATE can code.
+const MAX_MATCH: usize = 258;
+/// The number of bits of the hash of the next three bytes.
+const HASH_BITS: u32 = 15;
+/// The most candidates looked at for each match.
+const MAX_CHAIN: usize = 64;
+/// Marks an empty hash chain.
+const NONE: usize = usize::MAX;
+
+/// Compresses `data` into a gzip member.
+pub(crate) fn gzip(data: &[u8]) -> Vec<u8> {
+    // No name, comment or modification time, and an unknown operating system.
+    let mut output = vec![0x1f, 0x8b, 8, 0, 0, 0, 0, 0, 0, 0xff];
+    deflate_into(data, &mut output);
+    output.extend_from_slice(&crc32(data).to_le_bytes());
+    // The size is only kept modulo 2^32.
+    output.extend_from_slice(&(data.len() as u32).to_le_bytes());
+    output
+}
+
+/// Compresses `data` into a zlib stream, which is what HTTP calls `deflate`.
+pub(crate) fn zlib_compress(data: &[u8]) -> Vec<u8> {
+    // A 32 KiB window at the default level, which passes the header check.
+    let mut output = vec![0x78, 0x9c];
+    deflate_into(data, &mut output);
+    output.extend_from_slice(&adler32(data).to_be_bytes());
+    output
+}
+
+/// Appends `data` compressed as raw DEFLATE data to `output`.
+fn deflate_into(data: &[u8], output: &mut Vec<u8>) {
+    let mut bits = BitWriter::new(output);
+    // A final block with the fixed codes.
+    bits.write(0b011, 3);
+    let mut matches = MatchFinder::new();
+    let mut position = 0;
+    while position < data.len() {
+        let limit = MAX_MATCH.min(data.len() - position);
+        let (length, distance) = matches.longest(data, position, limit);
+        let step = if length >= MIN_MATCH {
+            bits.length(length);
+            bits.distance(distance);
+            length
+        } else {
+            bits.symbol(u16::from(data[position]));
+            1
+        };
+        matches.insert(data, position..position + step);
+        position += step;
+    }
+    bits.symbol(256);
+    bits.flush();
+}
+
+/// Finds earlier occurrences of the bytes at a position, through hash chains over the
+/// last [`WINDOW`] bytes, for DEFLATE and Brotli alike.
+pub(crate) struct MatchFinder {
+    /// The latest position of each hash.
+    head: Vec<usize>,
+    /// The position before each one with the same hash, indexed modulo the window.
+    previous: Vec<usize>,
+}
+
+impl MatchFinder {
+    /// Creates a finder that knows no position yet.
+    pub(crate) fn new() -> Self {
+        MatchFinder {
+            head: vec![NONE; 1 << HASH_BITS],
+            previous: vec![NONE; WINDOW],
+        }
+    }
+
+    /// Adds the positions in `range` to the chains, once they have been coded.
+    pub(crate) fn insert(&mut self, data: &[u8], range: std::ops::Range<usize>) {
+        for start in range {
+            if let Some(hash) = hash_at(data, start) {
+                self.previous[start % WINDOW] = self.head[hash];
+                self.head[hash] = start;
+            }
+        }
+    }
+
+    /// Finds the longest earlier match for the bytes at `position`, of at most `limit`
+    /// bytes.
+    ///
+    /// # Returns
+    ///
+    /// * `(usize, usize)` - The length and distance of the match, with a length of `0`
+    ///   when there is none.
+    pub(crate) fn longest(&self, data: &[u8], position: usize, limit: usize) -> (usize, usize) {
+        let Some(hash) = hash_at(data, position) else {
+            return (0, 0);
+        };
+        let (mut best_length, mut best_distance) = (0, 0);
+        let mut candidate = self.head[hash];
+        let mut chain = 0;
+        while candidate != NONE && position - candidate <= WINDOW && chain < MAX_CHAIN {
+            let length = data[candidate..]
+                .iter()
+                .zip(&data[position..position + limit])
+                .take_while(|(earlier, current)| earlier == current)
+                .count();
+            if length > best_length {
+                best_length = length;
+                best_distance = position - candidate;
+                if length == limit {
+                    break;
+                }
+            }
+            candidate = self.previous[candidate % WINDOW];
+            chain += 1;
+        }
+        (best_length, best_distance)
+    }
+}
+
+/// Hashes the three bytes at `position`, or returns `None` when fewer remain.
+fn hash_at(data: &[u8], position: usize) -> Option<usize> {
+    let bytes = data.get(position..position + MIN_MATCH)?;
+    let key = u32::from(bytes[0]) << 16 | u32::from(bytes[1]) << 8 | u32::from(bytes[2]);
+    Some((key.wrapping_mul(0x9e37_79b1) >> (32 - HASH_BITS)) as usize)
+}
+
+/// Writes bits least significant first, as DEFLATE and Brotli pack them.
+pub(crate) struct BitWriter<'a> {
+    output: &'a mut Vec<u8>,
+    buffer: u64,
+    count: u32,
+}
+
+impl<'a> BitWriter<'a> {
+    /// Creates a writer appending to `output`.
+    pub(crate) fn new(output: &'a mut Vec<u8>) -> Self {
+        BitWriter {
+            output,
+            buffer: 0,
+            count: 0,
+        }
+    }
+
+    /// Writes the low `count` bits of `value`, at most 32.
+    pub(crate) fn write(&mut self, value: u32, count: u32) {
+        self.buffer |= u64::from(value) << self.count;
+        self.count += count;
+        while self.count >= 8 {
+            self.output.push(self.buffer as u8);
+            self.buffer >>= 8;
+            self.count -= 8;
+        }
+    }
+
+    /// Writes a literal/length symbol with its fixed Huffman code.
+    fn symbol(&mut self, symbol: u16) {
+        let (code, length) = match symbol {
+            0..=143 => (0x30 + symbol, 8),
+            144..=255 => (0x190 + symbol - 144, 9),
+            256..=279 => (symbol - 256, 7),
+            _ => (0xc0 + symbol - 280, 8),
+        };
+        // Huffman codes are sent most significant bit first.
+        self.write(u32::from(code.reverse_bits() >> (16 - length)), length);
+    }
+
+    /// Writes the symbol and extra bits of a match length.
+    fn length(&mut self, length: usize) {
+        let index = LENGTH_BASE
+            .iter()
+            .rposition(|&base| usize::from(base) <= length)
+            .unwrap_or(0);
+        self.symbol(257 + index as u16);
+        let extra = length - usize::from(LENGTH_BASE[index]);
+        self.write(extra as u32, u32::from(LENGTH_EXTRA[index]));
+    }
+
+    /// Writes the code and extra bits of a match distance.
+    fn distance(&mut self, distance: usize) {
+        let index = DISTANCE_BASE
+            .iter()
+            .rposition(|&base| usize::from(base) <= distance)
+            .unwrap_or(0);
+        self.write(u32::from((index as u8).reverse_bits() >> 3), 5);
+        let extra = distance - usize::from(DISTANCE_BASE[index]);
+        self.write(extra as u32, u32::from(DISTANCE_EXTRA[index]));
+    }
+
+    /// Writes out the last partial byte, padded with zero bits.
+    pub(crate) fn flush(&mut self) {
+        if self.count > 0 {
+            self.output.push(self.buffer as u8);
+        }
+        self.buffer = 0;
+        self.count = 0;
+    }
+}
+
+#[cfg(test)]
+mod test_deflate {
+    use super::*;
+    use crate::inflate::{gunzip, zlib_decompress};
+
+    /// Tests that compressed data decodes back to the original, across matches as long
+    /// as DEFLATE allows and ones reaching back across the whole window.
+    #[test]
+    fn test_round_trip() {
+        let text = "Lorem ipsum dolor sit amet, consectetur adipiscing elit. ".repeat(200);
+        let mut noise = Vec::new();
+        let mut state = 7u32;
+        for _ in 0..70_000 {
+            state = state.wrapping_mul(1_103_515_245).wrapping_add(12_345);
+            noise.push((state >> 16) as u8);
+        }
+        let mut far = noise[..40_000].to_vec();
+        far.extend_from_slice(&noise[..1_000]);
+        let inputs: [&[u8]; 6] = [b"", b"a", b"abcabcabcabc", text.as_bytes(), &noise, &far];
+        for input in inputs {
+            assert_eq!(gunzip(&gzip(input), usize::MAX).unwrap(), input);
+            assert_eq!(
+                zlib_decompress(&zlib_compress(input), usize::MAX).unwrap(),
+                input
+            );
+        }
+        assert!(gzip(text.as_bytes()).len() < text.len() / 10);
+        assert_eq!(&zlib_compress(&[0; 1000])[..2], [0x78, 0x9c]);
+    }
+}
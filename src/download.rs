@@ -0,0 +1,192 @@
+use crate::http11_response::{Body, Response};
+use crate::http_error::HttpError;
+use crate::into_response::body_response;
+use crate::parse_url::percent_encode;
+use crate::static_files::content_type;
+use std::fs::File;
+use std::path::Path;
+
+impl Response {
+    /// Builds a `200 OK` response sending a file, with a `Content-Type` guessed from its
+    /// extension.
+    ///
+    /// The file is sent from disk as a [file body](Body::file) when the response is
+    /// written, so it is never held in memory.
+    ///
+    /// # Arguments
+    ///
+    /// * `path` - The file to send.
+    ///
+    /// # Errors
+    ///
+    /// Returns `404 Not Found` for a path that does not exist or is a directory,
+    /// `403 Forbidden` for a file that cannot be read and `500 Internal Server Error`
+    /// for other failures, which a handler can return with `?`.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use rustic::app::App;
+    /// use rustic::http11_response::Response;
+    /// use rustic::test::TestClient;
+    ///
+    /// let mut application = App::new();
+    /// application.get("report", |_| Response::file("reports/missing.pdf"));
+    /// let client = TestClient::new(application);
+    /// assert_eq!(client.get("/report").send().status, 404);
+    /// ```
+    pub fn file(path: impl AsRef<Path>) -> Result<Response, HttpError> {
+        let path = path.as_ref();
+        let file = File::open(path)?;
+        let metadata = file.metadata()?;
+        if metadata.is_dir() {
+            return Err(HttpError::not_found());
+        }
+        let body = Body::file(file, 0, metadata.len());
+        Ok(body_response(200, content_type(path), body))
+    }
+
+    /// Builds a `200 OK` response sending a file as a download, which browsers save as
+    /// `filename` rather than display; see [`Response::file`].
+    ///
+    /// The name is sent in `Content-Disposition`, quoted with its quotes and backslashes
+    /// escaped and control characters replaced. A name that is not ASCII is also sent
+    /// percent-encoded as UTF-8 in `filename*`, which clients prefer, with non-ASCII
+    /// characters replaced in the plain `filename` for those that do not support it.
+    ///
+    /// # Arguments
+    ///
+    /// * `path` - The file to send.
+    /// * `filename` - The name to suggest saving the file as, which need not be that of
+    ///   `path`.
+    ///
+    /// # Errors
+    ///
+    /// Fails as [`Response::file`] does.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use rustic::app::App;
+    /// use rustic::http11_response::Response;
+    ///
+    /// let mut application = App::new();
+    /// application.get("invoices/{id:u64}", |request| {
+    ///     let id = request.path_param("id").unwrap_or_default();
+    ///     Response::download(
+    ///         format!("invoices/{}.pdf", id),
+    ///         &format!("Invoice {}.pdf", id),
+    ///     )
+    /// });
+    /// ```
+    pub fn download(path: impl AsRef<Path>, filename: &str) -> Result<Response, HttpError> {
+        let mut response = Response::file(path)?;
+        response
+            .headers
+            .insert("Content-Disposition", content_disposition(filename));
+        Ok(response)
+    }
+}
+
+/// Builds the `Content-Disposition` value of an attachment saved as `filename`, with
+/// the RFC 5987 `filename*` parameter when the name is not ASCII.
+fn content_disposition(filename: &str) -> String {
+    let mut fallback = String::with_capacity(filename.len());
+    for character in filename.chars() {
+        match character {
+            '"' | '\\' => {
+                fallback.push('\\');
+                fallback.push(character);
+            }
+            ' ' | '!'..='~' => fallback.push(character),
+            _ => fallback.push('_'),
+        }
+    }
+    let mut value = format!("attachment; filename=\"{}\"", fallback);
+    if !filename.is_ascii() {
+        let sanitized: String = filename
+            .chars()
+            .map(|character| {
+                if character.is_control() {
+                    '_'
+                } else {
+                    character
+                }
+            })
+            .collect();
+        value.push_str("; filename*=UTF-8''");
+        value.push_str(&percent_encode(&sanitized));
+    }
+    value
+}
+
+#[cfg(test)]
+mod test_download {
+    use super::*;
+    use crate::app::App;
+    use crate::test::TestClient;
+
+    /// Tests that names are quoted and escaped, with `filename*` for names that are not
+    /// ASCII.
+    #[test]
+    fn test_content_disposition() {
+        assert_eq!(
+            content_disposition("annual report.pdf"),
+            "attachment; filename=\"annual report.pdf\""
+        );
+        assert_eq!(
+            content_disposition("say \"hi\" \\ bye.txt"),
+            "attachment; filename=\"say \\\"hi\\\" \\\\ bye.txt\""
+        );
+        assert_eq!(
+            content_disposition("line\r\nbreak.txt"),
+            "attachment; filename=\"line__break.txt\""
+        );
+        assert_eq!(
+            content_disposition("報告 \"最終\".pdf"),
+            "attachment; filename=\"__ \\\"__\\\".pdf\"; \
+             filename*=UTF-8''%E5%A0%B1%E5%91%8A%20%22%E6%9C%80%E7%B5%82%22.pdf"
+        );
+    }
+
+    /// Tests that files are sent with their type and suggested name, and that missing
+    /// files and directories are answered with `404 Not Found`.
+    #[test]
+    fn test_file_responses() {
+        let path = std::env::temp_dir().join(format!("rustic-download-{}.txt", std::process::id()));
+        std::fs::write(&path, "quarterly numbers").unwrap();
+        let mut application = App::new();
+        let file = path.clone();
+        application.get("view", move |_| Response::file(&file));
+        let file = path.clone();
+        application.get("save", move |_| {
+            Response::download(&file, "Q3 \"final\" 数字.txt")
+        });
+        application.get("missing", |_| {
+            Response::download("/nonexistent/file.txt", "a.txt")
+        });
+        application.get("directory", |_| Response::file(std::env::temp_dir()));
+        let client = TestClient::new(application);
+
+        let response = client.get("/view").send();
+        assert_eq!(response.text(), "quarterly numbers");
+        assert_eq!(
+            response.header("Content-Type"),
+            Some("text/plain; charset=utf-8")
+        );
+        assert_eq!(response.header("Content-Disposition"), None);
+
+        let response = client.get("/save").send();
+        assert_eq!(response.text(), "quarterly numbers");
+        assert_eq!(
+            response.header("Content-Disposition"),
+            Some(
+                "attachment; filename=\"Q3 \\\"final\\\" __.txt\"; \
+                 filename*=UTF-8''Q3%20%22final%22%20%E6%95%B0%E5%AD%97.txt"
+            )
+        );
+        assert_eq!(client.get("/missing").send().status, 404);
+        assert_eq!(client.get("/directory").send().status, 404);
+        std::fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,228 @@
+use crate::app::{App, Request};
+use crate::into_response::text_response;
+use crate::parse_headers::RequestType;
+use std::fmt::Write;
+
+/// The request line and header lines of a request as they were received, kept under
+/// [`ServerConfig::keep_raw_head`](crate::app::ServerConfig::keep_raw_head).
+pub(crate) struct RawHead(pub(crate) Vec<String>);
+
+impl Request {
+    /// Returns the request line followed by the header lines, as they were received
+    /// with their line endings removed, or `None` unless the server was started with
+    /// [`ServerConfig::keep_raw_head`](crate::app::ServerConfig::keep_raw_head).
+    ///
+    /// Unlike [`Request::headers`], these keep the order, casing and whitespace the
+    /// client sent, along with the lines the parser drops.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use rustic::app::{App, ServerConfig};
+    /// use rustic::test::TestClient;
+    ///
+    /// let mut application = App::new();
+    /// application.get("first", |request| match request.raw_head_lines() {
+    ///     Some(lines) => lines[0].clone(),
+    ///     None => "not kept".to_string(),
+    /// });
+    /// let config = ServerConfig::new().keep_raw_head(true);
+    /// let client = TestClient::with_config(application, config);
+    /// assert_eq!(client.get("/first?x=1").send().text(), "GET /first?x=1 HTTP/1.1");
+    /// ```
+    pub fn raw_head_lines(&self) -> Option<&[String]> {
+        self.extensions.get::<RawHead>().map(|raw| raw.0.as_slice())
+    }
+
+    /// Describes what the parser dropped or normalized in the head of the request, such
+    /// as header lines without a colon or whitespace trimmed around names and values.
+    ///
+    /// The head is checked from [`Request::raw_head_lines`], so this is empty when they
+    /// are not kept.
+    pub fn parse_diagnostics(&self) -> Vec<String> {
+        self.raw_head_lines()
+            .map_or_else(Vec::new, |lines| diagnose(lines.get(1..).unwrap_or(&[])))
+    }
+}
+
+impl App {
+    /// Serves a debugging endpoint at `path`, such as `/_echo`, that answers `GET`
+    /// requests with a plain text report of what the server received: the method,
+    /// request target and body length, the head lines as they were received, the headers
+    /// as they were parsed and the [diagnostics](Request::parse_diagnostics) of parsing
+    /// them.
+    ///
+    /// The raw lines and diagnostics are only reported when the server keeps them, with
+    /// [`ServerConfig::keep_raw_head`](crate::app::ServerConfig::keep_raw_head). The
+    /// report echoes every header, credentials included, so the endpoint should not be
+    /// reachable in production.
+    ///
+    /// # Arguments
+    ///
+    /// * `path` - The path to serve the report at; a leading `/` is ignored.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use rustic::app::{App, ServerConfig};
+    /// use rustic::test::TestClient;
+    ///
+    /// let mut application = App::new();
+    /// application.enable_echo_endpoint("/_echo");
+    /// let config = ServerConfig::new().keep_raw_head(true);
+    /// let client = TestClient::with_config(application, config);
+    /// let report = client.get("/_echo").header("X-Odd", "value  ").send().text();
+    /// assert!(report.contains("X-Odd: value  \n"));
+    /// assert!(report.contains("trimmed whitespace around the value of `X-Odd`"));
+    /// ```
+    pub fn enable_echo_endpoint(&mut self, path: &str) {
+        self.add_endpoint(path.trim_start_matches('/'), RequestType::GET, |request| {
+            text_response(200, echo_report(&request))
+        });
+    }
+}
+
+/// Renders the report of [`App::enable_echo_endpoint`].
+fn echo_report(request: &Request) -> String {
+    let mut report = String::new();
+    let _ = writeln!(report, "Method: {}", request.method.as_str());
+    let _ = writeln!(report, "Target: {}", request.url);
+    let _ = writeln!(report, "Body length: {}", request.body.len());
+
+    report.push_str("\nRaw head:\n");
+    match request.raw_head_lines() {
+        Some(lines) => {
+            for line in lines {
+                let _ = writeln!(report, "{}", line);
+            }
+        }
+        None => report.push_str("(not kept, see ServerConfig::keep_raw_head)\n"),
+    }
+
+    report.push_str("\nParsed headers:\n");
+    let mut headers: Vec<_> = request.headers.iter().collect();
+    headers.sort();
+    for (name, value) in headers {
+        let _ = writeln!(report, "{}: {}", name, value);
+    }
+
+    if request.raw_head_lines().is_some() {
+        report.push_str("\nDiagnostics:\n");
+        let diagnostics = request.parse_diagnostics();
+        if diagnostics.is_empty() {
+            report.push_str("(none)\n");
+        }
+        for diagnostic in diagnostics {
+            let _ = writeln!(report, "{}", diagnostic);
+        }
+    }
+    report
+}
+
+/// Describes what parsing `lines`, the header lines of a request, drops or normalizes.
+fn diagnose(lines: &[String]) -> Vec<String> {
+    let mut diagnostics = Vec::new();
+    let mut names: Vec<(&str, usize)> = Vec::new();
+    for line in lines {
+        let Some((name, value)) = line.split_once(':') else {
+            diagnostics.push(format!("dropped a header line without a colon: {:?}", line));
+            continue;
+        };
+        let trimmed = name.trim();
+        if trimmed != name {
+            diagnostics.push(format!(
+                "trimmed whitespace around the name of `{}`",
+                trimmed
+            ));
+        }
+        // One space or tab after the colon is the usual separator, not normalization.
+        let value = value.strip_prefix([' ', '\t']).unwrap_or(value);
+        if value.trim_matches([' ', '\t', '\r']) != value {
+            diagnostics.push(format!(
+                "trimmed whitespace around the value of `{}`",
+                trimmed
+            ));
+        }
+        match names
+            .iter_mut()
+            .find(|(seen, _)| seen.eq_ignore_ascii_case(trimmed))
+        {
+            Some((_, count)) => *count += 1,
+            None => names.push((trimmed, 1)),
+        }
+    }
+    for (name, count) in names.into_iter().filter(|(_, count)| *count > 1) {
+        diagnostics.push(format!(
+            "`{}` was sent {} times, but only one of its values is read",
+            name, count
+        ));
+    }
+    diagnostics
+}
+
+#[cfg(test)]
+mod test_echo {
+    use super::*;
+    use crate::app::ServerConfig;
+    use crate::test::TestClient;
+
+    /// Tests that dropped lines, trimmed whitespace and repeated headers are described.
+    #[test]
+    fn test_diagnose() {
+        let lines: Vec<String> = [
+            "Host: example.com",
+            "Accept:*/*",
+            "not a header",
+            " X-Indented: 1",
+            "X-Padded:   2 ",
+            "accept: text/html",
+        ]
+        .map(str::to_string)
+        .into();
+        assert_eq!(
+            diagnose(&lines),
+            [
+                "dropped a header line without a colon: \"not a header\"",
+                "trimmed whitespace around the name of `X-Indented`",
+                "trimmed whitespace around the value of `X-Padded`",
+                "`Accept` was sent 2 times, but only one of its values is read",
+            ]
+        );
+        assert!(diagnose(&lines[..2]).is_empty());
+    }
+
+    /// Tests that the raw head is only kept when the server is configured to.
+    #[test]
+    fn test_raw_head_opt_in() {
+        let app = || {
+            let mut application = App::new();
+            application.get("lines", |request| {
+                format!("{:?}", request.raw_head_lines().map(<[String]>::len))
+            });
+            application.enable_echo_endpoint("_echo");
+            application
+        };
+
+        let client = TestClient::new(app());
+        assert_eq!(client.get("/lines").send().text(), "None");
+        let report = client.get("/_echo").send().text();
+        assert!(report.contains("(not kept, see ServerConfig::keep_raw_head)"));
+        assert!(!report.contains("Diagnostics:"));
+
+        let client = TestClient::with_config(app(), ServerConfig::new().keep_raw_head(true));
+        assert_ne!(client.get("/lines").send().text(), "None");
+        let report = client
+            .get("/_echo?x=1")
+            .header("X-Debug", "yes")
+            .send()
+            .text();
+        assert!(
+            report.starts_with("Method: GET\nTarget: /_echo?x=1\n"),
+            "{}",
+            report
+        );
+        assert!(report.contains("\nGET /_echo?x=1 HTTP/1.1\n"), "{}", report);
+        assert!(report.contains("\nX-Debug: yes\n"), "{}", report);
+        assert!(report.contains("\nDiagnostics:\n(none)\n"), "{}", report);
+    }
+}
@@ -0,0 +1,105 @@
+use std::fmt;
+use std::io;
+
+/// Why a server could not start or keep running, returned by [`run`](crate::app::run),
+/// [`run_with_config`](crate::app::run_with_config),
+/// [`run_with_listener`](crate::app::run_with_listener),
+/// [`listen_at_port`](crate::connection::listen_at_port) and their async counterparts.
+///
+/// Nothing a client sends makes these fail: a malformed or failing connection is
+/// answered or closed on its own, and a panicking handler is answered with
+/// `500 Internal Server Error`.
+///
+/// # Examples
+///
+/// ```no_run
+/// use rustic::app::{run, App};
+///
+/// fn main() -> Result<(), rustic::Error> {
+///     let mut application = App::new();
+///     application.get("hello", |_| "Hello!");
+///     run(application, 8080)
+/// }
+/// ```
+#[derive(Debug)]
+#[non_exhaustive]
+pub enum Error {
+    /// The listening socket could not be bound, for example because the port is taken.
+    Bind(io::Error),
+    /// The listener failed in a way that accepting again would not fix.
+    Accept(io::Error),
+    /// Another I/O operation the server depends on failed, such as starting its threads.
+    Io(io::Error),
+    /// Input the server was given, such as an address, could not be parsed.
+    Parse(String),
+    /// The server refused to start with the application or settings it was given, for
+    /// example because an [`App::on_start`](crate::app::App::on_start) hook panicked.
+    Config(String),
+    /// The graceful shutdown could not be set up, such as signal handlers that failed
+    /// to install.
+    Shutdown(io::Error),
+}
+
+impl Error {
+    /// Returns the I/O error behind this one, if any, to tell its kind apart.
+    pub fn io_error(&self) -> Option<&io::Error> {
+        match self {
+            Error::Bind(err) | Error::Accept(err) | Error::Io(err) | Error::Shutdown(err) => {
+                Some(err)
+            }
+            Error::Parse(_) | Error::Config(_) => None,
+        }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Bind(err) => write!(f, "failed to bind the listener: {}", err),
+            Error::Accept(err) => write!(f, "failed to accept connections: {}", err),
+            Error::Io(err) => write!(f, "I/O error: {}", err),
+            Error::Parse(message) => write!(f, "failed to parse: {}", message),
+            Error::Config(message) => write!(f, "failed to start: {}", message),
+            Error::Shutdown(err) => write!(f, "failed to set up the shutdown: {}", err),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        self.io_error()
+            .map(|err| err as &(dyn std::error::Error + 'static))
+    }
+}
+
+impl From<io::Error> for Error {
+    fn from(err: io::Error) -> Self {
+        Error::Io(err)
+    }
+}
+
+#[cfg(test)]
+mod test_error {
+    use super::*;
+    use std::error::Error as _;
+
+    /// Tests that errors describe themselves and expose the I/O error behind them.
+    #[test]
+    fn test_error() {
+        let err = Error::Bind(io::ErrorKind::AddrInUse.into());
+        assert!(err.to_string().starts_with("failed to bind the listener: "));
+        assert_eq!(err.io_error().unwrap().kind(), io::ErrorKind::AddrInUse);
+        assert!(err.source().is_some());
+
+        let err = Error::Config("an on_start hook panicked".to_string());
+        assert_eq!(
+            err.to_string(),
+            "failed to start: an on_start hook panicked"
+        );
+        assert!(err.source().is_none());
+        assert!(matches!(
+            Error::from(io::Error::other("no threads")),
+            Error::Io(_)
+        ));
+    }
+}
@@ -0,0 +1,79 @@
+use std::any::{Any, TypeId};
+use std::collections::HashMap;
+
+/// A type map attached to each request for sharing data between middleware and handlers.
+///
+/// Values are keyed by their type, so each type can be stored at most once. Middleware
+/// usually stores a small handle type of its own (such as a session) that handlers then
+/// retrieve.
+///
+/// # Examples
+///
+/// ```
+/// use rustic::extensions::Extensions;
+///
+/// struct UserId(u32);
+///
+/// let mut extensions = Extensions::new();
+/// extensions.insert(UserId(7));
+/// assert_eq!(extensions.get::<UserId>().map(|id| id.0), Some(7));
+/// ```
+#[derive(Default)]
+pub struct Extensions {
+    map: HashMap<TypeId, Box<dyn Any + Send + Sync>>,
+}
+
+impl Extensions {
+    /// Creates an empty extension map.
+    pub fn new() -> Self {
+        Extensions {
+            map: HashMap::new(),
+        }
+    }
+
+    /// Inserts a value, returning the previous value of the same type if there was one.
+    pub fn insert<T: Send + Sync + 'static>(&mut self, value: T) -> Option<T> {
+        self.map
+            .insert(TypeId::of::<T>(), Box::new(value))
+            .and_then(|previous| previous.downcast().ok().map(|boxed| *boxed))
+    }
+
+    /// Returns a reference to the stored value of type `T`, if any.
+    pub fn get<T: Send + Sync + 'static>(&self) -> Option<&T> {
+        self.map
+            .get(&TypeId::of::<T>())
+            .and_then(|value| value.downcast_ref())
+    }
+
+    /// Returns a mutable reference to the stored value of type `T`, if any.
+    pub fn get_mut<T: Send + Sync + 'static>(&mut self) -> Option<&mut T> {
+        self.map
+            .get_mut(&TypeId::of::<T>())
+            .and_then(|value| value.downcast_mut())
+    }
+
+    /// Removes and returns the stored value of type `T`, if any.
+    pub fn remove<T: Send + Sync + 'static>(&mut self) -> Option<T> {
+        self.map
+            .remove(&TypeId::of::<T>())
+            .and_then(|value| value.downcast().ok().map(|boxed| *boxed))
+    }
+}
+
+#[cfg(test)]
+mod test_extensions {
+    use super::*;
+
+    /// Tests that values of different types are stored independently.
+    #[test]
+    fn test_insert_get_remove() {
+        let mut extensions = Extensions::new();
+        assert_eq!(extensions.insert(5u32), None);
+        assert_eq!(extensions.insert("name"), None);
+        assert_eq!(extensions.insert(6u32), Some(5));
+        assert_eq!(extensions.get::<u32>(), Some(&6));
+        assert_eq!(extensions.get::<&str>(), Some(&"name"));
+        assert_eq!(extensions.remove::<u32>(), Some(6));
+        assert_eq!(extensions.get::<u32>(), None);
+    }
+}
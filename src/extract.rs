@@ -0,0 +1,771 @@
+#[cfg(feature = "serde")]
+use crate::app::PathParamMap;
+use crate::app::{IntoHandlerResult, Request};
+use crate::header_map::HeaderMap;
+#[cfg(feature = "serde")]
+use crate::http11_response::reason_phrase;
+use crate::http11_response::Response;
+use crate::http_error::HttpError;
+use crate::into_response::IntoResponse;
+#[cfg(feature = "serde")]
+pub use crate::into_response::Json;
+#[cfg(feature = "serde")]
+use crate::parse_url::query_pairs;
+#[cfg(feature = "serde")]
+use std::collections::HashMap;
+#[cfg(feature = "serde")]
+use std::fmt;
+
+/// A value that can be extracted from a request before a handler runs.
+///
+/// Handlers wrapped with [`with_extractors`] take extractors as arguments instead of the
+/// raw [`Request`]. When an extractor fails, the handler is not called and the request
+/// is answered with the returned [`HttpError`], `400 Bad Request` for malformed input and
+/// `415 Unsupported Media Type` for a body of the wrong type.
+///
+/// # Examples
+///
+/// ```
+/// use rustic::app::{App, Request};
+/// use rustic::extract::{with_extractors, FromRequest};
+/// use rustic::http_error::HttpError;
+///
+/// struct ApiKey(String);
+///
+/// impl FromRequest for ApiKey {
+///     fn from_request(request: &Request) -> Result<Self, HttpError> {
+///         request
+///             .header("X-Api-Key")
+///             .map(|key| ApiKey(key.to_string()))
+///             .ok_or_else(|| HttpError::new(401, "Missing API key"))
+///     }
+/// }
+///
+/// let mut application = App::new();
+/// application.get("whoami", with_extractors(|ApiKey(key): ApiKey| format!("Key {}", key)));
+/// ```
+pub trait FromRequest: Sized {
+    /// Extracts the value from the request.
+    fn from_request(request: &Request) -> Result<Self, HttpError>;
+}
+
+/// The request headers.
+///
+/// # Examples
+///
+/// ```
+/// use rustic::app::App;
+/// use rustic::extract::{with_extractors, Headers};
+/// let mut application = App::new();
+/// application.get(
+///     "agent",
+///     with_extractors(|headers: Headers| headers.get("User-Agent").unwrap_or("unknown").to_string()),
+/// );
+/// ```
+#[derive(Debug)]
+pub struct Headers(pub HeaderMap);
+
+impl Headers {
+    /// Looks up the first value of a header by name, ignoring ASCII case.
+    pub fn get(&self, name: &str) -> Option<&str> {
+        self.0.get(name)
+    }
+}
+
+impl FromRequest for Headers {
+    fn from_request(request: &Request) -> Result<Self, HttpError> {
+        Ok(Headers(request.headers.clone()))
+    }
+}
+
+/// The raw request body.
+#[derive(Debug)]
+pub struct Body(pub String);
+
+impl FromRequest for Body {
+    fn from_request(request: &Request) -> Result<Self, HttpError> {
+        Ok(Body(request.body.clone()))
+    }
+}
+
+/// The URL query parameters, deserialized into `T`.
+///
+/// The parameters are deserialized with [`Request::query_as`]. A missing field or a value
+/// that does not parse is answered with `400 Bad Request` naming the field.
+///
+/// # Examples
+///
+/// ```
+/// use rustic::app::App;
+/// use rustic::extract::{with_extractors, Query};
+/// use std::collections::HashMap;
+/// let mut application = App::new();
+/// application.get(
+///     "search",
+///     with_extractors(|Query(params): Query<HashMap<String, String>>| {
+///         format!("{} parameters", params.len())
+///     }),
+/// );
+/// ```
+#[cfg(feature = "serde")]
+#[derive(Debug)]
+pub struct Query<T>(pub T);
+
+#[cfg(feature = "serde")]
+impl<T: serde::de::DeserializeOwned> FromRequest for Query<T> {
+    fn from_request(request: &Request) -> Result<Self, HttpError> {
+        request.query_as().map(Query).map_err(HttpError::from)
+    }
+}
+
+/// The parameters captured by `{name}` segments of the endpoint path, deserialized
+/// into `T`.
+///
+/// # Examples
+///
+/// ```
+/// use rustic::app::App;
+/// use rustic::extract::{with_extractors, PathParams};
+/// let mut application = App::new();
+/// application.get(
+///     "users/{id}",
+///     with_extractors(|PathParams(id): PathParams<u64>| format!("User {}", id)),
+/// );
+/// ```
+///
+/// A single parameter can be extracted directly, as above; several are extracted into
+/// a map or a struct with one field per parameter.
+#[cfg(feature = "serde")]
+#[derive(Debug)]
+pub struct PathParams<T>(pub T);
+
+#[cfg(feature = "serde")]
+impl<T: serde::de::DeserializeOwned> FromRequest for PathParams<T> {
+    fn from_request(request: &Request) -> Result<Self, HttpError> {
+        let empty = HashMap::new();
+        let captured = request
+            .extensions
+            .get::<PathParamMap>()
+            .map_or(&empty, |params| &params.0);
+        params::from_path_params(captured)
+            .map(PathParams)
+            .map_err(HttpError::from)
+    }
+}
+
+/// The request body, parsed as JSON into `T`.
+///
+/// The request must declare an `application/json` or `+json` content type, otherwise it
+/// is answered with `415 Unsupported Media Type`. A body that does not parse is answered
+/// with `400 Bad Request`.
+#[cfg(feature = "serde")]
+impl<T: serde::de::DeserializeOwned> FromRequest for Json<T> {
+    fn from_request(request: &Request) -> Result<Self, HttpError> {
+        if !request.is_json() {
+            return Err(HttpError::new(415, reason_phrase(415)));
+        }
+        serde_json::from_str(&request.body)
+            .map(Json)
+            .map_err(|err| HttpError::bad_request(format!("Bad Request: {}", err)))
+    }
+}
+
+/// A handler taking [`FromRequest`] extractors as arguments.
+///
+/// Implemented for functions and closures of up to four extractor arguments whose
+/// return type is one [`App::add_endpoint`](crate::app::App::add_endpoint) accepts.
+pub trait ExtractorHandler<Args>: Send + Sync + 'static {
+    /// Extracts the arguments from the request and calls the handler.
+    fn call(&self, request: &Request) -> Option<Response>;
+}
+
+macro_rules! impl_extractor_handler {
+    ($($ty:ident $var:ident),+) => {
+        impl<F, R, $($ty),+> ExtractorHandler<($($ty,)+)> for F
+        where
+            F: Fn($($ty),+) -> R + Send + Sync + 'static,
+            R: IntoHandlerResult,
+            $($ty: FromRequest,)+
+        {
+            fn call(&self, request: &Request) -> Option<Response> {
+                $(
+                    let $var = match $ty::from_request(request) {
+                        Ok(value) => value,
+                        Err(err) => return Some(err.into_response()),
+                    };
+                )+
+                self($($var),+).into_handler_result()
+            }
+        }
+    };
+}
+
+impl_extractor_handler!(A a);
+impl_extractor_handler!(A a, B b);
+impl_extractor_handler!(A a, B b, C c);
+impl_extractor_handler!(A a, B b, C c, D d);
+
+/// Adapts a handler taking extractors into one [`App::add_endpoint`](crate::app::App::add_endpoint)
+/// accepts.
+///
+/// Endpoint handlers take the [`Request`] itself, which keeps closures such as `|_| "hi"`
+/// free of type annotations. Wrapping a handler lets it declare what it needs from the
+/// request instead; every argument is extracted in order and the first failure answers
+/// the request.
+///
+/// # Arguments
+///
+/// * `handler` - A function or closure taking one to four [`FromRequest`] arguments.
+///
+/// # Returns
+///
+/// * `impl Fn(Request) -> Option<Response>` - The handler to register.
+///
+/// # Examples
+///
+/// ```
+/// use rustic::app::App;
+/// use rustic::extract::{with_extractors, Body, Headers};
+///
+/// fn echo(headers: Headers, Body(body): Body) -> String {
+///     format!("{} ({} bytes)", body, headers.get("Content-Length").unwrap_or("0"))
+/// }
+///
+/// let mut application = App::new();
+/// application.post("echo", with_extractors(echo));
+/// ```
+pub fn with_extractors<Args: 'static, H: ExtractorHandler<Args>>(
+    handler: H,
+) -> impl Fn(Request) -> Option<Response> + Send + Sync + 'static {
+    move |mut request| {
+        // Extractors read the body from `Request::body`, so a streamed one is buffered.
+        if let Err(err) = request.buffer_body() {
+            return Some(HttpError::bad_request(format!("Bad Request: {}", err)).into_response());
+        }
+        handler.call(&request)
+    }
+}
+
+/// An error deserializing query or path parameters.
+///
+/// The message names the offending field where there is one, so handlers can echo it
+/// back to the client.
+#[cfg(feature = "serde")]
+#[derive(Debug, Clone, PartialEq)]
+pub struct QueryError {
+    field: Option<String>,
+    message: String,
+}
+
+#[cfg(feature = "serde")]
+impl QueryError {
+    /// Returns the name of the field that failed to deserialize, if known.
+    pub fn field(&self) -> Option<&str> {
+        self.field.as_deref()
+    }
+
+    /// Creates an error for a value of `field` that could not be deserialized.
+    fn in_field(field: &str, reason: impl fmt::Display) -> Self {
+        QueryError {
+            field: Some(field.to_string()),
+            message: format!("invalid value for field `{}`: {}", field, reason),
+        }
+    }
+
+    /// Creates an error for a value that does not parse as the field's type.
+    fn invalid(field: &str, text: &str, expected: &dyn serde::de::Expected) -> Self {
+        Self::in_field(
+            field,
+            format_args!("expected {}, found {:?}", expected, text),
+        )
+    }
+}
+
+#[cfg(feature = "serde")]
+impl fmt::Display for QueryError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.message)
+    }
+}
+
+#[cfg(feature = "serde")]
+impl std::error::Error for QueryError {}
+
+#[cfg(feature = "serde")]
+impl serde::de::Error for QueryError {
+    fn custom<T: fmt::Display>(message: T) -> Self {
+        QueryError {
+            field: None,
+            message: message.to_string(),
+        }
+    }
+
+    fn missing_field(field: &'static str) -> Self {
+        QueryError {
+            field: Some(field.to_string()),
+            message: format!("missing field `{}`", field),
+        }
+    }
+}
+
+#[cfg(feature = "serde")]
+impl From<QueryError> for HttpError {
+    fn from(error: QueryError) -> Self {
+        HttpError::bad_request(format!("Bad Request: {}", error))
+    }
+}
+
+#[cfg(feature = "serde")]
+impl Request {
+    /// Deserializes the URL query parameters into `T`.
+    ///
+    /// Values are parsed from their text form into the field types: numbers, `bool`s
+    /// from `true`/`false` or `1`/`0`, and enums from their variant names. Missing
+    /// `Option` fields become `None`, fields marked `#[serde(default)]` take their default,
+    /// and repeated keys fill `Vec` fields. The parameters are read from the request
+    /// target, so repeated keys are kept.
+    ///
+    /// # Returns
+    ///
+    /// * `Result<T, QueryError>` - The deserialized value, or an error naming the field
+    ///   that is missing or does not parse.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use rustic::app::App;
+    /// use rustic::http_error::HttpError;
+    /// use std::collections::HashMap;
+    /// let mut application = App::new();
+    /// application.get("search", |request| -> Result<String, HttpError> {
+    ///     let params: HashMap<String, Vec<String>> = request.query_as()?;
+    ///     Ok(format!("{} tags", params.get("tag").map_or(0, Vec::len)))
+    /// });
+    /// ```
+    pub fn query_as<T: serde::de::DeserializeOwned>(&self) -> Result<T, QueryError> {
+        params::from_pairs(query_pairs(&self.url))
+    }
+}
+
+/// Deserialization of string key/value pairs, such as query and path parameters.
+#[cfg(feature = "serde")]
+mod params {
+    use super::QueryError;
+    use serde::de::value::{MapDeserializer, SeqDeserializer};
+    use serde::de::{DeserializeOwned, Deserializer, IntoDeserializer, Visitor};
+    use std::collections::HashMap;
+
+    /// Deserializes key/value pairs into `T`, collecting the values of repeated keys.
+    pub(super) fn from_pairs<'a, T: DeserializeOwned>(
+        pairs: impl Iterator<Item = (&'a str, &'a str)>,
+    ) -> Result<T, QueryError> {
+        let mut fields: Vec<Values> = Vec::new();
+        for (key, text) in pairs {
+            match fields.iter_mut().find(|values| values.field == key) {
+                Some(values) => values.texts.push(text),
+                None => fields.push(Values {
+                    field: key,
+                    texts: vec![text],
+                }),
+            }
+        }
+        let entries = fields.into_iter().map(|values| (values.field, values));
+        T::deserialize(MapDeserializer::new(entries))
+    }
+
+    /// Deserializes path parameters into `T`, which may be a plain value when exactly one
+    /// parameter was captured.
+    pub(super) fn from_path_params<T: DeserializeOwned>(
+        params: &HashMap<String, String>,
+    ) -> Result<T, QueryError> {
+        T::deserialize(PathParams(params))
+    }
+
+    /// A single parameter value, deserialized by parsing its text as the requested type.
+    struct Value<'a> {
+        field: &'a str,
+        text: &'a str,
+    }
+
+    impl<'de, 'a> IntoDeserializer<'de, QueryError> for Value<'a> {
+        type Deserializer = Self;
+
+        fn into_deserializer(self) -> Self {
+            self
+        }
+    }
+
+    macro_rules! parse_value {
+        ($($method:ident => $visit:ident),+ $(,)?) => {
+            $(
+                fn $method<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, QueryError> {
+                    match self.text.parse() {
+                        Ok(value) => visitor.$visit(value),
+                        Err(_) => Err(QueryError::invalid(self.field, self.text, &visitor)),
+                    }
+                }
+            )+
+        };
+    }
+
+    impl<'de, 'a> Deserializer<'de> for Value<'a> {
+        type Error = QueryError;
+
+        fn deserialize_any<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, QueryError> {
+            visitor.visit_str(self.text)
+        }
+
+        fn deserialize_bool<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, QueryError> {
+            match self.text {
+                "true" | "1" => visitor.visit_bool(true),
+                "false" | "0" => visitor.visit_bool(false),
+                _ => Err(QueryError::invalid(self.field, self.text, &visitor)),
+            }
+        }
+
+        parse_value! {
+            deserialize_i8 => visit_i8,
+            deserialize_i16 => visit_i16,
+            deserialize_i32 => visit_i32,
+            deserialize_i64 => visit_i64,
+            deserialize_u8 => visit_u8,
+            deserialize_u16 => visit_u16,
+            deserialize_u32 => visit_u32,
+            deserialize_u64 => visit_u64,
+            deserialize_f32 => visit_f32,
+            deserialize_f64 => visit_f64,
+        }
+
+        fn deserialize_option<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, QueryError> {
+            visitor.visit_some(self)
+        }
+
+        fn deserialize_newtype_struct<V: Visitor<'de>>(
+            self,
+            _name: &'static str,
+            visitor: V,
+        ) -> Result<V::Value, QueryError> {
+            visitor.visit_newtype_struct(self)
+        }
+
+        fn deserialize_seq<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, QueryError> {
+            Values {
+                field: self.field,
+                texts: vec![self.text],
+            }
+            .deserialize_seq(visitor)
+        }
+
+        fn deserialize_enum<V: Visitor<'de>>(
+            self,
+            _name: &'static str,
+            _variants: &'static [&'static str],
+            visitor: V,
+        ) -> Result<V::Value, QueryError> {
+            visitor
+                .visit_enum(self.text.into_deserializer())
+                .map_err(|err: QueryError| QueryError::in_field(self.field, err))
+        }
+
+        serde::forward_to_deserialize_any! {
+            char str string bytes byte_buf unit unit_struct tuple tuple_struct map struct
+            identifier ignored_any
+        }
+    }
+
+    /// Every value sent for one key. Sequences take all of them; anything else takes the
+    /// last one, matching [`Request::query_param`].
+    struct Values<'a> {
+        field: &'a str,
+        texts: Vec<&'a str>,
+    }
+
+    impl<'a> Values<'a> {
+        /// Returns the last value sent for the key.
+        fn last(&self) -> Result<Value<'a>, QueryError> {
+            match self.texts.last() {
+                Some(text) => Ok(Value {
+                    field: self.field,
+                    text,
+                }),
+                None => Err(QueryError::in_field(self.field, "no value")),
+            }
+        }
+    }
+
+    impl<'de, 'a> IntoDeserializer<'de, QueryError> for Values<'a> {
+        type Deserializer = Self;
+
+        fn into_deserializer(self) -> Self {
+            self
+        }
+    }
+
+    macro_rules! forward_to_value {
+        ($target:ident; $($method:ident)+) => {
+            $(
+                fn $method<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, QueryError> {
+                    self.$target()?.$method(visitor)
+                }
+            )+
+        };
+    }
+
+    impl<'de, 'a> Deserializer<'de> for Values<'a> {
+        type Error = QueryError;
+
+        forward_to_value! { last;
+            deserialize_any deserialize_bool deserialize_i8 deserialize_i16 deserialize_i32
+            deserialize_i64 deserialize_u8 deserialize_u16 deserialize_u32 deserialize_u64
+            deserialize_f32 deserialize_f64 deserialize_char deserialize_str
+            deserialize_string deserialize_bytes deserialize_byte_buf deserialize_unit
+            deserialize_map deserialize_identifier deserialize_ignored_any
+        }
+
+        fn deserialize_option<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, QueryError> {
+            visitor.visit_some(self)
+        }
+
+        fn deserialize_seq<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, QueryError> {
+            let field = self.field;
+            let items = self.texts.into_iter().map(|text| Value { field, text });
+            let mut seq = SeqDeserializer::new(items);
+            let value = visitor.visit_seq(&mut seq)?;
+            seq.end()?;
+            Ok(value)
+        }
+
+        fn deserialize_tuple<V: Visitor<'de>>(
+            self,
+            _len: usize,
+            visitor: V,
+        ) -> Result<V::Value, QueryError> {
+            self.deserialize_seq(visitor)
+        }
+
+        fn deserialize_tuple_struct<V: Visitor<'de>>(
+            self,
+            _name: &'static str,
+            _len: usize,
+            visitor: V,
+        ) -> Result<V::Value, QueryError> {
+            self.deserialize_seq(visitor)
+        }
+
+        fn deserialize_unit_struct<V: Visitor<'de>>(
+            self,
+            name: &'static str,
+            visitor: V,
+        ) -> Result<V::Value, QueryError> {
+            self.last()?.deserialize_unit_struct(name, visitor)
+        }
+
+        fn deserialize_newtype_struct<V: Visitor<'de>>(
+            self,
+            _name: &'static str,
+            visitor: V,
+        ) -> Result<V::Value, QueryError> {
+            visitor.visit_newtype_struct(self)
+        }
+
+        fn deserialize_struct<V: Visitor<'de>>(
+            self,
+            name: &'static str,
+            fields: &'static [&'static str],
+            visitor: V,
+        ) -> Result<V::Value, QueryError> {
+            self.last()?.deserialize_struct(name, fields, visitor)
+        }
+
+        fn deserialize_enum<V: Visitor<'de>>(
+            self,
+            name: &'static str,
+            variants: &'static [&'static str],
+            visitor: V,
+        ) -> Result<V::Value, QueryError> {
+            self.last()?.deserialize_enum(name, variants, visitor)
+        }
+    }
+
+    /// Path parameters, deserialized as a map or, for a single parameter, as its value.
+    struct PathParams<'a>(&'a HashMap<String, String>);
+
+    impl<'a> PathParams<'a> {
+        /// Returns the only parameter value.
+        fn single(&self) -> Result<Value<'a>, QueryError> {
+            match self.0.iter().next() {
+                Some((field, text)) if self.0.len() == 1 => Ok(Value { field, text }),
+                _ => Err(serde::de::Error::invalid_length(
+                    self.0.len(),
+                    &"one path parameter",
+                )),
+            }
+        }
+    }
+
+    impl<'de, 'a> Deserializer<'de> for PathParams<'a> {
+        type Error = QueryError;
+
+        fn deserialize_any<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, QueryError> {
+            let entries = self.0.iter().map(|(field, text)| {
+                let value = Value { field, text };
+                (field.as_str(), value)
+            });
+            MapDeserializer::new(entries).deserialize_any(visitor)
+        }
+
+        forward_to_value! { single;
+            deserialize_bool deserialize_i8 deserialize_i16 deserialize_i32 deserialize_i64
+            deserialize_u8 deserialize_u16 deserialize_u32 deserialize_u64 deserialize_f32
+            deserialize_f64 deserialize_char deserialize_str deserialize_string
+        }
+
+        fn deserialize_newtype_struct<V: Visitor<'de>>(
+            self,
+            name: &'static str,
+            visitor: V,
+        ) -> Result<V::Value, QueryError> {
+            self.single()?.deserialize_newtype_struct(name, visitor)
+        }
+
+        fn deserialize_enum<V: Visitor<'de>>(
+            self,
+            name: &'static str,
+            variants: &'static [&'static str],
+            visitor: V,
+        ) -> Result<V::Value, QueryError> {
+            self.single()?.deserialize_enum(name, variants, visitor)
+        }
+
+        serde::forward_to_deserialize_any! {
+            bytes byte_buf option unit unit_struct seq tuple tuple_struct map struct
+            identifier ignored_any
+        }
+    }
+}
+
+#[cfg(test)]
+mod test_extract {
+    use super::*;
+    use crate::parse_headers::RequestType;
+
+    /// Builds a request with the given headers and body.
+    fn request(headers: &[(&str, &str)], body: &str) -> Request {
+        Request {
+            method: RequestType::POST,
+            path: "items".to_string(),
+            url: "/items".to_string(),
+            headers: headers
+                .iter()
+                .map(|(name, value)| (name.to_string(), value.to_string()))
+                .collect(),
+            body: body.to_string(),
+            ..Request::default()
+        }
+    }
+
+    /// Tests calling handlers of one and two extractor arguments.
+    #[test]
+    fn test_extractor_handlers() {
+        let handler = with_extractors(|Body(body): Body| body.to_uppercase());
+        let response = handler(request(&[], "shout")).unwrap();
+        assert_eq!(response.response_body.unwrap().as_bytes(), b"SHOUT");
+
+        let handler = with_extractors(|headers: Headers, Body(body): Body| {
+            format!("{}:{}", headers.get("x-tag").unwrap_or("none"), body)
+        });
+        let response = handler(request(&[("X-Tag", "a")], "b")).unwrap();
+        assert_eq!(response.response_body.unwrap().as_bytes(), b"a:b");
+    }
+
+    /// Tests that a failing extractor answers the request without calling the handler.
+    #[cfg(feature = "serde")]
+    #[test]
+    fn test_extraction_failure() {
+        let handler = with_extractors(|Json(values): Json<Vec<u32>>| -> String {
+            panic!("called with {:?}", values)
+        });
+        let response = handler(request(&[("Content-Type", "text/plain")], "[1]")).unwrap();
+        assert_eq!(response.status_code, 415);
+
+        let response = handler(request(&[("Content-Type", "application/json")], "[x")).unwrap();
+        assert_eq!(response.status_code, 400);
+    }
+
+    /// Tests extracting query parameters into typed values.
+    #[cfg(feature = "serde")]
+    #[test]
+    fn test_query() {
+        let mut request = request(&[], "");
+        request.url = "/items?page=3".to_string();
+        let Query(params) = Query::<HashMap<String, u32>>::from_request(&request).unwrap();
+        assert_eq!(params["page"], 3);
+
+        request.url = "/items?page=three".to_string();
+        let err = Query::<HashMap<String, u32>>::from_request(&request).unwrap_err();
+        assert_eq!(err.status, 400);
+    }
+
+    #[cfg(feature = "serde")]
+    #[derive(serde::Deserialize, Debug, PartialEq)]
+    struct Pagination {
+        page: u32,
+        per_page: Option<u32>,
+        #[serde(default)]
+        tags: Vec<String>,
+        #[serde(default)]
+        archived: bool,
+    }
+
+    /// Builds a request for `/items` with the given query string.
+    #[cfg(feature = "serde")]
+    fn with_query(query: &str) -> Request {
+        let mut request = request(&[], "");
+        request.url = format!("/items?{}", query);
+        request
+    }
+
+    /// Tests deserializing a pagination struct with optional, defaulted and repeated fields.
+    #[cfg(feature = "serde")]
+    #[test]
+    fn test_query_as() {
+        let request = with_query("page=2&tags=rust&per_page=50&tags=http&archived=1");
+        assert_eq!(
+            request.query_as::<Pagination>().unwrap(),
+            Pagination {
+                page: 2,
+                per_page: Some(50),
+                tags: vec!["rust".to_string(), "http".to_string()],
+                archived: true,
+            }
+        );
+
+        let request = with_query("page=1&tags=solo");
+        let pagination: Pagination = request.query_as().unwrap();
+        assert_eq!(pagination.per_page, None);
+        assert_eq!(pagination.tags, vec!["solo".to_string()]);
+        assert!(!pagination.archived);
+    }
+
+    /// Tests that deserialization errors name the offending field.
+    #[cfg(feature = "serde")]
+    #[test]
+    fn test_query_as_errors() {
+        let err = with_query("per_page=5")
+            .query_as::<Pagination>()
+            .unwrap_err();
+        assert_eq!(err.field(), Some("page"));
+        assert_eq!(err.to_string(), "missing field `page`");
+
+        let err = with_query("page=1&per_page=many")
+            .query_as::<Pagination>()
+            .unwrap_err();
+        assert_eq!(err.field(), Some("per_page"));
+        assert!(err.to_string().contains("`per_page`"), "{}", err);
+
+        let err = with_query("page=1&archived=maybe")
+            .query_as::<Pagination>()
+            .unwrap_err();
+        assert_eq!(err.field(), Some("archived"));
+    }
+}
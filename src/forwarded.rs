@@ -0,0 +1,663 @@
+use crate::app::Request;
+use crate::http11_response::Response;
+use crate::middleware::{Middleware, Next};
+use std::fmt;
+use std::net::{IpAddr, SocketAddr};
+use std::str::FromStr;
+
+/// A block of IP addresses in CIDR notation, such as `10.0.0.0/8` or `fd00::/8`.
+///
+/// A bare address is a block of one. IPv4-mapped IPv6 addresses are matched as the IPv4
+/// address they map.
+///
+/// # Examples
+///
+/// ```
+/// use rustic::forwarded::IpRange;
+/// let range: IpRange = "192.168.0.0/16".parse().unwrap();
+/// assert!(range.contains("192.168.4.2".parse().unwrap()));
+/// assert!(!range.contains("10.0.0.1".parse().unwrap()));
+/// assert!("10.0.0.0/33".parse::<IpRange>().is_err());
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct IpRange {
+    network: IpAddr,
+    prefix_len: u8,
+}
+
+impl IpRange {
+    /// Checks whether an address lies in the block.
+    pub fn contains(&self, address: IpAddr) -> bool {
+        match (self.network, address.to_canonical()) {
+            (IpAddr::V4(network), IpAddr::V4(address)) => {
+                let mask = u32::MAX
+                    .checked_shl(32 - self.prefix_len as u32)
+                    .unwrap_or(0);
+                u32::from(network) & mask == u32::from(address) & mask
+            }
+            (IpAddr::V6(network), IpAddr::V6(address)) => {
+                let mask = u128::MAX
+                    .checked_shl(128 - self.prefix_len as u32)
+                    .unwrap_or(0);
+                u128::from(network) & mask == u128::from(address) & mask
+            }
+            _ => false,
+        }
+    }
+}
+
+impl From<IpAddr> for IpRange {
+    fn from(address: IpAddr) -> Self {
+        let address = address.to_canonical();
+        let prefix_len = if address.is_ipv4() { 32 } else { 128 };
+        IpRange {
+            network: address,
+            prefix_len,
+        }
+    }
+}
+
+/// The error returned when an [`IpRange`] cannot be parsed.
+#[derive(Debug, Clone, PartialEq)]
+pub struct InvalidIpRange(String);
+
+impl fmt::Display for InvalidIpRange {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "invalid IP range: {}", self.0)
+    }
+}
+
+impl std::error::Error for InvalidIpRange {}
+
+impl FromStr for IpRange {
+    type Err = InvalidIpRange;
+
+    fn from_str(range: &str) -> Result<Self, Self::Err> {
+        let invalid = || InvalidIpRange(range.to_string());
+        let (address, prefix_len) = match range.split_once('/') {
+            Some((address, prefix_len)) => (address, Some(prefix_len)),
+            None => (range, None),
+        };
+        let address: IpAddr = address.parse().map_err(|_| invalid())?;
+        let mut parsed = IpRange::from(address);
+        if let Some(prefix_len) = prefix_len {
+            let prefix_len: u8 = prefix_len.parse().map_err(|_| invalid())?;
+            // A mapped IPv6 block shrinks to the IPv4 block it covers.
+            let mapped_bits = if address.is_ipv6() && parsed.network.is_ipv4() {
+                96
+            } else {
+                0
+            };
+            if prefix_len > parsed.prefix_len + mapped_bits || prefix_len < mapped_bits {
+                return Err(invalid());
+            }
+            parsed.prefix_len = prefix_len - mapped_bits;
+        }
+        Ok(parsed)
+    }
+}
+
+/// What the proxies in front of the server reported about a request.
+#[derive(Debug, Clone, PartialEq)]
+struct ForwardedInfo {
+    client: IpAddr,
+    scheme: Option<String>,
+    host: Option<String>,
+}
+
+impl Request {
+    /// Returns the address of the client that sent the request.
+    ///
+    /// Behind [`TrustedProxies`], this is the nearest address in the forwarding chain that
+    /// is not a trusted proxy. Otherwise it is the address of the peer.
+    pub fn client_addr(&self) -> Option<IpAddr> {
+        match self.extensions.get::<ForwardedInfo>() {
+            Some(info) => Some(info.client),
+            None => self.remote_addr.map(|address| address.ip()),
+        }
+    }
+
+    /// Returns the scheme the client used, `"https"` or `"http"`.
+    ///
+    /// The server itself only speaks plain HTTP, so this is `"http"` unless a trusted
+    /// proxy reported otherwise, such as one terminating TLS in front of it.
+    pub fn scheme(&self) -> &str {
+        self.extensions
+            .get::<ForwardedInfo>()
+            .and_then(|info| info.scheme.as_deref())
+            .unwrap_or("http")
+    }
+
+    /// Returns the `Host` the client asked a trusted proxy for, if the proxy reported it.
+    pub fn forwarded_host(&self) -> Option<&str> {
+        self.extensions
+            .get::<ForwardedInfo>()
+            .and_then(|info| info.host.as_deref())
+    }
+    /// Returns the host, with any port, that the client sent the request to.
+    ///
+    /// This is the first valid one of:
+    ///
+    /// 1. the host reported by a trusted proxy, see [`Request::forwarded_host`];
+    /// 2. the authority of a request target in absolute form, such as
+    ///    `GET http://example.com/ HTTP/1.1`, which takes precedence over `Host`;
+    /// 3. the `Host` header;
+    /// 4. the address the server accepted the connection on, for clients that send no
+    ///    `Host`, as HTTP/1.0 ones may.
+    ///
+    /// Values that are not a host name or IP address with an optional port are skipped,
+    /// so that they cannot smuggle a path or credentials into links built from them.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use rustic::app::App;
+    /// use rustic::test::TestClient;
+    ///
+    /// let mut application = App::new();
+    /// application.get("where", |request| request.host().unwrap_or("-").to_string());
+    /// let client = TestClient::new(application);
+    /// let response = client.get("/where").header("Host", "example.com:8080").send();
+    /// assert_eq!(response.text(), "example.com:8080");
+    /// ```
+    pub fn host(&self) -> Option<&str> {
+        let absolute = self
+            .url
+            .split_once("://")
+            .filter(|(scheme, _)| !scheme.contains('/'))
+            .map(|(_, rest)| rest.split(['/', '?']).next().unwrap_or(rest));
+        let local = self
+            .extensions
+            .get::<LocalHost>()
+            .map(|local| local.0.as_str());
+        [self.forwarded_host(), absolute, self.header("Host"), local]
+            .into_iter()
+            .flatten()
+            .find(|host| is_valid_host(host))
+    }
+
+    /// Reconstructs the absolute URL the client requested, `scheme://host/path?query`,
+    /// for links that must be absolute, such as `Location` headers or OAuth redirect
+    /// URIs.
+    ///
+    /// The scheme and host are those of [`Request::scheme`] and [`Request::host`], so
+    /// behind [`TrustedProxies`] they are the ones the client used rather than those the
+    /// proxy connected with. The path and query are the request target's as sent, still
+    /// percent-encoded. A request with no known host, which only happens to one built by
+    /// hand, gets `localhost`.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use rustic::app::App;
+    /// use rustic::test::TestClient;
+    ///
+    /// let mut application = App::new();
+    /// application.get("items", |request| {
+    ///     format!("Next page: {}", request.full_url().replace("page=1", "page=2"))
+    /// });
+    /// let client = TestClient::new(application);
+    /// let response = client.get("/items?page=1").header("Host", "shop.example").send();
+    /// assert_eq!(response.text(), "Next page: http://shop.example/items?page=2");
+    /// ```
+    pub fn full_url(&self) -> String {
+        let target = match self.url.split_once("://") {
+            Some((scheme, rest)) if !scheme.contains('/') => {
+                rest.find(['/', '?']).map_or("/", |start| &rest[start..])
+            }
+            _ => self.url.as_str(),
+        };
+        let separator = if target.starts_with('/') { "" } else { "/" };
+        format!(
+            "{}://{}{}{}",
+            self.scheme(),
+            self.host().unwrap_or("localhost"),
+            separator,
+            target
+        )
+    }
+}
+
+/// The address a connection was accepted on, formatted as a host, for requests sent
+/// without a `Host` header.
+pub(crate) struct LocalHost(pub(crate) String);
+
+/// Checks that a host is a name or an IP address, optionally followed by a port, as it
+/// may appear in a URL authority.
+fn is_valid_host(host: &str) -> bool {
+    let (name, port) = match host.strip_prefix('[') {
+        Some(bracketed) => match bracketed.split_once(']') {
+            Some((address, rest)) if address.parse::<std::net::Ipv6Addr>().is_ok() => {
+                match rest.strip_prefix(':') {
+                    Some(port) => ("v6", Some(port)),
+                    None if rest.is_empty() => ("v6", None),
+                    None => return false,
+                }
+            }
+            _ => return false,
+        },
+        None => match host.rsplit_once(':') {
+            Some((name, port)) => (name, Some(port)),
+            None => (host, None),
+        },
+    };
+    !name.is_empty()
+        && name
+            .bytes()
+            .all(|byte| byte.is_ascii_alphanumeric() || matches!(byte, b'-' | b'.' | b'_'))
+        && port.is_none_or(|port| port.parse::<u16>().is_ok())
+}
+
+/// Middleware reading the client address, scheme and host reported by trusted proxies.
+///
+/// When the peer of a request lies in one of the trusted ranges, the `Forwarded` header
+/// (RFC 7239) or, without it, the `X-Forwarded-For`, `X-Forwarded-Proto` and
+/// `X-Forwarded-Host` headers are read. The forwarding chain is walked from the nearest
+/// hop, skipping trusted proxies, so that a client cannot forge entries past the first
+/// trusted proxy it reached. Requests from untrusted peers have these headers ignored.
+///
+/// The results are exposed through [`Request::client_addr`], [`Request::scheme`] and
+/// [`Request::forwarded_host`].
+///
+/// # Examples
+///
+/// ```
+/// use rustic::app::App;
+/// use rustic::forwarded::TrustedProxies;
+///
+/// let mut application = App::new();
+/// application.add_middleware(
+///     TrustedProxies::new()
+///         .trust("127.0.0.1".parse().unwrap())
+///         .trust("10.0.0.0/8".parse().unwrap()),
+/// );
+/// application.get("ip", |request| {
+///     format!("{:?} over {}", request.client_addr(), request.scheme())
+/// });
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct TrustedProxies {
+    ranges: Vec<IpRange>,
+}
+
+impl TrustedProxies {
+    /// Creates the middleware trusting no proxy.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Trusts the proxies in a range of addresses.
+    pub fn trust(mut self, range: IpRange) -> Self {
+        self.ranges.push(range);
+        self
+    }
+
+    /// Checks whether an address belongs to a trusted proxy.
+    pub fn is_trusted(&self, address: IpAddr) -> bool {
+        self.ranges.iter().any(|range| range.contains(address))
+    }
+
+    /// Works out what the proxies reported about a request, if its peer is trusted.
+    fn resolve(&self, request: &Request) -> Option<ForwardedInfo> {
+        let peer = request.remote_addr?.ip().to_canonical();
+        if !self.is_trusted(peer) {
+            return None;
+        }
+        let mut info = ForwardedInfo {
+            client: peer,
+            scheme: None,
+            host: None,
+        };
+        // Proxies may each add a line of a list header rather than extend the last one.
+        let headers = &request.headers;
+        if let Some(forwarded) = headers.get_joined("Forwarded") {
+            // Each element describes the request as one proxy received it, so the element
+            // naming the client also holds the scheme and host the client used.
+            for hop in parse_forwarded(&forwarded).into_iter().rev() {
+                let Some(address) = hop.address else {
+                    break;
+                };
+                info = ForwardedInfo {
+                    client: address,
+                    scheme: hop.scheme,
+                    host: hop.host,
+                };
+                if !self.is_trusted(address) {
+                    break;
+                }
+            }
+        } else {
+            let chain = headers.get_joined("X-Forwarded-For").unwrap_or_default();
+            for address in chain.rsplit(',').map(parse_node) {
+                let Some(address) = address else {
+                    break;
+                };
+                info.client = address;
+                if !self.is_trusted(address) {
+                    break;
+                }
+            }
+            info.scheme = last_item(headers.get_joined("X-Forwarded-Proto").as_deref());
+            info.host = last_item(headers.get_joined("X-Forwarded-Host").as_deref());
+        }
+        info.scheme = info
+            .scheme
+            .map(|scheme| scheme.to_ascii_lowercase())
+            .filter(|scheme| scheme == "http" || scheme == "https");
+        Some(info)
+    }
+}
+
+impl Middleware for TrustedProxies {
+    fn handle(&self, mut request: Request, next: Next) -> Response {
+        if let Some(info) = self.resolve(&request) {
+            request.extensions.insert(info);
+        }
+        next.run(request)
+    }
+}
+
+/// One element of a `Forwarded` header.
+#[derive(Debug, Default)]
+struct Hop {
+    /// The address the hop received the request from, if it is a usable IP address.
+    address: Option<IpAddr>,
+    scheme: Option<String>,
+    host: Option<String>,
+}
+
+/// Parses the elements of a `Forwarded` header, from the farthest hop to the nearest.
+fn parse_forwarded(header: &str) -> Vec<Hop> {
+    header
+        .split(',')
+        .map(|element| {
+            let mut hop = Hop::default();
+            for pair in element.split(';') {
+                let Some((key, value)) = pair.split_once('=') else {
+                    continue;
+                };
+                let value = value.trim().trim_matches('"');
+                match key.trim().to_ascii_lowercase().as_str() {
+                    "for" => hop.address = parse_node(value),
+                    "proto" => hop.scheme = Some(value.to_string()),
+                    "host" => hop.host = Some(value.to_string()),
+                    _ => {}
+                }
+            }
+            hop
+        })
+        .collect()
+}
+
+/// Parses a node of a forwarding chain: an IP address, optionally with a port and, for
+/// IPv6, in brackets. Obfuscated identifiers and `unknown` yield `None`.
+fn parse_node(node: &str) -> Option<IpAddr> {
+    let node = node.trim().trim_matches('"');
+    let address = match node.parse::<SocketAddr>() {
+        Ok(address) => address.ip(),
+        Err(_) => {
+            let unbracketed = node
+                .strip_prefix('[')
+                .and_then(|node| node.strip_suffix(']'));
+            unbracketed.unwrap_or(node).parse().ok()?
+        }
+    };
+    Some(address.to_canonical())
+}
+
+/// Returns the last item of a comma-separated header, the one set by the nearest proxy.
+fn last_item(header: Option<&str>) -> Option<String> {
+    header?
+        .rsplit(',')
+        .map(str::trim)
+        .find(|item| !item.is_empty())
+        .map(str::to_string)
+}
+
+#[cfg(test)]
+mod test_forwarded {
+    use super::*;
+    use crate::extensions::Extensions;
+    use crate::parse_headers::RequestType;
+
+    /// Builds a request from a peer with the given headers, resolved by `proxies`.
+    fn resolve(proxies: &TrustedProxies, peer: &str, headers: &[(&str, &str)]) -> Request {
+        let mut request = Request {
+            method: RequestType::GET,
+            path: String::new(),
+            url: "/".to_string(),
+            headers: headers
+                .iter()
+                .map(|(name, value)| (name.to_string(), value.to_string()))
+                .collect(),
+            body: String::new(),
+            remote_addr: Some(peer.parse().unwrap()),
+            ..Request::default()
+        };
+        if let Some(info) = proxies.resolve(&request) {
+            request.extensions.insert(info);
+        }
+        request
+    }
+
+    fn proxies() -> TrustedProxies {
+        TrustedProxies::new()
+            .trust("10.0.0.0/8".parse().unwrap())
+            .trust("fd00::/8".parse().unwrap())
+    }
+
+    fn ip(address: &str) -> Option<IpAddr> {
+        Some(address.parse().unwrap())
+    }
+
+    /// Tests parsing and matching IPv4, IPv6 and mapped ranges.
+    #[test]
+    fn test_ip_range() {
+        let range: IpRange = "10.0.0.0/8".parse().unwrap();
+        assert!(range.contains("10.255.0.1".parse().unwrap()));
+        assert!(range.contains("::ffff:10.1.2.3".parse().unwrap()));
+        assert!(!range.contains("11.0.0.1".parse().unwrap()));
+        let mapped: IpRange = "::ffff:10.0.0.0/104".parse().unwrap();
+        assert_eq!(mapped, range);
+        let single: IpRange = "2001:db8::1".parse().unwrap();
+        assert!(single.contains("2001:db8::1".parse().unwrap()));
+        assert!(!single.contains("2001:db8::2".parse().unwrap()));
+        let everything: IpRange = "0.0.0.0/0".parse().unwrap();
+        assert!(everything.contains("203.0.113.9".parse().unwrap()));
+        for invalid in ["10.0.0.0/33", "fd00::/129", "10.0.0/8", "10.0.0.0/x", ""] {
+            assert!(invalid.parse::<IpRange>().is_err(), "{}", invalid);
+        }
+    }
+
+    /// Tests that a chain of trusted hops is skipped to find the client.
+    #[test]
+    fn test_multi_hop_chain() {
+        let request = resolve(
+            &proxies(),
+            "10.0.0.2:5000",
+            &[
+                ("X-Forwarded-For", "203.0.113.7, 198.51.100.4, 10.0.0.9"),
+                ("X-Forwarded-Proto", "http, HTTPS"),
+                ("X-Forwarded-Host", "example.com"),
+            ],
+        );
+        assert_eq!(request.client_addr(), ip("198.51.100.4"));
+        assert_eq!(request.scheme(), "https");
+        assert_eq!(request.forwarded_host(), Some("example.com"));
+
+        let all_trusted = resolve(
+            &proxies(),
+            "10.0.0.2:5000",
+            &[("X-Forwarded-For", "10.0.0.5, [fd00::1]:443")],
+        );
+        assert_eq!(all_trusted.client_addr(), ip("10.0.0.5"));
+        assert_eq!(all_trusted.scheme(), "http");
+    }
+
+    /// Tests that untrusted peers cannot set the client address, scheme or host.
+    #[test]
+    fn test_spoofing_untrusted_peer() {
+        let headers = [
+            ("X-Forwarded-For", "1.2.3.4"),
+            ("X-Forwarded-Proto", "https"),
+            ("X-Forwarded-Host", "evil.example"),
+            ("Forwarded", "for=1.2.3.4;proto=https"),
+        ];
+        let request = resolve(&proxies(), "203.0.113.50:7000", &headers);
+        assert_eq!(request.client_addr(), ip("203.0.113.50"));
+        assert_eq!(request.scheme(), "http");
+        assert_eq!(request.forwarded_host(), None);
+    }
+
+    /// Tests that entries a client prepends in front of a trusted proxy are ignored.
+    #[test]
+    fn test_spoofing_through_trusted_proxy() {
+        let request = resolve(
+            &proxies(),
+            "10.0.0.2:5000",
+            &[("X-Forwarded-For", "127.0.0.1, 10.0.0.1, 198.51.100.4")],
+        );
+        assert_eq!(request.client_addr(), ip("198.51.100.4"));
+
+        let garbage = resolve(
+            &proxies(),
+            "10.0.0.2:5000",
+            &[("X-Forwarded-For", "1.1.1.1, not-an-ip, 10.0.0.3")],
+        );
+        assert_eq!(garbage.client_addr(), ip("10.0.0.3"));
+
+        // A proxy adding its own line instead of extending the client's is read as one
+        // chain in order, so the client's forged entry stays in front of it.
+        let split = resolve(
+            &proxies(),
+            "10.0.0.2:5000",
+            &[
+                ("X-Forwarded-For", "127.0.0.1"),
+                ("x-forwarded-for", "198.51.100.4, 10.0.0.1"),
+            ],
+        );
+        assert_eq!(split.client_addr(), ip("198.51.100.4"));
+        let bad_scheme = resolve(
+            &proxies(),
+            "10.0.0.2:5000",
+            &[("X-Forwarded-Proto", "gopher")],
+        );
+        assert_eq!(bad_scheme.scheme(), "http");
+    }
+
+    /// Tests the standardized `Forwarded` header, which takes precedence.
+    #[test]
+    fn test_forwarded_header() {
+        let request = resolve(
+            &proxies(),
+            "[fd00::2]:8080",
+            &[
+                (
+                    "Forwarded",
+                    "for=192.0.2.60;proto=http, for=\"[2001:db8:cafe::17]:4711\";proto=https;host=shop.example, for=10.0.0.4",
+                ),
+                ("X-Forwarded-For", "1.2.3.4"),
+            ],
+        );
+        assert_eq!(request.client_addr(), ip("2001:db8:cafe::17"));
+        assert_eq!(request.scheme(), "https");
+        assert_eq!(request.forwarded_host(), Some("shop.example"));
+
+        let hidden = resolve(
+            &proxies(),
+            "10.0.0.2:1",
+            &[("Forwarded", "for=_hidden, for=10.0.0.8")],
+        );
+        assert_eq!(hidden.client_addr(), ip("10.0.0.8"));
+    }
+
+    /// Tests that hosts and absolute URLs come from the client's request when it is
+    /// direct, and from a trusted proxy's headers when it is proxied.
+    #[test]
+    fn test_full_url() {
+        let mut direct = resolve(&proxies(), "192.0.2.1:1", &[("Host", "example.com:8080")]);
+        direct.url = "/search?q=rust%20lang".to_string();
+        assert_eq!(direct.host(), Some("example.com:8080"));
+        assert_eq!(
+            direct.full_url(),
+            "http://example.com:8080/search?q=rust%20lang"
+        );
+
+        // An untrusted peer cannot pick the host of links built for it.
+        let mut spoofed = resolve(
+            &proxies(),
+            "192.0.2.1:1",
+            &[
+                ("Host", "example.com"),
+                ("X-Forwarded-Host", "evil.example"),
+            ],
+        );
+        spoofed.url = "/".to_string();
+        assert_eq!(spoofed.full_url(), "http://example.com/");
+
+        let mut proxied = resolve(
+            &proxies(),
+            "10.0.0.2:1",
+            &[
+                ("Host", "backend:3000"),
+                ("X-Forwarded-Host", "shop.example"),
+                ("X-Forwarded-Proto", "https"),
+            ],
+        );
+        proxied.url = "/cart".to_string();
+        assert_eq!(proxied.full_url(), "https://shop.example/cart");
+
+        // A proxy terminating TLS with the standardized header.
+        let mut terminated = resolve(
+            &proxies(),
+            "10.0.0.2:1",
+            &[
+                ("Host", "backend:3000"),
+                (
+                    "Forwarded",
+                    "for=192.0.2.60;proto=https;host=\"[2001:db8::1]:8443\"",
+                ),
+            ],
+        );
+        terminated.url = "/login?next=%2F".to_string();
+        assert_eq!(
+            terminated.full_url(),
+            "https://[2001:db8::1]:8443/login?next=%2F"
+        );
+
+        let mut absolute = resolve(&proxies(), "192.0.2.1:1", &[("Host", "ignored.example")]);
+        absolute.url = "http://api.example/v1?x=1".to_string();
+        assert_eq!(absolute.host(), Some("api.example"));
+        assert_eq!(absolute.full_url(), "http://api.example/v1?x=1");
+        absolute.url = "http://api.example".to_string();
+        assert_eq!(absolute.full_url(), "http://api.example/");
+
+        let mut fallback = resolve(&proxies(), "192.0.2.1:1", &[("Host", "bad/host@x")]);
+        fallback
+            .extensions
+            .insert(LocalHost("127.0.0.1:8080".to_string()));
+        assert_eq!(fallback.host(), Some("127.0.0.1:8080"));
+        fallback.extensions = Extensions::new();
+        assert_eq!(fallback.host(), None);
+        assert_eq!(fallback.full_url(), "http://localhost/");
+    }
+
+    /// Tests which host values are accepted.
+    #[test]
+    fn test_is_valid_host() {
+        for host in [
+            "example.com",
+            "a-b.c:80",
+            "127.0.0.1:65535",
+            "[::1]",
+            "[::1]:8080",
+        ] {
+            assert!(is_valid_host(host), "{}", host);
+        }
+        for host in [
+            "", ":80", "a:b", "a:70000", "a/b", "u@h", "[::1", "[zz]", "[::1]x", "a b",
+        ] {
+            assert!(!is_valid_host(host), "{}", host);
+        }
+    }
+}
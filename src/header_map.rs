@@ -0,0 +1,508 @@
+use std::borrow::Cow;
+use std::collections::HashMap;
+
+/// Header names whose conventional casing is not one capital letter per word.
+const CANONICAL_EXCEPTIONS: &[&str] = &[
+    "Content-MD5",
+    "DNT",
+    "ETag",
+    "Expect-CT",
+    "Sec-WebSocket-Accept",
+    "Sec-WebSocket-Extensions",
+    "Sec-WebSocket-Key",
+    "Sec-WebSocket-Protocol",
+    "Sec-WebSocket-Version",
+    "SourceMap",
+    "TE",
+    "WWW-Authenticate",
+    "X-DNS-Prefetch-Control",
+    "X-UA-Compatible",
+    "X-XSS-Protection",
+];
+
+/// An insertion-ordered collection of HTTP headers that may repeat.
+///
+/// Names are compared ignoring ASCII case and are kept as they were first given, but
+/// are written out in their [canonical form](canonical_name), so that responses never
+/// mix `content-type` and `Content-Type`. Headers added with
+/// [`HeaderMap::insert_verbatim`] or [`HeaderMap::append_verbatim`] are written out
+/// exactly as given instead, and those [suppressed](HeaderMap::suppress) are not written
+/// out at all. Headers are serialized in the order they were added, so output is
+/// deterministic and repeated headers such as `Set-Cookie` or `Link` can each be sent on
+/// their own line.
+///
+/// # Examples
+///
+/// ```
+/// use rustic::header_map::HeaderMap;
+/// let mut headers = HeaderMap::new();
+/// headers.insert("Content-Type", "text/html");
+/// headers.append("Set-Cookie", "a=1");
+/// headers.append("set-cookie", "b=2");
+/// assert_eq!(headers.get("content-type"), Some("text/html"));
+/// assert_eq!(headers.get_all("Set-Cookie").collect::<Vec<_>>(), vec!["a=1", "b=2"]);
+/// headers.insert("Set-Cookie", "c=3");
+/// assert_eq!(headers.get_all("Set-Cookie").collect::<Vec<_>>(), vec!["c=3"]);
+/// ```
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct HeaderMap {
+    entries: Vec<Entry>,
+    /// The names never written out, set through [`HeaderMap::suppress`].
+    suppressed: Vec<String>,
+}
+
+/// One header value, with whether its name is written out exactly as given.
+#[derive(Debug, Clone, PartialEq)]
+struct Entry {
+    name: String,
+    value: String,
+    verbatim: bool,
+}
+
+impl HeaderMap {
+    /// Creates an empty header map.
+    pub fn new() -> Self {
+        HeaderMap {
+            entries: Vec::new(),
+            suppressed: Vec::new(),
+        }
+    }
+
+    /// Creates an empty header map with room for `capacity` header values.
+    pub fn with_capacity(capacity: usize) -> Self {
+        HeaderMap {
+            entries: Vec::with_capacity(capacity),
+            suppressed: Vec::new(),
+        }
+    }
+
+    /// Sets a header, replacing every value it already has.
+    ///
+    /// The header keeps the position of its first existing value, if any.
+    ///
+    /// # Arguments
+    ///
+    /// * `name` - The header name.
+    /// * `value` - The header value.
+    ///
+    /// # Returns
+    ///
+    /// * `Option<String>` - The first value the header had before.
+    pub fn insert(&mut self, name: impl Into<String>, value: impl Into<String>) -> Option<String> {
+        self.set(name.into(), value.into(), false)
+    }
+
+    /// Sets a header like [`HeaderMap::insert`], but writes its name out exactly as
+    /// given rather than in canonical form, for a client that demands unusual casing.
+    ///
+    /// # Arguments
+    ///
+    /// * `name` - The header name, as it is to be sent.
+    /// * `value` - The header value.
+    ///
+    /// # Returns
+    ///
+    /// * `Option<String>` - The first value the header had before.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use rustic::header_map::HeaderMap;
+    /// let mut headers = HeaderMap::new();
+    /// headers.insert("x-api-key", "a");
+    /// headers.insert_verbatim("x-legacy-TOKEN", "b");
+    /// let names: Vec<_> = headers.iter_canonical().map(|(name, _)| name).collect();
+    /// assert_eq!(names, ["X-Api-Key", "x-legacy-TOKEN"]);
+    /// ```
+    pub fn insert_verbatim(
+        &mut self,
+        name: impl Into<String>,
+        value: impl Into<String>,
+    ) -> Option<String> {
+        self.set(name.into(), value.into(), true)
+    }
+
+    /// Adds a value to a header, keeping the values it already has.
+    ///
+    /// # Arguments
+    ///
+    /// * `name` - The header name.
+    /// * `value` - The header value.
+    pub fn append(&mut self, name: impl Into<String>, value: impl Into<String>) {
+        self.entries.push(Entry {
+            name: name.into(),
+            value: value.into(),
+            verbatim: false,
+        });
+    }
+
+    /// Adds a value to a header like [`HeaderMap::append`], but writes its name out
+    /// exactly as given rather than in canonical form.
+    ///
+    /// # Arguments
+    ///
+    /// * `name` - The header name, as it is to be sent.
+    /// * `value` - The header value.
+    pub fn append_verbatim(&mut self, name: impl Into<String>, value: impl Into<String>) {
+        self.entries.push(Entry {
+            name: name.into(),
+            value: value.into(),
+            verbatim: true,
+        });
+    }
+
+    /// Returns the first value of a header.
+    pub fn get(&self, name: &str) -> Option<&str> {
+        self.position(name)
+            .map(|index| self.entries[index].value.as_str())
+    }
+
+    /// Returns every value of a header, in the order they were added.
+    pub fn get_all<'a>(&'a self, name: &'a str) -> impl Iterator<Item = &'a str> + 'a {
+        self.entries
+            .iter()
+            .filter(move |entry| entry.name.eq_ignore_ascii_case(name))
+            .map(|entry| entry.value.as_str())
+    }
+
+    /// Returns every value of a header joined with `", "`, as RFC 9110 reads a field
+    /// line repeated for a list-based header, such as `X-Forwarded-For` split across
+    /// lines by successive proxies.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use rustic::header_map::HeaderMap;
+    /// let mut headers = HeaderMap::new();
+    /// headers.append("X-Forwarded-For", "203.0.113.7");
+    /// headers.append("x-forwarded-for", "10.0.0.1, 10.0.0.2");
+    /// assert_eq!(
+    ///     headers.get_joined("X-Forwarded-For").as_deref(),
+    ///     Some("203.0.113.7, 10.0.0.1, 10.0.0.2")
+    /// );
+    /// assert_eq!(headers.get_joined("Via"), None);
+    /// ```
+    pub fn get_joined(&self, name: &str) -> Option<Cow<'_, str>> {
+        let mut values = self
+            .entries
+            .iter()
+            .filter(|entry| entry.name.eq_ignore_ascii_case(name))
+            .map(|entry| entry.value.as_str());
+        let first = values.next()?;
+        let Some(second) = values.next() else {
+            return Some(Cow::Borrowed(first));
+        };
+        let mut joined = format!("{}, {}", first, second);
+        for value in values {
+            joined.push_str(", ");
+            joined.push_str(value);
+        }
+        Some(Cow::Owned(joined))
+    }
+
+    /// Returns whether the header is set.
+    pub fn contains_key(&self, name: &str) -> bool {
+        self.position(name).is_some()
+    }
+
+    /// Removes every value of a header.
+    ///
+    /// # Returns
+    ///
+    /// * `Option<String>` - The first value the header had.
+    pub fn remove(&mut self, name: &str) -> Option<String> {
+        let index = self.position(name)?;
+        let first = self.entries.remove(index).value;
+        self.entries
+            .retain(|entry| !entry.name.eq_ignore_ascii_case(name));
+        Some(first)
+    }
+
+    /// Removes every value of a header and keeps it out of the serialized message, even
+    /// when it is set again or is one the framework adds by default, such as `Date`.
+    ///
+    /// `Content-Length` and `Transfer-Encoding` frame the body, so they cannot be
+    /// suppressed and are left as they are.
+    ///
+    /// # Arguments
+    ///
+    /// * `name` - The header name.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use rustic::header_map::HeaderMap;
+    /// use rustic::http11_response::write_header;
+    /// let mut headers = HeaderMap::new();
+    /// headers.suppress("Date");
+    /// assert_eq!(write_header(&mut headers, None), "Content-Length: 0\r\n\r\n");
+    /// ```
+    pub fn suppress(&mut self, name: &str) {
+        if name.eq_ignore_ascii_case("Content-Length")
+            || name.eq_ignore_ascii_case("Transfer-Encoding")
+        {
+            return;
+        }
+        self.remove(name);
+        if !self.is_suppressed(name) {
+            self.suppressed.push(name.to_string());
+        }
+    }
+
+    /// Returns whether a header was suppressed with [`HeaderMap::suppress`].
+    pub fn is_suppressed(&self, name: &str) -> bool {
+        self.suppressed
+            .iter()
+            .any(|suppressed| suppressed.eq_ignore_ascii_case(name))
+    }
+
+    /// Keeps only the headers for which `keep` returns `true`.
+    pub fn retain(&mut self, mut keep: impl FnMut(&str, &str) -> bool) {
+        self.entries.retain(|entry| keep(&entry.name, &entry.value));
+    }
+
+    /// Iterates over every header value, in the order they were added, with names as
+    /// they were given.
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.entries
+            .iter()
+            .map(|entry| (entry.name.as_str(), entry.value.as_str()))
+    }
+
+    /// Iterates over the header values written out, in the order they were added, with
+    /// names in [canonical form](canonical_name) except for those added verbatim.
+    /// Suppressed headers are left out.
+    pub fn iter_canonical(&self) -> impl Iterator<Item = (Cow<'_, str>, &str)> {
+        self.entries
+            .iter()
+            .filter(|entry| !self.is_suppressed(&entry.name))
+            .map(|entry| {
+                let name = if entry.verbatim {
+                    Cow::Borrowed(entry.name.as_str())
+                } else {
+                    canonical_name(&entry.name)
+                };
+                (name, entry.value.as_str())
+            })
+    }
+
+    /// Returns the number of header values.
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    /// Returns whether there are no headers.
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Replaces every value of a header, for [`HeaderMap::insert`] and
+    /// [`HeaderMap::insert_verbatim`].
+    fn set(&mut self, name: String, value: String, verbatim: bool) -> Option<String> {
+        match self.position(&name) {
+            Some(index) => {
+                let entry = &mut self.entries[index];
+                let previous = std::mem::replace(&mut entry.value, value);
+                if verbatim {
+                    entry.name.clone_from(&name);
+                    entry.verbatim = true;
+                }
+                let rest = self.entries.split_off(index + 1);
+                self.entries.extend(
+                    rest.into_iter()
+                        .filter(|entry| !entry.name.eq_ignore_ascii_case(&name)),
+                );
+                Some(previous)
+            }
+            None => {
+                self.entries.push(Entry {
+                    name,
+                    value,
+                    verbatim,
+                });
+                None
+            }
+        }
+    }
+
+    /// Finds the index of the first value of a header.
+    fn position(&self, name: &str) -> Option<usize> {
+        self.entries
+            .iter()
+            .position(|entry| entry.name.eq_ignore_ascii_case(name))
+    }
+}
+
+/// Returns the conventional casing of a header name, as headers are written out.
+///
+/// Each dash-separated word starts with a capital letter and continues in lower case,
+/// except for names conventionally cased otherwise, such as `ETag` and
+/// `WWW-Authenticate`. Names already in canonical form are borrowed.
+///
+/// # Arguments
+///
+/// * `name` - The header name, in any casing.
+///
+/// # Returns
+///
+/// * `Cow<str>` - The name in canonical form.
+///
+/// # Examples
+///
+/// ```
+/// use rustic::header_map::canonical_name;
+/// assert_eq!(canonical_name("content-type"), "Content-Type");
+/// assert_eq!(canonical_name("X-REQUEST-ID"), "X-Request-Id");
+/// assert_eq!(canonical_name("etag"), "ETag");
+/// ```
+pub fn canonical_name(name: &str) -> Cow<'_, str> {
+    if let Some(exception) = CANONICAL_EXCEPTIONS
+        .iter()
+        .find(|exception| exception.eq_ignore_ascii_case(name))
+    {
+        return if *exception == name {
+            Cow::Borrowed(name)
+        } else {
+            Cow::Owned(exception.to_string())
+        };
+    }
+    let mut canonical = String::with_capacity(name.len());
+    let mut word_start = true;
+    for c in name.chars() {
+        canonical.push(if word_start {
+            c.to_ascii_uppercase()
+        } else {
+            c.to_ascii_lowercase()
+        });
+        word_start = c == '-';
+    }
+    if canonical == name {
+        Cow::Borrowed(name)
+    } else {
+        Cow::Owned(canonical)
+    }
+}
+
+/// Converts an unordered map, for code written against the previous `HashMap` headers.
+///
+/// The headers are ordered by name so that the result does not depend on hash order.
+impl From<HashMap<String, String>> for HeaderMap {
+    fn from(map: HashMap<String, String>) -> Self {
+        let mut entries: Vec<_> = map.into_iter().collect();
+        entries.sort();
+        entries.into_iter().collect()
+    }
+}
+
+/// Iterates over every header value like [`HeaderMap::iter`], taking the names and
+/// values out of the map.
+impl IntoIterator for HeaderMap {
+    type Item = (String, String);
+    type IntoIter = IntoIter;
+
+    fn into_iter(self) -> IntoIter {
+        IntoIter {
+            entries: self.entries.into_iter(),
+        }
+    }
+}
+
+/// The names and values taken out of a [`HeaderMap`], in the order they were added.
+#[derive(Debug)]
+pub struct IntoIter {
+    entries: std::vec::IntoIter<Entry>,
+}
+
+impl Iterator for IntoIter {
+    type Item = (String, String);
+
+    fn next(&mut self) -> Option<(String, String)> {
+        self.entries.next().map(|entry| (entry.name, entry.value))
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.entries.size_hint()
+    }
+}
+
+impl ExactSizeIterator for IntoIter {}
+
+impl<K: Into<String>, V: Into<String>> FromIterator<(K, V)> for HeaderMap {
+    fn from_iter<I: IntoIterator<Item = (K, V)>>(iter: I) -> Self {
+        let mut headers = HeaderMap::new();
+        headers.extend(iter);
+        headers
+    }
+}
+
+/// Appends every header, keeping repeated names.
+impl<K: Into<String>, V: Into<String>> Extend<(K, V)> for HeaderMap {
+    fn extend<I: IntoIterator<Item = (K, V)>>(&mut self, iter: I) {
+        for (name, value) in iter {
+            self.append(name, value);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test_header_map {
+    use super::*;
+
+    /// Tests that `insert` replaces every value in place while `append` adds to the end.
+    #[test]
+    fn test_insert_and_append() {
+        let mut headers = HeaderMap::new();
+        headers.append("Link", "<a>");
+        headers.append("Content-Type", "text/plain");
+        headers.append("link", "<b>");
+        assert_eq!(headers.insert("LINK", "<c>"), Some("<a>".to_string()));
+        assert_eq!(
+            headers.iter().collect::<Vec<_>>(),
+            vec![("Link", "<c>"), ("Content-Type", "text/plain")]
+        );
+    }
+
+    /// Tests removing and filtering headers regardless of name case.
+    #[test]
+    fn test_remove_and_retain() {
+        let mut headers: HeaderMap = vec![("A", "1"), ("B", "2"), ("a", "3")]
+            .into_iter()
+            .collect();
+        assert_eq!(headers.remove("a"), Some("1".to_string()));
+        assert_eq!(headers.len(), 1);
+        headers.retain(|name, _| name != "B");
+        assert!(headers.is_empty());
+    }
+
+    /// Tests canonical casing, including the names conventionally cased otherwise.
+    #[test]
+    fn test_canonical_name() {
+        assert_eq!(canonical_name("content-type"), "Content-Type");
+        assert_eq!(canonical_name("CONTENT-LENGTH"), "Content-Length");
+        assert_eq!(canonical_name("x-forwarded-for"), "X-Forwarded-For");
+        assert_eq!(canonical_name("-odd--name-"), "-Odd--Name-");
+        assert!(matches!(canonical_name("Set-Cookie"), Cow::Borrowed(_)));
+        for exception in CANONICAL_EXCEPTIONS {
+            assert_eq!(canonical_name(&exception.to_lowercase()), *exception);
+            assert_eq!(canonical_name(&exception.to_uppercase()), *exception);
+            assert!(matches!(canonical_name(exception), Cow::Borrowed(_)));
+        }
+        assert_eq!(canonical_name("www-authenticate"), "WWW-Authenticate");
+        assert_eq!(canonical_name("etag"), "ETag");
+        assert_eq!(canonical_name("content-md5"), "Content-MD5");
+    }
+
+    /// Tests that verbatim names are written out as given, even when replacing a
+    /// header added with another casing.
+    #[test]
+    fn test_verbatim_names() {
+        let mut headers = HeaderMap::new();
+        headers.append("etag", "\"1\"");
+        headers.append_verbatim("x-Mixed", "a");
+        headers.insert("vary", "Accept");
+        headers.insert_verbatim("VARY", "Origin");
+        let names: Vec<_> = headers.iter_canonical().map(|(name, _)| name).collect();
+        assert_eq!(names, ["ETag", "x-Mixed", "VARY"]);
+        assert_eq!(headers.get("Vary"), Some("Origin"));
+    }
+}
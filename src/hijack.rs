@@ -0,0 +1,200 @@
+use crate::app::Request;
+use std::io::{self, Read, Write};
+use std::net::TcpStream;
+use std::sync::{Arc, Mutex};
+
+/// What takes over a connection once its handler returns.
+pub(crate) type HijackHandler = Box<dyn FnOnce(Hijacked) + Send>;
+
+/// How a handler asked to take its connection over.
+pub(crate) enum Takeover {
+    /// With [`Request::hijack`], discarding the response.
+    Hijack(HijackHandler),
+    /// With [`Request::on_upgrade`](crate::app::Request::on_upgrade), once the
+    /// `101 Switching Protocols` response is written.
+    Upgrade(HijackHandler),
+}
+
+/// The per-request slot a handler leaves its takeover callback in, shared with the
+/// server serving the connection.
+#[derive(Clone)]
+pub(crate) struct HijackSlot {
+    takeover: Arc<Mutex<Option<Takeover>>>,
+    /// Whether the request is HTTP/1.1, as HTTP/1.0 has no upgrades.
+    pub(crate) http_1_1: bool,
+}
+
+impl HijackSlot {
+    /// Creates an empty slot for a request of the given version.
+    pub(crate) fn new(http_1_1: bool) -> Self {
+        HijackSlot {
+            takeover: Arc::default(),
+            http_1_1,
+        }
+    }
+
+    /// Leaves a callback, replacing the previous one.
+    pub(crate) fn set(&self, takeover: Takeover) {
+        *self.takeover.lock().unwrap() = Some(takeover);
+    }
+
+    /// Takes the callback a handler left, if any.
+    pub(crate) fn take(&self) -> Option<Takeover> {
+        self.takeover.lock().unwrap().take()
+    }
+
+    /// Settles the takeover against the status of the response, dropping an upgrade
+    /// the response does not switch protocols for, and returns whether one remains.
+    pub(crate) fn settle(&self, status_code: u16) -> bool {
+        let mut takeover = self.takeover.lock().unwrap();
+        if matches!(*takeover, Some(Takeover::Upgrade(_))) && status_code != 101 {
+            *takeover = None;
+        }
+        takeover.is_some()
+    }
+}
+
+/// A connection taken over with [`Request::hijack`].
+///
+/// Reading returns the bytes the client sent after the request, which the server may
+/// already have buffered, before reading from the socket. Writing goes straight to the
+/// socket. The socket has no read or write timeout; set them on [`Hijacked::stream`]
+/// if the protocol needs them.
+pub struct Hijacked {
+    stream: TcpStream,
+    buffered: Vec<u8>,
+    position: usize,
+}
+
+impl Hijacked {
+    pub(crate) fn new(stream: TcpStream, buffered: Vec<u8>) -> Self {
+        let _ = stream.set_read_timeout(None);
+        let _ = stream.set_write_timeout(None);
+        Hijacked {
+            stream,
+            buffered,
+            position: 0,
+        }
+    }
+
+    /// Returns the underlying socket, for example to set timeouts or shut it down.
+    pub fn stream(&self) -> &TcpStream {
+        &self.stream
+    }
+
+    /// Splits the connection into its socket and the bytes received after the request
+    /// that have not been read yet.
+    ///
+    /// # Returns
+    ///
+    /// * `(TcpStream, Vec<u8>)` - The socket, and the bytes to process before reading it.
+    pub fn into_parts(mut self) -> (TcpStream, Vec<u8>) {
+        let unread = self.buffered.split_off(self.position);
+        (self.stream, unread)
+    }
+}
+
+impl Read for Hijacked {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let unread = &self.buffered[self.position..];
+        if unread.is_empty() {
+            return self.stream.read(buf);
+        }
+        let read = unread.len().min(buf.len());
+        buf[..read].copy_from_slice(&unread[..read]);
+        self.position += read;
+        Ok(read)
+    }
+}
+
+impl Write for Hijacked {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.stream.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.stream.flush()
+    }
+}
+
+impl Request {
+    /// Takes the connection over once the handler returns, for protocols that stop being
+    /// HTTP, such as tunnels or WebSockets.
+    ///
+    /// The response the handler returns is then discarded: `handler` writes everything
+    /// the client receives, including any `101 Switching Protocols` or `200` answering
+    /// the handshake. The connection is not reused for further requests, and is closed
+    /// once `handler` drops it.
+    ///
+    /// `handler` runs on the worker thread that served the request, which keeps counting
+    /// against [`crate::app::ServerConfig::max_connections`] until it returns. Calling
+    /// this again replaces the previous handler.
+    ///
+    /// # Arguments
+    ///
+    /// * `handler` - What to do with the connection.
+    ///
+    /// # Errors
+    ///
+    /// Fails with `ErrorKind::Unsupported` when the request did not come over a
+    /// connection that can be taken over, such as one sent by
+    /// [`crate::test::TestClient`] or served by the `async` server.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use rustic::app::App;
+    /// use std::io::{Read, Write};
+    ///
+    /// let mut application = App::new();
+    /// application.get("shout", |request| {
+    ///     let hijacked = request.hijack(|mut connection| {
+    ///         let _ = connection.write_all(b"HTTP/1.1 101 Switching Protocols\r\n\r\n");
+    ///         let mut byte = [0];
+    ///         while let Ok(1) = connection.read(&mut byte) {
+    ///             let _ = connection.write_all(&byte.to_ascii_uppercase());
+    ///         }
+    ///     });
+    ///     match hijacked {
+    ///         Ok(()) => "",
+    ///         Err(_) => "Upgrade unavailable",
+    ///     }
+    /// });
+    /// ```
+    pub fn hijack(&self, handler: impl FnOnce(Hijacked) + Send + 'static) -> io::Result<()> {
+        let slot = self.extensions.get::<HijackSlot>().ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::Unsupported,
+                "the request's connection cannot be taken over",
+            )
+        })?;
+        slot.set(Takeover::Hijack(Box::new(handler)));
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test_hijack {
+    use super::*;
+    use std::net::TcpListener;
+
+    /// Tests that buffered bytes are read before the socket and handed back when split.
+    #[test]
+    fn test_hijacked_reads_buffered_first() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let mut client = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        let (stream, _) = listener.accept().unwrap();
+        client.write_all(b"cd").unwrap();
+
+        let mut hijacked = Hijacked::new(stream, b"ab".to_vec());
+        let mut first = [0; 1];
+        hijacked.read_exact(&mut first).unwrap();
+        assert_eq!(&first, b"a");
+        let (mut stream, unread) = hijacked.into_parts();
+        assert_eq!(unread, b"b");
+
+        let mut rest = [0; 2];
+        stream.read_exact(&mut rest).unwrap();
+        assert_eq!(&rest, b"cd");
+    }
+}
@@ -1,15 +1,491 @@
+use crate::content_type::ContentType;
+use crate::header_map::HeaderMap;
+use crate::into_response::text_response;
+use crate::response_writer::ResponseWriter;
+use crate::sendfile::{copy_file, send_file};
+use crate::traffic::CountingWriter;
+use std::borrow::Cow;
+use std::cell::RefCell;
 use std::collections::HashMap;
-use std::io::Write;
+use std::fs::File;
+use std::io::{self, Write};
 use std::net::TcpStream;
-use std::time::{SystemTime, UNIX_EPOCH};
+use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 
 #[derive(Clone)]
 /// Represents an HTTP response sent by the server.
-pub struct Response<'a> {
+pub struct Response {
     pub status_code: u16,
-    pub reason: &'a str,
-    pub response_body: Option<&'a str>,
-    pub headers: HashMap<String, String>,
+    pub reason: Cow<'static, str>,
+    pub response_body: Option<Body>,
+    pub headers: HeaderMap,
+}
+
+/// The body of an HTTP response.
+///
+/// A body holds raw bytes, so binary payloads can be sent as well as text. It converts
+/// from string literals, byte string literals, `String` and `Vec<u8>`, which keeps
+/// building a response short. A body can also be a region of a file, sent from the file
+/// when the response is written; see [`Body::file`].
+///
+/// # Examples
+///
+/// ```
+/// use rustic::header_map::HeaderMap;
+/// use rustic::http11_response::{Body, Response};
+/// let response = Response {
+///     status_code: 200,
+///     reason: "OK".into(),
+///     response_body: Some("Hello, world!".into()),
+///     headers: HeaderMap::new(),
+/// };
+/// let generated = Body::from(format!("{} + {} = {}", 1, 2, 1 + 2));
+/// assert_eq!(generated.as_bytes(), b"1 + 2 = 3");
+/// ```
+#[derive(Debug, Clone, PartialEq)]
+pub struct Body {
+    content: Content,
+}
+
+/// What a [`Body`] holds.
+#[derive(Debug, Clone)]
+enum Content {
+    Bytes(Cow<'static, [u8]>),
+    /// A region of a file, only read when the body is written.
+    File {
+        file: Arc<File>,
+        offset: u64,
+        len: u64,
+    },
+}
+
+impl PartialEq for Content {
+    fn eq(&self, other: &Self) -> bool {
+        match (self, other) {
+            (Content::Bytes(bytes), Content::Bytes(other)) => bytes == other,
+            (
+                Content::File { file, offset, len },
+                Content::File {
+                    file: other,
+                    offset: other_offset,
+                    len: other_len,
+                },
+            ) => Arc::ptr_eq(file, other) && offset == other_offset && len == other_len,
+            _ => false,
+        }
+    }
+}
+
+impl Body {
+    /// Creates a body sending `len` bytes of a file from `offset`.
+    ///
+    /// The file is not read up front: the servers send the region straight from it
+    /// after the head, with `sendfile(2)` on Linux and through a buffer elsewhere, so
+    /// large files are never held in memory. A region of a file, as answered to a
+    /// `Range` request, keeps the keep-alive framing intact as long as `Content-Length`
+    /// is left for the server to set. If the file turns out shorter than the region,
+    /// the connection is closed once what it holds has been sent.
+    ///
+    /// # Arguments
+    ///
+    /// * `file` - The file to send from.
+    /// * `offset` - Where the region starts in the file.
+    /// * `len` - The length of the region in bytes.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use rustic::http11_response::Body;
+    /// use std::fs::File;
+    ///
+    /// // The second kibibyte of the file.
+    /// let body = Body::file(File::open("video.mp4").unwrap(), 1024, 1024);
+    /// assert_eq!(body.len(), 1024);
+    /// ```
+    pub fn file(file: File, offset: u64, len: u64) -> Self {
+        Body {
+            content: Content::File {
+                file: Arc::new(file),
+                offset,
+                len,
+            },
+        }
+    }
+
+    /// Creates a body sending a whole file; see [`Body::file`].
+    ///
+    /// # Errors
+    ///
+    /// Fails when the metadata giving the file's length cannot be read.
+    pub fn from_file(file: File) -> io::Result<Self> {
+        let len = file.metadata()?.len();
+        Ok(Body::file(file, 0, len))
+    }
+
+    /// Returns the body content.
+    ///
+    /// A [file body](Body::file) is only read when it is written, so it has no content
+    /// in memory and this is empty; use [`Body::read`] to read it.
+    pub fn as_bytes(&self) -> &[u8] {
+        match &self.content {
+            Content::Bytes(bytes) => bytes,
+            Content::File { .. } => &[],
+        }
+    }
+
+    /// Returns the body content, reading it from its file for a [file body](Body::file).
+    ///
+    /// # Errors
+    ///
+    /// Fails when the file cannot be read or ends before the region of the body.
+    pub fn read(&self) -> io::Result<Cow<'_, [u8]>> {
+        match &self.content {
+            Content::Bytes(bytes) => Ok(Cow::Borrowed(bytes)),
+            Content::File { file, offset, len } => {
+                let mut contents = Vec::new();
+                copy_file(&mut contents, file, *offset, *len)?;
+                Ok(Cow::Owned(contents))
+            }
+        }
+    }
+
+    /// Returns whether the body is a region of a file; see [`Body::file`].
+    pub fn is_file(&self) -> bool {
+        matches!(self.content, Content::File { .. })
+    }
+
+    /// Writes the body to `writer`, copying a file body from its file through a buffer.
+    pub(crate) fn write_to<W: Write + ?Sized>(&self, writer: &mut W) -> io::Result<()> {
+        match &self.content {
+            Content::Bytes(bytes) => writer.write_all(bytes),
+            Content::File { file, offset, len } => copy_file(writer, file, *offset, *len),
+        }
+    }
+
+    /// Returns the length of the body in bytes.
+    pub fn len(&self) -> usize {
+        match &self.content {
+            Content::Bytes(bytes) => bytes.len(),
+            Content::File { len, .. } => *len as usize,
+        }
+    }
+
+    /// Returns whether the body is empty.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+impl From<&'static str> for Body {
+    fn from(text: &'static str) -> Self {
+        Body {
+            content: Content::Bytes(Cow::Borrowed(text.as_bytes())),
+        }
+    }
+}
+
+impl From<String> for Body {
+    fn from(text: String) -> Self {
+        Body {
+            content: Content::Bytes(Cow::Owned(text.into_bytes())),
+        }
+    }
+}
+
+impl From<&'static [u8]> for Body {
+    fn from(bytes: &'static [u8]) -> Self {
+        Body {
+            content: Content::Bytes(Cow::Borrowed(bytes)),
+        }
+    }
+}
+
+impl From<Vec<u8>> for Body {
+    fn from(bytes: Vec<u8>) -> Self {
+        Body {
+            content: Content::Bytes(Cow::Owned(bytes)),
+        }
+    }
+}
+
+/// A response serialized for sending: its head, followed by the body when it is in
+/// memory, and the region of the file following them for a [file body](Body::file).
+pub(crate) struct Message {
+    /// The head, followed by the body unless it is sent from a file.
+    pub(crate) bytes: Vec<u8>,
+    /// The file the body is sent from, with the offset and length of its region.
+    pub(crate) file: Option<(Arc<File>, u64, u64)>,
+}
+
+impl Message {
+    /// Returns the length of the whole message in bytes.
+    pub(crate) fn len(&self) -> usize {
+        self.bytes.len() + self.file.as_ref().map_or(0, |(_, _, len)| *len as usize)
+    }
+
+    /// Returns the length of the status line and headers, up to the empty line that
+    /// ends them.
+    pub(crate) fn head_len(&self) -> usize {
+        self.bytes
+            .windows(4)
+            .position(|window| window == b"\r\n\r\n")
+            .map_or(self.bytes.len(), |end| end + 4)
+    }
+
+    /// Returns the whole message, reading the region of the file it ends with.
+    pub(crate) fn into_bytes(self) -> io::Result<Vec<u8>> {
+        let mut bytes = self.bytes;
+        if let Some((file, offset, len)) = self.file {
+            copy_file(&mut bytes, &file, offset, len)?;
+        }
+        Ok(bytes)
+    }
+
+    /// Writes the message onto a connection, offloading the region of the file it ends
+    /// with to the kernel where the platform allows it.
+    ///
+    /// Fails with the error that stopped the write, such as the connection's write
+    /// timeout passing, along with how many bytes of the message were written before.
+    pub(crate) fn send(&self, stream: &TcpStream) -> Result<(), (io::Error, usize)> {
+        let mut writer = CountingWriter::new(stream);
+        let mut sent_file = 0;
+        let sent = writer
+            .write_all(&self.bytes)
+            .and_then(|()| match &self.file {
+                Some((file, offset, len)) => send_file(stream, file, *offset, *len, &mut sent_file),
+                None => Ok(()),
+            });
+        sent.map_err(|err| (err, writer.count() + sent_file))
+    }
+
+    /// Writes the message to any writer, copying the region of the file it ends with
+    /// through a buffer.
+    pub(crate) fn write_to<W: Write + ?Sized>(&self, writer: &mut W) -> io::Result<()> {
+        writer.write_all(&self.bytes)?;
+        match &self.file {
+            Some((file, offset, len)) => copy_file(writer, file, *offset, *len),
+            None => Ok(()),
+        }
+    }
+}
+
+/// A response rejecting a request before it is routed, such as `400 Bad Request` for a
+/// malformed request line, when there may be no [`Request`](crate::app::Request) to
+/// build it from.
+///
+/// The connection is closed after it, which it says with `Connection: close`, and its
+/// body always has a `Content-Type`. `Date` and `Content-Length` are added by the
+/// serializer [`write_connection`] uses, like for any response.
+pub(crate) struct ErrorResponse(Response);
+
+impl ErrorResponse {
+    /// Creates the response with a plain text `message` as its body.
+    pub(crate) fn new(status_code: u16, message: impl Into<Body>) -> Self {
+        ErrorResponse::from(text_response(status_code, message))
+    }
+
+    /// Sets a header of the response.
+    pub(crate) fn header(mut self, name: &str, value: impl Into<String>) -> Self {
+        self.0.headers.insert(name, value);
+        self
+    }
+
+    /// Returns the response, to finish like any other.
+    pub(crate) fn into_response(self) -> Response {
+        self.0
+    }
+
+    /// Serializes the response for sending.
+    pub(crate) fn into_message(self) -> Message {
+        self.0.into_message()
+    }
+}
+
+impl From<Response> for ErrorResponse {
+    /// Takes a response built elsewhere, such as in the app's error format, adding the
+    /// headers it lacks.
+    fn from(mut response: Response) -> Self {
+        response.headers.insert("Connection", "close");
+        let has_body = response
+            .response_body
+            .as_ref()
+            .is_some_and(|body| !body.is_empty());
+        if has_body && !response.headers.contains_key("Content-Type") {
+            response
+                .headers
+                .insert("Content-Type", ContentType::TEXT_PLAIN_UTF8);
+        }
+        ErrorResponse(response)
+    }
+}
+
+impl From<Vec<u8>> for Message {
+    fn from(bytes: Vec<u8>) -> Self {
+        Message { bytes, file: None }
+    }
+}
+
+impl Response {
+    /// Looks up a response header by name, ignoring ASCII case.
+    ///
+    /// # Arguments
+    ///
+    /// * `name` - The header name to look up.
+    ///
+    /// # Returns
+    ///
+    /// * `Option<&str>` - The header value, if the header is set.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use rustic::header_map::HeaderMap;
+    /// use rustic::http11_response::Response;
+    /// let mut headers = HeaderMap::new();
+    /// headers.insert("Content-Type", "text/plain");
+    /// let response = Response {
+    ///     status_code: 200,
+    ///     reason: "OK".into(),
+    ///     response_body: None,
+    ///     headers,
+    /// };
+    /// assert_eq!(response.header("content-type"), Some("text/plain"));
+    /// ```
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers.get(name)
+    }
+
+    /// Serializes the response to the bytes [`write_connection`] sends: the status line,
+    /// the headers in insertion order followed by `Date` and `Content-Length` unless
+    /// already set or the response is informational, and the body. See [`write_header`]
+    /// for how they are added.
+    ///
+    /// # Returns
+    ///
+    /// * `Vec<u8>` - The serialized response.
+    ///
+    /// # Panics
+    ///
+    /// Panics when the body is a [file](Body::file) that cannot be read.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use rustic::header_map::HeaderMap;
+    /// use rustic::http11_response::Response;
+    /// let response = Response {
+    ///     status_code: 404,
+    ///     reason: "Not Found".into(),
+    ///     response_body: Some("Missing".into()),
+    ///     headers: HeaderMap::new(),
+    /// };
+    /// let bytes = response.to_bytes();
+    /// assert!(bytes.starts_with(b"HTTP/1.1 404 Not Found"));
+    /// assert!(bytes.ends_with(b"Content-Length: 7\r\n\r\nMissing"));
+    /// ```
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = self.head(self.body_length()).into_bytes();
+        match self.response_body.as_ref().map(|body| &body.content) {
+            Some(Content::Bytes(body)) => bytes.extend_from_slice(body),
+            Some(Content::File { file, offset, len }) => {
+                copy_file(&mut bytes, file, *offset, *len)
+                    .expect("Failed to read the file of a response body");
+            }
+            None => {}
+        }
+        bytes
+    }
+
+    /// Checks that no header value is longer than `max_value` bytes and that the header
+    /// lines add up to at most `max_total` bytes, or describes the header at fault,
+    /// which is the largest one when only the total is too large.
+    pub(crate) fn check_header_sizes(
+        &self,
+        max_value: usize,
+        max_total: usize,
+    ) -> Result<(), String> {
+        let mut total = 0;
+        let mut largest = ("", 0);
+        for (name, value) in self.headers.iter() {
+            if value.len() > max_value {
+                return Err(format!(
+                    "the value of `{}` is {} bytes, past the limit of {}",
+                    name,
+                    value.len(),
+                    max_value
+                ));
+            }
+            // Each line is `name: value` and its line ending.
+            let line = name.len() + value.len() + 4;
+            total += line;
+            if line > largest.1 {
+                largest = (name, line);
+            }
+        }
+        if total > max_total {
+            return Err(format!(
+                "the headers are {} bytes, past the limit of {}, the largest being `{}`",
+                total, max_total, largest.0
+            ));
+        }
+        Ok(())
+    }
+
+    /// Serializes the response for sending, leaving a file body to be sent from its file.
+    pub(crate) fn into_message(self) -> Message {
+        let in_memory = match self.response_body.as_ref().map(|body| &body.content) {
+            Some(Content::Bytes(bytes)) => bytes.len(),
+            _ => 0,
+        };
+        let head = self.head(in_memory);
+        match self.response_body.map(|body| body.content) {
+            Some(Content::Bytes(body)) => {
+                // The head was given room for the body, so appending it allocates nothing.
+                let mut bytes = head.into_bytes();
+                bytes.extend_from_slice(&body);
+                bytes.into()
+            }
+            Some(Content::File { file, offset, len }) => Message {
+                bytes: head.into_bytes(),
+                file: Some((file, offset, len)),
+            },
+            None => head.into_bytes().into(),
+        }
+    }
+
+    /// Returns the length framing the body, in bytes of the body as it is sent.
+    ///
+    /// This is the only place the servers compute `Content-Length`. It reads the final
+    /// body, once middleware such as [`crate::compression::Compression`] has replaced it,
+    /// so a length is never taken from text before it is encoded.
+    fn body_length(&self) -> usize {
+        self.response_body.as_ref().map_or(0, Body::len)
+    }
+
+    /// Writes the status line and headers, leaving room for `reserve` more bytes after
+    /// them.
+    pub(crate) fn head(&self, reserve: usize) -> String {
+        let mut head = String::with_capacity(256 + reserve);
+        head.push_str(&write_status_header(self.status_code, &self.reason));
+        // Writes what `write_header` would, without copying the headers to add to them.
+        let framed_by_coding = self.headers.contains_key("Transfer-Encoding");
+        for (name, value) in self.headers.iter_canonical() {
+            if !(framed_by_coding && name.eq_ignore_ascii_case("Content-Length")) {
+                push_header_line(&mut head, &name, value);
+            }
+        }
+        if !self.headers.contains_key("Date") && !self.headers.is_suppressed("Date") {
+            with_current_date(|date| push_header_line(&mut head, "Date", date));
+        }
+        // An informational response has no body to frame, so must not claim one.
+        let informational = (100..200).contains(&self.status_code);
+        if !framed_by_coding && !informational && !self.headers.contains_key("Content-Length") {
+            push_header_line(&mut head, "Content-Length", &self.body_length().to_string());
+        }
+        head.push_str("\r\n");
+        head
+    }
 }
 
 /// Retrieves the current date and time in UTC format as a string.
@@ -29,17 +505,158 @@ pub struct Response<'a> {
 /// println!("{}", date); // Example: "Sun, 07 Jul 2024 12:00:00 GMT"
 /// ```
 pub fn get_current_utc_date() -> String {
+    with_current_date(str::to_string)
+}
+
+/// Runs `f` on the current HTTP-date, which each thread formats once per second.
+fn with_current_date<R>(f: impl FnOnce(&str) -> R) -> R {
+    thread_local! {
+        static CURRENT_DATE: RefCell<(u64, String)> = const { RefCell::new((0, String::new())) };
+    }
     let now = SystemTime::now();
-    let seconds_since_epoch = now.duration_since(UNIX_EPOCH).unwrap().as_secs();
-    let formatted_date = chrono::DateTime::<chrono::Utc>::from_utc(
-        chrono::NaiveDateTime::from_timestamp(seconds_since_epoch as i64, 0),
-        chrono::Utc,
-    );
+    let second = now
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |elapsed| elapsed.as_secs());
+    CURRENT_DATE.with(|current| {
+        let mut current = current.borrow_mut();
+        if current.1.is_empty() || current.0 != second {
+            *current = (second, format_http_date(now));
+        }
+        f(&current.1)
+    })
+}
+
+/// Formats a point in time as an HTTP-date (RFC 9110, section 5.6.7).
+///
+/// Times before the Unix epoch are clamped to the epoch.
+///
+/// # Arguments
+///
+/// * `time` - The point in time to format.
+///
+/// # Returns
+///
+/// * `String` - The time in IMF-fixdate format.
+///
+/// # Examples
+///
+/// ```
+/// use rustic::http11_response::format_http_date;
+/// use std::time::{Duration, UNIX_EPOCH};
+/// let time = UNIX_EPOCH + Duration::from_secs(784111777);
+/// assert_eq!(format_http_date(time), "Sun, 06 Nov 1994 08:49:37 GMT");
+/// ```
+pub fn format_http_date(time: SystemTime) -> String {
+    let seconds_since_epoch = time
+        .duration_since(UNIX_EPOCH)
+        .map(|elapsed| elapsed.as_secs())
+        .unwrap_or(0);
+    // Times past what the calendar covers, such as a forged file time, read as the epoch.
+    let formatted_date =
+        chrono::DateTime::<chrono::Utc>::from_timestamp(seconds_since_epoch as i64, 0)
+            .unwrap_or_default();
     formatted_date
         .format("%a, %d %b %Y %H:%M:%S GMT")
         .to_string()
 }
 
+/// Parses an HTTP-date in IMF-fixdate format, the inverse of [`format_http_date`].
+///
+/// The obsolete RFC 850 and asctime formats are not accepted.
+///
+/// # Arguments
+///
+/// * `date` - The header value to parse.
+///
+/// # Returns
+///
+/// * `Option<SystemTime>` - The point in time, or `None` if the value is not a valid date.
+///
+/// # Examples
+///
+/// ```
+/// use rustic::http11_response::parse_http_date;
+/// use std::time::{Duration, UNIX_EPOCH};
+/// let time = parse_http_date("Sun, 06 Nov 1994 08:49:37 GMT").unwrap();
+/// assert_eq!(time, UNIX_EPOCH + Duration::from_secs(784111777));
+/// assert!(parse_http_date("yesterday").is_none());
+/// ```
+pub fn parse_http_date(date: &str) -> Option<SystemTime> {
+    let parsed =
+        chrono::NaiveDateTime::parse_from_str(date.trim(), "%a, %d %b %Y %H:%M:%S GMT").ok()?;
+    let seconds = u64::try_from(parsed.and_utc().timestamp()).ok()?;
+    Some(UNIX_EPOCH + Duration::from_secs(seconds))
+}
+
+/// Returns the standard reason phrase for an HTTP status code.
+///
+/// # Arguments
+///
+/// * `status_code` - The HTTP status code.
+///
+/// # Returns
+///
+/// * `&'static str` - The reason phrase from RFC 9110, or `"Unknown"` for unregistered codes.
+///
+/// # Examples
+///
+/// ```
+/// use rustic::http11_response::reason_phrase;
+/// assert_eq!(reason_phrase(404), "Not Found");
+/// assert_eq!(reason_phrase(299), "Unknown");
+/// ```
+pub fn reason_phrase(status_code: u16) -> &'static str {
+    match status_code {
+        100 => "Continue",
+        101 => "Switching Protocols",
+        103 => "Early Hints",
+        200 => "OK",
+        201 => "Created",
+        202 => "Accepted",
+        203 => "Non-Authoritative Information",
+        204 => "No Content",
+        205 => "Reset Content",
+        206 => "Partial Content",
+        300 => "Multiple Choices",
+        301 => "Moved Permanently",
+        302 => "Found",
+        303 => "See Other",
+        304 => "Not Modified",
+        307 => "Temporary Redirect",
+        308 => "Permanent Redirect",
+        400 => "Bad Request",
+        401 => "Unauthorized",
+        403 => "Forbidden",
+        404 => "Not Found",
+        405 => "Method Not Allowed",
+        406 => "Not Acceptable",
+        407 => "Proxy Authentication Required",
+        408 => "Request Timeout",
+        409 => "Conflict",
+        410 => "Gone",
+        411 => "Length Required",
+        412 => "Precondition Failed",
+        413 => "Content Too Large",
+        414 => "URI Too Long",
+        415 => "Unsupported Media Type",
+        416 => "Range Not Satisfiable",
+        417 => "Expectation Failed",
+        421 => "Misdirected Request",
+        422 => "Unprocessable Content",
+        426 => "Upgrade Required",
+        428 => "Precondition Required",
+        429 => "Too Many Requests",
+        431 => "Request Header Fields Too Large",
+        500 => "Internal Server Error",
+        501 => "Not Implemented",
+        502 => "Bad Gateway",
+        503 => "Service Unavailable",
+        504 => "Gateway Timeout",
+        505 => "HTTP Version Not Supported",
+        _ => "Unknown",
+    }
+}
+
 /// Constructs the status line for an HTTP response.
 ///
 /// This function formats the HTTP status line based on the provided status code and reason phrase.
@@ -64,16 +681,22 @@ pub fn write_status_header(status_code: u16, reason: &str) -> String {
     format!("HTTP/1.1 {} {} \r\n", status_code, reason)
 }
 
-/// Constructs the HTTP headers from a given `HashMap` and includes an optional body and Content-Length.
+/// Constructs the HTTP headers from a given `HeaderMap` and includes an optional body and Content-Length.
 ///
 /// This function formats the HTTP headers, adds the current date if not already present,
 /// and includes the Content-Length header based on the length of the provided body if present.
 /// If `body` is `None`, it adds Content-Length as 0.
 ///
+/// Values the handler set take precedence: a `Date` or `Content-Length` already present
+/// is kept, and a [suppressed](HeaderMap::suppress) `Date` is not added. A body framed by
+/// `Transfer-Encoding` gets no `Content-Length`, and any set is removed. Header names are
+/// written in their [canonical form](crate::header_map::canonical_name), except for those
+/// added verbatim.
+///
 /// # Arguments
 ///
-/// * `headers` - A mutable reference to a `HashMap` containing the headers.
-/// * `body` - An optional body content as a string slice (`Option<&str>`).
+/// * `headers` - A mutable reference to a `HeaderMap` containing the headers.
+/// * `body` - An optional body content as bytes (`Option<&[u8]>`).
 ///
 /// # Returns
 ///
@@ -82,65 +705,141 @@ pub fn write_status_header(status_code: u16, reason: &str) -> String {
 /// # Examples
 ///
 /// ```
+/// use rustic::header_map::HeaderMap;
 /// use rustic::http11_response::write_header;
-/// use std::collections::HashMap;
-/// let mut headers = HashMap::new();
-/// headers.insert("Content-Type".to_string(), "text/plain".to_string());
-/// let body = Some("Hello, world!");
+/// let mut headers = HeaderMap::new();
+/// headers.insert("content-type", "text/plain");
+/// let body = Some("Hello, world!".as_bytes());
 /// let headers_string = write_header(&mut headers, body);
 /// println!("{}", headers_string);
 /// assert!(headers_string.contains("Content-Type: text/plain\r\n"));
 /// ```
-pub fn write_header(headers: &mut HashMap<String, String>, body: Option<&str>) -> String {
-    headers.insert("Date".to_string(), get_current_utc_date());
-    if let Some(body) = body {
-        headers.insert("Content-Length".to_string(), body.len().to_string());
-    } else {
-        headers.insert("Content-Length".to_string(), "0".to_string());
+pub fn write_header(headers: &mut HeaderMap, body: Option<&[u8]>) -> String {
+    if !headers.contains_key("Date") && !headers.is_suppressed("Date") {
+        headers.insert("Date", get_current_utc_date());
+    }
+    if headers.contains_key("Transfer-Encoding") {
+        // A length next to a transfer coding may be read instead of it, desyncing the
+        // connection, so only the coding frames the body.
+        headers.remove("Content-Length");
+    } else if !headers.contains_key("Content-Length") {
+        headers.insert("Content-Length", body.map_or(0, <[u8]>::len).to_string());
     }
 
+    format_header_lines(headers)
+}
+
+/// Formats headers as `Name: value` lines, in insertion order and with canonical names,
+/// followed by the blank line ending the header block.
+pub(crate) fn format_header_lines(headers: &HeaderMap) -> String {
     let mut header_string = String::new();
-    for (key, value) in headers {
-        header_string.push_str(&format!("{}: {}\r\n", key, value));
+    for (key, value) in headers.iter_canonical() {
+        push_header_line(&mut header_string, &key, value);
     }
     header_string.push_str("\r\n");
     header_string
 }
 
-/// Writes an HTTP response to the given TCP stream.
+/// Appends a `Name: value` line to a message head.
+fn push_header_line(head: &mut String, name: &str, value: &str) {
+    head.reserve(name.len() + value.len() + 4);
+    head.push_str(name);
+    head.push_str(": ");
+    head.push_str(value);
+    head.push_str("\r\n");
+}
+
+/// Writes an interim `1xx` response, such as `103 Early Hints`, ahead of the final
+/// response to a request.
+///
+/// The status line and headers are written with no body. `Content-Length` and
+/// `Transfer-Encoding` are left out, as an interim response has no body to frame, and
+/// no `Date` is added. `101 Switching Protocols` ends HTTP on the connection, so it is
+/// not an interim response and is refused.
+///
+/// Handlers send interim responses with [`crate::app::Request::send_interim`], which
+/// also ensures none is sent once the final response has begun.
+///
+/// # Arguments
+///
+/// * `stream` - The stream to write to.
+/// * `status_code` - The `1xx` status code.
+/// * `headers` - The headers of the interim response.
+///
+/// # Errors
+///
+/// Fails with `ErrorKind::InvalidInput` for a status outside `1xx` or equal to `101`,
+/// and with the error of the write otherwise.
+///
+/// # Examples
+///
+/// ```
+/// use rustic::header_map::HeaderMap;
+/// use rustic::http11_response::write_interim_response;
+/// let mut headers = HeaderMap::new();
+/// headers.append("Link", "</style.css>; rel=preload; as=style");
+/// let mut written = Vec::new();
+/// write_interim_response(&mut written, 103, &headers).unwrap();
+/// assert_eq!(
+///     written,
+///     b"HTTP/1.1 103 Early Hints \r\nLink: </style.css>; rel=preload; as=style\r\n\r\n"
+/// );
+/// assert!(write_interim_response(&mut written, 200, &headers).is_err());
+/// ```
+pub fn write_interim_response<W: Write>(
+    stream: &mut W,
+    status_code: u16,
+    headers: &HeaderMap,
+) -> io::Result<()> {
+    if !(100..200).contains(&status_code) || status_code == 101 {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("{} is not an interim status", status_code),
+        ));
+    }
+    let mut headers = headers.clone();
+    headers.retain(|name, _| {
+        !name.eq_ignore_ascii_case("Content-Length")
+            && !name.eq_ignore_ascii_case("Transfer-Encoding")
+    });
+    let mut message = write_status_header(status_code, reason_phrase(status_code)).into_bytes();
+    message.extend_from_slice(format_header_lines(&headers).as_bytes());
+    stream.write_all(&message)
+}
+
+/// Writes an HTTP response to the given stream.
 ///
-/// This function writes the status line, headers, and optionally the response body to the TCP stream.
+/// This function writes the status line, headers, and optionally the response body to the
+/// stream, which can be a `TcpStream`, a TLS or Unix socket stream, or an in-memory buffer.
+/// A [file body](Body::file) is copied from its file through a buffer.
 ///
 /// # Arguments
 ///
-/// * `stream` - A mutable reference to the `TcpStream`.
+/// * `stream` - A mutable reference to the stream to write to.
 /// * `response` - The HTTP response to be written.
 ///
+/// # Returns
+///
+/// * `io::Result<usize>` - The number of bytes written, including the status line and
+///   headers, or why writing failed, for example because the client went away.
+///
 /// # Examples
 ///
 /// ```no_run
+/// use rustic::header_map::HeaderMap;
 /// use rustic::http11_response::{write_connection,Response};
 /// use std::net::TcpStream;
-/// use std::collections::HashMap;
 /// let mut stream = TcpStream::connect("127.0.0.1:8080").unwrap();
 /// let response = Response {
 ///     status_code: 200,
-///     reason: "OK",
-///     response_body: Some("Hello, world!"),
-///     headers: HashMap::new(),
+///     reason: "OK".into(),
+///     response_body: Some("Hello, world!".into()),
+///     headers: HeaderMap::new(),
 /// };
-/// write_connection(&mut stream, response);
+/// write_connection(&mut stream, response).unwrap();
 /// ```
-pub fn write_connection(stream: &mut TcpStream, mut response: Response) {
-    let status_line = write_status_header(response.status_code, response.reason);
-    let headers_string = write_header(&mut response.headers, response.response_body);
-    let mut full_response = status_line;
-    full_response.push_str(&headers_string);
-
-    if let Some(response_body) = response.response_body {
-        full_response.push_str(response_body);
-    }
-    stream.write_all(full_response.as_bytes()).unwrap();
+pub fn write_connection<W: Write>(stream: &mut W, response: Response) -> io::Result<usize> {
+    Ok(ResponseWriter::new(stream).write_response(response)?)
 }
 
 /// Converts a `HashMap` to a JSON string.
@@ -185,6 +884,32 @@ pub fn hashmap_to_json<K: std::fmt::Display, V: std::fmt::Display>(map: &HashMap
 mod test_http_response_functions {
     use super::*;
 
+    /// Tests that responses are checked against both the per-value and the total limit
+    /// on their headers, naming the header at fault.
+    #[test]
+    fn test_check_header_sizes() {
+        let mut headers = HeaderMap::new();
+        headers.insert("Content-Type", "text/plain");
+        headers.insert("X-Trace", "a".repeat(100));
+        headers.insert("X-Small", "b".repeat(40));
+        let response = Response {
+            status_code: 200,
+            reason: "OK".into(),
+            response_body: None,
+            headers,
+        };
+        // Each line is the name and value along with 4 bytes of separators.
+        let total = (12 + 10 + 4) + (7 + 100 + 4) + (7 + 40 + 4);
+        assert_eq!(response.check_header_sizes(100, total), Ok(()));
+
+        let err = response.check_header_sizes(99, usize::MAX).unwrap_err();
+        assert!(err.contains("`X-Trace` is 100 bytes"), "{}", err);
+
+        let err = response.check_header_sizes(100, total - 1).unwrap_err();
+        assert!(err.contains(&format!("{} bytes", total)), "{}", err);
+        assert!(err.contains("the largest being `X-Trace`"), "{}", err);
+    }
+
     /// Tests the `write_status_header` function.
     #[test]
     fn test_write_status_header() {
@@ -195,14 +920,277 @@ mod test_http_response_functions {
     /// Tests the `write_header` function.
     #[test]
     fn test_write_header() {
-        let mut headers = HashMap::new();
-        headers.insert("Content-Type".to_string(), "text/plain".to_string());
+        let mut headers = HeaderMap::new();
+        headers.insert("Content-Type", "text/plain");
 
         let header_string = write_header(&mut headers, None);
         assert!(header_string.contains("Content-Type: text/plain\r\n"));
         assert!(header_string.contains("Content-Length: 0\r\n"));
     }
 
+    /// Tests that headers are written in insertion order, with repeated headers kept.
+    #[test]
+    fn test_write_header_order() {
+        let mut headers = HeaderMap::new();
+        headers.insert("Content-Type", "text/plain");
+        headers.append("Link", "</a>; rel=next");
+        headers.append("Link", "</b>; rel=prev");
+        headers.insert("Date", "Thu, 01 Jan 1970 00:00:00 GMT");
+
+        let header_string = write_header(&mut headers, Some(b"hi"));
+        let lines: Vec<&str> = header_string.split("\r\n").collect();
+        assert_eq!(lines[0], "Content-Type: text/plain");
+        assert_eq!(lines[1], "Link: </a>; rel=next");
+        assert_eq!(lines[2], "Link: </b>; rel=prev");
+        assert!(lines[3].starts_with("Date: "));
+        assert_eq!(lines[4], "Content-Length: 2");
+    }
+
+    /// Tests that names are written in canonical form whatever casing they were set
+    /// with, unless they were set verbatim.
+    #[test]
+    fn test_write_header_canonical_names() {
+        let mut headers = HeaderMap::new();
+        headers.insert("content-type", "text/plain");
+        headers.insert("etag", "\"v1\"");
+        headers.insert("CONTENT-LENGTH", "2");
+        headers.append_verbatim("x-Legacy-ID", "7");
+
+        let header_string = write_header(&mut headers, Some(b"hi"));
+        let lines: Vec<&str> = header_string.split("\r\n").collect();
+        assert_eq!(lines[0], "Content-Type: text/plain");
+        assert_eq!(lines[1], "ETag: \"v1\"");
+        assert_eq!(lines[2], "Content-Length: 2");
+        assert_eq!(lines[3], "x-Legacy-ID: 7");
+    }
+
+    /// Tests that a `Date` set by the handler is kept and a suppressed one is left out.
+    #[test]
+    fn test_write_header_date_precedence() {
+        let mut headers = HeaderMap::new();
+        headers.insert("date", "Thu, 01 Jan 1970 00:00:00 GMT");
+        let header_string = write_header(&mut headers, None);
+        assert_eq!(
+            header_string,
+            "Date: Thu, 01 Jan 1970 00:00:00 GMT\r\nContent-Length: 0\r\n\r\n"
+        );
+
+        let mut headers = HeaderMap::new();
+        headers.insert("Date", "Thu, 01 Jan 1970 00:00:00 GMT");
+        headers.suppress("Date");
+        headers.insert("Date", "Fri, 02 Jan 1970 00:00:00 GMT");
+        assert_eq!(
+            write_header(&mut headers, None),
+            "Content-Length: 0\r\n\r\n"
+        );
+    }
+
+    /// Tests that a `Content-Length` set by the handler is kept, and that none is sent
+    /// next to `Transfer-Encoding`.
+    #[test]
+    fn test_write_header_length_precedence() {
+        let mut headers = HeaderMap::new();
+        headers.suppress("Date");
+        headers.insert("Content-Length", "5");
+        assert_eq!(
+            write_header(&mut headers, Some("é".as_bytes())),
+            "Content-Length: 5\r\n\r\n"
+        );
+
+        let mut headers = HeaderMap::new();
+        headers.suppress("Date");
+        headers.suppress("Content-Length");
+        headers.insert("Transfer-Encoding", "chunked");
+        headers.insert("Content-Length", "5");
+        assert_eq!(
+            write_header(&mut headers, Some(b"0\r\n\r\n")),
+            "Transfer-Encoding: chunked\r\n\r\n"
+        );
+    }
+
+    /// Tests that `to_bytes`, which writes the head without copying the headers, writes
+    /// what `write_header` does.
+    #[test]
+    fn test_to_bytes_matches_write_header() {
+        let date = ("Date", "Thu, 01 Jan 1970 00:00:00 GMT");
+        let cases: [&[(&str, &str)]; 4] = [
+            &[("content-type", "text/plain"), date],
+            &[date, ("Content-Length", "3"), ("X-A", "1")],
+            &[
+                ("Transfer-Encoding", "chunked"),
+                ("content-length", "3"),
+                date,
+            ],
+            &[("Link", "<a>"), ("link", "<b>"), date, ("Vary", "Accept")],
+        ];
+        for headers in cases {
+            let headers: HeaderMap = headers.iter().copied().collect();
+            let response = Response {
+                status_code: 200,
+                reason: "OK".into(),
+                response_body: Some("abc".into()),
+                headers: headers.clone(),
+            };
+            let mut expected = write_status_header(200, "OK");
+            expected.push_str(&write_header(&mut headers.clone(), Some(b"abc")));
+            expected.push_str("abc");
+            assert_eq!(String::from_utf8(response.to_bytes()).unwrap(), expected);
+        }
+    }
+
+    /// Tests the exact bytes `write_connection` sends, using a buffer as the stream.
+    #[test]
+    fn test_write_connection() {
+        let mut headers = HeaderMap::new();
+        headers.insert("Content-Type", "text/plain");
+        let response = Response {
+            status_code: 200,
+            reason: "OK".into(),
+            response_body: Some("Hello".into()),
+            headers,
+        };
+
+        let mut written = Vec::new();
+        let length = write_connection(&mut written, response).unwrap();
+        assert_eq!(length, written.len());
+        let written = String::from_utf8(written).unwrap();
+        // The date is the only part that changes between runs.
+        let date = written
+            .lines()
+            .find_map(|line| line.strip_prefix("Date: "))
+            .unwrap();
+        assert!(date.ends_with(" GMT"));
+        assert_eq!(
+            written,
+            format!(
+                "HTTP/1.1 200 OK \r\nContent-Type: text/plain\r\nDate: {}\r\n\
+                 Content-Length: 5\r\n\r\nHello",
+                date
+            )
+        );
+    }
+
+    /// Tests `to_bytes` against a golden serialization, with the current date filled in.
+    #[test]
+    fn test_to_bytes() {
+        let mut headers = HeaderMap::new();
+        headers.insert("Content-Type", "application/json");
+        headers.append("Set-Cookie", "a=1");
+        headers.append("Set-Cookie", "b=2");
+        let response = Response {
+            status_code: 201,
+            reason: "Created".into(),
+            response_body: Some(b"{\"id\":7}".to_vec().into()),
+            headers,
+        };
+
+        let bytes = response.to_bytes();
+        let text = String::from_utf8(bytes.clone()).unwrap();
+        let date = text
+            .lines()
+            .find_map(|line| line.strip_prefix("Date: "))
+            .unwrap();
+        let golden = format!(
+            "HTTP/1.1 201 Created \r\n\
+             Content-Type: application/json\r\n\
+             Set-Cookie: a=1\r\n\
+             Set-Cookie: b=2\r\n\
+             Date: {}\r\n\
+             Content-Length: 8\r\n\
+             \r\n\
+             {{\"id\":7}}",
+            date
+        );
+        assert_eq!(bytes, golden.as_bytes());
+        // Serializing leaves the response untouched, so it can be rendered again.
+        assert!(response.header("Date").is_none());
+        let mut written = Vec::new();
+        write_connection(&mut written, response).unwrap();
+        assert_eq!(written.len(), bytes.len());
+    }
+
+    /// Tests that multi-byte UTF-8 bodies are framed by their length in bytes, in every
+    /// serialization.
+    #[test]
+    fn test_multibyte_body_length() {
+        for text in ["héllo", "こんにちは、世界", "🦀🚀 ok", "混合 mixed ✓"] {
+            let response = text_response_for(text);
+            let bytes = response.to_bytes();
+            let split = bytes.windows(4).position(|w| w == b"\r\n\r\n").unwrap() + 4;
+            let head = String::from_utf8(bytes[..split].to_vec()).unwrap();
+            let length = format!("Content-Length: {}\r\n", text.len());
+            assert!(head.contains(&length), "{}", head);
+            assert_eq!(&bytes[split..], text.as_bytes());
+            assert_eq!(response.into_message().into_bytes().unwrap(), bytes);
+        }
+    }
+
+    fn text_response_for(text: &str) -> Response {
+        let mut headers = HeaderMap::new();
+        headers.insert("Date", "Thu, 01 Jan 1970 00:00:00 GMT");
+        Response {
+            status_code: 200,
+            reason: "OK".into(),
+            response_body: Some(text.to_string().into()),
+            headers,
+        }
+    }
+
+    /// Tests that a file body is framed by the length of its region and serialized with
+    /// just that region, whether read into memory or copied by `write_connection`.
+    #[test]
+    fn test_file_body() {
+        let path = std::env::temp_dir().join(format!("rustic-body-{}", std::process::id()));
+        std::fs::write(&path, "0123456789").unwrap();
+        let body = Body::file(File::open(&path).unwrap(), 3, 4);
+        std::fs::remove_file(&path).unwrap();
+        assert!(body.is_file());
+        assert_eq!(body.len(), 4);
+        assert!(body.as_bytes().is_empty());
+        assert_eq!(*body.read().unwrap(), *b"3456");
+        assert_eq!(body, body.clone());
+        assert_ne!(body, Body::from("3456"));
+
+        let mut headers = HeaderMap::new();
+        headers.insert("Date", "Thu, 01 Jan 1970 00:00:00 GMT");
+        let response = Response {
+            status_code: 206,
+            reason: "Partial Content".into(),
+            response_body: Some(body),
+            headers,
+        };
+        let expected = "HTTP/1.1 206 Partial Content \r\nDate: Thu, 01 Jan 1970 00:00:00 GMT\r\n\
+                        Content-Length: 4\r\n\r\n3456";
+        assert_eq!(response.to_bytes(), expected.as_bytes());
+        let message = response.clone().into_message();
+        assert_eq!(message.len(), expected.len());
+        assert_eq!(message.into_bytes().unwrap(), expected.as_bytes());
+        let mut written = Vec::new();
+        assert_eq!(
+            write_connection(&mut written, response).unwrap(),
+            expected.len()
+        );
+        assert_eq!(written, expected.as_bytes());
+    }
+
+    /// Tests that interim responses only accept `1xx` statuses and carry no framing.
+    #[test]
+    fn test_write_interim_response() {
+        let mut headers = HeaderMap::new();
+        headers.append("Link", "</a.js>; rel=preload");
+        headers.insert("Content-Length", "10");
+        let mut written = Vec::new();
+        write_interim_response(&mut written, 103, &headers).unwrap();
+        assert_eq!(
+            written,
+            b"HTTP/1.1 103 Early Hints \r\nLink: </a.js>; rel=preload\r\n\r\n"
+        );
+        for status in [101, 200, 99] {
+            let err = write_interim_response(&mut written, status, &headers).unwrap_err();
+            assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+        }
+    }
+
     /// Tests the `hashmap_to_json` function.
     #[test]
     fn test_hashmap_to_json() {
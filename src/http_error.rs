@@ -0,0 +1,150 @@
+use crate::content_type::ContentType;
+use crate::header_map::HeaderMap;
+use crate::http11_response::{reason_phrase, Response};
+use std::error::Error;
+use std::fmt;
+use std::io;
+use std::num::{ParseFloatError, ParseIntError};
+use std::string::FromUtf8Error;
+
+/// An error a handler returns to answer the request with an error status.
+///
+/// Handlers returning `Result<Response, HttpError>` can use `?` on fallible operations:
+/// parse errors convert to `400 Bad Request`, and I/O errors to `404 Not Found`,
+/// `403 Forbidden` or `500 Internal Server Error` depending on their kind. The response
+/// carries the message as a plain-text body.
+///
+/// # Examples
+///
+/// ```
+/// use rustic::app::{App, Request};
+/// use rustic::header_map::HeaderMap;
+/// use rustic::http11_response::Response;
+/// use rustic::http_error::HttpError;
+/// use rustic::parse_headers::RequestType;
+///
+/// fn double(request: Request) -> Result<Response, HttpError> {
+///     let value = request
+///         .query_param("value")
+///         .value()
+///         .ok_or_else(|| HttpError::bad_request("Missing value"))?;
+///     let value: i64 = value.parse()?;
+///     Ok(Response {
+///         status_code: 200,
+///         reason: "OK".into(),
+///         response_body: Some((value * 2).to_string().into()),
+///         headers: HeaderMap::new(),
+///     })
+/// }
+///
+/// let mut application = App::new();
+/// application.add_endpoint("double", RequestType::GET, double);
+/// ```
+#[derive(Debug, Clone, PartialEq)]
+pub struct HttpError {
+    pub status: u16,
+    pub message: String,
+}
+
+impl HttpError {
+    /// Creates an error with the given status and message.
+    pub fn new(status: u16, message: impl Into<String>) -> Self {
+        HttpError {
+            status,
+            message: message.into(),
+        }
+    }
+
+    /// Creates a `400 Bad Request` error.
+    pub fn bad_request(message: impl Into<String>) -> Self {
+        Self::new(400, message)
+    }
+
+    /// Creates a `404 Not Found` error.
+    pub fn not_found() -> Self {
+        Self::new(404, reason_phrase(404))
+    }
+
+    /// Creates a `500 Internal Server Error` error.
+    pub fn internal() -> Self {
+        Self::new(500, reason_phrase(500))
+    }
+}
+
+impl fmt::Display for HttpError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} {}", self.status, self.message)
+    }
+}
+
+impl Error for HttpError {}
+
+impl From<HttpError> for Response {
+    fn from(error: HttpError) -> Self {
+        let mut headers = HeaderMap::new();
+        headers.insert("Content-Type", ContentType::TEXT_PLAIN_UTF8);
+        Response {
+            status_code: error.status,
+            reason: reason_phrase(error.status).into(),
+            response_body: Some(error.message.into()),
+            headers,
+        }
+    }
+}
+
+/// I/O errors never expose their description, which may name server-side paths.
+impl From<io::Error> for HttpError {
+    fn from(error: io::Error) -> Self {
+        let status = match error.kind() {
+            io::ErrorKind::NotFound => 404,
+            io::ErrorKind::PermissionDenied => 403,
+            _ => 500,
+        };
+        Self::new(status, reason_phrase(status))
+    }
+}
+
+impl From<ParseIntError> for HttpError {
+    fn from(error: ParseIntError) -> Self {
+        Self::bad_request(format!("Bad Request: {}", error))
+    }
+}
+
+impl From<ParseFloatError> for HttpError {
+    fn from(error: ParseFloatError) -> Self {
+        Self::bad_request(format!("Bad Request: {}", error))
+    }
+}
+
+impl From<FromUtf8Error> for HttpError {
+    fn from(error: FromUtf8Error) -> Self {
+        Self::bad_request(format!("Bad Request: {}", error))
+    }
+}
+
+#[cfg(test)]
+mod test_http_error {
+    use super::*;
+
+    /// Tests converting errors into responses.
+    #[test]
+    fn test_into_response() {
+        let response = Response::from(HttpError::bad_request("Missing id"));
+        assert_eq!(response.status_code, 400);
+        assert_eq!(response.reason, "Bad Request");
+        assert_eq!(response.response_body.unwrap().as_bytes(), b"Missing id");
+
+        let parse_error = "x".parse::<u32>().unwrap_err();
+        assert_eq!(HttpError::from(parse_error).status, 400);
+    }
+
+    /// Tests mapping I/O errors to statuses without exposing their description.
+    #[test]
+    fn test_from_io_error() {
+        let error = io::Error::new(io::ErrorKind::NotFound, "/srv/secret.txt");
+        assert_eq!(HttpError::from(error), HttpError::not_found());
+
+        let error = io::Error::other("disk on fire");
+        assert_eq!(HttpError::from(error), HttpError::internal());
+    }
+}
@@ -0,0 +1,509 @@
+//! DEFLATE decompression (RFC 1951) with the gzip (RFC 1952) and zlib (RFC 1950)
+//! wrappers, for decoding compressed request bodies without pulling in a dependency.
+
+use std::fmt;
+
+/// The longest Huffman code DEFLATE uses, in bits.
+const MAX_BITS: usize = 15;
+
+/// The base lengths of the length symbols 257 to 285.
+pub(crate) const LENGTH_BASE: [u16; 29] = [
+    3, 4, 5, 6, 7, 8, 9, 10, 11, 13, 15, 17, 19, 23, 27, 31, 35, 43, 51, 59, 67, 83, 99, 115, 131,
+    163, 195, 227, 258,
+];
+/// The number of extra bits of the length symbols 257 to 285.
+pub(crate) const LENGTH_EXTRA: [u8; 29] = [
+    0, 0, 0, 0, 0, 0, 0, 0, 1, 1, 1, 1, 2, 2, 2, 2, 3, 3, 3, 3, 4, 4, 4, 4, 5, 5, 5, 5, 0,
+];
+/// The base distances of the distance symbols 0 to 29.
+pub(crate) const DISTANCE_BASE: [u16; 30] = [
+    1, 2, 3, 4, 5, 7, 9, 13, 17, 25, 33, 49, 65, 97, 129, 193, 257, 385, 513, 769, 1025, 1537,
+    2049, 3073, 4097, 6145, 8193, 12289, 16385, 24577,
+];
+/// The number of extra bits of the distance symbols 0 to 29.
+pub(crate) const DISTANCE_EXTRA: [u8; 30] = [
+    0, 0, 0, 0, 1, 1, 2, 2, 3, 3, 4, 4, 5, 5, 6, 6, 7, 7, 8, 8, 9, 9, 10, 10, 11, 11, 12, 12, 13,
+    13,
+];
+/// The order in which the code lengths of the code length alphabet are sent.
+const CODE_LENGTH_ORDER: [usize; 19] = [
+    16, 17, 18, 0, 8, 7, 9, 6, 10, 5, 11, 4, 12, 3, 13, 2, 14, 1, 15,
+];
+
+/// Why a compressed body could not be decoded.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) enum InflateError {
+    /// The data is truncated, corrupt or fails its checksum.
+    Invalid,
+    /// The decoded data would exceed the size limit.
+    TooLarge,
+}
+
+impl fmt::Display for InflateError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            InflateError::Invalid => f.write_str("invalid compressed data"),
+            InflateError::TooLarge => f.write_str("decompressed data too large"),
+        }
+    }
+}
+
+/// Decodes gzip data, including several concatenated members.
+///
+/// # Arguments
+///
+/// * `data` - The gzip data.
+/// * `limit` - The largest number of decoded bytes accepted.
+pub(crate) fn gunzip(data: &[u8], limit: usize) -> Result<Vec<u8>, InflateError> {
+    let mut output = Vec::new();
+    let mut rest = data;
+    loop {
+        let start = output.len();
+        let body = skip_gzip_header(rest)?;
+        let mut bits = BitReader::new(body);
+        inflate_into(&mut bits, &mut output, limit)?;
+        let trailer = body
+            .get(bits.position..bits.position + 8)
+            .ok_or(InflateError::Invalid)?;
+        let crc = u32::from_le_bytes(trailer[..4].try_into().unwrap());
+        let size = u32::from_le_bytes(trailer[4..].try_into().unwrap());
+        let member = &output[start..];
+        if crc != crc32(member) || size != member.len() as u32 {
+            return Err(InflateError::Invalid);
+        }
+        rest = &body[bits.position + 8..];
+        if rest.is_empty() {
+            return Ok(output);
+        }
+    }
+}
+
+/// Decodes the `deflate` content coding, which is zlib data, falling back to raw DEFLATE
+/// since some clients send that instead.
+///
+/// # Arguments
+///
+/// * `data` - The compressed data.
+/// * `limit` - The largest number of decoded bytes accepted.
+pub(crate) fn zlib_decompress(data: &[u8], limit: usize) -> Result<Vec<u8>, InflateError> {
+    let is_zlib = data.len() >= 2
+        && data[0] & 0x0f == 8
+        && data[0] >> 4 <= 7
+        && data[1] & 0x20 == 0
+        && (u16::from(data[0]) << 8 | u16::from(data[1])) % 31 == 0;
+    if !is_zlib {
+        return inflate(data, limit);
+    }
+    let mut output = Vec::new();
+    let mut bits = BitReader::new(&data[2..]);
+    inflate_into(&mut bits, &mut output, limit)?;
+    let trailer = data[2..]
+        .get(bits.position..bits.position + 4)
+        .ok_or(InflateError::Invalid)?;
+    if u32::from_be_bytes(trailer.try_into().unwrap()) != adler32(&output) {
+        return Err(InflateError::Invalid);
+    }
+    Ok(output)
+}
+
+/// Decodes raw DEFLATE data.
+pub(crate) fn inflate(data: &[u8], limit: usize) -> Result<Vec<u8>, InflateError> {
+    let mut output = Vec::new();
+    inflate_into(&mut BitReader::new(data), &mut output, limit)?;
+    Ok(output)
+}
+
+/// Returns the DEFLATE data following a gzip member header.
+fn skip_gzip_header(data: &[u8]) -> Result<&[u8], InflateError> {
+    const FHCRC: u8 = 0x02;
+    const FEXTRA: u8 = 0x04;
+    const FNAME: u8 = 0x08;
+    const FCOMMENT: u8 = 0x10;
+    if data.len() < 10 || data[..3] != [0x1f, 0x8b, 8] || data[3] & 0xe0 != 0 {
+        return Err(InflateError::Invalid);
+    }
+    let flags = data[3];
+    let mut position = 10;
+    if flags & FEXTRA != 0 {
+        let length = data
+            .get(position..position + 2)
+            .ok_or(InflateError::Invalid)?;
+        position += 2 + usize::from(u16::from_le_bytes([length[0], length[1]]));
+    }
+    for flag in [FNAME, FCOMMENT] {
+        if flags & flag != 0 {
+            let end = data
+                .get(position..)
+                .and_then(|rest| rest.iter().position(|&byte| byte == 0))
+                .ok_or(InflateError::Invalid)?;
+            position += end + 1;
+        }
+    }
+    if flags & FHCRC != 0 {
+        position += 2;
+    }
+    data.get(position..).ok_or(InflateError::Invalid)
+}
+
+/// Reads bits least significant first, as DEFLATE packs them.
+struct BitReader<'a> {
+    data: &'a [u8],
+    /// The index of the next byte to load into `buffer`.
+    position: usize,
+    buffer: u32,
+    count: u32,
+}
+
+impl<'a> BitReader<'a> {
+    fn new(data: &'a [u8]) -> Self {
+        BitReader {
+            data,
+            position: 0,
+            buffer: 0,
+            count: 0,
+        }
+    }
+
+    /// Reads `count` bits, at most 16, as a number.
+    fn bits(&mut self, count: u32) -> Result<u32, InflateError> {
+        while self.count < count {
+            let byte = *self.data.get(self.position).ok_or(InflateError::Invalid)?;
+            self.position += 1;
+            self.buffer |= u32::from(byte) << self.count;
+            self.count += 8;
+        }
+        let value = self.buffer & ((1 << count) - 1);
+        self.buffer >>= count;
+        self.count -= count;
+        Ok(value)
+    }
+
+    /// Drops the bits left in the current byte.
+    fn align(&mut self) {
+        self.buffer = 0;
+        self.count = 0;
+    }
+}
+
+/// A canonical Huffman code, stored as the number of codes of each length and the
+/// symbols ordered by code.
+struct Huffman {
+    counts: [u16; MAX_BITS + 1],
+    symbols: Vec<u16>,
+}
+
+impl Huffman {
+    /// Builds the code from the code length of each symbol, where 0 means unused.
+    fn new(lengths: &[u8]) -> Result<Self, InflateError> {
+        let mut counts = [0u16; MAX_BITS + 1];
+        for &length in lengths {
+            counts[usize::from(length)] += 1;
+        }
+        let mut left: i32 = 1;
+        for &count in &counts[1..] {
+            left = (left << 1) - i32::from(count);
+            if left < 0 {
+                return Err(InflateError::Invalid);
+            }
+        }
+        let mut offsets = [0u16; MAX_BITS + 2];
+        for length in 1..=MAX_BITS {
+            offsets[length + 1] = offsets[length] + counts[length];
+        }
+        let mut symbols = vec![0; usize::from(offsets[MAX_BITS + 1])];
+        for (symbol, &length) in lengths.iter().enumerate() {
+            if length != 0 {
+                let offset = &mut offsets[usize::from(length)];
+                symbols[usize::from(*offset)] = symbol as u16;
+                *offset += 1;
+            }
+        }
+        counts[0] = 0;
+        Ok(Huffman { counts, symbols })
+    }
+
+    /// Decodes one symbol.
+    fn decode(&self, bits: &mut BitReader) -> Result<u16, InflateError> {
+        let (mut code, mut first, mut index) = (0i32, 0i32, 0i32);
+        for &count in &self.counts[1..] {
+            code |= bits.bits(1)? as i32;
+            let count = i32::from(count);
+            if code - count < first {
+                return Ok(self.symbols[(index + code - first) as usize]);
+            }
+            index += count;
+            first = (first + count) << 1;
+            code <<= 1;
+        }
+        Err(InflateError::Invalid)
+    }
+}
+
+/// Decodes DEFLATE blocks up to and including the final one, appending to `output`.
+fn inflate_into(
+    bits: &mut BitReader,
+    output: &mut Vec<u8>,
+    limit: usize,
+) -> Result<(), InflateError> {
+    loop {
+        let last = bits.bits(1)? == 1;
+        match bits.bits(2)? {
+            0 => stored_block(bits, output, limit)?,
+            1 => {
+                let mut lengths = [0u8; 288];
+                lengths[..144].fill(8);
+                lengths[144..256].fill(9);
+                lengths[256..280].fill(7);
+                lengths[280..].fill(8);
+                let literals = Huffman::new(&lengths)?;
+                let distances = Huffman::new(&[5; 30])?;
+                compressed_block(bits, output, limit, &literals, &distances)?;
+            }
+            2 => {
+                let (literals, distances) = dynamic_codes(bits)?;
+                compressed_block(bits, output, limit, &literals, &distances)?;
+            }
+            _ => return Err(InflateError::Invalid),
+        }
+        if last {
+            // Leave the reader at the first byte after the compressed data.
+            bits.position -= (bits.count / 8) as usize;
+            bits.align();
+            return Ok(());
+        }
+    }
+}
+
+/// Copies a block stored without compression.
+fn stored_block(
+    bits: &mut BitReader,
+    output: &mut Vec<u8>,
+    limit: usize,
+) -> Result<(), InflateError> {
+    bits.position -= (bits.count / 8) as usize;
+    bits.align();
+    let header = bits
+        .data
+        .get(bits.position..bits.position + 4)
+        .ok_or(InflateError::Invalid)?;
+    let length = u16::from_le_bytes([header[0], header[1]]);
+    if length != !u16::from_le_bytes([header[2], header[3]]) {
+        return Err(InflateError::Invalid);
+    }
+    let start = bits.position + 4;
+    let block = bits
+        .data
+        .get(start..start + usize::from(length))
+        .ok_or(InflateError::Invalid)?;
+    if output.len() + block.len() > limit {
+        return Err(InflateError::TooLarge);
+    }
+    output.extend_from_slice(block);
+    bits.position = start + block.len();
+    Ok(())
+}
+
+/// Reads the literal/length and distance codes of a dynamic Huffman block.
+fn dynamic_codes(bits: &mut BitReader) -> Result<(Huffman, Huffman), InflateError> {
+    let literal_count = bits.bits(5)? as usize + 257;
+    let distance_count = bits.bits(5)? as usize + 1;
+    let code_length_count = bits.bits(4)? as usize + 4;
+    if literal_count > 286 || distance_count > 30 {
+        return Err(InflateError::Invalid);
+    }
+    let mut code_lengths = [0u8; 19];
+    for &symbol in &CODE_LENGTH_ORDER[..code_length_count] {
+        code_lengths[symbol] = bits.bits(3)? as u8;
+    }
+    let code_length_code = Huffman::new(&code_lengths)?;
+
+    let mut lengths = vec![0u8; literal_count + distance_count];
+    let mut index = 0;
+    while index < lengths.len() {
+        let symbol = code_length_code.decode(bits)?;
+        let (value, repeat) = match symbol {
+            0..=15 => (symbol as u8, 1),
+            16 => {
+                let previous = *lengths[..index].last().ok_or(InflateError::Invalid)?;
+                (previous, 3 + bits.bits(2)? as usize)
+            }
+            17 => (0, 3 + bits.bits(3)? as usize),
+            _ => (0, 11 + bits.bits(7)? as usize),
+        };
+        let end = index + repeat;
+        if end > lengths.len() {
+            return Err(InflateError::Invalid);
+        }
+        lengths[index..end].fill(value);
+        index = end;
+    }
+    if lengths[256] == 0 {
+        return Err(InflateError::Invalid);
+    }
+    let literals = Huffman::new(&lengths[..literal_count])?;
+    let distances = Huffman::new(&lengths[literal_count..])?;
+    Ok((literals, distances))
+}
+
+/// Decodes the symbols of a Huffman-compressed block up to its end-of-block symbol.
+fn compressed_block(
+    bits: &mut BitReader,
+    output: &mut Vec<u8>,
+    limit: usize,
+    literals: &Huffman,
+    distances: &Huffman,
+) -> Result<(), InflateError> {
+    loop {
+        let symbol = usize::from(literals.decode(bits)?);
+        if symbol == 256 {
+            return Ok(());
+        }
+        if symbol < 256 {
+            if output.len() >= limit {
+                return Err(InflateError::TooLarge);
+            }
+            output.push(symbol as u8);
+            continue;
+        }
+        let symbol = symbol - 257;
+        if symbol >= LENGTH_BASE.len() {
+            return Err(InflateError::Invalid);
+        }
+        let length =
+            usize::from(LENGTH_BASE[symbol]) + bits.bits(u32::from(LENGTH_EXTRA[symbol]))? as usize;
+        let symbol = usize::from(distances.decode(bits)?);
+        if symbol >= DISTANCE_BASE.len() {
+            return Err(InflateError::Invalid);
+        }
+        let distance = usize::from(DISTANCE_BASE[symbol])
+            + bits.bits(u32::from(DISTANCE_EXTRA[symbol]))? as usize;
+        if distance > output.len() {
+            return Err(InflateError::Invalid);
+        }
+        if output.len() + length > limit {
+            return Err(InflateError::TooLarge);
+        }
+        let start = output.len() - distance;
+        // Copies may overlap the bytes they produce, so go byte by byte.
+        for offset in 0..length {
+            output.push(output[start + offset]);
+        }
+    }
+}
+
+/// Computes the CRC-32 checksum gzip uses (ISO 3309).
+pub(crate) fn crc32(data: &[u8]) -> u32 {
+    const TABLE: [u32; 256] = {
+        let mut table = [0u32; 256];
+        let mut index = 0;
+        while index < 256 {
+            let mut crc = index as u32;
+            let mut bit = 0;
+            while bit < 8 {
+                crc = if crc & 1 != 0 {
+                    0xedb8_8320 ^ (crc >> 1)
+                } else {
+                    crc >> 1
+                };
+                bit += 1;
+            }
+            table[index] = crc;
+            index += 1;
+        }
+        table
+    };
+    !data.iter().fold(!0u32, |crc, &byte| {
+        TABLE[((crc ^ u32::from(byte)) & 0xff) as usize] ^ (crc >> 8)
+    })
+}
+
+/// Computes the Adler-32 checksum zlib uses.
+pub(crate) fn adler32(data: &[u8]) -> u32 {
+    let (mut a, mut b) = (1u32, 0u32);
+    for chunk in data.chunks(5552) {
+        for &byte in chunk {
+            a += u32::from(byte);
+            b += a;
+        }
+        a %= 65521;
+        b %= 65521;
+    }
+    b << 16 | a
+}
+
+#[cfg(test)]
+mod test_inflate {
+    use super::*;
+
+    /// `{"name":"rustic"}` compressed by Python's `gzip.compress`.
+    const GZIP_JSON: [u8; 37] = [
+        31, 139, 8, 0, 0, 0, 0, 0, 2, 3, 171, 86, 202, 75, 204, 77, 85, 178, 82, 42, 42, 45, 46,
+        201, 76, 86, 170, 5, 0, 169, 191, 161, 150, 17, 0, 0, 0,
+    ];
+    /// `hello hello hello hello` as a raw DEFLATE fixed Huffman block.
+    const RAW_FIXED: [u8; 10] = [203, 72, 205, 201, 201, 87, 200, 64, 39, 1];
+    /// `stored` as a zlib stream holding a stored block.
+    const ZLIB_STORED: [u8; 17] = [
+        120, 1, 1, 6, 0, 249, 255, 115, 116, 111, 114, 101, 100, 9, 60, 2, 146,
+    ];
+    /// Two pangrams as a zlib stream holding a dynamic Huffman block.
+    const ZLIB_DYNAMIC: [u8; 89] = [
+        120, 218, 117, 140, 71, 1, 128, 48, 12, 69, 173, 124, 5, 213, 194, 1, 3, 1, 210, 18, 70,
+        67, 23, 75, 61, 49, 192, 249, 141, 126, 102, 164, 38, 227, 138, 33, 235, 21, 225, 245, 198,
+        210, 246, 163, 64, 79, 206, 168, 134, 55, 122, 31, 76, 26, 28, 58, 50, 111, 127, 48, 152,
+        116, 73, 157, 225, 229, 100, 67, 47, 71, 108, 146, 154, 102, 107, 67, 113, 232, 127, 175,
+        20, 72, 162, 251, 0, 229, 184, 42, 143,
+    ];
+
+    /// Tests decoding stored, fixed and dynamic blocks in each wrapper.
+    #[test]
+    fn test_decode() {
+        assert_eq!(gunzip(&GZIP_JSON, 1024).unwrap(), br#"{"name":"rustic"}"#);
+        assert_eq!(
+            inflate(&RAW_FIXED, 1024).unwrap(),
+            b"hello hello hello hello"
+        );
+        assert_eq!(
+            zlib_decompress(&RAW_FIXED, 1024).unwrap(),
+            b"hello hello hello hello"
+        );
+        assert_eq!(zlib_decompress(&ZLIB_STORED, 1024).unwrap(), b"stored");
+        assert_eq!(
+            zlib_decompress(&ZLIB_DYNAMIC, 1024).unwrap(),
+            b"The quick brown fox jumps over the lazy dog. Pack my box with five dozen \
+              liquor jugs. The quick brown fox jumps again."
+                .as_slice()
+        );
+
+        let twice = [GZIP_JSON, GZIP_JSON].concat();
+        assert_eq!(
+            gunzip(&twice, 1024).unwrap(),
+            br#"{"name":"rustic"}{"name":"rustic"}"#
+        );
+    }
+
+    /// Tests that the size limit applies to the decoded data.
+    #[test]
+    fn test_limit() {
+        assert_eq!(inflate(&RAW_FIXED, 23).unwrap().len(), 23);
+        assert_eq!(inflate(&RAW_FIXED, 22), Err(InflateError::TooLarge));
+        assert_eq!(
+            zlib_decompress(&ZLIB_STORED, 5),
+            Err(InflateError::TooLarge)
+        );
+        assert_eq!(gunzip(&GZIP_JSON, 16), Err(InflateError::TooLarge));
+    }
+
+    /// Tests that truncated and corrupted data is refused.
+    #[test]
+    fn test_invalid() {
+        assert_eq!(gunzip(&GZIP_JSON[..30], 1024), Err(InflateError::Invalid));
+        let mut corrupt = GZIP_JSON;
+        corrupt[29] ^= 1;
+        assert_eq!(gunzip(&corrupt, 1024), Err(InflateError::Invalid));
+        assert_eq!(gunzip(b"{\"name\"}", 1024), Err(InflateError::Invalid));
+        let mut corrupt = ZLIB_DYNAMIC;
+        corrupt[88] ^= 1;
+        assert_eq!(zlib_decompress(&corrupt, 1024), Err(InflateError::Invalid));
+    }
+}
@@ -0,0 +1,322 @@
+use crate::content_type::ContentType;
+use crate::header_map::HeaderMap;
+use crate::http11_response::{reason_phrase, Body, Response};
+use crate::http_error::HttpError;
+
+/// Conversion of a handler's return value into a response.
+///
+/// Handlers registered with [`App::add_endpoint`](crate::app::App::add_endpoint) may
+/// return any type implementing this trait, directly or wrapped in an `Option` or a
+/// `Result`. The crate implements it for:
+///
+/// * `Response`, which is sent as is.
+/// * `&'static str` and `String`, sent as `200 OK` with a `text/plain` body.
+/// * `(u16, &'static str)` and `(u16, String)`, sent as text with the given status.
+/// * `Vec<u8>`, sent as `200 OK` with an `application/octet-stream` body.
+/// * `()`, sent as `204 No Content`.
+/// * [`HttpError`], sent with its status and message.
+/// * `serde_json::Value` and [`Json`], sent as `application/json` (`serde` feature).
+///
+/// # Examples
+///
+/// ```
+/// use rustic::app::App;
+/// use rustic::http11_response::Response;
+/// use rustic::into_response::{text_response, IntoResponse};
+///
+/// struct Greeting(String);
+///
+/// impl IntoResponse for Greeting {
+///     fn into_response(self) -> Response {
+///         text_response(200, format!("Hello, {}!", self.0))
+///     }
+/// }
+///
+/// let mut application = App::new();
+/// application.get("hello", |_| "hi");
+/// application.get("greet", |_| Greeting("world".to_string()));
+/// application.post("teapot", |_| (418, "short and stout"));
+/// application.delete("items/*", |_| ());
+/// ```
+pub trait IntoResponse {
+    /// Converts the value into the response to send.
+    fn into_response(self) -> Response;
+}
+
+/// Builds a response with a body and its `Content-Type`.
+///
+/// # Arguments
+///
+/// * `status_code` - The HTTP status code.
+/// * `content_type` - The media type of the body, as a [`ContentType`] or a string.
+/// * `body` - The body content.
+///
+/// # Returns
+///
+/// * `Response` - The response, with the standard reason phrase for the status.
+pub fn body_response(
+    status_code: u16,
+    content_type: impl Into<String>,
+    body: impl Into<Body>,
+) -> Response {
+    let mut headers = HeaderMap::new();
+    headers.insert("Content-Type", content_type);
+    Response {
+        status_code,
+        reason: reason_phrase(status_code).into(),
+        response_body: Some(body.into()),
+        headers,
+    }
+}
+
+/// Builds a response with a UTF-8 `text/plain` body.
+///
+/// # Arguments
+///
+/// * `status_code` - The HTTP status code.
+/// * `text` - The body content.
+///
+/// # Returns
+///
+/// * `Response` - The response, with the standard reason phrase for the status.
+pub fn text_response(status_code: u16, text: impl Into<Body>) -> Response {
+    body_response(status_code, ContentType::TEXT_PLAIN_UTF8, text)
+}
+
+impl IntoResponse for Response {
+    fn into_response(self) -> Response {
+        self
+    }
+}
+
+impl IntoResponse for &'static str {
+    fn into_response(self) -> Response {
+        text_response(200, self)
+    }
+}
+
+impl IntoResponse for String {
+    fn into_response(self) -> Response {
+        text_response(200, self)
+    }
+}
+
+impl IntoResponse for (u16, &'static str) {
+    fn into_response(self) -> Response {
+        text_response(self.0, self.1)
+    }
+}
+
+impl IntoResponse for (u16, String) {
+    fn into_response(self) -> Response {
+        text_response(self.0, self.1)
+    }
+}
+
+impl IntoResponse for Vec<u8> {
+    fn into_response(self) -> Response {
+        body_response(200, ContentType::OCTET_STREAM, self)
+    }
+}
+
+impl IntoResponse for () {
+    fn into_response(self) -> Response {
+        Response {
+            status_code: 204,
+            reason: reason_phrase(204).into(),
+            response_body: None,
+            headers: HeaderMap::new(),
+        }
+    }
+}
+
+impl IntoResponse for HttpError {
+    fn into_response(self) -> Response {
+        self.into()
+    }
+}
+
+/// A value serialized as an `application/json` response body.
+///
+/// Serialization failures are answered with `500 Internal Server Error`.
+///
+/// # Examples
+///
+/// ```
+/// use rustic::app::App;
+/// use rustic::into_response::Json;
+/// use std::collections::HashMap;
+/// let mut application = App::new();
+/// application.get("config", |_| {
+///     let mut config = HashMap::new();
+///     config.insert("mode", "production");
+///     Json(config)
+/// });
+/// ```
+#[cfg(feature = "serde")]
+pub struct Json<T>(pub T);
+
+#[cfg(feature = "serde")]
+impl<T: serde::Serialize> IntoResponse for Json<T> {
+    fn into_response(self) -> Response {
+        Response::json_of(&self.0).unwrap_or_else(Response::from)
+    }
+}
+
+#[cfg(feature = "serde")]
+impl Response {
+    /// Builds a `200 OK` response with a value serialized as its JSON body.
+    ///
+    /// Unlike [`hashmap_to_json`](crate::http11_response::hashmap_to_json), this accepts
+    /// any `Serialize` type, including nested structures.
+    ///
+    /// # Arguments
+    ///
+    /// * `value` - The value to serialize.
+    ///
+    /// # Returns
+    ///
+    /// * `Result<Response, HttpError>` - The response with `Content-Type` and
+    ///   `Content-Length` set, or a `500 Internal Server Error` if serialization fails,
+    ///   which a handler can return with `?`.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use rustic::http11_response::Response;
+    /// use std::collections::BTreeMap;
+    /// let mut scores = BTreeMap::new();
+    /// scores.insert("ada", vec![3, 5]);
+    /// let response = Response::json_of(&scores).unwrap();
+    /// assert_eq!(response.header("Content-Type"), Some("application/json"));
+    /// assert_eq!(response.header("Content-Length"), Some("13"));
+    /// assert_eq!(response.response_body.unwrap().as_bytes(), b"{\"ada\":[3,5]}");
+    /// ```
+    pub fn json_of<T: serde::Serialize + ?Sized>(value: &T) -> Result<Response, HttpError> {
+        serde_json::to_vec(value)
+            .map(json_response)
+            .map_err(|_| HttpError::internal())
+    }
+
+    /// Builds a `200 OK` response with a value serialized as indented JSON.
+    ///
+    /// # Arguments
+    ///
+    /// * `value` - The value to serialize.
+    ///
+    /// # Returns
+    ///
+    /// * `Result<Response, HttpError>` - As for [`Response::json_of`].
+    pub fn json_pretty_of<T: serde::Serialize + ?Sized>(value: &T) -> Result<Response, HttpError> {
+        serde_json::to_vec_pretty(value)
+            .map(json_response)
+            .map_err(|_| HttpError::internal())
+    }
+}
+
+/// Builds the response for a serialized JSON body.
+#[cfg(feature = "serde")]
+fn json_response(json: Vec<u8>) -> Response {
+    let length = json.len();
+    let mut response = body_response(200, ContentType::APPLICATION_JSON, json);
+    response
+        .headers
+        .insert("Content-Length", length.to_string());
+    response
+}
+
+#[cfg(feature = "serde")]
+impl IntoResponse for serde_json::Value {
+    fn into_response(self) -> Response {
+        Json(self).into_response()
+    }
+}
+
+#[cfg(test)]
+mod test_into_response {
+    use super::*;
+    #[cfg(feature = "serde")]
+    use std::collections::HashMap;
+
+    /// Tests the status, content type and body of the built-in conversions.
+    #[test]
+    fn test_conversions() {
+        let response = "hi".into_response();
+        assert_eq!(response.status_code, 200);
+        assert_eq!(
+            response.header("Content-Type"),
+            Some("text/plain; charset=utf-8")
+        );
+        assert_eq!(response.response_body.unwrap().as_bytes(), b"hi");
+
+        let response = (201, "made".to_string()).into_response();
+        assert_eq!(response.status_code, 201);
+        assert_eq!(response.reason, "Created");
+
+        let response = vec![0u8, 1].into_response();
+        assert_eq!(
+            response.header("Content-Type"),
+            Some("application/octet-stream")
+        );
+
+        let response = ().into_response();
+        assert_eq!(response.status_code, 204);
+        assert!(response.response_body.is_none());
+    }
+
+    /// Tests serializing values as JSON bodies.
+    #[cfg(feature = "serde")]
+    #[test]
+    fn test_json() {
+        let response = Json(vec![1, 2, 3]).into_response();
+        assert_eq!(response.header("Content-Type"), Some("application/json"));
+        assert_eq!(response.response_body.unwrap().as_bytes(), b"[1,2,3]");
+
+        let response = serde_json::json!({ "ok": true }).into_response();
+        assert_eq!(response.response_body.unwrap().as_bytes(), b"{\"ok\":true}");
+    }
+
+    #[cfg(feature = "serde")]
+    #[derive(serde::Serialize)]
+    struct Order {
+        id: u32,
+        items: Vec<Vec<&'static str>>,
+        note: Option<&'static str>,
+    }
+
+    /// Tests serializing a struct with a nested vec and an `Option`, compact and pretty.
+    #[cfg(feature = "serde")]
+    #[test]
+    fn test_json_of() {
+        let order = Order {
+            id: 7,
+            items: vec![vec!["tea", "milk"], vec![]],
+            note: None,
+        };
+        let response = Response::json_of(&order).unwrap();
+        let expected = r#"{"id":7,"items":[["tea","milk"],[]],"note":null}"#;
+        assert_eq!(response.status_code, 200);
+        assert_eq!(response.header("Content-Length"), Some("48"));
+        assert_eq!(
+            response.response_body.unwrap().as_bytes(),
+            expected.as_bytes()
+        );
+
+        let response = Response::json_pretty_of(&order).unwrap();
+        let expected = "{\n  \"id\": 7,\n  \"items\": [\n    [\n      \"tea\",\n      \"milk\"\n    ],\n    []\n  ],\n  \"note\": null\n}";
+        assert_eq!(
+            response.response_body.unwrap().as_bytes(),
+            expected.as_bytes()
+        );
+    }
+
+    /// Tests that a serialization failure becomes a 500 instead of a panic.
+    #[cfg(feature = "serde")]
+    #[test]
+    fn test_json_of_failure() {
+        let mut map = HashMap::new();
+        map.insert(vec![1], "keys must be strings");
+        assert_eq!(Response::json_of(&map).err(), Some(HttpError::internal()));
+        assert_eq!(Json(map).into_response().status_code, 500);
+    }
+}
@@ -1,6 +1,66 @@
 pub mod app;
+#[cfg(feature = "async")]
+pub mod async_app;
+pub mod body_reader;
+#[cfg(feature = "brotli")]
+mod brotli;
+pub mod cache;
+pub mod client;
+pub mod compression;
 pub mod connection;
+pub mod content_type;
+pub mod cookie;
+pub mod cors;
+mod crypto;
+pub mod csrf;
+mod deflate;
+mod download;
+pub mod echo;
+mod error;
+pub mod extensions;
+pub mod extract;
+pub mod forwarded;
+pub mod header_map;
+pub mod hijack;
 pub mod http11_response;
+pub mod http_error;
+mod inflate;
+pub mod into_response;
+pub mod lifecycle;
+pub mod maintenance;
+pub mod method_override;
+pub mod metrics;
+pub mod middleware;
+pub mod negotiate;
+#[cfg(feature = "serde")]
+pub mod openapi;
 pub mod parse_headers;
 pub mod parse_path;
 pub mod parse_url;
+pub mod parsed_body;
+pub mod proxy;
+pub mod query;
+pub mod request_id;
+pub mod response_writer;
+pub mod route_table;
+mod schedule;
+pub mod scope;
+pub mod security_headers;
+mod sendfile;
+pub mod session;
+pub mod shutdown;
+pub mod site_files;
+mod spool;
+pub mod static_files;
+pub mod tasks;
+pub mod template;
+pub mod test;
+pub mod trace;
+pub mod traffic;
+pub mod tunnel;
+pub mod upgrade;
+#[cfg(feature = "serde")]
+pub mod webhook;
+mod worker_pool;
+
+pub use error::Error;
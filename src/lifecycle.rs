@@ -0,0 +1,146 @@
+use crate::app::{App, RouteInfo};
+use std::net::SocketAddr;
+use std::panic::{self, AssertUnwindSafe};
+
+/// Runs once the server is listening, before it serves any request.
+pub(crate) type StartHook = Box<dyn Fn(&ServerInfo) + Send + Sync>;
+
+/// Runs once the server has stopped.
+pub(crate) type ShutdownHook = Box<dyn Fn() + Send + Sync>;
+
+/// What a server passes to its [`App::on_start`] hooks.
+#[derive(Debug, Clone)]
+pub struct ServerInfo {
+    /// The address the server is listening at, with the real port when it was bound
+    /// to port 0.
+    pub local_addr: SocketAddr,
+}
+
+impl App {
+    /// Runs `hook` once the server is listening, before it serves any request, for
+    /// initialization such as running migrations or warming caches.
+    ///
+    /// Hooks run in the order they were added, on the thread that started the server.
+    /// Connections arriving meanwhile wait in the listener's backlog. A hook that
+    /// panics aborts the startup: the server stops listening without serving any
+    /// request, and the remaining hooks do not run.
+    ///
+    /// # Arguments
+    ///
+    /// * `hook` - What to run, given the address the server is listening at.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use rustic::app::App;
+    ///
+    /// let mut application = App::new();
+    /// application.on_start(|server| println!("Serving at http://{}", server.local_addr));
+    /// ```
+    pub fn on_start<F>(&mut self, hook: F)
+    where
+        F: Fn(&ServerInfo) + Send + Sync + 'static,
+    {
+        self.start_hooks.push(Box::new(hook));
+    }
+
+    /// Runs `hook` once the server has stopped, after in-flight requests and background
+    /// tasks were drained, for cleanup such as flushing buffers or closing pools.
+    ///
+    /// Hooks run in the order they were added. A hook that panics is logged, and the
+    /// remaining hooks still run. They do not run when startup was aborted by an
+    /// [`App::on_start`] hook.
+    ///
+    /// # Arguments
+    ///
+    /// * `hook` - What to run.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use rustic::app::App;
+    ///
+    /// let mut application = App::new();
+    /// application.on_shutdown(|| println!("Goodbye"));
+    /// ```
+    pub fn on_shutdown<F>(&mut self, hook: F)
+    where
+        F: Fn() + Send + Sync + 'static,
+    {
+        self.shutdown_hooks.push(Box::new(hook));
+    }
+}
+
+/// Runs the start hooks in order, stopping at the first that panics.
+///
+/// # Returns
+///
+/// * `bool` - Whether every hook returned.
+pub(crate) fn run_start_hooks(app: &App, server: &ServerInfo) -> bool {
+    for hook in &app.start_hooks {
+        if panic::catch_unwind(AssertUnwindSafe(|| hook(server))).is_err() {
+            log::error!("A start hook panicked, so the server is not starting");
+            return false;
+        }
+    }
+    true
+}
+
+/// Logs what the server runs with, then a line for each endpoint sorted by pattern then
+/// method, as set by [`ServerConfig::log_startup`](crate::app::ServerConfig::log_startup).
+///
+/// # Arguments
+///
+/// * `app` - The application being served.
+/// * `server` - Where it is served.
+/// * `workers` - The number of worker threads, or `None` for the async server.
+pub(crate) fn log_startup(app: &App, server: &ServerInfo, workers: Option<usize>) {
+    let threads = match workers {
+        Some(workers) => format!("{} workers", workers),
+        None => "tokio".to_string(),
+    };
+    log::info!(
+        "rustic {} listening at {} with {}, TLS off",
+        env!("CARGO_PKG_VERSION"),
+        server.local_addr,
+        threads
+    );
+    let mut routes = app.routes.list();
+    if routes.is_empty() {
+        log::info!("No endpoints are registered");
+        return;
+    }
+    routes.sort_by(|a, b| {
+        a.pattern
+            .cmp(&b.pattern)
+            .then_with(|| method_name(a).cmp(method_name(b)))
+    });
+    let patterns: Vec<String> = routes
+        .iter()
+        .map(|route| format!("/{}", route.pattern.trim_start_matches('/')))
+        .collect();
+    let width = patterns.iter().map(String::len).max().unwrap_or(0);
+    for (route, pattern) in routes.iter().zip(&patterns) {
+        log::info!(
+            "Route {:<7} {:<width$}  {}",
+            method_name(route),
+            pattern,
+            route.name.as_deref().unwrap_or("-"),
+            width = width
+        );
+    }
+}
+
+/// Returns the method of a listed endpoint, `ANY` for one serving every method.
+fn method_name(route: &RouteInfo) -> &'static str {
+    route.method.map_or("ANY", |method| method.as_str())
+}
+
+/// Runs every shutdown hook in order.
+pub(crate) fn run_shutdown_hooks(app: &App) {
+    for hook in &app.shutdown_hooks {
+        if panic::catch_unwind(AssertUnwindSafe(hook)).is_err() {
+            log::error!("A shutdown hook panicked");
+        }
+    }
+}
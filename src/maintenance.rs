@@ -0,0 +1,167 @@
+use crate::http11_response::Response;
+use crate::into_response::text_response;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+/// The default of [`Maintenance::retry_after`].
+pub const DEFAULT_MAINTENANCE_RETRY_AFTER: Duration = Duration::from_secs(30);
+
+/// A switch putting a running server into maintenance mode, in which requests are
+/// answered with `503 Service Unavailable` instead of being routed.
+///
+/// Install it with [`ServerConfig::maintenance`](crate::app::ServerConfig::maintenance)
+/// and keep a clone to flip it: every clone shares the same switch, which applies to
+/// requests read after it is flipped. The `503` carries a `Retry-After` header and a
+/// plain text body, goes through the app's middleware and is counted in the metrics like
+/// any response, and leaves keep-alive connections open, so that clients are served
+/// normally again on their next request once maintenance is over. Paths on the
+/// [allow-list](Maintenance::allow), such as health checks and admin endpoints, are
+/// served as usual throughout.
+///
+/// # Examples
+///
+/// ```
+/// use rustic::app::{App, ServerConfig};
+/// use rustic::maintenance::Maintenance;
+/// use rustic::test::TestClient;
+///
+/// let maintenance = Maintenance::new().allow("/healthz").body("Back soon");
+/// let mut application = App::new();
+/// application.get("healthz", |_| "ok");
+/// application.get("orders", |_| "no orders");
+/// let config = ServerConfig::new().maintenance(maintenance.clone());
+/// let client = TestClient::with_config(application, config);
+///
+/// maintenance.enable();
+/// let response = client.get("/orders").send();
+/// assert_eq!(response.status, 503);
+/// assert_eq!(response.text(), "Back soon");
+/// assert_eq!(client.get("/healthz").send().text(), "ok");
+///
+/// maintenance.disable();
+/// assert_eq!(client.get("/orders").send().text(), "no orders");
+/// ```
+#[derive(Clone)]
+pub struct Maintenance {
+    enabled: Arc<AtomicBool>,
+    retry_after: Duration,
+    body: String,
+    allowed: Vec<String>,
+}
+
+impl Default for Maintenance {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Maintenance {
+    /// Creates a switch that is off, answering with `Retry-After: 30` and the body
+    /// `Service Unavailable: down for maintenance` once flipped.
+    pub fn new() -> Self {
+        Maintenance {
+            enabled: Arc::new(AtomicBool::new(false)),
+            retry_after: DEFAULT_MAINTENANCE_RETRY_AFTER,
+            body: "Service Unavailable: down for maintenance".to_string(),
+            allowed: Vec::new(),
+        }
+    }
+
+    /// Sets how long clients are asked to wait before retrying, sent in whole seconds.
+    pub fn retry_after(mut self, retry_after: Duration) -> Self {
+        self.retry_after = retry_after;
+        self
+    }
+
+    /// Sets the plain text body of the `503` responses.
+    pub fn body(mut self, body: impl Into<String>) -> Self {
+        self.body = body.into();
+        self
+    }
+
+    /// Keeps serving `path` and every path below it during maintenance, so that
+    /// `/admin` allows `/admin` and `/admin/deploys` but not `/administrators`.
+    pub fn allow(mut self, path: &str) -> Self {
+        self.allowed.push(path.trim_matches('/').to_string());
+        self
+    }
+
+    /// Starts answering requests with `503 Service Unavailable`.
+    pub fn enable(&self) {
+        self.enabled.store(true, Ordering::SeqCst);
+    }
+
+    /// Resumes serving requests normally.
+    pub fn disable(&self) {
+        self.enabled.store(false, Ordering::SeqCst);
+    }
+
+    /// Returns whether maintenance mode is on.
+    pub fn is_enabled(&self) -> bool {
+        self.enabled.load(Ordering::SeqCst)
+    }
+
+    /// Checks whether a request for `path`, without its leading slash, is answered with
+    /// a `503` rather than routed.
+    pub(crate) fn refuses(&self, path: &str) -> bool {
+        self.is_enabled()
+            && !self.allowed.iter().any(|allowed| {
+                allowed.is_empty()
+                    || path
+                        .strip_prefix(allowed.as_str())
+                        .is_some_and(|rest| rest.is_empty() || rest.starts_with('/'))
+            })
+    }
+
+    /// Builds the `503 Service Unavailable` sent during maintenance.
+    pub(crate) fn response(&self) -> Response {
+        let mut response = text_response(503, self.body.clone());
+        response
+            .headers
+            .insert("Retry-After", self.retry_after.as_secs().to_string());
+        response
+    }
+}
+
+#[cfg(test)]
+mod test_maintenance {
+    use super::*;
+
+    /// Tests that allowed paths and the paths below them are served during maintenance,
+    /// and everything else only while it is off.
+    #[test]
+    fn test_refuses() {
+        let maintenance = Maintenance::new().allow("/healthz").allow("admin/");
+        assert!(!maintenance.refuses("orders"));
+
+        maintenance.clone().enable();
+        assert!(maintenance.is_enabled());
+        assert!(maintenance.refuses("orders"));
+        assert!(maintenance.refuses(""));
+        assert!(!maintenance.refuses("healthz"));
+        assert!(!maintenance.refuses("admin"));
+        assert!(!maintenance.refuses("admin/deploys"));
+        assert!(maintenance.refuses("administrators"));
+        assert!(maintenance.refuses("healthz2"));
+
+        maintenance.disable();
+        assert!(!maintenance.refuses("orders"));
+    }
+
+    /// Tests that the response carries the configured body and `Retry-After`.
+    #[test]
+    fn test_response() {
+        let response = Maintenance::new()
+            .retry_after(Duration::from_millis(120_500))
+            .body("Upgrading")
+            .response();
+        assert_eq!(response.status_code, 503);
+        assert_eq!(response.header("Retry-After"), Some("120"));
+        assert_eq!(
+            response.header("Content-Type"),
+            Some("text/plain; charset=utf-8")
+        );
+        assert_eq!(response.response_body.unwrap().as_bytes(), b"Upgrading");
+    }
+}
@@ -0,0 +1,213 @@
+use crate::app::Request;
+use crate::http11_response::Response;
+use crate::middleware::{Middleware, Next};
+use crate::parse_headers::RequestType;
+use crate::parse_url::form_pairs;
+
+/// The method a request was sent with, stored in its extensions by [`MethodOverride`]
+/// when the method was overridden.
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct OriginalMethod(RequestType);
+
+impl Request {
+    /// Returns the method the request was sent with, before any [`MethodOverride`].
+    pub fn original_method(&self) -> RequestType {
+        self.extensions
+            .get::<OriginalMethod>()
+            .map_or(self.method, |original| original.0)
+    }
+}
+
+/// Middleware letting `POST` requests stand in for `PUT`, `PATCH` and `DELETE`.
+///
+/// HTML forms can only send `GET` and `POST`. With this middleware, a `POST` request
+/// naming another method in its `X-HTTP-Method-Override` header or, for a form body
+/// (`application/x-www-form-urlencoded`), in its `_method` field is routed as a request
+/// with that method. The header wins when both are present. Only `PUT`, `PATCH` and
+/// `DELETE` can be requested, so that an override cannot turn a request into a safe
+/// method that skips CSRF checks or lands in a cache. Requests with other methods are
+/// never overridden.
+///
+/// The body is left untouched for the handler, and the method the request was sent with
+/// remains available through [`Request::original_method`].
+///
+/// # Examples
+///
+/// ```
+/// use rustic::app::App;
+/// use rustic::method_override::MethodOverride;
+///
+/// let mut application = App::new();
+/// application.add_middleware(MethodOverride::new());
+/// application.delete("posts/{id}", |request| {
+///     format!("Deleted post {}", request.path_param("id").unwrap_or_default())
+/// });
+/// ```
+#[derive(Debug, Clone)]
+pub struct MethodOverride {
+    header: Option<String>,
+    form_field: Option<String>,
+}
+
+impl Default for MethodOverride {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl MethodOverride {
+    /// Creates the middleware reading the `X-HTTP-Method-Override` header and the
+    /// `_method` form field.
+    pub fn new() -> Self {
+        MethodOverride {
+            header: Some("X-HTTP-Method-Override".to_string()),
+            form_field: Some("_method".to_string()),
+        }
+    }
+
+    /// Sets or disables the header naming the method.
+    pub fn header<'a>(mut self, name: impl Into<Option<&'a str>>) -> Self {
+        self.header = name.into().map(str::to_string);
+        self
+    }
+
+    /// Sets or disables the form field naming the method.
+    pub fn form_field<'a>(mut self, name: impl Into<Option<&'a str>>) -> Self {
+        self.form_field = name.into().map(str::to_string);
+        self
+    }
+
+    /// Finds the method a `POST` request asks to be treated as, if any.
+    fn requested_method(&self, request: &Request) -> Option<String> {
+        if let Some(method) = self.header.as_deref().and_then(|name| request.header(name)) {
+            return Some(method.to_string());
+        }
+        let field = self.form_field.as_deref()?;
+        let is_form = request.header("Content-Type").is_some_and(|content_type| {
+            content_type
+                .split(';')
+                .next()
+                .unwrap_or("")
+                .trim()
+                .eq_ignore_ascii_case("application/x-www-form-urlencoded")
+        });
+        if !is_form {
+            return None;
+        }
+        form_pairs(&request.body)
+            .find(|(key, _)| key == field)
+            .map(|(_, value)| value)
+    }
+}
+
+impl Middleware for MethodOverride {
+    fn handle(&self, mut request: Request, next: Next) -> Response {
+        if request.method == RequestType::POST {
+            let method = self.requested_method(&request).and_then(|method| {
+                match method.trim().to_ascii_uppercase().as_str() {
+                    "PUT" => Some(RequestType::PUT),
+                    "PATCH" => Some(RequestType::PATCH),
+                    "DELETE" => Some(RequestType::DELETE),
+                    _ => None,
+                }
+            });
+            if let Some(method) = method {
+                request.extensions.insert(OriginalMethod(request.method));
+                request.method = method;
+            }
+        }
+        next.run(request)
+    }
+}
+
+#[cfg(test)]
+mod test_method_override {
+    use super::*;
+    use crate::app::App;
+
+    fn request(method: RequestType, headers: &[(&str, &str)], body: &str) -> Request {
+        Request {
+            method,
+            path: "posts/1".to_string(),
+            url: "/posts/1".to_string(),
+            headers: headers
+                .iter()
+                .map(|(name, value)| (name.to_string(), value.to_string()))
+                .collect(),
+            body: body.to_string(),
+            ..Request::default()
+        }
+    }
+
+    /// Builds an application echoing the method a request was routed as.
+    fn app(middleware: MethodOverride) -> App {
+        let mut application = App::new();
+        application.add_middleware(middleware);
+        for method in [
+            RequestType::GET,
+            RequestType::POST,
+            RequestType::PUT,
+            RequestType::DELETE,
+        ] {
+            application.add_endpoint("posts/1", method, |request: Request| {
+                format!(
+                    "{} via {} with {}",
+                    request.method.as_str(),
+                    request.original_method().as_str(),
+                    request.body
+                )
+            });
+        }
+        application
+    }
+
+    fn body(response: Response) -> String {
+        String::from_utf8(response.response_body.unwrap().as_bytes().to_vec()).unwrap()
+    }
+
+    const FORM: (&str, &str) = ("Content-Type", "application/x-www-form-urlencoded");
+
+    /// Tests that a form field or header reroutes a `POST`, keeping the body intact.
+    #[test]
+    fn test_override() {
+        let application = app(MethodOverride::new());
+        let form = request(RequestType::POST, &[FORM], "title=x&_method=delete");
+        assert_eq!(
+            body(application.dispatch(form)),
+            "DELETE via POST with title=x&_method=delete"
+        );
+        let header = request(
+            RequestType::POST,
+            &[("X-HTTP-Method-Override", "PUT"), FORM],
+            "_method=DELETE",
+        );
+        assert_eq!(
+            body(application.dispatch(header)),
+            "PUT via POST with _method=DELETE"
+        );
+    }
+
+    /// Tests that safe target methods, other source methods and non-form bodies are refused.
+    #[test]
+    fn test_refused_overrides() {
+        let application = app(MethodOverride::new());
+        let to_get = request(RequestType::POST, &[("X-HTTP-Method-Override", "GET")], "");
+        assert_eq!(body(application.dispatch(to_get)), "POST via POST with ");
+        let from_get = request(
+            RequestType::GET,
+            &[("X-HTTP-Method-Override", "DELETE")],
+            "",
+        );
+        assert_eq!(body(application.dispatch(from_get)), "GET via GET with ");
+        let json = request(
+            RequestType::POST,
+            &[("Content-Type", "application/json")],
+            "_method=DELETE",
+        );
+        assert!(body(application.dispatch(json)).starts_with("POST via POST"));
+
+        let header_only = app(MethodOverride::new().form_field(None));
+        let form = request(RequestType::POST, &[FORM], "_method=DELETE");
+        assert!(body(header_only.dispatch(form)).starts_with("POST via POST"));
+    }
+}
@@ -0,0 +1,375 @@
+use crate::app::App;
+use crate::header_map::HeaderMap;
+use crate::http11_response::Response;
+use crate::parse_headers::RequestType;
+use std::collections::HashMap;
+use std::fmt::Write;
+use std::sync::atomic::{AtomicI64, AtomicU64, Ordering};
+use std::sync::{Arc, RwLock};
+use std::time::Duration;
+
+/// Upper bounds of the request duration histogram buckets, in seconds.
+const DURATION_BUCKETS: [f64; 11] = [
+    0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0,
+];
+
+/// Counters describing the requests a server has handled.
+///
+/// Every value is an atomic, so recording a request only takes a lock, for writing,
+/// the first time its route is seen. The registry is created by
+/// [`App::enable_metrics_endpoint`] and updated by `run` around each dispatched
+/// request.
+///
+/// Requests are also counted per route, labelled with the
+/// [pattern](crate::app::Request::matched_route) of the endpoint they were routed to
+/// rather than their path, so that there is one series per endpoint however many
+/// paths it serves. Requests matching no endpoint share the
+/// [`UNMATCHED_ROUTE`](crate::app::UNMATCHED_ROUTE) series.
+#[derive(Debug, Default)]
+pub struct Metrics {
+    /// Requests by status class, from `1xx` at index 0 to `5xx` at index 4.
+    requests: [AtomicU64; 5],
+    /// Request counts per duration bucket, with the overflow bucket last.
+    duration_buckets: [AtomicU64; DURATION_BUCKETS.len() + 1],
+    duration_sum_micros: AtomicU64,
+    in_flight: AtomicI64,
+    bytes_read: AtomicU64,
+    bytes_written: AtomicU64,
+    connections_shed: AtomicU64,
+    responses_aborted: AtomicU64,
+    /// The series of each route pattern seen so far.
+    routes: RwLock<HashMap<String, RouteSeries>>,
+}
+
+/// The series of requests routed to one endpoint.
+#[derive(Debug, Default)]
+struct RouteSeries {
+    /// Requests by status class, as in [`Metrics`].
+    requests: [AtomicU64; 5],
+    duration_sum_micros: AtomicU64,
+}
+
+impl RouteSeries {
+    /// Records a finished request in status `class`.
+    fn record(&self, class: usize, duration: Duration) {
+        self.requests[class].fetch_add(1, Ordering::Relaxed);
+        self.duration_sum_micros
+            .fetch_add(duration.as_micros() as u64, Ordering::Relaxed);
+    }
+}
+
+impl Metrics {
+    /// Creates a registry with every series at zero.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Marks a request as being handled.
+    pub(crate) fn request_started(&self) {
+        self.in_flight.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Records a finished request routed to the endpoint with pattern `route`, the bytes
+    /// read for it and those written in answer to it.
+    pub(crate) fn request_finished(
+        &self,
+        route: &str,
+        status_code: u16,
+        duration: Duration,
+        read: usize,
+        written: usize,
+    ) {
+        self.in_flight.fetch_sub(1, Ordering::Relaxed);
+        let class = (status_code / 100).clamp(1, 5) as usize - 1;
+        self.requests[class].fetch_add(1, Ordering::Relaxed);
+
+        let seconds = duration.as_secs_f64();
+        let bucket = DURATION_BUCKETS
+            .iter()
+            .position(|&bound| seconds <= bound)
+            .unwrap_or(DURATION_BUCKETS.len());
+        self.duration_buckets[bucket].fetch_add(1, Ordering::Relaxed);
+        self.duration_sum_micros
+            .fetch_add(duration.as_micros() as u64, Ordering::Relaxed);
+        self.bytes_read.fetch_add(read as u64, Ordering::Relaxed);
+        self.bytes_written
+            .fetch_add(written as u64, Ordering::Relaxed);
+
+        if let Some(series) = self.routes.read().unwrap().get(route) {
+            series.record(class, duration);
+            return;
+        }
+        self.routes
+            .write()
+            .unwrap()
+            .entry(route.to_string())
+            .or_default()
+            .record(class, duration);
+    }
+
+    /// Records a response whose write failed, such as one to a client that stopped
+    /// reading, taking the `unsent` bytes of it back out of the bytes written.
+    pub(crate) fn response_aborted(&self, unsent: usize) {
+        self.responses_aborted.fetch_add(1, Ordering::Relaxed);
+        self.bytes_written
+            .fetch_sub(unsent as u64, Ordering::Relaxed);
+    }
+
+    /// Records a connection refused with `503 Service Unavailable` because the server
+    /// was overloaded.
+    pub(crate) fn connection_shed(&self) {
+        self.connections_shed.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Renders every series in the Prometheus text exposition format.
+    ///
+    /// # Returns
+    ///
+    /// * `String` - The scrape output.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use rustic::metrics::Metrics;
+    /// let metrics = Metrics::new();
+    /// assert!(metrics
+    ///     .render()
+    ///     .contains("rustic_http_requests_total{class=\"2xx\"} 0\n"));
+    /// ```
+    pub fn render(&self) -> String {
+        let mut output = String::new();
+
+        output.push_str("# HELP rustic_http_requests_total Requests handled, by status class.\n");
+        output.push_str("# TYPE rustic_http_requests_total counter\n");
+        for (i, count) in self.requests.iter().enumerate() {
+            let _ = writeln!(
+                output,
+                "rustic_http_requests_total{{class=\"{}xx\"}} {}",
+                i + 1,
+                count.load(Ordering::Relaxed)
+            );
+        }
+
+        output.push_str(
+            "# HELP rustic_http_request_duration_seconds Time spent handling a request.\n",
+        );
+        output.push_str("# TYPE rustic_http_request_duration_seconds histogram\n");
+        let mut cumulative = 0;
+        for (i, count) in self.duration_buckets.iter().enumerate() {
+            cumulative += count.load(Ordering::Relaxed);
+            let bound = match DURATION_BUCKETS.get(i) {
+                Some(bound) => bound.to_string(),
+                None => "+Inf".to_string(),
+            };
+            let _ = writeln!(
+                output,
+                "rustic_http_request_duration_seconds_bucket{{le=\"{}\"}} {}",
+                bound, cumulative
+            );
+        }
+        let sum = self.duration_sum_micros.load(Ordering::Relaxed) as f64 / 1_000_000.0;
+        let _ = writeln!(output, "rustic_http_request_duration_seconds_sum {}", sum);
+        let _ = writeln!(
+            output,
+            "rustic_http_request_duration_seconds_count {}",
+            cumulative
+        );
+
+        output
+            .push_str("# HELP rustic_http_requests_in_flight Requests currently being handled.\n");
+        output.push_str("# TYPE rustic_http_requests_in_flight gauge\n");
+        let _ = writeln!(
+            output,
+            "rustic_http_requests_in_flight {}",
+            self.in_flight.load(Ordering::Relaxed)
+        );
+
+        output.push_str(
+            "# HELP rustic_http_request_bytes_total Bytes read in requests, heads included.\n",
+        );
+        output.push_str("# TYPE rustic_http_request_bytes_total counter\n");
+        let _ = writeln!(
+            output,
+            "rustic_http_request_bytes_total {}",
+            self.bytes_read.load(Ordering::Relaxed)
+        );
+
+        output.push_str("# HELP rustic_http_response_bytes_total Bytes written in responses.\n");
+        output.push_str("# TYPE rustic_http_response_bytes_total counter\n");
+        let _ = writeln!(
+            output,
+            "rustic_http_response_bytes_total {}",
+            self.bytes_written.load(Ordering::Relaxed)
+        );
+
+        output.push_str(
+            "# HELP rustic_http_connections_shed_total Connections refused under load.\n",
+        );
+        output.push_str("# TYPE rustic_http_connections_shed_total counter\n");
+        let _ = writeln!(
+            output,
+            "rustic_http_connections_shed_total {}",
+            self.connections_shed.load(Ordering::Relaxed)
+        );
+
+        output.push_str(
+            "# HELP rustic_http_responses_aborted_total Responses whose write failed before \
+             they were sent whole.\n",
+        );
+        output.push_str("# TYPE rustic_http_responses_aborted_total counter\n");
+        let _ = writeln!(
+            output,
+            "rustic_http_responses_aborted_total {}",
+            self.responses_aborted.load(Ordering::Relaxed)
+        );
+
+        let routes = self.routes.read().unwrap();
+        let mut patterns: Vec<&String> = routes.keys().collect();
+        patterns.sort();
+        output.push_str(
+            "# HELP rustic_http_route_requests_total Requests handled, by route pattern and \
+             status class.\n",
+        );
+        output.push_str("# TYPE rustic_http_route_requests_total counter\n");
+        for pattern in &patterns {
+            let label = escape_label(pattern);
+            for (i, count) in routes[*pattern].requests.iter().enumerate() {
+                let count = count.load(Ordering::Relaxed);
+                if count > 0 {
+                    let _ = writeln!(
+                        output,
+                        "rustic_http_route_requests_total{{route=\"{}\",class=\"{}xx\"}} {}",
+                        label,
+                        i + 1,
+                        count
+                    );
+                }
+            }
+        }
+        output.push_str(
+            "# HELP rustic_http_route_request_duration_seconds Time spent handling a request, \
+             by route pattern.\n",
+        );
+        output.push_str("# TYPE rustic_http_route_request_duration_seconds summary\n");
+        for pattern in &patterns {
+            let label = escape_label(pattern);
+            let series = &routes[*pattern];
+            let sum = series.duration_sum_micros.load(Ordering::Relaxed) as f64 / 1_000_000.0;
+            let count: u64 = series
+                .requests
+                .iter()
+                .map(|count| count.load(Ordering::Relaxed))
+                .sum();
+            let _ = writeln!(
+                output,
+                "rustic_http_route_request_duration_seconds_sum{{route=\"{}\"}} {}",
+                label, sum
+            );
+            let _ = writeln!(
+                output,
+                "rustic_http_route_request_duration_seconds_count{{route=\"{}\"}} {}",
+                label, count
+            );
+        }
+
+        output
+    }
+}
+
+/// Escapes a label value for the text exposition format, in which backslashes, double
+/// quotes and line feeds are written as escape sequences.
+fn escape_label(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+impl App {
+    /// Starts collecting request metrics and serves them at `path`.
+    ///
+    /// The endpoint answers `GET` requests with the registry rendered in the Prometheus
+    /// text exposition format.
+    ///
+    /// # Arguments
+    ///
+    /// * `path` - The path to serve the metrics at; a leading `/` is ignored.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use rustic::app::App;
+    /// let mut application = App::new();
+    /// application.enable_metrics_endpoint("/metrics");
+    /// ```
+    pub fn enable_metrics_endpoint(&mut self, path: &str) {
+        let metrics = Arc::clone(self.metrics.get_or_insert_with(Default::default));
+        self.add_endpoint(path.trim_start_matches('/'), RequestType::GET, move |_| {
+            let mut headers = HeaderMap::new();
+            headers.insert("Content-Type", "text/plain; version=0.0.4");
+            Some(Response {
+                status_code: 200,
+                reason: "OK".into(),
+                response_body: Some(metrics.render().into()),
+                headers,
+            })
+        });
+    }
+}
+
+#[cfg(test)]
+mod test_metrics {
+    use super::*;
+    use crate::app::UNMATCHED_ROUTE;
+
+    /// Tests that finished requests land in their status class and duration bucket.
+    #[test]
+    fn test_request_finished() {
+        let metrics = Metrics::new();
+        metrics.request_started();
+        metrics.request_started();
+        metrics.request_finished(UNMATCHED_ROUTE, 404, Duration::from_millis(20), 60, 100);
+
+        let output = metrics.render();
+        assert!(output.contains("rustic_http_requests_total{class=\"4xx\"} 1\n"));
+        assert!(output.contains("rustic_http_request_duration_seconds_bucket{le=\"0.01\"} 0\n"));
+        assert!(output.contains("rustic_http_request_duration_seconds_bucket{le=\"0.025\"} 1\n"));
+        assert!(output.contains("rustic_http_request_duration_seconds_bucket{le=\"+Inf\"} 1\n"));
+        assert!(output.contains("rustic_http_request_duration_seconds_sum 0.02\n"));
+        assert!(output.contains("rustic_http_requests_in_flight 1\n"));
+        assert!(output.contains("rustic_http_request_bytes_total 60\n"));
+        assert!(output.contains("rustic_http_response_bytes_total 100\n"));
+        assert!(output.contains("rustic_http_connections_shed_total 0\n"));
+
+        metrics.response_aborted(40);
+        let output = metrics.render();
+        assert!(output.contains("rustic_http_response_bytes_total 60\n"));
+        assert!(output.contains("rustic_http_responses_aborted_total 1\n"));
+    }
+
+    /// Tests that requests are counted per route pattern, with label values escaped.
+    #[test]
+    fn test_route_series() {
+        let metrics = Metrics::new();
+        for (route, status_code) in [("users/{id}", 200), ("users/{id}", 500), ("a\"b", 200)] {
+            metrics.request_started();
+            metrics.request_finished(route, status_code, Duration::from_millis(250), 0, 0);
+        }
+
+        let output = metrics.render();
+        for line in [
+            "rustic_http_route_requests_total{route=\"users/{id}\",class=\"2xx\"} 1\n",
+            "rustic_http_route_requests_total{route=\"users/{id}\",class=\"5xx\"} 1\n",
+            "rustic_http_route_requests_total{route=\"a\\\"b\",class=\"2xx\"} 1\n",
+            "rustic_http_route_request_duration_seconds_sum{route=\"users/{id}\"} 0.5\n",
+            "rustic_http_route_request_duration_seconds_count{route=\"users/{id}\"} 2\n",
+        ] {
+            assert!(output.contains(line), "{}", output);
+        }
+        assert!(
+            !output.contains("route=\"users/{id}\",class=\"4xx\""),
+            "{}",
+            output
+        );
+    }
+}
@@ -0,0 +1,79 @@
+use crate::app::Request;
+use crate::http11_response::Response;
+use std::sync::Arc;
+
+/// A layer wrapped around request dispatch.
+///
+/// Middleware receives every request before it is routed, together with a [`Next`]
+/// handle to the rest of the chain. Calling `next.run(request)` continues towards the
+/// endpoint and yields its response, which the middleware may then inspect or modify
+/// before returning it. Returning a response without calling `next` short-circuits
+/// the chain.
+///
+/// Closures of the shape `Fn(Request, Next) -> Response` implement this trait.
+///
+/// # Examples
+///
+/// ```
+/// use rustic::app::App;
+/// use rustic::middleware::Next;
+/// use rustic::app::Request;
+///
+/// let mut application = App::new();
+/// application.add_middleware(|request: Request, next: Next| {
+///     let mut response = next.run(request);
+///     response
+///         .headers
+///         .insert("X-Powered-By".to_string(), "rustic".to_string());
+///     response
+/// });
+/// ```
+pub trait Middleware: Send + Sync {
+    /// Handles a request, usually by delegating to `next` and post-processing its response.
+    fn handle(&self, request: Request, next: Next) -> Response;
+}
+
+impl<F> Middleware for F
+where
+    F: Fn(Request, Next) -> Response + Send + Sync,
+{
+    fn handle(&self, request: Request, next: Next) -> Response {
+        self(request, next)
+    }
+}
+
+/// Middleware shared by the chains of several endpoints, such as those of a
+/// [`Scope`](crate::scope::Scope).
+pub(crate) struct SharedMiddleware(pub(crate) Arc<dyn Middleware>);
+
+impl Middleware for SharedMiddleware {
+    fn handle(&self, request: Request, next: Next) -> Response {
+        self.0.handle(request, next)
+    }
+}
+
+/// The remainder of a middleware chain, ending in the routed endpoint.
+pub struct Next<'a> {
+    middleware: &'a [Box<dyn Middleware>],
+    endpoint: &'a dyn Fn(Request) -> Response,
+}
+
+impl<'a> Next<'a> {
+    pub(crate) fn new(
+        middleware: &'a [Box<dyn Middleware>],
+        endpoint: &'a dyn Fn(Request) -> Response,
+    ) -> Self {
+        Next {
+            middleware,
+            endpoint,
+        }
+    }
+
+    /// Passes the request on to the next middleware, or to the endpoint if none remain.
+    pub fn run(self, request: Request) -> Response {
+        match self.middleware.split_first() {
+            Some((current, rest)) => current.handle(request, Next::new(rest, self.endpoint)),
+            None => (self.endpoint)(request),
+        }
+    }
+}
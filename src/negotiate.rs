@@ -0,0 +1,286 @@
+use crate::app::Request;
+use crate::http11_response::Response;
+use crate::http_error::HttpError;
+
+/// A media range of an `Accept` header, such as `text/*;q=0.8`.
+#[derive(Debug, Clone, PartialEq)]
+struct MediaRange<'a> {
+    kind: &'a str,
+    subtype: &'a str,
+    quality: f32,
+}
+
+impl MediaRange<'_> {
+    /// Rates how specifically the range names a media type: 2 for an exact match, 1 for a
+    /// `type/*` range, 0 for `*/*`, or `None` if it does not match.
+    fn specificity(&self, kind: &str, subtype: &str) -> Option<u8> {
+        if self.kind == "*" {
+            Some(0)
+        } else if !self.kind.eq_ignore_ascii_case(kind) {
+            None
+        } else if self.subtype == "*" {
+            Some(1)
+        } else {
+            self.subtype.eq_ignore_ascii_case(subtype).then_some(2)
+        }
+    }
+}
+
+/// Parses the media ranges of an `Accept` header, skipping malformed ones.
+///
+/// Parameters other than `q` are ignored. A `q` value that is not a number counts as
+/// `1.0`, and values are clamped to the `0.0..=1.0` range.
+fn parse_accept(accept: &str) -> Vec<MediaRange<'_>> {
+    accept
+        .split(',')
+        .filter_map(|item| {
+            let mut parts = item.split(';');
+            let (kind, subtype) = parts.next()?.trim().split_once('/')?;
+            if kind.is_empty() || subtype.is_empty() || (kind == "*" && subtype != "*") {
+                return None;
+            }
+            let quality = parts
+                .find_map(|param| {
+                    let (key, value) = param.split_once('=')?;
+                    let quality = value.trim().parse::<f32>().ok();
+                    let quality = quality.filter(|quality| !quality.is_nan());
+                    key.trim()
+                        .eq_ignore_ascii_case("q")
+                        .then(|| quality.unwrap_or(1.0))
+                })
+                .unwrap_or(1.0)
+                .clamp(0.0, 1.0);
+            Some(MediaRange {
+                kind: kind.trim(),
+                subtype: subtype.trim(),
+                quality,
+            })
+        })
+        .collect()
+}
+
+/// Returns the quality the most specific matching range gives a media type.
+fn quality(ranges: &[MediaRange], media_type: &str) -> f32 {
+    let essence = media_type.split(';').next().unwrap_or("").trim();
+    let (kind, subtype) = essence.split_once('/').unwrap_or((essence, ""));
+    ranges
+        .iter()
+        .filter_map(|range| Some((range.specificity(kind, subtype)?, range.quality)))
+        .max_by_key(|(specificity, _)| *specificity)
+        .map_or(0.0, |(_, quality)| quality)
+}
+
+impl Request {
+    /// Picks the media type to respond with from those the handler can produce, following
+    /// the request's `Accept` header.
+    ///
+    /// Each offer takes the quality of the most specific media range matching it, so
+    /// `text/html` beats `text/*`, which beats `*/*`. The offer with the highest quality
+    /// above zero wins, and ties go to the earlier offer. Without an `Accept` header,
+    /// the first offer is picked.
+    ///
+    /// # Arguments
+    ///
+    /// * `offers` - The media types the handler can produce, in order of preference.
+    ///
+    /// # Returns
+    ///
+    /// * `Option<&str>` - The chosen offer, or `None` if the client accepts none of them.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use rustic::app::App;
+    ///
+    /// let mut application = App::new();
+    /// application.get("report", |request| {
+    ///     match request.negotiate(&["application/json", "text/html"]) {
+    ///         Some("text/html") => "<p>ok</p>",
+    ///         _ => "{\"status\":\"ok\"}",
+    ///     }
+    /// });
+    /// ```
+    pub fn negotiate<'a>(&self, offers: &[&'a str]) -> Option<&'a str> {
+        let Some(accept) = self.header("Accept") else {
+            return offers.first().copied();
+        };
+        let ranges = parse_accept(accept);
+        let mut best = None;
+        let mut best_quality = 0.0;
+        for offer in offers {
+            let quality = quality(&ranges, offer);
+            if quality > best_quality {
+                best = Some(*offer);
+                best_quality = quality;
+            }
+        }
+        best
+    }
+}
+
+/// Renders a response in the media type the client prefers among those offered.
+///
+/// The renderer of the type chosen by [`Request::negotiate`] is called, its response
+/// gets that `Content-Type` unless the renderer set one, and `Vary: Accept` is added so
+/// that caches keep the representations apart. When the client accepts none of the
+/// offered types, the answer is `406 Not Acceptable` listing them.
+///
+/// # Arguments
+///
+/// * `request` - The request being answered.
+/// * `renderers` - The offered media types, in order of preference, with what renders each.
+///
+/// # Returns
+///
+/// * `Response` - The rendered response, or the `406 Not Acceptable` error.
+///
+/// # Examples
+///
+/// ```
+/// use rustic::app::App;
+/// use rustic::into_response::body_response;
+/// use rustic::negotiate::respond_with;
+///
+/// let mut application = App::new();
+/// application.get("status", |request| {
+///     respond_with(
+///         &request,
+///         &[
+///             ("application/json", &|| body_response(200, "application/json", "{\"up\":true}")),
+///             ("text/html", &|| body_response(200, "text/html", "<p>Up</p>")),
+///         ],
+///     )
+/// });
+/// ```
+pub fn respond_with(request: &Request, renderers: &[(&str, &dyn Fn() -> Response)]) -> Response {
+    let offers: Vec<&str> = renderers
+        .iter()
+        .map(|(media_type, _)| *media_type)
+        .collect();
+    let mut response = match request.negotiate(&offers) {
+        Some(chosen) => {
+            let (_, render) = renderers
+                .iter()
+                .find(|(media_type, _)| *media_type == chosen)
+                .expect("the chosen media type is one of the offers");
+            let mut response = render();
+            if !response.headers.contains_key("Content-Type") {
+                response.headers.insert("Content-Type", chosen);
+            }
+            response
+        }
+        None => HttpError::new(
+            406,
+            format!("Not Acceptable: available types are {}", offers.join(", ")),
+        )
+        .into(),
+    };
+    response.headers.append("Vary", "Accept");
+    response
+}
+
+#[cfg(test)]
+mod test_negotiate {
+    use super::*;
+    use crate::header_map::HeaderMap;
+    use crate::into_response::text_response;
+    use crate::parse_headers::RequestType;
+
+    /// The `Accept` header Firefox sends for page loads.
+    const FIREFOX: &str =
+        "text/html,application/xhtml+xml,application/xml;q=0.9,image/avif,image/webp,*/*;q=0.8";
+    /// The `Accept` header Chrome sends for page loads.
+    const CHROME: &str = "text/html,application/xhtml+xml,application/xml;q=0.9,image/avif,\
+                          image/webp,image/apng,*/*;q=0.8,application/signed-exchange;v=b3;q=0.7";
+
+    fn request(accept: Option<&str>) -> Request {
+        let mut headers = HeaderMap::new();
+        if let Some(accept) = accept {
+            headers.insert("Accept", accept);
+        }
+        Request {
+            method: RequestType::GET,
+            path: String::new(),
+            url: "/".to_string(),
+            headers,
+            body: String::new(),
+            ..Request::default()
+        }
+    }
+
+    const OFFERS: [&str; 2] = ["application/json", "text/html"];
+
+    /// Tests negotiation against real browser and curl `Accept` headers.
+    #[test]
+    fn test_negotiate_real_clients() {
+        assert_eq!(request(Some(FIREFOX)).negotiate(&OFFERS), Some("text/html"));
+        assert_eq!(request(Some(CHROME)).negotiate(&OFFERS), Some("text/html"));
+        assert_eq!(
+            request(Some("*/*")).negotiate(&OFFERS),
+            Some("application/json")
+        );
+        assert_eq!(request(None).negotiate(&OFFERS), Some("application/json"));
+        assert_eq!(
+            request(Some("application/json, text/javascript, */*; q=0.01")).negotiate(&OFFERS),
+            Some("application/json")
+        );
+    }
+
+    /// Tests that specific ranges override wildcards and that `q=0` excludes a type.
+    #[test]
+    fn test_negotiate_precedence() {
+        let accept = "text/*;q=0.5, text/html;q=0, */*;q=0.1";
+        assert_eq!(
+            request(Some(accept)).negotiate(&OFFERS),
+            Some("application/json")
+        );
+        assert_eq!(
+            request(Some(accept)).negotiate(&["text/html", "text/plain"]),
+            Some("text/plain")
+        );
+        assert_eq!(request(Some("image/png")).negotiate(&OFFERS), None);
+        assert_eq!(
+            request(Some("application/*;q=0, */*")).negotiate(&OFFERS),
+            Some("text/html")
+        );
+        assert_eq!(
+            request(Some("TEXT/HTML")).negotiate(&["text/html; charset=utf-8"]),
+            Some("text/html; charset=utf-8")
+        );
+    }
+
+    /// Tests that malformed q-values count as `1.0` and malformed ranges are skipped.
+    #[test]
+    fn test_parse_accept_malformed() {
+        let ranges = parse_accept("text/html;q=high, application/json;q=0.2, garbage, */json");
+        assert_eq!(ranges.len(), 2);
+        assert_eq!(ranges[0].quality, 1.0);
+        assert_eq!(quality(&ranges, "application/json"), 0.2);
+    }
+
+    /// Tests that `respond_with` renders the chosen type and refuses with `406` otherwise.
+    #[test]
+    fn test_respond_with() {
+        let json = || text_response(200, "{}");
+        let html = || {
+            let mut response = text_response(200, "<p></p>");
+            response.headers.remove("Content-Type");
+            response
+        };
+        let renderers: [(&str, &dyn Fn() -> Response); 2] =
+            [("application/json", &json), ("text/html", &html)];
+
+        let response = respond_with(&request(Some(FIREFOX)), &renderers);
+        assert_eq!(response.header("Content-Type"), Some("text/html"));
+        assert_eq!(response.header("Vary"), Some("Accept"));
+
+        let response = respond_with(&request(Some("image/png")), &renderers);
+        assert_eq!(response.status_code, 406);
+        assert_eq!(response.header("Vary"), Some("Accept"));
+        let body = response.response_body.unwrap();
+        assert_eq!(
+            body.as_bytes(),
+            b"Not Acceptable: available types are application/json, text/html"
+        );
+    }
+}
@@ -0,0 +1,341 @@
+//! OpenAPI 3.0 documents describing the endpoints of an app.
+
+use crate::app::{App, Endpoint, Routes};
+use crate::parse_headers::RequestType;
+use serde_json::{json, Map, Value};
+
+/// The version of the OpenAPI Specification the documents follow.
+const OPENAPI_VERSION: &str = "3.0.3";
+
+/// The `info` object of an OpenAPI document, describing the API as a whole.
+///
+/// # Examples
+///
+/// ```
+/// use rustic::openapi::ApiInfo;
+/// let info = ApiInfo::new("Inventory", "1.2.0").description("Stock levels by warehouse");
+/// ```
+#[derive(Debug, Clone, PartialEq)]
+pub struct ApiInfo {
+    title: String,
+    version: String,
+    description: Option<String>,
+}
+
+impl ApiInfo {
+    /// Describes an API by its title and the version of the API itself, such as
+    /// `1.2.0`.
+    pub fn new(title: impl Into<String>, version: impl Into<String>) -> Self {
+        ApiInfo {
+            title: title.into(),
+            version: version.into(),
+            description: None,
+        }
+    }
+
+    /// Sets a longer description of the API.
+    pub fn description(mut self, description: impl Into<String>) -> Self {
+        self.description = Some(description.into());
+        self
+    }
+}
+
+impl App {
+    /// Renders an OpenAPI 3.0 document describing the endpoints of the app, as JSON.
+    ///
+    /// Each endpoint becomes an operation of its path, with its
+    /// [name](crate::app::EndpointConfig::name) as the summary. Path parameters are
+    /// described from the `{name}` segments, typed by their constraint, and
+    /// [required query parameters](crate::app::EndpointConfig::require_params) as
+    /// required `query` parameters. Operations that take a body declare the media types
+    /// the endpoint [accepts](crate::app::EndpointConfig::accepts), or any media type.
+    /// Bodies and responses are described as free-form, since handlers do not declare
+    /// their shapes.
+    ///
+    /// Endpoints OpenAPI cannot describe are left out: those with a `*` in their path,
+    /// those added with [`App::any`], defaults, and those serving `CONNECT` or `UPDATE`.
+    ///
+    /// # Arguments
+    ///
+    /// * `info` - The title, version and description of the API.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use rustic::app::App;
+    /// use rustic::openapi::ApiInfo;
+    ///
+    /// let mut application = App::new();
+    /// application.get("users/{id:u64}", |request| {
+    ///     format!("User {}", request.path_param("id").unwrap_or_default())
+    /// });
+    /// let document = application.openapi_json(ApiInfo::new("Users", "1.0.0"));
+    /// assert!(document.contains("\"/users/{id}\""));
+    /// ```
+    pub fn openapi_json(&self, info: ApiInfo) -> String {
+        openapi_document(&self.routes, &info).to_string()
+    }
+
+    /// Serves the [OpenAPI document](App::openapi_json) of the app at `path`, such as
+    /// `/openapi.json`, as `application/json`.
+    ///
+    /// The document is rendered for each request, so it includes endpoints added to
+    /// the [route table](App::routes) after the server started.
+    ///
+    /// # Arguments
+    ///
+    /// * `path` - The path to serve the document at; a leading `/` is ignored.
+    /// * `info` - The title, version and description of the API.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use rustic::app::App;
+    /// use rustic::openapi::ApiInfo;
+    /// use rustic::test::TestClient;
+    ///
+    /// let mut application = App::new();
+    /// application.get("health", |_| "ok");
+    /// application.enable_openapi_endpoint("/openapi.json", ApiInfo::new("Status", "1.0.0"));
+    /// let client = TestClient::new(application);
+    /// let response = client.get("/openapi.json").send();
+    /// assert_eq!(response.header("Content-Type"), Some("application/json"));
+    /// assert!(response.text().contains("\"/health\""));
+    /// ```
+    pub fn enable_openapi_endpoint(&mut self, path: &str, info: ApiInfo) {
+        let routes = self.routes();
+        self.add_endpoint(path.trim_start_matches('/'), RequestType::GET, move |_| {
+            openapi_document(&routes, &info)
+        });
+    }
+}
+
+/// Builds the OpenAPI document of the endpoints in `routes`.
+fn openapi_document(routes: &Routes, info: &ApiInfo) -> Value {
+    let mut info_object = json!({ "title": info.title, "version": info.version });
+    if let Some(description) = &info.description {
+        info_object["description"] = json!(description);
+    }
+
+    let mut paths = Map::new();
+    for endpoint in routes.endpoints.read().unwrap().iter() {
+        let Some(method) = endpoint.request.and_then(operation_key) else {
+            continue;
+        };
+        if endpoint.fallback || endpoint.path.split('/').any(|part| part == "*") {
+            continue;
+        }
+        let path_item = paths
+            .entry(openapi_path(&endpoint.path))
+            .or_insert_with(|| json!({}));
+        // Endpoints match in order, so only the first one for a method is reachable.
+        if path_item.get(method).is_none() {
+            path_item[method] = operation(endpoint);
+        }
+    }
+
+    json!({
+        "openapi": OPENAPI_VERSION,
+        "info": info_object,
+        "paths": paths,
+    })
+}
+
+/// Describes the operation of an endpoint.
+fn operation(endpoint: &Endpoint) -> Value {
+    let mut operation = json!({
+        "responses": { "default": { "description": "The response of the endpoint" } }
+    });
+    if let Some(name) = &endpoint.config.name {
+        operation["summary"] = json!(name);
+    }
+
+    let mut parameters: Vec<Value> = endpoint
+        .path
+        .split('/')
+        .filter_map(|part| part.strip_prefix('{')?.strip_suffix('}'))
+        .map(|param| {
+            let (name, kind) = param.split_once(':').unwrap_or((param, ""));
+            json!({
+                "name": name,
+                "in": "path",
+                "required": true,
+                "schema": parameter_schema(kind),
+            })
+        })
+        .collect();
+    parameters.extend(endpoint.config.required_params.iter().map(|name| {
+        json!({
+            "name": name,
+            "in": "query",
+            "required": true,
+            "schema": { "type": "string" },
+        })
+    }));
+    if !parameters.is_empty() {
+        operation["parameters"] = Value::Array(parameters);
+    }
+
+    let takes_body = matches!(
+        endpoint.request,
+        Some(RequestType::POST | RequestType::PUT | RequestType::PATCH)
+    );
+    if takes_body || !endpoint.config.accepts.is_empty() {
+        let content: Map<String, Value> = if endpoint.config.accepts.is_empty() {
+            vec!["*/*".to_string()]
+        } else {
+            endpoint.config.accepts.clone()
+        }
+        .into_iter()
+        .map(|media_type| (media_type, json!({ "schema": { "type": "object" } })))
+        .collect();
+        operation["requestBody"] = json!({ "content": content });
+    }
+    operation
+}
+
+/// Returns the key of the operation for `request` in a path item, or `None` for a
+/// method OpenAPI has no operation for.
+fn operation_key(request: RequestType) -> Option<&'static str> {
+    Some(match request {
+        RequestType::GET => "get",
+        RequestType::HEAD => "head",
+        RequestType::POST => "post",
+        RequestType::PUT => "put",
+        RequestType::PATCH => "patch",
+        RequestType::DELETE => "delete",
+        RequestType::OPTIONS => "options",
+        RequestType::TRACE => "trace",
+        RequestType::CONNECT | RequestType::UPDATE => return None,
+    })
+}
+
+/// Converts an endpoint path into an OpenAPI path, such as `users/{id:u64}` into
+/// `/users/{id}`.
+fn openapi_path(pattern: &str) -> String {
+    let mut path = String::new();
+    for part in pattern.split('/').filter(|part| !part.is_empty()) {
+        path.push('/');
+        match part
+            .strip_prefix('{')
+            .and_then(|param| param.strip_suffix('}'))
+        {
+            Some(param) => {
+                let name = param.split_once(':').map_or(param, |(name, _)| name);
+                path.push_str(&format!("{{{}}}", name));
+            }
+            None => path.push_str(part),
+        }
+    }
+    if path.is_empty() {
+        path.push('/');
+    }
+    path
+}
+
+/// Describes the values a path parameter with the constraint `kind` takes.
+fn parameter_schema(kind: &str) -> Value {
+    match kind {
+        "u64" => json!({ "type": "integer", "format": "int64", "minimum": 0 }),
+        "uuid" => json!({ "type": "string", "format": "uuid" }),
+        "alpha" => json!({ "type": "string", "pattern": "^[A-Za-z]+$" }),
+        _ => json!({ "type": "string" }),
+    }
+}
+
+#[cfg(test)]
+mod test_openapi {
+    use super::*;
+    use crate::app::EndpointConfig;
+    use crate::test::TestClient;
+
+    /// Tests that endpoint paths are converted to OpenAPI paths.
+    #[test]
+    fn test_openapi_path() {
+        assert_eq!(openapi_path("users/{id:u64}/posts"), "/users/{id}/posts");
+        assert_eq!(openapi_path("/files/{name}"), "/files/{name}");
+        assert_eq!(openapi_path(""), "/");
+    }
+
+    /// Tests that the document describes operations, parameters and request bodies,
+    /// and leaves out what OpenAPI cannot describe.
+    #[test]
+    fn test_openapi_document() {
+        let mut application = App::new();
+        application.add_endpoint_with_config(
+            "users/{id:u64}",
+            RequestType::GET,
+            |_| "user",
+            EndpointConfig::new().name("get_user"),
+        );
+        application.add_endpoint_with_config(
+            "users",
+            RequestType::POST,
+            |_| "created",
+            EndpointConfig::new()
+                .accepts(&["application/json"])
+                .require_params(&["org"]),
+        );
+        application.delete("users/{id:u64}/sessions/{session}", |_| "removed");
+        application.any("anything", |_| "any");
+        application.proxy("api/*", "http://127.0.0.1:9000");
+        application.enable_openapi_endpoint(
+            "openapi.json",
+            ApiInfo::new("Users", "2.1.0").description("Accounts"),
+        );
+
+        let client = TestClient::new(application);
+        let response = client.get("/openapi.json").send();
+        assert_eq!(response.header("Content-Type"), Some("application/json"));
+        let document: Value = serde_json::from_str(&response.text()).unwrap();
+        assert_eq!(document["openapi"], "3.0.3");
+        assert_eq!(
+            document["info"],
+            json!({ "title": "Users", "version": "2.1.0", "description": "Accounts" })
+        );
+
+        let paths = document["paths"].as_object().unwrap();
+        let mut names: Vec<&str> = paths.keys().map(String::as_str).collect();
+        names.sort();
+        assert_eq!(
+            names,
+            [
+                "/openapi.json",
+                "/users",
+                "/users/{id}",
+                "/users/{id}/sessions/{session}"
+            ]
+        );
+
+        let get_user = &paths["/users/{id}"]["get"];
+        assert_eq!(get_user["summary"], "get_user");
+        assert_eq!(
+            get_user["parameters"],
+            json!([{
+                "name": "id",
+                "in": "path",
+                "required": true,
+                "schema": { "type": "integer", "format": "int64", "minimum": 0 },
+            }])
+        );
+        assert!(get_user.get("requestBody").is_none());
+
+        let create = &paths["/users"]["post"];
+        assert_eq!(create["parameters"][0]["name"], "org");
+        assert_eq!(create["parameters"][0]["in"], "query");
+        assert_eq!(
+            create["requestBody"]["content"],
+            json!({ "application/json": { "schema": { "type": "object" } } })
+        );
+
+        let remove = &paths["/users/{id}/sessions/{session}"]["delete"];
+        let params: Vec<&Value> = remove["parameters"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|param| &param["name"])
+            .collect();
+        assert_eq!(params, ["id", "session"]);
+        assert!(remove["responses"]["default"].is_object());
+    }
+}
@@ -1,6 +1,7 @@
+use crate::header_map::HeaderMap;
 use std::collections::HashMap;
 
-#[derive(Debug, PartialEq)]
+#[derive(Debug, Clone, Copy, PartialEq)]
 pub enum RequestType {
     GET,
     HEAD,
@@ -14,12 +15,78 @@ pub enum RequestType {
     TRACE,
 }
 
-#[derive(Debug, PartialEq)]
+impl RequestType {
+    /// Returns the method name as it appears on the request line.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use rustic::parse_headers::RequestType;
+    /// assert_eq!(RequestType::DELETE.as_str(), "DELETE");
+    /// ```
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            RequestType::GET => "GET",
+            RequestType::HEAD => "HEAD",
+            RequestType::POST => "POST",
+            RequestType::PUT => "PUT",
+            RequestType::PATCH => "PATCH",
+            RequestType::UPDATE => "UPDATE",
+            RequestType::DELETE => "DELETE",
+            RequestType::CONNECT => "CONNECT",
+            RequestType::OPTIONS => "OPTIONS",
+            RequestType::TRACE => "TRACE",
+        }
+    }
+
+    /// Tells whether requests with this method carry a body, so that they must state
+    /// its length.
+    pub(crate) fn requires_length(&self) -> bool {
+        matches!(
+            self,
+            RequestType::POST | RequestType::PUT | RequestType::PATCH
+        )
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
 pub enum HttpType {
     OnePointOne,
     NotSupported(String),
 }
 
+/// The request line and headers of a request, as parsed by [`parse_headers`].
+///
+/// # Examples
+///
+/// ```
+/// use rustic::header_map::HeaderMap;
+/// use rustic::parse_headers::{HttpType, RawRequest, RequestType};
+/// let mut headers = HeaderMap::new();
+/// headers.append("Host", "localhost:8002");
+/// let request = RawRequest {
+///     method: RequestType::GET,
+///     version: HttpType::OnePointOne,
+///     target: "/test".to_string(),
+///     headers,
+/// };
+/// assert_eq!(request.headers.get("host"), Some("localhost:8002"));
+/// ```
+#[derive(Debug, Clone, PartialEq)]
+pub struct RawRequest {
+    /// The method of the request line.
+    pub method: RequestType,
+    /// The HTTP version of the request line.
+    pub version: HttpType,
+    /// The request target as it was sent, such as `/search?q=rust`.
+    pub target: String,
+    /// The headers in the order they were sent, with names as they were given. Repeated
+    /// headers keep every value.
+    pub headers: HeaderMap,
+}
+
+/// The method, HTTP version, headers and request target returned by
+/// [`parse_headers_tuple`].
 type ParsedHeaders = Result<
     (
         RequestType,
@@ -30,24 +97,25 @@ type ParsedHeaders = Result<
     String,
 >;
 
-/// Parses a vector of HTTP header strings into a structured format.
+/// Parses the request line and header lines of a request.
 ///
-/// This function processes a list of HTTP headers and extracts the request type, HTTP version,
-/// headers, and constructs the URL if the "Host" header is present. It returns a tuple containing
-/// the request type, HTTP version, headers as a `HashMap`, and an optional parsed URL.
+/// The request line gives the method, request target and HTTP version. Header lines are
+/// split at their first colon, with the optional whitespace around values removed, and
+/// lines without a colon are skipped.
 ///
 /// # Arguments
 ///
-/// * `headers` - A vector of strings where each string represents a single HTTP header.
+/// * `headers` - The request line followed by the header lines, without line endings.
 ///
 /// # Returns
 ///
-/// * `ParsedHeaders` - A result containing a tuple with the request type, HTTP version, headers map, and optional URL,
-///   or an error message if parsing fails.
+/// * `Result<RawRequest, String>` - The parsed request, or an error message if parsing
+///   fails.
 ///
 /// # Errors
 ///
 /// This function returns an error if the headers are empty, if the request line is invalid, or if the HTTP version is invalid.
+/// The `PRI * HTTP/2.0` line starting an HTTP/2 connection preface is an error too.
 ///
 /// # Examples
 ///
@@ -60,74 +128,241 @@ type ParsedHeaders = Result<
 ///     "Accept: */*".to_string(),
 /// ];
 ///
-/// let result = parse_headers(headers).unwrap();
-/// assert_eq!(result.0, RequestType::GET);
-/// assert_eq!(result.1, HttpType::OnePointOne);
-/// assert_eq!(result.2.get("Host"), Some(&"localhost:8002".to_string()));
-/// assert_eq!(result.3, Some("/test".to_string()));
+/// let request = parse_headers(headers).unwrap();
+/// assert_eq!(request.method, RequestType::GET);
+/// assert_eq!(request.version, HttpType::OnePointOne);
+/// assert_eq!(request.headers.get("Host"), Some("localhost:8002"));
+/// assert_eq!(request.target, "/test");
 /// ```
-pub fn parse_headers(headers: Vec<String>) -> ParsedHeaders {
-    if headers.is_empty() {
+pub fn parse_headers(headers: Vec<String>) -> Result<RawRequest, String> {
+    let mut lines = headers.into_iter();
+    let Some(request_line) = lines.next() else {
         return Err("No headers to parse.".to_string());
+    };
+    if is_http2_preface(&request_line) {
+        return Err("HTTP/2 connection preface, but only HTTP/1.x is supported.".to_string());
     }
+    let mut split_request = request_line.split_whitespace();
+    let (method, target, version) = (
+        split_request.next(),
+        split_request.next(),
+        split_request.next(),
+    );
 
-    let split_request: Vec<&str> = headers[0].split_whitespace().collect();
-
-    let request_type = match split_request.first() {
-        Some(&"GET") => RequestType::GET,
-        Some(&"HEAD") => RequestType::HEAD,
-        Some(&"POST") => RequestType::POST,
-        Some(&"PUT") => RequestType::PUT,
-        Some(&"DELETE") => RequestType::DELETE,
-        Some(&"PATCH") => RequestType::PATCH,
-        Some(&"UPDATE") => RequestType::UPDATE,
-        Some(&"CONNECT") => RequestType::CONNECT,
-        Some(&"OPTIONS") => RequestType::OPTIONS,
-        Some(&"TRACE") => RequestType::TRACE,
-        Some(option) => return Err(format!("Invalid request type: {option}")),
+    let method = match method {
+        Some(token) => match parse_method(token) {
+            Some(method) => method,
+            None if is_token(token) => return Err(format!("Unsupported request type: {token}")),
+            None => return Err(format!("Invalid request type: {token}")),
+        },
         None => return Err("Invalid request line.".to_string()),
     };
 
-    let http_type = match split_request.get(2) {
-        Some(&"HTTP/1.1") => HttpType::OnePointOne,
-        Some(other) => HttpType::NotSupported(other.to_string()),
-        None => return Err("Invalid HTTP version.".to_string()),
+    // A version can only follow a target, so both are present or the version is missing.
+    let (Some(target), Some(version)) = (target, version) else {
+        return Err("Invalid HTTP version.".to_string());
+    };
+    let version = match version {
+        "HTTP/1.1" => HttpType::OnePointOne,
+        other => HttpType::NotSupported(other.to_string()),
     };
 
-    let mut header_map = HashMap::new();
+    // The lines are owned, so each name can keep the allocation of its line.
+    let mut headers = HeaderMap::with_capacity(lines.len());
+    for line in lines {
+        if let Some((name, value)) = split_header_line(line) {
+            headers.append(name, value);
+        }
+    }
 
-    // Parse headers
-    for header in headers.iter().skip(1) {
-        let parts: Vec<&str> = header.splitn(2, ": ").collect();
-        if parts.len() == 2 {
-            let key = parts[0].trim();
-            let value = parts[1].trim().to_string();
-            header_map.insert(key.to_string(), value.clone());
+    Ok(RawRequest {
+        method,
+        version,
+        target: target.to_string(),
+        headers,
+    })
+}
+
+/// Returns the request type named by a method token, or `None` when it is not one the
+/// server implements. Methods are case-sensitive, so `get` is not `GET`.
+pub(crate) fn parse_method(token: &str) -> Option<RequestType> {
+    Some(match token {
+        "GET" => RequestType::GET,
+        "HEAD" => RequestType::HEAD,
+        "POST" => RequestType::POST,
+        "PUT" => RequestType::PUT,
+        "DELETE" => RequestType::DELETE,
+        "PATCH" => RequestType::PATCH,
+        "UPDATE" => RequestType::UPDATE,
+        "CONNECT" => RequestType::CONNECT,
+        "OPTIONS" => RequestType::OPTIONS,
+        "TRACE" => RequestType::TRACE,
+        _ => return None,
+    })
+}
+
+/// Checks whether `text` is a token as RFC 9110 defines it, the syntax of methods and
+/// header names: one or more visible ASCII characters other than delimiters.
+pub(crate) fn is_token(text: &str) -> bool {
+    !text.is_empty()
+        && text
+            .bytes()
+            .all(|byte| byte.is_ascii_alphanumeric() || b"!#$%&'*+-.^_`|~".contains(&byte))
+}
+
+/// Returns the major version of an HTTP version token such as `HTTP/1.1`, or `None` when
+/// it does not have the `HTTP/<digit>.<digit>` syntax.
+pub(crate) fn http_major_version(version: &str) -> Option<u8> {
+    match version.strip_prefix("HTTP/")?.as_bytes() {
+        [major @ b'0'..=b'9', b'.', b'0'..=b'9'] => Some(major - b'0'),
+        _ => None,
+    }
+}
+
+/// Returns whether `request_line` starts the connection preface of an HTTP/2 client
+/// speaking it without negotiation, `PRI * HTTP/2.0`.
+pub(crate) fn is_http2_preface(request_line: &str) -> bool {
+    request_line == "PRI * HTTP/2.0"
+}
+
+/// Parses a request head like [`parse_headers`], returning its parts as a tuple.
+///
+/// Header names are kept as given, so the map only merges repeated headers sent with
+/// the same casing, keeping the last value. The request target is always present.
+#[deprecated(
+    note = "use `parse_headers`, which returns a `RawRequest`; this wrapper \
+            will be removed in the next release"
+)]
+pub fn parse_headers_tuple(headers: Vec<String>) -> ParsedHeaders {
+    let request = parse_headers(headers)?;
+    Ok((
+        request.method,
+        request.version,
+        request.headers.into_iter().collect(),
+        Some(request.target),
+    ))
+}
+
+/// Parses `Name: value` header lines into a map, skipping lines without a colon. Every
+/// value of a repeated header is kept, in order.
+///
+/// The optional whitespace around values is removed, so `Name:value` and
+/// `Name: value\t` are read alike.
+pub(crate) fn parse_header_lines(lines: &[String]) -> HeaderMap {
+    let mut header_map = HeaderMap::with_capacity(lines.len());
+    for header in lines {
+        if let Some((key, value)) = header.split_once(':') {
+            header_map.append(key.trim(), value.trim_matches([' ', '\t', '\r']));
         }
     }
+    header_map
+}
+
+/// Splits an owned `Name: value` line like [`parse_header_lines`] does, reusing the
+/// line for the name.
+fn split_header_line(mut line: String) -> Option<(String, String)> {
+    let colon = line.find(':')?;
+    let value = line[colon + 1..]
+        .trim_matches([' ', '\t', '\r'])
+        .to_string();
+    line.truncate(line[..colon].trim_end().len());
+    let leading = line.len() - line.trim_start().len();
+    line.drain(..leading);
+    Some((line, value))
+}
 
-    // Construct the URL
-    let url = split_request.get(1).map(|&path| path.to_string());
+/// Checks whether a header line has whitespace between its name and colon, which RFC
+/// 9112 requires servers to refuse, as proxies may read the name differently.
+pub(crate) fn has_space_before_colon(line: &str) -> bool {
+    line.split_once(':')
+        .is_some_and(|(name, _)| name.ends_with([' ', '\t']) && !line.starts_with([' ', '\t']))
+}
 
-    Ok((request_type, http_type, header_map, url))
+/// Parses the status line and headers of an HTTP response received from another server.
+///
+/// Returns the status code and the headers, with the values of repeated headers kept in
+/// order, or an error message if the status line is missing or malformed.
+pub(crate) fn parse_response_head(lines: &[String]) -> Result<(u16, HeaderMap), String> {
+    let status_line = lines
+        .first()
+        .ok_or_else(|| "No status line to parse.".to_string())?;
+    let mut parts = status_line.split_whitespace();
+    match parts.next() {
+        Some(version) if version.starts_with("HTTP/") => {}
+        _ => return Err(format!("Invalid status line: {status_line}")),
+    }
+    let status_code = parts
+        .next()
+        .and_then(|code| code.parse::<u16>().ok())
+        .filter(|code| (100..1000).contains(code))
+        .ok_or_else(|| format!("Invalid status line: {status_line}"))?;
+    Ok((status_code, parse_header_lines(&lines[1..])))
 }
 
 #[cfg(test)]
 mod parse_headers_test {
     use super::*;
 
-    /// Tests the `parse_headers` function with a valid set of HTTP headers.
-    #[test]
-    pub fn test_parse_headers() {
-        let headers = vec![
+    /// The sample head the tests parse.
+    fn sample_headers() -> Vec<String> {
+        vec![
             "GET /test HTTP/1.1".to_string(),
             "Host: localhost:8002".to_string(),
             "User-Agent: curl/8.2.1".to_string(),
             "Accept: */*".to_string(),
-        ];
+        ]
+    }
+
+    /// Tests the `parse_headers` function with a valid set of HTTP headers.
+    #[test]
+    pub fn test_parse_headers() {
+        let mut headers = HeaderMap::new();
+        headers.append("Host", "localhost:8002");
+        headers.append("User-Agent", "curl/8.2.1");
+        headers.append("Accept", "*/*");
+        let expected = RawRequest {
+            method: RequestType::GET,
+            version: HttpType::OnePointOne,
+            target: "/test".to_string(),
+            headers,
+        };
+        assert_eq!(parse_headers(sample_headers()).unwrap(), expected);
 
-        let (request_type, http_type, headers_map, url) = parse_headers(headers).unwrap();
+        let mut lines = sample_headers();
+        lines[0] = "POST /upload HTTP/1.0".to_string();
+        lines.push("Accept: text/html".to_string());
+        let request = parse_headers(lines).unwrap();
+        assert_eq!(request.method, RequestType::POST);
+        assert_eq!(
+            request.version,
+            HttpType::NotSupported("HTTP/1.0".to_string())
+        );
+        assert_eq!(request.target, "/upload");
+        let accepted: Vec<&str> = request.headers.get_all("accept").collect();
+        assert_eq!(accepted, ["*/*", "text/html"]);
+    }
 
+    /// Tests that malformed request lines are refused.
+    #[test]
+    fn test_parse_headers_errors() {
+        assert!(parse_headers(Vec::new()).is_err());
+        for line in ["", "FETCH /test HTTP/1.1", "GET", "GET /test"] {
+            assert!(parse_headers(vec![line.to_string()]).is_err(), "{:?}", line);
+        }
+        let err = parse_headers(vec!["PROPFIND /x HTTP/1.1".to_string()]).unwrap_err();
+        assert_eq!(err, "Unsupported request type: PROPFIND");
+        let err = parse_headers(vec!["G<ET /x HTTP/1.1".to_string()]).unwrap_err();
+        assert_eq!(err, "Invalid request type: G<ET");
+        let err = parse_headers(vec!["PRI * HTTP/2.0".to_string()]).unwrap_err();
+        assert!(err.starts_with("HTTP/2 connection preface"), "{}", err);
+    }
+
+    /// Tests that the deprecated wrapper returns the same parts as a tuple.
+    #[test]
+    #[allow(deprecated)]
+    fn test_parse_headers_tuple() {
+        let (request_type, http_type, headers_map, url) =
+            parse_headers_tuple(sample_headers()).unwrap();
         assert_eq!(request_type, RequestType::GET);
         assert_eq!(http_type, HttpType::OnePointOne);
         assert_eq!(headers_map.get("Host"), Some(&"localhost:8002".to_string()));
@@ -137,4 +372,63 @@ mod parse_headers_test {
         );
         assert_eq!(url, Some("/test".to_string()));
     }
+
+    /// Tests parsing the head of a response from an upstream server.
+    #[test]
+    pub fn test_parse_response_head() {
+        let lines = vec![
+            "HTTP/1.1 201 Created".to_string(),
+            "Content-Length: 2".to_string(),
+            "Set-Cookie: a=1".to_string(),
+            "set-cookie:b=2".to_string(),
+        ];
+        let (status_code, headers) = parse_response_head(&lines).unwrap();
+        assert_eq!(status_code, 201);
+        assert_eq!(headers.get("content-length"), Some("2"));
+        let cookies: Vec<&str> = headers.get_all("Set-Cookie").collect();
+        assert_eq!(cookies, ["a=1", "b=2"]);
+
+        assert!(parse_response_head(&["GET / HTTP/1.1".to_string()]).is_err());
+        assert!(parse_response_head(&["HTTP/1.1 OK".to_string()]).is_err());
+        assert!(parse_response_head(&[]).is_err());
+    }
+
+    /// Tests that optional whitespace around values is removed, with or without a space
+    /// after the colon.
+    #[test]
+    fn test_parse_header_lines_ows() {
+        let lines = [
+            "Host:localhost",
+            "Accept: \t*/* \t",
+            "Via: 1.1 a:80",
+            "broken",
+        ]
+        .map(String::from);
+        let headers = parse_header_lines(&lines);
+        assert_eq!(headers.get("Host"), Some("localhost"));
+        assert_eq!(headers.get("Accept"), Some("*/*"));
+        assert_eq!(headers.get("Via"), Some("1.1 a:80"));
+        assert_eq!(headers.len(), 3);
+    }
+
+    /// Tests that whitespace before the colon is caught, but not in continuation lines.
+    #[test]
+    fn test_has_space_before_colon() {
+        assert!(has_space_before_colon("Host : localhost"));
+        assert!(has_space_before_colon("Host\t: localhost"));
+        assert!(!has_space_before_colon("Host: a b : c"));
+        assert!(!has_space_before_colon(" folded : value"));
+        assert!(!has_space_before_colon("no colon "));
+    }
+
+    /// Tests reading the major version from version tokens, and refusing malformed ones.
+    #[test]
+    fn test_http_major_version() {
+        assert_eq!(http_major_version("HTTP/1.1"), Some(1));
+        assert_eq!(http_major_version("HTTP/1.0"), Some(1));
+        assert_eq!(http_major_version("HTTP/3.0"), Some(3));
+        for version in ["HTTP/1", "HTTP/1.10", "http/1.1", "FTP/1.1", "HTTP/x.1", ""] {
+            assert_eq!(http_major_version(version), None, "{}", version);
+        }
+    }
 }
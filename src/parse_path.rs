@@ -1,6 +1,7 @@
 /// Extracts the path from a given URL string, removing any leading and trailing slashes.
 ///
 /// This function handles URLs with and without schemes (e.g., `https://`, `http://`, etc.).
+/// Any query string or fragment is not part of the path and is dropped.
 /// If the URL does not contain a path, the function returns `None`.
 ///
 /// # Arguments
@@ -24,8 +25,11 @@
 ///     parse_path("example.com/path/to/resource"),
 ///     Some("path/to/resource")
 /// );
+/// assert_eq!(parse_path("/search?q=rust"), Some("search"));
 /// ```
 pub fn parse_path(url: &str) -> Option<&str> {
+    // Drop the query string and fragment, which are not part of the path
+    let url = url.split(['?', '#']).next().unwrap_or(url);
     url.find("://")
         // Check if the URL contains a scheme (e.g., "https://")
         .map_or_else(
@@ -61,4 +65,12 @@ mod test_parse_path {
     fn test_parse_path_no_scheme() {
         assert_eq!(parse_path("/path/to/resource"), Some("path/to/resource"));
     }
+
+    /// Tests the `parse_path` function with a query string and a fragment.
+    #[test]
+    fn test_parse_path_query() {
+        assert_eq!(parse_path("/login?user=alice"), Some("login"));
+        assert_eq!(parse_path("/docs/#intro"), Some("docs"));
+        assert_eq!(parse_path("/?next=/home"), None);
+    }
 }
@@ -22,14 +22,162 @@ use std::collections::HashMap;
 /// assert_eq!(result, expected);
 /// ```
 pub fn parse_url_param(url: &str) -> HashMap<String, String> {
+    query_pairs(url)
+        .map(|(k, v)| (k.to_string(), v.to_string())) // Convert (key, value) pairs into (String, String)
+        .collect() // Collect into a HashMap<String, String>
+}
+
+/// Iterates over the parameters of a URL in the order they appear, keeping repeated keys.
+///
+/// # Arguments
+///
+/// * `url` - A string slice representing the URL containing parameters.
+///
+/// # Returns
+///
+/// An iterator over the `(key, value)` pairs of the query string.
+///
+/// # Examples
+///
+/// ```
+/// use rustic::parse_url::query_pairs;
+/// let pairs: Vec<_> = query_pairs("/items?tag=a&tag=b").collect();
+/// assert_eq!(pairs, vec![("tag", "a"), ("tag", "b")]);
+/// ```
+pub fn query_pairs(url: &str) -> impl Iterator<Item = (&str, &str)> {
     // Split the URL at the '?' character to isolate parameters part.
     url.split_once('?')
         .map(|(_, params)| params) // Take the parameters part or an empty string if no '?'
         .unwrap_or("")
         .split('&') // Split parameters into key-value pairs
         .filter_map(|s| s.split_once('=')) // Filter out invalid pairs and split into (key, value)
-        .map(|(k, v)| (k.to_string(), v.to_string())) // Convert (key, value) pairs into (String, String)
-        .collect() // Collect into a HashMap<String, String>
+}
+
+/// Decodes the percent escapes of a URL component.
+///
+/// A `+` is kept as is; see [`form_pairs`] for form bodies, where it encodes a space.
+///
+/// # Arguments
+///
+/// * `component` - A path segment, query key or query value.
+///
+/// # Returns
+///
+/// * `Option<String>` - The decoded component, or `None` if an escape is malformed or the
+///   result is not UTF-8.
+///
+/// # Examples
+///
+/// ```
+/// use rustic::parse_url::percent_decode;
+/// assert_eq!(percent_decode("read%20me.txt"), Some("read me.txt".to_string()));
+/// assert_eq!(percent_decode("100%"), None);
+/// ```
+pub fn percent_decode(component: &str) -> Option<String> {
+    let bytes = component.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut index = 0;
+    while index < bytes.len() {
+        if bytes[index] == b'%' {
+            let hex = bytes.get(index + 1..index + 3)?;
+            if !hex.iter().all(u8::is_ascii_hexdigit) {
+                return None;
+            }
+            let hex = std::str::from_utf8(hex).ok()?;
+            decoded.push(u8::from_str_radix(hex, 16).ok()?);
+            index += 3;
+        } else {
+            decoded.push(bytes[index]);
+            index += 1;
+        }
+    }
+    String::from_utf8(decoded).ok()
+}
+
+/// Percent-encodes a URL component, keeping only the unreserved characters of RFC 3986.
+pub(crate) fn percent_encode(component: &str) -> String {
+    let mut encoded = String::with_capacity(component.len());
+    for byte in component.bytes() {
+        if byte.is_ascii_alphanumeric() || matches!(byte, b'-' | b'.' | b'_' | b'~') {
+            encoded.push(byte as char);
+        } else {
+            encoded.push_str(&format!("%{:02X}", byte));
+        }
+    }
+    encoded
+}
+
+/// Iterates over the fields of an `application/x-www-form-urlencoded` body, decoded.
+///
+/// Fields that are not `key=value` pairs or whose escapes are malformed are skipped.
+///
+/// # Arguments
+///
+/// * `body` - The request body.
+///
+/// # Returns
+///
+/// An iterator over the decoded `(key, value)` pairs, in the order they appear.
+///
+/// # Examples
+///
+/// ```
+/// use rustic::parse_url::form_pairs;
+/// let pairs: Vec<_> = form_pairs("name=Ada+Lovelace&note=1%2B1").collect();
+/// assert_eq!(
+///     pairs,
+///     vec![
+///         ("name".to_string(), "Ada Lovelace".to_string()),
+///         ("note".to_string(), "1+1".to_string()),
+///     ]
+/// );
+/// ```
+pub fn form_pairs(body: &str) -> impl Iterator<Item = (String, String)> + '_ {
+    body.split('&').filter_map(|field| {
+        let (key, value) = field.split_once('=')?;
+        let key = percent_decode(&key.replace('+', " "))?;
+        let value = percent_decode(&value.replace('+', " "))?;
+        Some((key, value))
+    })
+}
+
+/// Splits an authority-form request target, which `CONNECT` requests carry, into its
+/// host and port.
+///
+/// IPv6 hosts are written in brackets, which are removed. The port is required, and
+/// targets with a path, user info or other URL parts are refused.
+///
+/// # Arguments
+///
+/// * `target` - The request target, such as `example.com:443`.
+///
+/// # Returns
+///
+/// * `Option<(&str, u16)>` - The host and port, or `None` if the target is not in
+///   authority form.
+///
+/// # Examples
+///
+/// ```
+/// use rustic::parse_url::parse_authority;
+/// assert_eq!(parse_authority("example.com:443"), Some(("example.com", 443)));
+/// assert_eq!(parse_authority("[::1]:8080"), Some(("::1", 8080)));
+/// assert_eq!(parse_authority("/index.html"), None);
+/// assert_eq!(parse_authority("example.com"), None);
+/// ```
+pub fn parse_authority(target: &str) -> Option<(&str, u16)> {
+    let (host, port) = target.rsplit_once(':')?;
+    let host = match host.strip_prefix('[') {
+        Some(bracketed) => bracketed.strip_suffix(']')?,
+        None if host.contains(':') => return None,
+        None => host,
+    };
+    let invalid = |c: char| c.is_whitespace() || "/?#@[]".contains(c);
+    if host.is_empty() || host.contains(invalid) || !port.bytes().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+    let port = port.parse().ok().filter(|&port| port != 0)?;
+    Some((host, port))
 }
 
 #[cfg(test)]
@@ -63,4 +211,51 @@ mod test_parse_url_param {
         let expected = HashMap::new();
         assert_eq!(result, expected);
     }
+
+    /// Tests decoding escapes and rejecting malformed ones.
+    #[test]
+    fn test_percent_decode() {
+        assert_eq!(percent_decode("a%2Fb%20c"), Some("a/b c".to_string()));
+        assert_eq!(percent_decode("a+b"), Some("a+b".to_string()));
+        assert_eq!(percent_decode("%+1"), None);
+        assert_eq!(percent_decode("%4"), None);
+        assert_eq!(percent_decode("%ff"), None);
+    }
+
+    /// Tests decoding form fields, skipping those that cannot be decoded.
+    #[test]
+    fn test_form_pairs() {
+        let pairs: Vec<_> = form_pairs("a=1&flag&b=%zz&c+d=e%26f").collect();
+        assert_eq!(
+            pairs,
+            vec![
+                ("a".to_string(), "1".to_string()),
+                ("c d".to_string(), "e&f".to_string()),
+            ]
+        );
+    }
+
+    /// Tests splitting authority-form targets and refusing other forms.
+    #[test]
+    fn test_parse_authority() {
+        assert_eq!(parse_authority("localhost:80"), Some(("localhost", 80)));
+        assert_eq!(parse_authority("10.0.0.1:65535"), Some(("10.0.0.1", 65535)));
+        assert_eq!(
+            parse_authority("[2001:db8::1]:443"),
+            Some(("2001:db8::1", 443))
+        );
+        for target in [
+            "2001:db8::1:443",
+            "example.com:",
+            "example.com:0",
+            "example.com:70000",
+            "example.com:+80",
+            ":443",
+            "user@example.com:443",
+            "example.com:443/path",
+            "http://example.com:443",
+        ] {
+            assert_eq!(parse_authority(target), None, "{}", target);
+        }
+    }
 }
@@ -0,0 +1,434 @@
+use crate::app::Request;
+use crate::content_type::essence;
+#[cfg(feature = "serde")]
+use crate::content_type::parameter;
+#[cfg(feature = "serde")]
+use crate::http_error::HttpError;
+#[cfg(feature = "serde")]
+use crate::parse_url::form_pairs;
+#[cfg(feature = "serde")]
+use std::collections::HashMap;
+#[cfg(feature = "serde")]
+use std::fmt;
+
+impl Request {
+    /// Returns whether the request declares a JSON body, with a `Content-Type` of
+    /// `application/json` or a `+json` type such as `application/problem+json`.
+    pub fn is_json(&self) -> bool {
+        self.media_type().is_some_and(|essence| {
+            essence.eq_ignore_ascii_case("application/json")
+                || essence.to_ascii_lowercase().ends_with("+json")
+        })
+    }
+
+    /// Returns whether the request declares a URL-encoded form body, with a
+    /// `Content-Type` of `application/x-www-form-urlencoded`.
+    pub fn is_form(&self) -> bool {
+        self.media_type().is_some_and(|essence| {
+            essence.eq_ignore_ascii_case("application/x-www-form-urlencoded")
+        })
+    }
+
+    /// Returns whether the request declares a multipart form body, with a `Content-Type`
+    /// of `multipart/form-data`.
+    pub fn is_multipart(&self) -> bool {
+        self.media_type()
+            .is_some_and(|essence| essence.eq_ignore_ascii_case("multipart/form-data"))
+    }
+
+    /// Returns the media type of the `Content-Type` header, without its parameters.
+    fn media_type(&self) -> Option<&str> {
+        self.header("Content-Type").map(essence)
+    }
+
+    /// Parses the body according to its `Content-Type`, as JSON, a URL-encoded form or
+    /// a multipart form, or returns it as raw bytes for any other type.
+    ///
+    /// The body is parsed on the first call and the outcome is kept in the request's
+    /// extensions, so later calls, from middleware or the handler, return it without
+    /// parsing again. A body that does not parse as its type claims is returned as a
+    /// [`BodyError`], which converts to `400 Bad Request` with `?`; handlers that only
+    /// take some types can answer a [`ParsedBody::Raw`] with `415 Unsupported Media
+    /// Type`.
+    ///
+    /// The body is read with [`Request::body_bytes`], so a streamed body should be
+    /// [buffered](Request::buffer_body) first. As [`Request::body`] only holds UTF-8, a
+    /// body that is not, such as a multipart upload of a binary file, is only parsed
+    /// whole once it is [spilled](crate::app::EndpointConfig::spill_threshold) to a file.
+    ///
+    /// # Returns
+    ///
+    /// * `Result<&ParsedBody, &BodyError>` - The parsed body, or why it did not parse.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use rustic::app::{App, Request};
+    /// use rustic::http_error::HttpError;
+    /// use rustic::parsed_body::ParsedBody;
+    /// use rustic::test::TestClient;
+    ///
+    /// fn greet(mut request: Request) -> Result<String, HttpError> {
+    ///     let name = match request.parsed_body()? {
+    ///         ParsedBody::Json(value) => value["name"].as_str().unwrap_or("").to_string(),
+    ///         ParsedBody::Form(fields) => fields.get("name").cloned().unwrap_or_default(),
+    ///         _ => return Err(HttpError::new(415, "Send JSON or a form")),
+    ///     };
+    ///     Ok(format!("Hello, {}!", name))
+    /// }
+    ///
+    /// let mut application = App::new();
+    /// application.post("greet", greet);
+    /// let client = TestClient::new(application);
+    /// let response = client
+    ///     .post("/greet")
+    ///     .header("Content-Type", "application/json")
+    ///     .body(r#"{"name": "Ada"}"#)
+    ///     .send();
+    /// assert_eq!(response.text(), "Hello, Ada!");
+    /// let response = client
+    ///     .post("/greet")
+    ///     .header("Content-Type", "application/x-www-form-urlencoded")
+    ///     .body("name=Grace+Hopper")
+    ///     .send();
+    /// assert_eq!(response.text(), "Hello, Grace Hopper!");
+    /// ```
+    #[cfg(feature = "serde")]
+    pub fn parsed_body(&mut self) -> Result<&ParsedBody, &BodyError> {
+        if self.extensions.get::<CachedBody>().is_none() {
+            let parsed = self.parse_body();
+            self.extensions.insert(CachedBody(parsed));
+        }
+        match self.extensions.get::<CachedBody>() {
+            Some(CachedBody(parsed)) => parsed.as_ref(),
+            None => unreachable!("the parsed body was just cached"),
+        }
+    }
+
+    /// Parses the body according to its `Content-Type`; see [`Request::parsed_body`].
+    #[cfg(feature = "serde")]
+    fn parse_body(&self) -> Result<ParsedBody, BodyError> {
+        let body = self
+            .body_bytes()
+            .map_err(|err| BodyError::Unreadable(err.to_string()))?;
+        if self.is_json() {
+            serde_json::from_slice(&body)
+                .map(ParsedBody::Json)
+                .map_err(|err| BodyError::Json(err.to_string()))
+        } else if self.is_form() {
+            let body = std::str::from_utf8(&body).map_err(|_| BodyError::Form)?;
+            Ok(ParsedBody::Form(form_pairs(body).collect()))
+        } else if self.is_multipart() {
+            let boundary = self
+                .content_type()
+                .and_then(|content_type| content_type.boundary().map(str::to_string))
+                .ok_or_else(|| BodyError::Multipart("no boundary is declared".to_string()))?;
+            parse_multipart(&body, &boundary)
+                .map(ParsedBody::Multipart)
+                .map_err(|message| BodyError::Multipart(message.to_string()))
+        } else {
+            Ok(ParsedBody::Raw(body.into_owned()))
+        }
+    }
+}
+
+/// A request body parsed according to its `Content-Type`, returned by
+/// [`Request::parsed_body`].
+#[cfg(feature = "serde")]
+#[derive(Debug, Clone, PartialEq)]
+pub enum ParsedBody {
+    /// An `application/json` or `+json` body.
+    Json(serde_json::Value),
+    /// An `application/x-www-form-urlencoded` body, decoded. The last value of a field
+    /// sent several times is kept.
+    Form(HashMap<String, String>),
+    /// The parts of a `multipart/form-data` body, in the order they were sent.
+    Multipart(Vec<Part>),
+    /// A body of any other type, or without a `Content-Type`.
+    Raw(Vec<u8>),
+}
+
+/// A part of a `multipart/form-data` body.
+#[cfg(feature = "serde")]
+#[derive(Debug, Clone, PartialEq)]
+pub struct Part {
+    /// The name of the form field, from the part's `Content-Disposition`.
+    pub name: String,
+    /// The name of the uploaded file, for file fields.
+    pub filename: Option<String>,
+    /// The `Content-Type` of the part, if it declares one.
+    pub content_type: Option<String>,
+    /// The content of the part.
+    pub data: Vec<u8>,
+}
+
+/// Why a request body did not parse as its `Content-Type` claims.
+#[cfg(feature = "serde")]
+#[derive(Debug, Clone, PartialEq)]
+pub enum BodyError {
+    /// The body is not valid JSON, described by the parser's message.
+    Json(String),
+    /// The form body is not UTF-8.
+    Form,
+    /// The multipart body is malformed, as described.
+    Multipart(String),
+    /// The body could not be read from the file it was spilled to.
+    Unreadable(String),
+}
+
+#[cfg(feature = "serde")]
+impl fmt::Display for BodyError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            BodyError::Json(message) => write!(f, "invalid JSON body: {}", message),
+            BodyError::Form => f.write_str("form body is not UTF-8"),
+            BodyError::Multipart(message) => write!(f, "invalid multipart body: {}", message),
+            BodyError::Unreadable(message) => write!(f, "unreadable body: {}", message),
+        }
+    }
+}
+
+#[cfg(feature = "serde")]
+impl std::error::Error for BodyError {}
+
+/// Converts a body that does not parse into `400 Bad Request`.
+#[cfg(feature = "serde")]
+impl From<&BodyError> for HttpError {
+    fn from(error: &BodyError) -> Self {
+        HttpError::bad_request(format!("Bad Request: {}", error))
+    }
+}
+
+/// The outcome of [`Request::parsed_body`], kept in the request's extensions.
+#[cfg(feature = "serde")]
+struct CachedBody(Result<ParsedBody, BodyError>);
+
+/// Splits a multipart body into its parts.
+///
+/// # Arguments
+///
+/// * `body` - The body, preamble and epilogue included.
+/// * `boundary` - The boundary declared in the `Content-Type` header.
+///
+/// # Returns
+///
+/// * `Result<Vec<Part>, &'static str>` - The parts, or what is malformed in the body.
+#[cfg(feature = "serde")]
+fn parse_multipart(body: &[u8], boundary: &str) -> Result<Vec<Part>, &'static str> {
+    let delimiter = format!("--{}", boundary);
+    let delimiter = delimiter.as_bytes();
+    let start = if body.starts_with(delimiter) {
+        0
+    } else {
+        find(body, &[b"\r\n", delimiter].concat()).ok_or("the first boundary is missing")? + 2
+    };
+    let mut rest = &body[start + delimiter.len()..];
+    let mut parts = Vec::new();
+    loop {
+        if rest.starts_with(b"--") {
+            return Ok(parts);
+        }
+        // Whitespace may follow a boundary before its line ends.
+        let padding = rest
+            .iter()
+            .take_while(|&&byte| byte == b' ' || byte == b'\t')
+            .count();
+        rest = rest[padding..]
+            .strip_prefix(b"\r\n")
+            .ok_or("a boundary line is malformed")?;
+
+        let (head, content) = match rest.strip_prefix(b"\r\n") {
+            Some(content) => (&[][..], content),
+            None => {
+                let end = find(rest, b"\r\n\r\n").ok_or("a part has no end of headers")?;
+                (&rest[..end], &rest[end + 4..])
+            }
+        };
+        let end = find(content, &[b"\r\n", delimiter].concat())
+            .ok_or("the closing boundary is missing")?;
+        parts.push(parse_part(head, &content[..end])?);
+        rest = &content[end + 2 + delimiter.len()..];
+    }
+}
+
+/// Builds a part from its header block and content.
+#[cfg(feature = "serde")]
+fn parse_part(head: &[u8], data: &[u8]) -> Result<Part, &'static str> {
+    let head = std::str::from_utf8(head).map_err(|_| "part headers are not UTF-8")?;
+    let mut disposition = None;
+    let mut content_type = None;
+    for line in head.split("\r\n") {
+        let (name, value) = line.split_once(':').ok_or("a part header has no colon")?;
+        if name.trim().eq_ignore_ascii_case("Content-Disposition") {
+            disposition = Some(value.trim());
+        } else if name.trim().eq_ignore_ascii_case("Content-Type") {
+            content_type = Some(value.trim().to_string());
+        }
+    }
+    let disposition = disposition.ok_or("a part has no Content-Disposition")?;
+    Ok(Part {
+        name: parameter(disposition, "name").ok_or("a part has no name")?,
+        filename: parameter(disposition, "filename"),
+        content_type,
+        data: data.to_vec(),
+    })
+}
+
+/// Returns where `needle` first occurs in `haystack`.
+#[cfg(feature = "serde")]
+fn find(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    haystack
+        .windows(needle.len())
+        .position(|window| window == needle)
+}
+
+#[cfg(all(test, feature = "serde"))]
+mod test_parsed_body {
+    use super::*;
+    use crate::parse_headers::RequestType;
+    use crate::test::TestClient;
+
+    /// Builds a request with the given `Content-Type` and body.
+    fn request(content_type: Option<&str>, body: &str) -> Request {
+        Request {
+            method: RequestType::POST,
+            path: "items".to_string(),
+            url: "/items".to_string(),
+            headers: content_type
+                .map(|content_type| ("Content-Type".to_string(), content_type.to_string()))
+                .into_iter()
+                .collect(),
+            body: body.to_string(),
+            ..Request::default()
+        }
+    }
+
+    /// Tests that the predicates read the media type, whatever its case and parameters.
+    #[test]
+    fn test_predicates() {
+        let json = request(Some("Application/JSON; charset=utf-8"), "");
+        assert!(json.is_json() && !json.is_form() && !json.is_multipart());
+        assert!(request(Some("application/problem+json"), "").is_json());
+        assert!(request(Some("application/x-www-form-urlencoded"), "").is_form());
+        assert!(request(Some("multipart/form-data; boundary=x"), "").is_multipart());
+        let untyped = request(None, "");
+        assert!(!untyped.is_json() && !untyped.is_form() && !untyped.is_multipart());
+    }
+
+    /// Tests that each kind of body parses into its variant, and that the outcome is
+    /// cached.
+    #[test]
+    fn test_parsed_body() {
+        let mut json = request(Some("application/json"), r#"{"id": 7}"#);
+        assert_eq!(
+            json.parsed_body(),
+            Ok(&ParsedBody::Json(serde_json::json!({ "id": 7 })))
+        );
+        json.body.clear();
+        assert!(matches!(json.parsed_body(), Ok(ParsedBody::Json(_))));
+
+        let mut form = request(
+            Some("application/x-www-form-urlencoded"),
+            "name=Ada+Lovelace&lang=en",
+        );
+        let Ok(ParsedBody::Form(fields)) = form.parsed_body() else {
+            panic!("not a form");
+        };
+        assert_eq!(fields["name"], "Ada Lovelace");
+        assert_eq!(fields["lang"], "en");
+
+        let mut raw = request(Some("text/csv"), "a,b\n1,2");
+        assert_eq!(
+            raw.parsed_body(),
+            Ok(&ParsedBody::Raw(b"a,b\n1,2".to_vec()))
+        );
+        let mut untyped = request(None, "");
+        assert_eq!(untyped.parsed_body(), Ok(&ParsedBody::Raw(Vec::new())));
+    }
+
+    /// Tests that a multipart body is split into named parts, file parts included.
+    #[test]
+    fn test_multipart() {
+        let body = "preamble\r\n--XyZ\r\n\
+                    Content-Disposition: form-data; name=\"title\"\r\n\r\n\
+                    Holiday\r\n\
+                    --XyZ  \r\n\
+                    Content-Disposition: form-data; name=\"photo\"; filename=\"a \\\"b\\\"; c.jpg\"\r\n\
+                    Content-Type: image/jpeg\r\n\r\n\
+                    \u{1}\u{2}\r\n--not the boundary\r\n\
+                    --XyZ--\r\nepilogue";
+        let mut upload = request(Some("multipart/form-data; boundary=\"XyZ\""), body);
+        let Ok(ParsedBody::Multipart(parts)) = upload.parsed_body() else {
+            panic!("not multipart: {:?}", upload.parsed_body());
+        };
+        assert_eq!(
+            parts,
+            &[
+                Part {
+                    name: "title".to_string(),
+                    filename: None,
+                    content_type: None,
+                    data: b"Holiday".to_vec(),
+                },
+                Part {
+                    name: "photo".to_string(),
+                    filename: Some("a \"b\"; c.jpg".to_string()),
+                    content_type: Some("image/jpeg".to_string()),
+                    data: b"\x01\x02\r\n--not the boundary".to_vec(),
+                },
+            ]
+        );
+        let mut empty = request(Some("multipart/form-data; boundary=b"), "--b--\r\n");
+        assert_eq!(empty.parsed_body(), Ok(&ParsedBody::Multipart(Vec::new())));
+    }
+
+    /// Tests that bodies not matching their declared type are reported, and answered
+    /// with `400 Bad Request` through `?`.
+    #[test]
+    fn test_mislabeled_bodies() {
+        let mut json = request(Some("application/json"), "{\"id\": ");
+        assert!(matches!(json.parsed_body(), Err(BodyError::Json(_))));
+
+        let mut unbounded = request(Some("multipart/form-data"), "--b--");
+        assert_eq!(
+            unbounded.parsed_body().unwrap_err().to_string(),
+            "invalid multipart body: no boundary is declared"
+        );
+        let mut unclosed = request(
+            Some("multipart/form-data; boundary=b"),
+            "--b\r\nContent-Disposition: form-data; name=\"a\"\r\n\r\nvalue",
+        );
+        assert_eq!(
+            unclosed.parsed_body(),
+            Err(&BodyError::Multipart(
+                "the closing boundary is missing".to_string()
+            ))
+        );
+        let mut nameless = request(
+            Some("multipart/form-data; boundary=b"),
+            "--b\r\nContent-Type: text/plain\r\n\r\nvalue\r\n--b--",
+        );
+        assert_eq!(
+            nameless.parsed_body(),
+            Err(&BodyError::Multipart(
+                "a part has no Content-Disposition".to_string()
+            ))
+        );
+
+        let mut application = crate::app::App::new();
+        application.post("items", |mut request: Request| {
+            request.parsed_body()?;
+            Ok::<_, HttpError>("stored")
+        });
+        let client = TestClient::new(application);
+        let response = client
+            .post("/items")
+            .header("Content-Type", "application/json")
+            .body("not json")
+            .send();
+        assert_eq!(response.status, 400);
+        assert!(response
+            .text()
+            .starts_with("Bad Request: invalid JSON body: "));
+    }
+}
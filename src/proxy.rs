@@ -0,0 +1,223 @@
+use crate::app::{App, EndpointConfig, Request};
+use crate::client::{ClientError, ClientRequest};
+use crate::header_map::HeaderMap;
+use crate::http11_response::{reason_phrase, Response};
+use crate::into_response::text_response;
+use crate::parse_headers::RequestType;
+use std::io::Read;
+use std::sync::Arc;
+use std::time::Duration;
+
+/// Headers that describe a single connection and must not be forwarded (RFC 9110, 7.6.1).
+const HOP_BY_HOP_HEADERS: [&str; 9] = [
+    "connection",
+    "keep-alive",
+    "proxy-authenticate",
+    "proxy-authorization",
+    "proxy-connection",
+    "te",
+    "trailer",
+    "transfer-encoding",
+    "upgrade",
+];
+
+/// The methods a proxy route forwards.
+const PROXIED_METHODS: [RequestType; 9] = [
+    RequestType::GET,
+    RequestType::HEAD,
+    RequestType::POST,
+    RequestType::PUT,
+    RequestType::PATCH,
+    RequestType::UPDATE,
+    RequestType::DELETE,
+    RequestType::OPTIONS,
+    RequestType::TRACE,
+];
+
+/// How long to wait on a silent upstream before giving up with `502 Bad Gateway`.
+const UPSTREAM_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// The server a proxy route forwards to.
+struct Upstream {
+    /// The `host:port` to connect to.
+    authority: String,
+    /// A path prefix prepended to every forwarded request target.
+    base_path: String,
+}
+
+impl Upstream {
+    /// Parses an upstream URL of the form `http://host[:port][/base]`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the URL does not use the `http` scheme.
+    fn parse(url: &str) -> Self {
+        let rest = url
+            .strip_prefix("http://")
+            .expect("Proxy upstream must be an http:// URL");
+        let (authority, base_path) = match rest.find('/') {
+            Some(i) => (&rest[..i], rest[i..].trim_end_matches('/')),
+            None => (rest, ""),
+        };
+        let authority = if authority.contains(':') {
+            authority.to_string()
+        } else {
+            format!("{}:80", authority)
+        };
+        Upstream {
+            authority,
+            base_path: base_path.to_string(),
+        }
+    }
+}
+
+impl App {
+    /// Forwards every request under `path` to an upstream HTTP server.
+    ///
+    /// The request target is forwarded unchanged, after the upstream's own path if it
+    /// has one, so `app.proxy("api/*", "http://127.0.0.1:9000")` relays `/api/users` to
+    /// `http://127.0.0.1:9000/api/users`. Hop-by-hop headers are dropped in both
+    /// directions and the peer address is appended to `X-Forwarded-For`. The request ID
+    /// and trace context are sent on as [`Request::propagation_headers`] gives them, so
+    /// an ID generated by [`RequestIdMiddleware`](crate::request_id::RequestIdMiddleware)
+    /// reaches the upstream too. When the upstream cannot be reached or sends an invalid
+    /// response, the client receives `502 Bad Gateway`.
+    ///
+    /// Bodies are relayed as bytes, so they need not be text, and every value of a
+    /// repeated response header, such as `Set-Cookie`, is passed on. The routes stream
+    /// their request bodies, as with [`EndpointConfig::stream_body`].
+    ///
+    /// # Arguments
+    ///
+    /// * `path` - The endpoint path to mount the proxy at, usually ending in `/*`.
+    /// * `upstream` - The `http://` URL of the upstream server.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `upstream` is not an `http://` URL.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use rustic::app::App;
+    /// let mut application = App::new();
+    /// application.proxy("api/*", "http://127.0.0.1:9000");
+    /// ```
+    pub fn proxy(&mut self, path: &str, upstream: &str) {
+        let upstream = Arc::new(Upstream::parse(upstream));
+        for method in PROXIED_METHODS {
+            let upstream = Arc::clone(&upstream);
+            self.add_endpoint_with_config(
+                path,
+                method,
+                move |request| Some(forward(&upstream, request).unwrap_or_else(|_| bad_gateway())),
+                EndpointConfig::new().stream_body(),
+            );
+        }
+    }
+}
+
+/// Sends a request to the upstream server and converts its answer into a response.
+fn forward(upstream: &Upstream, mut request: Request) -> Result<Response, ClientError> {
+    let mut body = Vec::new();
+    if request.body_reader().read_to_end(&mut body).is_err() {
+        return Ok(text_response(400, "Bad Request"));
+    }
+
+    let mut headers = request.headers.clone();
+    strip_hop_by_hop(&mut headers);
+    if let Some(peer) = request.remote_addr {
+        let previous = headers
+            .get_joined("X-Forwarded-For")
+            .map(|value| value.into_owned());
+        headers.remove("X-Forwarded-For");
+        let forwarded_for = match previous {
+            Some(previous) => format!("{}, {}", previous, peer.ip()),
+            None => peer.ip().to_string(),
+        };
+        headers.insert("X-Forwarded-For", forwarded_for);
+    }
+    for (name, value) in request.propagation_headers() {
+        headers.insert(name, value);
+    }
+
+    let url = format!(
+        "http://{}{}{}",
+        upstream.authority, upstream.base_path, request.url
+    );
+    let mut outbound = ClientRequest::new(request.method, &url)
+        .body(body)
+        .timeout(UPSTREAM_TIMEOUT);
+    for (name, value) in headers.iter() {
+        outbound = outbound.header(name, value);
+    }
+    let response = outbound.send()?;
+
+    let mut headers = response.headers;
+    strip_hop_by_hop(&mut headers);
+    headers.retain(|key, _| {
+        !key.eq_ignore_ascii_case("Content-Length") && !key.eq_ignore_ascii_case("Date")
+    });
+
+    Ok(Response {
+        status_code: response.status,
+        reason: reason_phrase(response.status).into(),
+        response_body: Some(response.body.into()),
+        headers,
+    })
+}
+
+/// Removes hop-by-hop headers, including any the `Connection` header names.
+fn strip_hop_by_hop(headers: &mut HeaderMap) {
+    let listed: Vec<String> = headers
+        .get_all("Connection")
+        .flat_map(|value| value.split(','))
+        .map(|name| name.trim().to_ascii_lowercase())
+        .collect();
+    headers.retain(|key, _| {
+        let key = key.to_ascii_lowercase();
+        !HOP_BY_HOP_HEADERS.contains(&key.as_str()) && !listed.contains(&key)
+    });
+}
+
+/// Builds the response sent when the upstream server fails.
+fn bad_gateway() -> Response {
+    Response {
+        status_code: 502,
+        reason: "Bad Gateway".into(),
+        response_body: Some("Bad Gateway".into()),
+        headers: HeaderMap::new(),
+    }
+}
+
+#[cfg(test)]
+mod test_proxy {
+    use super::*;
+
+    /// Tests parsing upstream URLs with and without a port and base path.
+    #[test]
+    fn test_parse_upstream() {
+        let upstream = Upstream::parse("http://127.0.0.1:9000");
+        assert_eq!(upstream.authority, "127.0.0.1:9000");
+        assert_eq!(upstream.base_path, "");
+
+        let upstream = Upstream::parse("http://backend/v1/");
+        assert_eq!(upstream.authority, "backend:80");
+        assert_eq!(upstream.base_path, "/v1");
+    }
+
+    /// Tests that hop-by-hop headers, including ones named by `Connection`, are removed.
+    #[test]
+    fn test_strip_hop_by_hop() {
+        let mut headers = HeaderMap::new();
+        headers.append("connection", "keep-alive, X-Secret");
+        headers.append("Connection", "X-Other");
+        headers.append("Transfer-Encoding", "chunked");
+        headers.append("x-secret", "1");
+        headers.append("X-Other", "2");
+        headers.append("Accept", "*/*");
+        strip_hop_by_hop(&mut headers);
+        assert_eq!(headers.len(), 1);
+        assert!(headers.contains_key("Accept"));
+    }
+}
@@ -0,0 +1,182 @@
+use crate::app::Request;
+use crate::http_error::HttpError;
+use crate::parse_url::{percent_decode, query_pairs};
+use std::fmt;
+use std::str::FromStr;
+
+/// A URL query parameter looked up with [`Request::query_param`], decoded and ready to
+/// be converted with an error naming it.
+#[derive(Debug, Clone, PartialEq)]
+pub struct QueryParam<'a> {
+    name: &'a str,
+    /// The decoded value, `None` when it is missing or empty, or `Err` holding the raw
+    /// value when it is not valid percent-encoded UTF-8.
+    value: Result<Option<String>, &'a str>,
+}
+
+impl QueryParam<'_> {
+    /// Returns the decoded value, or `None` when the parameter is missing, empty or
+    /// malformed.
+    pub fn value(&self) -> Option<String> {
+        self.value.clone().ok()?
+    }
+
+    /// Returns the decoded value.
+    ///
+    /// # Errors
+    ///
+    /// Fails with `400 Bad Request` naming the parameter when it is missing, empty or
+    /// malformed.
+    pub fn as_str(&self) -> Result<&str, HttpError> {
+        match &self.value {
+            Ok(Some(value)) => Ok(value),
+            Ok(None) => Err(HttpError::bad_request(format!(
+                "Bad Request: missing query parameter `{}`",
+                self.name
+            ))),
+            Err(raw) => Err(self.invalid(raw, "not valid percent-encoded UTF-8")),
+        }
+    }
+
+    /// Parses the decoded value as `T`.
+    ///
+    /// # Errors
+    ///
+    /// Fails with `400 Bad Request` naming the parameter when it is missing, empty or
+    /// malformed, or when its value does not parse.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use rustic::app::App;
+    /// use rustic::http_error::HttpError;
+    /// use rustic::test::TestClient;
+    ///
+    /// let mut application = App::new();
+    /// application.get("zoom", |request| -> Result<String, HttpError> {
+    ///     let level: f32 = request.query_param("level").parse()?;
+    ///     Ok(format!("Zoomed to {}x", level))
+    /// });
+    /// let client = TestClient::new(application);
+    /// assert_eq!(client.get("/zoom?level=1.5").send().text(), "Zoomed to 1.5x");
+    /// let response = client.get("/zoom?level=max").send();
+    /// assert_eq!(
+    ///     response.text(),
+    ///     "Bad Request: invalid value for query parameter `level`: \
+    ///      invalid float literal, found \"max\""
+    /// );
+    /// ```
+    pub fn parse<T: FromStr>(&self) -> Result<T, HttpError>
+    where
+        T::Err: fmt::Display,
+    {
+        let value = self.as_str()?;
+        value.parse().map_err(|err| self.invalid(value, err))
+    }
+
+    /// Parses the decoded value as an unsigned 32-bit integer, such as a page number;
+    /// see [`QueryParam::parse`].
+    pub fn as_u32(&self) -> Result<u32, HttpError> {
+        self.parse()
+    }
+
+    /// Parses the decoded value as a signed 64-bit integer; see [`QueryParam::parse`].
+    pub fn as_i64(&self) -> Result<i64, HttpError> {
+        self.parse()
+    }
+
+    /// Parses the decoded value as `true` or `false`; see [`QueryParam::parse`].
+    pub fn as_bool(&self) -> Result<bool, HttpError> {
+        self.parse()
+    }
+
+    /// Creates the error for a value `text` that is invalid for `reason`.
+    fn invalid(&self, text: &str, reason: impl fmt::Display) -> HttpError {
+        HttpError::bad_request(format!(
+            "Bad Request: invalid value for query parameter `{}`: {}, found {:?}",
+            self.name, reason, text
+        ))
+    }
+}
+
+impl Request {
+    /// Looks up a URL query parameter by its decoded name, decoding its value.
+    ///
+    /// The query string is only decoded as far as needed to find the parameter, on each
+    /// call. When the parameter is repeated, the last value is used. A parameter with an
+    /// empty value, as in `?page=`, counts as missing.
+    ///
+    /// # Arguments
+    ///
+    /// * `name` - The parameter name.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use rustic::app::App;
+    /// use rustic::http_error::HttpError;
+    /// use rustic::test::TestClient;
+    ///
+    /// let mut application = App::new();
+    /// application.get("users", |request| -> Result<String, HttpError> {
+    ///     let page = request.query_param("page").as_u32()?;
+    ///     let sort = request.query_param("sort").value();
+    ///     let sort = sort.unwrap_or_else(|| "name".to_string());
+    ///     Ok(format!("Page {} by {}", page, sort))
+    /// });
+    /// let client = TestClient::new(application);
+    /// assert_eq!(client.get("/users?page=2").send().text(), "Page 2 by name");
+    /// let response = client.get("/users?page=-1&sort=age").send();
+    /// assert_eq!(response.status, 400);
+    /// ```
+    pub fn query_param<'a>(&'a self, name: &'a str) -> QueryParam<'a> {
+        let raw = query_pairs(&self.url)
+            .filter(|(key, _)| percent_decode(key).as_deref() == Some(name))
+            .last()
+            .map(|(_, value)| value);
+        let value = match raw {
+            Some(raw) => percent_decode(raw)
+                .ok_or(raw)
+                .map(|value| Some(value).filter(|value| !value.is_empty())),
+            None => Ok(None),
+        };
+        QueryParam { name, value }
+    }
+}
+
+/// Returns the parameters of `required` missing from the query of `url`, in the order
+/// they are given, once names and values are decoded. A parameter with an empty value
+/// counts as missing.
+pub(crate) fn missing_params<'a>(url: &str, required: &'a [String]) -> Vec<&'a str> {
+    if required.is_empty() {
+        return Vec::new();
+    }
+    let present: Vec<String> = query_pairs(url)
+        .filter(|(_, value)| !value.is_empty())
+        .filter_map(|(key, _)| percent_decode(key))
+        .collect();
+    required
+        .iter()
+        .filter(|name| !present.contains(name))
+        .map(String::as_str)
+        .collect()
+}
+
+#[cfg(test)]
+mod test_query {
+    use super::*;
+
+    /// Tests that required parameters are matched by their decoded names, and that empty
+    /// values do not count.
+    #[test]
+    fn test_missing_params() {
+        let required = ["user_id".to_string(), "from".to_string()];
+        assert!(missing_params("/report?user%5Fid=7&from=2024", &required).is_empty());
+        assert_eq!(missing_params("/report?user_id=7", &required), ["from"]);
+        assert_eq!(
+            missing_params("/report?user_id=&to=2025", &required),
+            ["user_id", "from"]
+        );
+        assert_eq!(missing_params("/report", &required[1..]), ["from"]);
+    }
+}
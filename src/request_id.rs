@@ -0,0 +1,323 @@
+use crate::app::{Request, UNMATCHED_ROUTE};
+use crate::http11_response::Response;
+use crate::middleware::{Middleware, Next};
+use crate::traffic::{Exchange, Traffic};
+use std::time::{Instant, SystemTime, UNIX_EPOCH};
+
+/// The longest incoming request ID that is accepted instead of generating a new one.
+const MAX_REQUEST_ID_LEN: usize = 200;
+/// The header a request ID is carried in unless [`RequestIdMiddleware::header`] says
+/// otherwise.
+const DEFAULT_HEADER: &str = "X-Request-Id";
+
+/// The correlation ID of a request, stored in its extensions by [`RequestIdMiddleware`].
+#[derive(Debug, Clone, PartialEq)]
+pub struct RequestId(pub String);
+
+/// The header [`RequestIdMiddleware`] was configured with, stored next to the
+/// [`RequestId`] when it is not `X-Request-Id`.
+struct RequestIdHeader(String);
+
+impl Request {
+    /// Returns the correlation ID assigned by [`RequestIdMiddleware`], if it is installed.
+    pub fn request_id(&self) -> Option<&str> {
+        self.extensions.get::<RequestId>().map(|id| id.0.as_str())
+    }
+
+    /// Returns the headers to send on calls made to other services while handling this
+    /// request, so that they can be correlated with it: the request ID, and the W3C
+    /// `traceparent` and `tracestate` headers when the request carried a valid trace
+    /// context.
+    ///
+    /// The ID is the one [`RequestIdMiddleware`] assigned, in the header it was
+    /// configured with, and otherwise the incoming `X-Request-Id` when it is valid. The
+    /// reverse [proxy](crate::app::App::proxy) sends these upstream on its own.
+    ///
+    /// # Returns
+    ///
+    /// * `Vec<(String, String)>` - The header names and values, empty when there is
+    ///   nothing to propagate.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use rustic::app::App;
+    /// use rustic::request_id::RequestIdMiddleware;
+    /// use rustic::test::TestClient;
+    ///
+    /// let mut application = App::new();
+    /// application.add_middleware(RequestIdMiddleware::new());
+    /// application.get("outbound", |request| {
+    ///     let names: Vec<String> = request
+    ///         .propagation_headers()
+    ///         .into_iter()
+    ///         .map(|(name, _)| name)
+    ///         .collect();
+    ///     names.join(",")
+    /// });
+    /// let client = TestClient::new(application);
+    /// let response = client
+    ///     .get("/outbound")
+    ///     .header("traceparent", "00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01")
+    ///     .send();
+    /// assert_eq!(response.text(), "X-Request-Id,traceparent");
+    /// ```
+    pub fn propagation_headers(&self) -> Vec<(String, String)> {
+        let mut headers = Vec::new();
+        match self.request_id() {
+            Some(id) => {
+                let name = self
+                    .extensions
+                    .get::<RequestIdHeader>()
+                    .map_or(DEFAULT_HEADER, |header| header.0.as_str());
+                headers.push((name.to_string(), id.to_string()));
+            }
+            None => {
+                if let Some(id) = self
+                    .header(DEFAULT_HEADER)
+                    .filter(|id| is_valid_request_id(id))
+                {
+                    headers.push((DEFAULT_HEADER.to_string(), id.to_string()));
+                }
+            }
+        }
+        if let Some(parent) = self
+            .header("traceparent")
+            .filter(|parent| is_valid_traceparent(parent))
+        {
+            headers.push(("traceparent".to_string(), parent.to_string()));
+            // A trace state means nothing without the trace it belongs to.
+            if let Some(state) = self.header("tracestate") {
+                headers.push(("tracestate".to_string(), state.to_string()));
+            }
+        }
+        headers
+    }
+}
+
+/// Middleware giving every request a correlation ID.
+///
+/// The ID is taken from the request's `X-Request-Id` header when the client or an
+/// upstream service sent one, and generated otherwise. An incoming ID that is empty,
+/// longer than 200 bytes or contains whitespace or control characters is replaced by a
+/// generated one. The ID is exposed through [`Request::request_id`], copied onto the
+/// response and, when enabled, included in an access log line written once the response
+/// is ready, along with the [route pattern](crate::app::Request::matched_route), the
+/// [route name](crate::app::Request::route_name) when the endpoint has one and the
+/// bytes read for the request and written in answer to it.
+/// With the `tracing` feature, each
+/// request also runs inside a `request` span carrying the method, path and ID, so events
+/// logged by handlers are correlated automatically.
+///
+/// Register it before other middleware so that they see the ID as well.
+///
+/// # Examples
+///
+/// ```
+/// use rustic::app::App;
+/// use rustic::request_id::RequestIdMiddleware;
+///
+/// let mut application = App::new();
+/// application.add_middleware(RequestIdMiddleware::new().access_log(true));
+/// application.get("whoami", |request| {
+///     format!("Request {}", request.request_id().unwrap_or("-"))
+/// });
+/// ```
+pub struct RequestIdMiddleware {
+    header: String,
+    access_log: bool,
+}
+
+impl Default for RequestIdMiddleware {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl RequestIdMiddleware {
+    /// Creates the middleware reading and setting `X-Request-Id`, without an access log.
+    pub fn new() -> Self {
+        RequestIdMiddleware {
+            header: DEFAULT_HEADER.to_string(),
+            access_log: false,
+        }
+    }
+
+    /// Sets the header the ID is read from and sent back in.
+    pub fn header(mut self, name: &str) -> Self {
+        self.header = name.to_string();
+        self
+    }
+
+    /// Sets whether an access log line is logged for every request, at info level under
+    /// the `rustic::access` target.
+    pub fn access_log(mut self, enabled: bool) -> Self {
+        self.access_log = enabled;
+        self
+    }
+}
+
+impl Middleware for RequestIdMiddleware {
+    fn handle(&self, mut request: Request, next: Next) -> Response {
+        let started = Instant::now();
+        let id = request
+            .header(&self.header)
+            .filter(|id| is_valid_request_id(id))
+            .map(str::to_string)
+            .unwrap_or_else(generate_request_id);
+        request.extensions.insert(RequestId(id.clone()));
+        if !self.header.eq_ignore_ascii_case(DEFAULT_HEADER) {
+            request
+                .extensions
+                .insert(RequestIdHeader(self.header.clone()));
+        }
+        let line_start = self.access_log.then(|| {
+            let remote = request
+                .remote_addr
+                .map_or_else(|| "-".to_string(), |address| address.to_string());
+            let route = request.matched_route().unwrap_or(UNMATCHED_ROUTE);
+            let mut line = format!(
+                "{} \"{} {}\" route={}",
+                remote,
+                request.method.as_str(),
+                request.url,
+                route
+            );
+            if let Some(name) = request.route_name() {
+                line.push_str(&format!(" name={}", name));
+            }
+            line
+        });
+        let traffic = request.extensions.get::<Traffic>().cloned();
+
+        #[cfg(feature = "tracing")]
+        let span = tracing::info_span!(
+            "request",
+            method = request.method.as_str(),
+            path = %request.path,
+            request_id = %id,
+        );
+        #[cfg(feature = "tracing")]
+        let _entered = span.enter();
+
+        let mut response = next.run(request);
+        response.headers.insert(self.header.as_str(), id.as_str());
+        if let Some(line_start) = line_start {
+            let status_code = response.status_code;
+            // The sizes are known once the response is serialized, after every
+            // middleware has returned. The duration is then the one the server measured,
+            // which the slow request log reports as well.
+            match traffic {
+                Some(traffic) => traffic.push(Box::new(move |exchange: &Exchange| {
+                    log::info!(
+                        target: "rustic::access",
+                        "{} {} {}ms request_id={} read={} written={}",
+                        line_start,
+                        status_code,
+                        exchange.handled.as_millis(),
+                        id,
+                        exchange.bytes.read(),
+                        exchange.bytes.written()
+                    );
+                })),
+                None => log::info!(
+                    target: "rustic::access",
+                    "{} {} {}ms request_id={}",
+                    line_start,
+                    status_code,
+                    started.elapsed().as_millis(),
+                    id
+                ),
+            }
+        }
+        response
+    }
+}
+
+/// Checks whether an incoming ID is short and printable enough to be trusted in logs.
+fn is_valid_request_id(id: &str) -> bool {
+    !id.is_empty() && id.len() <= MAX_REQUEST_ID_LEN && id.bytes().all(|b| b.is_ascii_graphic())
+}
+
+/// Checks whether `value` is a `traceparent` header as W3C Trace Context defines it:
+/// a version, a trace ID and a parent ID that are not all zeros, and trace flags, in
+/// lowercase hexadecimal separated by dashes.
+///
+/// Versions after `00` may append more fields, which are kept.
+fn is_valid_traceparent(value: &str) -> bool {
+    let is_hex = |field: &str, len: usize| {
+        field.len() == len
+            && field
+                .bytes()
+                .all(|b| matches!(b, b'0'..=b'9' | b'a'..=b'f'))
+    };
+    let not_zero = |field: &str| field.bytes().any(|b| b != b'0');
+    let mut fields = value.split('-');
+    let (Some(version), Some(trace_id), Some(parent_id), Some(flags)) =
+        (fields.next(), fields.next(), fields.next(), fields.next())
+    else {
+        return false;
+    };
+    is_hex(version, 2)
+        && version != "ff"
+        && (version != "00" || fields.next().is_none())
+        && is_hex(trace_id, 32)
+        && not_zero(trace_id)
+        && is_hex(parent_id, 16)
+        && not_zero(parent_id)
+        && is_hex(flags, 2)
+}
+
+/// Generates an ID from the current time in milliseconds and a random suffix.
+fn generate_request_id() -> String {
+    let millis = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |elapsed| elapsed.as_millis());
+    let mut suffix = [0u8; 6];
+    getrandom::fill(&mut suffix).expect("Failed to gather randomness for request ID");
+    let suffix: String = suffix.iter().map(|byte| format!("{:02x}", byte)).collect();
+    format!("{:x}-{}", millis, suffix)
+}
+
+#[cfg(test)]
+mod test_request_id {
+    use super::*;
+
+    /// Tests that generated IDs have a timestamp and a random suffix and are distinct.
+    #[test]
+    fn test_generate_request_id() {
+        let id = generate_request_id();
+        let (timestamp, suffix) = id.split_once('-').unwrap();
+        assert!(u64::from_str_radix(timestamp, 16).is_ok());
+        assert_eq!(suffix.len(), 12);
+        assert_ne!(id, generate_request_id());
+    }
+
+    /// Tests that empty, oversized or unprintable incoming IDs are rejected.
+    #[test]
+    fn test_is_valid_request_id() {
+        assert!(is_valid_request_id("req-42"));
+        assert!(!is_valid_request_id(""));
+        assert!(!is_valid_request_id("has space"));
+        assert!(!is_valid_request_id(&"x".repeat(MAX_REQUEST_ID_LEN + 1)));
+    }
+
+    /// Tests that only well-formed trace contexts with non-zero IDs are accepted.
+    #[test]
+    fn test_is_valid_traceparent() {
+        let valid = "00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01";
+        assert!(is_valid_traceparent(valid));
+        assert!(is_valid_traceparent(&format!("01{}-extra", &valid[2..])));
+        assert!(!is_valid_traceparent(&format!("{}-extra", valid)));
+        assert!(!is_valid_traceparent(&format!("ff{}", &valid[2..])));
+        assert!(!is_valid_traceparent(&valid.to_uppercase()));
+        assert!(!is_valid_traceparent(
+            "00-00000000000000000000000000000000-00f067aa0ba902b7-01"
+        ));
+        assert!(!is_valid_traceparent(
+            "00-4bf92f3577b34da6a3ce929d0e0e4736-0000000000000000-01"
+        ));
+        assert!(!is_valid_traceparent("00-4bf92f35-00f067aa0ba902b7-01"));
+        assert!(!is_valid_traceparent(""));
+    }
+}
@@ -0,0 +1,522 @@
+use crate::header_map::HeaderMap;
+use crate::http11_response::{write_interim_response, Body, Message, Response};
+use crate::traffic::CountingWriter;
+use std::fmt;
+use std::io::{self, Write};
+use std::net::TcpStream;
+
+/// How far a [`ResponseWriter`] is through the response to the current request.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WriterState {
+    /// Nothing of the final response is written; interim responses may be.
+    Idle,
+    /// The status line and headers are written.
+    HeadersSent,
+    /// The body is written.
+    BodySent,
+    /// The response is complete, and nothing more may be written until the next one.
+    Done,
+}
+
+/// A write a [`ResponseWriter`] was asked for.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WriteStep {
+    /// An interim `1xx` response.
+    Interim,
+    /// The status line and headers of the final response.
+    Head,
+    /// The body of the final response.
+    Body,
+    /// The end of the final response.
+    Finish,
+    /// The start of the response to the next request.
+    Next,
+}
+
+impl fmt::Display for WriteStep {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            WriteStep::Interim => "an interim response",
+            WriteStep::Head => "the head of a response",
+            WriteStep::Body => "the body of a response",
+            WriteStep::Finish => "the end of a response",
+            WriteStep::Next => "the next response",
+        })
+    }
+}
+
+/// Why a [`ResponseWriter`] refused or failed a write.
+#[derive(Debug)]
+pub enum WriteError {
+    /// The write does not follow what was written before, such as a second head or a
+    /// body before its head. Nothing was written.
+    OutOfOrder {
+        /// How far the writer was.
+        state: WriterState,
+        /// The write that was refused.
+        step: WriteStep,
+    },
+    /// The body is not as long as the head said. Nothing was written.
+    BodyLength {
+        /// The length the head announced.
+        expected: usize,
+        /// The length of the body.
+        actual: usize,
+    },
+    /// A write failed before, or the connection was taken over, so where another
+    /// response would start is unknown and nothing more is written.
+    Closed,
+    /// Writing to the connection failed after `written` bytes of this write.
+    Io {
+        /// The error of the write.
+        error: io::Error,
+        /// How many bytes were written before it failed.
+        written: usize,
+    },
+}
+
+impl fmt::Display for WriteError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            WriteError::OutOfOrder { state, step } => {
+                write!(f, "cannot write {} in state {:?}", step, state)
+            }
+            WriteError::BodyLength { expected, actual } => write!(
+                f,
+                "the body is {} bytes long but the head announced {}",
+                actual, expected
+            ),
+            WriteError::Closed => f.write_str("the connection is closed to responses"),
+            WriteError::Io { error, .. } => error.fmt(f),
+        }
+    }
+}
+
+impl std::error::Error for WriteError {}
+
+impl From<WriteError> for io::Error {
+    fn from(err: WriteError) -> Self {
+        match err {
+            WriteError::Io { error, .. } => error,
+            err => io::Error::new(io::ErrorKind::InvalidInput, err),
+        }
+    }
+}
+
+/// The writing side of a connection, enforcing that each request gets exactly one
+/// response, written in order.
+///
+/// A response goes from [`Idle`](WriterState::Idle), where interim responses may be
+/// written, through its head and body to [`Done`](WriterState::Done), after which
+/// [`ResponseWriter::next_response`] starts the response to the next request. A write
+/// out of that order, such as a second response to the same request or a body before
+/// its head, is refused with [`WriteError::OutOfOrder`] and logged, rather than
+/// interleaving its bytes with those of another response. Once a write fails, the
+/// client cannot tell where another response would start, so every later write is
+/// refused with [`WriteError::Closed`].
+///
+/// The servers write every response through one, and [`write_connection`] writes
+/// through one for a single response.
+///
+/// [`write_connection`]: crate::http11_response::write_connection
+///
+/// # Examples
+///
+/// ```
+/// use rustic::header_map::HeaderMap;
+/// use rustic::into_response::text_response;
+/// use rustic::response_writer::{ResponseWriter, WriteError, WriterState};
+///
+/// let mut writer = ResponseWriter::new(Vec::new());
+/// writer.write_interim(100, &HeaderMap::new()).unwrap();
+/// writer.write_response(text_response(200, "first")).unwrap();
+/// assert_eq!(writer.state(), WriterState::Done);
+/// assert!(matches!(
+///     writer.write_response(text_response(200, "again")),
+///     Err(WriteError::OutOfOrder { .. })
+/// ));
+///
+/// writer.next_response().unwrap();
+/// writer.write_response(text_response(200, "second")).unwrap();
+/// let written = String::from_utf8(writer.into_inner()).unwrap();
+/// assert!(written.starts_with("HTTP/1.1 100 Continue \r\n\r\nHTTP/1.1 200 OK \r\n"));
+/// assert!(written.ends_with("\r\n\r\nsecond"));
+/// assert!(!written.contains("again"));
+/// ```
+pub struct ResponseWriter<W: Write> {
+    stream: W,
+    state: WriterState,
+    /// The length of the body the head that was written announced.
+    body_length: usize,
+    /// Whether a write failed or the connection was taken over.
+    closed: bool,
+}
+
+impl<W: Write> ResponseWriter<W> {
+    /// Creates a writer for the first response on `stream`.
+    pub fn new(stream: W) -> Self {
+        ResponseWriter {
+            stream,
+            state: WriterState::Idle,
+            body_length: 0,
+            closed: false,
+        }
+    }
+
+    /// Returns how far the writer is through the current response.
+    pub fn state(&self) -> WriterState {
+        self.state
+    }
+
+    /// Writes an interim `1xx` response, such as `103 Early Hints`, before the final
+    /// response; see [`write_interim_response`].
+    ///
+    /// # Errors
+    ///
+    /// Fails with [`WriteError::OutOfOrder`] once the final response has begun, and
+    /// with [`WriteError::Io`] for a status that is not interim or a failed write.
+    pub fn write_interim(
+        &mut self,
+        status_code: u16,
+        headers: &HeaderMap,
+    ) -> Result<(), WriteError> {
+        self.check(WriteStep::Interim)?;
+        let mut writer = CountingWriter::new(&mut self.stream);
+        let written = write_interim_response(&mut writer, status_code, headers);
+        let count = writer.count();
+        // A refused status writes nothing, so the connection can still be used.
+        if let Err(error) = written {
+            if count > 0 {
+                self.closed = true;
+            }
+            return Err(WriteError::Io {
+                error,
+                written: count,
+            });
+        }
+        Ok(())
+    }
+
+    /// Writes the status line and headers of the final response, framing its body,
+    /// which is then written with [`ResponseWriter::write_body`].
+    ///
+    /// # Errors
+    ///
+    /// Fails with [`WriteError::OutOfOrder`] unless the writer is idle.
+    pub fn write_head(&mut self, response: &Response) -> Result<(), WriteError> {
+        self.check(WriteStep::Head)?;
+        let head = response.head(0);
+        self.write_all(head.as_bytes())?;
+        self.body_length = response.response_body.as_ref().map_or(0, Body::len);
+        self.state = WriterState::HeadersSent;
+        Ok(())
+    }
+
+    /// Writes the body of the final response, after its head.
+    ///
+    /// # Errors
+    ///
+    /// Fails with [`WriteError::OutOfOrder`] unless only the head was written, and
+    /// with [`WriteError::BodyLength`] for a body of another length than the head
+    /// announced.
+    pub fn write_body(&mut self, body: &Body) -> Result<(), WriteError> {
+        self.check(WriteStep::Body)?;
+        if body.len() != self.body_length {
+            let err = WriteError::BodyLength {
+                expected: self.body_length,
+                actual: body.len(),
+            };
+            log::error!("Refused to write a response body: {}", err);
+            return Err(err);
+        }
+        let mut writer = CountingWriter::new(&mut self.stream);
+        if let Err(error) = body.write_to(&mut writer) {
+            let written = writer.count();
+            self.closed = true;
+            return Err(WriteError::Io { error, written });
+        }
+        self.state = WriterState::BodySent;
+        Ok(())
+    }
+
+    /// Ends the final response, after its head and any body, and flushes the stream.
+    ///
+    /// # Errors
+    ///
+    /// Fails with [`WriteError::OutOfOrder`] before the head is written or once the
+    /// response is done.
+    pub fn finish(&mut self) -> Result<(), WriteError> {
+        self.check(WriteStep::Finish)?;
+        if let Err(error) = self.stream.flush() {
+            self.closed = true;
+            return Err(WriteError::Io { error, written: 0 });
+        }
+        self.state = WriterState::Done;
+        Ok(())
+    }
+
+    /// Writes a whole final response, head and body in one write where the body is in
+    /// memory.
+    ///
+    /// # Returns
+    ///
+    /// * `Result<usize, WriteError>` - How many bytes were written.
+    ///
+    /// # Errors
+    ///
+    /// Fails with [`WriteError::OutOfOrder`] unless the writer is idle.
+    pub fn write_response(&mut self, response: Response) -> Result<usize, WriteError> {
+        let message = response.into_message();
+        self.write_message(&message)?;
+        Ok(message.len())
+    }
+
+    /// Starts the response to the next request on the connection, once the current one
+    /// is done.
+    ///
+    /// # Errors
+    ///
+    /// Fails with [`WriteError::OutOfOrder`] while the current response is unfinished,
+    /// and with [`WriteError::Closed`] once a write has failed.
+    pub fn next_response(&mut self) -> Result<(), WriteError> {
+        self.check(WriteStep::Next)?;
+        self.state = WriterState::Idle;
+        Ok(())
+    }
+
+    /// Returns the stream the writer writes to.
+    pub fn into_inner(self) -> W {
+        self.stream
+    }
+
+    /// Writes a serialized final response, going from idle to done.
+    pub(crate) fn write_message(&mut self, message: &Message) -> Result<(), WriteError> {
+        self.transmit(message, |stream, message| {
+            let mut writer = CountingWriter::new(stream);
+            message
+                .write_to(&mut writer)
+                .map_err(|err| (err, writer.count()))
+        })
+    }
+
+    /// Stops all writes, for a connection a handler took over and writes to itself.
+    pub(crate) fn hand_over(&mut self) {
+        self.closed = true;
+        self.state = WriterState::Done;
+    }
+
+    /// Writes a serialized final response with `send`, which returns how many bytes it
+    /// wrote when it fails.
+    fn transmit(
+        &mut self,
+        message: &Message,
+        send: impl FnOnce(&mut W, &Message) -> Result<(), (io::Error, usize)>,
+    ) -> Result<(), WriteError> {
+        self.check(WriteStep::Head)?;
+        match send(&mut self.stream, message) {
+            Ok(()) => {
+                self.state = WriterState::Done;
+                Ok(())
+            }
+            Err((error, written)) => {
+                self.closed = true;
+                if written >= message.head_len() {
+                    self.state = WriterState::HeadersSent;
+                }
+                Err(WriteError::Io { error, written })
+            }
+        }
+    }
+
+    /// Writes bytes of the current step, closing the writer if that fails.
+    fn write_all(&mut self, bytes: &[u8]) -> Result<(), WriteError> {
+        let mut writer = CountingWriter::new(&mut self.stream);
+        if let Err(error) = writer.write_all(bytes) {
+            let written = writer.count();
+            self.closed = true;
+            return Err(WriteError::Io { error, written });
+        }
+        Ok(())
+    }
+
+    /// Checks that `step` may be written in the current state, logging a write that is
+    /// refused.
+    fn check(&self, step: WriteStep) -> Result<(), WriteError> {
+        if self.closed {
+            log::debug!("Refused to write {} to a closed connection", step);
+            return Err(WriteError::Closed);
+        }
+        let allowed = match step {
+            WriteStep::Interim | WriteStep::Head => self.state == WriterState::Idle,
+            WriteStep::Body => self.state == WriterState::HeadersSent,
+            WriteStep::Finish => {
+                matches!(self.state, WriterState::HeadersSent | WriterState::BodySent)
+            }
+            WriteStep::Next => self.state == WriterState::Done,
+        };
+        if allowed {
+            return Ok(());
+        }
+        let err = WriteError::OutOfOrder {
+            state: self.state,
+            step,
+        };
+        log::error!("Refused to write out of order: {}", err);
+        Err(err)
+    }
+}
+
+impl ResponseWriter<TcpStream> {
+    /// Writes a serialized final response onto a socket, offloading a file body to the
+    /// kernel where the platform allows it; see [`Message::send`].
+    pub(crate) fn send(&mut self, message: &Message) -> Result<(), WriteError> {
+        self.transmit(message, |stream, message| message.send(stream))
+    }
+}
+
+#[cfg(test)]
+mod test_response_writer {
+    use super::*;
+    use crate::into_response::text_response;
+
+    /// A stream failing every write after its first `capacity` bytes.
+    struct Full(Vec<u8>, usize);
+
+    impl Write for Full {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            let room = self.1 - self.0.len();
+            if room == 0 {
+                return Err(io::ErrorKind::BrokenPipe.into());
+            }
+            let written = buf.len().min(room);
+            self.0.extend_from_slice(&buf[..written]);
+            Ok(written)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    /// Tests that a response written step by step goes through every state, and the
+    /// next one only starts once it is done.
+    #[test]
+    fn test_steps() {
+        let response = text_response(200, "hello");
+        let mut writer = ResponseWriter::new(Vec::new());
+        writer.write_interim(103, &HeaderMap::new()).unwrap();
+        writer.write_head(&response).unwrap();
+        assert_eq!(writer.state(), WriterState::HeadersSent);
+        writer
+            .write_body(response.response_body.as_ref().unwrap())
+            .unwrap();
+        assert_eq!(writer.state(), WriterState::BodySent);
+        writer.finish().unwrap();
+        assert_eq!(writer.state(), WriterState::Done);
+        writer.next_response().unwrap();
+        assert_eq!(writer.state(), WriterState::Idle);
+        writer.write_head(&text_response(204, "")).unwrap();
+        writer.finish().unwrap();
+
+        let written = String::from_utf8(writer.into_inner()).unwrap();
+        assert!(written.starts_with("HTTP/1.1 103 Early Hints \r\n\r\nHTTP/1.1 200 OK \r\n"));
+        assert!(written.contains("\r\nContent-Length: 5\r\n"));
+        assert!(written.contains("\r\n\r\nhelloHTTP/1.1 204 No Content \r\n"));
+    }
+
+    /// Tests that writes out of order are refused without writing anything.
+    #[test]
+    fn test_out_of_order() {
+        let out_of_order = |result: Result<(), WriteError>, state, step| match result {
+            Err(WriteError::OutOfOrder {
+                state: actual_state,
+                step: actual_step,
+            }) => assert_eq!((actual_state, actual_step), (state, step)),
+            result => panic!("{:?}", result),
+        };
+        let response = text_response(200, "hello");
+        let body = response.response_body.clone().unwrap();
+        let mut writer = ResponseWriter::new(Vec::new());
+        out_of_order(writer.write_body(&body), WriterState::Idle, WriteStep::Body);
+        out_of_order(writer.finish(), WriterState::Idle, WriteStep::Finish);
+        out_of_order(writer.next_response(), WriterState::Idle, WriteStep::Next);
+
+        writer.write_head(&response).unwrap();
+        out_of_order(
+            writer.write_head(&response),
+            WriterState::HeadersSent,
+            WriteStep::Head,
+        );
+        out_of_order(
+            writer.write_interim(100, &HeaderMap::new()),
+            WriterState::HeadersSent,
+            WriteStep::Interim,
+        );
+        out_of_order(
+            writer.next_response(),
+            WriterState::HeadersSent,
+            WriteStep::Next,
+        );
+        assert!(matches!(
+            writer.write_body(&Body::from("hi")),
+            Err(WriteError::BodyLength {
+                expected: 5,
+                actual: 2
+            })
+        ));
+        writer.write_body(&body).unwrap();
+        out_of_order(
+            writer.write_body(&body),
+            WriterState::BodySent,
+            WriteStep::Body,
+        );
+        writer.finish().unwrap();
+        out_of_order(writer.finish(), WriterState::Done, WriteStep::Finish);
+        let err = writer
+            .write_response(text_response(200, "again"))
+            .unwrap_err();
+        assert!(matches!(
+            err,
+            WriteError::OutOfOrder {
+                state: WriterState::Done,
+                step: WriteStep::Head
+            }
+        ));
+        assert_eq!(
+            err.to_string(),
+            "cannot write the head of a response in state Done"
+        );
+
+        let written = String::from_utf8(writer.into_inner()).unwrap();
+        assert_eq!(written.matches("HTTP/1.1").count(), 1);
+        assert!(written.ends_with("\r\n\r\nhello"));
+    }
+
+    /// Tests that a failed write closes the writer to every later write.
+    #[test]
+    fn test_failed_write() {
+        let mut writer = ResponseWriter::new(Full(Vec::new(), 20));
+        match writer.write_response(text_response(200, "hello")) {
+            Err(WriteError::Io { error, written }) => {
+                assert_eq!(error.kind(), io::ErrorKind::BrokenPipe);
+                assert_eq!(written, 20);
+            }
+            result => panic!("{:?}", result),
+        }
+        assert_eq!(writer.state(), WriterState::Idle);
+        assert!(matches!(writer.next_response(), Err(WriteError::Closed)));
+        assert!(matches!(
+            writer.write_response(text_response(500, "")),
+            Err(WriteError::Closed)
+        ));
+        assert_eq!(writer.into_inner().0.len(), 20);
+
+        let mut writer = ResponseWriter::new(Vec::new());
+        writer.hand_over();
+        assert!(matches!(
+            writer.write_interim(100, &HeaderMap::new()),
+            Err(WriteError::Closed)
+        ));
+        assert!(writer.into_inner().is_empty());
+    }
+}
@@ -0,0 +1,363 @@
+//! Endpoints described as data, such as a section of a configuration file, and wired to
+//! handlers registered by name.
+
+use crate::app::{unknown_constraint, App, EndpointConfig, Handler};
+use crate::parse_headers::parse_method;
+#[cfg(feature = "serde")]
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::fmt;
+use std::time::Duration;
+
+/// The endpoints of an app, in the order they are added, for [`App::from_route_table`].
+///
+/// With the `serde` feature, tables deserialize from any format serde supports, such as
+/// JSON or TOML:
+///
+/// ```json
+/// {"routes": [
+///     {"path": "users", "method": "GET", "handler": "list_users"},
+///     {"path": "users", "method": "POST", "handler": "create_user",
+///      "config": {"accepts": ["application/json"], "limit_body": 65536}}
+/// ]}
+/// ```
+#[derive(Debug, Clone, Default, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Deserialize))]
+pub struct RouteTable {
+    pub routes: Vec<RouteEntry>,
+}
+
+/// One endpoint of a [`RouteTable`].
+#[derive(Debug, Clone, Default, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Deserialize))]
+#[cfg_attr(feature = "serde", serde(deny_unknown_fields))]
+pub struct RouteEntry {
+    /// The endpoint path, as given to [`App::add_endpoint`].
+    pub path: String,
+    /// The request method in uppercase, such as `GET`, or `ANY` for every method, as
+    /// with [`App::any`].
+    pub method: String,
+    /// The name the handler is registered under.
+    pub handler: String,
+    /// The settings of the endpoint, the defaults of [`EndpointConfig`] if left out.
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub config: RouteSettings,
+}
+
+/// The [`EndpointConfig`] of a [`RouteEntry`], with every setting optional.
+#[derive(Debug, Clone, Default, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Deserialize))]
+#[cfg_attr(feature = "serde", serde(default, deny_unknown_fields))]
+pub struct RouteSettings {
+    /// See [`EndpointConfig::name`].
+    pub name: Option<String>,
+    /// See [`EndpointConfig::accepts`].
+    pub accepts: Vec<String>,
+    /// See [`EndpointConfig::require_params`].
+    pub require_params: Vec<String>,
+    /// See [`EndpointConfig::limit_body`].
+    pub limit_body: Option<usize>,
+    /// See [`EndpointConfig::timeout`], in milliseconds.
+    pub timeout_ms: Option<u64>,
+    /// See [`EndpointConfig::stream_body`].
+    pub stream_body: bool,
+    /// See [`EndpointConfig::spill_threshold`].
+    pub spill_threshold: Option<usize>,
+}
+
+impl RouteSettings {
+    /// Converts the settings into the configuration they describe.
+    fn to_config(&self) -> EndpointConfig {
+        let mut config = EndpointConfig::new();
+        if let Some(name) = &self.name {
+            config = config.name(name.as_str());
+        }
+        if !self.accepts.is_empty() {
+            let accepts: Vec<&str> = self.accepts.iter().map(String::as_str).collect();
+            config = config.accepts(&accepts);
+        }
+        if !self.require_params.is_empty() {
+            let names: Vec<&str> = self.require_params.iter().map(String::as_str).collect();
+            config = config.require_params(&names);
+        }
+        if let Some(bytes) = self.limit_body {
+            config = config.limit_body(bytes);
+        }
+        if let Some(millis) = self.timeout_ms {
+            config = config.timeout(Duration::from_millis(millis));
+        }
+        if self.stream_body {
+            config = config.stream_body();
+        }
+        if let Some(bytes) = self.spill_threshold {
+            config = config.spill_threshold(bytes);
+        }
+        config
+    }
+}
+
+/// Why [`App::from_route_table`] refused a table, naming the entry at fault by its
+/// position in [`RouteTable::routes`] and its path.
+#[derive(Debug, Clone, PartialEq)]
+pub enum RouteTableError {
+    /// No handler is registered under the entry's handler name.
+    UnknownHandler {
+        index: usize,
+        path: String,
+        handler: String,
+    },
+    /// The entry's method is not one the server implements.
+    InvalidMethod {
+        index: usize,
+        path: String,
+        method: String,
+    },
+    /// The entry's path constrains a parameter with an unknown kind.
+    InvalidPath {
+        index: usize,
+        path: String,
+        constraint: String,
+    },
+}
+
+impl fmt::Display for RouteTableError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RouteTableError::UnknownHandler {
+                index,
+                path,
+                handler,
+            } => write!(
+                f,
+                "route {} ({}): no handler is registered as `{}`",
+                index, path, handler
+            ),
+            RouteTableError::InvalidMethod {
+                index,
+                path,
+                method,
+            } => write!(f, "route {} ({}): invalid method `{}`", index, path, method),
+            RouteTableError::InvalidPath {
+                index,
+                path,
+                constraint,
+            } => write!(
+                f,
+                "route {} ({}): unknown constraint `{}`, expected u64, uuid or alpha",
+                index, path, constraint
+            ),
+        }
+    }
+}
+
+impl std::error::Error for RouteTableError {}
+
+impl App {
+    /// Builds an app serving the endpoints of `table`, each answered by the handler
+    /// registered under its name in `registry`.
+    ///
+    /// The table is checked as a whole before any endpoint is added, so a deployment
+    /// can enable, disable or reconfigure endpoints from its configuration while the
+    /// handlers stay in code. Middleware and other settings are added to the returned
+    /// app as usual.
+    ///
+    /// # Arguments
+    ///
+    /// * `table` - The endpoints to serve, added in order.
+    /// * `registry` - The handlers, by the names the table refers to them with. A
+    ///   handler may serve several entries, and need not serve any.
+    ///
+    /// # Errors
+    ///
+    /// Returns a [`RouteTableError`] for the first entry naming an unregistered handler,
+    /// an unknown method or an unknown path constraint.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use rustic::app::{App, Handler};
+    /// use rustic::into_response::text_response;
+    /// use rustic::route_table::{RouteEntry, RouteTable};
+    /// use rustic::test::TestClient;
+    /// use std::collections::HashMap;
+    /// use std::sync::Arc;
+    ///
+    /// let mut registry: HashMap<String, Handler> = HashMap::new();
+    /// registry.insert(
+    ///     "health".to_string(),
+    ///     Arc::new(|_| text_response(200, "ok").into()),
+    /// );
+    /// let table = RouteTable {
+    ///     routes: vec![RouteEntry {
+    ///         path: "healthz".to_string(),
+    ///         method: "GET".to_string(),
+    ///         handler: "health".to_string(),
+    ///         ..RouteEntry::default()
+    ///     }],
+    /// };
+    /// let application = App::from_route_table(table, registry).unwrap();
+    /// let client = TestClient::new(application);
+    /// assert_eq!(client.get("/healthz").send().text(), "ok");
+    /// ```
+    pub fn from_route_table(
+        table: RouteTable,
+        registry: HashMap<String, Handler>,
+    ) -> Result<App, RouteTableError> {
+        let mut endpoints = Vec::with_capacity(table.routes.len());
+        for (index, entry) in table.routes.into_iter().enumerate() {
+            let Some(handler) = registry.get(&entry.handler) else {
+                return Err(RouteTableError::UnknownHandler {
+                    index,
+                    path: entry.path,
+                    handler: entry.handler,
+                });
+            };
+            let method = match entry.method.as_str() {
+                "ANY" => None,
+                token => match parse_method(token) {
+                    Some(method) => Some(method),
+                    None => {
+                        return Err(RouteTableError::InvalidMethod {
+                            index,
+                            path: entry.path,
+                            method: entry.method,
+                        })
+                    }
+                },
+            };
+            if let Some(constraint) = unknown_constraint(&entry.path) {
+                return Err(RouteTableError::InvalidPath {
+                    index,
+                    constraint: constraint.to_string(),
+                    path: entry.path,
+                });
+            }
+            let config = entry.config.to_config();
+            endpoints.push((entry.path, method, handler.clone(), config));
+        }
+
+        let mut application = App::new();
+        for (path, method, handler, config) in endpoints {
+            let mapper = move |request| handler(request);
+            match method {
+                Some(method) => application.add_endpoint_with_config(path, method, mapper, config),
+                None => application
+                    .routes
+                    .insert_any(path, mapper, config, Vec::new()),
+            }
+        }
+        Ok(application)
+    }
+}
+
+#[cfg(test)]
+mod test_route_table {
+    use super::*;
+    use crate::app::Request;
+    use crate::into_response::text_response;
+    use crate::test::TestClient;
+    use std::sync::Arc;
+
+    /// Builds a registry with handlers echoing the path and answering with the body size.
+    fn registry() -> HashMap<String, Handler> {
+        let mut registry: HashMap<String, Handler> = HashMap::new();
+        registry.insert(
+            "show".to_string(),
+            Arc::new(|request: Request| {
+                let name = request.route_name().unwrap_or("-").to_string();
+                Some(text_response(200, format!("{} {}", name, request.path)))
+            }),
+        );
+        registry.insert(
+            "size".to_string(),
+            Arc::new(|request: Request| Some(text_response(201, request.body.len().to_string()))),
+        );
+        registry
+    }
+
+    /// Builds an entry without settings.
+    fn entry(path: &str, method: &str, handler: &str) -> RouteEntry {
+        RouteEntry {
+            path: path.to_string(),
+            method: method.to_string(),
+            handler: handler.to_string(),
+            config: RouteSettings::default(),
+        }
+    }
+
+    /// Tests that the entries of a table are served by their handlers, with their
+    /// settings applied.
+    #[test]
+    fn test_from_route_table() {
+        let mut show = entry("items/{id:u64}", "GET", "show");
+        show.config.name = Some("item".to_string());
+        let mut upload = entry("uploads", "POST", "size");
+        upload.config.limit_body = Some(4);
+        let table = RouteTable {
+            routes: vec![show, upload, entry("anything", "ANY", "show")],
+        };
+        let client = TestClient::new(App::from_route_table(table, registry()).unwrap());
+
+        let response = client.get("/items/7").send();
+        assert_eq!(response.status, 200);
+        assert_eq!(response.text(), "item items/7");
+        assert_eq!(client.get("/items/seven").send().status, 404);
+        assert_eq!(client.post("/uploads").body("abc").send().text(), "3");
+        assert_eq!(client.post("/uploads").body("abcde").send().status, 413);
+        assert_eq!(client.get("/uploads").send().status, 405);
+        assert_eq!(client.delete("/anything").send().text(), "- anything");
+    }
+
+    /// Tests that a faulty entry is reported by position and path.
+    #[test]
+    fn test_invalid_entries() {
+        let build = |bad: RouteEntry| {
+            let table = RouteTable {
+                routes: vec![entry("ok", "GET", "show"), bad],
+            };
+            App::from_route_table(table, registry()).err().unwrap()
+        };
+        let error = build(entry("users", "GET", "list_users"));
+        assert_eq!(
+            error,
+            RouteTableError::UnknownHandler {
+                index: 1,
+                path: "users".to_string(),
+                handler: "list_users".to_string(),
+            }
+        );
+        assert_eq!(
+            error.to_string(),
+            "route 1 (users): no handler is registered as `list_users`"
+        );
+        assert_eq!(
+            build(entry("users", "get", "show")).to_string(),
+            "route 1 (users): invalid method `get`"
+        );
+        assert_eq!(
+            build(entry("users/{id:int}", "GET", "show")).to_string(),
+            "route 1 (users/{id:int}): unknown constraint `int`, expected u64, uuid or alpha"
+        );
+    }
+
+    /// Tests that a table deserializes, with settings left out taking their defaults.
+    #[cfg(feature = "serde")]
+    #[test]
+    fn test_deserialize() {
+        let table: RouteTable = serde_json::from_str(
+            r#"{"routes": [
+                {"path": "a", "method": "GET", "handler": "show"},
+                {"path": "b", "method": "POST", "handler": "size",
+                 "config": {"accepts": ["text/plain"], "timeout_ms": 500}}
+            ]}"#,
+        )
+        .unwrap();
+        assert_eq!(table.routes[0], entry("a", "GET", "show"));
+        assert_eq!(table.routes[1].config.accepts, ["text/plain"]);
+        assert_eq!(table.routes[1].config.timeout_ms, Some(500));
+        assert!(serde_json::from_str::<RouteTable>(
+            r#"{"routes": [{"path": "a", "method": "GET", "handler": "show", "typo": 1}]}"#
+        )
+        .is_err());
+    }
+}
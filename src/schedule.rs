@@ -0,0 +1,170 @@
+use crate::app::App;
+use std::io;
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::{Arc, Condvar, Mutex};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+/// A job registered with [`App::schedule`].
+pub(crate) struct ScheduledJob {
+    interval: Duration,
+    job: Arc<dyn Fn() + Send + Sync>,
+}
+
+impl App {
+    /// Runs `job` every `interval` while the server runs, for recurring work such as
+    /// cleaning up expired sessions or flushing metrics.
+    ///
+    /// The first run happens one interval after the server starts, and the job stops
+    /// being run once the server shuts down, which waits for a run in progress to
+    /// finish. Runs are timed from when the server started rather than from when the
+    /// previous one ended, so they keep to the interval. A run that takes longer than
+    /// the interval skips the runs it overlapped. Each job runs on a thread of its own,
+    /// and a run that panics is logged without stopping the next ones.
+    ///
+    /// # Arguments
+    ///
+    /// * `interval` - How long to wait between runs.
+    /// * `job` - The work to run.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use rustic::app::App;
+    /// use std::time::Duration;
+    ///
+    /// let mut application = App::new();
+    /// application.schedule(Duration::from_secs(60), || println!("Still running"));
+    /// ```
+    pub fn schedule<F>(&mut self, interval: Duration, job: F)
+    where
+        F: Fn() + Send + Sync + 'static,
+    {
+        self.scheduled.push(ScheduledJob {
+            interval,
+            job: Arc::new(job),
+        });
+    }
+}
+
+/// Runs an app's scheduled jobs until it is dropped.
+pub(crate) struct Scheduler {
+    stopped: Arc<(Mutex<bool>, Condvar)>,
+    threads: Vec<JoinHandle<()>>,
+}
+
+impl Scheduler {
+    /// Starts a thread for each job.
+    ///
+    /// Fails when a thread cannot be spawned, stopping those already started.
+    pub(crate) fn start(jobs: &[ScheduledJob]) -> io::Result<Self> {
+        let mut scheduler = Scheduler {
+            stopped: Arc::new((Mutex::new(false), Condvar::new())),
+            threads: Vec::with_capacity(jobs.len()),
+        };
+        for (index, job) in jobs.iter().enumerate() {
+            let stopped = Arc::clone(&scheduler.stopped);
+            let interval = job.interval;
+            let job = Arc::clone(&job.job);
+            let thread = thread::Builder::new()
+                .name(format!("rustic-schedule-{}", index))
+                .spawn(move || tick(&stopped, interval, &*job))?;
+            scheduler.threads.push(thread);
+        }
+        Ok(scheduler)
+    }
+}
+
+impl Drop for Scheduler {
+    /// Stops the jobs, waiting for runs in progress to finish.
+    fn drop(&mut self) {
+        let (stopped, wake) = &*self.stopped;
+        *stopped.lock().unwrap() = true;
+        wake.notify_all();
+        for thread in self.threads.drain(..) {
+            let _ = thread.join();
+        }
+    }
+}
+
+/// Runs a job at every deadline until the scheduler stops.
+fn tick(stopped: &(Mutex<bool>, Condvar), interval: Duration, job: &(dyn Fn() + Send + Sync)) {
+    // A zero interval would spin, so it is treated as the shortest one that does not.
+    let interval = interval.max(Duration::from_millis(1));
+    let (stopped, wake) = stopped;
+    let mut deadline = Instant::now() + interval;
+    loop {
+        let mut is_stopped = stopped.lock().unwrap();
+        loop {
+            if *is_stopped {
+                return;
+            }
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            if remaining.is_zero() {
+                break;
+            }
+            is_stopped = wake.wait_timeout(is_stopped, remaining).unwrap().0;
+        }
+        drop(is_stopped);
+
+        if panic::catch_unwind(AssertUnwindSafe(job)).is_err() {
+            log::error!("A scheduled job panicked");
+        }
+        deadline += interval;
+        let now = Instant::now();
+        if deadline <= now {
+            // Skip the runs the last one overlapped, keeping to the original timing.
+            let behind = (now - deadline).as_nanos() / interval.as_nanos() + 1;
+            deadline += interval * behind as u32;
+        }
+    }
+}
+
+#[cfg(test)]
+mod test_schedule {
+    use super::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    fn counting_job(interval: Duration, counter: &Arc<AtomicUsize>) -> ScheduledJob {
+        let counter = Arc::clone(counter);
+        ScheduledJob {
+            interval,
+            job: Arc::new(move || {
+                let count = counter.fetch_add(1, Ordering::SeqCst);
+                assert!(count.is_multiple_of(2), "odd tick");
+            }),
+        }
+    }
+
+    /// Tests that jobs run at each interval, survive panics and stop with the scheduler.
+    #[test]
+    fn test_scheduler() {
+        let counter = Arc::new(AtomicUsize::new(0));
+        let scheduler =
+            Scheduler::start(&[counting_job(Duration::from_millis(20), &counter)]).unwrap();
+        thread::sleep(Duration::from_millis(210));
+        drop(scheduler);
+        let ticks = counter.load(Ordering::SeqCst);
+        assert!((5..=11).contains(&ticks), "{} ticks", ticks);
+        thread::sleep(Duration::from_millis(60));
+        assert_eq!(counter.load(Ordering::SeqCst), ticks);
+    }
+
+    /// Tests that a slow run skips the runs it overlapped instead of catching up.
+    #[test]
+    fn test_slow_job_skips_ticks() {
+        let counter = Arc::new(AtomicUsize::new(0));
+        let runs = Arc::clone(&counter);
+        let job = ScheduledJob {
+            interval: Duration::from_millis(20),
+            job: Arc::new(move || {
+                runs.fetch_add(1, Ordering::SeqCst);
+                thread::sleep(Duration::from_millis(70));
+            }),
+        };
+        let scheduler = Scheduler::start(&[job]).unwrap();
+        thread::sleep(Duration::from_millis(200));
+        drop(scheduler);
+        assert!(counter.load(Ordering::SeqCst) <= 3);
+    }
+}
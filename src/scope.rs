@@ -0,0 +1,212 @@
+use crate::app::{App, EndpointBuilder, EndpointConfig, IntoHandlerResult, Request};
+use crate::middleware::{Middleware, SharedMiddleware};
+use crate::parse_headers::RequestType;
+use std::sync::Arc;
+
+/// A group of endpoints mounted below a path prefix, sharing middleware.
+///
+/// Endpoints added through a scope are registered under its prefix, and their handlers
+/// run behind the scope's middleware. Requests go through the app's middleware first,
+/// then through that of each enclosing scope, outermost first, then through the
+/// endpoint's own, with the same short-circuiting as the app's. Middleware added to a
+/// scope only wraps the endpoints added to it afterwards, and never runs for requests
+/// matching no endpoint of the scope.
+///
+/// # Examples
+///
+/// ```
+/// use rustic::app::{App, Request};
+/// use rustic::into_response::text_response;
+/// use rustic::middleware::Next;
+///
+/// let mut application = App::new();
+/// application
+///     .scope("admin")
+///     .middleware(|request: Request, next: Next| {
+///         if request.header("Authorization") == Some("Bearer letmein") {
+///             next.run(request)
+///         } else {
+///             text_response(401, "Sign in first")
+///         }
+///     })
+///     .get("users", |_| "alice, bob")
+///     .delete("users/{id}", |request| {
+///         format!("Removed {}", request.path_param("id").unwrap_or_default())
+///     });
+/// application.get("public/about", |_| "Anyone can read this");
+/// ```
+pub struct Scope<'a> {
+    app: &'a mut App,
+    prefix: String,
+    middleware: Vec<Arc<dyn Middleware>>,
+}
+
+impl App {
+    /// Starts a scope mounting endpoints below `prefix`, such as `"admin"`; see
+    /// [`Scope`].
+    pub fn scope(&mut self, prefix: &str) -> Scope<'_> {
+        Scope {
+            app: self,
+            prefix: prefix.trim_matches('/').to_string(),
+            middleware: Vec::new(),
+        }
+    }
+}
+
+impl Scope<'_> {
+    /// Adds a middleware layer wrapped around the endpoints added to the scope from now
+    /// on, including those of nested scopes.
+    ///
+    /// # Arguments
+    ///
+    /// * `middleware` - The layer to add.
+    pub fn middleware(&mut self, middleware: impl Middleware + 'static) -> &mut Self {
+        self.middleware.push(Arc::new(middleware));
+        self
+    }
+
+    /// Starts a scope nested below this one, whose endpoints also run behind this
+    /// scope's middleware.
+    pub fn scope(&mut self, prefix: &str) -> Scope<'_> {
+        Scope {
+            prefix: self.path(prefix),
+            middleware: self.middleware.clone(),
+            app: &mut *self.app,
+        }
+    }
+
+    /// Adds an endpoint below the scope's prefix; see [`App::add_endpoint`].
+    ///
+    /// An empty `path` registers the prefix itself.
+    pub fn add_endpoint<R: IntoHandlerResult>(
+        &mut self,
+        path: &str,
+        request: RequestType,
+        mapper: impl Fn(Request) -> R + Send + Sync + 'static,
+    ) -> &mut Self {
+        self.add_endpoint_with_config(path, request, mapper, EndpointConfig::new())
+    }
+
+    /// Adds an endpoint with its own settings below the scope's prefix; see
+    /// [`App::add_endpoint_with_config`].
+    pub fn add_endpoint_with_config<R: IntoHandlerResult>(
+        &mut self,
+        path: &str,
+        request: RequestType,
+        mapper: impl Fn(Request) -> R + Send + Sync + 'static,
+        config: EndpointConfig,
+    ) -> &mut Self {
+        let path = self.path(path);
+        let middleware = self.chain();
+        self.app
+            .routes
+            .insert(path, &[request], mapper, config, middleware);
+        self
+    }
+
+    /// Starts adding an endpoint below the scope's prefix with its own settings and
+    /// middleware; see [`App::endpoint`].
+    pub fn endpoint(&mut self, path: &str, request: RequestType) -> EndpointBuilder<'_> {
+        let path = self.path(path);
+        let middleware = self.chain();
+        EndpointBuilder::new(self.app, path, request, middleware)
+    }
+
+    /// Adds an endpoint serving every request type below the scope's prefix; see
+    /// [`App::any`].
+    pub fn any<R: IntoHandlerResult>(
+        &mut self,
+        path: &str,
+        mapper: impl Fn(Request) -> R + Send + Sync + 'static,
+    ) -> &mut Self {
+        let path = self.path(path);
+        let middleware = self.chain();
+        self.app
+            .routes
+            .insert_any(path, mapper, EndpointConfig::new(), middleware);
+        self
+    }
+
+    /// Adds a `GET` endpoint below the scope's prefix; see [`App::add_endpoint`].
+    pub fn get<R: IntoHandlerResult>(
+        &mut self,
+        path: &str,
+        mapper: impl Fn(Request) -> R + Send + Sync + 'static,
+    ) -> &mut Self {
+        self.add_endpoint(path, RequestType::GET, mapper)
+    }
+
+    /// Adds a `POST` endpoint below the scope's prefix; see [`App::add_endpoint`].
+    pub fn post<R: IntoHandlerResult>(
+        &mut self,
+        path: &str,
+        mapper: impl Fn(Request) -> R + Send + Sync + 'static,
+    ) -> &mut Self {
+        self.add_endpoint(path, RequestType::POST, mapper)
+    }
+
+    /// Adds a `PUT` endpoint below the scope's prefix; see [`App::add_endpoint`].
+    pub fn put<R: IntoHandlerResult>(
+        &mut self,
+        path: &str,
+        mapper: impl Fn(Request) -> R + Send + Sync + 'static,
+    ) -> &mut Self {
+        self.add_endpoint(path, RequestType::PUT, mapper)
+    }
+
+    /// Adds a `PATCH` endpoint below the scope's prefix; see [`App::add_endpoint`].
+    pub fn patch<R: IntoHandlerResult>(
+        &mut self,
+        path: &str,
+        mapper: impl Fn(Request) -> R + Send + Sync + 'static,
+    ) -> &mut Self {
+        self.add_endpoint(path, RequestType::PATCH, mapper)
+    }
+
+    /// Adds a `DELETE` endpoint below the scope's prefix; see [`App::add_endpoint`].
+    pub fn delete<R: IntoHandlerResult>(
+        &mut self,
+        path: &str,
+        mapper: impl Fn(Request) -> R + Send + Sync + 'static,
+    ) -> &mut Self {
+        self.add_endpoint(path, RequestType::DELETE, mapper)
+    }
+
+    /// Joins a path below the scope's prefix.
+    fn path(&self, path: &str) -> String {
+        let path = path.trim_start_matches('/');
+        match (self.prefix.is_empty(), path.is_empty()) {
+            (true, _) => path.to_string(),
+            (false, true) => self.prefix.clone(),
+            (false, false) => format!("{}/{}", self.prefix, path),
+        }
+    }
+
+    /// Builds the middleware chain of an endpoint added to the scope now.
+    fn chain(&self) -> Vec<Box<dyn Middleware>> {
+        self.middleware
+            .iter()
+            .map(|middleware| {
+                Box::new(SharedMiddleware(Arc::clone(middleware))) as Box<dyn Middleware>
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod test_scope {
+    use super::*;
+
+    /// Tests that scoped paths are joined below the prefix without doubled slashes.
+    #[test]
+    fn test_path() {
+        let mut application = App::new();
+        let mut admin = application.scope("/admin/");
+        assert_eq!(admin.path("users"), "admin/users");
+        assert_eq!(admin.path("/users"), "admin/users");
+        assert_eq!(admin.path(""), "admin");
+        let reports = admin.scope("reports");
+        assert_eq!(reports.path("daily"), "admin/reports/daily");
+        assert_eq!(application.scope("").path("users"), "users");
+    }
+}
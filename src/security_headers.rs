@@ -0,0 +1,148 @@
+use crate::app::Request;
+use crate::http11_response::Response;
+use crate::middleware::{Middleware, Next};
+
+/// Middleware that adds standard hardening headers to every response.
+///
+/// By default it sets:
+///
+/// * `X-Content-Type-Options: nosniff`
+/// * `X-Frame-Options: DENY`
+/// * `Referrer-Policy: strict-origin-when-cross-origin`
+///
+/// `Strict-Transport-Security` and `Content-Security-Policy` are off until configured,
+/// since HSTS only makes sense when the site is served over TLS and a useful CSP depends
+/// on the application. Every header can be overridden or disabled (by passing `None`)
+/// through the builder methods. Headers already set by a handler are never replaced.
+///
+/// # Examples
+///
+/// ```
+/// use rustic::app::App;
+/// use rustic::security_headers::SecurityHeaders;
+///
+/// let mut application = App::new();
+/// application.add_middleware(
+///     SecurityHeaders::new()
+///         .frame_options(None)
+///         .csp("default-src 'self'"),
+/// );
+/// ```
+#[derive(Debug, Clone)]
+pub struct SecurityHeaders {
+    content_type_options: Option<String>,
+    frame_options: Option<String>,
+    referrer_policy: Option<String>,
+    strict_transport_security: Option<String>,
+    csp: Option<String>,
+}
+
+impl Default for SecurityHeaders {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl SecurityHeaders {
+    /// Creates the middleware with the default header set.
+    pub fn new() -> Self {
+        SecurityHeaders {
+            content_type_options: Some("nosniff".to_string()),
+            frame_options: Some("DENY".to_string()),
+            referrer_policy: Some("strict-origin-when-cross-origin".to_string()),
+            strict_transport_security: None,
+            csp: None,
+        }
+    }
+
+    /// Sets or disables the `X-Content-Type-Options` header.
+    pub fn content_type_options<'a>(mut self, value: impl Into<Option<&'a str>>) -> Self {
+        self.content_type_options = value.into().map(str::to_string);
+        self
+    }
+
+    /// Sets or disables the `X-Frame-Options` header.
+    pub fn frame_options<'a>(mut self, value: impl Into<Option<&'a str>>) -> Self {
+        self.frame_options = value.into().map(str::to_string);
+        self
+    }
+
+    /// Sets or disables the `Referrer-Policy` header.
+    pub fn referrer_policy<'a>(mut self, value: impl Into<Option<&'a str>>) -> Self {
+        self.referrer_policy = value.into().map(str::to_string);
+        self
+    }
+
+    /// Sets or disables the `Strict-Transport-Security` header.
+    ///
+    /// Only enable this when the application is reachable exclusively over TLS, for
+    /// example behind a TLS-terminating proxy: `"max-age=63072000; includeSubDomains"`.
+    pub fn strict_transport_security<'a>(mut self, value: impl Into<Option<&'a str>>) -> Self {
+        self.strict_transport_security = value.into().map(str::to_string);
+        self
+    }
+
+    /// Sets or disables the `Content-Security-Policy` header.
+    pub fn csp<'a>(mut self, value: impl Into<Option<&'a str>>) -> Self {
+        self.csp = value.into().map(str::to_string);
+        self
+    }
+
+    /// Iterates over the enabled headers as `(name, value)` pairs.
+    fn enabled(&self) -> impl Iterator<Item = (&'static str, &str)> {
+        [
+            ("X-Content-Type-Options", &self.content_type_options),
+            ("X-Frame-Options", &self.frame_options),
+            ("Referrer-Policy", &self.referrer_policy),
+            ("Strict-Transport-Security", &self.strict_transport_security),
+            ("Content-Security-Policy", &self.csp),
+        ]
+        .into_iter()
+        .filter_map(|(name, value)| value.as_deref().map(|value| (name, value)))
+    }
+}
+
+impl Middleware for SecurityHeaders {
+    fn handle(&self, request: Request, next: Next) -> Response {
+        let mut response = next.run(request);
+        for (name, value) in self.enabled() {
+            if !response.headers.contains_key(name) {
+                response.headers.insert(name, value);
+            }
+        }
+        response
+    }
+}
+
+#[cfg(test)]
+mod test_security_headers {
+    use super::*;
+
+    /// Tests that the default configuration enables only the always-safe headers.
+    #[test]
+    fn test_default_headers() {
+        let names: Vec<&str> = SecurityHeaders::new()
+            .enabled()
+            .map(|(name, _)| name)
+            .collect();
+        assert_eq!(
+            names,
+            vec![
+                "X-Content-Type-Options",
+                "X-Frame-Options",
+                "Referrer-Policy"
+            ]
+        );
+    }
+
+    /// Tests overriding and disabling headers through the builder.
+    #[test]
+    fn test_builder_overrides() {
+        let headers = SecurityHeaders::new()
+            .frame_options(None)
+            .csp("default-src 'self'");
+        let enabled: Vec<(&str, &str)> = headers.enabled().collect();
+        assert!(!enabled.iter().any(|(name, _)| *name == "X-Frame-Options"));
+        assert!(enabled.contains(&("Content-Security-Policy", "default-src 'self'")));
+    }
+}
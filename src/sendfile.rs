@@ -0,0 +1,167 @@
+use crate::traffic::CountingWriter;
+use std::fs::File;
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::net::TcpStream;
+
+/// The most `sendfile(2)` transfers in one call on Linux.
+#[cfg(target_os = "linux")]
+const MAX_SENDFILE_CHUNK: u64 = 0x7fff_f000;
+
+/// Sends `len` bytes of a file from `offset` onto a connection, adding the bytes sent to
+/// `sent` as they go, so that it also counts those sent before a failure.
+///
+/// On Linux, the kernel copies the bytes from the page cache to the socket with
+/// `sendfile(2)`, without them passing through userspace. Elsewhere, or when the file
+/// cannot be sent that way, they are copied with [`copy_file`].
+///
+/// # Errors
+///
+/// Fails with `ErrorKind::UnexpectedEof` when the file ends before `len` bytes, which
+/// leaves the response short of its `Content-Length`, and with the error of the read or
+/// write otherwise.
+#[cfg(target_os = "linux")]
+pub(crate) fn send_file(
+    stream: &TcpStream,
+    file: &File,
+    offset: u64,
+    len: u64,
+    sent: &mut usize,
+) -> io::Result<()> {
+    use std::os::unix::io::AsRawFd;
+
+    let mut position = libc::off_t::try_from(offset)
+        .map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "file offset out of range"))?;
+    let mut remaining = len;
+    while remaining > 0 {
+        let count = remaining.min(MAX_SENDFILE_CHUNK) as usize;
+        // SAFETY: both descriptors stay open for the call, as `stream` and `file` are
+        // borrowed, and `position` is a valid `off_t` the kernel advances.
+        let result =
+            unsafe { libc::sendfile(stream.as_raw_fd(), file.as_raw_fd(), &mut position, count) };
+        match result {
+            -1 => {
+                let err = io::Error::last_os_error();
+                match err.raw_os_error() {
+                    Some(libc::EINTR) => continue,
+                    // The file or socket does not support it, so nothing was sent yet.
+                    Some(libc::EINVAL | libc::ENOSYS | libc::EOPNOTSUPP) => {
+                        let mut writer = CountingWriter::new(stream);
+                        let copied = copy_file(&mut writer, file, position as u64, remaining);
+                        *sent += writer.count();
+                        return copied;
+                    }
+                    _ => return Err(err),
+                }
+            }
+            0 => return Err(file_ended()),
+            count => {
+                remaining -= count as u64;
+                *sent += count as usize;
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Sends `len` bytes of a file from `offset` onto a connection with [`copy_file`].
+#[cfg(not(target_os = "linux"))]
+pub(crate) fn send_file(
+    stream: &TcpStream,
+    file: &File,
+    offset: u64,
+    len: u64,
+    sent: &mut usize,
+) -> io::Result<()> {
+    let mut writer = CountingWriter::new(stream);
+    let copied = copy_file(&mut writer, file, offset, len);
+    *sent += writer.count();
+    copied
+}
+
+/// Copies `len` bytes of a file from `offset` to a writer through a userspace buffer.
+///
+/// The file's position is moved, so it does not matter where it was left.
+///
+/// # Errors
+///
+/// Fails with `ErrorKind::UnexpectedEof` when the file ends before `len` bytes, and with
+/// the error of the read or write otherwise.
+pub(crate) fn copy_file<W: Write + ?Sized>(
+    writer: &mut W,
+    file: &File,
+    offset: u64,
+    len: u64,
+) -> io::Result<()> {
+    let mut file = file;
+    file.seek(SeekFrom::Start(offset))?;
+    if io::copy(&mut file.take(len), writer)? < len {
+        return Err(file_ended());
+    }
+    Ok(())
+}
+
+/// The error of a file that ended before the region being sent.
+pub(crate) fn file_ended() -> io::Error {
+    io::Error::new(
+        io::ErrorKind::UnexpectedEof,
+        "file ended before the body was sent",
+    )
+}
+
+#[cfg(test)]
+mod test_sendfile {
+    use super::*;
+    use std::net::TcpListener;
+    use std::thread;
+
+    /// Creates a file under the system temporary directory holding `contents`.
+    fn temp_file(name: &str, contents: &[u8]) -> File {
+        let path = std::env::temp_dir().join(format!("rustic-{}-{}", name, std::process::id()));
+        std::fs::write(&path, contents).unwrap();
+        let file = File::open(&path).unwrap();
+        std::fs::remove_file(path).unwrap();
+        file
+    }
+
+    /// Tests that the portable copy sends exactly the region asked for and reports a
+    /// file that is too short.
+    #[test]
+    fn test_copy_file() {
+        let file = temp_file("copy", b"0123456789");
+        let mut written = Vec::new();
+        copy_file(&mut written, &file, 2, 5).unwrap();
+        copy_file(&mut written, &file, 0, 1).unwrap();
+        assert_eq!(written, b"234560");
+
+        let err = copy_file(&mut Vec::new(), &file, 8, 5).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::UnexpectedEof);
+    }
+
+    /// Tests that a region sent onto a connection arrives intact.
+    #[test]
+    fn test_send_file() {
+        let contents: Vec<u8> = (0..300_000u32).map(|index| (index % 251) as u8).collect();
+        let file = temp_file("send", &contents);
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        let reader = thread::spawn(move || {
+            let mut received = Vec::new();
+            let (mut stream, _) = listener.accept().unwrap();
+            stream.read_to_end(&mut received).unwrap();
+            received
+        });
+
+        let stream = TcpStream::connect(address).unwrap();
+        let mut sent = 0;
+        send_file(&stream, &file, 1000, 250_000, &mut sent).unwrap();
+        assert_eq!(sent, 250_000);
+        let err = send_file(&stream, &file, 299_990, 20, &mut sent).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::UnexpectedEof);
+        assert_eq!(sent, 250_010);
+        drop(stream);
+        let received = reader.join().unwrap();
+        assert_eq!(received.len(), 250_010);
+        assert_eq!(received[..250_000], contents[1000..251_000]);
+        assert_eq!(received[250_000..], contents[299_990..]);
+    }
+}
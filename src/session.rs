@@ -0,0 +1,291 @@
+use crate::app::Request;
+use crate::cookie::{Cookie, SameSite};
+use crate::http11_response::Response;
+use crate::middleware::{Middleware, Next};
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+/// A handle to the server-side session of the current request.
+///
+/// The session middleware stores a `Session` in the request extensions, where handlers
+/// find it through [`Request::session`]. Changes made through the handle are persisted
+/// once the handler has returned.
+#[derive(Clone)]
+pub struct Session {
+    inner: Arc<Mutex<SessionState>>,
+}
+
+struct SessionState {
+    id: String,
+    data: HashMap<String, String>,
+    is_new: bool,
+    changed: bool,
+}
+
+impl Session {
+    fn new(id: String, data: HashMap<String, String>, is_new: bool) -> Self {
+        Session {
+            inner: Arc::new(Mutex::new(SessionState {
+                id,
+                data,
+                is_new,
+                changed: false,
+            })),
+        }
+    }
+
+    /// Returns the session ID sent to the client in the session cookie.
+    pub fn id(&self) -> String {
+        self.inner.lock().unwrap().id.clone()
+    }
+
+    /// Returns the value stored under `key`, if any.
+    pub fn get(&self, key: &str) -> Option<String> {
+        self.inner.lock().unwrap().data.get(key).cloned()
+    }
+
+    /// Stores `value` under `key`, replacing any previous value.
+    pub fn insert(&self, key: &str, value: &str) {
+        let mut state = self.inner.lock().unwrap();
+        state.data.insert(key.to_string(), value.to_string());
+        state.changed = true;
+    }
+
+    /// Removes and returns the value stored under `key`, if any.
+    pub fn remove(&self, key: &str) -> Option<String> {
+        let mut state = self.inner.lock().unwrap();
+        let removed = state.data.remove(key);
+        state.changed |= removed.is_some();
+        removed
+    }
+
+    /// Removes every value from the session, for example on logout.
+    pub fn clear(&self) {
+        let mut state = self.inner.lock().unwrap();
+        state.changed |= !state.data.is_empty();
+        state.data.clear();
+    }
+}
+
+impl Request {
+    /// Returns the session attached by [`SessionMiddleware`], if it is installed.
+    pub fn session(&self) -> Option<&Session> {
+        self.extensions.get::<Session>()
+    }
+}
+
+struct StoredSession {
+    data: HashMap<String, String>,
+    last_access: Instant,
+}
+
+/// The concurrent in-memory table of live sessions.
+struct SessionStore {
+    sessions: Mutex<HashMap<String, StoredSession>>,
+    last_sweep: Mutex<Instant>,
+}
+
+impl SessionStore {
+    fn new() -> Self {
+        SessionStore {
+            sessions: Mutex::new(HashMap::new()),
+            last_sweep: Mutex::new(Instant::now()),
+        }
+    }
+
+    /// Loads a live session and marks it as accessed, dropping it if it has expired.
+    fn load(&self, id: &str, ttl: Duration) -> Option<Session> {
+        let mut sessions = self.sessions.lock().unwrap();
+        let stored = sessions.get_mut(id)?;
+        if stored.last_access.elapsed() > ttl {
+            sessions.remove(id);
+            return None;
+        }
+        stored.last_access = Instant::now();
+        Some(Session::new(id.to_string(), stored.data.clone(), false))
+    }
+
+    fn save(&self, id: &str, data: HashMap<String, String>) {
+        self.sessions.lock().unwrap().insert(
+            id.to_string(),
+            StoredSession {
+                data,
+                last_access: Instant::now(),
+            },
+        );
+    }
+
+    /// Drops every expired session if at least `interval` has passed since the last sweep.
+    fn sweep_if_due(&self, ttl: Duration, interval: Duration) {
+        let mut last_sweep = self.last_sweep.lock().unwrap();
+        if last_sweep.elapsed() < interval {
+            return;
+        }
+        *last_sweep = Instant::now();
+        self.sessions
+            .lock()
+            .unwrap()
+            .retain(|_, stored| stored.last_access.elapsed() <= ttl);
+    }
+}
+
+/// Generates a new session ID from 32 bytes of operating-system randomness.
+fn generate_session_id() -> String {
+    let mut bytes = [0u8; 32];
+    getrandom::fill(&mut bytes).expect("Failed to gather randomness for session ID");
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+/// Middleware providing server-side sessions identified by a cookie.
+///
+/// For every request the middleware looks up the session named by the session cookie,
+/// or starts a fresh one, and exposes it through [`Request::session`]. After the handler
+/// returns, changes are persisted. A cookie is only issued once a new session actually
+/// holds data, so anonymous visitors do not fill the store.
+///
+/// Sessions expire after being idle for the configured TTL. Expired sessions are
+/// rejected on lookup and swept from the store periodically, piggybacking on incoming
+/// requests at most once per cleanup interval.
+///
+/// # Examples
+///
+/// ```
+/// use rustic::app::App;
+/// use rustic::cookie::SameSite;
+/// use rustic::session::SessionMiddleware;
+/// use std::time::Duration;
+///
+/// let mut application = App::new();
+/// application.add_middleware(
+///     SessionMiddleware::new()
+///         .ttl(Duration::from_secs(15 * 60))
+///         .same_site(SameSite::Strict),
+/// );
+/// ```
+pub struct SessionMiddleware {
+    store: SessionStore,
+    cookie_name: String,
+    ttl: Duration,
+    cleanup_interval: Duration,
+    same_site: SameSite,
+    secure: bool,
+}
+
+impl Default for SessionMiddleware {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl SessionMiddleware {
+    /// Creates the middleware with a 30 minute idle TTL, a cleanup sweep every minute and
+    /// a `SameSite=Lax` cookie named `session_id`.
+    pub fn new() -> Self {
+        SessionMiddleware {
+            store: SessionStore::new(),
+            cookie_name: "session_id".to_string(),
+            ttl: Duration::from_secs(30 * 60),
+            cleanup_interval: Duration::from_secs(60),
+            same_site: SameSite::Lax,
+            secure: false,
+        }
+    }
+
+    /// Sets the name of the session cookie.
+    pub fn cookie_name(mut self, name: &str) -> Self {
+        self.cookie_name = name.to_string();
+        self
+    }
+
+    /// Sets how long a session may stay idle before it expires.
+    pub fn ttl(mut self, ttl: Duration) -> Self {
+        self.ttl = ttl;
+        self
+    }
+
+    /// Sets the minimum time between sweeps of expired sessions.
+    pub fn cleanup_interval(mut self, interval: Duration) -> Self {
+        self.cleanup_interval = interval;
+        self
+    }
+
+    /// Sets the `SameSite` attribute of the session cookie.
+    pub fn same_site(mut self, same_site: SameSite) -> Self {
+        self.same_site = same_site;
+        self
+    }
+
+    /// Sets whether the session cookie is only sent over HTTPS.
+    pub fn secure(mut self, secure: bool) -> Self {
+        self.secure = secure;
+        self
+    }
+}
+
+impl Middleware for SessionMiddleware {
+    fn handle(&self, mut request: Request, next: Next) -> Response {
+        self.store.sweep_if_due(self.ttl, self.cleanup_interval);
+
+        let session = request
+            .cookie(&self.cookie_name)
+            .and_then(|id| self.store.load(&id, self.ttl))
+            .unwrap_or_else(|| Session::new(generate_session_id(), HashMap::new(), true));
+        request.extensions.insert(session.clone());
+
+        let mut response = next.run(request);
+
+        let state = session.inner.lock().unwrap();
+        if state.is_new && !state.data.is_empty() {
+            self.store.save(&state.id, state.data.clone());
+            let cookie = Cookie::new(&self.cookie_name, &state.id)
+                .http_only(true)
+                .secure(self.secure)
+                .same_site(self.same_site);
+            response.set_cookie(&cookie);
+        } else if !state.is_new && state.changed {
+            self.store.save(&state.id, state.data.clone());
+        }
+        response
+    }
+}
+
+#[cfg(test)]
+mod test_session {
+    use super::*;
+    use std::thread;
+
+    /// Tests that a session idle for longer than the TTL can no longer be loaded.
+    #[test]
+    fn test_session_expiry() {
+        let store = SessionStore::new();
+        store.save("id", HashMap::new());
+        assert!(store.load("id", Duration::from_secs(60)).is_some());
+
+        thread::sleep(Duration::from_millis(20));
+        assert!(store.load("id", Duration::from_millis(10)).is_none());
+        assert!(store.sessions.lock().unwrap().is_empty());
+    }
+
+    /// Tests that the periodic sweep drops expired sessions.
+    #[test]
+    fn test_session_sweep() {
+        let store = SessionStore::new();
+        store.save("old", HashMap::new());
+        thread::sleep(Duration::from_millis(20));
+        store.save("fresh", HashMap::new());
+
+        store.sweep_if_due(Duration::from_millis(10), Duration::ZERO);
+        assert_eq!(store.sessions.lock().unwrap().len(), 1);
+        assert!(store.load("fresh", Duration::from_secs(60)).is_some());
+    }
+
+    /// Tests that generated IDs are long, hex-encoded and distinct.
+    #[test]
+    fn test_generate_session_id() {
+        let first = generate_session_id();
+        assert_eq!(first.len(), 64);
+        assert!(first.chars().all(|c| c.is_ascii_hexdigit()));
+        assert_ne!(first, generate_session_id());
+    }
+}
@@ -0,0 +1,373 @@
+use std::collections::HashMap;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, TcpStream};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Condvar, Mutex};
+use std::time::{Duration, Instant};
+
+/// How a server stopped after its shutdown was triggered.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ShutdownOutcome {
+    /// Every in-flight request finished within the drain timeout.
+    Drained,
+    /// The drain timeout passed while requests were still being handled.
+    TimedOut,
+    /// [`Shutdown::force`] was called, so in-flight requests were abandoned.
+    Forced,
+}
+
+struct ShutdownState {
+    triggered: AtomicBool,
+    forced: AtomicBool,
+    drain_timeout: Duration,
+    in_flight: Mutex<usize>,
+    idle: Condvar,
+    listeners: Mutex<Vec<SocketAddr>>,
+    connections: Mutex<Connections>,
+}
+
+/// The open connections of a server, to close when it shuts down.
+#[derive(Default)]
+struct Connections {
+    next_id: u64,
+    open: HashMap<u64, OpenConnection>,
+}
+
+struct OpenConnection {
+    stream: TcpStream,
+    /// Whether the connection is waiting for its next keep-alive request.
+    idle: bool,
+}
+
+/// A handle for stopping a server started with [`run_with_listener`](crate::app::run_with_listener).
+///
+/// Clones share the same state, so one clone can be handed to the server while another
+/// triggers the shutdown from a different thread. Once triggered, the server stops
+/// accepting connections, closes the keep-alive connections waiting for their next
+/// request and waits up to the drain timeout for in-flight requests, which
+/// [`Shutdown::in_flight`] counts. Connections still open once the timeout passes, or
+/// once the shutdown is [forced](Shutdown::force), are closed.
+///
+/// # Examples
+///
+/// ```no_run
+/// use rustic::app::{run_with_listener, App, ServerConfig};
+/// use rustic::shutdown::Shutdown;
+/// use std::net::TcpListener;
+/// use std::thread;
+/// use std::time::Duration;
+/// let shutdown = Shutdown::new(Duration::from_secs(10));
+/// let trigger = shutdown.clone();
+/// thread::spawn(move || {
+///     thread::sleep(Duration::from_secs(60));
+///     trigger.trigger();
+/// });
+/// let listener = TcpListener::bind("127.0.0.1:8080").unwrap();
+/// let config = ServerConfig::new().shutdown(shutdown);
+/// match run_with_listener(App::new(), listener, config) {
+///     Ok(outcome) => println!("Stopped: {:?}", outcome),
+///     Err(err) => eprintln!("The server failed: {}", err),
+/// }
+/// ```
+#[derive(Clone)]
+pub struct Shutdown {
+    state: Arc<ShutdownState>,
+}
+
+impl Shutdown {
+    /// Creates a handle that waits up to `drain_timeout` for in-flight requests.
+    pub fn new(drain_timeout: Duration) -> Self {
+        Shutdown {
+            state: Arc::new(ShutdownState {
+                triggered: AtomicBool::new(false),
+                forced: AtomicBool::new(false),
+                drain_timeout,
+                in_flight: Mutex::new(0),
+                idle: Condvar::new(),
+                listeners: Mutex::new(Vec::new()),
+                connections: Mutex::new(Connections::default()),
+            }),
+        }
+    }
+
+    /// Stops accepting connections and starts draining in-flight requests.
+    pub fn trigger(&self) {
+        if !self.state.triggered.swap(true, Ordering::SeqCst) {
+            self.wake_listeners();
+            self.close_connections(false);
+        }
+    }
+
+    /// Stops the server immediately, without waiting for in-flight requests.
+    pub fn force(&self) {
+        self.state.forced.store(true, Ordering::SeqCst);
+        self.trigger();
+        let _in_flight = self.state.in_flight.lock().unwrap();
+        self.state.idle.notify_all();
+    }
+
+    /// Returns whether the shutdown has been triggered.
+    pub fn is_triggered(&self) -> bool {
+        self.state.triggered.load(Ordering::SeqCst)
+    }
+
+    /// Returns how many requests are being handled, from when their first byte is read
+    /// until their response is written or their connection fails.
+    pub fn in_flight(&self) -> usize {
+        *self.state.in_flight.lock().unwrap()
+    }
+
+    /// Triggers the shutdown on the first `SIGINT` or `SIGTERM` and forces it on the second.
+    ///
+    /// # Returns
+    ///
+    /// * `Result<(), Error>` - [`Error::Shutdown`](crate::Error::Shutdown) if the signal
+    ///   handlers could not be installed.
+    #[cfg(all(unix, feature = "signals"))]
+    pub fn install_signal_handlers(&self) -> Result<(), crate::Error> {
+        use signal_hook::consts::{SIGINT, SIGTERM};
+        use signal_hook::iterator::Signals;
+
+        let mut signals = Signals::new([SIGINT, SIGTERM]).map_err(crate::Error::Shutdown)?;
+        let shutdown = self.clone();
+        std::thread::spawn(move || {
+            let mut received = signals.forever();
+            if received.next().is_some() {
+                shutdown.trigger();
+            }
+            if received.next().is_some() {
+                shutdown.force();
+            }
+        });
+        Ok(())
+    }
+
+    /// Returns how long the server waits for in-flight work once triggered.
+    pub(crate) fn drain_timeout(&self) -> Duration {
+        self.state.drain_timeout
+    }
+
+    /// Registers the address of a listener to wake up when the shutdown is triggered.
+    pub(crate) fn register_listener(&self, address: SocketAddr) {
+        self.state.listeners.lock().unwrap().push(address);
+        if self.is_triggered() {
+            self.wake_listeners();
+        }
+    }
+
+    /// Unblocks every registered listener stuck in `accept` by connecting to it.
+    fn wake_listeners(&self) {
+        for address in self.state.listeners.lock().unwrap().iter() {
+            let mut address = *address;
+            if address.ip().is_unspecified() {
+                address.set_ip(match address.ip() {
+                    IpAddr::V4(_) => IpAddr::V4(Ipv4Addr::LOCALHOST),
+                    IpAddr::V6(_) => IpAddr::V6(Ipv6Addr::LOCALHOST),
+                });
+            }
+            let _ = TcpStream::connect_timeout(&address, Duration::from_secs(1));
+        }
+    }
+
+    /// Marks a request as in flight until the returned guard is dropped.
+    pub(crate) fn track_request(&self) -> InFlightGuard {
+        *self.state.in_flight.lock().unwrap() += 1;
+        InFlightGuard {
+            state: Arc::clone(&self.state),
+        }
+    }
+
+    /// Counts a connection as open until the returned guard is dropped, so that it is
+    /// closed when the server shuts down.
+    pub(crate) fn track_connection(&self, stream: &TcpStream) -> ConnectionGuard {
+        let mut connections = self.state.connections.lock().unwrap();
+        let id = connections.next_id;
+        connections.next_id += 1;
+        if let Ok(stream) = stream.try_clone() {
+            let connection = OpenConnection {
+                stream,
+                idle: false,
+            };
+            connections.open.insert(id, connection);
+        }
+        ConnectionGuard {
+            state: Arc::clone(&self.state),
+            id,
+        }
+    }
+
+    /// Shuts down the open connections, or only the idle ones, which unblocks the
+    /// threads reading from them.
+    fn close_connections(&self, busy_too: bool) {
+        let connections = self.state.connections.lock().unwrap();
+        for connection in connections.open.values() {
+            if busy_too || connection.idle {
+                let _ = connection.stream.shutdown(std::net::Shutdown::Both);
+            }
+        }
+    }
+
+    /// Waits for in-flight requests to finish, up to the drain timeout, then closes the
+    /// connections left open.
+    pub(crate) fn drain(&self) -> ShutdownOutcome {
+        let outcome = self.wait_for_requests();
+        if outcome != ShutdownOutcome::Drained {
+            self.close_connections(true);
+        }
+        outcome
+    }
+
+    /// Waits for in-flight requests to finish, up to the drain timeout.
+    fn wait_for_requests(&self) -> ShutdownOutcome {
+        let deadline = Instant::now() + self.state.drain_timeout;
+        let mut in_flight = self.state.in_flight.lock().unwrap();
+        loop {
+            if self.state.forced.load(Ordering::SeqCst) {
+                return ShutdownOutcome::Forced;
+            }
+            if *in_flight == 0 {
+                return ShutdownOutcome::Drained;
+            }
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            if remaining.is_zero() {
+                return ShutdownOutcome::TimedOut;
+            }
+            in_flight = self
+                .state
+                .idle
+                .wait_timeout(in_flight, remaining)
+                .unwrap()
+                .0;
+        }
+    }
+}
+
+/// Keeps a connection counted as open while it is alive.
+pub(crate) struct ConnectionGuard {
+    state: Arc<ShutdownState>,
+    id: u64,
+}
+
+impl ConnectionGuard {
+    /// Marks the connection as waiting for its next keep-alive request, so that a
+    /// shutdown closes it at once.
+    ///
+    /// # Returns
+    ///
+    /// * `bool` - `false` if the shutdown was already triggered, in which case the
+    ///   connection is to be closed instead of waited on.
+    pub(crate) fn idle(&self) -> bool {
+        // Checked under the lock `trigger` closes idle connections with, so that a
+        // connection is either closed by it or sees the shutdown here.
+        let mut connections = self.state.connections.lock().unwrap();
+        if self.state.triggered.load(Ordering::SeqCst) {
+            return false;
+        }
+        if let Some(connection) = connections.open.get_mut(&self.id) {
+            connection.idle = true;
+        }
+        true
+    }
+
+    /// Marks the connection as serving a request again.
+    pub(crate) fn busy(&self) {
+        let mut connections = self.state.connections.lock().unwrap();
+        if let Some(connection) = connections.open.get_mut(&self.id) {
+            connection.idle = false;
+        }
+    }
+}
+
+impl Drop for ConnectionGuard {
+    fn drop(&mut self) {
+        self.state.connections.lock().unwrap().open.remove(&self.id);
+    }
+}
+
+/// Keeps a request counted as in flight while it is alive.
+pub(crate) struct InFlightGuard {
+    state: Arc<ShutdownState>,
+}
+
+impl Drop for InFlightGuard {
+    fn drop(&mut self) {
+        let mut in_flight = self.state.in_flight.lock().unwrap();
+        *in_flight -= 1;
+        if *in_flight == 0 {
+            self.state.idle.notify_all();
+        }
+    }
+}
+
+#[cfg(test)]
+mod test_shutdown {
+    use super::*;
+    use std::thread;
+
+    /// Tests that draining waits for in-flight requests to finish.
+    #[test]
+    fn test_drain_waits_for_requests() {
+        let shutdown = Shutdown::new(Duration::from_secs(5));
+        let guard = shutdown.track_request();
+        thread::spawn(move || {
+            thread::sleep(Duration::from_millis(50));
+            drop(guard);
+        });
+        assert_eq!(shutdown.drain(), ShutdownOutcome::Drained);
+    }
+
+    /// Tests that draining gives up after the drain timeout.
+    #[test]
+    fn test_drain_timeout() {
+        let shutdown = Shutdown::new(Duration::from_millis(20));
+        let _guard = shutdown.track_request();
+        assert_eq!(shutdown.drain(), ShutdownOutcome::TimedOut);
+    }
+
+    /// Tests that forcing the shutdown interrupts draining.
+    #[test]
+    fn test_force() {
+        let shutdown = Shutdown::new(Duration::from_secs(5));
+        let _guard = shutdown.track_request();
+        let forcer = shutdown.clone();
+        thread::spawn(move || {
+            thread::sleep(Duration::from_millis(20));
+            forcer.force();
+        });
+        assert_eq!(shutdown.drain(), ShutdownOutcome::Forced);
+        assert!(shutdown.is_triggered());
+    }
+
+    /// Connects a client to a server socket, returning both ends.
+    fn socket_pair() -> (TcpStream, TcpStream) {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let client = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        (client, listener.accept().unwrap().0)
+    }
+
+    /// Tests that triggering closes idle connections only, and that a drain running
+    /// out of time closes the rest.
+    #[test]
+    fn test_close_connections() {
+        use std::io::Read;
+
+        let shutdown = Shutdown::new(Duration::from_millis(20));
+        let (mut idle_client, idle_server) = socket_pair();
+        let (mut busy_client, busy_server) = socket_pair();
+        let idle = shutdown.track_connection(&idle_server);
+        let _busy = shutdown.track_connection(&busy_server);
+        assert!(idle.idle());
+        let _guard = shutdown.track_request();
+        assert_eq!(shutdown.in_flight(), 1);
+
+        shutdown.trigger();
+        assert_eq!(idle_client.read(&mut [0; 1]).unwrap(), 0);
+        busy_client
+            .set_read_timeout(Some(Duration::from_millis(20)))
+            .unwrap();
+        assert!(busy_client.read(&mut [0; 1]).is_err());
+        assert!(!idle.idle());
+
+        assert_eq!(shutdown.drain(), ShutdownOutcome::TimedOut);
+        assert_eq!(busy_client.read(&mut [0; 1]).unwrap(), 0);
+    }
+}
@@ -0,0 +1,221 @@
+use crate::app::App;
+use crate::content_type::ContentType;
+use crate::into_response::body_response;
+use crate::parse_headers::RequestType;
+use std::fmt;
+
+/// How long browsers keep the favicon before asking for it again: 30 days.
+const FAVICON_MAX_AGE: u32 = 30 * 24 * 60 * 60;
+
+/// How long crawlers and caches keep `robots.txt`: one day.
+const ROBOTS_MAX_AGE: u32 = 24 * 60 * 60;
+
+impl App {
+    /// Serves `bytes` as the site's icon at `/favicon.ico`, which browsers request for
+    /// every site they show.
+    ///
+    /// The icon is sent as `image/x-icon` with a `Cache-Control` header letting browsers
+    /// and shared caches keep it for 30 days. An endpoint added for `favicon.ico` with
+    /// [`App::get`] takes precedence, whether it was added before or after. Without this
+    /// call, or such an endpoint, the request is answered with `404 Not Found`.
+    ///
+    /// # Arguments
+    ///
+    /// * `bytes` - The icon, usually embedded with `include_bytes!`.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use rustic::app::App;
+    ///
+    /// let mut application = App::new();
+    /// application.favicon(&[0, 0, 1, 0]);
+    /// ```
+    pub fn favicon(&mut self, bytes: &'static [u8]) {
+        self.routes
+            .insert_fallback("favicon.ico".to_string(), RequestType::GET, move |_| {
+                let mut response = body_response(200, "image/x-icon", bytes);
+                let cache_control = format!("public, max-age={}", FAVICON_MAX_AGE);
+                response.headers.insert("Cache-Control", cache_control);
+                response
+            });
+    }
+
+    /// Serves `rules` at `/robots.txt`, which crawlers read to learn what they may
+    /// fetch.
+    ///
+    /// The rules are sent as `text/plain` with a `Cache-Control` header letting them be
+    /// kept for a day. They can be written out or built with [`RobotsTxt`]. An endpoint
+    /// added for `robots.txt` with [`App::get`] takes precedence, whether it was added
+    /// before or after. Without this call, or such an endpoint, the request is answered
+    /// with `404 Not Found`, which crawlers read as permission to fetch everything.
+    ///
+    /// # Arguments
+    ///
+    /// * `rules` - The content of the file.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use rustic::app::App;
+    /// use rustic::site_files::RobotsTxt;
+    ///
+    /// let mut application = App::new();
+    /// application.robots_txt(RobotsTxt::new().disallow("/admin/"));
+    /// application.robots_txt("User-agent: *\nDisallow: /admin/\n");
+    /// ```
+    pub fn robots_txt(&mut self, rules: impl Into<String>) {
+        let rules = rules.into();
+        self.routes
+            .insert_fallback("robots.txt".to_string(), RequestType::GET, move |_| {
+                let mut response = body_response(200, ContentType::TEXT_PLAIN_UTF8, rules.clone());
+                let cache_control = format!("public, max-age={}", ROBOTS_MAX_AGE);
+                response.headers.insert("Cache-Control", cache_control);
+                response
+            });
+    }
+}
+
+/// A builder for the content of `robots.txt`, served with [`App::robots_txt`].
+///
+/// Rules are grouped under the `User-agent` lines before them. Rules added before any
+/// user agent apply to every crawler, as if under `User-agent: *`. `Sitemap` lines are
+/// written after every group.
+///
+/// # Examples
+///
+/// ```
+/// use rustic::site_files::RobotsTxt;
+///
+/// let robots = RobotsTxt::new()
+///     .disallow("/admin/")
+///     .user_agent("BadBot")
+///     .disallow("/")
+///     .sitemap("https://example.com/sitemap.xml");
+/// assert_eq!(
+///     robots.to_string(),
+///     "User-agent: *\nDisallow: /admin/\n\nUser-agent: BadBot\nDisallow: /\n\n\
+///      Sitemap: https://example.com/sitemap.xml\n"
+/// );
+/// ```
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct RobotsTxt {
+    groups: Vec<Group>,
+    sitemaps: Vec<String>,
+}
+
+/// The user agents sharing a set of rules, and those rules as written.
+#[derive(Debug, Clone, Default, PartialEq)]
+struct Group {
+    user_agents: Vec<String>,
+    rules: Vec<String>,
+}
+
+impl RobotsTxt {
+    /// Creates an empty file, which lets crawlers fetch everything.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Starts the rules for a crawler, such as `"Googlebot"` or `"*"` for all of them.
+    ///
+    /// User agents added one after another share the rules that follow them.
+    pub fn user_agent(mut self, agent: &str) -> Self {
+        match self.groups.last_mut() {
+            Some(group) if group.rules.is_empty() => group.user_agents.push(agent.to_string()),
+            _ => self.groups.push(Group {
+                user_agents: vec![agent.to_string()],
+                rules: Vec::new(),
+            }),
+        }
+        self
+    }
+
+    /// Lets the crawlers of the current group fetch paths starting with `path`, as an
+    /// exception to a broader `Disallow`.
+    pub fn allow(self, path: &str) -> Self {
+        self.rule("Allow", path)
+    }
+
+    /// Forbids the crawlers of the current group from fetching paths starting with
+    /// `path`.
+    pub fn disallow(self, path: &str) -> Self {
+        self.rule("Disallow", path)
+    }
+
+    /// Points crawlers to a sitemap, by its absolute URL.
+    pub fn sitemap(mut self, url: &str) -> Self {
+        self.sitemaps.push(url.to_string());
+        self
+    }
+
+    /// Adds a rule to the current group, starting one for every crawler if there is none.
+    fn rule(mut self, field: &str, path: &str) -> Self {
+        if self.groups.is_empty() {
+            self = self.user_agent("*");
+        }
+        let group = self.groups.last_mut().expect("a group was just started");
+        group.rules.push(format!("{}: {}", field, path));
+        self
+    }
+}
+
+/// Writes the file, with a blank line between groups.
+impl fmt::Display for RobotsTxt {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut first = true;
+        for group in &self.groups {
+            if !first {
+                f.write_str("\n")?;
+            }
+            first = false;
+            for agent in &group.user_agents {
+                writeln!(f, "User-agent: {}", agent)?;
+            }
+            for rule in &group.rules {
+                writeln!(f, "{}", rule)?;
+            }
+        }
+        if !self.sitemaps.is_empty() && !first {
+            f.write_str("\n")?;
+        }
+        for sitemap in &self.sitemaps {
+            writeln!(f, "Sitemap: {}", sitemap)?;
+        }
+        Ok(())
+    }
+}
+
+impl From<RobotsTxt> for String {
+    fn from(robots: RobotsTxt) -> Self {
+        robots.to_string()
+    }
+}
+
+#[cfg(test)]
+mod test_site_files {
+    use super::*;
+
+    /// Tests that consecutive user agents share a group and that rules without one
+    /// apply to every crawler.
+    #[test]
+    fn test_robots_txt() {
+        assert_eq!(RobotsTxt::new().to_string(), "");
+        let robots = RobotsTxt::new()
+            .user_agent("Googlebot")
+            .user_agent("Bingbot")
+            .disallow("/private/")
+            .allow("/private/press/")
+            .user_agent("*")
+            .disallow("/");
+        assert_eq!(
+            robots.to_string(),
+            "User-agent: Googlebot\nUser-agent: Bingbot\nDisallow: /private/\n\
+             Allow: /private/press/\n\nUser-agent: *\nDisallow: /\n"
+        );
+        assert_eq!(
+            String::from(RobotsTxt::new().sitemap("https://example.com/a.xml")),
+            "Sitemap: https://example.com/a.xml\n"
+        );
+    }
+}
@@ -0,0 +1,184 @@
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+
+/// Numbers the temporary files of the process, so that they never collide.
+static NEXT_FILE: AtomicU64 = AtomicU64::new(0);
+
+/// A request body stored in a temporary file, which is removed when this is dropped.
+pub(crate) struct TempBody {
+    path: PathBuf,
+    file: Arc<File>,
+}
+
+impl TempBody {
+    /// Creates an empty file under the system temporary directory, readable by the
+    /// owner only where permissions allow it.
+    fn create() -> io::Result<Self> {
+        loop {
+            let number = NEXT_FILE.fetch_add(1, Ordering::Relaxed);
+            let name = format!("rustic-body-{}-{}", std::process::id(), number);
+            let path = std::env::temp_dir().join(name);
+            let mut options = OpenOptions::new();
+            options.read(true).write(true).create_new(true);
+            #[cfg(unix)]
+            std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+            match options.open(&path) {
+                Ok(file) => {
+                    return Ok(TempBody {
+                        path,
+                        file: Arc::new(file),
+                    })
+                }
+                // Left behind by an earlier process with the same id.
+                Err(err) if err.kind() == io::ErrorKind::AlreadyExists => continue,
+                Err(err) => return Err(err),
+            }
+        }
+    }
+
+    /// Returns where the file is.
+    pub(crate) fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Returns the file, opened for reading and writing.
+    pub(crate) fn file(&self) -> &Arc<File> {
+        &self.file
+    }
+}
+
+impl Drop for TempBody {
+    fn drop(&mut self) {
+        // The handler may have moved the file into place already.
+        let _ = fs::remove_file(&self.path);
+    }
+}
+
+/// Where a [`Spool`] ended up keeping a body.
+pub(crate) enum Spooled {
+    /// The body did not exceed the threshold.
+    Memory(Vec<u8>),
+    /// The body exceeded the threshold.
+    File(TempBody),
+}
+
+/// A writer keeping a body in memory up to a threshold, and moving it to a temporary
+/// file as soon as it grows past it.
+pub(crate) struct Spool {
+    memory: Vec<u8>,
+    threshold: usize,
+    file: Option<TempBody>,
+    len: usize,
+    /// Why writing to the file failed, which readers copying into the spool may not
+    /// tell apart from their own errors.
+    failure: Option<io::Error>,
+}
+
+impl Spool {
+    /// Creates an empty spool keeping at most `threshold` bytes in memory, of which
+    /// `capacity` are reserved upfront.
+    pub(crate) fn new(threshold: usize, capacity: usize) -> Self {
+        Spool {
+            memory: Vec::with_capacity(capacity.min(threshold)),
+            threshold,
+            file: None,
+            len: 0,
+            failure: None,
+        }
+    }
+
+    /// Returns how many bytes have been written.
+    pub(crate) fn len(&self) -> usize {
+        self.len
+    }
+
+    /// Returns the body once fully written, with a file rewound to its start.
+    ///
+    /// # Errors
+    ///
+    /// Fails when the temporary file could not be created or written.
+    pub(crate) fn finish(self) -> io::Result<Spooled> {
+        if let Some(err) = self.failure {
+            return Err(err);
+        }
+        match self.file {
+            Some(body) => {
+                let mut file = &*body.file;
+                file.flush()?;
+                file.seek(SeekFrom::Start(0))?;
+                Ok(Spooled::File(body))
+            }
+            None => Ok(Spooled::Memory(self.memory)),
+        }
+    }
+}
+
+impl Spool {
+    /// Writes to memory, or to the file once the threshold is crossed.
+    fn write_spilling(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if self.file.is_none() && buf.len() > self.threshold - self.memory.len() {
+            let body = TempBody::create()?;
+            (&*body.file).write_all(&self.memory)?;
+            self.memory = Vec::new();
+            self.file = Some(body);
+        }
+        match &self.file {
+            Some(body) => (&*body.file).write(buf),
+            None => self.memory.write(buf),
+        }
+    }
+}
+
+impl Write for Spool {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self.write_spilling(buf) {
+            Ok(written) => {
+                self.len += written;
+                Ok(written)
+            }
+            Err(err) => {
+                let kind = err.kind();
+                self.failure = Some(err);
+                Err(kind.into())
+            }
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test_spool {
+    use super::*;
+    use std::io::Read;
+
+    /// Tests that bodies up to the threshold stay in memory, that larger ones move to a
+    /// file intact, and that the file is removed on drop.
+    #[test]
+    fn test_spool() {
+        let mut spool = Spool::new(8, 4);
+        spool.write_all(b"12345678").unwrap();
+        assert!(matches!(spool.finish().unwrap(), Spooled::Memory(body) if body == b"12345678"));
+
+        let mut spool = Spool::new(8, 4);
+        spool.write_all(b"12345").unwrap();
+        spool.write_all(b"6789").unwrap();
+        spool.write_all(b"0").unwrap();
+        assert_eq!(spool.len(), 10);
+        let Spooled::File(body) = spool.finish().unwrap() else {
+            panic!("the body should have been spilled");
+        };
+        let path = body.path().to_path_buf();
+        let mut contents = String::new();
+        (&**body.file()).read_to_string(&mut contents).unwrap();
+        assert_eq!(contents, "1234567890");
+        assert_eq!(fs::read(&path).unwrap(), b"1234567890");
+        drop(body);
+        assert!(!path.exists());
+    }
+}
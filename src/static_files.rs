@@ -0,0 +1,945 @@
+use crate::app::{App, Request};
+use crate::compression::coding_quality;
+use crate::content_type::ContentType;
+use crate::crypto::{base64_url_encode, sha256};
+use crate::header_map::HeaderMap;
+use crate::http11_response::{format_http_date, parse_http_date, reason_phrase, Body, Response};
+use crate::http_error::HttpError;
+use crate::into_response::body_response;
+use crate::parse_url::{percent_decode, percent_encode};
+use crate::template::escape_html;
+use std::fmt::{self, Write};
+use std::fs::{self, File, Metadata};
+use std::io;
+use std::path::{Component, Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// The file served in place of a directory that contains it.
+const INDEX_FILE: &str = "index.html";
+
+/// The precompressed sidecar files looked for next to a static file, by coding.
+const SIDECARS: [(&str, &str); 2] = [("br", ".br"), ("gzip", ".gz")];
+
+/// How many times a file is read again when it changes while being read.
+const READ_ATTEMPTS: usize = 3;
+
+/// Options for [`App::serve_static`].
+///
+/// # Examples
+///
+/// ```
+/// use rustic::static_files::StaticOptions;
+/// let options = StaticOptions {
+///     directory_listing: true,
+///     ..StaticOptions::default()
+/// };
+/// assert!(!options.show_hidden);
+/// ```
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct StaticOptions {
+    /// Whether a directory without an `index.html` is answered with a generated listing
+    /// of its entries instead of `404 Not Found`.
+    ///
+    /// Listings reveal every file name under the root, so this is off by default.
+    pub directory_listing: bool,
+    /// Whether files and directories whose names start with a dot are served and listed.
+    ///
+    /// Dotfiles often hold configuration or credentials, so this is off by default.
+    pub show_hidden: bool,
+    /// Whether the `ETag` of a file is a strong tag hashed from its contents instead of a
+    /// weak one derived from its size and modification time.
+    ///
+    /// Strong tags survive copying files to another server, but every request, including
+    /// a conditional one, then has to read the whole file.
+    pub strong_etags: bool,
+    /// Whether symlinks under the root may lead to files outside of it.
+    ///
+    /// Off by default, so that a symlink cannot expose the rest of the filesystem.
+    pub follow_symlinks: bool,
+}
+
+impl App {
+    /// Serves the files under a directory for `GET` requests below a path prefix.
+    ///
+    /// A request for a directory is answered with its `index.html`, or with a listing when
+    /// [`StaticOptions::directory_listing`] is set. Paths refused by [`resolve_safe_path`]
+    /// are answered with `404 Not Found` and logged. Precompressed
+    /// `.br` and `.gz` files next to a file are sent instead of it to clients accepting
+    /// that encoding.
+    ///
+    /// Files are sent with an `ETag` and a `Last-Modified` header, and requests whose
+    /// `If-None-Match` or `If-Modified-Since` header matches the file are answered with
+    /// `304 Not Modified`. Files are sent from disk without being read into memory, with
+    /// `sendfile(2)` on Linux.
+    ///
+    /// # Arguments
+    ///
+    /// * `prefix` - The path prefix, such as `"assets"`, or `""` to serve from the root.
+    /// * `root` - The directory holding the files.
+    /// * `options` - How directories and hidden files are treated.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use rustic::app::App;
+    /// use rustic::static_files::StaticOptions;
+    ///
+    /// let mut application = App::new();
+    /// application.serve_static("assets", "./public", StaticOptions::default());
+    /// ```
+    pub fn serve_static(&mut self, prefix: &str, root: impl Into<PathBuf>, options: StaticOptions) {
+        let prefix = prefix.trim_matches('/').to_string();
+        let pattern = if prefix.is_empty() {
+            "*".to_string()
+        } else {
+            format!("{}/*", prefix)
+        };
+        let root = root.into();
+        self.get(pattern, move |request: Request| {
+            serve_file(&root, &prefix, &request, &options)
+        });
+    }
+}
+
+/// Answers a request for a file below `root`, given the prefix its path starts with.
+///
+/// # Returns
+///
+/// * `Option<Response>` - The file, index or listing, or `None` if there is nothing to serve.
+fn serve_file(
+    root: &Path,
+    prefix: &str,
+    request: &Request,
+    options: &StaticOptions,
+) -> Option<Response> {
+    let skip = prefix.split('/').filter(|part| !part.is_empty()).count();
+    let relative = request
+        .path
+        .split('/')
+        .filter(|part| !part.is_empty())
+        .skip(skip)
+        .collect::<Vec<_>>()
+        .join("/");
+    let contain = !options.follow_symlinks;
+    let resolved = decode_segments(&relative).and_then(|segments| {
+        let target = resolve_segments(root, &segments, contain)?;
+        Ok((segments, target))
+    });
+    let (mut segments, target) = match resolved {
+        Ok(resolved) => resolved,
+        Err(PathError::Io(_)) => return None,
+        Err(err) => {
+            log::warn!("Rejected static file request for {}: {}", request.url, err);
+            return None;
+        }
+    };
+    if !options.show_hidden && segments.iter().any(|segment| segment.starts_with('.')) {
+        return None;
+    }
+    if target.is_file() {
+        return file_response(root, &segments, &target, request, options);
+    }
+    segments.push(INDEX_FILE.to_string());
+    if let Ok(index) = resolve_segments(root, &segments, contain) {
+        if index.is_file() {
+            return file_response(root, &segments, &index, request, options);
+        }
+    }
+    segments.pop();
+    if !options.directory_listing {
+        return None;
+    }
+    let html = directory_listing(&target, prefix, &segments, options.show_hidden)?;
+    Some(body_response(200, ContentType::TEXT_HTML_UTF8, html))
+}
+
+/// Why a request path was refused by [`resolve_safe_path`].
+#[derive(Debug)]
+pub enum PathError {
+    /// The path contains a malformed percent escape or is not UTF-8 once decoded.
+    Malformed,
+    /// A segment is `.` or `..`, or contains a separator, a drive prefix or a NUL byte once
+    /// decoded.
+    Traversal,
+    /// The path leads out of the root through a symlink.
+    OutsideRoot,
+    /// The path does not exist or cannot be accessed.
+    Io(io::Error),
+}
+
+impl fmt::Display for PathError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PathError::Malformed => f.write_str("malformed path"),
+            PathError::Traversal => f.write_str("path traversal attempt"),
+            PathError::OutsideRoot => f.write_str("path escapes the root"),
+            PathError::Io(err) => write!(f, "cannot resolve path: {}", err),
+        }
+    }
+}
+
+impl std::error::Error for PathError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            PathError::Io(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+/// Converts a refused path into `404 Not Found`, whatever the reason, so that responses
+/// do not reveal which files exist outside the root.
+impl From<PathError> for HttpError {
+    fn from(_: PathError) -> Self {
+        HttpError::not_found()
+    }
+}
+
+/// Resolves a percent-encoded request path to an existing file or directory under a root.
+///
+/// Every segment is decoded and must name an entry: `.` and `..` segments, encoded
+/// slashes and backslashes, drive prefixes and NUL bytes are refused. The result is
+/// canonicalized, so it must also stay under the root once symlinks are followed.
+///
+/// # Arguments
+///
+/// * `root` - The directory the path must stay under.
+/// * `request_path` - The path below the root as sent by the client, such as
+///   `"docs/read%20me.txt"`.
+///
+/// # Returns
+///
+/// * `Result<PathBuf, PathError>` - The canonical path, or why it was refused.
+///
+/// # Examples
+///
+/// ```
+/// use rustic::http_error::HttpError;
+/// use rustic::static_files::{resolve_safe_path, PathError};
+///
+/// let root = std::env::temp_dir();
+/// assert!(matches!(
+///     resolve_safe_path(&root, "%2e%2e/etc/passwd"),
+///     Err(PathError::Traversal)
+/// ));
+/// let error: HttpError = resolve_safe_path(&root, "../etc/passwd").unwrap_err().into();
+/// assert_eq!(error.status, 404);
+/// ```
+pub fn resolve_safe_path(root: &Path, request_path: &str) -> Result<PathBuf, PathError> {
+    resolve_segments(root, &decode_segments(request_path)?, true)
+}
+
+/// Decodes the segments of a request path, refusing those that do not name an entry.
+fn decode_segments(request_path: &str) -> Result<Vec<String>, PathError> {
+    let mut segments = Vec::new();
+    for raw in request_path.split('/').filter(|part| !part.is_empty()) {
+        let segment = percent_decode(raw).ok_or(PathError::Malformed)?;
+        let mut components = Path::new(&segment).components();
+        let single_name =
+            matches!(components.next(), Some(Component::Normal(_))) && components.next().is_none();
+        if !single_name || segment.contains(['/', '\\', '\0']) {
+            return Err(PathError::Traversal);
+        }
+        segments.push(segment);
+    }
+    Ok(segments)
+}
+
+/// Joins decoded segments onto a root, checking with `contain` that symlinks do not lead
+/// out of it.
+fn resolve_segments(root: &Path, segments: &[String], contain: bool) -> Result<PathBuf, PathError> {
+    let target = segments
+        .iter()
+        .fold(root.to_path_buf(), |path, segment| path.join(segment));
+    if !contain {
+        fs::metadata(&target).map_err(PathError::Io)?;
+        return Ok(target);
+    }
+    let root = root.canonicalize().map_err(PathError::Io)?;
+    let target = target.canonicalize().map_err(PathError::Io)?;
+    if target.starts_with(&root) {
+        Ok(target)
+    } else {
+        Err(PathError::OutsideRoot)
+    }
+}
+
+/// Answers with a file and a `Content-Type` guessed from its extension.
+///
+/// The file is sent from disk as a [file body](Body::file), so it is never held in
+/// memory, unless strong tags need its contents to hash.
+///
+/// When the client accepts it, a precompressed sidecar such as `app.js.br` or `app.js.gz`
+/// is sent instead, with the `Content-Type` of the original file and the matching
+/// `Content-Encoding`. The validators describe the file that is sent, and a request they
+/// match is answered with `304 Not Modified`. With weak tags, that answer only needs the
+/// metadata of the file and skips reading it.
+fn file_response(
+    root: &Path,
+    segments: &[String],
+    path: &Path,
+    request: &Request,
+    options: &StaticOptions,
+) -> Option<Response> {
+    let accept_encoding = request.header("Accept-Encoding").unwrap_or("");
+    let contain = !options.follow_symlinks;
+    let variant = select_variant(root, segments, path, contain, accept_encoding);
+    let (contents, mut validators) = if options.strong_etags {
+        let (contents, metadata) = read_consistent(&variant.path)?;
+        let validators = Validators::new(&metadata, Some(&contents));
+        (Some(contents), validators)
+    } else {
+        let metadata = fs::metadata(&variant.path).ok()?;
+        (None, Validators::new(&metadata, None))
+    };
+    let mut response = if validators.matches(request) {
+        Response {
+            status_code: 304,
+            reason: reason_phrase(304).into(),
+            response_body: None,
+            headers: HeaderMap::new(),
+        }
+    } else {
+        let body = match contents {
+            Some(contents) => Body::from(contents),
+            None => {
+                // The length and validators come from the open file, so they describe
+                // what is sent even if the path is replaced meanwhile.
+                let file = File::open(&variant.path).ok()?;
+                let metadata = file.metadata().ok()?;
+                validators = Validators::new(&metadata, None);
+                Body::file(file, 0, metadata.len())
+            }
+        };
+        let mut response = body_response(200, content_type(path), body);
+        if let Some(encoding) = variant.encoding {
+            response.headers.insert("Content-Encoding", encoding);
+        }
+        response
+    };
+    validators.apply(&mut response);
+    if variant.has_sidecars {
+        response.headers.insert("Vary", "Accept-Encoding");
+    }
+    Some(response)
+}
+
+/// Reads a file along with metadata describing exactly the bytes that were read.
+///
+/// The file is read again, up to [`READ_ATTEMPTS`] times, when its size or modification
+/// time changes during the read. A file that keeps changing is sent as last read, with
+/// validators from the metadata taken afterwards; a client can then at worst revalidate
+/// a body that does not match its `ETag` once more.
+fn read_consistent(path: &Path) -> Option<(Vec<u8>, Metadata)> {
+    let mut before = fs::metadata(path).ok()?;
+    let mut attempt = 1;
+    loop {
+        let contents = fs::read(path).ok()?;
+        let after = fs::metadata(path).ok()?;
+        let unchanged = contents.len() as u64 == after.len()
+            && before.len() == after.len()
+            && before.modified().ok() == after.modified().ok();
+        if unchanged || attempt == READ_ATTEMPTS {
+            return Some((contents, after));
+        }
+        before = after;
+        attempt += 1;
+    }
+}
+
+/// The `ETag` and `Last-Modified` validators of a static file.
+struct Validators {
+    etag: String,
+    last_modified: Option<SystemTime>,
+}
+
+impl Validators {
+    /// Derives the validators of a file, with a strong tag when its contents are given.
+    fn new(metadata: &Metadata, contents: Option<&[u8]>) -> Self {
+        let last_modified = metadata.modified().ok();
+        let etag = match contents {
+            Some(contents) => format!("\"{}\"", base64_url_encode(&sha256(contents)[..16])),
+            None => {
+                let since_epoch = last_modified
+                    .and_then(|modified| modified.duration_since(UNIX_EPOCH).ok())
+                    .unwrap_or_default();
+                format!(
+                    "W/\"{:x}-{:x}.{:x}\"",
+                    metadata.len(),
+                    since_epoch.as_secs(),
+                    since_epoch.subsec_nanos()
+                )
+            }
+        };
+        Validators {
+            etag,
+            last_modified,
+        }
+    }
+
+    /// Checks whether a request's conditional headers show that the client's copy is current.
+    ///
+    /// `If-None-Match` is compared weakly and, when present, `If-Modified-Since` is
+    /// ignored (RFC 9110, section 13.2.2).
+    fn matches(&self, request: &Request) -> bool {
+        if let Some(if_none_match) = request.header("If-None-Match") {
+            let etag = opaque_tag(&self.etag);
+            return if_none_match
+                .split(',')
+                .map(str::trim)
+                .any(|tag| tag == "*" || opaque_tag(tag) == etag);
+        }
+        let since = request
+            .header("If-Modified-Since")
+            .and_then(parse_http_date);
+        match (since, self.last_modified) {
+            (Some(since), Some(modified)) => whole_seconds(modified) <= whole_seconds(since),
+            _ => false,
+        }
+    }
+
+    /// Sets the `ETag` and `Last-Modified` headers of a response.
+    fn apply(&self, response: &mut Response) {
+        response.headers.insert("ETag", self.etag.as_str());
+        if let Some(modified) = self.last_modified {
+            response
+                .headers
+                .insert("Last-Modified", format_http_date(modified));
+        }
+    }
+}
+
+/// Strips the weakness indicator of an entity tag, for weak comparison.
+fn opaque_tag(tag: &str) -> &str {
+    tag.strip_prefix("W/").unwrap_or(tag)
+}
+
+/// Truncates a point in time to the whole seconds that an HTTP-date can express.
+fn whole_seconds(time: SystemTime) -> u64 {
+    time.duration_since(UNIX_EPOCH)
+        .map_or(0, |elapsed| elapsed.as_secs())
+}
+
+/// The file chosen to answer a request for a static file.
+struct Variant {
+    path: PathBuf,
+    /// The `Content-Encoding` of the file, or `None` for the original file.
+    encoding: Option<&'static str>,
+    /// Whether any precompressed sidecar exists, so that the response varies by encoding.
+    has_sidecars: bool,
+}
+
+/// Picks the original file or one of its precompressed sidecars.
+///
+/// Sidecars are preferred in the order of [`SIDECARS`] among the codings the client
+/// rates highest. The original is only kept over them when the client rates `identity`
+/// higher, either by name or through `*`. Sidecars are resolved from the `segments` of
+/// the original under `root` like any other request, so with `contain` a sidecar that
+/// symlinks out of the root is ignored.
+fn select_variant(
+    root: &Path,
+    segments: &[String],
+    path: &Path,
+    contain: bool,
+    accept_encoding: &str,
+) -> Variant {
+    let identity = coding_quality(accept_encoding, "identity").unwrap_or(0.0);
+    let mut variant = Variant {
+        path: path.to_path_buf(),
+        encoding: None,
+        has_sidecars: false,
+    };
+    let mut best = 0.0;
+    let Some((name, parents)) = segments.split_last() else {
+        return variant;
+    };
+    let mut sidecar_segments = parents.to_vec();
+    for (encoding, extension) in SIDECARS {
+        sidecar_segments.push(format!("{}{}", name, extension));
+        let sidecar = resolve_segments(root, &sidecar_segments, contain);
+        sidecar_segments.pop();
+        let Some(sidecar) = sidecar.ok().filter(|sidecar| sidecar.is_file()) else {
+            continue;
+        };
+        variant.has_sidecars = true;
+        let quality = coding_quality(accept_encoding, encoding).unwrap_or(0.0);
+        if quality > best && quality >= identity {
+            best = quality;
+            variant.path = sidecar;
+            variant.encoding = Some(encoding);
+        }
+    }
+    variant
+}
+
+/// Guesses the media type of a file from its extension.
+pub(crate) fn content_type(path: &Path) -> &'static str {
+    let extension = path
+        .extension()
+        .and_then(|extension| extension.to_str())
+        .map(str::to_ascii_lowercase);
+    match extension.as_deref() {
+        Some("html" | "htm") => "text/html; charset=utf-8",
+        Some("css") => "text/css; charset=utf-8",
+        Some("js" | "mjs") => "text/javascript; charset=utf-8",
+        Some("json") => "application/json",
+        Some("txt") => "text/plain; charset=utf-8",
+        Some("xml") => "application/xml",
+        Some("svg") => "image/svg+xml",
+        Some("png") => "image/png",
+        Some("jpg" | "jpeg") => "image/jpeg",
+        Some("gif") => "image/gif",
+        Some("webp") => "image/webp",
+        Some("ico") => "image/x-icon",
+        Some("pdf") => "application/pdf",
+        Some("wasm") => "application/wasm",
+        Some("woff") => "font/woff",
+        Some("woff2") => "font/woff2",
+        _ => "application/octet-stream",
+    }
+}
+
+/// An entry shown in a directory listing.
+struct ListingEntry {
+    name: String,
+    is_dir: bool,
+    size: u64,
+    modified: Option<String>,
+}
+
+/// Renders the HTML listing of a directory, directories first and then by name.
+///
+/// # Arguments
+///
+/// * `directory` - The directory to list.
+/// * `prefix` - The path prefix the static files are served under.
+/// * `segments` - The decoded path of the directory below the prefix.
+/// * `show_hidden` - Whether entries starting with a dot are listed.
+///
+/// # Returns
+///
+/// * `Option<String>` - The listing, or `None` if the directory cannot be read.
+fn directory_listing(
+    directory: &Path,
+    prefix: &str,
+    segments: &[String],
+    show_hidden: bool,
+) -> Option<String> {
+    let mut entries = Vec::new();
+    for entry in fs::read_dir(directory).ok()?.flatten() {
+        let Ok(name) = entry.file_name().into_string() else {
+            continue;
+        };
+        if name.starts_with('.') && !show_hidden {
+            continue;
+        }
+        // Follow symlinks so that linked directories are listed as directories.
+        let Ok(metadata) = fs::metadata(entry.path()) else {
+            continue;
+        };
+        entries.push(ListingEntry {
+            name,
+            is_dir: metadata.is_dir(),
+            size: metadata.len(),
+            modified: metadata.modified().ok().map(format_http_date),
+        });
+    }
+    entries.sort_by(|a, b| (!a.is_dir, &a.name).cmp(&(!b.is_dir, &b.name)));
+
+    let mut base = String::from("/");
+    for part in prefix.split('/').filter(|part| !part.is_empty()) {
+        base.push_str(&percent_encode(part));
+        base.push('/');
+    }
+    let mut shown = base.clone();
+    for segment in segments {
+        base.push_str(&percent_encode(segment));
+        base.push('/');
+        shown.push_str(segment);
+        shown.push('/');
+    }
+    let title = escape_html(&shown);
+
+    let mut html = String::new();
+    let _ = write!(
+        html,
+        "<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n\
+         <title>Index of {title}</title>\n</head>\n<body>\n<h1>Index of {title}</h1>\n\
+         <table>\n<tr><th>Name</th><th>Size</th><th>Last modified</th></tr>\n"
+    );
+    if !segments.is_empty() {
+        let parent = &base[..base[..base.len() - 1].rfind('/').unwrap_or(0) + 1];
+        let _ = writeln!(
+            html,
+            "<tr><td><a href=\"{}\">../</a></td><td>-</td><td>-</td></tr>",
+            parent
+        );
+    }
+    for entry in &entries {
+        let slash = if entry.is_dir { "/" } else { "" };
+        let size = if entry.is_dir {
+            "-".to_string()
+        } else {
+            humanize_size(entry.size)
+        };
+        let _ = writeln!(
+            html,
+            "<tr><td><a href=\"{}{}{}\">{}{}</a></td><td>{}</td><td>{}</td></tr>",
+            base,
+            percent_encode(&entry.name),
+            slash,
+            escape_html(&entry.name),
+            slash,
+            size,
+            entry.modified.as_deref().unwrap_or("-")
+        );
+    }
+    html.push_str("</table>\n</body>\n</html>\n");
+    Some(html)
+}
+
+/// Formats a byte count with a binary unit, such as `"1.5 KiB"`.
+fn humanize_size(bytes: u64) -> String {
+    const UNITS: [&str; 5] = ["KiB", "MiB", "GiB", "TiB", "PiB"];
+    if bytes < 1024 {
+        return format!("{} B", bytes);
+    }
+    let mut size = bytes as f64 / 1024.0;
+    let mut unit = 0;
+    while size >= 1024.0 && unit < UNITS.len() - 1 {
+        size /= 1024.0;
+        unit += 1;
+    }
+    format!("{:.1} {}", size, UNITS[unit])
+}
+
+#[cfg(test)]
+mod test_static_files {
+    use super::*;
+    use crate::parse_headers::RequestType;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    /// Creates an empty directory under the system temporary directory.
+    fn temp_dir(name: &str) -> PathBuf {
+        static COUNTER: AtomicUsize = AtomicUsize::new(0);
+        let directory = std::env::temp_dir().join(format!(
+            "rustic-{}-{}-{}",
+            name,
+            std::process::id(),
+            COUNTER.fetch_add(1, Ordering::Relaxed)
+        ));
+        let _ = fs::remove_dir_all(&directory);
+        fs::create_dir_all(&directory).unwrap();
+        directory
+    }
+
+    /// Builds a `GET` request for a path with the given headers.
+    fn request(path: &str, headers: &[(&str, &str)]) -> Request {
+        Request {
+            method: RequestType::GET,
+            path: path.to_string(),
+            url: format!("/{}", path),
+            headers: headers
+                .iter()
+                .map(|(name, value)| (name.to_string(), value.to_string()))
+                .collect(),
+            body: String::new(),
+            ..Request::default()
+        }
+    }
+
+    /// Returns the body of a response as text.
+    fn text(response: Response) -> String {
+        String::from_utf8(response.response_body.unwrap().read().unwrap().into_owned()).unwrap()
+    }
+
+    /// Tests that listings escape names, sort directories first and hide dotfiles.
+    #[test]
+    fn test_directory_listing() {
+        let root = temp_dir("listing");
+        fs::create_dir(root.join("zeta")).unwrap();
+        fs::write(root.join("<script>alert(1) x.js"), "x").unwrap();
+        fs::write(root.join("a.txt"), vec![0; 2048]).unwrap();
+        fs::write(root.join(".env"), "SECRET=1").unwrap();
+
+        let html = directory_listing(&root, "static", &[], false).unwrap();
+        assert!(!html.contains("<script>"));
+        assert!(html.contains(
+            "<a href=\"/static/%3Cscript%3Ealert%281%29%20x.js\">&lt;script&gt;alert(1) x.js</a>"
+        ));
+        assert!(html.contains("<td>2.0 KiB</td>"));
+        assert!(!html.contains(".env"));
+        assert!(!html.contains("../"));
+        let zeta = html.find("zeta/").unwrap();
+        assert!(zeta < html.find("a.txt").unwrap());
+
+        let html = directory_listing(&root, "static", &[], true).unwrap();
+        assert!(html.contains(".env"));
+        fs::remove_dir_all(root).unwrap();
+    }
+
+    /// Tests that nested listings link to their parent and encode the directory path.
+    #[test]
+    fn test_nested_listing() {
+        let root = temp_dir("nested");
+        let segments = vec!["my docs".to_string()];
+        fs::create_dir(root.join(&segments[0])).unwrap();
+        fs::write(root.join("my docs").join("b.txt"), "b").unwrap();
+
+        let html = directory_listing(&root.join("my docs"), "", &segments, false).unwrap();
+        assert!(html.contains("<title>Index of /my docs/</title>"));
+        assert!(html.contains("<a href=\"/\">../</a>"));
+        assert!(html.contains("<a href=\"/my%20docs/b.txt\">b.txt</a>"));
+        fs::remove_dir_all(root).unwrap();
+    }
+
+    /// Tests that files and indexes are served and that listings are opt-in.
+    #[test]
+    fn test_serve_file() {
+        let root = temp_dir("serve");
+        fs::create_dir(root.join("site")).unwrap();
+        fs::write(root.join("site").join("index.html"), "<h1>Home</h1>").unwrap();
+        fs::write(root.join("style.css"), "body {}").unwrap();
+        let listing = StaticOptions {
+            directory_listing: true,
+            ..StaticOptions::default()
+        };
+
+        let response =
+            serve_file(&root, "static", &request("static/style.css", &[]), &listing).unwrap();
+        assert_eq!(
+            response.header("Content-Type"),
+            Some("text/css; charset=utf-8")
+        );
+        assert_eq!(text(response), "body {}");
+        let index = serve_file(&root, "static", &request("static/site", &[]), &listing).unwrap();
+        assert_eq!(text(index), "<h1>Home</h1>");
+        assert!(
+            text(serve_file(&root, "static", &request("static", &[]), &listing).unwrap())
+                .contains("site/")
+        );
+        assert!(serve_file(
+            &root,
+            "static",
+            &request("static", &[]),
+            &StaticOptions::default()
+        )
+        .is_none());
+        fs::remove_dir_all(root).unwrap();
+    }
+
+    /// Tests that dot segments, encoded separators and hidden files are not served.
+    #[test]
+    fn test_rejected_paths() {
+        let root = temp_dir("rejected");
+        fs::write(root.join(".secret"), "hidden").unwrap();
+        let options = StaticOptions::default();
+        for path in [
+            ".secret", "..", "%2e%2e", "a/../..", "./a", "a%2Fb", "%00", "%zz",
+        ] {
+            let response = serve_file(&root, "", &request(path, &[]), &options);
+            assert!(response.is_none(), "{}", path);
+        }
+        fs::remove_dir_all(root).unwrap();
+    }
+
+    /// Tests that traversal payloads are refused for the right reason.
+    #[test]
+    fn test_resolve_safe_path() {
+        let root = temp_dir("traversal");
+        fs::create_dir(root.join("public")).unwrap();
+        fs::write(root.join("public").join("ok.txt"), "ok").unwrap();
+        fs::write(root.join("secret.txt"), "secret").unwrap();
+        let public = root.join("public");
+
+        let resolved = resolve_safe_path(&public, "ok.txt").unwrap();
+        assert_eq!(resolved, public.join("ok.txt").canonicalize().unwrap());
+        assert!(resolve_safe_path(&public, "").unwrap().is_dir());
+        assert!(matches!(
+            resolve_safe_path(&public, "missing.txt"),
+            Err(PathError::Io(_))
+        ));
+        for payload in [
+            "..",
+            "../secret.txt",
+            "./ok.txt",
+            "ok.txt/..",
+            "%2e%2e/secret.txt",
+            "%2E%2E/secret.txt",
+            ".%2e/secret.txt",
+            "%2e/ok.txt",
+            "..%2fsecret.txt",
+            "..%2Fsecret.txt",
+            "..%5csecret.txt",
+            "..%5Csecret.txt",
+            "..\\secret.txt",
+            "ok.txt%00.png",
+            "%00",
+            "a/%2e%2e/%2e%2e/secret.txt",
+        ] {
+            let result = resolve_safe_path(&public, payload);
+            assert!(matches!(result, Err(PathError::Traversal)), "{}", payload);
+        }
+        for payload in ["%2", "%zz/ok.txt", "%c0%ae%c0%ae/secret.txt", "%ff"] {
+            let result = resolve_safe_path(&public, payload);
+            assert!(matches!(result, Err(PathError::Malformed)), "{}", payload);
+        }
+        fs::remove_dir_all(root).unwrap();
+    }
+
+    /// Tests that symlinks leading out of the root, sidecars included, are only followed when
+    /// allowed.
+    #[cfg(unix)]
+    #[test]
+    fn test_symlink_escape() {
+        let root = temp_dir("symlinks");
+        fs::create_dir(root.join("public")).unwrap();
+        fs::write(root.join("secret.txt"), "secret").unwrap();
+        fs::write(root.join("public").join("real.txt"), "real").unwrap();
+        let public = root.join("public");
+        std::os::unix::fs::symlink(root.join("secret.txt"), public.join("escape.txt")).unwrap();
+        std::os::unix::fs::symlink(public.join("real.txt"), public.join("inner.txt")).unwrap();
+
+        assert!(matches!(
+            resolve_safe_path(&public, "escape.txt"),
+            Err(PathError::OutsideRoot)
+        ));
+        assert!(resolve_safe_path(&public, "inner.txt").is_ok());
+        let options = StaticOptions::default();
+        assert!(serve_file(&public, "", &request("escape.txt", &[]), &options).is_none());
+        let follow = StaticOptions {
+            follow_symlinks: true,
+            ..StaticOptions::default()
+        };
+        let response = serve_file(&public, "", &request("escape.txt", &[]), &follow).unwrap();
+        assert_eq!(text(response), "secret");
+
+        fs::write(public.join("app.js"), "app").unwrap();
+        std::os::unix::fs::symlink(root.join("secret.txt"), public.join("app.js.gz")).unwrap();
+        let gzip = request("app.js", &[("Accept-Encoding", "gzip")]);
+        let response = serve_file(&public, "", &gzip, &options).unwrap();
+        assert_eq!(response.header("Content-Encoding"), None);
+        assert_eq!(response.header("Vary"), None);
+        assert_eq!(text(response), "app");
+        let response = serve_file(&public, "", &gzip, &follow).unwrap();
+        assert_eq!(response.header("Content-Encoding"), Some("gzip"));
+        assert_eq!(text(response), "secret");
+        fs::remove_dir_all(root).unwrap();
+    }
+
+    /// Tests that precompressed sidecars are served by preference with the original type.
+    #[test]
+    fn test_precompressed_sidecars() {
+        let root = temp_dir("sidecars");
+        let gzip = [0x1f, 0x8b, 0x08, 0x00, 0xff];
+        let brotli = [0x0b, 0x02, 0x80, 0xff];
+        fs::write(root.join("app.js"), "console.log(1);").unwrap();
+        fs::write(root.join("app.js.gz"), gzip).unwrap();
+        fs::write(root.join("app.js.br"), brotli).unwrap();
+        fs::write(root.join("plain.css"), "p {}").unwrap();
+        let options = StaticOptions::default();
+        let serve = |path: &str, accept: &str| {
+            let headers = [("Accept-Encoding", accept)];
+            serve_file(&root, "", &request(path, &headers), &options).unwrap()
+        };
+
+        let response = serve("app.js", "gzip, deflate, br");
+        assert_eq!(response.header("Content-Encoding"), Some("br"));
+        assert_eq!(
+            response.header("Content-Type"),
+            Some("text/javascript; charset=utf-8")
+        );
+        assert_eq!(response.header("Vary"), Some("Accept-Encoding"));
+        assert_eq!(*response.response_body.unwrap().read().unwrap(), brotli);
+
+        let response = serve("app.js", "br;q=0.5, gzip;q=0.8");
+        assert_eq!(response.header("Content-Encoding"), Some("gzip"));
+        assert_eq!(*response.response_body.unwrap().read().unwrap(), gzip);
+
+        let response = serve("app.js", "identity, gzip;q=0.5");
+        assert_eq!(response.header("Content-Encoding"), None);
+        assert_eq!(response.header("Vary"), Some("Accept-Encoding"));
+        assert_eq!(text(response), "console.log(1);");
+
+        assert_eq!(
+            serve("app.js", "br;q=0, *").header("Content-Encoding"),
+            Some("gzip")
+        );
+        assert_eq!(text(serve("app.js", "")), "console.log(1);");
+        let plain = serve("plain.css", "gzip, br");
+        assert_eq!(plain.header("Vary"), None);
+        assert_eq!(text(plain), "p {}");
+        fs::remove_dir_all(root).unwrap();
+    }
+
+    /// Tests that files carry validators and matching conditional requests get a `304`.
+    #[test]
+    fn test_conditional_requests() {
+        let root = temp_dir("conditional");
+        fs::write(root.join("app.js"), "let a;").unwrap();
+        fs::write(root.join("app.js.gz"), [0x1f, 0x8b]).unwrap();
+        let options = StaticOptions::default();
+        let serve = |headers: &[(&str, &str)]| {
+            serve_file(&root, "", &request("app.js", headers), &options).unwrap()
+        };
+
+        let response = serve(&[]);
+        let etag = response.header("ETag").unwrap().to_string();
+        let last_modified = response.header("Last-Modified").unwrap().to_string();
+        assert!(etag.starts_with("W/\"6-"));
+        let gzip_etag = serve(&[("Accept-Encoding", "gzip")])
+            .header("ETag")
+            .unwrap()
+            .to_string();
+        assert_ne!(gzip_etag, etag);
+
+        let cached = serve(&[("If-None-Match", &format!("\"x\", {}", etag))]);
+        assert_eq!(cached.status_code, 304);
+        assert!(cached.response_body.is_none());
+        assert_eq!(cached.header("ETag"), Some(etag.as_str()));
+        assert_eq!(cached.header("Vary"), Some("Accept-Encoding"));
+        assert_eq!(
+            serve(&[("If-Modified-Since", &last_modified)]).status_code,
+            304
+        );
+        let stale = serve(&[
+            ("If-None-Match", &gzip_etag),
+            ("If-Modified-Since", &last_modified),
+        ]);
+        assert_eq!(stale.status_code, 200);
+        let old = serve(&[("If-Modified-Since", "Sun, 06 Nov 1994 08:49:37 GMT")]);
+        assert_eq!(text(old), "let a;");
+
+        let strong = StaticOptions {
+            strong_etags: true,
+            ..StaticOptions::default()
+        };
+        let response = serve_file(&root, "", &request("app.js", &[]), &strong).unwrap();
+        let etag = response.header("ETag").unwrap().to_string();
+        assert!(etag.starts_with('"') && etag.ends_with('"'));
+        let headers = [("If-None-Match", etag.as_str())];
+        let cached = serve_file(&root, "", &request("app.js", &headers), &strong).unwrap();
+        assert_eq!(cached.status_code, 304);
+        fs::remove_dir_all(root).unwrap();
+    }
+
+    /// Tests reading qualities from `Accept-Encoding`, including wildcards and bad values.
+    #[test]
+    fn test_coding_quality() {
+        let accept = "gzip;q=0.6, BR, *;q=0.1, deflate;q=oops";
+        assert_eq!(coding_quality(accept, "gzip"), Some(0.6));
+        assert_eq!(coding_quality(accept, "br"), Some(1.0));
+        assert_eq!(coding_quality(accept, "zstd"), Some(0.1));
+        assert_eq!(coding_quality(accept, "deflate"), Some(1.0));
+        assert_eq!(coding_quality("gzip", "br"), None);
+    }
+
+    /// Tests that sizes are shown with binary units.
+    #[test]
+    fn test_humanize_size() {
+        assert_eq!(humanize_size(0), "0 B");
+        assert_eq!(humanize_size(1023), "1023 B");
+        assert_eq!(humanize_size(1536), "1.5 KiB");
+        assert_eq!(humanize_size(5 * 1024 * 1024), "5.0 MiB");
+    }
+}
@@ -0,0 +1,203 @@
+use crate::app::{App, Request};
+use crate::worker_pool::WorkerPool;
+use std::io;
+use std::sync::{Arc, Condvar, Mutex, OnceLock};
+use std::time::{Duration, Instant};
+
+/// How many threads run background tasks.
+pub const BACKGROUND_WORKERS: usize = 4;
+
+/// How many tasks may wait for a free thread before spawning blocks.
+const MAX_QUEUED_TASKS: usize = 1024;
+
+type Task = Box<dyn FnOnce() + Send>;
+
+struct TasksState {
+    /// Started with the first task, so apps that never spawn any start no threads.
+    pool: OnceLock<WorkerPool<Task>>,
+    pending: Mutex<usize>,
+    idle: Condvar,
+}
+
+/// A handle for running work that outlives the response, such as sending an email or
+/// refreshing a cache.
+///
+/// Tasks run on a small pool of threads shared by the whole app. A task that panics is
+/// logged and does not take its thread down. When a server started with
+/// [`crate::app::run_with_listener`] shuts down, it waits for the tasks still queued or
+/// running, within the same drain timeout as in-flight requests, before returning.
+///
+/// Clones share the same pool. Handlers reach it through [`Request::spawn_background`]
+/// or the request extensions, and other code through [`App::tasks`].
+///
+/// # Examples
+///
+/// ```
+/// use rustic::app::App;
+///
+/// let mut application = App::new();
+/// application.post("signup", |request| {
+///     let address = request.body.clone();
+///     let _ = request.spawn_background(move || println!("Welcome mail to {}", address));
+///     "Signed up"
+/// });
+/// ```
+#[derive(Clone)]
+pub struct Tasks {
+    state: Arc<TasksState>,
+}
+
+impl Tasks {
+    pub(crate) fn new() -> Self {
+        Tasks {
+            state: Arc::new(TasksState {
+                pool: OnceLock::new(),
+                pending: Mutex::new(0),
+                idle: Condvar::new(),
+            }),
+        }
+    }
+
+    /// Queues a task to run on a background thread.
+    ///
+    /// Waits for room when [`BACKGROUND_WORKERS`] threads are busy and many more tasks
+    /// are already queued.
+    ///
+    /// # Arguments
+    ///
+    /// * `task` - The work to run.
+    pub fn spawn(&self, task: impl FnOnce() + Send + 'static) {
+        *self.state.pending.lock().unwrap() += 1;
+        // Dropped once the task returns or panics.
+        let guard = PendingGuard {
+            state: Arc::clone(&self.state),
+        };
+        let pool = self.state.pool.get_or_init(|| {
+            WorkerPool::new(
+                "task",
+                BACKGROUND_WORKERS,
+                MAX_QUEUED_TASKS,
+                |task: Task| task(),
+            )
+            // Reached from handlers, whose panics are answered with an error response.
+            .expect("failed to spawn a background task thread")
+        });
+        pool.execute(Box::new(move || {
+            let _guard = guard;
+            task();
+        }));
+    }
+
+    /// Returns how many tasks are queued or running.
+    pub fn in_flight(&self) -> usize {
+        *self.state.pending.lock().unwrap()
+    }
+
+    /// Waits for every queued and running task to finish, up to `timeout`.
+    ///
+    /// # Returns
+    ///
+    /// * `bool` - Whether every task finished in time.
+    pub(crate) fn drain(&self, timeout: Duration) -> bool {
+        let deadline = Instant::now() + timeout;
+        let mut pending = self.state.pending.lock().unwrap();
+        while *pending > 0 {
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            if remaining.is_zero() {
+                return false;
+            }
+            pending = self.state.idle.wait_timeout(pending, remaining).unwrap().0;
+        }
+        true
+    }
+}
+
+/// Keeps a task counted as pending while it is alive.
+struct PendingGuard {
+    state: Arc<TasksState>,
+}
+
+impl Drop for PendingGuard {
+    fn drop(&mut self) {
+        let mut pending = self.state.pending.lock().unwrap();
+        *pending -= 1;
+        if *pending == 0 {
+            self.state.idle.notify_all();
+        }
+    }
+}
+
+impl App {
+    /// Returns the handle running the app's background tasks, for code outside handlers
+    /// that needs to spawn them.
+    pub fn tasks(&self) -> Tasks {
+        self.tasks.clone()
+    }
+
+    /// Runs a task on the app's background threads; see [`Tasks`].
+    ///
+    /// # Arguments
+    ///
+    /// * `task` - The work to run.
+    pub fn spawn_background(&self, task: impl FnOnce() + Send + 'static) {
+        self.tasks.spawn(task);
+    }
+}
+
+impl Request {
+    /// Runs a task on the app's background threads without holding up the response;
+    /// see [`Tasks`].
+    ///
+    /// # Arguments
+    ///
+    /// * `task` - The work to run.
+    ///
+    /// # Errors
+    ///
+    /// Fails with `ErrorKind::Unsupported` when the request was not routed by an app,
+    /// such as one built by hand to call a handler directly.
+    pub fn spawn_background(&self, task: impl FnOnce() + Send + 'static) -> io::Result<()> {
+        let tasks = self.extensions.get::<Tasks>().ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::Unsupported,
+                "the request has no background tasks to spawn on",
+            )
+        })?;
+        tasks.spawn(task);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test_tasks {
+    use super::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::thread;
+
+    /// Tests that tasks keep running after one panics and that draining waits for them.
+    #[test]
+    fn test_spawn_survives_panics() {
+        let tasks = Tasks::new();
+        let done = Arc::new(AtomicUsize::new(0));
+        for index in 0..BACKGROUND_WORKERS * 2 {
+            let done = Arc::clone(&done);
+            tasks.spawn(move || {
+                thread::sleep(Duration::from_millis(20));
+                assert!(index % 2 == 0, "odd task");
+                done.fetch_add(1, Ordering::SeqCst);
+            });
+        }
+        assert!(tasks.drain(Duration::from_secs(5)));
+        assert_eq!(done.load(Ordering::SeqCst), BACKGROUND_WORKERS);
+        assert_eq!(tasks.in_flight(), 0);
+    }
+
+    /// Tests that draining gives up after the timeout.
+    #[test]
+    fn test_drain_timeout() {
+        let tasks = Tasks::new();
+        tasks.spawn(|| thread::sleep(Duration::from_millis(500)));
+        assert!(!tasks.drain(Duration::from_millis(20)));
+        assert_eq!(tasks.in_flight(), 1);
+    }
+}
@@ -0,0 +1,308 @@
+use crate::content_type::ContentType;
+use crate::http11_response::Response;
+use crate::http_error::HttpError;
+use crate::into_response::body_response;
+use std::collections::HashMap;
+use std::error::Error;
+use std::fmt;
+
+/// What [`render`] does with a placeholder naming a variable it was not given.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub enum MissingVariable {
+    /// Fail with [`TemplateError::Missing`], so a typo never goes unnoticed.
+    #[default]
+    Error,
+    /// Render the placeholder as nothing.
+    Empty,
+}
+
+/// Why a template could not be rendered.
+#[derive(Debug, Clone, PartialEq)]
+pub enum TemplateError {
+    /// A placeholder names a variable that was not given.
+    Missing(String),
+    /// A placeholder opened at this byte offset is never closed.
+    Unclosed(usize),
+    /// A placeholder at this byte offset has an empty name, or one containing
+    /// whitespace or braces.
+    InvalidName(usize),
+}
+
+impl fmt::Display for TemplateError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TemplateError::Missing(name) => write!(f, "no value for template variable {}", name),
+            TemplateError::Unclosed(offset) => {
+                write!(f, "unclosed template placeholder at byte {}", offset)
+            }
+            TemplateError::InvalidName(offset) => {
+                write!(f, "invalid template placeholder at byte {}", offset)
+            }
+        }
+    }
+}
+
+impl Error for TemplateError {}
+
+/// Converts a template error into `500 Internal Server Error`, as it is a bug of the
+/// server rather than of the request. The description, which names template internals,
+/// is logged instead of sent.
+impl From<TemplateError> for HttpError {
+    fn from(error: TemplateError) -> Self {
+        log::error!("Failed to render template: {}", error);
+        HttpError::internal()
+    }
+}
+
+impl Response {
+    /// Renders an HTML template into a `200 OK` `text/html` response; see [`render`].
+    ///
+    /// A placeholder naming a variable missing from `vars` is an error.
+    ///
+    /// # Arguments
+    ///
+    /// * `template` - The HTML, with `{{name}}` placeholders for escaped values and
+    ///   `{{{name}}}` ones for trusted HTML fragments.
+    /// * `vars` - The values of the variables.
+    ///
+    /// # Errors
+    ///
+    /// Fails when a variable is missing or a placeholder is malformed. The error
+    /// converts into an [`HttpError`], so handlers can use `?` on it.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use rustic::http11_response::Response;
+    /// use std::collections::HashMap;
+    ///
+    /// let vars = HashMap::from([("name", "<script>alert(1)</script>")]);
+    /// let response = Response::html_template("<h1>Hello, {{name}}!</h1>", &vars).unwrap();
+    /// assert_eq!(response.header("Content-Type"), Some("text/html; charset=utf-8"));
+    /// assert_eq!(
+    ///     response.response_body.unwrap().as_bytes(),
+    ///     b"<h1>Hello, &lt;script&gt;alert(1)&lt;/script&gt;!</h1>"
+    /// );
+    /// ```
+    pub fn html_template(
+        template: &str,
+        vars: &HashMap<&str, &str>,
+    ) -> Result<Response, TemplateError> {
+        Self::html_template_with(template, vars, MissingVariable::Error)
+    }
+
+    /// Renders an HTML template like [`Response::html_template`], treating missing
+    /// variables as `missing` says.
+    ///
+    /// # Errors
+    ///
+    /// Fails when a placeholder is malformed, or a variable is missing and `missing` is
+    /// [`MissingVariable::Error`].
+    pub fn html_template_with(
+        template: &str,
+        vars: &HashMap<&str, &str>,
+        missing: MissingVariable,
+    ) -> Result<Response, TemplateError> {
+        let html = render(template, vars, missing)?;
+        Ok(body_response(200, ContentType::TEXT_HTML_UTF8, html))
+    }
+}
+
+/// Substitutes the placeholders of an HTML template.
+///
+/// `{{name}}` is replaced by the value of `name` escaped with [`escape_html`], which
+/// makes it safe in element content and in quoted attribute values. `{{{name}}}` inserts
+/// the value as is, for HTML that is already escaped or comes from a trusted source.
+/// Whitespace around names is ignored. Values are never scanned for placeholders
+/// themselves, and the rest of the template is copied as written.
+///
+/// This is not a template language: there are no loops, conditionals or partials, and
+/// escaping does not make values safe inside `<script>` or `<style>` elements, unquoted
+/// attributes or URLs such as `href`, where a `javascript:` value stays dangerous.
+///
+/// # Arguments
+///
+/// * `template` - The HTML with placeholders.
+/// * `vars` - The values of the variables.
+/// * `missing` - What to do with placeholders naming a variable not in `vars`.
+///
+/// # Returns
+///
+/// * `Result<String, TemplateError>` - The rendered HTML, or why it could not be rendered.
+///
+/// # Examples
+///
+/// ```
+/// use rustic::template::{render, MissingVariable, TemplateError};
+/// use std::collections::HashMap;
+///
+/// let vars = HashMap::from([("title", "Fish & Chips"), ("menu", "<b>Today</b>")]);
+/// let html = render("<h1>{{ title }}</h1>{{{menu}}}", &vars, MissingVariable::Error);
+/// assert_eq!(html.unwrap(), "<h1>Fish &amp; Chips</h1><b>Today</b>");
+/// assert_eq!(
+///     render("{{price}}", &vars, MissingVariable::Error),
+///     Err(TemplateError::Missing("price".to_string()))
+/// );
+/// assert_eq!(render("[{{price}}]", &vars, MissingVariable::Empty).unwrap(), "[]");
+/// ```
+pub fn render(
+    template: &str,
+    vars: &HashMap<&str, &str>,
+    missing: MissingVariable,
+) -> Result<String, TemplateError> {
+    let mut html = String::with_capacity(template.len());
+    let mut rest = template;
+    while let Some(start) = rest.find("{{") {
+        let offset = template.len() - rest.len() + start;
+        html.push_str(&rest[..start]);
+        let after = &rest[start..];
+        let (raw, open, close) = if after.starts_with("{{{") {
+            (true, 3, "}}}")
+        } else {
+            (false, 2, "}}")
+        };
+        let end = after[open..]
+            .find(close)
+            .ok_or(TemplateError::Unclosed(offset))?;
+        let name = after[open..open + end].trim();
+        if name.is_empty() || name.contains(|c: char| c.is_whitespace() || c == '{' || c == '}') {
+            return Err(TemplateError::InvalidName(offset));
+        }
+        match (vars.get(name), missing) {
+            (Some(value), _) if raw => html.push_str(value),
+            (Some(value), _) => push_escaped(&mut html, value),
+            (None, MissingVariable::Empty) => {}
+            (None, MissingVariable::Error) => return Err(TemplateError::Missing(name.to_string())),
+        }
+        rest = &after[open + end + close.len()..];
+    }
+    html.push_str(rest);
+    Ok(html)
+}
+
+/// Escapes the characters that are significant in HTML text and attribute values:
+/// `&`, `<`, `>`, `"` and `'`.
+///
+/// # Examples
+///
+/// ```
+/// use rustic::template::escape_html;
+/// assert_eq!(
+///     escape_html("<a href=\"x\" title='y'>&</a>"),
+///     "&lt;a href=&quot;x&quot; title=&#39;y&#39;&gt;&amp;&lt;/a&gt;"
+/// );
+/// ```
+pub fn escape_html(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    push_escaped(&mut escaped, text);
+    escaped
+}
+
+/// Appends text to HTML, escaped with [`escape_html`].
+fn push_escaped(html: &mut String, text: &str) {
+    for c in text.chars() {
+        match c {
+            '&' => html.push_str("&amp;"),
+            '<' => html.push_str("&lt;"),
+            '>' => html.push_str("&gt;"),
+            '"' => html.push_str("&quot;"),
+            '\'' => html.push_str("&#39;"),
+            _ => html.push(c),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test_template {
+    use super::*;
+
+    /// Renders a template with a single `value` variable, failing on missing ones.
+    fn render_value(template: &str, value: &str) -> String {
+        render(
+            template,
+            &HashMap::from([("value", value)]),
+            MissingVariable::Error,
+        )
+        .unwrap()
+    }
+
+    /// Tests that script-injection payloads come out inert in element content and
+    /// quoted attributes.
+    #[test]
+    fn test_injection_payloads() {
+        let payloads = [
+            "<script>alert(1)</script>",
+            "<img src=x onerror=alert(1)>",
+            "\"><script>alert(1)</script>",
+            "' onmouseover='alert(1)",
+            "\" autofocus onfocus=\"alert(1)",
+            "</textarea><svg onload=alert(1)>",
+            "<!--<script>-->",
+            "&lt;script&gt;",
+            "<<script>script>",
+            "<scr\u{0}ipt>",
+        ];
+        for payload in payloads {
+            for template in [
+                "<p>{{value}}</p>",
+                "<a title=\"{{value}}\">x</a>",
+                "<a title='{{value}}'>x</a>",
+                "<textarea>{{ value }}</textarea>",
+            ] {
+                let html = render_value(template, payload);
+                let escaped = escape_html(payload);
+                assert!(!escaped.contains(['<', '>', '"', '\'']), "{:?}", escaped);
+                let placeholder = if template.contains("{{ value }}") {
+                    "{{ value }}"
+                } else {
+                    "{{value}}"
+                };
+                assert_eq!(html, template.replace(placeholder, &escaped));
+                assert_eq!(html.matches('<').count(), template.matches('<').count());
+                assert_eq!(html.matches('"').count(), template.matches('"').count());
+                assert_eq!(html.matches('\'').count(), template.matches('\'').count());
+            }
+        }
+        assert_eq!(
+            render_value("<p>{{value}}</p>", "\"><script>alert('x')</script>&amp;"),
+            "<p>&quot;&gt;&lt;script&gt;alert(&#39;x&#39;)&lt;/script&gt;&amp;amp;</p>"
+        );
+    }
+
+    /// Tests that raw placeholders insert values as is, while the template itself and
+    /// values containing placeholders are never expanded again.
+    #[test]
+    fn test_raw_and_nested_placeholders() {
+        let vars = HashMap::from([("fragment", "<b>{{secret}}</b>"), ("secret", "s3cret")]);
+        assert_eq!(
+            render("{{{fragment}}}|{{fragment}}", &vars, MissingVariable::Error).unwrap(),
+            "<b>{{secret}}</b>|&lt;b&gt;{{secret}}&lt;/b&gt;"
+        );
+        assert_eq!(render_value("{ {value} }", "x"), "{ {value} }");
+        assert_eq!(render_value("é{{value}}ü", "ß"), "éßü");
+        assert_eq!(render_value("", "x"), "");
+    }
+
+    /// Tests that missing variables fail or render empty per the flag, and that
+    /// malformed placeholders always fail with their position.
+    #[test]
+    fn test_template_errors() {
+        let vars = HashMap::from([("value", "x")]);
+        let strict = |template| render(template, &vars, MissingVariable::Error);
+        assert_eq!(
+            strict("a {{other}} b"),
+            Err(TemplateError::Missing("other".to_string()))
+        );
+        assert_eq!(
+            render("a {{other}}{{{other}}} b", &vars, MissingVariable::Empty).unwrap(),
+            "a  b"
+        );
+        assert_eq!(strict("ab {{value"), Err(TemplateError::Unclosed(3)));
+        assert_eq!(strict("{{{value}}"), Err(TemplateError::Unclosed(0)));
+        assert_eq!(strict("x{{ }}"), Err(TemplateError::InvalidName(1)));
+        assert_eq!(strict("{{two words}}"), Err(TemplateError::InvalidName(0)));
+        assert_eq!(strict("{{{{value}}}}"), Err(TemplateError::InvalidName(0)));
+        let error: HttpError = TemplateError::Unclosed(0).into();
+        assert_eq!(error.status, 500);
+    }
+}
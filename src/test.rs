@@ -0,0 +1,276 @@
+use crate::app::{process_request, App, ServerConfig};
+use crate::connection::read_head;
+use crate::header_map::HeaderMap;
+use crate::http11_response::format_header_lines;
+use crate::parse_headers::{parse_response_head, RequestType};
+use crate::parse_url::percent_encode;
+use std::io::{Cursor, Read};
+
+/// Sends requests to an [`App`] in memory, without binding a port.
+///
+/// Each request is serialized as it would be sent over the wire, then parsed, routed,
+/// passed through the middleware and handled by the same code that serves connections
+/// in [`crate::app::run`]. The serialized response is parsed back into a
+/// [`TestResponse`], so what a handler sets is checked exactly as a client would see it.
+///
+/// # Examples
+///
+/// ```
+/// use rustic::app::App;
+/// use rustic::test::TestClient;
+///
+/// let mut application = App::new();
+/// application.get("hello", |request| {
+///     let name = request.query_param("name").value();
+///     format!("Hello, {}!", name.as_deref().unwrap_or("world"))
+/// });
+///
+/// let client = TestClient::new(application);
+/// let response = client.get("/hello").query("name", "Ferris").send();
+/// assert_eq!(response.status, 200);
+/// assert_eq!(response.text(), "Hello, Ferris!");
+/// assert_eq!(client.get("/missing").send().status, 404);
+/// ```
+pub struct TestClient {
+    app: App,
+    config: ServerConfig,
+}
+
+impl TestClient {
+    /// Creates a client for the app, served with the default [`ServerConfig`].
+    pub fn new(app: App) -> Self {
+        Self::with_config(app, ServerConfig::new())
+    }
+
+    /// Creates a client for the app, served with the given settings, such as the body
+    /// size limit or request decompression.
+    pub fn with_config(app: App, config: ServerConfig) -> Self {
+        TestClient { app, config }
+    }
+
+    /// Starts a request with the given method and path.
+    pub fn request(&self, method: RequestType, path: &str) -> TestRequest<'_> {
+        TestRequest {
+            client: self,
+            method,
+            path: path.to_string(),
+            query: Vec::new(),
+            headers: HeaderMap::new(),
+            body: Vec::new(),
+        }
+    }
+
+    /// Starts a `GET` request.
+    pub fn get(&self, path: &str) -> TestRequest<'_> {
+        self.request(RequestType::GET, path)
+    }
+
+    /// Starts a `HEAD` request.
+    pub fn head(&self, path: &str) -> TestRequest<'_> {
+        self.request(RequestType::HEAD, path)
+    }
+
+    /// Starts a `POST` request.
+    pub fn post(&self, path: &str) -> TestRequest<'_> {
+        self.request(RequestType::POST, path)
+    }
+
+    /// Starts a `PUT` request.
+    pub fn put(&self, path: &str) -> TestRequest<'_> {
+        self.request(RequestType::PUT, path)
+    }
+
+    /// Starts a `PATCH` request.
+    pub fn patch(&self, path: &str) -> TestRequest<'_> {
+        self.request(RequestType::PATCH, path)
+    }
+
+    /// Starts a `DELETE` request.
+    pub fn delete(&self, path: &str) -> TestRequest<'_> {
+        self.request(RequestType::DELETE, path)
+    }
+}
+
+/// A request being built by a [`TestClient`].
+pub struct TestRequest<'a> {
+    client: &'a TestClient,
+    method: RequestType,
+    path: String,
+    query: Vec<(String, String)>,
+    headers: HeaderMap,
+    body: Vec<u8>,
+}
+
+impl TestRequest<'_> {
+    /// Sets the request method.
+    pub fn method(mut self, method: RequestType) -> Self {
+        self.method = method;
+        self
+    }
+
+    /// Sets the request path, which may carry a query string of its own.
+    pub fn path(mut self, path: &str) -> Self {
+        self.path = path.to_string();
+        self
+    }
+
+    /// Adds a query parameter, percent-encoding its name and value.
+    pub fn query(mut self, name: &str, value: &str) -> Self {
+        self.query.push((name.to_string(), value.to_string()));
+        self
+    }
+
+    /// Sets a request header, replacing any previous value.
+    pub fn header(mut self, name: &str, value: &str) -> Self {
+        self.headers.insert(name, value);
+        self
+    }
+
+    /// Sets the request body.
+    pub fn body(mut self, body: impl Into<Vec<u8>>) -> Self {
+        self.body = body.into();
+        self
+    }
+
+    /// Sends the request to the app and returns its response.
+    ///
+    /// `Host: localhost` is added unless set, and `Content-Length` is added for bodies
+    /// and bodied methods unless a framing header is set, as [`crate::client`] does.
+    /// A request the server cannot parse at all gets no response, which is reported as
+    /// a panic since it means the test built a malformed request.
+    ///
+    /// # Returns
+    ///
+    /// * `TestResponse` - The response, as the client would receive it.
+    pub fn send(self) -> TestResponse {
+        let mut target = self.path;
+        if !target.starts_with('/') {
+            target.insert(0, '/');
+        }
+        for (index, (name, value)) in self.query.iter().enumerate() {
+            let separator = if index == 0 && !target.contains('?') {
+                '?'
+            } else {
+                '&'
+            };
+            target.push(separator);
+            target.push_str(&percent_encode(name));
+            target.push('=');
+            target.push_str(&percent_encode(value));
+        }
+
+        let mut headers = self.headers;
+        if !headers.contains_key("Host") {
+            headers.insert("Host", "localhost");
+        }
+        let framed =
+            headers.contains_key("Content-Length") || headers.contains_key("Transfer-Encoding");
+        if !framed && (self.method.requires_length() || !self.body.is_empty()) {
+            headers.insert("Content-Length", self.body.len().to_string());
+        }
+
+        let mut message = format!("{} {} HTTP/1.1\r\n", self.method.as_str(), target).into_bytes();
+        message.extend_from_slice(format_header_lines(&headers).as_bytes());
+        message.extend_from_slice(&self.body);
+
+        let client = self.client;
+        let mut reader = Cursor::new(message);
+        let (response, _, slow) =
+            process_request(&client.app, &client.config, &mut reader, None, true)
+                .expect("the request could not be parsed");
+        let response = response
+            .into_bytes()
+            .expect("the file of the response body could not be read");
+        // Reading the response stands for writing it to a connection.
+        if let Some(slow) = slow {
+            slow.written();
+        }
+        TestResponse::parse(response)
+    }
+}
+
+/// A response received through a [`TestClient`].
+#[derive(Debug, Clone)]
+pub struct TestResponse {
+    pub status: u16,
+    pub headers: HeaderMap,
+    pub body: Vec<u8>,
+}
+
+impl TestResponse {
+    /// Parses the serialized response the server produced.
+    fn parse(response: Vec<u8>) -> Self {
+        let mut reader = Cursor::new(response);
+        let head = read_head(&mut reader).expect("reading from memory cannot fail");
+        let (status, headers) = parse_response_head(&head).expect("the server wrote a status line");
+        // The server always frames its responses, so the rest is the body.
+        let mut body = Vec::new();
+        let _ = reader.read_to_end(&mut body);
+        TestResponse {
+            status,
+            headers,
+            body,
+        }
+    }
+
+    /// Looks up a response header by name, ignoring ASCII case.
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers.get(name)
+    }
+
+    /// Returns the body as text, replacing invalid UTF-8 sequences.
+    pub fn text(&self) -> String {
+        String::from_utf8_lossy(&self.body).into_owned()
+    }
+}
+
+#[cfg(test)]
+mod test_test_client {
+    use super::*;
+    use crate::app::Request;
+
+    fn echo_app() -> App {
+        let mut application = App::new();
+        application.add_endpoint("echo", RequestType::POST, |request: Request| {
+            format!(
+                "{} {} [{}]",
+                request.url,
+                request.header("X-Trace").unwrap_or("-"),
+                request.body
+            )
+        });
+        application
+    }
+
+    /// Tests that the path, query, headers and body reach the handler as sent.
+    #[test]
+    fn test_send() {
+        let client = TestClient::new(echo_app());
+        let response = client
+            .post("echo")
+            .query("q", "a b&c")
+            .header("X-Trace", "7")
+            .body("hello")
+            .send();
+        assert_eq!(response.status, 200);
+        assert_eq!(response.text(), "/echo?q=a%20b%26c 7 [hello]");
+        let length = response.body.len().to_string();
+        assert_eq!(response.header("content-length"), Some(length.as_str()));
+    }
+
+    /// Tests that errors are produced as the server would produce them.
+    #[test]
+    fn test_send_errors() {
+        let client = TestClient::new(echo_app());
+        assert_eq!(client.get("/echo").send().status, 405);
+        assert_eq!(client.get("/nowhere").send().status, 404);
+        let response = client
+            .request(RequestType::PUT, "/")
+            .method(RequestType::POST)
+            .path("/echo")
+            .header("Transfer-Encoding", "chunked")
+            .body("0\r\n\r\n")
+            .send();
+        assert_eq!(response.text(), "/echo - []");
+    }
+}
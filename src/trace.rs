@@ -0,0 +1,107 @@
+use crate::app::{App, Request};
+use crate::http11_response::Response;
+use crate::into_response::body_response;
+
+/// Headers left out of `TRACE` echoes, as they carry credentials a cross-site script
+/// could otherwise read back.
+const SENSITIVE_HEADERS: [&str; 4] = [
+    "authorization",
+    "cookie",
+    "proxy-authorization",
+    "x-csrf-token",
+];
+
+/// The largest `TRACE` echo sent, in bytes. Headers that would go past it are left out.
+pub const MAX_TRACE_SIZE: usize = 8 * 1024;
+
+impl App {
+    /// Answers `TRACE` requests by echoing the request line and headers they arrived
+    /// with, for diagnosing what intermediaries change on the way.
+    ///
+    /// TRACE is off by default, since echoing requests has been used in cross-site
+    /// tracing attacks to read headers a script has no access to. The echo is sent with
+    /// `Content-Type: message/http` and leaves out `Authorization`, `Cookie` and other
+    /// credential headers. It is capped at [`MAX_TRACE_SIZE`] bytes. Middleware runs
+    /// for `TRACE` requests as for any other.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use rustic::app::App;
+    ///
+    /// let mut application = App::new();
+    /// application.enable_trace();
+    /// ```
+    pub fn enable_trace(&mut self) {
+        self.trace_enabled = true;
+    }
+}
+
+/// Builds the `200 OK` echo of a `TRACE` request.
+pub(crate) fn trace_response(request: &Request) -> Response {
+    let mut echo = format!("TRACE {} HTTP/1.1\r\n", request.url);
+    // Sorted, as the order the headers arrived in is not kept.
+    let mut headers: Vec<_> = request
+        .headers
+        .iter()
+        .filter(|(name, _)| !SENSITIVE_HEADERS.contains(&name.to_ascii_lowercase().as_str()))
+        .collect();
+    headers.sort();
+    for (name, value) in headers {
+        let line = format!("{}: {}\r\n", name, value);
+        if echo.len() + line.len() + 2 <= MAX_TRACE_SIZE {
+            echo.push_str(&line);
+        }
+    }
+    echo.push_str("\r\n");
+    body_response(200, "message/http", echo)
+}
+
+#[cfg(test)]
+mod test_trace {
+    use super::*;
+    use crate::parse_headers::RequestType;
+
+    fn request(headers: &[(&str, &str)]) -> Request {
+        Request {
+            method: RequestType::TRACE,
+            path: "debug".to_string(),
+            url: "/debug?x=1".to_string(),
+            headers: headers
+                .iter()
+                .map(|(name, value)| (name.to_string(), value.to_string()))
+                .collect(),
+            body: String::new(),
+            ..Request::default()
+        }
+    }
+
+    /// Tests that the echo reconstructs the request without its credentials.
+    #[test]
+    fn test_trace_response() {
+        let response = trace_response(&request(&[
+            ("Host", "example.com"),
+            ("Authorization", "Bearer secret"),
+            ("cookie", "session=secret"),
+            ("Accept", "*/*"),
+        ]));
+        assert_eq!(response.header("Content-Type"), Some("message/http"));
+        assert_eq!(
+            response.response_body.unwrap().as_bytes(),
+            b"TRACE /debug?x=1 HTTP/1.1\r\nAccept: */*\r\nHost: example.com\r\n\r\n"
+        );
+    }
+
+    /// Tests that headers past the size limit are left out.
+    #[test]
+    fn test_trace_response_limit() {
+        let long = "a".repeat(MAX_TRACE_SIZE / 2);
+        let response = trace_response(&request(&[("A", &long), ("B", &long), ("C", "c")]));
+        let echo = response.response_body.unwrap();
+        assert!(echo.len() <= MAX_TRACE_SIZE);
+        let echo = String::from_utf8(echo.as_bytes().to_vec()).unwrap();
+        assert!(echo.contains("\r\nA: "));
+        assert!(!echo.contains("\r\nB: "));
+        assert!(echo.ends_with("C: c\r\n\r\n"));
+    }
+}
@@ -0,0 +1,302 @@
+use crate::app::Request;
+use std::io::{self, BufRead, Read, Write};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+/// The bytes a request took on the wire, as counted by the server while reading it.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct RequestBytes {
+    /// The request line and headers, with their line endings and the empty line that
+    /// ends them.
+    pub head: usize,
+    /// The body as received, with its chunked framing and trailers and before any
+    /// content coding is undone.
+    pub body: usize,
+    /// The body as the handler gets it, once chunked framing and content codings are
+    /// undone.
+    pub decoded_body: usize,
+}
+
+impl RequestBytes {
+    /// Returns the bytes read from the connection for the request.
+    pub fn total(&self) -> usize {
+        self.head + self.body
+    }
+}
+
+/// The bytes a request and its response took on the wire, passed to the hooks added
+/// with [`Request::after_response`].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ByteCounts {
+    /// What the request took.
+    pub request: RequestBytes,
+    /// The status line and headers of the response, with their line endings and the
+    /// empty line that ends them.
+    pub response_head: usize,
+    /// The body of the response as sent, so after any content coding such as a
+    /// precompressed static file.
+    pub response_body: usize,
+}
+
+impl ByteCounts {
+    /// Returns the bytes read from the connection for the request.
+    pub fn read(&self) -> usize {
+        self.request.total()
+    }
+
+    /// Returns the bytes of the response written to the connection.
+    pub fn written(&self) -> usize {
+        self.response_head + self.response_body
+    }
+}
+
+/// What the framework learns of an exchange once its response is serialized.
+pub(crate) struct Exchange {
+    pub(crate) bytes: ByteCounts,
+    /// How long the handler took, middleware included.
+    pub(crate) handled: Duration,
+}
+
+/// A callback run once the response to a request is serialized.
+type ResponseHook = Box<dyn FnOnce(&Exchange) + Send>;
+
+/// What the server counted of a request so far, and the hooks waiting for its response,
+/// stored in the request's extensions.
+#[derive(Clone)]
+pub(crate) struct Traffic {
+    request: RequestBytes,
+    hooks: Arc<Mutex<Vec<ResponseHook>>>,
+}
+
+impl Traffic {
+    /// Starts waiting for the response to a request that took `request` bytes.
+    pub(crate) fn new(request: RequestBytes) -> Self {
+        Traffic {
+            request,
+            hooks: Arc::default(),
+        }
+    }
+
+    /// Queues a hook to run once the response is serialized.
+    pub(crate) fn push(&self, hook: ResponseHook) {
+        self.hooks.lock().unwrap().push(hook);
+    }
+
+    /// Runs the queued hooks, in the order they were added.
+    pub(crate) fn finish(&self, exchange: &Exchange) {
+        let hooks = std::mem::take(&mut *self.hooks.lock().unwrap());
+        for hook in hooks {
+            hook(exchange);
+        }
+    }
+}
+
+impl Request {
+    /// Returns the bytes the request took on the wire, when it was read by the server
+    /// or a [`TestClient`](crate::test::TestClient).
+    ///
+    /// A [streamed body](crate::app::EndpointConfig::stream_body) is only counted
+    /// once the response is ready, in the [`ByteCounts`] passed to
+    /// [`Request::after_response`] hooks, so it reads as empty here.
+    pub fn request_bytes(&self) -> Option<RequestBytes> {
+        self.extensions
+            .get::<Traffic>()
+            .map(|traffic| traffic.request)
+    }
+
+    /// Runs `hook` with the bytes the request and its response took, once the response
+    /// has been serialized, just before it is written to the connection.
+    ///
+    /// Hooks run in the order they were added, after every middleware has returned, so
+    /// that what middleware adds to the response is counted. A request that was not read
+    /// by the server or a [`TestClient`](crate::test::TestClient), such as one built by
+    /// hand, never runs its hooks.
+    ///
+    /// # Arguments
+    ///
+    /// * `hook` - The callback, for example recording the traffic for billing.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use rustic::app::App;
+    /// use rustic::test::TestClient;
+    /// use std::sync::atomic::{AtomicUsize, Ordering};
+    /// use std::sync::Arc;
+    ///
+    /// let written = Arc::new(AtomicUsize::new(0));
+    /// let total = Arc::clone(&written);
+    /// let mut application = App::new();
+    /// application.get("report", move |request| {
+    ///     let total = Arc::clone(&total);
+    ///     request.after_response(move |bytes| {
+    ///         total.fetch_add(bytes.written(), Ordering::Relaxed);
+    ///     });
+    ///     "Quarterly report"
+    /// });
+    ///
+    /// let client = TestClient::new(application);
+    /// client.get("/report").send();
+    /// assert!(written.load(Ordering::Relaxed) > "Quarterly report".len());
+    /// ```
+    pub fn after_response(&self, hook: impl FnOnce(&ByteCounts) + Send + 'static) {
+        if let Some(traffic) = self.extensions.get::<Traffic>() {
+            traffic.push(Box::new(move |exchange: &Exchange| hook(&exchange.bytes)));
+        }
+    }
+}
+
+/// A buffered reader counting the bytes consumed from it.
+pub(crate) struct CountingReader<R> {
+    inner: R,
+    count: usize,
+}
+
+impl<R> CountingReader<R> {
+    /// Starts counting the bytes consumed from `inner`.
+    pub(crate) fn new(inner: R) -> Self {
+        CountingReader { inner, count: 0 }
+    }
+
+    /// Returns how many bytes have been consumed.
+    pub(crate) fn count(&self) -> usize {
+        self.count
+    }
+}
+
+impl<R: BufRead> Read for CountingReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let read = self.inner.read(buf)?;
+        self.count += read;
+        Ok(read)
+    }
+}
+
+impl<R: BufRead> BufRead for CountingReader<R> {
+    fn fill_buf(&mut self) -> io::Result<&[u8]> {
+        self.inner.fill_buf()
+    }
+
+    fn consume(&mut self, amount: usize) {
+        self.count += amount;
+        self.inner.consume(amount);
+    }
+}
+
+/// A writer counting the bytes written through it, which are those the writes it
+/// passes on accepted, even when a later write fails.
+pub(crate) struct CountingWriter<W> {
+    inner: W,
+    count: usize,
+}
+
+impl<W> CountingWriter<W> {
+    /// Starts counting the bytes written to `inner`.
+    pub(crate) fn new(inner: W) -> Self {
+        CountingWriter { inner, count: 0 }
+    }
+
+    /// Returns how many bytes have been written.
+    pub(crate) fn count(&self) -> usize {
+        self.count
+    }
+}
+
+impl<W: Write> Write for CountingWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let written = self.inner.write(buf)?;
+        self.count += written;
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+#[cfg(feature = "async")]
+impl<R: tokio::io::AsyncBufRead + Unpin> tokio::io::AsyncRead for CountingReader<R> {
+    fn poll_read(
+        self: std::pin::Pin<&mut Self>,
+        cx: &mut std::task::Context<'_>,
+        buf: &mut tokio::io::ReadBuf<'_>,
+    ) -> std::task::Poll<io::Result<()>> {
+        let this = self.get_mut();
+        let before = buf.filled().len();
+        let polled = std::pin::Pin::new(&mut this.inner).poll_read(cx, buf);
+        this.count += buf.filled().len() - before;
+        polled
+    }
+}
+
+#[cfg(feature = "async")]
+impl<R: tokio::io::AsyncBufRead + Unpin> tokio::io::AsyncBufRead for CountingReader<R> {
+    fn poll_fill_buf(
+        self: std::pin::Pin<&mut Self>,
+        cx: &mut std::task::Context<'_>,
+    ) -> std::task::Poll<io::Result<&[u8]>> {
+        std::pin::Pin::new(&mut self.get_mut().inner).poll_fill_buf(cx)
+    }
+
+    fn consume(self: std::pin::Pin<&mut Self>, amount: usize) {
+        let this = self.get_mut();
+        this.count += amount;
+        std::pin::Pin::new(&mut this.inner).consume(amount);
+    }
+}
+
+#[cfg(test)]
+mod test_traffic {
+    use super::*;
+
+    /// Tests that reads and consumed buffer bytes are both counted, and nothing more.
+    #[test]
+    fn test_counting_reader() {
+        let mut reader = CountingReader::new(&b"first line\nsecond line\nrest"[..]);
+        let mut line = String::new();
+        reader.read_line(&mut line).unwrap();
+        assert_eq!(reader.count(), 11);
+        let mut word = [0; 6];
+        reader.read_exact(&mut word).unwrap();
+        assert_eq!(&word, b"second");
+        assert_eq!(reader.fill_buf().unwrap(), b" line\nrest");
+        assert_eq!(reader.count(), 17);
+        let mut rest = Vec::new();
+        reader.read_to_end(&mut rest).unwrap();
+        assert_eq!(reader.count(), 27);
+    }
+
+    /// Tests that hooks run once, in order, with the counts of the exchange.
+    #[test]
+    fn test_response_hooks() {
+        let traffic = Traffic::new(RequestBytes {
+            head: 40,
+            body: 12,
+            decoded_body: 5,
+        });
+        let seen = Arc::new(Mutex::new(Vec::new()));
+        for name in ["first", "second"] {
+            let seen = Arc::clone(&seen);
+            traffic.push(Box::new(move |exchange: &Exchange| {
+                let bytes = exchange.bytes;
+                seen.lock()
+                    .unwrap()
+                    .push((name, bytes.read(), bytes.written()));
+            }));
+        }
+        let exchange = Exchange {
+            bytes: ByteCounts {
+                request: traffic.request,
+                response_head: 90,
+                response_body: 10,
+            },
+            handled: Duration::from_millis(3),
+        };
+        traffic.finish(&exchange);
+        traffic.finish(&exchange);
+        assert_eq!(
+            *seen.lock().unwrap(),
+            [("first", 52, 100), ("second", 52, 100)]
+        );
+    }
+}
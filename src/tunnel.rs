@@ -0,0 +1,119 @@
+use crate::app::{App, Request};
+use crate::client::connect;
+use crate::hijack::Hijacked;
+use crate::http11_response::Response;
+use crate::into_response::text_response;
+use crate::parse_url::parse_authority;
+use std::io::{self, Write};
+use std::net::{Shutdown, TcpStream};
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+/// Decides whether a `CONNECT` request may open a tunnel to a host and port.
+pub(crate) type ConnectHandler = Arc<dyn Fn(&Request, &str, u16) -> bool + Send + Sync>;
+
+/// How long to wait for the upstream to accept before answering `502 Bad Gateway`.
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(30);
+
+impl App {
+    /// Serves `CONNECT` requests as a tunneling proxy, such as the one HTTPS clients go
+    /// through to reach servers behind a firewall.
+    ///
+    /// `handler` receives each request with the host and port of its authority-form
+    /// target, like `example.com:443`, and approves the tunnel by returning `true`.
+    /// Denied targets are answered with `403 Forbidden` and malformed ones with
+    /// `400 Bad Request`. For approved targets, the server connects to the upstream,
+    /// answers `200 Connection Established`, and copies bytes both ways until either
+    /// side closes. An upstream that cannot be reached is answered with
+    /// `502 Bad Gateway`.
+    ///
+    /// Middleware runs for `CONNECT` requests as for any other. Without a handler, they
+    /// are routed like other requests, which usually answers `404 Not Found`. Tunnels
+    /// only work under [`crate::app::run`] and its variants, as they take the connection
+    /// over with [`Request::hijack`].
+    ///
+    /// # Arguments
+    ///
+    /// * `handler` - Whether to open a tunnel for the request, host and port.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use rustic::app::App;
+    ///
+    /// let mut application = App::new();
+    /// application.on_connect(|_, host, port| port == 443 && host.ends_with(".example.com"));
+    /// ```
+    pub fn on_connect<F>(&mut self, handler: F)
+    where
+        F: Fn(&Request, &str, u16) -> bool + Send + Sync + 'static,
+    {
+        self.connect_handler = Some(Arc::new(handler));
+    }
+
+    /// Answers a `CONNECT` request, opening the tunnel when `handler` approves it.
+    pub(crate) fn connect_tunnel(&self, handler: &ConnectHandler, request: Request) -> Response {
+        let Some((host, port)) = parse_authority(&request.url) else {
+            log::debug!("Refused CONNECT to malformed target {}", request.url);
+            return self.error_response(400, Some(request));
+        };
+        if !handler(&request, host, port) {
+            log::debug!("Denied CONNECT to {}", request.url);
+            return self.error_response(403, Some(request));
+        }
+
+        let target = (host.to_string(), port);
+        let mut bad_gateway = self.error_response(502, None);
+        bad_gateway.headers.insert("Connection", "close");
+        let bad_gateway = bad_gateway.to_bytes();
+        match request.hijack(move |connection| tunnel(connection, target, &bad_gateway)) {
+            // Never sent, as the tunnel writes its own response.
+            Ok(()) => text_response(200, ""),
+            Err(err) => {
+                log::warn!("Cannot tunnel CONNECT to {}: {}", request.url, err);
+                self.error_response(501, Some(request))
+            }
+        }
+    }
+}
+
+/// Connects to the upstream and copies bytes between it and the client until either
+/// side closes.
+fn tunnel(connection: Hijacked, (host, port): (String, u16), bad_gateway: &[u8]) {
+    let (mut client, buffered) = connection.into_parts();
+    let mut upstream = match connect((host.as_str(), port), CONNECT_TIMEOUT) {
+        Ok(upstream) => upstream,
+        Err(err) => {
+            log::warn!("Failed to connect to {}:{}: {}", host, port, err);
+            let _ = client.write_all(bad_gateway);
+            return;
+        }
+    };
+    if client
+        .write_all(b"HTTP/1.1 200 Connection Established\r\n\r\n")
+        .and_then(|()| upstream.write_all(&buffered))
+        .is_err()
+    {
+        return;
+    }
+    let (Ok(client_reader), Ok(upstream_writer)) = (client.try_clone(), upstream.try_clone())
+    else {
+        return;
+    };
+    let client_control = client_reader.try_clone();
+    let upload = thread::spawn(move || splice(client_reader, upstream_writer));
+    splice(upstream, client);
+    // Once the upstream is done, stop waiting on the client so the tunnel ends.
+    if let Ok(client) = client_control {
+        let _ = client.shutdown(Shutdown::Read);
+    }
+    let _ = upload.join();
+}
+
+/// Copies bytes from one socket to another, then tells the receiving side no more are
+/// coming.
+fn splice(mut from: TcpStream, mut to: TcpStream) {
+    let _ = io::copy(&mut from, &mut to);
+    let _ = to.shutdown(Shutdown::Write);
+}
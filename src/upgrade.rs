@@ -0,0 +1,195 @@
+use crate::app::Request;
+use crate::header_map::HeaderMap;
+use crate::hijack::{HijackSlot, Hijacked, Takeover};
+use crate::http11_response::{reason_phrase, Response};
+use crate::into_response::text_response;
+
+impl Request {
+    /// Returns the protocols the client offers to switch to, in its order of
+    /// preference, as listed by `Upgrade` when `Connection` includes `upgrade`.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use rustic::app::App;
+    /// use rustic::test::TestClient;
+    ///
+    /// let mut application = App::new();
+    /// application.get("offers", |request| request.upgrade_protocols().join(" then "));
+    /// let client = TestClient::new(application);
+    /// let response = client
+    ///     .get("/offers")
+    ///     .header("Connection", "keep-alive, Upgrade")
+    ///     .header("Upgrade", "chat/2, chat")
+    ///     .send();
+    /// assert_eq!(response.text(), "chat/2 then chat");
+    /// ```
+    pub fn upgrade_protocols(&self) -> Vec<&str> {
+        let asks_upgrade = self.header("Connection").is_some_and(|value| {
+            value
+                .split(',')
+                .any(|token| token.trim().eq_ignore_ascii_case("upgrade"))
+        });
+        match self.header("Upgrade") {
+            Some(value) if asks_upgrade => value
+                .split(',')
+                .map(str::trim)
+                .filter(|protocol| !protocol.is_empty())
+                .collect(),
+            _ => Vec::new(),
+        }
+    }
+
+    /// Switches the connection to `protocol` once the handler returns
+    /// [`Response::switching_protocols`], handing it to `handler` right after the `101`
+    /// response is written.
+    ///
+    /// `handler` gets the socket along with the bytes the client sent after the
+    /// request, which often arrive with it and are read from the [`Hijacked`] before the
+    /// socket. It runs on the worker thread that served the request, and the connection
+    /// is closed once `handler` drops it. A handler returning any other status keeps
+    /// the connection on HTTP and `handler` is dropped.
+    ///
+    /// # Arguments
+    ///
+    /// * `protocol` - The protocol the endpoint speaks, matched against those the client
+    ///   offers without regard to case.
+    /// * `handler` - What to do with the connection once it speaks `protocol`.
+    ///
+    /// # Errors
+    ///
+    /// Fails with the response to answer instead:
+    ///
+    /// * `426 Upgrade Required`, naming `protocol`, when the client does not offer it or
+    ///   sent an HTTP/1.0 request, which cannot be upgraded.
+    /// * `400 Bad Request` when the request has a body, which would leave the new
+    ///   protocol starting after bytes the handler may or may not have read.
+    /// * `501 Not Implemented` when the connection cannot be taken over, such as one
+    ///   from a [`crate::test::TestClient`] or served by the `async` server.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use rustic::app::App;
+    /// use rustic::header_map::HeaderMap;
+    /// use rustic::http11_response::Response;
+    /// use std::io::{BufRead, BufReader, Write};
+    ///
+    /// let mut application = App::new();
+    /// application.get("echo", |request| {
+    ///     request.on_upgrade("line-echo", |connection| {
+    ///         let mut writer = match connection.stream().try_clone() {
+    ///             Ok(stream) => stream,
+    ///             Err(_) => return,
+    ///         };
+    ///         for line in BufReader::new(connection).lines().map_while(Result::ok) {
+    ///             let _ = writeln!(writer, "{}", line);
+    ///         }
+    ///     })?;
+    ///     let mut headers = HeaderMap::new();
+    ///     headers.insert("Upgrade", "line-echo");
+    ///     Ok::<_, Response>(Response::switching_protocols(headers))
+    /// });
+    /// ```
+    pub fn on_upgrade(
+        &self,
+        protocol: &str,
+        handler: impl FnOnce(Hijacked) + Send + 'static,
+    ) -> Result<(), Response> {
+        let slot = self.extensions.get::<HijackSlot>();
+        let offered = self
+            .upgrade_protocols()
+            .iter()
+            .any(|offer| offer.eq_ignore_ascii_case(protocol));
+        if !offered || slot.is_some_and(|slot| !slot.http_1_1) {
+            let mut response = text_response(426, reason_phrase(426));
+            response.headers.insert("Upgrade", protocol);
+            response.headers.insert("Connection", "Upgrade");
+            return Err(response);
+        }
+        let has_body = self.header("Transfer-Encoding").is_some()
+            || self
+                .header("Content-Length")
+                .is_some_and(|length| length.trim() != "0");
+        if has_body {
+            return Err(text_response(
+                400,
+                "Bad Request: a request with a body cannot be upgraded",
+            ));
+        }
+        let Some(slot) = slot else {
+            return Err(text_response(501, reason_phrase(501)));
+        };
+        slot.set(Takeover::Upgrade(Box::new(handler)));
+        Ok(())
+    }
+}
+
+impl Response {
+    /// Creates the `101 Switching Protocols` response accepting an upgrade registered
+    /// with [`Request::on_upgrade`], with `Connection: Upgrade` added to `headers`.
+    ///
+    /// `headers` should include `Upgrade`, naming the protocol switched to, along with
+    /// whatever its handshake requires. The response has no body.
+    ///
+    /// # Arguments
+    ///
+    /// * `headers` - The headers of the response.
+    pub fn switching_protocols(mut headers: HeaderMap) -> Response {
+        if !headers.contains_key("Connection") {
+            headers.insert("Connection", "Upgrade");
+        }
+        Response {
+            status_code: 101,
+            reason: reason_phrase(101).into(),
+            response_body: None,
+            headers,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test_upgrade {
+    use super::*;
+    use crate::app::App;
+    use crate::test::TestClient;
+
+    /// Tests that upgrades are refused with the status matching what is wrong with the
+    /// request, before the connection is considered.
+    #[test]
+    fn test_refused_upgrades() {
+        let mut application = App::new();
+        application.get("toy", |request| {
+            request.on_upgrade("toy", |_| {})?;
+            Ok::<_, Response>(Response::switching_protocols(HeaderMap::new()))
+        });
+        let client = TestClient::new(application);
+
+        let response = client.get("/toy").send();
+        assert_eq!(response.status, 426);
+        assert_eq!(response.header("Upgrade"), Some("toy"));
+        let response = client
+            .get("/toy")
+            .header("Connection", "Upgrade")
+            .header("Upgrade", "other")
+            .send();
+        assert_eq!(response.status, 426);
+
+        let response = client
+            .get("/toy")
+            .header("Connection", "Upgrade")
+            .header("Upgrade", "TOY")
+            .header("Content-Length", "2")
+            .body("hi")
+            .send();
+        assert_eq!(response.status, 400);
+
+        // An in-memory request has no connection to hand over.
+        let response = client
+            .get("/toy")
+            .header("Connection", "Upgrade")
+            .header("Upgrade", "TOY")
+            .send();
+        assert_eq!(response.status, 501);
+    }
+}
@@ -0,0 +1,380 @@
+//! Outbound webhooks: JSON payloads POSTed to a configured URL, signed and retried until
+//! they are delivered.
+
+use crate::client::{ClientError, ClientRequest};
+use crate::crypto::{constant_time_eq, hmac_sha256};
+use crate::tasks::Tasks;
+use std::fmt;
+use std::sync::mpsc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// The default of [`Webhook::max_attempts`].
+pub const DEFAULT_MAX_ATTEMPTS: u32 = 5;
+/// The default of [`Webhook::backoff`].
+pub const DEFAULT_BACKOFF: Duration = Duration::from_millis(500);
+/// The longest wait between two attempts, however many have failed.
+pub const MAX_BACKOFF: Duration = Duration::from_secs(30);
+/// The default of [`Webhook::attempt_timeout`].
+pub const DEFAULT_ATTEMPT_TIMEOUT: Duration = Duration::from_secs(10);
+/// The default of [`Webhook::deadline`].
+pub const DEFAULT_DEADLINE: Duration = Duration::from_secs(120);
+
+/// A URL that JSON payloads are POSTed to when something happens, with at-least-once
+/// delivery.
+///
+/// Every payload is sent with `Content-Type: application/json` and an `X-Webhook-Id`
+/// that stays the same across the attempts to deliver it, so that the receiver can
+/// ignore duplicates. With a [secret](Webhook::secret), the body is signed with
+/// HMAC-SHA256 in an `X-Signature: sha256=<hex>` header, which receivers check with
+/// [`verify_signature`].
+///
+/// An attempt answered with a `5xx`, `408` or `429` status, or that fails to connect or
+/// to get a response, is retried after a wait that starts at the
+/// [backoff](Webhook::backoff) and doubles with every failure, up to [`MAX_BACKOFF`].
+/// Delivery stops after [`Webhook::max_attempts`] attempts or once the
+/// [deadline](Webhook::deadline) has passed, whichever comes first, and any other status
+/// stops it at once.
+///
+/// # Examples
+///
+/// ```no_run
+/// use rustic::webhook::Webhook;
+///
+/// let webhook = Webhook::new("http://hooks.internal:9000/deploys").secret("s3cret");
+/// let delivery = webhook.send(&serde_json::json!({ "event": "deployed" })).unwrap();
+/// match delivery.wait().result {
+///     Ok(status) => println!("Delivered with {}", status),
+///     Err(err) => eprintln!("Gave up: {}", err),
+/// }
+/// ```
+#[derive(Clone)]
+pub struct Webhook {
+    url: String,
+    secret: Option<Vec<u8>>,
+    max_attempts: u32,
+    backoff: Duration,
+    attempt_timeout: Duration,
+    deadline: Duration,
+    tasks: Option<Tasks>,
+}
+
+impl Webhook {
+    /// Creates a webhook POSTing to an `http://` URL, unsigned, with the default limits.
+    pub fn new(url: &str) -> Self {
+        Webhook {
+            url: url.to_string(),
+            secret: None,
+            max_attempts: DEFAULT_MAX_ATTEMPTS,
+            backoff: DEFAULT_BACKOFF,
+            attempt_timeout: DEFAULT_ATTEMPT_TIMEOUT,
+            deadline: DEFAULT_DEADLINE,
+            tasks: None,
+        }
+    }
+
+    /// Sets the key the bodies are signed with in `X-Signature`.
+    pub fn secret(mut self, key: impl AsRef<[u8]>) -> Self {
+        self.secret = Some(key.as_ref().to_vec());
+        self
+    }
+
+    /// Sets how many attempts are made to deliver a payload, the first one included.
+    pub fn max_attempts(mut self, attempts: u32) -> Self {
+        self.max_attempts = attempts.max(1);
+        self
+    }
+
+    /// Sets how long to wait before the first retry, which doubles for every retry after.
+    pub fn backoff(mut self, backoff: Duration) -> Self {
+        self.backoff = backoff;
+        self
+    }
+
+    /// Sets how long each attempt may take to connect, send and receive the response.
+    pub fn attempt_timeout(mut self, timeout: Duration) -> Self {
+        self.attempt_timeout = timeout;
+        self
+    }
+
+    /// Sets how long after [`Webhook::send`] no attempt is started any more, and the
+    /// last one is cut short.
+    pub fn deadline(mut self, deadline: Duration) -> Self {
+        self.deadline = deadline;
+        self
+    }
+
+    /// Delivers payloads on the app's background threads, so that a graceful shutdown
+    /// waits for them, instead of on a thread of their own.
+    pub fn tasks(mut self, tasks: Tasks) -> Self {
+        self.tasks = Some(tasks);
+        self
+    }
+
+    /// Serializes `payload` as JSON and starts delivering it in the background.
+    ///
+    /// # Arguments
+    ///
+    /// * `payload` - The value to send.
+    ///
+    /// # Returns
+    ///
+    /// * `Result<PendingDelivery, serde_json::Error>` - A handle to wait for the outcome
+    ///   with, or the error serializing the payload, in which case nothing is sent.
+    pub fn send(
+        &self,
+        payload: &impl serde::Serialize,
+    ) -> Result<PendingDelivery, serde_json::Error> {
+        let body = serde_json::to_vec(payload)?;
+        let (sender, receiver) = mpsc::channel();
+        let webhook = self.clone();
+        let deliver = move || {
+            let _ = sender.send(webhook.deliver(&body));
+        };
+        match &self.tasks {
+            Some(tasks) => tasks.spawn(deliver),
+            None => {
+                thread::spawn(deliver);
+            }
+        }
+        Ok(PendingDelivery { receiver })
+    }
+
+    /// Makes the attempts to deliver `body`, waiting between them.
+    fn deliver(&self, body: &[u8]) -> Delivery {
+        let deadline = Instant::now() + self.deadline;
+        let id = delivery_id();
+        let signature = self.secret.as_deref().map(|key| sign(key, body));
+        let mut backoff = self.backoff;
+        let mut attempts = 0;
+        loop {
+            attempts += 1;
+            let timeout = self
+                .attempt_timeout
+                .min(deadline.saturating_duration_since(Instant::now()));
+            let mut request = ClientRequest::post(&self.url)
+                .header("Content-Type", "application/json")
+                .header("X-Webhook-Id", &id)
+                .body(body)
+                .timeout(timeout);
+            if let Some(signature) = &signature {
+                request = request.header("X-Signature", signature);
+            }
+            let error = match request.send() {
+                Ok(response) if (200..300).contains(&response.status) => {
+                    return Delivery {
+                        attempts,
+                        result: Ok(response.status),
+                    };
+                }
+                Ok(response) if matches!(response.status, 408 | 429 | 500..=599) => {
+                    DeliveryError::ServerError(response.status)
+                }
+                Ok(response) => DeliveryError::Rejected(response.status),
+                Err(err) => DeliveryError::Client(err),
+            };
+            let retryable = !matches!(
+                error,
+                DeliveryError::Rejected(_) | DeliveryError::Client(ClientError::InvalidUrl(_))
+            );
+            let retry_at = Instant::now() + backoff;
+            if !retryable || attempts >= self.max_attempts || retry_at >= deadline {
+                log::warn!(
+                    "Gave up delivering webhook {} to {} after {} attempts: {}",
+                    id,
+                    self.url,
+                    attempts,
+                    error
+                );
+                return Delivery {
+                    attempts,
+                    result: Err(error),
+                };
+            }
+            log::debug!(
+                "Webhook {} attempt {} failed, retrying in {:?}: {}",
+                id,
+                attempts,
+                backoff,
+                error
+            );
+            thread::sleep(backoff);
+            backoff = (backoff * 2).min(MAX_BACKOFF);
+        }
+    }
+}
+
+/// A payload being delivered by [`Webhook::send`].
+pub struct PendingDelivery {
+    receiver: mpsc::Receiver<Delivery>,
+}
+
+impl PendingDelivery {
+    /// Waits until the payload is delivered or delivery is given up.
+    pub fn wait(self) -> Delivery {
+        self.receiver.recv().unwrap_or(Delivery {
+            attempts: 0,
+            result: Err(DeliveryError::Abandoned),
+        })
+    }
+
+    /// Waits up to `timeout` for the outcome, returning `None` if delivery is still
+    /// going on.
+    pub fn wait_timeout(&self, timeout: Duration) -> Option<Delivery> {
+        match self.receiver.recv_timeout(timeout) {
+            Ok(delivery) => Some(delivery),
+            Err(mpsc::RecvTimeoutError::Timeout) => None,
+            Err(mpsc::RecvTimeoutError::Disconnected) => Some(Delivery {
+                attempts: 0,
+                result: Err(DeliveryError::Abandoned),
+            }),
+        }
+    }
+}
+
+/// The outcome of delivering a payload.
+#[derive(Debug)]
+pub struct Delivery {
+    /// How many attempts were made.
+    pub attempts: u32,
+    /// The `2xx` status the payload was accepted with, or why it was not delivered.
+    pub result: Result<u16, DeliveryError>,
+}
+
+/// Why a payload was not delivered, as of the last attempt.
+#[derive(Debug)]
+pub enum DeliveryError {
+    /// The receiver answered with a status that retrying would not change, such as `400`
+    /// or `404`.
+    Rejected(u16),
+    /// The receiver answered with a `5xx`, `408` or `429` status until no attempt or
+    /// time was left.
+    ServerError(u16),
+    /// The request could not be sent or its response read, until no attempt or time was
+    /// left, or the URL is invalid.
+    Client(ClientError),
+    /// The delivery stopped without an outcome, as when its task panicked.
+    Abandoned,
+}
+
+impl fmt::Display for DeliveryError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DeliveryError::Rejected(status) => write!(f, "rejected with {}", status),
+            DeliveryError::ServerError(status) => write!(f, "failed with {}", status),
+            DeliveryError::Client(err) => err.fmt(f),
+            DeliveryError::Abandoned => f.write_str("abandoned"),
+        }
+    }
+}
+
+impl std::error::Error for DeliveryError {}
+
+/// Checks the `X-Signature` header of a webhook received from a [`Webhook`], in constant
+/// time.
+///
+/// # Arguments
+///
+/// * `secret` - The key the webhook was configured with.
+/// * `body` - The body as it was received.
+/// * `signature` - The value of the `X-Signature` header.
+///
+/// # Examples
+///
+/// ```
+/// use rustic::app::{App, Request};
+/// use rustic::webhook::verify_signature;
+///
+/// let mut application = App::new();
+/// application.post("hooks/deploys", |request: Request| {
+///     let signature = request.header("X-Signature").unwrap_or("");
+///     if !verify_signature(b"s3cret", request.body.as_bytes(), signature) {
+///         return (401, "Bad signature");
+///     }
+///     (204, "")
+/// });
+/// ```
+pub fn verify_signature(secret: &[u8], body: &[u8], signature: &str) -> bool {
+    constant_time_eq(sign(secret, body).as_bytes(), signature.as_bytes())
+}
+
+/// Signs a body into the value of `X-Signature`.
+fn sign(key: &[u8], body: &[u8]) -> String {
+    let mac = hmac_sha256(key, body);
+    let hex: String = mac.iter().map(|byte| format!("{:02x}", byte)).collect();
+    format!("sha256={}", hex)
+}
+
+/// Generates the ID of a delivery, shared by its attempts.
+fn delivery_id() -> String {
+    let mut bytes = [0u8; 16];
+    getrandom::fill(&mut bytes).expect("Failed to gather randomness for a webhook ID");
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+#[cfg(test)]
+mod test_webhook {
+    use super::*;
+
+    /// Tests that signatures are the hexadecimal HMAC-SHA256 of the body and only
+    /// verify for the same key and body.
+    #[test]
+    fn test_signature() {
+        // RFC 4231, test case 2.
+        assert_eq!(
+            sign(b"Jefe", b"what do ya want for nothing?"),
+            "sha256=5bdcc146bf60754e6a042426089575c75a003f089d2739839dec58b964ec3843"
+        );
+        let signature = sign(b"key", b"{}");
+        assert!(verify_signature(b"key", b"{}", &signature));
+        assert!(!verify_signature(b"other", b"{}", &signature));
+        assert!(!verify_signature(b"key", b"{ }", &signature));
+        assert!(!verify_signature(b"key", b"{}", ""));
+    }
+
+    /// Tests that an invalid URL fails without retrying, and an unreachable one is
+    /// retried until the attempts run out.
+    #[test]
+    fn test_failed_deliveries() {
+        let delivery = Webhook::new("https://example.com/hooks")
+            .send(&[1, 2])
+            .unwrap()
+            .wait();
+        assert_eq!(delivery.attempts, 1);
+        assert!(matches!(
+            delivery.result,
+            Err(DeliveryError::Client(ClientError::InvalidUrl(_)))
+        ));
+
+        let delivery = Webhook::new("http://127.0.0.1:1/hooks")
+            .max_attempts(3)
+            .backoff(Duration::from_millis(1))
+            .send(&"payload")
+            .unwrap()
+            .wait();
+        assert_eq!(delivery.attempts, 3);
+        assert!(matches!(
+            delivery.result,
+            Err(DeliveryError::Client(ClientError::Io(_)))
+        ));
+    }
+
+    /// Tests that no attempt starts once the deadline would pass during the backoff.
+    #[test]
+    fn test_deadline() {
+        let started = Instant::now();
+        let delivery = Webhook::new("http://127.0.0.1:1/hooks")
+            .max_attempts(100)
+            .backoff(Duration::from_millis(40))
+            .deadline(Duration::from_millis(100))
+            .send(&"payload")
+            .unwrap()
+            .wait();
+        assert!(
+            delivery.attempts >= 2 && delivery.attempts <= 3,
+            "{:?}",
+            delivery
+        );
+        assert!(started.elapsed() < Duration::from_secs(1));
+    }
+}
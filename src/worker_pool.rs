@@ -0,0 +1,113 @@
+use std::io;
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::mpsc::{self, Receiver, SyncSender, TrySendError};
+use std::sync::{Arc, Mutex};
+use std::thread;
+
+/// A fixed set of threads handling items from a bounded queue.
+///
+/// Dropping the pool lets the workers exit once the queued items are handled, without
+/// waiting for them.
+pub(crate) struct WorkerPool<T> {
+    sender: SyncSender<T>,
+}
+
+impl<T: Send + 'static> WorkerPool<T> {
+    /// Starts `workers` threads named after `name` passing items to `handler`, with room
+    /// for `queue` items waiting for a free worker.
+    ///
+    /// Fails when a thread cannot be spawned, letting those already started exit.
+    pub(crate) fn new<F>(
+        name: &'static str,
+        workers: usize,
+        queue: usize,
+        handler: F,
+    ) -> io::Result<Self>
+    where
+        F: Fn(T) + Send + Sync + 'static,
+    {
+        let (sender, receiver) = mpsc::sync_channel::<T>(queue);
+        let receiver = Arc::new(Mutex::new(receiver));
+        let handler = Arc::new(handler);
+        for index in 0..workers.max(1) {
+            let receiver = Arc::clone(&receiver);
+            let handler = Arc::clone(&handler);
+            thread::Builder::new()
+                .name(format!("rustic-{}-{}", name, index))
+                .spawn(move || work(name, &receiver, &*handler))?;
+        }
+        Ok(WorkerPool { sender })
+    }
+
+    /// Queues an item, waiting for room in the queue if it is full.
+    pub(crate) fn execute(&self, item: T) {
+        // The workers only stop once the sender is dropped, so sending cannot fail.
+        let _ = self.sender.send(item);
+    }
+
+    /// Queues an item unless the queue is full, in which case the item is handed back.
+    pub(crate) fn try_execute(&self, item: T) -> Result<(), T> {
+        self.sender.try_send(item).map_err(|err| match err {
+            TrySendError::Full(item) | TrySendError::Disconnected(item) => item,
+        })
+    }
+}
+
+/// Handles items until the pool is dropped, surviving a handler that panics.
+fn work<T>(name: &str, receiver: &Mutex<Receiver<T>>, handler: &dyn Fn(T)) {
+    loop {
+        // Release the lock before handling the item so other workers can take the next.
+        let item = receiver.lock().unwrap().recv();
+        let Ok(item) = item else {
+            return;
+        };
+        if panic::catch_unwind(AssertUnwindSafe(|| handler(item))).is_err() {
+            log::error!("A {} thread panicked", name);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test_worker_pool {
+    use super::*;
+    use std::sync::mpsc::channel;
+    use std::time::Duration;
+
+    /// Tests that items reach the handler and that a panicking handler does not kill
+    /// its worker.
+    #[test]
+    fn test_execute() {
+        let (sender, receiver) = channel();
+        let sender = Mutex::new(sender);
+        let pool = WorkerPool::new("test", 1, 4, move |item: i32| {
+            assert!(item >= 0, "negative item");
+            sender.lock().unwrap().send(item).unwrap();
+        })
+        .unwrap();
+        pool.execute(-1);
+        for item in 0..3 {
+            pool.execute(item);
+        }
+        let received: Vec<i32> = (0..3)
+            .map(|_| receiver.recv_timeout(Duration::from_secs(2)).unwrap())
+            .collect();
+        assert_eq!(received, [0, 1, 2]);
+    }
+
+    /// Tests that items beyond the queue are handed back while the worker is busy.
+    #[test]
+    fn test_try_execute() {
+        let (release, wait) = channel::<()>();
+        let wait = Mutex::new(wait);
+        let pool = WorkerPool::new("test", 1, 1, move |_: i32| {
+            let _ = wait.lock().unwrap().recv_timeout(Duration::from_secs(2));
+        })
+        .unwrap();
+        pool.execute(0);
+        // Give the worker time to take the first item off the queue.
+        thread::sleep(Duration::from_millis(50));
+        assert_eq!(pool.try_execute(1), Ok(()));
+        assert_eq!(pool.try_execute(2), Err(2));
+        release.send(()).unwrap();
+    }
+}
@@ -0,0 +1,88 @@
+//! Measures the allocations and throughput of serving keep-alive requests.
+//!
+//! A counting allocator replaces the global one for this test binary only, which is
+//! why these tests live apart from the integration tests.
+
+use rustic::app::{run_with_listener, App, ServerConfig};
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::io::{Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::thread;
+use std::time::Instant;
+
+/// Counts every allocation made by the process, on any thread.
+struct CountingAllocator;
+
+static ALLOCATIONS: AtomicUsize = AtomicUsize::new(0);
+
+unsafe impl GlobalAlloc for CountingAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout)
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        System.realloc(ptr, layout, new_size)
+    }
+}
+
+#[global_allocator]
+static GLOBAL: CountingAllocator = CountingAllocator;
+
+/// Sends a request over a kept-alive connection and reads its response into `buffer`,
+/// which is reused so that the client side allocates nothing.
+fn exchange(stream: &mut TcpStream, request: &[u8], buffer: &mut [u8], expected: usize) {
+    stream.write_all(request).unwrap();
+    let mut read = 0;
+    while read < expected {
+        let count = stream.read(&mut buffer[read..]).unwrap();
+        assert!(count > 0, "connection closed");
+        read += count;
+    }
+}
+
+/// Tests that serving a keep-alive request allocates a bounded number of times, and
+/// reports the allocations and throughput measured.
+#[test]
+fn test_keep_alive_allocations() {
+    const REQUESTS: usize = 2_000;
+
+    let mut application = App::new();
+    application.post("echo", |request| request.body);
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let address = listener.local_addr().unwrap();
+    let config = ServerConfig::new().max_requests_per_connection(usize::MAX);
+    thread::spawn(move || run_with_listener(application, listener, config));
+
+    let request = b"POST /echo HTTP/1.1\r\nHost: localhost\r\nUser-Agent: bench\r\n\
+                    Accept: */*\r\nContent-Length: 11\r\n\r\nhello world";
+    let mut stream = TcpStream::connect(address).unwrap();
+    let mut buffer = vec![0; 4096];
+    // The response has a fixed size, as its Date header does.
+    stream.write_all(request).unwrap();
+    let size = stream.read(&mut buffer).unwrap();
+    assert!(buffer[..size].ends_with(b"hello world"));
+    for _ in 0..100 {
+        exchange(&mut stream, request, &mut buffer, size);
+    }
+
+    let allocations = ALLOCATIONS.load(Ordering::Relaxed);
+    let started = Instant::now();
+    for _ in 0..REQUESTS {
+        exchange(&mut stream, request, &mut buffer, size);
+    }
+    let elapsed = started.elapsed();
+    let per_request = (ALLOCATIONS.load(Ordering::Relaxed) - allocations) / REQUESTS;
+    eprintln!(
+        "{} allocations per request, {:.0} requests/s",
+        per_request,
+        REQUESTS as f64 / elapsed.as_secs_f64()
+    );
+    assert!(per_request <= 36, "{} allocations per request", per_request);
+}
@@ -1,35 +1,85 @@
 #[cfg(test)]
 mod integration_tests {
     use reqwest::blocking::Client;
-    use rustic::app::{run, App, Request};
+    use rustic::app::{
+        run_with_config, run_with_listener, App, EndpointConfig, ErrorFormat, Leniency,
+        MissingLength, OverloadPolicy, Request, ServerConfig,
+    };
+    use rustic::client::{ClientError, ClientRequest};
     use rustic::connection::{handle_connection, listen_at_port};
-    use rustic::http11_response::Response;
-    use rustic::parse_headers::RequestType;
-    use std::collections::HashMap;
+    use rustic::cors::Cors;
+    use rustic::csrf::CsrfMiddleware;
+    use rustic::header_map::HeaderMap;
+    use rustic::http11_response::{Body, Response};
+    use rustic::into_response::text_response;
+    use rustic::maintenance::Maintenance;
+    use rustic::method_override::MethodOverride;
+    use rustic::middleware::Next;
+    use rustic::parse_headers::{parse_headers, RequestType};
+    use rustic::parse_path::parse_path;
+    use rustic::parse_url::{
+        form_pairs, parse_authority, parse_url_param, percent_decode, query_pairs,
+    };
+    use rustic::request_id::RequestIdMiddleware;
+    use rustic::security_headers::SecurityHeaders;
+    use rustic::session::SessionMiddleware;
+    use rustic::shutdown::{Shutdown, ShutdownOutcome};
+    use rustic::site_files::RobotsTxt;
+    use rustic::static_files::StaticOptions;
+    use rustic::test::TestClient;
+    use std::fs;
+    use std::io::ErrorKind;
+    use std::io::{Read, Write};
+    use std::net::{TcpListener, TcpStream};
+    use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
     use std::sync::mpsc;
+    use std::sync::Arc;
+    use std::sync::Mutex;
     use std::thread;
-    use std::time::Duration;
+    use std::time::{Duration, Instant};
+
+    /// Serves `application` on an ephemeral port and returns its base URL.
+    fn spawn_app(application: App) -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        thread::spawn(move || run_with_listener(application, listener, ServerConfig::new()));
+        format!("http://{}", address)
+    }
 
     #[test]
     fn test_port_bind() {
-        listen_at_port(8000);
+        let _listener = listen_at_port(8000).expect("Failed to bind to port");
+        let err = listen_at_port(8000).unwrap_err();
+        assert!(matches!(&err, rustic::Error::Bind(err) if err.kind() == ErrorKind::AddrInUse));
+    }
+
+    /// Tests that starting the server reports a taken port as an error instead of
+    /// panicking.
+    #[test]
+    fn test_run_bind_error() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let err = run_with_config(App::new(), port, ServerConfig::new()).unwrap_err();
+        assert!(matches!(err, rustic::Error::Bind(_)), "{}", err);
+        assert_eq!(err.io_error().unwrap().kind(), ErrorKind::AddrInUse);
     }
 
     #[test]
     fn test_request() {
-        let listener = listen_at_port(8001);
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
         let (tx, rx) = mpsc::channel();
 
         let handle = thread::spawn(move || {
             if let Ok((mut stream, _)) = listener.accept() {
-                let request = handle_connection(&mut stream);
+                let request = handle_connection(&mut stream).unwrap().unwrap();
                 tx.send(request).unwrap();
             }
         });
 
         // Send a request
         let client = Client::new();
-        let url = "http://localhost:8001/";
+        let url = format!("http://{}/", address);
         let _ = client.get(url).send();
 
         // Wait to receive the processed request
@@ -51,7 +101,8 @@ mod integration_tests {
             "The second line should be accept header"
         );
         assert_eq!(
-            received_headers[2], "host: localhost:8001",
+            received_headers[2],
+            format!("host: {}", address),
             "The third line should be host header"
         );
 
@@ -63,13 +114,13 @@ mod integration_tests {
     fn test_create_app() {
         let mut application = App::new();
 
-        fn hello_world(_: Request) -> Option<Response<'static>> {
-            let mut headers = HashMap::new();
-            headers.insert("Content-Type".to_string(), "text/plain".to_string());
+        fn hello_world(_: Request) -> Option<Response> {
+            let mut headers = HeaderMap::new();
+            headers.insert("Content-Type", "text/plain");
             let response = Response {
                 status_code: 200,
-                reason: "Ok",
-                response_body: Some("Hi!"),
+                reason: "Ok".into(),
+                response_body: Some("Hi!".into()),
                 headers,
             };
             Some(response)
@@ -77,22 +128,19 @@ mod integration_tests {
 
         application.add_endpoint("test", RequestType::POST, hello_world);
 
-        let (tx, rx) = mpsc::channel();
-
         // Start the server in a separate thread
-        let server_handle = thread::spawn(move || {
-            tx.send(()).unwrap();
-            run(application, 8002, true);
-        });
-
-        // Wait for the signal that the server has started
-        rx.recv_timeout(Duration::from_secs(2))
-            .expect("Server did not start in time");
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        thread::spawn(move || run_with_listener(application, listener, ServerConfig::new()));
 
         // Create a client and send a POST request
         let client = Client::new();
-        let url = "http://localhost:8002/test";
-        let response = client.post(url).send().expect("Failed to send request");
+        let url = format!("http://{}/test", address);
+        let response = client
+            .post(url)
+            .body("")
+            .send()
+            .expect("Failed to send request");
 
         // Assert that we received the expected response
         assert_eq!(response.status().as_u16(), 200, "Status code should be 200");
@@ -102,4 +150,3363 @@ mod integration_tests {
             "Response body should be 'Hi!'"
         );
     }
+
+    #[test]
+    fn test_security_headers() {
+        let mut application = App::new();
+
+        fn plain(_: Request) -> Option<Response> {
+            Some(Response {
+                status_code: 200,
+                reason: "Ok".into(),
+                response_body: Some("plain".into()),
+                headers: HeaderMap::new(),
+            })
+        }
+
+        fn framed(_: Request) -> Option<Response> {
+            let mut headers = HeaderMap::new();
+            headers.insert("X-Frame-Options", "SAMEORIGIN");
+            Some(Response {
+                status_code: 200,
+                reason: "Ok".into(),
+                response_body: Some("framed".into()),
+                headers,
+            })
+        }
+
+        application.add_endpoint("plain", RequestType::GET, plain);
+        application.add_endpoint("framed", RequestType::GET, framed);
+        application.add_middleware(SecurityHeaders::new().csp("default-src 'self'"));
+
+        let base = spawn_app(application);
+
+        let client = Client::new();
+        let response = client
+            .get(format!("{}/plain", base))
+            .send()
+            .expect("Failed to send request");
+        let headers = response.headers();
+        assert_eq!(headers["x-content-type-options"], "nosniff");
+        assert_eq!(headers["x-frame-options"], "DENY");
+        assert_eq!(headers["content-security-policy"], "default-src 'self'");
+        assert!(headers.get("strict-transport-security").is_none());
+
+        let response = client
+            .get(format!("{}/framed", base))
+            .send()
+            .expect("Failed to send request");
+        assert_eq!(
+            response.headers()["x-frame-options"],
+            "SAMEORIGIN",
+            "Handler-provided header should win"
+        );
+
+        let response = client
+            .get(format!("{}/missing", base))
+            .send()
+            .expect("Failed to send request");
+        assert_eq!(response.status().as_u16(), 404);
+        assert_eq!(response.headers()["x-content-type-options"], "nosniff");
+    }
+
+    #[test]
+    fn test_session_login_flow() {
+        let mut application = App::new();
+
+        fn login(request: Request) -> Option<Response> {
+            let session = request.session()?;
+            let user = request.query_param("user").value()?;
+            session.insert("user", &user);
+            Some(Response {
+                status_code: 200,
+                reason: "Ok".into(),
+                response_body: Some("logged in".into()),
+                headers: HeaderMap::new(),
+            })
+        }
+
+        fn whoami(request: Request) -> Option<Response> {
+            let user = request.session()?.get("user");
+            Some(Response {
+                status_code: 200,
+                reason: "Ok".into(),
+                response_body: Some(
+                    if user.as_deref() == Some("alice") {
+                        "alice"
+                    } else {
+                        "anonymous"
+                    }
+                    .into(),
+                ),
+                headers: HeaderMap::new(),
+            })
+        }
+
+        application.add_endpoint("login", RequestType::POST, login);
+        application.add_endpoint("whoami", RequestType::GET, whoami);
+        application.add_middleware(SessionMiddleware::new());
+
+        let base = spawn_app(application);
+
+        let client = Client::builder().cookie_store(true).build().unwrap();
+        let anonymous = client
+            .get(format!("{}/whoami", base))
+            .send()
+            .expect("Failed to send request");
+        assert!(anonymous.headers().get("set-cookie").is_none());
+        assert_eq!(anonymous.text().unwrap(), "anonymous");
+
+        let login = client
+            .post(format!("{}/login?user=alice", base))
+            .body("")
+            .send()
+            .expect("Failed to send request");
+        let cookie = login.headers()["set-cookie"].to_str().unwrap().to_string();
+        assert!(cookie.starts_with("session_id="));
+        assert!(cookie.contains("HttpOnly"));
+        assert!(cookie.contains("SameSite=Lax"));
+
+        let whoami = client
+            .get(format!("{}/whoami", base))
+            .send()
+            .expect("Failed to send request");
+        assert_eq!(whoami.text().unwrap(), "alice");
+
+        let stranger = Client::new()
+            .get(format!("{}/whoami", base))
+            .send()
+            .expect("Failed to send request");
+        assert_eq!(stranger.text().unwrap(), "anonymous");
+    }
+
+    #[test]
+    fn test_reverse_proxy() {
+        let mut upstream = App::new();
+        upstream.add_endpoint("api/echo", RequestType::POST, |request: Request| {
+            let mut headers = HeaderMap::new();
+            headers.insert("X-Upstream", "yes");
+            let forwarded_for = request.header("X-Forwarded-For").unwrap_or("").to_string();
+            Some(Response {
+                status_code: 201,
+                reason: "Created".into(),
+                response_body: Some(
+                    format!("{} {} {}", request.url, request.body, forwarded_for).into(),
+                ),
+                headers,
+            })
+        });
+        let upstream_url = spawn_app(upstream);
+
+        let mut proxy = App::new();
+        proxy.proxy("api/*", &upstream_url);
+        proxy.proxy("down/*", "http://127.0.0.1:1");
+        let base = spawn_app(proxy);
+
+        let client = Client::new();
+        let response = client
+            .post(format!("{}/api/echo?x=1", base))
+            .body("payload")
+            .send()
+            .expect("Failed to send request");
+        assert_eq!(response.status().as_u16(), 201);
+        assert_eq!(response.headers()["x-upstream"], "yes");
+        assert_eq!(response.text().unwrap(), "/api/echo?x=1 payload 127.0.0.1");
+
+        let response = client
+            .get(format!("{}/api/missing", base))
+            .send()
+            .expect("Failed to send request");
+        assert_eq!(response.status().as_u16(), 404);
+
+        let response = client
+            .get(format!("{}/down/anything", base))
+            .send()
+            .expect("Failed to send request");
+        assert_eq!(response.status().as_u16(), 502);
+    }
+
+    /// Tests that the proxy relays bodies that are not UTF-8 both ways, and every
+    /// `Set-Cookie` header of the upstream response.
+    #[test]
+    fn test_proxy_binary_body_and_cookies() {
+        let mut upstream = App::new();
+        upstream.add_endpoint_with_config(
+            "files/upload",
+            RequestType::PUT,
+            |mut request: Request| {
+                let mut body = Vec::new();
+                request.body_reader().read_to_end(&mut body).unwrap();
+                body.reverse();
+                let mut response = text_response(200, body);
+                response.headers.append("Set-Cookie", "a=1; Path=/");
+                response.headers.append("Set-Cookie", "b=2; Path=/");
+                response
+            },
+            EndpointConfig::new().stream_body(),
+        );
+        let upstream_url = spawn_app(upstream);
+
+        let mut proxy = App::new();
+        proxy.proxy("files/*", &upstream_url);
+        let base = spawn_app(proxy);
+
+        let payload = vec![0xff, 0xfe, 0x00, 0x80, 0xc3];
+        let response = Client::new()
+            .put(format!("{}/files/upload", base))
+            .body(payload.clone())
+            .send()
+            .expect("Failed to send request");
+        assert_eq!(response.status().as_u16(), 200);
+        let cookies: Vec<_> = response
+            .headers()
+            .get_all("set-cookie")
+            .iter()
+            .map(|value| value.to_str().unwrap().to_string())
+            .collect();
+        assert_eq!(cookies, ["a=1; Path=/", "b=2; Path=/"]);
+        let mut expected = payload;
+        expected.reverse();
+        assert_eq!(response.bytes().unwrap().as_ref(), expected.as_slice());
+    }
+
+    #[test]
+    fn test_client_requests() {
+        let mut application = App::new();
+        application.add_endpoint("echo", RequestType::POST, |request: Request| {
+            let token = request.header("X-Token").unwrap_or("").to_string();
+            Some(Response {
+                status_code: 200,
+                reason: "Ok".into(),
+                response_body: Some(format!("{}:{}", token, request.body).into()),
+                headers: HeaderMap::new(),
+            })
+        });
+        let base = spawn_app(application);
+
+        let response = ClientRequest::post(&format!("{}/echo", base))
+            .header("X-Token", "abc")
+            .body("ping")
+            .send()
+            .expect("Failed to send request");
+        assert_eq!(response.status, 200);
+        assert_eq!(response.header("content-length"), Some("8"));
+        assert_eq!(response.text(), "abc:ping");
+
+        let response = ClientRequest::get(&format!("{}/missing", base))
+            .send()
+            .expect("Failed to send request");
+        assert_eq!(response.status, 404);
+
+        let result = ClientRequest::get(&base.replace("http://", "https://")).send();
+        assert!(matches!(result, Err(ClientError::InvalidUrl(_))));
+    }
+
+    #[test]
+    fn test_client_chunked_response() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut request = [0u8; 1024];
+            let _ = stream.read(&mut request).unwrap();
+            stream
+                .write_all(
+                    b"HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\n\r\n5\r\nHello\r\n7\r\n, world\r\n0\r\n\r\n",
+                )
+                .unwrap();
+        });
+
+        let response = ClientRequest::get(&format!("http://{}/", address))
+            .send()
+            .expect("Failed to send request");
+        assert_eq!(response.text(), "Hello, world");
+    }
+
+    #[test]
+    fn test_response_cache() {
+        static CALLS: AtomicUsize = AtomicUsize::new(0);
+
+        let mut application = App::new();
+        application.add_endpoint("reports/daily", RequestType::GET, |_| {
+            let calls = CALLS.fetch_add(1, Ordering::SeqCst) + 1;
+            Some(Response {
+                status_code: 200,
+                reason: "Ok".into(),
+                response_body: Some(format!("report {}", calls).into()),
+                headers: HeaderMap::new(),
+            })
+        });
+        application.cache("reports", Duration::from_millis(300), 10);
+        let base = spawn_app(application);
+
+        let client = Client::new();
+        let get = |url: &str| client.get(url).send().expect("Failed to send request");
+
+        let first = get(&format!("{}/reports/daily", base));
+        assert!(first.headers().get("age").is_none());
+        assert_eq!(first.text().unwrap(), "report 1");
+
+        let second = get(&format!("{}/reports/daily", base));
+        assert_eq!(second.headers()["age"], "0");
+        assert!(second.headers().get("date").is_some());
+        assert_eq!(second.text().unwrap(), "report 1");
+        assert_eq!(CALLS.load(Ordering::SeqCst), 1);
+
+        let other_query = get(&format!("{}/reports/daily?day=monday", base));
+        assert_eq!(other_query.text().unwrap(), "report 2");
+
+        thread::sleep(Duration::from_millis(350));
+        let expired = get(&format!("{}/reports/daily", base));
+        assert_eq!(expired.text().unwrap(), "report 3");
+        assert_eq!(CALLS.load(Ordering::SeqCst), 3);
+    }
+
+    #[test]
+    fn test_metrics_endpoint() {
+        let mut application = App::new();
+        application.add_endpoint("ping", RequestType::GET, |_| {
+            Some(Response {
+                status_code: 200,
+                reason: "OK".into(),
+                response_body: Some("pong".into()),
+                headers: HeaderMap::new(),
+            })
+        });
+        application.get("users/{id}", |_| "user");
+        application.enable_metrics_endpoint("/metrics");
+        let base = spawn_app(application);
+
+        let client = Client::new();
+        for path in ["ping", "users/12345", "missing"] {
+            client
+                .get(format!("{}/{}", base, path))
+                .send()
+                .expect("Failed to send request");
+        }
+
+        let scrape = client
+            .get(format!("{}/metrics", base))
+            .send()
+            .expect("Failed to send request");
+        assert!(scrape.headers()["content-type"]
+            .to_str()
+            .unwrap()
+            .starts_with("text/plain"));
+        let output = scrape.text().unwrap();
+        assert!(output.contains("rustic_http_requests_total{class=\"2xx\"} 2\n"));
+        assert!(output.contains("rustic_http_requests_total{class=\"4xx\"} 1\n"));
+        assert!(output.contains("rustic_http_request_duration_seconds_bucket{le=\"+Inf\"} 3\n"));
+        assert!(output.contains("rustic_http_request_duration_seconds_count 3\n"));
+        assert!(output.contains("rustic_http_requests_in_flight 1\n"));
+        // Routes are labelled by pattern, so each endpoint has one series.
+        assert!(output
+            .contains("rustic_http_route_requests_total{route=\"users/{id}\",class=\"2xx\"} 1\n"));
+        assert!(output
+            .contains("rustic_http_route_requests_total{route=\"<unmatched>\",class=\"4xx\"} 1\n"));
+        assert!(!output.contains("12345"), "{}", output);
+
+        let bytes_line = output
+            .lines()
+            .find(|line| line.starts_with("rustic_http_response_bytes_total "))
+            .unwrap();
+        let bytes: u64 = bytes_line.rsplit(' ').next().unwrap().parse().unwrap();
+        assert!(bytes > 3 * "HTTP/1.1 200 OK".len() as u64);
+    }
+
+    /// Tests that the bytes of each exchange are counted as they went over the wire, for
+    /// both a sized and a chunked request, and added up in the metrics.
+    #[test]
+    fn test_byte_counts() {
+        let counted = Arc::new(Mutex::new(Vec::new()));
+        let seen = Arc::clone(&counted);
+        let mut application = App::new();
+        application.post("upload", move |request| {
+            let seen = Arc::clone(&seen);
+            let received = request.request_bytes().unwrap();
+            request.after_response(move |bytes| seen.lock().unwrap().push(*bytes));
+            format!("{} {}", received.total(), received.decoded_body)
+        });
+        application.enable_metrics_endpoint("/metrics");
+        let base = spawn_app(application);
+        let address = base.trim_start_matches("http://");
+
+        let sized = "POST /upload HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\
+                     Content-Length: 5\r\n\r\nhello";
+        let chunked = "POST /upload HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\
+                       Transfer-Encoding: chunked\r\n\r\n5\r\nhello\r\n0\r\n\r\n";
+        let mut responses = Vec::new();
+        for raw in [sized, chunked] {
+            let mut stream = TcpStream::connect(address).unwrap();
+            stream.write_all(raw.as_bytes()).unwrap();
+            let mut response = Vec::new();
+            stream.read_to_end(&mut response).unwrap();
+            responses.push(response);
+        }
+
+        let counted = counted.lock().unwrap();
+        assert_eq!(counted.len(), 2);
+        for ((bytes, raw), response) in counted.iter().zip([sized, chunked]).zip(&responses) {
+            let head_len = raw.find("\r\n\r\n").unwrap() + 4;
+            assert_eq!(bytes.request.head, head_len);
+            assert_eq!(bytes.request.body, raw.len() - head_len);
+            assert_eq!(bytes.request.decoded_body, 5);
+            assert_eq!(bytes.read(), raw.len());
+            assert_eq!(bytes.written(), response.len());
+            let body = format!("{} 5", raw.len());
+            assert_eq!(bytes.response_body, body.len());
+            let text = String::from_utf8_lossy(response);
+            assert!(text.ends_with(&format!("\r\n\r\n{}", body)));
+        }
+
+        let output = Client::new()
+            .get(format!("{}/metrics", base))
+            .send()
+            .unwrap()
+            .text()
+            .unwrap();
+        let read = sized.len() + chunked.len();
+        assert!(output.contains(&format!("rustic_http_request_bytes_total {}\n", read)));
+    }
+
+    /// Starts an app whose only endpoint sleeps for `delay` under the given shutdown handle.
+    ///
+    /// Returns the server address and a channel receiving the outcome once `run_with_listener`
+    /// returns.
+    fn spawn_slow_server(
+        delay: Duration,
+        shutdown: Shutdown,
+    ) -> (String, mpsc::Receiver<ShutdownOutcome>) {
+        let mut application = App::new();
+        application.add_endpoint("slow", RequestType::GET, move |_| {
+            thread::sleep(delay);
+            Some(Response {
+                status_code: 200,
+                reason: "OK".into(),
+                response_body: Some("done".into()),
+                headers: HeaderMap::new(),
+            })
+        });
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap().to_string();
+        let (sender, receiver) = mpsc::channel();
+        thread::spawn(move || {
+            let config = ServerConfig::new().shutdown(shutdown);
+            let outcome = run_with_listener(application, listener, config).unwrap();
+            sender.send(outcome).unwrap();
+        });
+        (address, receiver)
+    }
+
+    #[test]
+    fn test_graceful_shutdown_drains_requests() {
+        let shutdown = Shutdown::new(Duration::from_secs(5));
+        let (address, outcome) = spawn_slow_server(Duration::from_millis(300), shutdown.clone());
+
+        let url = format!("http://{}/slow", address);
+        let in_flight = thread::spawn(move || {
+            ClientRequest::get(&url)
+                .send()
+                .expect("In-flight request was cut off")
+        });
+        thread::sleep(Duration::from_millis(100));
+        shutdown.trigger();
+
+        let outcome = outcome.recv_timeout(Duration::from_secs(5)).unwrap();
+        assert_eq!(outcome, ShutdownOutcome::Drained);
+        let response = in_flight.join().unwrap();
+        assert_eq!(response.status, 200);
+        assert_eq!(response.text(), "done");
+        assert!(TcpStream::connect(&address).is_err());
+    }
+
+    /// Tests that shutting down waits for a slow request, but closes an idle keep-alive
+    /// connection right away instead of waiting for its next request.
+    #[test]
+    fn test_graceful_shutdown_closes_idle_connections() {
+        let shutdown = Shutdown::new(Duration::from_secs(5));
+        let (address, outcome) = spawn_slow_server(Duration::from_millis(300), shutdown.clone());
+        let request = "GET /slow HTTP/1.1\r\nHost: localhost\r\n\r\n";
+
+        let mut idle = TcpStream::connect(&address).unwrap();
+        idle.write_all(request.as_bytes()).unwrap();
+        let mut response = Vec::new();
+        while !response.ends_with(b"done") {
+            let mut buffer = [0; 256];
+            let read = idle.read(&mut buffer).unwrap();
+            assert!(read > 0);
+            response.extend_from_slice(&buffer[..read]);
+        }
+
+        let mut busy = TcpStream::connect(&address).unwrap();
+        busy.write_all(request.as_bytes()).unwrap();
+        thread::sleep(Duration::from_millis(100));
+        assert_eq!(shutdown.in_flight(), 1);
+        let triggered = Instant::now();
+        shutdown.trigger();
+
+        idle.set_read_timeout(Some(Duration::from_secs(1))).unwrap();
+        assert!(matches!(idle.read(&mut [0; 16]), Ok(0) | Err(_)));
+        assert!(triggered.elapsed() < Duration::from_millis(150));
+        let mut response = String::new();
+        busy.read_to_string(&mut response).unwrap();
+        assert!(response.starts_with("HTTP/1.1 200 "));
+        assert!(response.ends_with("done"));
+        assert_eq!(
+            outcome.recv_timeout(Duration::from_secs(2)).unwrap(),
+            ShutdownOutcome::Drained
+        );
+        assert!(triggered.elapsed() < Duration::from_secs(1));
+        assert_eq!(shutdown.in_flight(), 0);
+    }
+
+    #[test]
+    fn test_graceful_shutdown_timeout() {
+        let shutdown = Shutdown::new(Duration::from_millis(50));
+        let (address, outcome) = spawn_slow_server(Duration::from_secs(2), shutdown.clone());
+
+        let url = format!("http://{}/slow", address);
+        thread::spawn(move || ClientRequest::get(&url).send());
+        thread::sleep(Duration::from_millis(100));
+        shutdown.trigger();
+
+        let outcome = outcome.recv_timeout(Duration::from_secs(1)).unwrap();
+        assert_eq!(outcome, ShutdownOutcome::TimedOut);
+    }
+
+    /// Tests that a background task finishes after the response is received, and that
+    /// shutting down waits for it.
+    #[test]
+    fn test_background_task() {
+        let done = Arc::new(AtomicBool::new(false));
+        let mut application = App::new();
+        let flag = Arc::clone(&done);
+        application.get("signup", move |request| {
+            let flag = Arc::clone(&flag);
+            request
+                .spawn_background(move || {
+                    thread::sleep(Duration::from_millis(200));
+                    flag.store(true, Ordering::SeqCst);
+                })
+                .unwrap();
+            "queued"
+        });
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/signup", listener.local_addr().unwrap());
+        let shutdown = Shutdown::new(Duration::from_secs(5));
+        let config = ServerConfig::new().shutdown(shutdown.clone());
+        let server = thread::spawn(move || run_with_listener(application, listener, config));
+
+        let response = ClientRequest::get(&url).send().unwrap();
+        assert_eq!(response.text(), "queued");
+        assert!(!done.load(Ordering::SeqCst));
+        shutdown.trigger();
+        assert_eq!(server.join().unwrap().unwrap(), ShutdownOutcome::Drained);
+        assert!(done.load(Ordering::SeqCst));
+    }
+
+    /// Tests that scheduled jobs run while the server does and stop with it.
+    #[test]
+    fn test_scheduled_job() {
+        let ticks = Arc::new(AtomicUsize::new(0));
+        let mut application = App::new();
+        let counter = Arc::clone(&ticks);
+        application.schedule(Duration::from_millis(20), move || {
+            counter.fetch_add(1, Ordering::SeqCst);
+        });
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let shutdown = Shutdown::new(Duration::from_secs(5));
+        let config = ServerConfig::new().shutdown(shutdown.clone());
+        let server = thread::spawn(move || run_with_listener(application, listener, config));
+
+        thread::sleep(Duration::from_millis(210));
+        shutdown.trigger();
+        assert_eq!(server.join().unwrap().unwrap(), ShutdownOutcome::Drained);
+        let stopped_at = ticks.load(Ordering::SeqCst);
+        assert!((5..=11).contains(&stopped_at), "{} ticks", stopped_at);
+        thread::sleep(Duration::from_millis(60));
+        assert_eq!(ticks.load(Ordering::SeqCst), stopped_at);
+    }
+
+    /// Tests that lifecycle hooks run in order around the requests, with the real port.
+    #[test]
+    fn test_lifecycle_hooks() {
+        let events = Arc::new(Mutex::new(Vec::new()));
+        let mut application = App::new();
+        for hook in 1..=2 {
+            let events = Arc::clone(&events);
+            application.on_start(move |server| {
+                let event = format!("start {} {}", hook, server.local_addr);
+                events.lock().unwrap().push(event);
+            });
+        }
+        for hook in 1..=2 {
+            let events = Arc::clone(&events);
+            application.on_shutdown(move || {
+                events.lock().unwrap().push(format!("shutdown {}", hook));
+            });
+        }
+        let requests = Arc::clone(&events);
+        application.get("hello", move |_| {
+            requests.lock().unwrap().push("request".to_string());
+            "hello"
+        });
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        let shutdown = Shutdown::new(Duration::from_secs(5));
+        let config = ServerConfig::new().shutdown(shutdown.clone());
+        let server = thread::spawn(move || run_with_listener(application, listener, config));
+
+        let response = ClientRequest::get(&format!("http://{}/hello", address))
+            .send()
+            .unwrap();
+        assert_eq!(response.status, 200);
+        shutdown.trigger();
+        assert_eq!(server.join().unwrap().unwrap(), ShutdownOutcome::Drained);
+        assert_eq!(
+            *events.lock().unwrap(),
+            [
+                format!("start 1 {}", address),
+                format!("start 2 {}", address),
+                "request".to_string(),
+                "shutdown 1".to_string(),
+                "shutdown 2".to_string(),
+            ]
+        );
+    }
+
+    /// Tests that a panicking start hook stops the server before it serves anything.
+    #[test]
+    fn test_start_hook_panic() {
+        let shut_down = Arc::new(AtomicBool::new(false));
+        let mut application = App::new();
+        application.on_start(|_| panic!("migrations failed"));
+        let flag = Arc::clone(&shut_down);
+        application.on_shutdown(move || flag.store(true, Ordering::SeqCst));
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+
+        let err = run_with_listener(application, listener, ServerConfig::new()).unwrap_err();
+        assert!(matches!(err, rustic::Error::Config(_)), "{}", err);
+        assert!(TcpStream::connect(address).is_err());
+        assert!(!shut_down.load(Ordering::SeqCst));
+    }
+
+    /// Tests that an endpoint's body limit applies instead of the server's.
+    #[test]
+    fn test_endpoint_body_limit() {
+        let mut application = App::new();
+        application
+            .endpoint("upload", RequestType::POST)
+            .limit_body(64)
+            .handler(|request| format!("{} bytes", request.body.len()));
+        application.post("comment", |request| format!("{} bytes", request.body.len()));
+        let client = TestClient::with_config(application, ServerConfig::new().max_body_size(16));
+
+        let upload = client.post("/upload").body("x".repeat(32)).send();
+        assert_eq!(upload.status, 200);
+        assert_eq!(upload.text(), "32 bytes");
+        assert_eq!(
+            client.post("/upload").body("x".repeat(65)).send().status,
+            413
+        );
+        assert_eq!(
+            client.post("/comment").body("x".repeat(32)).send().status,
+            413
+        );
+        assert_eq!(
+            client.post("/comment").body("x".repeat(16)).send().status,
+            200
+        );
+    }
+
+    /// Tests that scope middleware guards the scope's endpoints only, running between
+    /// the app's middleware and the endpoint's.
+    #[test]
+    fn test_scope_middleware() {
+        let calls = Arc::new(Mutex::new(Vec::new()));
+        let layer = |name: &'static str, calls: &Arc<Mutex<Vec<String>>>| {
+            let calls = Arc::clone(calls);
+            move |request: Request, next: Next| {
+                calls
+                    .lock()
+                    .unwrap()
+                    .push(format!("{} /{}", name, request.path));
+                next.run(request)
+            }
+        };
+        let auth_calls = Arc::clone(&calls);
+        let mut application = App::new();
+        application.add_middleware(layer("global", &calls));
+        let mut admin = application.scope("admin");
+        admin.middleware(layer("scope", &calls));
+        admin.middleware(move |request: Request, next: Next| {
+            auth_calls.lock().unwrap().push("auth".to_string());
+            if request.header("Authorization") == Some("Bearer admin") {
+                next.run(request)
+            } else {
+                text_response(401, "Sign in first")
+            }
+        });
+        admin
+            .endpoint("users", RequestType::GET)
+            .middleware(layer("route", &calls))
+            .handler(|_| "alice, bob");
+        application.get("public/about", |_| "about");
+        let client = TestClient::new(application);
+
+        assert_eq!(client.get("/admin/users").send().status, 401);
+        assert_eq!(
+            *calls.lock().unwrap(),
+            ["global /admin/users", "scope /admin/users", "auth"]
+        );
+        calls.lock().unwrap().clear();
+
+        let response = client
+            .get("/admin/users")
+            .header("Authorization", "Bearer admin")
+            .send();
+        assert_eq!(response.text(), "alice, bob");
+        assert_eq!(
+            *calls.lock().unwrap(),
+            [
+                "global /admin/users",
+                "scope /admin/users",
+                "auth",
+                "route /admin/users"
+            ]
+        );
+        calls.lock().unwrap().clear();
+
+        assert_eq!(client.get("/public/about").send().text(), "about");
+        assert_eq!(client.get("/admin/missing").send().status, 404);
+        assert_eq!(
+            *calls.lock().unwrap(),
+            ["global /public/about", "global /admin/missing"]
+        );
+    }
+
+    /// Reads the regression corpus of malformed requests checked into `tests/corpus`.
+    fn request_corpus() -> Vec<(String, Vec<u8>)> {
+        let directory = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/corpus");
+        let mut corpus: Vec<_> = fs::read_dir(directory)
+            .unwrap()
+            .map(|entry| {
+                let path = entry.unwrap().path();
+                let name = path.file_name().unwrap().to_string_lossy().into_owned();
+                (name, fs::read(&path).unwrap())
+            })
+            .collect();
+        corpus.sort();
+        assert!(!corpus.is_empty());
+        corpus
+    }
+
+    /// Runs raw request bytes through the parsing functions the way a server composes
+    /// them, returning whether they parsed into a request.
+    fn parse_raw_request(input: &[u8]) -> bool {
+        let Ok(Some((lines, body))) = handle_connection(&mut &input[..]) else {
+            return false;
+        };
+        parse_url_param(&body);
+        form_pairs(&body).count();
+        let Ok(request) = parse_headers(lines) else {
+            return false;
+        };
+        assert!(request.headers.iter().all(|(name, _)| name == name.trim()));
+        let url = request.target;
+        if let Some(path) = parse_path(&url) {
+            assert!(!path.starts_with('/') && !path.ends_with('/'), "{:?}", path);
+            path.split('/').for_each(|segment| {
+                percent_decode(segment);
+            });
+        }
+        parse_url_param(&url);
+        query_pairs(&url).for_each(|(key, value)| {
+            percent_decode(key);
+            percent_decode(value);
+        });
+        parse_authority(&url);
+        true
+    }
+
+    /// Tests that every request of the regression corpus is parsed or refused, and that
+    /// a server answers or closes each without being disturbed.
+    #[test]
+    fn test_request_corpus() {
+        let mut application = echo_app();
+        application.get("users/{id}/posts", |request| {
+            format!("{:?}", request.path_param("id"))
+        });
+        let address = spawn_app(application).replace("http://", "");
+        for (name, input) in request_corpus() {
+            parse_raw_request(&input);
+
+            let mut stream = TcpStream::connect(&address).unwrap();
+            stream.write_all(&input).unwrap();
+            stream.shutdown(std::net::Shutdown::Write).unwrap();
+            let mut response = Vec::new();
+            let _ = stream.read_to_end(&mut response);
+            assert!(
+                response.is_empty() || response.starts_with(b"HTTP/1.1 "),
+                "{}: {}",
+                name,
+                String::from_utf8_lossy(&response)
+            );
+        }
+        let response = raw_exchange(&address, "GET /echo HTTP/1.1\r\nHost: a\r\n\r\n");
+        assert!(response.starts_with("HTTP/1.1 200 "));
+    }
+
+    /// A xorshift generator, so that every run checks the same inputs.
+    struct Xorshift(u64);
+
+    impl Xorshift {
+        fn below(&mut self, bound: usize) -> usize {
+            self.0 ^= self.0 << 13;
+            self.0 ^= self.0 >> 7;
+            self.0 ^= self.0 << 17;
+            (self.0 % bound.max(1) as u64) as usize
+        }
+
+        /// Picks a string from bytes that parsers treat specially, with an occasional
+        /// invalid UTF-8 sequence.
+        fn text(&mut self, max_len: usize) -> Vec<u8> {
+            const ALPHABET: &[u8] = b"aZ09/:?#&=%+;, \t\r\n{}*.-\xff\xc3";
+            (0..self.below(max_len + 1))
+                .map(|_| ALPHABET[self.below(ALPHABET.len())])
+                .collect()
+        }
+    }
+
+    /// Tests that arbitrary bytes, and corpus requests with random bytes spliced in,
+    /// never make the parsing functions panic.
+    #[test]
+    fn test_parsing_never_panics() {
+        let corpus = request_corpus();
+        let mut rng = Xorshift(0x9e37_79b9_7f4a_7c15);
+        for _ in 0..20_000 {
+            let mut input = corpus[rng.below(corpus.len())].1.clone();
+            let at = rng.below(input.len() + 1);
+            let end = (at + rng.below(8)).min(input.len());
+            input.splice(at..end, rng.text(12));
+            parse_raw_request(&input);
+            parse_raw_request(&rng.text(64));
+        }
+    }
+
+    /// Tests that well-formed requests with random targets, headers and bodies are read
+    /// back exactly as they were written.
+    #[test]
+    fn test_parsing_round_trip() {
+        let mut rng = Xorshift(0x0123_4567_89ab_cdef);
+        let token = |rng: &mut Xorshift| -> String {
+            (0..=rng.below(10))
+                .map(|_| b"abcXYZ019-_."[rng.below(12)] as char)
+                .collect()
+        };
+        for _ in 0..2_000 {
+            let path: Vec<String> = (0..rng.below(4)).map(|_| token(&mut rng)).collect();
+            let target = format!(
+                "/{}?{}={}",
+                path.join("/"),
+                token(&mut rng),
+                token(&mut rng)
+            );
+            let mut lines = vec![format!("POST {} HTTP/1.1", target)];
+            for _ in 0..rng.below(5) {
+                lines.push(format!("X-{}: {}", token(&mut rng), token(&mut rng)));
+            }
+            let body = token(&mut rng).repeat(rng.below(20));
+            lines.push(format!("Content-Length: {}", body.len()));
+            let request = format!("{}\r\n\r\n{}", lines.join("\r\n"), body);
+
+            let (read_lines, read_body) =
+                handle_connection(&mut request.as_bytes()).unwrap().unwrap();
+            assert_eq!(read_lines, lines);
+            assert_eq!(read_body, body);
+            let request = parse_headers(read_lines).unwrap();
+            assert_eq!(request.method, RequestType::POST);
+            assert_eq!(request.target, target);
+            let length = body.len().to_string();
+            assert_eq!(request.headers.get("Content-Length"), Some(length.as_str()));
+            let expected_path = path.join("/");
+            let expected_path = expected_path.trim_matches('/');
+            assert_eq!(
+                parse_path(&target),
+                Some(expected_path).filter(|path| !path.is_empty())
+            );
+            assert_eq!(parse_url_param(&target).len(), 1);
+        }
+    }
+
+    #[test]
+    fn test_dynamic_routes() {
+        fn plugin(_: Request) -> Option<Response> {
+            Some(Response {
+                status_code: 200,
+                reason: "OK".into(),
+                response_body: Some("plugin".into()),
+                headers: HeaderMap::new(),
+            })
+        }
+
+        let application = App::new();
+        let routes = application.routes();
+        let base = spawn_app(application);
+        let url = format!("{}/plugins/hello", base);
+
+        let client = Client::new();
+        let before = client.get(&url).send().expect("Failed to send request");
+        assert_eq!(before.status().as_u16(), 404);
+
+        routes.add_endpoint("plugins/hello", RequestType::GET, plugin);
+        let added = client.get(&url).send().expect("Failed to send request");
+        assert_eq!(added.status().as_u16(), 200);
+        assert_eq!(added.text().unwrap(), "plugin");
+
+        assert!(routes.remove_endpoint("plugins/hello", RequestType::GET));
+        let removed = client.get(&url).send().expect("Failed to send request");
+        assert_eq!(removed.status().as_u16(), 404);
+    }
+
+    #[test]
+    fn test_custom_error_handler() {
+        let mut application = App::new();
+        application.add_endpoint("page", RequestType::GET, |_| {
+            Some(Response {
+                status_code: 200,
+                reason: "OK".into(),
+                response_body: Some("page".into()),
+                headers: HeaderMap::new(),
+            })
+        });
+        application.set_error_handler(404, |request| {
+            let mut headers = HeaderMap::new();
+            headers.insert("Content-Type", "text/html");
+            Response {
+                status_code: 404,
+                reason: "Not Found".into(),
+                response_body: Some(format!("<h1>No page at /{}</h1>", request.path).into()),
+                headers,
+            }
+        });
+        let base = spawn_app(application);
+
+        let client = Client::new();
+        let missing = client
+            .get(format!("{}/nowhere", base))
+            .send()
+            .expect("Failed to send request");
+        assert_eq!(missing.status().as_u16(), 404);
+        assert_eq!(missing.headers()["content-type"], "text/html");
+        assert_eq!(missing.text().unwrap(), "<h1>No page at /nowhere</h1>");
+
+        let wrong_method = client
+            .post(format!("{}/page", base))
+            .body("")
+            .send()
+            .expect("Failed to send request");
+        assert_eq!(wrong_method.status().as_u16(), 405);
+        assert_eq!(wrong_method.headers()["allow"], "GET");
+        assert_eq!(wrong_method.text().unwrap(), "Method Not Allowed");
+    }
+
+    /// Tests that negotiated error bodies follow the client's `Accept` header.
+    #[test]
+    fn test_json_error_format() {
+        let mut application = App::new();
+        application.get("page", |_| "page");
+        application.error_format(ErrorFormat::Negotiate);
+        let base = spawn_app(application);
+
+        let client = Client::new();
+        let json = client
+            .get(format!("{}/nowhere", base))
+            .header("Accept", "application/json")
+            .send()
+            .expect("Failed to send request");
+        assert_eq!(json.status().as_u16(), 404);
+        assert_eq!(json.headers()["content-type"], "application/json");
+        assert_eq!(
+            json.text().unwrap(),
+            r#"{"error":{"code":404,"message":"Not Found"}}"#
+        );
+
+        let html = client
+            .get(format!("{}/nowhere", base))
+            .header("Accept", "text/html")
+            .send()
+            .expect("Failed to send request");
+        assert_eq!(html.status().as_u16(), 404);
+        assert_eq!(html.text().unwrap(), "Not Found");
+    }
+
+    #[test]
+    fn test_into_response_handlers() {
+        let mut application = App::new();
+        application.get("hello", |_| "hi");
+        application.post("items", |request: Request| (201, request.body));
+        application.delete("items/*", |_| ());
+        let base = spawn_app(application);
+
+        let client = Client::new();
+        let hello = client
+            .get(format!("{}/hello", base))
+            .send()
+            .expect("Failed to send request");
+        assert_eq!(hello.headers()["content-type"], "text/plain; charset=utf-8");
+        assert_eq!(hello.text().unwrap(), "hi");
+
+        let created = client
+            .post(format!("{}/items", base))
+            .body("widget")
+            .send()
+            .expect("Failed to send request");
+        assert_eq!(created.status().as_u16(), 201);
+        assert_eq!(created.text().unwrap(), "widget");
+
+        let deleted = client
+            .delete(format!("{}/items/1", base))
+            .send()
+            .expect("Failed to send request");
+        assert_eq!(deleted.status().as_u16(), 204);
+    }
+
+    /// Tests handlers taking query, path and JSON extractors, and a failed extraction.
+    #[cfg(feature = "serde")]
+    #[test]
+    fn test_extractor_handlers() {
+        use rustic::extract::{with_extractors, Json, PathParams, Query};
+        use serde::{Deserialize, Serialize};
+
+        #[derive(Deserialize)]
+        struct Page {
+            page: u32,
+            per_page: Option<u32>,
+        }
+
+        #[derive(Deserialize, Serialize)]
+        struct CreateUser {
+            name: String,
+            admin: bool,
+        }
+
+        let mut application = App::new();
+        application.get(
+            "users",
+            with_extractors(|Query(page): Query<Page>| {
+                format!("page {} of {}", page.page, page.per_page.unwrap_or(20))
+            }),
+        );
+        application.post(
+            "teams/{team}/users",
+            with_extractors(
+                |PathParams(team): PathParams<String>, Json(user): Json<CreateUser>| {
+                    Json(CreateUser {
+                        name: format!("{}@{}", user.name, team),
+                        ..user
+                    })
+                },
+            ),
+        );
+        let base = spawn_app(application);
+
+        let client = Client::new();
+        let listed = client
+            .get(format!("{}/users?page=2", base))
+            .send()
+            .expect("Failed to send request");
+        assert_eq!(listed.text().unwrap(), "page 2 of 20");
+
+        let invalid = client
+            .get(format!("{}/users?page=two", base))
+            .send()
+            .expect("Failed to send request");
+        assert_eq!(invalid.status().as_u16(), 400);
+
+        let created = client
+            .post(format!("{}/teams/core/users", base))
+            .header("Content-Type", "application/json")
+            .body(r#"{"name":"ada","admin":true}"#)
+            .send()
+            .expect("Failed to send request");
+        assert_eq!(created.status().as_u16(), 200);
+        assert_eq!(
+            created.text().unwrap(),
+            r#"{"name":"ada@core","admin":true}"#
+        );
+
+        let unsupported = client
+            .post(format!("{}/teams/core/users", base))
+            .body("name=ada")
+            .send()
+            .expect("Failed to send request");
+        assert_eq!(unsupported.status().as_u16(), 415);
+    }
+
+    /// Tests that a supplied request ID is echoed verbatim and a missing one is generated.
+    #[test]
+    fn test_request_id() {
+        let mut application = App::new();
+        application.add_middleware(RequestIdMiddleware::new());
+        application.get("id", |request| {
+            request.request_id().unwrap_or("-").to_string()
+        });
+        let base = spawn_app(application);
+
+        let client = Client::new();
+        let supplied = client
+            .get(format!("{}/id", base))
+            .header("X-Request-Id", "upstream-7f3a")
+            .send()
+            .expect("Failed to send request");
+        assert_eq!(supplied.headers()["x-request-id"], "upstream-7f3a");
+        assert_eq!(supplied.text().unwrap(), "upstream-7f3a");
+
+        let generated = client
+            .get(format!("{}/id", base))
+            .send()
+            .expect("Failed to send request");
+        let id = generated.headers()["x-request-id"]
+            .to_str()
+            .unwrap()
+            .to_string();
+        assert!(!id.is_empty());
+        assert_eq!(generated.text().unwrap(), id);
+    }
+
+    /// Tests that a directory listing links to files that can then be downloaded.
+    #[test]
+    fn test_static_directory_listing() {
+        let root = std::env::temp_dir().join(format!("rustic-static-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&root);
+        fs::create_dir_all(root.join("docs")).unwrap();
+        fs::write(root.join("docs").join("read me.txt"), "hello").unwrap();
+
+        let mut application = App::new();
+        application.serve_static(
+            "files",
+            &root,
+            StaticOptions {
+                directory_listing: true,
+                ..StaticOptions::default()
+            },
+        );
+        let base = spawn_app(application);
+
+        let client = Client::new();
+        let listing = client
+            .get(format!("{}/files/docs/", base))
+            .send()
+            .expect("Failed to send request");
+        assert_eq!(listing.status().as_u16(), 200);
+        let html = listing.text().unwrap();
+        assert!(html.contains("<a href=\"/files/\">../</a>"));
+        assert!(html.contains("<a href=\"/files/docs/read%20me.txt\">read me.txt</a>"));
+
+        let file = client
+            .get(format!("{}/files/docs/read%20me.txt", base))
+            .send()
+            .expect("Failed to send request");
+        assert_eq!(file.headers()["content-type"], "text/plain; charset=utf-8");
+        assert_eq!(file.text().unwrap(), "hello");
+        fs::remove_dir_all(root).unwrap();
+    }
+
+    /// Tests that revalidating a static file with its `ETag` yields an empty `304`.
+    #[test]
+    fn test_static_not_modified() {
+        let root = std::env::temp_dir().join(format!("rustic-etag-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&root);
+        fs::create_dir_all(&root).unwrap();
+        fs::write(root.join("logo.svg"), "<svg/>").unwrap();
+
+        let mut application = App::new();
+        application.serve_static("", &root, StaticOptions::default());
+        let base = spawn_app(application);
+
+        let client = Client::new();
+        let first = client
+            .get(format!("{}/logo.svg", base))
+            .send()
+            .expect("Failed to send request");
+        assert_eq!(first.status().as_u16(), 200);
+        let etag = first.headers()["etag"].clone();
+        assert!(first.headers().contains_key("last-modified"));
+
+        let second = client
+            .get(format!("{}/logo.svg", base))
+            .header("If-None-Match", etag.clone())
+            .send()
+            .expect("Failed to send request");
+        assert_eq!(second.status().as_u16(), 304);
+        assert_eq!(second.headers()["etag"], etag);
+        assert!(second.bytes().unwrap().is_empty());
+        fs::remove_dir_all(root).unwrap();
+    }
+
+    /// Computes the FNV-1a hash of some bytes, as a checksum.
+    fn fnv1a(bytes: &[u8]) -> u64 {
+        fnv1a_extend(0xcbf2_9ce4_8422_2325, bytes)
+    }
+
+    /// Continues an FNV-1a hash over more bytes.
+    fn fnv1a_extend(hash: u64, bytes: &[u8]) -> u64 {
+        bytes.iter().fold(hash, |hash, &byte| {
+            (hash ^ u64::from(byte)).wrapping_mul(0x0100_0000_01b3)
+        })
+    }
+
+    /// Splits the responses read from a connection by their `Content-Length`.
+    fn split_responses(mut received: &[u8]) -> Vec<(String, Vec<u8>)> {
+        let mut responses = Vec::new();
+        while !received.is_empty() {
+            let end = received
+                .windows(4)
+                .position(|window| window == b"\r\n\r\n")
+                .expect("a complete head")
+                + 4;
+            let head = String::from_utf8(received[..end].to_vec()).unwrap();
+            let length: usize = head
+                .lines()
+                .find_map(|line| line.strip_prefix("Content-Length: "))
+                .expect("a Content-Length")
+                .parse()
+                .unwrap();
+            responses.push((head, received[end..end + length].to_vec()));
+            received = &received[end + length..];
+        }
+        responses
+    }
+
+    /// Tests that a multi-megabyte static file and a region of one, both sent from the
+    /// file, arrive intact and keep pipelined keep-alive responses framed.
+    #[test]
+    fn test_file_bodies() {
+        let root = std::env::temp_dir().join(format!("rustic-sendfile-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&root);
+        fs::create_dir_all(&root).unwrap();
+        let mut state = 0x9e37_79b9_7f4a_7c15u64;
+        let contents: Vec<u8> = (0..5 * 1024 * 1024)
+            .map(|_| {
+                state ^= state << 13;
+                state ^= state >> 7;
+                state ^= state << 17;
+                state as u8
+            })
+            .collect();
+        let path = root.join("video.bin");
+        fs::write(&path, &contents).unwrap();
+
+        let mut application = App::new();
+        application.serve_static("static", &root, StaticOptions::default());
+        application.get("slice", move |_| {
+            let file = fs::File::open(&path).unwrap();
+            let mut response = text_response(206, Body::file(file, 1_000_000, 2_000_000));
+            response
+                .headers
+                .insert("Content-Range", "bytes 1000000-2999999/5242880");
+            response
+        });
+        let address = spawn_app(application)
+            .trim_start_matches("http://")
+            .to_string();
+
+        let mut stream = TcpStream::connect(&address).unwrap();
+        stream
+            .write_all(
+                b"GET /static/video.bin HTTP/1.1\r\nHost: x\r\n\r\n\
+                  GET /slice HTTP/1.1\r\nHost: x\r\n\r\n\
+                  GET /static/video.bin HTTP/1.1\r\nHost: x\r\nConnection: close\r\n\r\n",
+            )
+            .unwrap();
+        let mut received = Vec::new();
+        stream.read_to_end(&mut received).unwrap();
+        let responses = split_responses(&received);
+        assert_eq!(responses.len(), 3);
+        let (head, body) = &responses[0];
+        assert!(head.starts_with("HTTP/1.1 200 OK"), "{}", head);
+        assert_eq!(body.len(), contents.len());
+        assert_eq!(fnv1a(body), fnv1a(&contents));
+        let (head, body) = &responses[1];
+        assert!(head.starts_with("HTTP/1.1 206 Partial Content"), "{}", head);
+        assert_eq!(fnv1a(body), fnv1a(&contents[1_000_000..3_000_000]));
+        assert_eq!(fnv1a(&responses[2].1), fnv1a(&contents));
+        fs::remove_dir_all(root).unwrap();
+    }
+
+    /// A reader recording how many bytes it returned, and the most in one read.
+    struct CountingReader<R> {
+        inner: R,
+        total: usize,
+        largest: usize,
+    }
+
+    impl<R: Read> Read for CountingReader<R> {
+        fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+            let read = self.inner.read(buf)?;
+            self.total += read;
+            self.largest = self.largest.max(read);
+            Ok(read)
+        }
+    }
+
+    /// Tests that streamed bodies reach handlers piece by piece, and that what a handler
+    /// leaves unread is skipped so the next keep-alive request is still framed.
+    #[test]
+    fn test_streamed_request_body() {
+        const SIZE: usize = 20 * 1024 * 1024;
+        let streamed = || EndpointConfig::new().stream_body().limit_body(SIZE);
+        let mut application = App::new();
+        application.add_endpoint_with_config(
+            "upload",
+            RequestType::PUT,
+            |mut request: Request| {
+                assert!(request.body.is_empty());
+                let mut reader = CountingReader {
+                    inner: request.body_reader(),
+                    total: 0,
+                    largest: 0,
+                };
+                let mut chunk = vec![0; 64 * 1024];
+                let mut hash = 0xcbf2_9ce4_8422_2325;
+                loop {
+                    match reader.read(&mut chunk) {
+                        Ok(0) => break,
+                        Ok(read) => hash = fnv1a_extend(hash, &chunk[..read]),
+                        Err(err) => return text_response(400, err.to_string()),
+                    }
+                }
+                text_response(
+                    200,
+                    format!("{} {:x} {}", reader.total, hash, reader.largest),
+                )
+            },
+            streamed(),
+        );
+        application.add_endpoint_with_config(
+            "skim",
+            RequestType::PUT,
+            |mut request: Request| {
+                let mut start = [0; 4];
+                request.body_reader().read_exact(&mut start).unwrap();
+                text_response(200, start.to_vec())
+            },
+            streamed(),
+        );
+        application.get("page", |_| "page");
+        let address = spawn_app(application)
+            .trim_start_matches("http://")
+            .to_string();
+
+        let mut state = 0x2545_f491_4f6c_dd1du64;
+        let payload: Vec<u8> = (0..SIZE)
+            .map(|_| {
+                state ^= state << 13;
+                state ^= state >> 7;
+                state ^= state << 17;
+                state as u8
+            })
+            .collect();
+        let mut request = format!(
+            "PUT /upload HTTP/1.1\r\nHost: x\r\nContent-Length: {}\r\n\r\n",
+            SIZE
+        )
+        .into_bytes();
+        request.extend_from_slice(&payload);
+        request.extend_from_slice(
+            b"PUT /skim HTTP/1.1\r\nHost: x\r\nTransfer-Encoding: chunked\r\n\r\n",
+        );
+        for chunk in payload[..1024 * 1024].chunks(100_000) {
+            request.extend_from_slice(format!("{:x}\r\n", chunk.len()).as_bytes());
+            request.extend_from_slice(chunk);
+            request.extend_from_slice(b"\r\n");
+        }
+        request.extend_from_slice(
+            b"0\r\n\r\nGET /page HTTP/1.1\r\nHost: x\r\nConnection: close\r\n\r\n",
+        );
+
+        let mut stream = TcpStream::connect(&address).unwrap();
+        stream.write_all(&request).unwrap();
+        let mut received = Vec::new();
+        stream.read_to_end(&mut received).unwrap();
+        let responses = split_responses(&received);
+        assert_eq!(responses.len(), 3);
+        let (head, body) = &responses[0];
+        assert!(head.starts_with("HTTP/1.1 200 OK"), "{}", head);
+        let body = String::from_utf8(body.clone()).unwrap();
+        let fields: Vec<&str> = body.split(' ').collect();
+        assert_eq!(fields[0], SIZE.to_string());
+        assert_eq!(fields[1], format!("{:x}", fnv1a(&payload)));
+        // The handler never saw more than one buffer of the body at once.
+        assert!(fields[2].parse::<usize>().unwrap() <= 64 * 1024, "{}", body);
+        assert_eq!(responses[1].1, payload[..4]);
+        assert_eq!(responses[2].1, b"page");
+    }
+
+    /// Tests that bodies past the spill threshold reach handlers intact from a
+    /// temporary file, which is removed after the request even if the handler panics.
+    #[test]
+    fn test_spilled_request_body() {
+        let paths = Arc::new(Mutex::new(Vec::new()));
+        let mut application = App::new();
+        let seen = Arc::clone(&paths);
+        application.put("hash", move |mut request: Request| {
+            let spilled = request.body_path().map(|path| path.to_path_buf());
+            if let Some(path) = &spilled {
+                seen.lock().unwrap().push(path.clone());
+            }
+            let whole = fnv1a(&request.body_bytes().unwrap());
+            let mut streamed = Vec::new();
+            request.body_reader().read_to_end(&mut streamed).unwrap();
+            assert_eq!(fnv1a(&streamed), whole);
+            format!("{} {:x} {}", streamed.len(), whole, spilled.is_some())
+        });
+        let seen = Arc::clone(&paths);
+        application.put("boom", move |request: Request| -> &'static str {
+            seen.lock()
+                .unwrap()
+                .push(request.body_path().unwrap().to_path_buf());
+            panic!("upload handler failed")
+        });
+        application.add_endpoint_with_config(
+            "roomy",
+            RequestType::PUT,
+            |request: Request| format!("{} {}", request.body.len(), request.body_path().is_some()),
+            EndpointConfig::new().spill_threshold(1024 * 1024),
+        );
+        let config = ServerConfig::new().spill_threshold(64 * 1024);
+        let client = TestClient::with_config(application, config);
+
+        let large: Vec<u8> = (0..3 * 1024 * 1024u32)
+            .map(|n| b'a' + (n % 26) as u8)
+            .collect();
+        for body in [&large[..64 * 1024], &large[..64 * 1024 + 1], &large[..]] {
+            let response = client.put("hash").body(body).send();
+            let expected = format!(
+                "{} {:x} {}",
+                body.len(),
+                fnv1a(body),
+                body.len() > 64 * 1024
+            );
+            assert_eq!(response.text(), expected);
+        }
+        let response = client
+            .put("hash")
+            .header("Transfer-Encoding", "chunked")
+            .body(format!("{:x}\r\n", 70_000).into_bytes())
+            .send();
+        assert_eq!(response.status, 400);
+
+        assert_eq!(client.put("boom").body(&large[..]).send().status, 500);
+        let response = client.put("roomy").body(vec![b'a'; 512 * 1024]).send();
+        assert_eq!(response.text(), "524288 false");
+
+        let paths = paths.lock().unwrap();
+        assert_eq!(paths.len(), 3);
+        for path in paths.iter() {
+            assert!(!path.exists(), "{} was left behind", path.display());
+        }
+    }
+
+    /// Tests that the favicon and `robots.txt` are served with their types and caching
+    /// headers, and that endpoints added for their paths take precedence.
+    #[test]
+    fn test_favicon_and_robots_txt() {
+        const ICON: &[u8] = &[0, 0, 1, 0, 1, 0, 16, 16];
+        let mut application = App::new();
+        application.favicon(ICON);
+        application.robots_txt(RobotsTxt::new().disallow("/admin/"));
+        let base = spawn_app(application);
+        let client = Client::new();
+
+        let icon = client
+            .get(format!("{}/favicon.ico", base))
+            .send()
+            .expect("Failed to send request");
+        assert_eq!(icon.status().as_u16(), 200);
+        assert_eq!(icon.headers()["content-type"], "image/x-icon");
+        assert_eq!(icon.headers()["cache-control"], "public, max-age=2592000");
+        assert_eq!(icon.bytes().unwrap().as_ref(), ICON);
+        let robots = client
+            .get(format!("{}/robots.txt", base))
+            .send()
+            .expect("Failed to send request");
+        assert_eq!(
+            robots.headers()["content-type"],
+            "text/plain; charset=utf-8"
+        );
+        assert_eq!(robots.headers()["cache-control"], "public, max-age=86400");
+        assert_eq!(robots.text().unwrap(), "User-agent: *\nDisallow: /admin/\n");
+
+        let mut application = App::new();
+        application.get("robots.txt", |_| "User-agent: *\nDisallow: /\n");
+        application.favicon(ICON);
+        application.robots_txt("User-agent: *\nAllow: /\n");
+        application.get("favicon.ico", |_| text_response(204, ""));
+        let client = TestClient::new(application);
+        assert_eq!(client.get("/favicon.ico").send().status, 204);
+        assert_eq!(
+            client.get("/robots.txt").send().text(),
+            "User-agent: *\nDisallow: /\n"
+        );
+        assert_eq!(
+            TestClient::new(App::new())
+                .get("/favicon.ico")
+                .send()
+                .status,
+            404
+        );
+    }
+
+    /// Tests that a form `POST` with `_method=DELETE` reaches the `DELETE` endpoint.
+    #[test]
+    fn test_method_override() {
+        let mut application = App::new();
+        application.add_middleware(MethodOverride::new());
+        application.post("posts/{id}", |_| "updated");
+        application.delete("posts/{id}", |request| {
+            format!("deleted {}", request.path_param("id").unwrap_or_default())
+        });
+        let base = spawn_app(application);
+
+        let response = Client::new()
+            .post(format!("{}/posts/9", base))
+            .header("Content-Type", "application/x-www-form-urlencoded")
+            .body("_method=DELETE")
+            .send()
+            .expect("Failed to send request");
+        assert_eq!(response.text().unwrap(), "deleted 9");
+    }
+
+    /// Tests that a form post needs the CSRF token issued with the page, while pages are
+    /// served to anyone.
+    #[test]
+    fn test_csrf_protection() {
+        let mut application = App::new();
+        application.add_middleware(CsrfMiddleware::new());
+        application.get("settings", |request| {
+            request.csrf_token().unwrap_or_default().to_string()
+        });
+        application.post("settings", |_| "saved");
+        let client = TestClient::new(application);
+
+        let page = client.get("/settings").send();
+        assert_eq!(page.status, 200);
+        let token = page.text();
+        let cookie = page
+            .header("Set-Cookie")
+            .unwrap()
+            .split(';')
+            .next()
+            .unwrap();
+        assert_eq!(cookie, format!("csrf_token={}", token));
+        assert_eq!(
+            client
+                .get("/settings")
+                .header("Cookie", cookie)
+                .send()
+                .status,
+            200
+        );
+
+        let refused = client
+            .post("/settings")
+            .header("Cookie", cookie)
+            .header("Content-Type", "application/x-www-form-urlencoded")
+            .body("theme=dark")
+            .send();
+        assert_eq!(refused.status, 403);
+        assert_eq!(refused.text(), "Forbidden: missing or invalid CSRF token");
+
+        let saved = client
+            .post("/settings")
+            .header("Cookie", cookie)
+            .header("Content-Type", "application/x-www-form-urlencoded")
+            .body(format!("theme=dark&_csrf={}", token))
+            .send();
+        assert_eq!(saved.text(), "saved");
+        let saved = client
+            .post("/settings")
+            .header("Cookie", cookie)
+            .header("X-CSRF-Token", &token)
+            .body("theme=dark")
+            .send();
+        assert_eq!(saved.text(), "saved");
+    }
+
+    /// Tests a preflight as Chrome sends it when a public page calls a server on the
+    /// local network, followed by the request it clears.
+    #[test]
+    fn test_cors_private_network_preflight() {
+        let mut application = App::new();
+        application.add_middleware(
+            Cors::new()
+                .allow_origin("https://tools.example.com")
+                .allow_headers(&["Content-Type", "X-Device-Token"])
+                .max_age(Duration::from_secs(7200))
+                .allow_private_network(true),
+        );
+        application.put("devices/{id}", |_| "configured");
+        let base = spawn_app(application);
+        let client = Client::new();
+
+        let response = client
+            .request(reqwest::Method::OPTIONS, format!("{}/devices/7", base))
+            .header("Origin", "https://tools.example.com")
+            .header("Access-Control-Request-Method", "PUT")
+            .header(
+                "Access-Control-Request-Headers",
+                "content-type,x-device-token",
+            )
+            .header("Access-Control-Request-Private-Network", "true")
+            .header("Sec-Fetch-Mode", "cors")
+            .send()
+            .expect("Failed to send request");
+        assert_eq!(response.status().as_u16(), 204);
+        let header = |name| {
+            response
+                .headers()
+                .get(name)
+                .map(|value| value.to_str().unwrap())
+        };
+        assert_eq!(
+            header("Access-Control-Allow-Origin"),
+            Some("https://tools.example.com")
+        );
+        assert_eq!(
+            header("Access-Control-Allow-Methods"),
+            Some("GET, HEAD, POST, PUT, PATCH, DELETE")
+        );
+        assert_eq!(
+            header("Access-Control-Allow-Headers"),
+            Some("Content-Type, X-Device-Token")
+        );
+        assert_eq!(header("Access-Control-Max-Age"), Some("7200"));
+        assert_eq!(header("Access-Control-Allow-Private-Network"), Some("true"));
+        assert_eq!(
+            header("Vary"),
+            Some("Origin, Access-Control-Request-Method, Access-Control-Request-Headers")
+        );
+
+        let response = client
+            .put(format!("{}/devices/7", base))
+            .header("Origin", "https://tools.example.com")
+            .header("X-Device-Token", "t0ken")
+            .body("on")
+            .send()
+            .expect("Failed to send request");
+        assert_eq!(
+            response.headers()["Access-Control-Allow-Origin"],
+            "https://tools.example.com"
+        );
+        assert_eq!(response.headers()["Vary"], "Origin");
+        assert_eq!(response.text().unwrap(), "configured");
+    }
+
+    /// Tests that gzip request bodies reach handlers decoded when decompression is on.
+    #[cfg(feature = "serde")]
+    #[test]
+    fn test_gzip_request_body() {
+        use rustic::extract::{with_extractors, Json};
+        use serde::{Deserialize, Serialize};
+
+        /// `{"name":"ada","admin":true}` compressed by Python's `gzip.compress`.
+        const GZIP_USER: [u8; 45] = [
+            31, 139, 8, 0, 0, 0, 0, 0, 2, 3, 171, 86, 202, 75, 204, 77, 85, 178, 82, 74, 76, 73,
+            84, 210, 1, 146, 185, 153, 121, 74, 86, 37, 69, 165, 169, 181, 0, 184, 68, 143, 32, 27,
+            0, 0, 0,
+        ];
+        /// 4096 zeros compressed by Python's `gzip.compress`.
+        const GZIP_ZEROS: [u8; 40] = [
+            31, 139, 8, 0, 0, 0, 0, 0, 2, 3, 237, 193, 1, 13, 0, 0, 0, 194, 160, 74, 239, 159, 206,
+            30, 14, 40, 0, 0, 0, 224, 221, 0, 27, 159, 132, 121, 0, 16, 0, 0,
+        ];
+
+        #[derive(Deserialize, Serialize)]
+        struct User {
+            name: String,
+            admin: bool,
+        }
+
+        let mut application = App::new();
+        application.post(
+            "users",
+            with_extractors(|Json(user): Json<User>| Json(user)),
+        );
+        application.post("length", |request: Request| {
+            format!(
+                "{} {:?}",
+                request.header("Content-Length").unwrap_or_default(),
+                request.header("Content-Encoding")
+            )
+        });
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let base = format!("http://{}", listener.local_addr().unwrap());
+        let config = ServerConfig::new()
+            .decompress_requests(true)
+            .max_body_size(1024);
+        thread::spawn(move || run_with_listener(application, listener, config));
+
+        let client = Client::new();
+        let created = client
+            .post(format!("{}/users", base))
+            .header("Content-Type", "application/json")
+            .header("Content-Encoding", "gzip")
+            .body(GZIP_USER.to_vec())
+            .send()
+            .expect("Failed to send request");
+        assert_eq!(created.status().as_u16(), 200);
+        assert_eq!(created.text().unwrap(), r#"{"name":"ada","admin":true}"#);
+
+        let length = client
+            .post(format!("{}/length", base))
+            .header("Content-Encoding", "GZIP")
+            .body(GZIP_USER.to_vec())
+            .send()
+            .expect("Failed to send request");
+        assert_eq!(length.text().unwrap(), "27 None");
+
+        let bomb = client
+            .post(format!("{}/length", base))
+            .header("Content-Encoding", "gzip")
+            .body(GZIP_ZEROS.to_vec())
+            .send()
+            .expect("Failed to send request");
+        assert_eq!(bomb.status().as_u16(), 413);
+
+        let brotli = client
+            .post(format!("{}/length", base))
+            .header("Content-Encoding", "br")
+            .body(GZIP_USER.to_vec())
+            .send()
+            .expect("Failed to send request");
+        assert_eq!(brotli.status().as_u16(), 415);
+
+        let corrupt = client
+            .post(format!("{}/length", base))
+            .header("Content-Encoding", "gzip")
+            .body(&GZIP_USER[..20])
+            .send()
+            .expect("Failed to send request");
+        assert_eq!(corrupt.status().as_u16(), 400);
+    }
+
+    /// Sends raw request bytes, half-closing the connection so the server sees no further
+    /// request, and returns the raw response.
+    fn raw_exchange(address: &str, request: &str) -> String {
+        let mut stream = TcpStream::connect(address).unwrap();
+        stream.write_all(request.as_bytes()).unwrap();
+        stream.shutdown(std::net::Shutdown::Write).unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).unwrap();
+        response
+    }
+
+    /// Builds an application echoing the method and body of any request to `/echo`.
+    fn echo_app() -> App {
+        let mut application = App::new();
+        for method in [
+            RequestType::GET,
+            RequestType::HEAD,
+            RequestType::POST,
+            RequestType::PUT,
+            RequestType::PATCH,
+            RequestType::DELETE,
+        ] {
+            application.add_endpoint("echo", method, |request: Request| {
+                format!("{} [{}]", request.method.as_str(), request.body)
+            });
+        }
+        application
+    }
+
+    /// Tests a corpus of slightly-off requests common clients send: under lenient
+    /// parsing, the ones the server can read unambiguously are served, and those a proxy
+    /// could read differently still get `400 Bad Request`.
+    #[test]
+    fn test_request_format_compatibility() {
+        let mut application = App::new();
+        for method in [RequestType::GET, RequestType::POST] {
+            application.add_endpoint("compat", method, |request: Request| {
+                let mode = request.header("X-Mode").unwrap_or("-");
+                format!("mode={} body={}", mode, request.body)
+            });
+        }
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap().to_string();
+        let config = ServerConfig::new().lenient_parsing(true);
+        thread::spawn(move || run_with_listener(application, listener, config));
+        let served = [
+            (
+                "POST /compat HTTP/1.1\r\nHost: a\r\nX-Mode: crlf\r\nContent-Length: 2\r\n\r\nhi",
+                "mode=crlf body=hi",
+            ),
+            (
+                "POST /compat HTTP/1.1\nHost: a\nX-Mode: lf\nContent-Length: 2\n\nhi",
+                "mode=lf body=hi",
+            ),
+            (
+                "POST /compat HTTP/1.1\r\nHost:a\r\nX-Mode:tight\r\nContent-Length:2\r\n\r\nhi",
+                "mode=tight body=hi",
+            ),
+            (
+                "GET /compat HTTP/1.1\r\nHost: a\r\nX-Mode: \t padded \t\r\n\r\n",
+                "mode=padded body=",
+            ),
+            (
+                "\r\nGET /compat HTTP/1.1\r\nHost: a\r\nX-Mode: blank-line\r\n\r\n",
+                "mode=blank-line body=",
+            ),
+            (
+                "POST /compat HTTP/1.1\r\nHost: a\r\nContent-Length: 2\r\n\r\nhi\r\n\
+                 POST /compat HTTP/1.1\r\nHost: a\r\nX-Mode: second\r\nContent-Length: 2\r\n\r\nyo",
+                "mode=second body=yo",
+            ),
+        ];
+        for (request, expected) in served {
+            let response = raw_exchange(&address, request);
+            assert!(response.starts_with("HTTP/1.1 200 "), "{:?}", request);
+            assert!(response.ends_with(expected), "{:?}: {}", request, response);
+        }
+
+        for request in [
+            "GET /compat HTTP/1.1\r\nHost: a\r\nX-Mode : spaced\r\n\r\n",
+            "GET /compat HTTP/1.1\r\nHost\t: a\r\n\r\n",
+        ] {
+            let response = raw_exchange(&address, request);
+            assert!(response.starts_with("HTTP/1.1 400 "), "{:?}", request);
+        }
+        // Only one empty line is skipped before the request line.
+        let response = raw_exchange(&address, "\r\n\r\nGET /compat HTTP/1.1\r\nHost: a\r\n\r\n");
+        assert!(!response.contains("200 OK"), "{}", response);
+    }
+
+    /// Tests that bodied methods without framing headers get `411`, and others do not.
+    #[test]
+    fn test_length_required() {
+        let address = spawn_app(echo_app()).replace("http://", "");
+        for method in ["POST", "PUT", "PATCH"] {
+            let head = format!("{} /echo HTTP/1.1\r\nHost: localhost\r\n\r\n", method);
+            let response = raw_exchange(&address, &head);
+            assert!(response.starts_with("HTTP/1.1 411 "), "{}", response);
+
+            let sized = format!(
+                "{} /echo HTTP/1.1\r\nHost: localhost\r\nContent-Length: 2\r\n\r\nhi",
+                method
+            );
+            let response = raw_exchange(&address, &sized);
+            assert!(
+                response.ends_with(&format!("{} [hi]", method)),
+                "{}",
+                response
+            );
+
+            let chunked = format!(
+                "{} /echo HTTP/1.1\r\nHost: localhost\r\nTransfer-Encoding: chunked\r\n\r\n\
+                 2\r\nhi\r\n0\r\n\r\n",
+                method
+            );
+            let response = raw_exchange(&address, &chunked);
+            assert!(
+                response.ends_with(&format!("{} [hi]", method)),
+                "{}",
+                response
+            );
+        }
+        for method in ["GET", "DELETE"] {
+            let head = format!("{} /echo HTTP/1.1\r\nHost: localhost\r\n\r\n", method);
+            let response = raw_exchange(&address, &head);
+            assert!(response.starts_with("HTTP/1.1 200 "), "{}", response);
+            assert!(
+                response.ends_with(&format!("{} []", method)),
+                "{}",
+                response
+            );
+        }
+        let head = "HEAD /echo HTTP/1.1\r\nHost: localhost\r\n\r\n";
+        let response = raw_exchange(&address, head);
+        assert!(response.starts_with("HTTP/1.1 200 "), "{}", response);
+    }
+
+    /// Tests reading a body without framing headers until the client closes the connection.
+    #[test]
+    fn test_read_until_close() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap().to_string();
+        let config = ServerConfig::new().missing_length(MissingLength::ReadUntilClose);
+        thread::spawn(move || run_with_listener(echo_app(), listener, config));
+
+        let response = raw_exchange(&address, "POST /echo HTTP/1.0\r\n\r\nold client");
+        assert!(response.ends_with("POST [old client]"), "{}", response);
+        let close = "PUT /echo HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\nnew";
+        let response = raw_exchange(&address, close);
+        assert!(response.ends_with("PUT [new]"), "{}", response);
+
+        // A client that may keep the connection open still has to send a length.
+        let keep_alive = "POST /echo HTTP/1.1\r\nHost: localhost\r\n\r\n";
+        let response = raw_exchange(&address, keep_alive);
+        assert!(response.starts_with("HTTP/1.1 411 "), "{}", response);
+    }
+
+    /// Tests that request smuggling payloads get a clean `400` on a closed connection.
+    #[test]
+    fn test_smuggling_rejected() {
+        let address = spawn_app(echo_app()).replace("http://", "");
+        let smuggled = "0\r\n\r\nGET /echo HTTP/1.1\r\nHost: localhost\r\n\r\n";
+        for framing in [
+            "Content-Length: 5\r\nContent-Length: 45",
+            "Content-Length: 5, 45",
+            "Content-Length: -1",
+            "Content-Length : 45",
+            "Transfer-Encoding: chunked\r\nTransfer-Encoding: identity",
+            "Transfer-Encoding: chunked, identity",
+            "Transfer-Encoding: xchunked",
+            "Transfer-Encoding : chunked",
+            "Transfer-Encoding: identity\r\n chunked",
+            "Content-Length: 45\r\n 5",
+            "Content-Length: 5\r\ncontent-length: 5",
+            "Host: admin.internal\r\nContent-Length: 5",
+        ] {
+            let request = format!(
+                "POST /echo HTTP/1.1\r\nHost: localhost\r\n{}\r\n\r\n{}",
+                framing, smuggled
+            );
+            // Reading to the end only returns once the server closed the connection.
+            let response = raw_exchange(&address, &request);
+            assert!(response.starts_with("HTTP/1.1 400 "), "{}", response);
+            assert!(response.contains("Connection: close\r\n"), "{}", response);
+            assert_eq!(response.matches("HTTP/1.1").count(), 1, "{}", response);
+        }
+
+        // With both headers, chunked coding decides where the body ends.
+        let request = format!(
+            "POST /echo HTTP/1.1\r\nHost: localhost\r\nContent-Length: 45\r\n\
+             Transfer-Encoding: chunked\r\n\r\n{}",
+            smuggled
+        );
+        let response = raw_exchange(&address, &request);
+        assert!(response.ends_with("POST []"), "{}", response);
+        assert_eq!(response.matches("HTTP/1.1").count(), 1, "{}", response);
+    }
+
+    /// Tests both policies for connections beyond `max_connections`.
+    #[test]
+    fn test_connection_limit() {
+        for policy in [
+            OverloadPolicy::PauseAccepting,
+            OverloadPolicy::RespondUnavailable,
+        ] {
+            let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+            let address = listener.local_addr().unwrap();
+            let config = ServerConfig::new()
+                .max_connections(1)
+                .overload_policy(policy);
+            thread::spawn(move || run_with_listener(echo_app(), listener, config));
+
+            // An idle connection takes the only slot.
+            let idle = TcpStream::connect(address).unwrap();
+            thread::sleep(Duration::from_millis(100));
+            let mut second = TcpStream::connect(address).unwrap();
+            second
+                .set_read_timeout(Some(Duration::from_millis(300)))
+                .unwrap();
+            let mut response = String::new();
+
+            if policy == OverloadPolicy::RespondUnavailable {
+                // The answer comes without waiting for the request.
+                second.read_to_string(&mut response).unwrap();
+                assert!(response.starts_with("HTTP/1.1 503 "), "{}", response);
+                assert!(response.contains("Connection: close\r\n"), "{}", response);
+            } else {
+                second
+                    .write_all(b"GET /echo HTTP/1.1\r\nHost: localhost\r\n\r\n")
+                    .unwrap();
+                second.shutdown(std::net::Shutdown::Write).unwrap();
+                let result = second.read_to_string(&mut response);
+                let err = result.unwrap_err();
+                assert!(matches!(
+                    err.kind(),
+                    ErrorKind::WouldBlock | ErrorKind::TimedOut
+                ));
+                assert!(response.is_empty());
+                // Closing the idle connection frees the slot for the waiting one.
+                drop(idle);
+                second
+                    .set_read_timeout(Some(Duration::from_secs(2)))
+                    .unwrap();
+                second.read_to_string(&mut response).unwrap();
+                assert!(response.starts_with("HTTP/1.1 200 "), "{}", response);
+                assert!(response.ends_with("GET []"), "{}", response);
+            }
+        }
+    }
+
+    /// Tests that a connection serves several requests, and that oversized heads and
+    /// bodies are refused.
+    #[test]
+    fn test_keep_alive_and_limits() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        let config = ServerConfig::new()
+            .max_header_size(256)
+            .max_body_size(4)
+            .max_requests_per_connection(2);
+        thread::spawn(move || run_with_listener(echo_app(), listener, config));
+
+        let client = Client::new();
+        for body in ["one", "two", "six"] {
+            let response = client
+                .post(format!("http://{}/echo", address))
+                .body(body)
+                .send()
+                .expect("Failed to send request");
+            assert_eq!(response.text().unwrap(), format!("POST [{}]", body));
+        }
+
+        let mut stream = TcpStream::connect(address).unwrap();
+        let mut reader = std::io::BufReader::new(stream.try_clone().unwrap());
+        for _ in 0..2 {
+            stream
+                .write_all(b"GET /echo HTTP/1.1\r\nHost: localhost\r\n\r\n")
+                .unwrap();
+            let mut response = vec![0; 1024];
+            let read = reader.read(&mut response).unwrap();
+            let response = String::from_utf8_lossy(&response[..read]).to_string();
+            assert!(response.ends_with("GET []"), "{}", response);
+        }
+        // The second request was the last one the connection serves.
+        let mut rest = String::new();
+        reader.read_to_string(&mut rest).unwrap();
+        assert!(rest.is_empty());
+
+        let long = format!(
+            "GET /echo HTTP/1.1\r\nHost: localhost\r\nX-Long: {}\r\n\r\n",
+            "a".repeat(300)
+        );
+        let response = raw_exchange(&address.to_string(), &long);
+        assert!(response.starts_with("HTTP/1.1 431 "), "{}", response);
+        let large = "POST /echo HTTP/1.1\r\nHost: localhost\r\nContent-Length: 5\r\n\r\nlarge";
+        let response = raw_exchange(&address.to_string(), large);
+        assert!(response.starts_with("HTTP/1.1 413 "), "{}", response);
+        let chunked =
+            "POST /echo HTTP/1.1\r\nHost: localhost\r\nTransfer-Encoding: chunked\r\n\r\n\
+                       3\r\nlar\r\n2\r\nge\r\n0\r\n\r\n";
+        let response = raw_exchange(&address.to_string(), chunked);
+        assert!(response.starts_with("HTTP/1.1 413 "), "{}", response);
+    }
+
+    /// Tests that a connection arriving while the worker queue is full gets a fast `503`.
+    #[test]
+    fn test_queue_overflow_shed() {
+        let mut application = App::new();
+        application.get("slow", |_| {
+            thread::sleep(Duration::from_millis(500));
+            "done"
+        });
+        application.enable_metrics_endpoint("metrics");
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap().to_string();
+        let config = ServerConfig::new()
+            .workers(1)
+            .max_queued_connections(1)
+            .overload_policy(OverloadPolicy::RespondUnavailable)
+            .retry_after(Duration::from_millis(1500));
+        thread::spawn(move || run_with_listener(application, listener, config));
+
+        // One connection occupies the worker and the next one fills the queue.
+        let slow = "GET /slow HTTP/1.1\r\nHost: localhost\r\n\r\n";
+        let waiting: Vec<_> = (0..2)
+            .map(|_| {
+                let address = address.clone();
+                let handle = thread::spawn(move || raw_exchange(&address, slow));
+                thread::sleep(Duration::from_millis(100));
+                handle
+            })
+            .collect();
+
+        let started = Instant::now();
+        let mut overflow = TcpStream::connect(&address).unwrap();
+        let mut response = String::new();
+        overflow.read_to_string(&mut response).unwrap();
+        assert!(started.elapsed() < Duration::from_millis(250));
+        assert!(response.starts_with("HTTP/1.1 503 "), "{}", response);
+        assert!(response.contains("Retry-After: 2\r\n"), "{}", response);
+
+        for handle in waiting {
+            assert!(handle.join().unwrap().ends_with("done"));
+        }
+        let metrics = raw_exchange(&address, "GET /metrics HTTP/1.1\r\nHost: localhost\r\n\r\n");
+        assert!(
+            metrics.contains("rustic_http_connections_shed_total 1\n"),
+            "{}",
+            metrics
+        );
+    }
+
+    /// Tests that the tokio server reports an address it cannot parse as an error.
+    #[cfg(feature = "async")]
+    #[test]
+    fn test_async_invalid_address() {
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .unwrap();
+        let err = runtime
+            .block_on(rustic::async_app::run(App::new(), "not an address"))
+            .unwrap_err();
+        assert!(matches!(err, rustic::Error::Parse(_)), "{}", err);
+    }
+
+    /// Tests the tokio server with a blocking and an async endpoint, mirroring
+    /// `test_create_app`. The runtime is built by hand as the tests do without
+    /// tokio's macros.
+    #[cfg(feature = "async")]
+    #[test]
+    fn test_create_async_app() {
+        let mut application = App::new();
+        application.add_endpoint("test", RequestType::POST, |_| "Hi!");
+        application.add_async_endpoint("later", RequestType::GET, |_: Request| async {
+            tokio::time::sleep(Duration::from_millis(20)).await;
+            "Later!"
+        });
+
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .unwrap();
+        runtime.block_on(async {
+            tokio::spawn(rustic::async_app::run(application, "127.0.0.1:8004"));
+            tokio::time::sleep(Duration::from_millis(100)).await;
+
+            let client = reqwest::Client::new();
+            let response = client
+                .post("http://localhost:8004/test")
+                .body("hello")
+                .send()
+                .await
+                .expect("Failed to send request");
+            assert_eq!(response.status().as_u16(), 200);
+            assert_eq!(response.text().await.unwrap(), "Hi!");
+
+            let response = client
+                .get("http://localhost:8004/later")
+                .send()
+                .await
+                .expect("Failed to send request");
+            assert_eq!(response.status().as_u16(), 200);
+            assert_eq!(response.text().await.unwrap(), "Later!");
+        });
+    }
+
+    /// Tests that the tokio reader refuses invalid framing and oversized chunk lines,
+    /// rather than leaving a body in the stream to be read as the next request.
+    #[cfg(feature = "async")]
+    #[test]
+    fn test_async_handle_connection_framing() {
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .build()
+            .unwrap();
+        let read = |request: Vec<u8>| {
+            runtime.block_on(async {
+                let mut reader = &request[..];
+                rustic::async_app::handle_connection(&mut reader).await
+            })
+        };
+
+        let (_, body) = read(
+            b"POST / HTTP/1.1\r\nTransfer-Encoding: chunked\r\n\r\n2;x=y\r\nhi\r\n0\r\n\r\n"
+                .to_vec(),
+        )
+        .unwrap();
+        assert_eq!(body, "hi");
+
+        let padded = format!(
+            "POST / HTTP/1.1\r\nTransfer-Encoding: chunked\r\n\r\n{}5\r\nhello\r\n0\r\n\r\n",
+            "0".repeat(8192)
+        );
+        for request in [
+            b"POST / HTTP/1.1\r\nTransfer-Encoding: chunked, identity\r\n\r\n0\r\n\r\n".to_vec(),
+            b"POST / HTTP/1.1\r\nTransfer-Encoding: gzip\r\n\r\nGET /admin HTTP/1.1\r\n\r\n"
+                .to_vec(),
+            b"POST / HTTP/1.1\r\nContent-Length: 1, 2\r\n\r\nab".to_vec(),
+            b"POST / HTTP/1.1\r\nTransfer-Encoding: chunked\r\n\r\n+2\r\nhi\r\n0\r\n\r\n".to_vec(),
+            padded.into_bytes(),
+        ] {
+            let err = read(request).unwrap_err();
+            assert_eq!(err.kind(), std::io::ErrorKind::InvalidData);
+        }
+    }
+
+    /// Tests that the in-memory `TestClient` answers like the server does over a socket.
+    #[test]
+    fn test_test_client_parity() {
+        let client = TestClient::new(echo_app());
+        let base = spawn_app(echo_app());
+        let http = Client::new();
+
+        let cases = [
+            (RequestType::GET, "/echo", ""),
+            (RequestType::POST, "/echo", "hello"),
+            (RequestType::DELETE, "/echo", ""),
+            (RequestType::GET, "/missing", ""),
+            (RequestType::OPTIONS, "/echo", ""),
+        ];
+        for (method, path, body) in cases {
+            let in_memory = client.request(method, path).body(body).send();
+            let http_method = reqwest::Method::from_bytes(method.as_str().as_bytes()).unwrap();
+            let over_socket = http
+                .request(http_method, format!("{}{}", base, path))
+                .header("Content-Length", body.len())
+                .body(body)
+                .send()
+                .unwrap();
+            let method = method.as_str();
+            assert_eq!(
+                in_memory.status,
+                over_socket.status().as_u16(),
+                "{}",
+                method
+            );
+            assert_eq!(
+                in_memory.header("Content-Type"),
+                over_socket
+                    .headers()
+                    .get("Content-Type")
+                    .map(|value| value.to_str().unwrap()),
+            );
+            assert_eq!(in_memory.text(), over_socket.text().unwrap(), "{}", method);
+        }
+    }
+
+    /// Tests that pipelined requests sent in one write are all answered, in order.
+    #[test]
+    fn test_pipelining() {
+        let address = spawn_app(echo_app()).replace("http://", "");
+        let requests = "GET /echo HTTP/1.1\r\nHost: localhost\r\n\r\n\
+                        POST /echo HTTP/1.1\r\nHost: localhost\r\nContent-Length: 5\r\n\r\nhello\
+                        PUT /echo HTTP/1.1\r\nHost: localhost\r\nTransfer-Encoding: chunked\r\n\r\n\
+                        2\r\nhi\r\n0\r\n\r\n\
+                        DELETE /echo HTTP/1.1\r\nHost: localhost\r\n\r\n";
+        let response = raw_exchange(&address, requests);
+
+        assert_eq!(response.matches("HTTP/1.1 200 ").count(), 4, "{}", response);
+        let order: Vec<usize> = ["GET []", "POST [hello]", "PUT [hi]", "DELETE []"]
+            .iter()
+            .map(|body| response.find(body).expect(body))
+            .collect();
+        assert!(
+            order.windows(2).all(|pair| pair[0] < pair[1]),
+            "{}",
+            response
+        );
+    }
+
+    /// Tests that a client stalling mid-headers gets `408`, while one idling between
+    /// requests is closed without a response.
+    #[test]
+    fn test_request_timeouts() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        let config = ServerConfig::new()
+            .header_timeout(Duration::from_millis(300))
+            .keep_alive(Duration::from_millis(300));
+        thread::spawn(move || run_with_listener(echo_app(), listener, config));
+
+        // Trickling bytes does not extend the header deadline.
+        let mut stream = TcpStream::connect(address).unwrap();
+        let started = Instant::now();
+        stream.write_all(b"GET /echo HTTP/1.1\r\n").unwrap();
+        thread::sleep(Duration::from_millis(150));
+        stream.write_all(b"Host: localhost\r\n").unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).unwrap();
+        assert!(response.starts_with("HTTP/1.1 408 "), "{}", response);
+        assert!(response.contains("Connection: close\r\n"), "{}", response);
+        assert!(started.elapsed() < Duration::from_secs(2));
+
+        let mut stream = TcpStream::connect(address).unwrap();
+        stream
+            .write_all(b"GET /echo HTTP/1.1\r\nHost: localhost\r\n\r\n")
+            .unwrap();
+        let started = Instant::now();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).unwrap();
+        assert!(response.starts_with("HTTP/1.1 200 "), "{}", response);
+        assert!(response.ends_with("GET []"), "{}", response);
+        assert!(started.elapsed() >= Duration::from_millis(250));
+    }
+
+    /// Tests that early hints reach the client ahead of the final response.
+    #[test]
+    fn test_early_hints() {
+        let mut application = App::new();
+        application.get("page", |request| {
+            let mut hints = HeaderMap::new();
+            hints.append("Link", "</style.css>; rel=preload; as=style");
+            hints.append("Link", "</app.js>; rel=preload; as=script");
+            request.send_interim(103, &hints).unwrap();
+            "page"
+        });
+        application.get("late", |request| {
+            let hints = HeaderMap::new();
+            request.send_interim(103, &hints).unwrap_err().to_string()
+        });
+        let address = spawn_app(application).replace("http://", "");
+
+        let response = raw_exchange(&address, "GET /page HTTP/1.1\r\nHost: localhost\r\n\r\n");
+        let (interim, rest) = response.split_once("\r\n\r\n").unwrap();
+        assert_eq!(
+            interim,
+            "HTTP/1.1 103 Early Hints \r\n\
+             Link: </style.css>; rel=preload; as=style\r\n\
+             Link: </app.js>; rel=preload; as=script"
+        );
+        assert!(rest.starts_with("HTTP/1.1 200 "), "{}", rest);
+        assert!(rest.ends_with("\r\n\r\npage"), "{}", rest);
+
+        // HTTP/1.0 clients do not expect interim responses.
+        let response = raw_exchange(&address, "GET /late HTTP/1.0\r\n\r\n");
+        assert!(response.starts_with("HTTP/1.1 200 "), "{}", response);
+        assert!(response.ends_with("cannot receive interim responses"));
+    }
+
+    /// Tests that a handler can take the connection over and speak its own protocol.
+    #[test]
+    fn test_hijack() {
+        let mut application = App::new();
+        application.get("reverse", |request| {
+            request
+                .hijack(|mut connection| {
+                    use std::io::BufRead;
+                    connection.write_all(b"REVERSE 1\n").unwrap();
+                    let mut writer = connection.stream().try_clone().unwrap();
+                    // The first line arrived with the request, so it is read from what
+                    // the server had buffered.
+                    for line in std::io::BufReader::new(connection).lines() {
+                        let reversed: String = line.unwrap().chars().rev().collect();
+                        writer
+                            .write_all(format!("{}\n", reversed).as_bytes())
+                            .unwrap();
+                    }
+                })
+                .unwrap();
+            "never sent"
+        });
+        let address = spawn_app(application).replace("http://", "");
+
+        let mut stream = TcpStream::connect(&address).unwrap();
+        stream
+            .write_all(b"GET /reverse HTTP/1.1\r\nHost: localhost\r\n\r\nabc\n")
+            .unwrap();
+        stream.write_all(b"hello\n").unwrap();
+        stream.shutdown(std::net::Shutdown::Write).unwrap();
+        let mut response = Vec::new();
+        stream.read_to_end(&mut response).unwrap();
+        assert_eq!(response, b"REVERSE 1\ncba\nolleh\n");
+    }
+
+    /// Tests upgrading to a toy protocol echoing length-prefixed frames, with the first
+    /// frame sent in the same packet as the request, and the upgrades that are refused.
+    #[test]
+    fn test_upgrade() {
+        let mut application = App::new();
+        application.get("toy", |request| {
+            request.on_upgrade("toy-echo", |mut connection| {
+                let mut length = [0];
+                while connection.read_exact(&mut length).is_ok() {
+                    let mut frame = vec![0; usize::from(length[0])];
+                    if connection.read_exact(&mut frame).is_err() {
+                        return;
+                    }
+                    frame.make_ascii_uppercase();
+                    let _ = connection.write_all(&length);
+                    let _ = connection.write_all(&frame);
+                }
+            })?;
+            let mut headers = HeaderMap::new();
+            headers.insert("Upgrade", "toy-echo");
+            Ok::<_, Response>(Response::switching_protocols(headers))
+        });
+        application.get("stray", |_| Response::switching_protocols(HeaderMap::new()));
+        let address = spawn_app(application).replace("http://", "");
+
+        let mut stream = TcpStream::connect(&address).unwrap();
+        stream
+            .write_all(
+                b"GET /toy HTTP/1.1\r\nHost: x\r\nConnection: Upgrade\r\n\
+                  Upgrade: toy-echo\r\n\r\n\x02hi",
+            )
+            .unwrap();
+        stream.write_all(b"\x05there").unwrap();
+        stream.shutdown(std::net::Shutdown::Write).unwrap();
+        let mut response = Vec::new();
+        stream.read_to_end(&mut response).unwrap();
+        let response = String::from_utf8(response).unwrap();
+        let (head, frames) = response.split_once("\r\n\r\n").unwrap();
+        assert!(
+            head.starts_with("HTTP/1.1 101 Switching Protocols"),
+            "{}",
+            head
+        );
+        assert!(head.contains("\r\nUpgrade: toy-echo"), "{}", head);
+        assert!(head.contains("\r\nConnection: Upgrade"), "{}", head);
+        assert!(!head.contains("Content-Length"), "{}", head);
+        assert_eq!(frames, "\x02HI\x05THERE");
+
+        let response = raw_exchange(
+            &address,
+            "GET /toy HTTP/1.0\r\nConnection: Upgrade\r\nUpgrade: toy-echo\r\n\r\n",
+        );
+        assert!(response.starts_with("HTTP/1.1 426 "), "{}", response);
+        let response = raw_exchange(
+            &address,
+            "GET /toy HTTP/1.1\r\nHost: x\r\nConnection: Upgrade\r\n\
+             Upgrade: toy-echo\r\nContent-Length: 2\r\n\r\nhi",
+        );
+        assert!(response.starts_with("HTTP/1.1 400 "), "{}", response);
+        // A 101 with nothing to switch to is answered as the error it is.
+        let response = raw_exchange(&address, "GET /stray HTTP/1.1\r\nHost: x\r\n\r\n");
+        assert!(response.starts_with("HTTP/1.1 500 "), "{}", response);
+    }
+
+    /// Tests that an HTTP/2 client sending its connection preface is refused with `505`,
+    /// and one probing with `Upgrade: h2c` is served over HTTP/1.1 on a connection that
+    /// stays open.
+    #[test]
+    fn test_http2_probes() {
+        let mut application = App::new();
+        application.get("probe", |_| "HTTP/1.1 only");
+        let address = spawn_app(application).replace("http://", "");
+
+        let mut stream = TcpStream::connect(&address).unwrap();
+        // The preface, followed by an empty SETTINGS frame.
+        stream
+            .write_all(b"PRI * HTTP/2.0\r\n\r\nSM\r\n\r\n\0\0\0\x04\0\0\0\0\0")
+            .unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).unwrap();
+        assert!(response.starts_with("HTTP/1.1 505 "), "{}", response);
+        assert!(
+            response.contains("\r\nConnection: close\r\n"),
+            "{}",
+            response
+        );
+
+        let probe = "GET /probe HTTP/1.1\r\nHost: x\r\nConnection: Upgrade, HTTP2-Settings\r\n\
+                     Upgrade: h2c\r\nHTTP2-Settings: AAMAAABkAAQCAAAAAAIAAAAA\r\n\r\n";
+        let mut stream = TcpStream::connect(&address).unwrap();
+        stream
+            .write_all(format!("{0}{0}", probe).as_bytes())
+            .unwrap();
+        stream.shutdown(std::net::Shutdown::Write).unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).unwrap();
+        assert_eq!(response.matches("HTTP/1.1 200 ").count(), 2, "{}", response);
+        assert_eq!(response.matches("HTTP/1.1 only").count(), 2, "{}", response);
+        assert!(!response.contains("101"), "{}", response);
+    }
+
+    /// Tests that a client that stops reading its response frees the only worker once
+    /// the write timeout passes, and that the aborted response is counted with only the
+    /// bytes that were written.
+    #[test]
+    fn test_stalled_reader() {
+        const BODY: usize = 32 * 1024 * 1024;
+        let mut application = App::new();
+        application.get("large", |_| "x".repeat(BODY));
+        application.get("small", |_| "small");
+        application.enable_metrics_endpoint("/metrics");
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        let config = ServerConfig::new()
+            .workers(1)
+            .write_timeout(Duration::from_millis(200));
+        thread::spawn(move || run_with_listener(application, listener, config));
+
+        let mut stalled = TcpStream::connect(address).unwrap();
+        stalled
+            .write_all(b"GET /large HTTP/1.1\r\nHost: x\r\n\r\n")
+            .unwrap();
+        let mut start = [0; 12];
+        stalled.read_exact(&mut start).unwrap();
+        assert_eq!(&start, b"HTTP/1.1 200");
+
+        // The second request waits for the worker, which the timeout frees.
+        let started = Instant::now();
+        let response = raw_exchange(
+            &address.to_string(),
+            "GET /small HTTP/1.1\r\nHost: x\r\nConnection: close\r\n\r\n",
+        );
+        assert!(response.ends_with("\r\n\r\nsmall"), "{}", response);
+        assert!(started.elapsed() < Duration::from_secs(5));
+
+        let metrics = raw_exchange(
+            &address.to_string(),
+            "GET /metrics HTTP/1.1\r\nHost: x\r\nConnection: close\r\n\r\n",
+        );
+        assert!(
+            metrics.contains("rustic_http_responses_aborted_total 1\n"),
+            "{}",
+            metrics
+        );
+        let written: usize = metrics
+            .lines()
+            .find_map(|line| line.strip_prefix("rustic_http_response_bytes_total "))
+            .unwrap()
+            .parse()
+            .unwrap();
+        assert!(written < BODY, "{}", written);
+        drop(stalled);
+    }
+
+    /// Tests that methods the server does not implement get `501`, and methods that are
+    /// not tokens get `400`, each closing the connection before the requests after it.
+    #[test]
+    fn test_unknown_methods() {
+        let mut application = App::new();
+        application.get("x", |_| "Found");
+        let address = spawn_app(application).replace("http://", "");
+        let next = "GET /x HTTP/1.1\r\nHost: x\r\n\r\n";
+
+        let response = raw_exchange(
+            &address,
+            &format!("PROPFIND /x HTTP/1.1\r\nHost: x\r\n\r\n{}", next),
+        );
+        assert!(response.starts_with("HTTP/1.1 501 "), "{}", response);
+        assert!(
+            response.contains("\r\nConnection: close\r\n"),
+            "{}",
+            response
+        );
+        assert!(
+            response.ends_with("\r\n\r\nNot Implemented: the `PROPFIND` method is not supported"),
+            "{}",
+            response
+        );
+
+        let response = raw_exchange(
+            &address,
+            &format!("G<ET /x HTTP/1.1\r\nHost: x\r\n\r\n{}", next),
+        );
+        assert!(response.starts_with("HTTP/1.1 400 "), "{}", response);
+        assert!(
+            response.contains("\r\nConnection: close\r\n"),
+            "{}",
+            response
+        );
+        assert!(
+            response.ends_with("\r\n\r\nBad Request: invalid method \"G<ET\""),
+            "{}",
+            response
+        );
+
+        // Methods are case-sensitive, so a lowercase one is merely unknown.
+        let response = raw_exchange(&address, "get /x HTTP/1.1\r\nHost: x\r\n\r\n");
+        assert!(response.starts_with("HTTP/1.1 501 "), "{}", response);
+    }
+
+    /// Tests that absolute URLs are built from the `Host` header, or from the address the
+    /// connection was accepted on when an HTTP/1.0 client sends none.
+    #[test]
+    fn test_full_url() {
+        let mut application = App::new();
+        application.get("self", |request| request.full_url());
+        let address = spawn_app(application).replace("http://", "");
+
+        let response = raw_exchange(
+            &address,
+            "GET /self?x=1 HTTP/1.1\r\nHost: example.com\r\nConnection: close\r\n\r\n",
+        );
+        assert!(
+            response.ends_with("\r\n\r\nhttp://example.com/self?x=1"),
+            "{}",
+            response
+        );
+
+        let response = raw_exchange(&address, "GET /self HTTP/1.0\r\n\r\n");
+        assert!(
+            response.ends_with(&format!("\r\n\r\nhttp://{}/self", address)),
+            "{}",
+            response
+        );
+    }
+
+    /// Tests tunneling a plain HTTP request through `CONNECT` to a second server.
+    #[test]
+    fn test_connect_tunnel() {
+        let upstream = spawn_app(echo_app()).replace("http://", "");
+        let mut application = App::new();
+        application.on_connect(|_, host, _| host == "127.0.0.1");
+        let proxy = spawn_app(application).replace("http://", "");
+
+        let mut stream = TcpStream::connect(&proxy).unwrap();
+        stream
+            .write_all(format!("CONNECT {0} HTTP/1.1\r\nHost: {0}\r\n\r\n", upstream).as_bytes())
+            .unwrap();
+        let mut established = [0; 39];
+        stream.read_exact(&mut established).unwrap();
+        assert_eq!(&established, b"HTTP/1.1 200 Connection Established\r\n\r\n");
+        stream
+            .write_all(b"POST /echo HTTP/1.1\r\nHost: upstream\r\nContent-Length: 6\r\n\r\ntunnel")
+            .unwrap();
+        stream.shutdown(std::net::Shutdown::Write).unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).unwrap();
+        assert!(response.starts_with("HTTP/1.1 200 "), "{}", response);
+        assert!(response.ends_with("POST [tunnel]"), "{}", response);
+
+        let port = upstream.rsplit_once(':').unwrap().1;
+        let denied = format!("CONNECT localhost:{} HTTP/1.1\r\nHost: x\r\n\r\n", port);
+        let response = raw_exchange(&proxy, &denied);
+        assert!(response.starts_with("HTTP/1.1 403 "), "{}", response);
+        let response = raw_exchange(&proxy, "CONNECT /echo HTTP/1.1\r\nHost: x\r\n\r\n");
+        assert!(response.starts_with("HTTP/1.1 400 "), "{}", response);
+        // Nothing listens on port 1, so the upstream cannot be reached.
+        let response = raw_exchange(&proxy, "CONNECT 127.0.0.1:1 HTTP/1.1\r\nHost: x\r\n\r\n");
+        assert!(response.starts_with("HTTP/1.1 502 "), "{}", response);
+    }
+
+    /// Tests that `TRACE` is refused by default and echoes without credentials once
+    /// enabled.
+    #[test]
+    fn test_trace() {
+        let request = "TRACE /anything HTTP/1.1\r\nHost: localhost\r\n\
+                       Authorization: Basic c2VjcmV0\r\nCookie: id=1\r\n\
+                       X-Forwarded-For: 10.0.0.1\r\nMax-Forwards: 0\r\n\r\n";
+        let address = spawn_app(App::new()).replace("http://", "");
+        let response = raw_exchange(&address, request);
+        assert!(response.starts_with("HTTP/1.1 404 "), "{}", response);
+
+        let mut application = App::new();
+        application.enable_trace();
+        let address = spawn_app(application).replace("http://", "");
+        let response = raw_exchange(&address, request);
+        assert!(response.starts_with("HTTP/1.1 200 "), "{}", response);
+        assert!(response.contains("Content-Type: message/http\r\n"));
+        let (_, echo) = response.split_once("\r\n\r\n").unwrap();
+        assert_eq!(
+            echo,
+            "TRACE /anything HTTP/1.1\r\nHost: localhost\r\nMax-Forwards: 0\r\n\
+             X-Forwarded-For: 10.0.0.1\r\n\r\n"
+        );
+    }
+
+    /// Records every log message so tests can assert on what the server logged.
+    struct TestLogger {
+        records: Mutex<Vec<(log::Level, String)>>,
+    }
+
+    impl log::Log for TestLogger {
+        fn enabled(&self, _: &log::Metadata) -> bool {
+            true
+        }
+
+        fn log(&self, record: &log::Record) {
+            let message = record.args().to_string();
+            self.records.lock().unwrap().push((record.level(), message));
+        }
+
+        fn flush(&self) {}
+    }
+
+    static LOGGER: TestLogger = TestLogger {
+        records: Mutex::new(Vec::new()),
+    };
+
+    /// Tests that an unmatched request is logged at debug level with the peer address.
+    #[test]
+    fn test_not_found_is_logged() {
+        let _ = log::set_logger(&LOGGER);
+        log::set_max_level(log::LevelFilter::Debug);
+        let base = spawn_app(App::new());
+
+        let response = Client::new()
+            .get(format!("{}/no-such-page-for-logging", base))
+            .send()
+            .expect("Failed to send request");
+        assert_eq!(response.status().as_u16(), 404);
+
+        let records = LOGGER.records.lock().unwrap();
+        assert!(records.iter().any(|(level, message)| {
+            *level == log::Level::Debug
+                && message
+                    .starts_with("No endpoint for GET /no-such-page-for-logging from 127.0.0.1:")
+        }));
+    }
+
+    /// Tests that clients connecting and leaving without sending anything are let go
+    /// without a response or a log line, and leave the server healthy.
+    #[test]
+    fn test_connection_closed_before_request() {
+        let _ = log::set_logger(&LOGGER);
+        log::set_max_level(log::LevelFilter::Debug);
+        let mut application = App::new();
+        application.get("", |_| "still here");
+        let base = spawn_app(application);
+        let address = base.replace("http://", "");
+
+        let mut peers = Vec::new();
+        for _ in 0..20 {
+            let mut stream = TcpStream::connect(&address).unwrap();
+            peers.push(stream.local_addr().unwrap().to_string());
+            stream.shutdown(std::net::Shutdown::Write).unwrap();
+            let mut response = Vec::new();
+            stream.read_to_end(&mut response).unwrap();
+            assert!(
+                response.is_empty(),
+                "{}",
+                String::from_utf8_lossy(&response)
+            );
+        }
+        for _ in 0..20 {
+            drop(TcpStream::connect(&address).unwrap());
+        }
+
+        let response = Client::new().get(&base).send().unwrap();
+        assert_eq!(response.text().unwrap(), "still here");
+        let records = LOGGER.records.lock().unwrap();
+        for (level, message) in records.iter() {
+            assert!(
+                !message.starts_with("Error accepting connection"),
+                "{}: {}",
+                level,
+                message
+            );
+            assert!(
+                !peers.iter().any(|peer| message.contains(peer.as_str())),
+                "{}: {}",
+                level,
+                message
+            );
+        }
+    }
+
+    /// Tests that a request whose handler outlasts the slow request threshold is logged
+    /// once, with its route pattern and durations, and that a fast one is not.
+    #[test]
+    fn test_slow_request_log() {
+        let _ = log::set_logger(&LOGGER);
+        log::set_max_level(log::LevelFilter::Debug);
+        let mut application = App::new();
+        application.get("slow-log/{speed}", |request| {
+            if request.path.ends_with("slow") {
+                thread::sleep(Duration::from_millis(60));
+            }
+            "done"
+        });
+        let config = ServerConfig::new().slow_request_threshold(Duration::from_millis(30));
+        let client = TestClient::with_config(application, config);
+
+        assert_eq!(client.get("/slow-log/slow").send().text(), "done");
+        assert_eq!(client.get("/slow-log/fast").send().text(), "done");
+
+        let records = LOGGER.records.lock().unwrap();
+        let slow: Vec<_> = records
+            .iter()
+            .filter(|(_, message)| message.contains("GET /slow-log/"))
+            .collect();
+        assert_eq!(slow.len(), 1, "{:?}", slow);
+        let (level, message) = slow[0];
+        assert_eq!(*level, log::Level::Warn);
+        assert!(message.contains("GET /slow-log/slow route=slow-log/{speed} handler="));
+        let handler: u128 = message
+            .split("handler=")
+            .nth(1)
+            .and_then(|rest| rest.split("ms").next())
+            .unwrap()
+            .parse()
+            .unwrap();
+        assert!(handler >= 60, "{}", message);
+        assert!(message.contains(" total="));
+    }
+
+    /// Tests that the access log gives the route pattern of the request, the endpoint's
+    /// name when it has one, and the bytes of the exchange.
+    #[test]
+    fn test_access_log_route_name() {
+        let _ = log::set_logger(&LOGGER);
+        log::set_max_level(log::LevelFilter::Debug);
+        let mut application = App::new();
+        application.add_middleware(RequestIdMiddleware::new().access_log(true));
+        application
+            .endpoint("reports/{id}", RequestType::GET)
+            .name("report")
+            .handler(|request| request.route_name().unwrap_or("-").to_string());
+        let client = TestClient::new(application);
+
+        let response = client.get("/reports/access-log-7").send();
+        assert_eq!(response.text(), "report");
+        assert_eq!(client.get("/access-log-missing").send().status, 404);
+
+        let records = LOGGER.records.lock().unwrap();
+        assert!(records.iter().any(|(level, message)| {
+            *level == log::Level::Info
+                && message
+                    .contains("\"GET /reports/access-log-7\" route=reports/{id} name=report 200 ")
+                && message.contains(" read=")
+                && message.contains(" written=")
+        }));
+        assert!(records.iter().any(|(_, message)| {
+            message.contains("\"GET /access-log-missing\" route=<unmatched> 404 ")
+        }));
+    }
+
+    /// Tests that bodies with multi-byte UTF-8 text are framed by their length in bytes,
+    /// after rendering and compression, and read whole by a client.
+    #[test]
+    fn test_multibyte_bodies() {
+        use rustic::compression::Compression;
+        use rustic::http11_response::hashmap_to_json;
+        use std::collections::HashMap;
+
+        const GREETING: &str = "こんにちは、世界 🦀🚀 — 你好";
+        let mut application = App::new();
+        application.add_middleware(Compression::new().min_size(0));
+        application.get("text", |_| GREETING);
+        application.get("owned", |_| GREETING.repeat(50));
+        application.get("page", |_| {
+            let vars = HashMap::from([("greeting", GREETING)]);
+            Response::html_template("<h1>{{greeting}}</h1>", &vars).unwrap()
+        });
+        application.get("json", |_| {
+            let map = HashMap::from([("greeting", GREETING)]);
+            let mut response = text_response(200, hashmap_to_json(&map));
+            response.headers.insert("Content-Type", "application/json");
+            response
+        });
+        let base = spawn_app(application);
+
+        let client = Client::new();
+        let cases = [
+            ("text", GREETING.to_string(), None),
+            ("owned", GREETING.repeat(50), None),
+            ("page", format!("<h1>{}</h1>", GREETING), None),
+            ("json", format!("{{\"greeting\": \"{}\"}}", GREETING), None),
+            ("owned", GREETING.repeat(50), Some("gzip")),
+        ];
+        for (path, expected, encoding) in cases {
+            let mut request = client.get(format!("{}/{}", base, path));
+            if let Some(encoding) = encoding {
+                request = request.header("Accept-Encoding", encoding);
+            }
+            let response = request.send().expect("Failed to send request");
+            let length: usize = response.headers()["content-length"]
+                .to_str()
+                .unwrap()
+                .parse()
+                .unwrap();
+            let compressed = response.headers().contains_key("content-encoding");
+            let body = response.bytes().unwrap();
+            assert_eq!(length, body.len(), "{}", path);
+            assert_eq!(compressed, encoding.is_some(), "{}", path);
+            if !compressed {
+                assert_eq!(body, expected.as_bytes(), "{}", path);
+            }
+        }
+    }
+
+    /// Tests that requests rejected before routing get complete responses, with `Date`,
+    /// `Content-Type`, an exact `Content-Length` and `Connection: close`.
+    #[test]
+    fn test_early_rejection_headers() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap().to_string();
+        let config = ServerConfig::new()
+            .max_header_size(256)
+            .max_body_size(4)
+            .header_timeout(Duration::from_millis(300));
+        thread::spawn(move || run_with_listener(echo_app(), listener, config));
+
+        let long = format!(
+            "GET /echo HTTP/1.1\r\nHost: localhost\r\nX-Long: {}\r\n\r\n",
+            "a".repeat(300)
+        );
+        let cases = [
+            ("GET\r\n\r\n", 400),
+            ("GET /echo FTP/1.1\r\nHost: localhost\r\n\r\n", 400),
+            ("G<ET /echo HTTP/1.1\r\nHost: localhost\r\n\r\n", 400),
+            ("GET /echo HTTP/1.1\r\nHost: localhost\r\n", 408),
+            (
+                "POST /echo HTTP/1.1\r\nHost: localhost\r\nContent-Length: 9\r\n\r\ntoo large",
+                413,
+            ),
+            (&long, 431),
+            ("GET /echo HTTP/3.0\r\nHost: localhost\r\n\r\n", 505),
+        ];
+        for (request, status) in cases {
+            let mut stream = TcpStream::connect(&address).unwrap();
+            stream.write_all(request.as_bytes()).unwrap();
+            if status != 408 {
+                stream.shutdown(std::net::Shutdown::Write).unwrap();
+            }
+            let mut response = String::new();
+            stream.read_to_string(&mut response).unwrap();
+
+            let (head, body) = response.split_once("\r\n\r\n").unwrap();
+            let mut lines = head.split("\r\n");
+            let status_line = lines.next().unwrap();
+            assert!(
+                status_line.starts_with(&format!("HTTP/1.1 {} ", status)),
+                "{}",
+                response
+            );
+            let headers: HeaderMap = lines.filter_map(|line| line.split_once(": ")).collect();
+            assert!(headers.contains_key("Date"), "{}", response);
+            assert!(headers.contains_key("Content-Type"), "{}", response);
+            assert_eq!(headers.get("Connection"), Some("close"), "{}", response);
+            assert_eq!(
+                headers.get("Content-Length"),
+                Some(body.len().to_string().as_str()),
+                "{}",
+                response
+            );
+            assert!(!body.is_empty(), "{}", response);
+        }
+    }
+
+    /// Tests that responses the framework generates, for unknown paths and methods and
+    /// for refused bodies, go through the app's middleware.
+    #[test]
+    fn test_framework_responses_pass_middleware() {
+        let mut application = App::new();
+        application.add_middleware(Cors::new().allow_origin("https://app.example.com"));
+        application.add_middleware(|request: Request, next: Next| {
+            let mut response = next.run(request);
+            response.headers.insert("X-Served-By", "rustic");
+            response
+        });
+        application.post("uploads", |_| "stored");
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap().to_string();
+        let config = ServerConfig::new().max_body_size(4);
+        thread::spawn(move || run_with_listener(application, listener, config));
+
+        let client = Client::new();
+        for (method, path, body, status) in [
+            (reqwest::Method::GET, "missing", "", 404),
+            (reqwest::Method::GET, "uploads", "", 405),
+            (reqwest::Method::POST, "uploads", "too large", 413),
+        ] {
+            let response = client
+                .request(method, format!("http://{}/{}", address, path))
+                .header("Origin", "https://app.example.com")
+                .body(body)
+                .send()
+                .expect("Failed to send request");
+            assert_eq!(response.status().as_u16(), status);
+            assert_eq!(
+                response.headers()["Access-Control-Allow-Origin"],
+                "https://app.example.com"
+            );
+            assert_eq!(response.headers()["X-Served-By"], "rustic");
+        }
+    }
+
+    #[test]
+    fn test_proxy_propagates_request_id() {
+        let mut downstream = App::new();
+        downstream.get("api/seen", |request: Request| {
+            format!(
+                "{} {} {}",
+                request.header("X-Request-Id").unwrap_or("-"),
+                request.header("traceparent").unwrap_or("-"),
+                request.header("tracestate").unwrap_or("-")
+            )
+        });
+        let downstream_url = spawn_app(downstream);
+
+        let mut front = App::new();
+        front.add_middleware(RequestIdMiddleware::new());
+        front.proxy("api/*", &downstream_url);
+        let base = spawn_app(front);
+
+        let client = Client::new();
+        let response = client
+            .get(format!("{}/api/seen", base))
+            .header("X-Request-Id", "has space")
+            .send()
+            .expect("Failed to send request");
+        let generated = response.headers()["x-request-id"]
+            .to_str()
+            .unwrap()
+            .to_string();
+        assert_ne!(generated, "has space");
+        assert_eq!(response.text().unwrap(), format!("{} - -", generated));
+
+        let parent = "00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01";
+        let response = client
+            .get(format!("{}/api/seen", base))
+            .header("X-Request-Id", "req-42")
+            .header("traceparent", parent)
+            .header("tracestate", "vendor=1")
+            .send()
+            .expect("Failed to send request");
+        assert_eq!(
+            response.text().unwrap(),
+            format!("req-42 {} vendor=1", parent)
+        );
+    }
+
+    /// Tests that maintenance mode answers requests outside the allow-list with a `503`
+    /// while it is on, on a keep-alive connection that is served normally again once it
+    /// is off, and that the `503`s are counted in the metrics.
+    #[test]
+    fn test_maintenance_mode() {
+        let maintenance = Maintenance::new()
+            .allow("/healthz")
+            .allow("/metrics")
+            .retry_after(Duration::from_secs(120))
+            .body("Deploying, back soon");
+        let mut application = App::new();
+        application.get("healthz", |_| "ok");
+        application.get("orders", |_| "no orders");
+        application.enable_metrics_endpoint("/metrics");
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        let config = ServerConfig::new().maintenance(maintenance.clone());
+        thread::spawn(move || run_with_listener(application, listener, config));
+
+        let stream = TcpStream::connect(address).unwrap();
+        let mut reader = std::io::BufReader::new(stream.try_clone().unwrap());
+        let mut writer = stream;
+        let mut get = |path: &str| {
+            use std::io::BufRead;
+            write!(writer, "GET {} HTTP/1.1\r\nHost: localhost\r\n\r\n", path).unwrap();
+            let mut head = String::new();
+            loop {
+                let mut line = String::new();
+                reader.read_line(&mut line).unwrap();
+                if line == "\r\n" {
+                    break;
+                }
+                head.push_str(&line);
+            }
+            let length: usize = head
+                .lines()
+                .find_map(|line| line.strip_prefix("Content-Length: "))
+                .unwrap()
+                .parse()
+                .unwrap();
+            let mut body = vec![0; length];
+            reader.read_exact(&mut body).unwrap();
+            (head, String::from_utf8(body).unwrap())
+        };
+
+        let (head, body) = get("/orders");
+        assert!(head.starts_with("HTTP/1.1 200"), "{}", head);
+        assert_eq!(body, "no orders");
+
+        maintenance.enable();
+        let (head, body) = get("/orders");
+        assert!(head.starts_with("HTTP/1.1 503"), "{}", head);
+        assert!(head.contains("Retry-After: 120\r\n"), "{}", head);
+        assert!(!head.contains("Connection: close"), "{}", head);
+        assert_eq!(body, "Deploying, back soon");
+        let (head, _) = get("/missing");
+        assert!(head.starts_with("HTTP/1.1 503"), "{}", head);
+        let (head, body) = get("/healthz");
+        assert!(head.starts_with("HTTP/1.1 200"), "{}", head);
+        assert_eq!(body, "ok");
+
+        maintenance.disable();
+        let (head, body) = get("/orders");
+        assert!(head.starts_with("HTTP/1.1 200"), "{}", head);
+        assert_eq!(body, "no orders");
+        let (_, metrics) = get("/metrics");
+        assert!(
+            metrics.contains("rustic_http_requests_total{class=\"5xx\"} 2\n"),
+            "{}",
+            metrics
+        );
+        assert!(metrics
+            .contains("rustic_http_route_requests_total{route=\"orders\",class=\"5xx\"} 1\n"));
+    }
+
+    /// Tests that a webhook is retried after failing twice, with the same ID and a valid
+    /// signature on every delivery.
+    #[cfg(feature = "serde")]
+    #[test]
+    fn test_webhook_retries() {
+        use rustic::webhook::{verify_signature, Webhook};
+
+        let deliveries = Arc::new(Mutex::new(Vec::new()));
+        let received = deliveries.clone();
+        let mut application = App::new();
+        application.post("hooks", move |request: Request| {
+            let mut received = received.lock().unwrap();
+            received.push((
+                request.header("X-Webhook-Id").unwrap_or("").to_string(),
+                verify_signature(
+                    b"hook-secret",
+                    request.body.as_bytes(),
+                    request.header("X-Signature").unwrap_or(""),
+                ),
+                request.body.clone(),
+            ));
+            if received.len() < 3 {
+                (503, "Try again")
+            } else {
+                (200, "Thanks")
+            }
+        });
+        let url = spawn_app(application);
+
+        let delivery = Webhook::new(&format!("{}/hooks", url))
+            .secret("hook-secret")
+            .backoff(Duration::from_millis(10))
+            .send(&serde_json::json!({ "event": "order.paid", "id": 42 }))
+            .unwrap()
+            .wait();
+        assert_eq!(delivery.attempts, 3);
+        assert_eq!(delivery.result.unwrap(), 200);
+
+        let deliveries = deliveries.lock().unwrap();
+        assert_eq!(deliveries.len(), 3);
+        for (id, signed, body) in deliveries.iter() {
+            assert_eq!(id, &deliveries[0].0);
+            assert_eq!(id.len(), 32);
+            assert!(signed);
+            assert_eq!(body, r#"{"event":"order.paid","id":42}"#);
+        }
+    }
+
+    /// Tests that bodies that are not valid UTF-8 or cut short by a disconnect are
+    /// answered with `400 Bad Request` and a closed connection, without running the
+    /// handler.
+    #[test]
+    fn test_unreadable_bodies() {
+        let handled = Arc::new(AtomicUsize::new(0));
+        let counter = handled.clone();
+        let mut application = App::new();
+        application.post("notes", move |request: Request| {
+            counter.fetch_add(1, Ordering::SeqCst);
+            format!("Saved {} bytes", request.body.len())
+        });
+        let url = spawn_app(application);
+        let address = url.trim_start_matches("http://");
+        let exchange = |request: &[u8], close: bool| {
+            let mut stream = TcpStream::connect(address).unwrap();
+            stream.write_all(request).unwrap();
+            if close {
+                stream.shutdown(std::net::Shutdown::Write).unwrap();
+            }
+            let mut response = String::new();
+            stream.read_to_string(&mut response).unwrap();
+            response
+        };
+
+        let invalid =
+            b"POST /notes HTTP/1.1\r\nHost: localhost\r\nContent-Length: 4\r\n\r\nok\xff\xfe";
+        let response = exchange(invalid, false);
+        assert!(response.starts_with("HTTP/1.1 400 "), "{}", response);
+        assert!(response.contains("Connection: close\r\n"));
+        assert!(response.ends_with("\r\n\r\nBad Request: body read error"));
+
+        let cut = b"POST /notes HTTP/1.1\r\nHost: localhost\r\nContent-Length: 100\r\n\r\npartial";
+        let response = exchange(cut, true);
+        assert!(response.starts_with("HTTP/1.1 400 "), "{}", response);
+        assert!(response.contains("Connection: close\r\n"));
+        assert!(response.ends_with("Bad Request: body read error"));
+        assert_eq!(handled.load(Ordering::SeqCst), 0);
+
+        let valid = b"POST /notes HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\nContent-Length: 5\r\n\r\nnotes";
+        assert!(exchange(valid, false).ends_with("Saved 5 bytes"));
+        assert_eq!(handled.load(Ordering::SeqCst), 1);
+    }
+
+    /// Tests that the server logs its version and a line for each endpoint once it is
+    /// listening, and only its address when the startup log is turned off.
+    #[test]
+    fn test_startup_log() {
+        let _ = log::set_logger(&LOGGER);
+        log::set_max_level(log::LevelFilter::Debug);
+        let serve = |config: ServerConfig, path: &str| {
+            let mut application = App::new();
+            application.add_endpoint_with_config(
+                format!("{}/{{id:u64}}", path),
+                RequestType::GET,
+                |_| "found",
+                EndpointConfig::new().name("probe"),
+            );
+            let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+            let address = listener.local_addr().unwrap();
+            thread::spawn(move || run_with_listener(application, listener, config));
+            // The startup log is written before the first connection is accepted.
+            let response = Client::new()
+                .get(format!("http://{}/{}/7", address, path))
+                .send()
+                .unwrap();
+            assert_eq!(response.text().unwrap(), "found");
+            address
+        };
+
+        let address = serve(ServerConfig::new().workers(3), "startup-logged");
+        let quiet = serve(ServerConfig::new().log_startup(false), "startup-quiet");
+        let records = LOGGER.records.lock().unwrap();
+        let logged = |expected: &str| {
+            records
+                .iter()
+                .any(|(level, message)| *level == log::Level::Info && message == expected)
+        };
+        assert!(logged(&format!(
+            "rustic {} listening at {} with 3 workers, TLS off",
+            env!("CARGO_PKG_VERSION"),
+            address
+        )));
+        assert!(logged("Route GET     /startup-logged/{id:u64}  probe"));
+        assert!(logged(&format!("Listening at {}", quiet)));
+        assert!(!records
+            .iter()
+            .any(|(_, message)| message.contains("/startup-quiet/")));
+    }
+
+    /// Tests that requests deviating from the request syntax are refused by default and
+    /// under strict parsing, and served under lenient parsing, each deviation on its
+    /// own, and that the accepted ones are logged.
+    #[test]
+    fn test_lenient_parsing() {
+        let _ = log::set_logger(&LOGGER);
+        log::set_max_level(log::LevelFilter::Debug);
+        let serve = |config: ServerConfig| {
+            let mut application = App::new();
+            application.get("lenient", |request| {
+                format!("served {}", request.header("Host").unwrap_or_default())
+            });
+            let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+            let address = listener.local_addr().unwrap().to_string();
+            thread::spawn(move || run_with_listener(application, listener, config));
+            address
+        };
+        let corpus = [
+            ("get /lenient HTTP/1.1\r\nHost: a\r\n\r\n", "501"),
+            ("GET http://a/lenient HTTP/1.1\r\nHost: a\r\n\r\n", "400"),
+            ("GET /lenient HTTP/1.1\r\nHost:a\r\n\r\n", "400"),
+            ("GET /lenient HTTP/1.1\nHost: a\n\n", "400"),
+        ];
+        let toggles = [
+            Leniency {
+                lowercase_methods: true,
+                ..Leniency::STRICT
+            },
+            Leniency {
+                absolute_form: true,
+                ..Leniency::STRICT
+            },
+            Leniency {
+                missing_space: true,
+                ..Leniency::STRICT
+            },
+            Leniency {
+                bare_lf: true,
+                ..Leniency::STRICT
+            },
+        ];
+
+        let default = serve(ServerConfig::new());
+        let strict = serve(ServerConfig::new().lenient_parsing(false));
+        let lenient = serve(ServerConfig::new().lenient_parsing(true));
+        for (index, (request, refused)) in corpus.iter().enumerate() {
+            let expected = format!("HTTP/1.1 {} ", refused);
+            for refusing in [&default, &strict] {
+                let response = raw_exchange(refusing, request);
+                assert!(
+                    response.starts_with(&expected),
+                    "{:?}: {}",
+                    request,
+                    response
+                );
+                assert!(response.contains("Connection: close\r\n"), "{}", response);
+            }
+
+            let response = raw_exchange(&lenient, request);
+            assert!(
+                response.starts_with("HTTP/1.1 200 "),
+                "{:?}: {}",
+                request,
+                response
+            );
+            assert!(response.ends_with("served a"), "{}", response);
+
+            let only = serve(ServerConfig::new().leniency(toggles[index]));
+            for (other, (request, _)) in corpus.iter().enumerate() {
+                let response = raw_exchange(&only, request);
+                assert_eq!(
+                    response.starts_with("HTTP/1.1 200 "),
+                    other == index,
+                    "{:?} under {:?}: {}",
+                    request,
+                    toggles[index],
+                    response
+                );
+            }
+        }
+
+        let records = LOGGER.records.lock().unwrap();
+        for accepted in [
+            "Accepted the method `get` as GET from 127.0.0.1:",
+            "Accepted a request target in absolute form from 127.0.0.1:",
+            "Accepted a header without a space after its colon from 127.0.0.1:",
+            "Accepted a head with bare LF line endings from 127.0.0.1:",
+        ] {
+            assert!(
+                records.iter().any(|(level, message)| {
+                    *level == log::Level::Debug && message.starts_with(accepted)
+                }),
+                "{}",
+                accepted
+            );
+        }
+    }
+
+    /// Tests that requests ending their lines in a bare LF, in the head or in the
+    /// framing of a chunked body, are served when bare LF is allowed and answered with
+    /// `400 Bad Request` when it is not.
+    #[test]
+    fn test_bare_lf_requests() {
+        let serve = |config: ServerConfig| {
+            let mut application = App::new();
+            application.post("echo", |request| request.body);
+            let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+            let address = listener.local_addr().unwrap().to_string();
+            thread::spawn(move || run_with_listener(application, listener, config));
+            address
+        };
+        let strict = serve(ServerConfig::new());
+        let lenient = serve(ServerConfig::new().leniency(Leniency {
+            bare_lf: true,
+            ..Leniency::STRICT
+        }));
+        let requests = [
+            "POST /echo HTTP/1.1\nHost: a\nContent-Length: 5\n\nhello",
+            "POST /echo HTTP/1.1\nHost: a\nTransfer-Encoding: chunked\n\n5\nhello\n0\n\n",
+            "POST /echo HTTP/1.1\r\nHost: a\r\nTransfer-Encoding: chunked\r\n\r\n\
+             5\nhello\n0\n\n",
+        ];
+        for request in requests {
+            let response = raw_exchange(&lenient, request);
+            assert!(
+                response.starts_with("HTTP/1.1 200 "),
+                "{:?}: {}",
+                request,
+                response
+            );
+            assert!(response.ends_with("\r\n\r\nhello"), "{}", response);
+
+            let response = raw_exchange(&strict, request);
+            assert!(
+                response.starts_with("HTTP/1.1 400 "),
+                "{:?}: {}",
+                request,
+                response
+            );
+            assert!(response.contains("Connection: close\r\n"), "{}", response);
+        }
+
+        let crlf = "POST /echo HTTP/1.1\r\nHost: a\r\nTransfer-Encoding: chunked\r\n\r\n\
+                    5\r\nhello\r\n0\r\n\r\n";
+        let response = raw_exchange(&strict, crlf);
+        assert!(response.ends_with("\r\n\r\nhello"), "{}", response);
+    }
 }